@@ -0,0 +1,71 @@
+//! Throughput of the byte-scanning and hashing primitives on the extractor's hot path.
+//!
+//! `cargo bench --bench scanning` on the instance type you plan to run on (e.g. Graviton
+//! c7g vs c6i) prints MB/s per primitive; the naive rows are the pre-memchr implementations,
+//! kept for comparison.
+
+use memchr::memmem;
+use sha2::{Digest, Sha256};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ROUNDS: usize = 20;
+
+fn corpus() -> Vec<u8> {
+    // ~32 MiB of mbox-ish text with a rare separator near the end, so scans run the full length.
+    let line =
+        b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor.\r\n";
+    let mut out = Vec::with_capacity(32 << 20);
+    while out.len() < (32 << 20) {
+        out.extend_from_slice(line);
+    }
+    out.extend_from_slice(
+        b"\nFrom MAILER-DAEMON Mon Jan  1 00:00:00 2024\n--boundary-xyz\nexternal\n",
+    );
+    out
+}
+
+fn report(name: &str, bytes: usize, f: impl Fn() -> usize) {
+    black_box(f());
+    let started = Instant::now();
+    let mut sink = 0usize;
+    for _ in 0..ROUNDS {
+        sink = sink.wrapping_add(f());
+    }
+    black_box(sink);
+    let secs = started.elapsed().as_secs_f64();
+    println!(
+        "{name:<28} {:>9.0} MB/s",
+        (bytes * ROUNDS) as f64 / secs / 1e6
+    );
+}
+
+fn main() {
+    let data = corpus();
+    let n = data.len();
+    println!("{} {}", std::env::consts::ARCH, std::env::consts::OS);
+
+    report("mbox separator (naive)", n, || {
+        data.windows(6).position(|w| w == b"\nFrom ").unwrap_or(0)
+    });
+    report("mbox separator (memmem)", n, || {
+        memmem::find(&data, b"\nFrom ").unwrap_or(0)
+    });
+    report("mime boundary (naive)", n, || {
+        let delim = b"--boundary-xyz";
+        data.windows(delim.len() + 1)
+            .position(|w| w[0] == b'\n' && &w[1..] == delim)
+            .unwrap_or(0)
+    });
+    report("mime boundary (memmem)", n, || {
+        memmem::find_iter(&data, b"\n--boundary-xyz").count()
+    });
+    let text = std::str::from_utf8(&data).expect("ascii corpus");
+    report("banner keyword (str)", n, || {
+        text.find("external").unwrap_or(0)
+    });
+    report("banner keyword (memmem)", n, || {
+        memmem::find(&data, b"external").unwrap_or(0)
+    });
+    report("sha256", n, || Sha256::digest(&data)[0] as usize);
+}
@@ -0,0 +1,216 @@
+//! Aggregate-only processing report (`--aggregate-only`).
+//!
+//! Before a client authorizes transferring a mailbox into our environment they often want a
+//! processing report: how much mail, over what period, what kinds of attachments. In this mode
+//! the job runs as usual but uploads nothing record-level (no emails, attachments, bodies,
+//! sidecars or manifest), only `aggregate_report.json`:
+//!
+//! ```json
+//! {"pst_file_id": "...", "suppress_below": 5,
+//!  "totals": {"emails": 1432, "attachments": 611, "emails_failed": null, ...},
+//!  "emails_by_month": {"2021-03": 212, "2021-04": 187, "unknown": 9},
+//!  "attachments_by_type": {"pdf": 301, "xlsx": 64, ...},
+//!  "attachment_sizes": {"<10KB": 120, "10KB-100KB": 233, ...},
+//!  "suppressed_cells": 7}
+//! ```
+//!
+//! Small-count suppression: any count from 1 to `suppress_below - 1` is withheld (a null total,
+//! or a histogram cell left out and counted in `suppressed_cells`), so the report can't single
+//! out one message or one unusual attachment. Counts are exact otherwise; no noise is added.
+
+use crate::gzmembers::OutputCompression;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+const SIZE_BUCKETS: &[(u64, &str)] = &[
+    (10 << 10, "<10KB"),
+    (100 << 10, "10KB-100KB"),
+    (1 << 20, "100KB-1MB"),
+    (10 << 20, "1MB-10MB"),
+    (u64::MAX, ">=10MB"),
+];
+
+#[derive(Deserialize)]
+struct EmailFields {
+    date_epoch: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct AttachmentFields {
+    filename: String,
+    file_size_bytes: u64,
+}
+
+#[derive(Default)]
+pub struct Aggregates {
+    emails: u64,
+    attachments: u64,
+    emails_by_month: BTreeMap<String, u64>,
+    attachments_by_type: BTreeMap<String, u64>,
+    attachment_sizes: BTreeMap<&'static str, u64>,
+}
+
+fn read_ndjson<T: for<'de> Deserialize<'de>>(
+    path: &Path,
+    compression: OutputCompression,
+    mut each: impl FnMut(T),
+) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    for line in BufReader::new(compression.reader(file)?).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        each(serde_json::from_str(&line).with_context(|| format!("parse {}", path.display()))?);
+    }
+    Ok(())
+}
+
+/// `YYYY-MM` (UTC) of a date_epoch, or `unknown`.
+pub fn month(date_epoch: Option<i64>) -> String {
+    match date_epoch {
+        Some(epoch) => crate::dates::format_day(epoch)[..7].to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Lowercased filename extension (up to 8 characters), or `none`.
+pub fn attachment_type(filename: &str) -> String {
+    match filename.rsplit_once('.') {
+        Some((_, ext)) if !ext.is_empty() && ext.len() <= 8 => ext.to_ascii_lowercase(),
+        _ => "none".to_string(),
+    }
+}
+
+impl Aggregates {
+    pub fn add_email(&mut self, date_epoch: Option<i64>) {
+        self.emails += 1;
+        *self.emails_by_month.entry(month(date_epoch)).or_default() += 1;
+    }
+
+    pub fn add_attachment(&mut self, filename: &str, size: u64) {
+        self.attachments += 1;
+        *self
+            .attachments_by_type
+            .entry(attachment_type(filename))
+            .or_default() += 1;
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .find(|(below, _)| size < *below)
+            .map_or(">=10MB", |(_, name)| *name);
+        *self.attachment_sizes.entry(bucket).or_default() += 1;
+    }
+
+    /// Tally the job's local emails.ndjson.gz and attachments.ndjson.gz.
+    pub fn from_outputs(
+        emails: &Path,
+        attachments: &Path,
+        compression: OutputCompression,
+    ) -> Result<Self> {
+        let mut aggregates = Self::default();
+        read_ndjson(emails, compression, |e: EmailFields| {
+            aggregates.add_email(e.date_epoch)
+        })?;
+        read_ndjson(attachments, compression, |a: AttachmentFields| {
+            aggregates.add_attachment(&a.filename, a.file_size_bytes)
+        })?;
+        Ok(aggregates)
+    }
+
+    /// The report, with counts below `suppress_below` withheld. `totals` adds job counters
+    /// (failures, threads, ...) to the email and attachment totals.
+    pub fn report(&self, pst_file_id: &str, suppress_below: u64, totals: &[(&str, u64)]) -> Value {
+        let small = |count: u64| count > 0 && count < suppress_below;
+        let mut suppressed = 0u64;
+        let mut histogram = |cells: Vec<(String, u64)>| {
+            let mut out = Map::new();
+            for (name, count) in cells {
+                if small(count) {
+                    suppressed += 1;
+                } else {
+                    out.insert(name, count.into());
+                }
+            }
+            Value::Object(out)
+        };
+        let emails_by_month = histogram(
+            self.emails_by_month
+                .iter()
+                .map(|(k, v)| (k.clone(), *v))
+                .collect(),
+        );
+        let attachments_by_type = histogram(
+            self.attachments_by_type
+                .iter()
+                .map(|(k, v)| (k.clone(), *v))
+                .collect(),
+        );
+        let attachment_sizes = histogram(
+            SIZE_BUCKETS
+                .iter()
+                .filter_map(|(_, name)| {
+                    self.attachment_sizes
+                        .get(name)
+                        .map(|count| (name.to_string(), *count))
+                })
+                .collect(),
+        );
+        let mut total_map = Map::new();
+        for (name, count) in [("emails", self.emails), ("attachments", self.attachments)]
+            .into_iter()
+            .chain(totals.iter().copied())
+        {
+            let value = if small(count) {
+                Value::Null
+            } else {
+                count.into()
+            };
+            total_map.insert(name.to_string(), value);
+        }
+        json!({
+            "pst_file_id": pst_file_id,
+            "suppress_below": suppress_below,
+            "totals": total_map,
+            "emails_by_month": emails_by_month,
+            "attachments_by_type": attachments_by_type,
+            "attachment_sizes": attachment_sizes,
+            "suppressed_cells": suppressed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppresses_small_cells_and_totals() {
+        let mut aggregates = Aggregates::default();
+        // 2021-03-03 and 2021-04-01.
+        for _ in 0..6 {
+            aggregates.add_email(Some(1_614_765_600));
+        }
+        aggregates.add_email(Some(1_617_235_200));
+        aggregates.add_email(None);
+        for _ in 0..5 {
+            aggregates.add_attachment("Programme.PDF", 50_000);
+        }
+        aggregates.add_attachment("secret-plan.vsdx", 20 << 20);
+
+        let report = aggregates.report("p1", 5, &[("emails_failed", 2), ("files_skipped", 0)]);
+        assert_eq!(report["totals"]["emails"], 8);
+        assert_eq!(report["totals"]["attachments"], 6);
+        assert_eq!(report["totals"]["emails_failed"], Value::Null);
+        assert_eq!(report["totals"]["files_skipped"], 0);
+        assert_eq!(report["emails_by_month"], json!({"2021-03": 6}));
+        assert_eq!(report["attachments_by_type"], json!({"pdf": 5}));
+        assert_eq!(report["attachment_sizes"], json!({"10KB-100KB": 5}));
+        // 2021-04, unknown, vsdx, >=10MB.
+        assert_eq!(report["suppressed_cells"], 4);
+    }
+}
@@ -0,0 +1,549 @@
+//! Anonymized corpus export (`--anonymized-export`).
+//!
+//! Parser bugs are reported against client mailboxes we can't hand to the vendor or the dev
+//! team. This module rewrites each message into a scrubbed copy that keeps the MIME tree, the
+//! part types and encodings, and the length of every text. It also keeps what the extractor
+//! keys on: dates, reply prefixes, Content-IDs, and Message-ID / In-Reply-To / References
+//! linkage (hashed consistently, so threads survive).
+//!
+//! - Addresses become `u<hash>@d<hash>.example`; one domain always maps to the same `d<hash>`.
+//! - Display names, subjects, bodies and other header values become lorem ipsum of the same
+//!   length, with case, digits-as-digits, punctuation and whitespace in place.
+//! - Attachments keep their size and first 8 bytes (so type sniffing still works); the rest is
+//!   filler. Filenames keep their extension.
+//! - Attached messages (`message/rfc822`) are scrubbed recursively.
+//!
+//! Hashes are keyed with `--anonymize-key`, so pseudonyms can't be reversed by hashing a list of
+//! known addresses.
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use mailparse::{DispositionType, MailAddr, MailHeader, ParsedMail};
+use sha2::Sha256;
+
+const LOREM: &[u8] = b"loremipsumdolorsitametconsecteturadipiscingelitseddoeiusmodtemporincididuntutlaboreetdoloremagnaaliqua";
+const FILLER: &[u8] = b"ANONYMIZED";
+/// Bytes of each attachment kept as-is (magic numbers for content sniffing).
+const KEEP_PREFIX: usize = 8;
+const MAX_DEPTH: usize = 10;
+
+const ADDRESS_HEADERS: &[&str] = &[
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "reply-to",
+    "sender",
+    "return-path",
+    "delivered-to",
+    "x-original-to",
+    "disposition-notification-to",
+    "resent-from",
+    "resent-to",
+    "resent-cc",
+    "resent-sender",
+];
+const ID_HEADERS: &[&str] = &[
+    "message-id",
+    "in-reply-to",
+    "references",
+    "resent-message-id",
+];
+/// Headers that describe structure or routing metadata rather than content; copied as-is.
+const KEPT_HEADERS: &[&str] = &[
+    "date",
+    "resent-date",
+    "mime-version",
+    "content-transfer-encoding",
+    "content-id",
+    "content-language",
+    "importance",
+    "priority",
+    "x-priority",
+    "sensitivity",
+    "thread-index",
+    "x-mailer",
+    "auto-submitted",
+    "precedence",
+];
+/// Folder names that say nothing about the client; other path components are scrubbed.
+const STANDARD_FOLDERS: &[&str] = &[
+    "top of personal folders",
+    "top of information store",
+    "inbox",
+    "sent items",
+    "deleted items",
+    "drafts",
+    "outbox",
+    "junk e-mail",
+    "junk email",
+    "archive",
+    "calendar",
+    "contacts",
+    "tasks",
+    "notes",
+    "journal",
+    "_recovered",
+];
+
+/// Replaces letters and digits with lorem ipsum, starting at a text-dependent offset so equal
+/// texts scrub identically and different ones (usually) don't.
+struct Lorem {
+    at: usize,
+}
+
+impl Lorem {
+    fn char(&mut self, c: char) -> char {
+        self.at += 1;
+        if c.is_alphabetic() {
+            let l = LOREM[self.at % LOREM.len()] as char;
+            if c.is_uppercase() {
+                l.to_ascii_uppercase()
+            } else {
+                l
+            }
+        } else if c.is_numeric() {
+            char::from(b'0' + (self.at % 10) as u8)
+        } else if c.is_ascii() {
+            c
+        } else if c.is_whitespace() {
+            ' '
+        } else {
+            '-'
+        }
+    }
+}
+
+pub struct Anonymizer {
+    key: Vec<u8>,
+}
+
+impl Anonymizer {
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: key.as_bytes().to_vec(),
+        }
+    }
+
+    fn hex(&self, kind: &str, value: &str, chars: usize) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(kind.as_bytes());
+        mac.update(b"\0");
+        mac.update(value.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        digest
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()[..chars]
+            .to_string()
+    }
+
+    fn lorem(&self, text: &str) -> Lorem {
+        let seed = self.hex("lorem", text, 8);
+        Lorem {
+            at: usize::from_str_radix(&seed, 16).unwrap_or(0),
+        }
+    }
+
+    pub fn text(&self, text: &str) -> String {
+        let mut lorem = self.lorem(text);
+        text.chars().map(|c| lorem.char(c)).collect()
+    }
+
+    pub fn address(&self, addr: &str) -> String {
+        let addr = addr.trim().to_ascii_lowercase();
+        let domain = addr.rsplit_once('@').map_or("", |(_, d)| d);
+        format!(
+            "u{}@d{}.example",
+            self.hex("address", &addr, 10),
+            self.hex("domain", domain, 8)
+        )
+    }
+
+    /// Reply/forward prefixes stay readable so subject threading still works.
+    fn subject(&self, subject: &str) -> String {
+        let mut rest = subject;
+        let mut prefix = String::new();
+        loop {
+            let trimmed = rest.trim_start();
+            let Some((tag, tail)) = trimmed.split_once(':') else {
+                break;
+            };
+            if !["re", "fw", "fwd", "aw", "sv", "wg"].contains(&tag.to_ascii_lowercase().as_str()) {
+                break;
+            }
+            prefix.push_str(&rest[..rest.len() - tail.len()]);
+            rest = tail;
+        }
+        let body = rest.trim_start();
+        prefix.push_str(&rest[..rest.len() - body.len()]);
+        prefix + &self.text(body)
+    }
+
+    /// Every `<...>` id (or the bare value) becomes `<m<hash>@d<hash>.example>`.
+    fn ids(&self, value: &str) -> String {
+        let id = |inner: &str| {
+            let domain = inner.rsplit_once('@').map_or("", |(_, d)| d);
+            format!(
+                "m{}@d{}.example",
+                self.hex("id", inner, 16),
+                self.hex("domain", &domain.to_ascii_lowercase(), 8)
+            )
+        };
+        if !value.contains('<') {
+            return id(value.trim());
+        }
+        value
+            .split('<')
+            .skip(1)
+            .filter_map(|part| part.split_once('>').map(|(inner, _)| inner))
+            .map(|inner| format!("<{}>", id(inner.trim())))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn mailbox(&self, name: Option<&str>, addr: &str) -> String {
+        match name.filter(|n| !n.is_empty()) {
+            Some(name) => format!("\"{}\" <{}>", self.text(name), self.address(addr)),
+            None => format!("<{}>", self.address(addr)),
+        }
+    }
+
+    fn addresses(&self, header: &MailHeader) -> String {
+        let Ok(list) = mailparse::addrparse_header(header) else {
+            return self.text(&header.get_value());
+        };
+        list.iter()
+            .map(|addr| match addr {
+                MailAddr::Single(info) => self.mailbox(info.display_name.as_deref(), &info.addr),
+                MailAddr::Group(group) => format!(
+                    "{}: {};",
+                    self.text(&group.group_name),
+                    group
+                        .addrs
+                        .iter()
+                        .map(|info| self.mailbox(info.display_name.as_deref(), &info.addr))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn filename(&self, name: &str) -> String {
+        match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => format!("{}.{ext}", self.text(stem)),
+            _ => self.text(name),
+        }
+    }
+
+    /// Scrub a readpst-relative path's folders; standard folder names are kept.
+    pub fn folder_path(&self, source_path: &str) -> String {
+        let mut components: Vec<&str> = source_path.split(['/', '\\']).collect();
+        components.pop();
+        components
+            .into_iter()
+            .filter(|c| !c.is_empty())
+            .map(|c| {
+                if STANDARD_FOLDERS.contains(&c.to_ascii_lowercase().as_str()) {
+                    c.to_string()
+                } else {
+                    self.text(c)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn html(&self, html: &str) -> String {
+        let mut lorem = self.lorem(html);
+        let mut out = String::with_capacity(html.len());
+        let mut chars = html.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '<' => {
+                    out.push(c);
+                    while let Some(c) = chars.next() {
+                        out.push(c);
+                        match c {
+                            '>' => break,
+                            '"' | '\'' => {
+                                let value: String =
+                                    chars.by_ref().take_while(|v| *v != c).collect();
+                                if value.starts_with("cid:") || value.starts_with('#') {
+                                    out.push_str(&value);
+                                } else {
+                                    out.push_str(&self.text(&value));
+                                }
+                                out.push(c);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                '&' => {
+                    out.push(c);
+                    while let Some(&e) = chars.peek() {
+                        if !(e.is_ascii_alphanumeric() || e == '#') {
+                            break;
+                        }
+                        out.push(e);
+                        chars.next();
+                    }
+                }
+                _ => out.push(lorem.char(c)),
+            }
+        }
+        out
+    }
+
+    fn header(&self, header: &MailHeader, part: &ParsedMail) -> String {
+        let key = header.get_key();
+        let lower = key.to_ascii_lowercase();
+        let value = match lower.as_str() {
+            k if ADDRESS_HEADERS.contains(&k) => self.addresses(header),
+            k if ID_HEADERS.contains(&k) => self.ids(&header.get_value()),
+            k if KEPT_HEADERS.contains(&k) => header.get_value(),
+            "subject" | "thread-topic" => self.subject(&header.get_value()),
+            "content-type" => {
+                let mut value = part.ctype.mimetype.clone();
+                for (name, param) in &part.ctype.params {
+                    let param = match name.as_str() {
+                        "name" => self.filename(param),
+                        _ => param.clone(),
+                    };
+                    value.push_str(&format!("; {name}=\"{param}\""));
+                }
+                value
+            }
+            "content-disposition" => {
+                let disposition = part.get_content_disposition();
+                let mut value = match disposition.disposition {
+                    DispositionType::Inline => "inline".to_string(),
+                    DispositionType::Attachment => "attachment".to_string(),
+                    DispositionType::FormData => "form-data".to_string(),
+                    DispositionType::Extension(other) => other,
+                };
+                for (name, param) in &disposition.params {
+                    let param = match name.as_str() {
+                        "filename" | "name" => self.filename(param),
+                        _ => param.clone(),
+                    };
+                    value.push_str(&format!("; {name}=\"{param}\""));
+                }
+                value
+            }
+            // Routing hosts and IPs go; the timestamp after the last ';' is kept.
+            "received" => {
+                let value = header.get_value();
+                match value.rsplit_once(';') {
+                    Some((_, date)) => format!("from anonymized by anonymized;{date}"),
+                    None => "from anonymized by anonymized".to_string(),
+                }
+            }
+            _ => self.text(&header.get_value()),
+        };
+        format!("{key}: {value}\r\n")
+    }
+
+    fn part(&self, part: &ParsedMail, depth: usize, out: &mut Vec<u8>) -> Result<()> {
+        for header in &part.headers {
+            out.extend(self.header(header, part).as_bytes());
+        }
+        out.extend(b"\r\n");
+        let mimetype = part.ctype.mimetype.to_ascii_lowercase();
+        if mimetype.starts_with("multipart/") {
+            let boundary = part
+                .ctype
+                .params
+                .get("boundary")
+                .cloned()
+                .unwrap_or_default();
+            for sub in &part.subparts {
+                out.extend(format!("--{boundary}\r\n").as_bytes());
+                self.part(sub, depth, out)?;
+                if !out.ends_with(b"\n") {
+                    out.extend(b"\r\n");
+                }
+            }
+            out.extend(format!("--{boundary}--\r\n").as_bytes());
+            return Ok(());
+        }
+
+        let body = if mimetype == "message/rfc822" && depth < MAX_DEPTH {
+            let raw = part.get_body_raw().context("decode attached message")?;
+            match mailparse::parse_mail(&raw) {
+                Ok(inner) => {
+                    let mut scrubbed = Vec::with_capacity(raw.len());
+                    self.part(&inner, depth + 1, &mut scrubbed)?;
+                    scrubbed
+                }
+                Err(_) => filler(&raw),
+            }
+        } else if mimetype == "text/html" {
+            self.html(&part.get_body().context("decode html part")?)
+                .into_bytes()
+        } else if mimetype.starts_with("text/") {
+            self.text(&part.get_body().context("decode text part")?)
+                .into_bytes()
+        } else {
+            filler(&part.get_body_raw().context("decode part")?)
+        };
+        let encoding = part
+            .headers
+            .iter()
+            .find(|h| {
+                h.get_key()
+                    .eq_ignore_ascii_case("content-transfer-encoding")
+            })
+            .map(|h| h.get_value().trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        match encoding.as_str() {
+            "base64" => out.extend(crate::msg::base64_lines(&body).as_bytes()),
+            "quoted-printable" => out.extend(quoted_printable(&body)),
+            _ => out.extend(&body),
+        }
+        Ok(())
+    }
+
+    /// The scrubbed copy of one RFC822 message.
+    pub fn message(&self, raw: &[u8]) -> Result<Vec<u8>> {
+        let mail = mailparse::parse_mail(raw).context("parse message")?;
+        let mut out = Vec::with_capacity(raw.len());
+        self.part(&mail, 0, &mut out)?;
+        Ok(out)
+    }
+}
+
+/// Same length, same leading magic bytes, no content.
+fn filler(data: &[u8]) -> Vec<u8> {
+    let keep = data.len().min(KEEP_PREFIX);
+    let mut out = data[..keep].to_vec();
+    out.extend((keep..data.len()).map(|i| FILLER[i % FILLER.len()]));
+    out
+}
+
+/// Minimal quoted-printable encoding with soft line breaks at 76 columns.
+fn quoted_printable(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 8);
+    for (n, line) in data.split(|b| *b == b'\n').enumerate() {
+        if n > 0 {
+            out.extend(b"\r\n");
+        }
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let mut column = 0;
+        for (i, &b) in line.iter().enumerate() {
+            let trailing_space = (b == b' ' || b == b'\t') && i + 1 == line.len();
+            let encoded = if b == b'=' || !(32..127).contains(&b) && b != b'\t' || trailing_space {
+                format!("={b:02X}")
+            } else {
+                (b as char).to_string()
+            };
+            if column + encoded.len() > 75 {
+                out.extend(b"=\r\n");
+                column = 0;
+            }
+            column += encoded.len();
+            out.extend(encoded.as_bytes());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mailparse::MailHeaderMap;
+
+    const RAW: &[u8] = b"From: \"Jane Client\" <jane@client.co.uk>\r\n\
+To: bob@contractor.com, Alice <alice@client.co.uk>\r\n\
+Subject: RE: Falcon Tower delay claim 2024\r\n\
+Message-ID: <abc123@mail.client.co.uk>\r\n\
+References: <root@mail.client.co.uk> <abc122@mail.client.co.uk>\r\n\
+Date: Tue, 5 Mar 2024 10:00:00 +0000\r\n\
+Received: from mx1.client.co.uk (10.1.2.3) by relay; Tue, 5 Mar 2024 10:00:01 +0000\r\n\
+Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+\r\n\
+--outer\r\n\
+Content-Type: text/html; charset=utf-8\r\n\
+Content-Transfer-Encoding: quoted-printable\r\n\
+\r\n\
+<p style=3D\"color:red\">Pay =C2=A3120,000 to Falcon</p><img src=3D\"cid:img1\">\r\n\
+--outer\r\n\
+Content-Type: application/pdf; name=\"Falcon claim.pdf\"\r\n\
+Content-Disposition: attachment; filename=\"Falcon claim.pdf\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+JVBERi0xLjQgRmFsY29uIHNlY3JldCBjb250ZW50\r\n\
+--outer--\r\n";
+
+    #[test]
+    fn scrubs_content_but_keeps_structure_and_linkage() {
+        let anon = Anonymizer::new("k");
+        let out = anon.message(RAW).expect("anonymize");
+        let text = String::from_utf8_lossy(&out);
+        for secret in ["Jane", "client", "Falcon", "120", "10.1.2.3", "abc123"] {
+            assert!(!text.contains(secret), "{secret} leaked:\n{text}");
+        }
+
+        let original = mailparse::parse_mail(RAW).expect("original");
+        let mail = mailparse::parse_mail(&out).expect("scrubbed parses");
+        assert_eq!(mail.ctype.mimetype, "multipart/mixed");
+        assert_eq!(mail.subparts.len(), 2);
+        assert_eq!(
+            mail.headers.get_first_value("Date"),
+            original.headers.get_first_value("Date")
+        );
+        let subject = mail.headers.get_first_value("Subject").expect("subject");
+        assert!(subject.starts_with("RE: "));
+        assert_eq!(subject.len(), "RE: Falcon Tower delay claim 2024".len());
+        let from = mail.headers.get_first_value("From").expect("from");
+        let to = mail.headers.get_first_value("To").expect("to");
+        // Same domain → same pseudonymous domain.
+        let domain = |s: &str| s.split('@').nth(1).map(|d| d[..9].to_string());
+        assert_eq!(domain(&from), domain(to.split(", ").nth(1).expect("alice")));
+        // Threading ids hash the same wherever they appear.
+        assert_eq!(
+            anon.ids("<abc122@mail.client.co.uk>"),
+            mail.headers
+                .get_first_value("References")
+                .expect("refs")
+                .split(' ')
+                .nth(1)
+                .expect("second")
+        );
+        assert!(mail
+            .headers
+            .get_first_value("Received")
+            .expect("received")
+            .ends_with("Tue, 5 Mar 2024 10:00:01 +0000"));
+
+        let html = mail.subparts[0].get_body().expect("html");
+        assert!(html.contains("src=\"cid:img1\""));
+        assert_eq!(
+            html.chars().count(),
+            original.subparts[0]
+                .get_body()
+                .expect("html")
+                .chars()
+                .count()
+        );
+        let pdf = &mail.subparts[1];
+        assert_eq!(
+            pdf.get_content_disposition().params["filename"].len(),
+            "Falcon claim.pdf".len()
+        );
+        let body = pdf.get_body_raw().expect("pdf");
+        assert!(body.starts_with(b"%PDF-1.4"));
+        assert_eq!(
+            body.len(),
+            original.subparts[1].get_body_raw().expect("pdf").len()
+        );
+        assert_eq!(
+            anon.folder_path("Inbox/Project Falcon/3"),
+            format!("Inbox/{}", anon.text("Project Falcon"))
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! In-process extraction: the job a `pst-extractor` run performs, driven from Rust.
+//!
+//! Services that ingest a mailbox on demand (API uploads, re-parsing a single mailbox) can call
+//! [`Extractor`] instead of launching the container. A [`Config`] takes the same arguments as
+//! the command line, or a `--config` file, and the job writes the usual outputs and manifest
+//! (set `output_dir` to keep them local). Records can be observed as they are written with
+//! [`Extractor::on_email`] and [`Extractor::on_attachment`], which receive the NDJSON record.
+//!
+//! A [`Config`] is parsed like the command line, so any option not given as an argument is
+//! read from its environment variable (`OUTPUT_BUCKET`, `READPST_PATH`, `RAW_BLOBS` and so on,
+//! as listed in the README), and the AWS SDK reads its usual variables. In a service whose
+//! environment is set up for something else, pass every option that matters as an argument:
+//! arguments always take precedence.
+//!
+//! Logging, OpenTelemetry and the SIGTERM handler belong to the caller; [`Extractor::run`]
+//! doesn't install them. [`select_email_bodies`](crate::select_email_bodies) is public too, for
+//! callers that only want the body selection for a parsed message.
+
+use crate::{clients, config, load_rules, run_job, upload, Args};
+use anyhow::{anyhow, Result};
+use clap::{CommandFactory, Parser};
+use serde::Serialize;
+use serde_json::Value;
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::Arc;
+
+type RecordFn = Arc<dyn Fn(&Value) + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct Callbacks {
+    email: Option<RecordFn>,
+    attachment: Option<RecordFn>,
+}
+
+tokio::task_local! {
+    static CALLBACKS: Callbacks;
+}
+
+/// Hand a finished email record to the running job's `on_email` callback, if any.
+pub(crate) fn emit_email(record: &impl Serialize) {
+    emit(record, |callbacks| callbacks.email.as_ref());
+}
+
+/// Hand an attachment record to the running job's `on_attachment` callback, if any.
+pub(crate) fn emit_attachment(record: &impl Serialize) {
+    emit(record, |callbacks| callbacks.attachment.as_ref());
+}
+
+fn emit(record: &impl Serialize, pick: impl Fn(&Callbacks) -> Option<&RecordFn>) {
+    let _ = CALLBACKS.try_with(|callbacks| {
+        if let (Some(callback), Ok(value)) = (pick(callbacks), serde_json::to_value(record)) {
+            callback(&value);
+        }
+    });
+}
+
+/// One extraction's arguments.
+#[derive(Debug, Clone)]
+pub struct Config {
+    args: Args,
+}
+
+impl Config {
+    /// Command-line style arguments, the first being the program name, e.g.
+    /// `["pst-extractor", "--pst-file-id", "p1", "--source-path", "a.pst", ...]`. As on the
+    /// command line, `--config` files and then the process environment fill in what isn't
+    /// given: an unset option with its variable set in this process (say `OUTPUT_BUCKET`) takes
+    /// that value, not the default.
+    pub fn from_args<I, T>(argv: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let argv = config::expand(argv.into_iter().map(Into::into).collect(), &Args::command())?;
+        let args = Args::try_parse_from(argv)?;
+        let many = args.worker || args.jobs_file.is_some() || args.serve.is_some();
+        if args.command.is_some() || many {
+            return Err(anyhow!(
+                "a Config describes one extraction, not a tool, worker, batch or server"
+            ));
+        }
+        Ok(Self { args })
+    }
+
+    /// Arguments from a TOML, YAML or JSON file, as with `--config`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().as_os_str().to_owned();
+        Self::from_args([OsString::from("pst-extractor"), "--config".into(), path])
+    }
+
+    pub fn pst_file_id(&self) -> &str {
+        &self.args.pst_file_id
+    }
+}
+
+/// How a finished extraction went.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    /// The manifest status: `complete`, `partial` or `interrupted`.
+    pub status: &'static str,
+    pub manifest_key: String,
+    pub emails_total: usize,
+    pub attachments_total: usize,
+    pub duration_s: f64,
+}
+
+/// Runs one extraction in-process.
+pub struct Extractor {
+    config: Config,
+    callbacks: Callbacks,
+}
+
+impl Extractor {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            callbacks: Callbacks::default(),
+        }
+    }
+
+    /// Called with each email record (as written to emails.ndjson, thread fields included)
+    /// once the parse pass has finished.
+    pub fn on_email(mut self, callback: impl Fn(&Value) + Send + Sync + 'static) -> Self {
+        self.callbacks.email = Some(Arc::new(callback));
+        self
+    }
+
+    /// Called with each attachment record as it is written.
+    pub fn on_attachment(mut self, callback: impl Fn(&Value) + Send + Sync + 'static) -> Self {
+        self.callbacks.attachment = Some(Arc::new(callback));
+        self
+    }
+
+    /// Download, extract, and upload or write the outputs and manifest, as a run of the
+    /// binary with the same arguments would; `--callback-url` is notified too. The upload part
+    /// size and concurrency apply to this run only, not to other extractors in the process.
+    pub async fn run(self) -> Result<Summary> {
+        let args = &self.config.args;
+        let (cfg, s3) = clients(args).await;
+        let rules = load_rules(args, &s3).await?;
+        let job = run_job(args, &cfg, &s3, rules.as_ref());
+        let job = upload::with_settings(args.upload_part_size, args.upload_concurrency, job);
+        let summary = CALLBACKS.scope(self.callbacks, job).await?;
+        Ok(Summary {
+            status: summary.status,
+            manifest_key: summary.manifest_key,
+            emails_total: summary.emails_total,
+            attachments_total: summary.attachments_total,
+            duration_s: summary.duration_s,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn runs_a_local_job_and_streams_records() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let dir = std::env::temp_dir().join(format!("api-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        std::fs::write(dir.join("a.pst"), "!BDN").expect("pst");
+        // The readpst stand-in, with its fixture directory baked in.
+        let readpst = dir.join("readpst.sh");
+        std::fs::write(
+            &readpst,
+            format!(
+                "#!/bin/sh\nFIXTURE_MAIL_DIR={} exec {} \"$@\"\n",
+                fixtures.join("mail").display(),
+                fixtures.join("readpst-shim.sh").display()
+            ),
+        )
+        .expect("shim");
+        std::process::Command::new("chmod")
+            .arg("+x")
+            .arg(&readpst)
+            .status()
+            .expect("chmod");
+        let path = |name: &str| dir.join(name).display().to_string();
+        let config = Config::from_args([
+            "pst-extractor".to_string(),
+            "--pst-file-id=p1".into(),
+            format!("--source-path={}", path("a.pst")),
+            format!("--output-dir={}", path("out")),
+            format!("--work-dir={}", path("work")),
+            format!("--readpst-path={}", path("readpst.sh")),
+            "--progress-interval-secs=0".into(),
+        ])
+        .expect("config");
+
+        let emails = Arc::new(Mutex::new(Vec::new()));
+        let attachments = Arc::new(Mutex::new(Vec::new()));
+        let summary = Extractor::new(config)
+            .on_email({
+                let emails = Arc::clone(&emails);
+                move |record| emails.lock().expect("emails").push(record["subject"].clone())
+            })
+            .on_attachment({
+                let attachments = Arc::clone(&attachments);
+                move |record| {
+                    let name = record["filename"].clone();
+                    attachments.lock().expect("attachments").push(name)
+                }
+            })
+            .run()
+            .await
+            .expect("run");
+        assert_eq!(summary.status, "complete");
+        assert_eq!(summary.emails_total, 3);
+        assert_eq!(emails.lock().expect("emails").len(), 3);
+        assert_eq!(*attachments.lock().expect("attachments"), ["notice.pdf"]);
+        assert!(dir.join("out/manifest.json").is_file());
+
+        assert!(Config::from_args(["pst-extractor", "--worker", "--queue-url", "q"]).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
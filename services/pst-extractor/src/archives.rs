@@ -0,0 +1,247 @@
+//! Archive attachments (`--expand-archives`): ZIP, tar and gzip.
+//!
+//! Responsive documents are often sent zipped. With expansion on, each plain archive attachment
+//! is unpacked, and its files become attachments of their own that point back at the archive
+//! (`parent_attachment_id`). The caller handles nesting: a member that is itself an archive is
+//! expanded again, up to the configured depth. A `.tar.gz` is two levels: the gzip layer yields
+//! one `.tar` member.
+//!
+//! Unpacking is bounded by a byte budget shared across an email's archives, which stops
+//! decompression bombs. An archive that hits the budget keeps the members read so far. Office
+//! documents are ZIPs as well, but [`kind`] only accepts what content sniffing calls a plain ZIP.
+//! 7z and RAR need codecs this crate doesn't carry; they are left as ordinary attachments.
+
+use crate::ziparchive::ZipArchive;
+use std::io::{Cursor, Read};
+
+/// Members taken from a single archive.
+const MAX_MEMBERS: usize = 10_000;
+const TAR_BLOCK: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Zip,
+    Tar,
+    Gzip,
+}
+
+/// The archive kind of an attachment, or None for anything that shouldn't be unpacked.
+pub fn kind(data: &[u8]) -> Option<Kind> {
+    if data.get(257..262) == Some(b"ustar") {
+        return Some(Kind::Tar);
+    }
+    match crate::sniff::detect(data)? {
+        "application/zip" => Some(Kind::Zip),
+        "application/gzip" => Some(Kind::Gzip),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct Member {
+    /// Path inside the archive, `/`-separated.
+    pub path: String,
+    pub content: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Expansion {
+    pub members: Vec<Member>,
+    /// `expanded`, `encrypted` (password-protected entries were skipped), `limit_exceeded`
+    /// (byte budget or member cap reached) or `corrupt` (unreadable past some point).
+    pub status: &'static str,
+}
+
+/// Unpack one archive level. `name` is the attachment's filename (names the gzip member);
+/// `budget` is the number of decompressed bytes still allowed and is reduced by what's read.
+pub fn expand(kind: Kind, name: &str, data: &[u8], budget: &mut u64) -> Expansion {
+    let mut members = Vec::new();
+    let status = match kind {
+        Kind::Zip => expand_zip(data, budget, &mut members),
+        Kind::Tar => expand_tar(data, budget, &mut members),
+        Kind::Gzip => expand_gzip(name, data, budget, &mut members),
+    };
+    Expansion { members, status }
+}
+
+fn expand_zip(data: &[u8], budget: &mut u64, out: &mut Vec<Member>) -> &'static str {
+    let Ok(mut zip) = ZipArchive::new(Cursor::new(data)) else {
+        return "corrupt";
+    };
+    let mut status = "expanded";
+    for idx in 0..zip.entries().len() {
+        let entry = zip.entries()[idx].clone();
+        if entry.is_dir() {
+            continue;
+        }
+        if entry.is_encrypted() {
+            status = "encrypted";
+            continue;
+        }
+        if out.len() >= MAX_MEMBERS || entry.size > *budget {
+            return "limit_exceeded";
+        }
+        match zip.read(idx) {
+            Ok(content) => {
+                *budget -= content.len() as u64;
+                out.push(Member {
+                    path: entry.name,
+                    content,
+                });
+            }
+            Err(_) => return "corrupt",
+        }
+    }
+    status
+}
+
+/// Octal numeric field of a tar header (NUL/space padded).
+fn tar_number(field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// `path` from a pax extended header (`<len> path=<value>\n` records).
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .filter_map(|line| line.split_once(' ').map(|(_, record)| record))
+        .find_map(|record| record.strip_prefix("path="))
+        .map(str::to_string)
+}
+
+fn expand_tar(data: &[u8], budget: &mut u64, out: &mut Vec<Member>) -> &'static str {
+    let mut at = 0usize;
+    let mut long_name: Option<String> = None;
+    while at + TAR_BLOCK <= data.len() {
+        let header = &data[at..at + TAR_BLOCK];
+        if header.iter().all(|b| *b == 0) {
+            return "expanded";
+        }
+        let Some(size) = tar_number(&header[124..136]) else {
+            return "corrupt";
+        };
+        let body_start = at + TAR_BLOCK;
+        let Some(body) = usize::try_from(size)
+            .ok()
+            .and_then(|size| data.get(body_start..body_start.checked_add(size)?))
+        else {
+            return "corrupt";
+        };
+        at = body_start + body.len().div_ceil(TAR_BLOCK) * TAR_BLOCK;
+        match header[156] {
+            // GNU long name / pax extended header: applies to the next entry.
+            b'L' => long_name = Some(tar_string(body)),
+            b'x' => long_name = pax_path(body),
+            b'0' | 0 | b'7' => {
+                let path = long_name.take().unwrap_or_else(|| {
+                    let name = tar_string(&header[0..100]);
+                    let prefix = tar_string(&header[345..500]);
+                    if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                        format!("{prefix}/{name}")
+                    } else {
+                        name
+                    }
+                });
+                if out.len() >= MAX_MEMBERS || size > *budget {
+                    return "limit_exceeded";
+                }
+                *budget -= size;
+                out.push(Member {
+                    path,
+                    content: body.to_vec(),
+                });
+            }
+            // Directories, links, devices, global pax headers.
+            _ => long_name = None,
+        }
+    }
+    "expanded"
+}
+
+fn expand_gzip(name: &str, data: &[u8], budget: &mut u64, out: &mut Vec<Member>) -> &'static str {
+    let mut content = Vec::new();
+    let read = flate2::read::MultiGzDecoder::new(data)
+        .take(budget.saturating_add(1))
+        .read_to_end(&mut content);
+    if read.is_err() {
+        return "corrupt";
+    }
+    if content.len() as u64 > *budget {
+        return "limit_exceeded";
+    }
+    *budget -= content.len() as u64;
+    let lower = name.to_ascii_lowercase();
+    let path = if lower.ends_with(".tgz") {
+        format!("{}.tar", &name[..name.len() - 4])
+    } else if lower.ends_with(".gz") {
+        name[..name.len() - 3].to_string()
+    } else {
+        format!("{name}.out")
+    };
+    out.push(Member { path, content });
+    "expanded"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (name, data) in files {
+            let mut header = [0u8; TAR_BLOCK];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+            header[156] = b'0';
+            header[257..263].copy_from_slice(b"ustar\0");
+            out.extend(header);
+            out.extend(*data);
+            out.resize(out.len().div_ceil(TAR_BLOCK) * TAR_BLOCK, 0);
+        }
+        out.extend([0u8; TAR_BLOCK * 2]);
+        out
+    }
+
+    #[test]
+    fn unpacks_tar_gz_in_two_levels_within_budget() {
+        let tarball = tar(&[("docs/claim.txt", b"Notice of delay"), ("b.bin", &[1; 700])]);
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tarball).expect("gzip");
+        let gz = gz.finish().expect("gzip");
+
+        assert_eq!(kind(&gz), Some(Kind::Gzip));
+        let mut budget = 1 << 20;
+        let outer = expand(Kind::Gzip, "Claim.TGZ", &gz, &mut budget);
+        assert_eq!(outer.status, "expanded");
+        assert_eq!(outer.members[0].path, "Claim.tar");
+        let inner_data = &outer.members[0].content;
+        assert_eq!(kind(inner_data), Some(Kind::Tar));
+        let inner = expand(Kind::Tar, "Claim.tar", inner_data, &mut budget);
+        assert_eq!(inner.status, "expanded");
+        assert_eq!(inner.members[0].path, "docs/claim.txt");
+        assert_eq!(inner.members[0].content, b"Notice of delay");
+        assert_eq!(inner.members[1].content.len(), 700);
+        assert_eq!(budget, (1 << 20) - tarball.len() as u64 - 715);
+
+        let mut small = 100;
+        let bomb = expand(Kind::Tar, "x.tar", &tarball, &mut small);
+        assert_eq!(bomb.status, "limit_exceeded");
+        assert_eq!(bomb.members.len(), 1);
+
+        let zip = crate::ziparchive::build(&[("a.txt", b"alpha")]);
+        assert_eq!(kind(&zip), Some(Kind::Zip));
+        let docx = crate::ziparchive::build(&[("word/document.xml", b"<w/>")]);
+        assert_eq!(kind(&docx), None);
+    }
+}
@@ -0,0 +1,124 @@
+//! Which attachments are stored (`--max-attachment-bytes`, `--attachment-type-allowlist`,
+//! `--attachment-type-blocklist`).
+//!
+//! A single mailbox full of site videos can dominate the output bucket and the run time, while
+//! adding nothing to review. Skipped attachments still get an attachment record with their hash
+//! and a `skipped_reason`, so the gap is documented, but nothing is written to S3.
+//!
+//! Type patterns are MIME types (`video/mp4`), MIME families (`video/*`) or extensions (`.mov`).
+//! A pattern matches when the detected type, the declared type or the filename's extension does.
+
+pub struct AttachmentPolicy {
+    max_bytes: Option<u64>,
+    allow: Vec<String>,
+    block: Vec<String>,
+}
+
+fn normalize(patterns: &[String]) -> Vec<String> {
+    patterns
+        .iter()
+        .map(|p| p.trim().to_ascii_lowercase())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn matches(pattern: &str, mime_types: &[&str], extension: Option<&str>) -> bool {
+    if let Some(ext) = pattern.strip_prefix('.') {
+        return extension.is_some_and(|e| e == ext);
+    }
+    mime_types
+        .iter()
+        .any(|mime| match pattern.strip_suffix("/*") {
+            Some(family) => mime.split('/').next() == Some(family),
+            None => *mime == pattern,
+        })
+}
+
+impl AttachmentPolicy {
+    pub fn new(max_bytes: Option<u64>, allow: &[String], block: &[String]) -> Self {
+        Self {
+            max_bytes: max_bytes.filter(|m| *m > 0),
+            allow: normalize(allow),
+            block: normalize(block),
+        }
+    }
+
+    /// Why the attachment should not be stored: `too_large`, `type_blocked` or
+    /// `type_not_allowed`. None to keep it.
+    pub fn skip_reason(
+        &self,
+        size: u64,
+        filename: &str,
+        declared: Option<&str>,
+        detected: Option<&str>,
+    ) -> Option<&'static str> {
+        if self.max_bytes.is_some_and(|max| size > max) {
+            return Some("too_large");
+        }
+        let mut mime_types: Vec<String> = [detected, declared]
+            .into_iter()
+            .flatten()
+            .map(|m| {
+                m.split(';')
+                    .next()
+                    .unwrap_or("")
+                    .trim()
+                    .to_ascii_lowercase()
+            })
+            .collect();
+        mime_types.dedup();
+        let mime_types: Vec<&str> = mime_types.iter().map(String::as_str).collect();
+        let extension = filename
+            .rsplit_once('.')
+            .map(|(_, e)| e.to_ascii_lowercase());
+        let hit = |p: &String| matches(p, &mime_types, extension.as_deref());
+        if self.block.iter().any(hit) {
+            return Some("type_blocked");
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(hit) {
+            return Some("type_not_allowed");
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_block_and_allow_rules() {
+        let list = |s: &str| s.split(',').map(str::to_string).collect::<Vec<_>>();
+        let policy = AttachmentPolicy::new(Some(1000), &[], &list("video/*, .MOV"));
+        assert_eq!(
+            policy.skip_reason(5000, "a.pdf", None, Some("application/pdf")),
+            Some("too_large")
+        );
+        assert_eq!(
+            policy.skip_reason(
+                10,
+                "site.mp4",
+                Some("application/octet-stream"),
+                Some("video/mp4")
+            ),
+            Some("type_blocked")
+        );
+        assert_eq!(
+            policy.skip_reason(10, "walkround.mov", None, None),
+            Some("type_blocked")
+        );
+        assert_eq!(policy.skip_reason(10, "a.pdf", None, None), None);
+
+        let allow = AttachmentPolicy::new(None, &list("application/pdf,image/*"), &[]);
+        assert_eq!(
+            allow.skip_reason(10, "x.bin", Some("image/png; name=x.png"), None),
+            None
+        );
+        assert_eq!(
+            allow.skip_reason(10, "x.exe", None, Some("application/x-msdownload")),
+            Some("type_not_allowed")
+        );
+        let unlimited = AttachmentPolicy::new(Some(0), &[], &[]);
+        assert_eq!(unlimited.skip_reason(u64::MAX, "a.mp4", None, None), None);
+    }
+}
@@ -0,0 +1,289 @@
+//! `--jobs-file`: extract several PSTs in one run.
+//!
+//! Starting a container per small PST spends most of the run on cold start and image pull. A
+//! job list runs them all in one process instead: each entry overlays the run's own arguments,
+//! like a worker-mode queue message, and is extracted exactly as a single-PST run would be
+//! (its own work dir, outputs, manifest and callback).
+//!
+//! The list is a JSON array of objects, or CSV with a header row naming the fields (typically
+//! `pst_file_id,source_key,output_prefix`). CSV cells are strings, converted to a bool or number
+//! where the argument is one; list-valued or optional numeric options need the JSON form.
+//!
+//! Jobs run `--jobs-concurrency` at a time, each on its own thread, since readpst and parsing
+//! block. After SIGTERM no further job starts. One failed job doesn't stop the others; the
+//! batch summary lists every job's outcome.
+
+use crate::{rules::RuleSet, run_job, watchdog, worker, Args};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::{error, info};
+
+/// One job's outcome in the batch summary.
+#[derive(Serialize, Debug)]
+pub struct JobResult {
+    pub pst_file_id: String,
+    /// The manifest status (`complete`, `partial`, `interrupted`), `failed`, or `skipped` when
+    /// SIGTERM arrived before the job started.
+    pub status: &'static str,
+    pub manifest_key: Option<String>,
+    pub emails_total: Option<usize>,
+    pub attachments_total: Option<usize>,
+    pub duration_s: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchSummary {
+    pub jobs_total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub emails_total: usize,
+    pub attachments_total: usize,
+    pub duration_s: f64,
+    pub jobs: Vec<JobResult>,
+}
+
+impl BatchSummary {
+    fn new(jobs: Vec<JobResult>, duration_s: f64) -> Self {
+        let count = |status: &str| jobs.iter().filter(|j| j.status == status).count();
+        Self {
+            jobs_total: jobs.len(),
+            succeeded: jobs.len() - count("failed") - count("skipped"),
+            failed: count("failed"),
+            skipped: count("skipped"),
+            emails_total: jobs.iter().filter_map(|j| j.emails_total).sum(),
+            attachments_total: jobs.iter().filter_map(|j| j.attachments_total).sum(),
+            duration_s,
+            jobs,
+        }
+    }
+
+    pub fn interrupted(&self) -> bool {
+        self.skipped > 0 || self.jobs.iter().any(|j| j.status == "interrupted")
+    }
+}
+
+/// Parse a job list: a JSON array of objects, or CSV with a header row.
+pub fn parse_jobs(text: &str) -> Result<Vec<Map<String, Value>>> {
+    if text.trim_start().starts_with('[') {
+        let jobs: Vec<Value> = serde_json::from_str(text).context("jobs file is not valid JSON")?;
+        return jobs
+            .into_iter()
+            .enumerate()
+            .map(|(n, job)| match job {
+                Value::Object(fields) => Ok(fields),
+                _ => Err(anyhow!("job {} is not a JSON object", n + 1)),
+            })
+            .collect();
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes());
+    let header = reader
+        .headers()
+        .context("jobs file has no CSV header")?
+        .clone();
+    let mut jobs = Vec::new();
+    for (n, row) in reader.records().enumerate() {
+        let row = row.with_context(|| format!("jobs file row {}", n + 2))?;
+        let fields = header
+            .iter()
+            .zip(row.iter())
+            .filter(|(_, cell)| !cell.is_empty())
+            .map(|(name, cell)| (name.to_string(), Value::String(cell.to_string())))
+            .collect();
+        jobs.push(fields);
+    }
+    Ok(jobs)
+}
+
+/// A job's arguments: `base` overlaid with the job's fields. String values of bool and number
+/// arguments (as CSV gives them) are converted first.
+pub fn job_args(base: &Args, job: &Map<String, Value>) -> Result<Args> {
+    let defaults = serde_json::to_value(base)?;
+    let mut fields = job.clone();
+    for (name, value) in fields.iter_mut() {
+        let Value::String(text) = value else { continue };
+        *value = match &defaults[name.as_str()] {
+            Value::Bool(_) => Value::Bool(
+                text.parse()
+                    .with_context(|| format!("{name}: expected true or false"))?,
+            ),
+            Value::Number(_) => {
+                serde_json::from_str(text).with_context(|| format!("{name}: expected a number"))?
+            }
+            _ => continue,
+        };
+    }
+    let args = worker::overlay(base, &fields)?;
+    let missing = [
+        ("pst_file_id", args.pst_file_id.is_empty()),
+        ("output_prefix", args.output_prefix.is_empty()),
+        (
+            "source_key",
+            args.source_path.is_none() && args.source_key.is_empty(),
+        ),
+        (
+            "source_bucket",
+            args.source_path.is_none() && args.source_bucket.is_empty(),
+        ),
+        (
+            "output_bucket",
+            args.output_dir.is_none() && args.output_bucket.is_empty(),
+        ),
+    ];
+    if let Some((name, _)) = missing.iter().find(|(_, missing)| *missing) {
+        return Err(anyhow!("missing {name}"));
+    }
+    crate::diskspace::check_job_id(&args.pst_file_id)?;
+    Ok(args)
+}
+
+/// Every job's arguments, checked before any job starts.
+pub fn plan(base: &Args, jobs: &[Map<String, Value>]) -> Result<Vec<Args>> {
+    let mut ids = HashSet::new();
+    let mut planned = Vec::with_capacity(jobs.len());
+    for (n, job) in jobs.iter().enumerate() {
+        let args = job_args(base, job).with_context(|| format!("job {}", n + 1))?;
+        if !ids.insert(args.pst_file_id.clone()) {
+            return Err(anyhow!(
+                "job {}: duplicate pst_file_id {}",
+                n + 1,
+                args.pst_file_id
+            ));
+        }
+        planned.push(args);
+    }
+    Ok(planned)
+}
+
+/// Run the jobs, `concurrency` at a time, and summarize them in list order.
+pub fn run(
+    jobs: Vec<Args>,
+    concurrency: usize,
+    cfg: &aws_config::SdkConfig,
+    s3: &aws_sdk_s3::Client,
+    rules: Option<&RuleSet>,
+) -> BatchSummary {
+    let started = Instant::now();
+    let handle = tokio::runtime::Handle::current();
+    let total = jobs.len();
+    let queue = Mutex::new(jobs.into_iter().enumerate());
+    let results = Mutex::new(Vec::with_capacity(total));
+    info!(jobs = total, concurrency, "batch starting");
+    tokio::task::block_in_place(|| {
+        std::thread::scope(|scope| {
+            for _ in 0..concurrency.clamp(1, total.max(1)) {
+                scope.spawn(|| loop {
+                    let Some((n, args)) = queue.lock().expect("job queue").next() else {
+                        break;
+                    };
+                    let result = if watchdog::terminating() {
+                        JobResult::skipped(&args)
+                    } else {
+                        let outcome = handle.block_on(run_job(&args, cfg, s3, rules));
+                        JobResult::new(&args, outcome)
+                    };
+                    results.lock().expect("job results").push((n, result));
+                });
+            }
+        })
+    });
+    let mut results = results.into_inner().expect("job results");
+    results.sort_by_key(|(n, _)| *n);
+    let summary = BatchSummary::new(
+        results.into_iter().map(|(_, r)| r).collect(),
+        started.elapsed().as_secs_f64(),
+    );
+    info!(
+        jobs = summary.jobs_total,
+        succeeded = summary.succeeded,
+        failed = summary.failed,
+        skipped = summary.skipped,
+        "batch finished"
+    );
+    summary
+}
+
+impl JobResult {
+    pub(crate) fn new(args: &Args, outcome: Result<crate::JobSummary>) -> Self {
+        match outcome {
+            Ok(summary) => Self {
+                pst_file_id: args.pst_file_id.clone(),
+                status: summary.status,
+                manifest_key: Some(summary.manifest_key),
+                emails_total: Some(summary.emails_total),
+                attachments_total: Some(summary.attachments_total),
+                duration_s: Some(summary.duration_s),
+                error: None,
+            },
+            Err(e) => {
+                error!(pst_file_id = %args.pst_file_id, "job failed: {e:#}");
+                Self {
+                    status: "failed",
+                    error: Some(format!("{e:#}")),
+                    ..Self::skipped(args)
+                }
+            }
+        }
+    }
+
+    pub(crate) fn skipped(args: &Args) -> Self {
+        Self {
+            pst_file_id: args.pst_file_id.clone(),
+            status: "skipped",
+            manifest_key: None,
+            emails_total: None,
+            attachments_total: None,
+            duration_s: None,
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn parses_json_and_csv_job_lists() {
+        let base = Args::parse_from([
+            "pst-extractor",
+            "--jobs-file",
+            "jobs.csv",
+            "--source-bucket",
+            "in",
+            "--output-bucket",
+            "out",
+        ]);
+        let csv = "pst_file_id, source_key, output_prefix, recovery_mode\n\
+                   p1, a.pst, x/p1/, true\n\
+                   p2,\"b, final.pst\", x/p2/,\n";
+        let jobs = plan(&base, &parse_jobs(csv).expect("csv")).expect("plan");
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[1].source_key, "b, final.pst");
+        assert!(jobs[0].recovery_mode);
+        assert!(!jobs[1].recovery_mode);
+        assert_eq!(jobs[0].source_bucket, "in");
+
+        let json = r#"[{"pst_file_id":"p1","source_key":"a.pst","output_prefix":"x/",
+                        "gzip_member_bytes":1024}]"#;
+        let jobs = plan(&base, &parse_jobs(json).expect("json")).expect("plan");
+        assert_eq!(jobs[0].gzip_member_bytes, 1024);
+
+        let missing = parse_jobs("pst_file_id,source_key\np1,a.pst\n").expect("csv");
+        let err = plan(&base, &missing).expect_err("no output_prefix");
+        assert!(format!("{err:#}").contains("missing output_prefix"));
+        let twice = parse_jobs("pst_file_id,source_key,output_prefix\np1,a,x\np1,b,y\n");
+        assert!(plan(&base, &twice.expect("csv")).is_err());
+        let typo = parse_jobs("pst_file_id,source_key,output_prefix,recovery_mode\np,a,x,yes\n");
+        assert!(plan(&base, &typo.expect("csv")).is_err());
+        assert!(parse_jobs("[1]").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Completion webhook.
+//!
+//! On success or failure the extractor POSTs a JSON summary to `--callback-url`, so the platform
+//! doesn't have to poll S3 for manifest.json. When `--callback-secret` is set the request carries
+//! `X-Signature-256: sha256=<hex>` = HMAC-SHA256(secret, "{X-Timestamp}.{body}").
+
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const CALLBACK_MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Serialize)]
+pub struct CallbackPayload {
+    pub status: &'static str,
+    pub pst_file_id: String,
+    pub project_id: Option<String>,
+    pub case_id: Option<String>,
+    pub output_bucket: String,
+    pub manifest_key: Option<String>,
+    pub emails_total: Option<usize>,
+    pub attachments_total: Option<usize>,
+    pub duration_s: Option<f64>,
+    pub error: Option<String>,
+}
+
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256={hex}")
+}
+
+/// POST the payload, retrying on network errors and 5xx/429 responses with exponential backoff.
+pub async fn notify(url: &str, secret: Option<&str>, payload: &CallbackPayload) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let body = serde_json::to_vec(payload)?;
+    let mut last_err = anyhow!("callback not attempted");
+    for attempt in 1..=CALLBACK_MAX_ATTEMPTS {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut req = client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Timestamp", timestamp.to_string())
+            .body(body.clone());
+        if let Some(secret) = secret {
+            req = req.header("X-Signature-256", sign(secret, timestamp, &body));
+        }
+        match req.send().await {
+            Ok(resp) if resp.status().is_success() => return Ok(()),
+            Ok(resp) if resp.status().is_client_error() && resp.status().as_u16() != 429 => {
+                return Err(anyhow!("callback rejected with HTTP {}", resp.status()));
+            }
+            Ok(resp) => last_err = anyhow!("callback returned HTTP {}", resp.status()),
+            Err(e) => last_err = e.into(),
+        }
+        if attempt < CALLBACK_MAX_ATTEMPTS {
+            tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+        }
+    }
+    Err(last_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_covers_timestamp_and_body() {
+        let sig = sign("secret", 1700000000, b"{\"status\":\"succeeded\"}");
+        assert!(sig.starts_with("sha256="));
+        assert_eq!(sig.len(), "sha256=".len() + 64);
+        assert_ne!(
+            sig,
+            sign("secret", 1700000001, b"{\"status\":\"succeeded\"}")
+        );
+        assert_ne!(
+            sig,
+            sign("other", 1700000000, b"{\"status\":\"succeeded\"}")
+        );
+    }
+}
@@ -0,0 +1,437 @@
+//! Read-only OLE2 / Compound File Binary reader, enough to walk an Outlook `.msg` file.
+//!
+//! A compound file is a small FAT filesystem: a header, a sector allocation table (FAT), a
+//! directory of storages (folders) and streams (files) kept as a red-black tree per storage, and a
+//! "mini stream" holding streams under 4 KiB in 64-byte sectors. Corrupt chains are cut short
+//! rather than followed forever.
+
+const SIGNATURE: [u8; 8] = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+const END_OF_CHAIN: u32 = 0xFFFF_FFFE;
+const NO_STREAM: u32 = 0xFFFF_FFFF;
+const HEADER_DIFAT_ENTRIES: usize = 109;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Storage,
+    Stream,
+    Root,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    left: u32,
+    right: u32,
+    child: u32,
+    start: u32,
+    size: u64,
+}
+
+pub struct CompoundFile<'a> {
+    data: &'a [u8],
+    sector_shift: u32,
+    mini_shift: u32,
+    mini_cutoff: u64,
+    fat: Vec<u32>,
+    minifat: Vec<u32>,
+    mini_stream: Vec<u8>,
+    entries: Vec<DirEntry>,
+}
+
+pub fn is_compound_file(data: &[u8]) -> bool {
+    data.starts_with(&SIGNATURE)
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_le_bytes(b.try_into().expect("2 bytes")))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().expect("4 bytes")))
+}
+
+fn u64_at(data: &[u8], at: usize) -> Option<u64> {
+    data.get(at..at + 8)
+        .map(|b| u64::from_le_bytes(b.try_into().expect("8 bytes")))
+}
+
+impl<'a> CompoundFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if !is_compound_file(data) || data.len() < 512 {
+            return None;
+        }
+        let sector_shift = u16_at(data, 0x1E)? as u32;
+        let mini_shift = u16_at(data, 0x20)? as u32;
+        if !(7..=16).contains(&sector_shift) || mini_shift >= sector_shift {
+            return None;
+        }
+        let mut cf = CompoundFile {
+            data,
+            sector_shift,
+            mini_shift,
+            mini_cutoff: u32_at(data, 0x38)? as u64,
+            fat: Vec::new(),
+            minifat: Vec::new(),
+            mini_stream: Vec::new(),
+            entries: Vec::new(),
+        };
+
+        // FAT sector ids: 109 in the header, the rest in a chain of DIFAT sectors.
+        let fat_sectors = u32_at(data, 0x2C)? as usize;
+        let mut fat_ids: Vec<u32> = (0..HEADER_DIFAT_ENTRIES)
+            .filter_map(|i| u32_at(data, 0x4C + i * 4))
+            .collect();
+        let per_sector = cf.sector_size() / 4;
+        let mut difat = u32_at(data, 0x44)?;
+        let mut guard = 0;
+        while difat < END_OF_CHAIN && guard < 1 << 16 {
+            let sector = cf.sector(difat)?;
+            fat_ids.extend((0..per_sector - 1).filter_map(|i| u32_at(sector, i * 4)));
+            difat = u32_at(sector, (per_sector - 1) * 4)?;
+            guard += 1;
+        }
+        fat_ids.truncate(fat_sectors);
+        for id in fat_ids {
+            let sector = cf.sector(id)?;
+            cf.fat
+                .extend((0..per_sector).filter_map(|i| u32_at(sector, i * 4)));
+        }
+
+        let dir = cf.read_chain(u32_at(data, 0x30)?, None);
+        cf.entries = dir.chunks_exact(128).filter_map(parse_entry).collect();
+        if sector_shift == 9 {
+            // Version 3 files may leave junk in the high half of the size field.
+            for entry in &mut cf.entries {
+                entry.size &= 0xFFFF_FFFF;
+            }
+        }
+        let root = cf.entries.first()?.clone();
+        if root.kind != EntryKind::Root {
+            return None;
+        }
+        let minifat = cf.read_chain(u32_at(data, 0x3C)?, None);
+        cf.minifat = minifat
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes(b.try_into().expect("4 bytes")))
+            .collect();
+        cf.mini_stream = cf.read_chain(root.start, Some(root.size));
+        Some(cf)
+    }
+
+    fn sector_size(&self) -> usize {
+        1 << self.sector_shift
+    }
+
+    fn sector(&self, id: u32) -> Option<&'a [u8]> {
+        let start = (id as usize + 1) << self.sector_shift;
+        self.data.get(start..start + self.sector_size())
+    }
+
+    fn read_chain(&self, start: u32, size: Option<u64>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut id = start;
+        let limit = self.fat.len().max(1);
+        let mut steps = 0;
+        while id < END_OF_CHAIN && steps <= limit {
+            let Some(sector) = self.sector(id) else {
+                break;
+            };
+            out.extend_from_slice(sector);
+            if size.is_some_and(|s| out.len() as u64 >= s) {
+                break;
+            }
+            id = self.fat.get(id as usize).copied().unwrap_or(END_OF_CHAIN);
+            steps += 1;
+        }
+        if let Some(size) = size {
+            out.truncate(size as usize);
+        }
+        out
+    }
+
+    fn read_mini_chain(&self, start: u32, size: u64) -> Vec<u8> {
+        let mini = 1usize << self.mini_shift;
+        let mut out = Vec::new();
+        let mut id = start;
+        let mut steps = 0;
+        while id < END_OF_CHAIN && (out.len() as u64) < size && steps <= self.minifat.len() {
+            let at = id as usize * mini;
+            let Some(chunk) = self.mini_stream.get(at..at + mini) else {
+                break;
+            };
+            out.extend_from_slice(chunk);
+            id = self
+                .minifat
+                .get(id as usize)
+                .copied()
+                .unwrap_or(END_OF_CHAIN);
+            steps += 1;
+        }
+        out.truncate(size as usize);
+        out
+    }
+
+    pub fn root(&self) -> usize {
+        0
+    }
+
+    pub fn entry(&self, id: usize) -> &DirEntry {
+        &self.entries[id]
+    }
+
+    /// Entries directly inside storage `id`.
+    pub fn children(&self, id: usize) -> Vec<usize> {
+        let mut out = Vec::new();
+        let mut stack = vec![self.entries[id].child];
+        while let Some(node) = stack.pop() {
+            if node == NO_STREAM || out.len() >= self.entries.len() {
+                continue;
+            }
+            let Some(entry) = self.entries.get(node as usize) else {
+                continue;
+            };
+            out.push(node as usize);
+            stack.push(entry.left);
+            stack.push(entry.right);
+        }
+        out
+    }
+
+    /// The child of storage `id` called `name` (case-insensitive, as in the CFB spec).
+    pub fn child(&self, id: usize, name: &str) -> Option<usize> {
+        self.children(id)
+            .into_iter()
+            .find(|&c| self.entries[c].name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn read(&self, id: usize) -> Vec<u8> {
+        let entry = &self.entries[id];
+        if entry.kind != EntryKind::Stream {
+            return Vec::new();
+        }
+        if entry.size < self.mini_cutoff {
+            self.read_mini_chain(entry.start, entry.size)
+        } else {
+            self.read_chain(entry.start, Some(entry.size))
+        }
+    }
+}
+
+fn parse_entry(raw: &[u8]) -> Option<DirEntry> {
+    let kind = match raw[66] {
+        1 => EntryKind::Storage,
+        2 => EntryKind::Stream,
+        5 => EntryKind::Root,
+        // Unused slot.
+        _ => {
+            return Some(DirEntry {
+                name: String::new(),
+                kind: EntryKind::Stream,
+                left: NO_STREAM,
+                right: NO_STREAM,
+                child: NO_STREAM,
+                start: END_OF_CHAIN,
+                size: 0,
+            })
+        }
+    };
+    let name_len = (u16_at(raw, 64)? as usize).min(64);
+    let units: Vec<u16> = raw[..name_len.saturating_sub(2)]
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    Some(DirEntry {
+        name: String::from_utf16_lossy(&units),
+        kind,
+        left: u32_at(raw, 68)?,
+        right: u32_at(raw, 72)?,
+        child: u32_at(raw, 76)?,
+        start: u32_at(raw, 116)?,
+        size: u64_at(raw, 120)?,
+    })
+}
+
+/// Tree of storages and streams for [`build`].
+#[cfg(test)]
+pub enum TestNode<'a> {
+    Stream(&'a str, Vec<u8>),
+    Storage(&'a str, Vec<TestNode<'a>>),
+}
+
+/// Build a compound file (512-byte sectors, every stream in regular sectors) for tests.
+#[cfg(test)]
+pub fn build(nodes: &[TestNode]) -> Vec<u8> {
+    struct Flat {
+        name: String,
+        kind: u8,
+        right: u32,
+        child: u32,
+        data: Vec<u8>,
+    }
+    // Siblings are chained through `right`, which is a valid (degenerate) red-black tree.
+    fn add(nodes: &[TestNode], flat: &mut Vec<Flat>) -> u32 {
+        let mut first = NO_STREAM;
+        let mut prev: Option<usize> = None;
+        for node in nodes {
+            let idx = flat.len();
+            let (name, kind, data) = match node {
+                TestNode::Stream(name, data) => (*name, 2, data.clone()),
+                TestNode::Storage(name, _) => (*name, 1, Vec::new()),
+            };
+            flat.push(Flat {
+                name: name.to_string(),
+                kind,
+                right: NO_STREAM,
+                child: NO_STREAM,
+                data,
+            });
+            if let TestNode::Storage(_, children) = node {
+                flat[idx].child = add(children, flat);
+            }
+            match prev {
+                Some(p) => flat[p].right = idx as u32,
+                None => first = idx as u32,
+            }
+            prev = Some(idx);
+        }
+        first
+    }
+    let mut flat = vec![Flat {
+        name: "Root Entry".into(),
+        kind: 5,
+        right: NO_STREAM,
+        child: NO_STREAM,
+        data: Vec::new(),
+    }];
+    flat[0].child = add(nodes, &mut flat);
+
+    // Sector 0: FAT, then the directory, then stream data.
+    let dir_sectors = flat.len().div_ceil(4);
+    let mut fat: Vec<u32> = vec![0xFFFF_FFFD];
+    for i in 0..dir_sectors {
+        fat.push(if i + 1 == dir_sectors {
+            END_OF_CHAIN
+        } else {
+            i as u32 + 2
+        });
+    }
+    let mut data_sectors: Vec<u8> = Vec::new();
+    let mut dir = Vec::new();
+    for f in &flat {
+        let mut start = END_OF_CHAIN;
+        let chunks: Vec<&[u8]> = f.data.chunks(512).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            if i == 0 {
+                start = fat.len() as u32;
+            }
+            data_sectors.extend_from_slice(chunk);
+            data_sectors.resize(data_sectors.len().div_ceil(512) * 512, 0);
+            fat.push(if i + 1 == chunks.len() {
+                END_OF_CHAIN
+            } else {
+                fat.len() as u32 + 1
+            });
+        }
+        let mut e = vec![0u8; 128];
+        let units: Vec<u16> = f.name.encode_utf16().collect();
+        for (i, u) in units.iter().enumerate() {
+            e[i * 2..i * 2 + 2].copy_from_slice(&u.to_le_bytes());
+        }
+        e[64..66].copy_from_slice(&(((units.len() + 1) * 2) as u16).to_le_bytes());
+        e[66] = f.kind;
+        e[68..72].copy_from_slice(&NO_STREAM.to_le_bytes());
+        e[72..76].copy_from_slice(&f.right.to_le_bytes());
+        e[76..80].copy_from_slice(&f.child.to_le_bytes());
+        e[116..120].copy_from_slice(&start.to_le_bytes());
+        e[120..128].copy_from_slice(&(f.data.len() as u64).to_le_bytes());
+        dir.extend(e);
+    }
+    assert!(fat.len() <= 128, "test builder uses a single FAT sector");
+    dir.resize(dir_sectors * 512, 0);
+
+    let mut out = vec![0u8; 512];
+    out[..8].copy_from_slice(&SIGNATURE);
+    out[0x1A..0x1C].copy_from_slice(&3u16.to_le_bytes());
+    out[0x1C..0x1E].copy_from_slice(&0xFFFEu16.to_le_bytes());
+    out[0x1E..0x20].copy_from_slice(&9u16.to_le_bytes());
+    out[0x20..0x22].copy_from_slice(&6u16.to_le_bytes());
+    out[0x2C..0x30].copy_from_slice(&1u32.to_le_bytes());
+    out[0x30..0x34].copy_from_slice(&1u32.to_le_bytes());
+    // Cutoff 0 keeps every stream in regular sectors.
+    out[0x38..0x3C].copy_from_slice(&0u32.to_le_bytes());
+    out[0x3C..0x40].copy_from_slice(&END_OF_CHAIN.to_le_bytes());
+    out[0x44..0x48].copy_from_slice(&END_OF_CHAIN.to_le_bytes());
+    for i in 0..HEADER_DIFAT_ENTRIES {
+        let id: u32 = if i == 0 { 0 } else { NO_STREAM };
+        out[0x4C + i * 4..0x50 + i * 4].copy_from_slice(&id.to_le_bytes());
+    }
+    let mut fat_sector: Vec<u8> = fat.iter().flat_map(|v| v.to_le_bytes()).collect();
+    fat_sector.resize(512, 0xFF);
+    out.extend(fat_sector);
+    out.extend(dir);
+    out.extend(data_sectors);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_streams_by_name() {
+        let big: Vec<u8> = (0..1500u32).map(|i| i as u8).collect();
+        let file = build(&[
+            TestNode::Stream("small", b"hello".to_vec()),
+            TestNode::Storage("dir", vec![TestNode::Stream("Big", big.clone())]),
+        ]);
+        let cf = CompoundFile::parse(&file).expect("compound file");
+        let root = cf.root();
+        assert_eq!(cf.children(root).len(), 2);
+        assert_eq!(cf.read(cf.child(root, "SMALL").expect("small")), b"hello");
+        let dir = cf.child(root, "dir").expect("storage");
+        assert_eq!(cf.entry(dir).kind, EntryKind::Storage);
+        assert_eq!(cf.read(cf.child(dir, "big").expect("big")), big);
+        assert!(CompoundFile::parse(b"not a compound file").is_none());
+    }
+
+    #[test]
+    fn truncated_and_corrupt_files_are_cut_short() {
+        let big: Vec<u8> = (0..1500u32).map(|i| i as u8).collect();
+        let file = build(&[
+            TestNode::Stream("small", b"hello".to_vec()),
+            TestNode::Stream("Big", big.clone()),
+        ]);
+        // Header, FAT (sector 0), directory (sector 1), "small" (sector 2), "Big" (3..=5).
+        assert_eq!(file.len(), 512 * 7);
+
+        // Cut inside the header, the FAT or the directory: not a usable compound file.
+        for len in [8, 511, 700, 1100] {
+            assert!(CompoundFile::parse(&file[..len]).is_none(), "{len}");
+        }
+        // Cut inside a stream: what was there is read, nothing more.
+        let cut = &file[..512 * 5 + 100];
+        let cf = CompoundFile::parse(cut).expect("directory intact");
+        assert_eq!(cf.read(cf.child(0, "small").expect("small")), b"hello");
+        assert_eq!(cf.read(cf.child(0, "Big").expect("big")), big[..512]);
+
+        // A sector size outside 2^7..2^16 is rejected.
+        let mut bad = file.clone();
+        bad[0x1E..0x20].copy_from_slice(&20u16.to_le_bytes());
+        assert!(CompoundFile::parse(&bad).is_none());
+
+        // A FAT chain that loops back on itself stops after one pass over the FAT.
+        let mut looped = file.clone();
+        looped[512 + 4..512 + 8].copy_from_slice(&1u32.to_le_bytes());
+        let cf = CompoundFile::parse(&looped).expect("looped directory");
+        assert_eq!(cf.read(cf.child(0, "small").expect("small")), b"hello");
+
+        // A directory link past the last entry is ignored.
+        let mut dangling = file;
+        dangling[1024 + 76..1024 + 80].copy_from_slice(&99u32.to_le_bytes());
+        let cf = CompoundFile::parse(&dangling).expect("dangling child");
+        assert!(cf.children(0).is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+//! Charset detection for body parts and raw 8-bit header values.
+//!
+//! mailparse decodes a body with its declared charset and falls back to ASCII (replacement
+//! characters for every 8-bit byte) when the label is missing or unknown. PST exports get this
+//! wrong in every direction: Outlook omits the label, gateways keep `iso-8859-1` on text readpst
+//! `-8` has already converted to UTF-8, and old Japanese, Chinese and Korean mail arrives as raw
+//! Shift_JIS, GB2312 or EUC-KR with no label at all.
+//!
+//! [`decode`] trusts a byte order mark, then the declared charset as long as the bytes decode
+//! cleanly with it (except a single-byte label on valid UTF-8, which is taken as UTF-8).
+//! Otherwise the charset is sniffed: UTF-8, ISO-2022-JP escape sequences, the legacy CJK
+//! encodings (each must decode without errors and read like its language: kana for Japanese,
+//! frequent syllables or characters for Korean and Chinese), and windows-1252 as a last resort.
+//! Every guess is reported so the record can carry `charset_issues`.
+
+use encoding_rs::{
+    Encoding, BIG5, EUC_JP, EUC_KR, GBK, ISO_2022_JP, SHIFT_JIS, UTF_8, WINDOWS_1252,
+};
+
+/// Labels that say nothing about 8-bit bytes.
+const ASCII_LABELS: &[&str] = &[
+    "us-ascii",
+    "ascii",
+    "ansi_x3.4-1968",
+    "iso646-us",
+    "unknown-8bit",
+];
+
+/// Escape sequences that switch ISO-2022-JP into JIS X 0208 or JIS X 0201 Roman.
+const ISO_2022_JP_ESCAPES: &[&[u8]] = &[b"\x1b$B", b"\x1b$@", b"\x1b(J"];
+
+const COMMON_HANGUL: &str = "이다는의에을를하고가서한지로기자사있리도정시대수인해아나습니";
+const COMMON_HAN: &str =
+    "的一是不了在人有我他这這个個们們中来來上大为為和国國地到以说說时時要就出会會也你对對能请請";
+
+/// `bytes` as text, and whether the declared charset was missing, unknown or wrong for them.
+pub fn decode(bytes: &[u8], declared: Option<&str>) -> (String, bool) {
+    if let Some((encoding, bom)) = Encoding::for_bom(bytes) {
+        let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom..]);
+        return (text.into_owned(), had_errors);
+    }
+    let label = declared
+        .map(|l| l.trim().trim_matches('"').to_ascii_lowercase())
+        .filter(|l| !l.is_empty() && !ASCII_LABELS.contains(&l.as_str()));
+    let encoding = label
+        .as_deref()
+        .and_then(|l| Encoding::for_label_no_replacement(l.as_bytes()));
+    if encoding.is_none() {
+        // UTF-7, which encoding_rs leaves out.
+        if let Some(charset) = label
+            .as_deref()
+            .and_then(|l| charset::Charset::for_label_no_replacement(l.as_bytes()))
+        {
+            let (text, had_errors) = charset.decode_without_bom_handling(bytes);
+            return (text.into_owned(), had_errors);
+        }
+    }
+
+    if let Some(encoding) = encoding {
+        let utf8 =
+            encoding.is_single_byte() && !bytes.is_ascii() && std::str::from_utf8(bytes).is_ok();
+        if !utf8 {
+            if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(bytes)
+            {
+                return (text.into_owned(), false);
+            }
+        }
+    } else if bytes.is_ascii() && !is_iso_2022_jp(bytes) {
+        return (String::from_utf8_lossy(bytes).into_owned(), false);
+    }
+    let (text, _) = sniff(bytes).decode_without_bom_handling(bytes);
+    (text.into_owned(), true)
+}
+
+/// The most likely charset of unlabelled `bytes`.
+pub fn sniff(bytes: &[u8]) -> &'static Encoding {
+    if std::str::from_utf8(bytes).is_ok() && !is_iso_2022_jp(bytes) {
+        return UTF_8;
+    }
+    if is_iso_2022_jp(bytes) {
+        return ISO_2022_JP;
+    }
+    // Reversed so the earlier candidate wins a tie: max_by_key keeps the last maximum.
+    [SHIFT_JIS, EUC_JP, EUC_KR, GBK, BIG5]
+        .into_iter()
+        .rev()
+        .filter_map(|encoding| Some((encoding, cjk_score(encoding, bytes)?)))
+        .max_by_key(|&(_, score)| score)
+        .map_or(WINDOWS_1252, |(encoding, _)| encoding)
+}
+
+fn is_iso_2022_jp(bytes: &[u8]) -> bool {
+    bytes.is_ascii()
+        && ISO_2022_JP_ESCAPES
+            .iter()
+            .any(|esc| memchr::memmem::find(bytes, esc).is_some())
+}
+
+/// How much `bytes` read as text in `encoding`'s language, or None if they don't.
+fn cjk_score(encoding: &'static Encoding, bytes: &[u8]) -> Option<usize> {
+    let text = encoding.decode_without_bom_handling_and_without_replacement(bytes)?;
+    let wide: Vec<char> = text.chars().filter(|c| !c.is_ascii()).collect();
+    let cjk = wide.iter().filter(|&&c| is_cjk(c)).count();
+    if wide.is_empty() || cjk * 10 < wide.len() * 9 {
+        return None;
+    }
+    let hits = wide
+        .iter()
+        .filter(|&&c| match encoding.name() {
+            "Shift_JIS" | "EUC-JP" => ('\u{3041}'..='\u{309f}').contains(&c),
+            "EUC-KR" => COMMON_HANGUL.contains(c),
+            _ => COMMON_HAN.contains(c),
+        })
+        .count();
+    // Tens of thousands of characters could decode by chance; language-typical ones shouldn't.
+    if hits == 0 || hits * 10 < wide.len() {
+        return None;
+    }
+    Some(hits)
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{30ff}'     // CJK punctuation, kana
+        | '\u{3400}'..='\u{4dbf}'   // Han extension A
+        | '\u{4e00}'..='\u{9fff}'   // Han
+        | '\u{ac00}'..='\u{d7af}'   // Hangul syllables
+        | '\u{3130}'..='\u{318f}'   // Hangul compatibility jamo
+        | '\u{ff01}'..='\u{ff60}'   // fullwidth forms
+        | '\u{ffe0}'..='\u{ffe6}')
+}
+
+/// The `<meta charset>` or `http-equiv` charset near the top of an HTML part.
+pub fn html_meta_charset(html: &[u8]) -> Option<String> {
+    let head = &html[..html.len().min(2048)];
+    let lower = String::from_utf8_lossy(head).to_ascii_lowercase();
+    let at = lower.find("<meta")?;
+    let rest = &lower[at..];
+    let value = &rest[rest.find("charset=")? + "charset=".len()..];
+    let value = value.trim_start_matches(['"', '\'']);
+    let end = value
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')))
+        .unwrap_or(value.len());
+    Some(value[..end].to_string()).filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_mislabelled_and_unlabelled_text() {
+        let japanese = "会議は金曜日に変更になりました。資料を添付しますので、ご確認ください。";
+        let chinese = "会议改到星期五了，请查看附件中的资料。我们的项目进度有变化。";
+        let korean = "회의가 금요일로 변경되었습니다. 첨부된 자료를 확인해 주시기 바랍니다.";
+        for (text, encoding) in [
+            (japanese, SHIFT_JIS),
+            (japanese, EUC_JP),
+            (japanese, ISO_2022_JP),
+            (chinese, GBK),
+            (korean, EUC_KR),
+        ] {
+            let (bytes, _, _) = encoding.encode(text);
+            assert_eq!(sniff(&bytes), encoding, "{}", encoding.name());
+            assert_eq!(decode(&bytes, None), (text.to_string(), true));
+            assert_eq!(
+                decode(&bytes, Some(encoding.name())),
+                (text.to_string(), false)
+            );
+        }
+
+        let latin = "Café résumé – the naïve “draft”";
+        let (cp1252, _, _) = WINDOWS_1252.encode(latin);
+        assert_eq!(
+            decode(&cp1252, Some("windows-1252")),
+            (latin.to_string(), false)
+        );
+        assert_eq!(decode(&cp1252, Some("utf-8")), (latin.to_string(), true));
+        assert_eq!(
+            decode(&cp1252, Some("x-unknown")),
+            (latin.to_string(), true)
+        );
+        assert_eq!(
+            decode(latin.as_bytes(), Some("iso-8859-1")),
+            (latin.to_string(), true)
+        );
+        assert_eq!(decode(latin.as_bytes(), None), (latin.to_string(), true));
+        assert_eq!(
+            decode(b"plain", Some("us-ascii")),
+            ("plain".to_string(), false)
+        );
+
+        let html = b"<html><head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">";
+        assert_eq!(html_meta_charset(html).as_deref(), Some("shift_jis"));
+        assert_eq!(
+            html_meta_charset(b"<meta charset='gb2312'>").as_deref(),
+            Some("gb2312")
+        );
+    }
+}
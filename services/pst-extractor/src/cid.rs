@@ -0,0 +1,109 @@
+//! `--rewrite-cid`: point `cid:` references in HTML bodies at the stored inline parts.
+//!
+//! Inline images are referenced from the HTML as `<img src="cid:image001.png@01D9...">`
+//! (RFC 2392) and stored as ordinary attachments, so a preview rendering `body_html` shows
+//! broken images. `body_html_rewritten` is `body_html` with every resolvable `cid:` URL
+//! replaced by the part's attachment id or S3 key; unresolvable ones are left as they are.
+//!
+//! Content-IDs are matched without their angle brackets, percent-decoded and case-insensitively,
+//! as clients disagree on all three.
+
+use std::collections::HashMap;
+
+/// Replacement per [`key`].
+pub type Targets = HashMap<String, String>;
+
+/// The lookup key for a `Content-ID` header value or a `cid:` URL's id.
+pub fn key(content_id: &str) -> String {
+    let id = content_id
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    percent_decode(id).to_lowercase()
+}
+
+/// `html` with `cid:` URLs found in `targets` (keyed by [`key`]) replaced. None when nothing
+/// was replaced.
+pub fn rewrite(html: &str, targets: &Targets) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let mut out = String::with_capacity(html.len());
+    let (mut copied, mut replaced) = (0, false);
+    let mut from = 0;
+    while let Some(found) = lower[from..].find("cid:") {
+        let start = from + found;
+        let end = html[start..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '>' | ')' | '<'))
+            .map_or(html.len(), |at| start + at);
+        from = end.max(start + 4);
+        // Not the tail of a longer word, like "acid:".
+        let standalone = !html[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric());
+        if !standalone {
+            continue;
+        }
+        if let Some(target) = targets.get(&key(&html[start + 4..end])) {
+            out.push_str(&html[copied..start]);
+            out.push_str(target);
+            copied = end;
+            replaced = true;
+        }
+    }
+    out.push_str(&html[copied..]);
+    replaced.then_some(out)
+}
+
+fn percent_decode(value: &str) -> String {
+    if !value.contains('%') {
+        return value.to_string();
+    }
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_resolvable_cid_urls() {
+        let targets: Targets = [
+            (key("<image001.png@01D9A1B2.C3D4E5F0>"), "a1".to_string()),
+            (key("logo%20main@x"), "a2".to_string()),
+        ]
+        .into();
+        let html = "<img src=\"cid:image001.png@01D9A1B2.C3D4E5F0\"><img src='CID:Logo main@x'>\
+                    <div style=\"background:url(cid:missing@x)\">acid:image001.png@01d9a1b2.c3d4e5f0";
+        assert_eq!(
+            rewrite(html, &targets).as_deref(),
+            Some(
+                "<img src=\"a1\"><img src='CID:Logo main@x'>\
+                 <div style=\"background:url(cid:missing@x)\">acid:image001.png@01d9a1b2.c3d4e5f0"
+            )
+        );
+        assert_eq!(
+            rewrite("<img src=cid:logo%20main@x>", &targets).as_deref(),
+            Some("<img src=a2>")
+        );
+        assert_eq!(rewrite("<p>no images</p>", &targets), None);
+    }
+}
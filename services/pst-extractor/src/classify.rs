@@ -0,0 +1,338 @@
+//! Attachment document type and language (`--classify-attachments`).
+//!
+//! Review is batched by document kind ("all the invoices", "the French correspondence"), so each
+//! stored attachment gets a coarse `doc_type` and a `doc_language` from its extracted text.
+//!
+//! The built-in heuristics are deliberately small:
+//!
+//! * `doc_type`: `spreadsheet_report` and `presentation` by file type; `invoice` and `contract`
+//!   when the filename or text carries enough of their vocabulary; `other` for any other
+//!   attachment with text. Attachments without text and of neither file type get none.
+//! * `doc_language`: ISO 639-1 code from stopword counts (en, fr, de, es, it, nl, pt), or from
+//!   the script for Chinese, Japanese and Korean. Too little text gives none.
+//!
+//! `--classify-endpoint` replaces them with a model service: each attachment is POSTed as
+//! `{"attachment_id", "filename", "content_type", "text"}` and the response is
+//! `{"doc_type": "...", "doc_language": "..."}` (either may be null).
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Text sent to the endpoint / scanned by the heuristics.
+const MAX_TEXT_CHARS: usize = 20_000;
+
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "of", "to", "that", "for", "with", "this", "are", "be", "have", "will",
+            "please", "from", "would",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "les", "et", "est", "une", "pour", "dans", "nous", "vous", "avec", "sur", "pas",
+            "ce", "qui", "merci", "au",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "und", "das", "ist", "nicht", "mit", "den", "ein", "eine", "für", "wir",
+            "auf", "bitte", "ich", "zu",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "del", "por", "para", "está", "como", "pero", "muy", "y", "su",
+            "gracias", "este", "lo",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "che", "della", "per", "sono", "non", "gli", "alla", "questo", "grazie", "di",
+            "è", "anche", "nel", "ho",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "het", "een", "van", "niet", "met", "voor", "zijn", "wij", "dat", "ook", "naar", "ik",
+            "bij", "wordt", "dank",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "os", "não", "com", "da", "são", "você", "obrigado", "ao", "é", "mais", "pelo", "já",
+            "muito", "isso", "em",
+        ],
+    ),
+];
+
+/// Stopword hits needed before a language is named.
+const MIN_LANGUAGE_HITS: usize = 3;
+
+const INVOICE_WORDS: &[&str] = &[
+    "invoice",
+    "invoice no",
+    "invoice number",
+    "amount due",
+    "total due",
+    "vat",
+    "payment terms",
+    "remittance",
+    "bill to",
+    "subtotal",
+];
+
+const CONTRACT_WORDS: &[&str] = &[
+    "agreement",
+    "contract",
+    "whereas",
+    "hereby",
+    "the parties",
+    "clause",
+    "in witness whereof",
+    "terms and conditions",
+    "obligations",
+    "governing law",
+];
+
+/// Vocabulary hits needed for `invoice` / `contract`.
+const MIN_TYPE_HITS: usize = 3;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Classification {
+    pub doc_type: Option<String>,
+    pub doc_language: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DocInput<'a> {
+    pub attachment_id: &'a str,
+    pub filename: &'a str,
+    pub content_type: Option<&'a str>,
+    pub text: Option<&'a str>,
+}
+
+pub enum Classifier {
+    Heuristic,
+    Endpoint {
+        url: String,
+        client: reqwest::Client,
+    },
+}
+
+fn truncated(text: &str) -> &str {
+    match text.char_indices().nth(MAX_TEXT_CHARS) {
+        Some((cut, _)) => &text[..cut],
+        None => text,
+    }
+}
+
+fn extension(filename: &str) -> String {
+    filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+fn is_spreadsheet(filename: &str, content_type: &str) -> bool {
+    matches!(
+        extension(filename).as_str(),
+        "xls" | "xlsx" | "xlsm" | "xlsb" | "csv" | "ods"
+    ) || content_type.contains("spreadsheet")
+        || content_type.contains("ms-excel")
+        || content_type == "text/csv"
+}
+
+fn is_presentation(filename: &str, content_type: &str) -> bool {
+    matches!(
+        extension(filename).as_str(),
+        "ppt" | "pptx" | "pps" | "ppsx" | "odp" | "key"
+    ) || content_type.contains("presentation")
+        || content_type.contains("powerpoint")
+}
+
+/// How many of `words` (single words or phrases) occur in `haystack` on word boundaries.
+fn vocabulary_hits(haystack: &str, words: &[&str]) -> usize {
+    let words_only: Vec<&str> = haystack
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let padded = format!(" {} ", words_only.join(" "));
+    words
+        .iter()
+        .filter(|w| padded.contains(&format!(" {w} ")))
+        .count()
+}
+
+pub fn doc_type(
+    filename: &str,
+    content_type: Option<&str>,
+    text: Option<&str>,
+) -> Option<&'static str> {
+    let content_type = content_type.unwrap_or("").to_ascii_lowercase();
+    if is_spreadsheet(filename, &content_type) {
+        return Some("spreadsheet_report");
+    }
+    if is_presentation(filename, &content_type) {
+        return Some("presentation");
+    }
+    let name = filename.to_lowercase();
+    let body = text.map(|t| truncated(t).to_lowercase());
+    let haystack = format!("{name}\n{}", body.as_deref().unwrap_or(""));
+    // The filename alone is strong evidence ("INV-0042 invoice.pdf").
+    let invoice =
+        vocabulary_hits(&haystack, INVOICE_WORDS) + 2 * usize::from(name.contains("invoice"));
+    let contract = vocabulary_hits(&haystack, CONTRACT_WORDS)
+        + 2 * usize::from(name.contains("contract") || name.contains("agreement"));
+    match (invoice >= MIN_TYPE_HITS, contract >= MIN_TYPE_HITS) {
+        (true, false) => Some("invoice"),
+        (false, true) => Some("contract"),
+        (true, true) if invoice >= contract => Some("invoice"),
+        (true, true) => Some("contract"),
+        (false, false) if body.is_some() => Some("other"),
+        (false, false) => None,
+    }
+}
+
+pub fn language(text: &str) -> Option<&'static str> {
+    let text = truncated(text);
+    let (mut letters, mut cjk, mut kana, mut hangul) = (0usize, 0usize, 0usize, 0usize);
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        match c as u32 {
+            0x3040..=0x30FF => kana += 1,
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => hangul += 1,
+            _ if crate::terms::is_cjk(c) => cjk += 1,
+            _ => {}
+        }
+    }
+    let asian = cjk + kana + hangul;
+    if asian >= 10 && asian * 3 >= letters {
+        return Some(if hangul > kana.max(cjk) {
+            "ko"
+        } else if kana > 0 {
+            "ja"
+        } else {
+            "zh"
+        });
+    }
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let mut scores: Vec<(usize, &'static str)> = STOPWORDS
+        .iter()
+        .map(|(lang, list)| {
+            let hits = words.iter().filter(|w| list.contains(&w.as_str())).count();
+            (hits, *lang)
+        })
+        .collect();
+    scores.sort_by_key(|&(hits, _)| std::cmp::Reverse(hits));
+    let (best, lang) = scores[0];
+    let runner_up = scores[1].0;
+    (best >= MIN_LANGUAGE_HITS && best * 2 > runner_up * 3).then_some(lang)
+}
+
+impl Classifier {
+    pub fn endpoint(url: &str) -> Result<Self> {
+        Ok(Classifier::Endpoint {
+            url: url.to_string(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()?,
+        })
+    }
+
+    pub async fn classify(&self, input: &DocInput<'_>) -> Result<Classification> {
+        match self {
+            Classifier::Heuristic => Ok(Classification {
+                doc_type: doc_type(input.filename, input.content_type, input.text)
+                    .map(str::to_string),
+                doc_language: input.text.and_then(language).map(str::to_string),
+            }),
+            Classifier::Endpoint { url, client } => {
+                let body = DocInput {
+                    text: input.text.map(truncated),
+                    ..*input
+                };
+                let resp = client
+                    .post(url.as_str())
+                    .json(&body)
+                    .send()
+                    .await
+                    .with_context(|| format!("classify endpoint {url}"))?;
+                if !resp.status().is_success() {
+                    return Err(anyhow!("classify endpoint returned HTTP {}", resp.status()));
+                }
+                resp.json()
+                    .await
+                    .context("classify endpoint returned invalid JSON")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heuristic_types_and_languages() {
+        assert_eq!(
+            doc_type("Costs.xlsx", None, Some("a\tb")),
+            Some("spreadsheet_report")
+        );
+        assert_eq!(
+            doc_type("deck.bin", Some("application/vnd.ms-powerpoint"), None),
+            Some("presentation")
+        );
+        assert_eq!(
+            doc_type(
+                "INV-0042.pdf",
+                None,
+                Some("Invoice number 42. Subtotal 100. VAT 20.")
+            ),
+            Some("invoice")
+        );
+        assert_eq!(
+            doc_type(
+                "scan.pdf",
+                None,
+                Some("This Agreement is made between the parties. Whereas the Contractor...")
+            ),
+            Some("contract")
+        );
+        assert_eq!(
+            doc_type("notes.txt", None, Some("Site visit notes")),
+            Some("other")
+        );
+        assert_eq!(doc_type("photo.jpg", Some("image/jpeg"), None), None);
+
+        assert_eq!(
+            language("Please find attached the revised programme for the works, and we will send the rest."),
+            Some("en")
+        );
+        assert_eq!(
+            language("Nous vous remercions pour le document, qui est dans les délais. Merci."),
+            Some("fr")
+        );
+        assert_eq!(
+            language("Bitte finden Sie die Unterlagen für das Projekt, die nicht mit der Planung übereinstimmen."),
+            Some("de")
+        );
+        assert_eq!(
+            language("東京の現場で会議を行います。よろしくお願いします。"),
+            Some("ja")
+        );
+        assert_eq!(language("Ref 12345"), None);
+    }
+}
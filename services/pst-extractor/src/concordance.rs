@@ -0,0 +1,175 @@
+//! Corpus term-frequency concordance (`--concordance`).
+//!
+//! Early case assessment builds keyword lists from what the mailbox actually says before search
+//! terms are negotiated. Every email's subject and body is tokenized with the term-matching
+//! tokenizer. Tokens are filtered (minimum length, stopwords, no purely numeric tokens) and
+//! counted, both as total occurrences and as the number of emails containing them. The result
+//! is written as `concordance.ndjson.gz`, most frequent first.
+//!
+//! To bound memory on huge mailboxes, the table is pruned when it grows past `max_terms`. Terms
+//! seen in only a single email are dropped at that point, so counts for very rare terms are
+//! approximate. Frequent terms are exact.
+
+use crate::terms::Tokenizer;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+/// Distinct terms held before rare ones are pruned.
+pub const DEFAULT_MAX_TERMS: usize = 2_000_000;
+
+/// Common English function words (plus mail boilerplate) left out unless a stopword file
+/// replaces the list.
+const DEFAULT_STOPWORDS: &str = "a about above after again against all am an and any are as at \
+    be because been before being below between both but by can cannot could did do does doing \
+    down during each few for from further had has have having he her here hers herself him \
+    himself his how i if in into is it its itself just let me more most my myself no nor not \
+    now of off on once only or other our ours ourselves out over own same she should so some \
+    such than that the their theirs them themselves then there these they this those through to \
+    too under until up very was we were what when where which while who whom why will with \
+    would you your yours yourself yourselves re fw fwd cc sent subject regards thanks thank \
+    please dear hi hello kind best mailto http https www com";
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConcordanceEntry {
+    pub term: String,
+    /// Total occurrences across all subjects and bodies.
+    pub count: u64,
+    /// Emails the term appears in at least once.
+    pub emails: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ConcordanceStats {
+    pub emails_scanned: u64,
+    pub tokens_counted: u64,
+    /// Distinct terms written (after min_count).
+    pub terms_written: u64,
+    /// Times the table was pruned for memory; non-zero means rare-term counts are approximate.
+    pub prunes: u64,
+}
+
+pub struct Concordance {
+    tokenizer: Box<dyn Tokenizer>,
+    stopwords: HashSet<String>,
+    min_length: usize,
+    max_terms: usize,
+    counts: HashMap<String, (u64, u64)>,
+    stats: ConcordanceStats,
+}
+
+impl Concordance {
+    /// `stopwords`: one word per line (`#` comments allowed), replacing the built-in list.
+    pub fn new(
+        tokenizer: Box<dyn Tokenizer>,
+        stopwords: Option<&str>,
+        min_length: usize,
+        max_terms: usize,
+    ) -> Self {
+        let stopwords = match stopwords {
+            Some(text) => text
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(str::to_lowercase)
+                .collect(),
+            None => DEFAULT_STOPWORDS
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+        };
+        Self {
+            tokenizer,
+            stopwords,
+            min_length,
+            max_terms: max_terms.max(1),
+            counts: HashMap::new(),
+            stats: ConcordanceStats::default(),
+        }
+    }
+
+    fn keep(&self, token: &str) -> bool {
+        token.chars().count() >= self.min_length
+            && token.chars().any(char::is_alphabetic)
+            && !self.stopwords.contains(token)
+    }
+
+    /// Count the tokens of one email's texts.
+    pub fn add(&mut self, texts: &[&str]) {
+        let mut seen: HashSet<String> = HashSet::new();
+        for text in texts {
+            for token in self.tokenizer.tokens(text) {
+                let token = token.to_lowercase();
+                if !self.keep(&token) {
+                    continue;
+                }
+                self.stats.tokens_counted += 1;
+                let entry = self.counts.entry(token.clone()).or_default();
+                entry.0 += 1;
+                if seen.insert(token) {
+                    entry.1 += 1;
+                }
+            }
+        }
+        self.stats.emails_scanned += 1;
+        if self.counts.len() > self.max_terms {
+            self.counts.retain(|_, (_, emails)| *emails > 1);
+            self.stats.prunes += 1;
+        }
+    }
+
+    /// Write entries with at least `min_count` occurrences, most frequent first (ties by term).
+    pub fn write(
+        mut self,
+        out: &mut impl Write,
+        min_count: u64,
+    ) -> std::io::Result<ConcordanceStats> {
+        let mut entries: Vec<ConcordanceEntry> = self
+            .counts
+            .drain()
+            .filter(|(_, (count, _))| *count >= min_count)
+            .map(|(term, (count, emails))| ConcordanceEntry {
+                term,
+                count,
+                emails,
+            })
+            .collect();
+        entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.term.cmp(&b.term)));
+        for entry in &entries {
+            writeln!(out, "{}", serde_json::to_string(entry)?)?;
+        }
+        self.stats.terms_written = entries.len() as u64;
+        Ok(self.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terms::WordTokenizer;
+
+    #[test]
+    fn counts_occurrences_and_emails_without_stopwords() {
+        let mut c = Concordance::new(Box::new(WordTokenizer), None, 3, 1000);
+        c.add(&["Re: Falcon delay", "The Falcon pour is delayed. Falcon!"]);
+        c.add(&["Falcon programme", "Programme rev 2024 attached"]);
+        let mut out = Vec::new();
+        let stats = c.write(&mut out, 2).expect("write");
+        let lines: Vec<&str> = std::str::from_utf8(&out).expect("utf8").lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"term":"falcon","count":4,"emails":2}"#,
+                r#"{"term":"programme","count":2,"emails":1}"#,
+            ]
+        );
+        assert_eq!(stats.emails_scanned, 2);
+        assert_eq!(stats.terms_written, 2);
+
+        let mut custom = Concordance::new(Box::new(WordTokenizer), Some("falcon\n"), 1, 1000);
+        custom.add(&["Falcon the pour"]);
+        let mut out = Vec::new();
+        custom.write(&mut out, 1).expect("write");
+        assert!(!String::from_utf8(out).expect("utf8").contains("falcon"));
+    }
+}
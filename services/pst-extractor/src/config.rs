@@ -0,0 +1,156 @@
+//! `--config job.yaml`: arguments from a file.
+//!
+//! Orchestrators passing long lists (filters, domains, credentials) through environment
+//! variables run into size limits and quoting bugs. A TOML, YAML or JSON file (chosen by
+//! extension) holds the same options instead: a flat table keyed by argument name, in the
+//! job-message form (`source_bucket`) or the flag form (`source-bucket`). Lists become repeated
+//! flags and `true` sets a switch.
+//!
+//! Precedence is command line, then environment, then the file, then defaults: a file value is
+//! only used when the argument appears in neither argv nor its environment variable. The file
+//! is read before anything else starts, so it must be local.
+
+use anyhow::{anyhow, Context, Result};
+use clap::{ArgAction, Command};
+use serde_json::{Map, Value};
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+const FLAG: &str = "--config";
+const ENV: &str = "CONFIG_FILE";
+
+/// `argv` with the config file's options (if one is named) inserted before the given ones.
+pub fn expand(argv: Vec<OsString>, command: &Command) -> Result<Vec<OsString>> {
+    expand_with(argv, command, |name| std::env::var_os(name))
+}
+
+fn expand_with(
+    argv: Vec<OsString>,
+    command: &Command,
+    env: impl Fn(&OsStr) -> Option<OsString>,
+) -> Result<Vec<OsString>> {
+    let Some(path) = config_path(&argv).or_else(|| env(OsStr::new(ENV))) else {
+        return Ok(argv);
+    };
+    let options = load(Path::new(&path))?;
+    let tokens = tokens(&options, command, &argv, |name| env(name).is_some())
+        .with_context(|| format!("config file {}", Path::new(&path).display()))?;
+    let mut expanded = argv;
+    let at = expanded.len().min(1);
+    expanded.splice(at..at, tokens);
+    Ok(expanded)
+}
+
+fn config_path(argv: &[OsString]) -> Option<OsString> {
+    let mut args = argv.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == FLAG {
+            return args.next().cloned();
+        }
+        if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(path.into());
+        }
+    }
+    None
+}
+
+fn load(path: &Path) -> Result<Map<String, Value>> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let extension = path.extension().and_then(OsStr::to_str).unwrap_or_default();
+    let value: Value = match extension.to_ascii_lowercase().as_str() {
+        "toml" => toml::from_str(&text).context("invalid TOML")?,
+        "yaml" | "yml" => serde_yaml::from_str(&text).context("invalid YAML")?,
+        "json" => serde_json::from_str(&text).context("invalid JSON")?,
+        _ => return Err(anyhow!("config file must be .toml, .yaml, .yml or .json")),
+    };
+    match value {
+        Value::Object(options) => Ok(options),
+        Value::Null => Ok(Map::new()),
+        _ => Err(anyhow!("config file must be a table of options")),
+    }
+}
+
+/// Flag tokens for the options not already given in `argv` or the environment.
+fn tokens(
+    options: &Map<String, Value>,
+    command: &Command,
+    argv: &[OsString],
+    env_is_set: impl Fn(&OsStr) -> bool,
+) -> Result<Vec<OsString>> {
+    let mut tokens = Vec::new();
+    for (name, value) in options {
+        let id = name.replace('-', "_");
+        let arg = command
+            .get_arguments()
+            .find(|a| a.get_id() == id.as_str() && a.get_long().is_some())
+            .ok_or_else(|| anyhow!("unknown option {name:?}"))?;
+        let long = format!("--{}", arg.get_long().expect("long flag"));
+        let on_command_line = argv.iter().skip(1).filter_map(|a| a.to_str()).any(|a| {
+            a == long || a.strip_prefix(long.as_str()).is_some_and(|rest| rest.starts_with('='))
+        });
+        if on_command_line || arg.get_env().is_some_and(&env_is_set) {
+            continue;
+        }
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            Value::Null => Vec::new(),
+            value => vec![value],
+        };
+        for value in values {
+            match (arg.get_action(), value) {
+                (ArgAction::SetTrue, Value::Bool(true)) => tokens.push(long.clone().into()),
+                (ArgAction::SetTrue, Value::Bool(false)) => {}
+                (ArgAction::SetTrue, _) => return Err(anyhow!("{name}: expected true or false")),
+                (_, Value::String(text)) => tokens.push(format!("{long}={text}").into()),
+                (_, Value::Number(_) | Value::Bool(_)) => {
+                    tokens.push(format!("{long}={value}").into())
+                }
+                _ => return Err(anyhow!("{name}: expected a string, number or list")),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, Parser};
+
+    #[test]
+    fn file_options_yield_to_argv_and_env() {
+        let dir = std::env::temp_dir().join(format!("config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let path = dir.join("job.yaml");
+        std::fs::write(
+            &path,
+            "pst_file_id: p1\nsource-bucket: in\nsource_key: a.pst\noutput_bucket: out\n\
+             output_prefix: x/\ncase_id: from-file\ncustodian_id: from-file\n\
+             internal_domains: [a.example, b.example]\ndedupe: true\nrecovery_mode: false\n\
+             gzip_member_bytes: 1024\n",
+        )
+        .expect("write");
+        let argv = ["pst-extractor", "--config", path.to_str().expect("utf-8"), "--case-id=cli"];
+        let env = |name: &OsStr| (name == "CUSTODIAN_ID").then(|| OsString::from("from-env"));
+        let expanded = expand_with(argv.map(OsString::from).to_vec(), &crate::Args::command(), env)
+            .expect("expand");
+        let args = crate::Args::parse_from(expanded);
+        assert_eq!(args.pst_file_id, "p1");
+        assert_eq!(args.source_bucket, "in");
+        assert_eq!(args.case_id, "cli");
+        // The environment variable isn't visible to clap here; the file value was still skipped.
+        assert_eq!(args.custodian_id, "");
+        assert_eq!(args.internal_domains, ["a.example", "b.example"]);
+        assert!(args.dedupe);
+        assert!(!args.recovery_mode);
+        assert_eq!(args.gzip_member_bytes, 1024);
+
+        std::fs::write(&path, "no_such_option: 1\n").expect("write");
+        let argv = ["pst-extractor", "--config", path.to_str().expect("utf-8")];
+        let err = expand_with(argv.map(OsString::from).to_vec(), &crate::Args::command(), |_| None)
+            .expect_err("unknown option");
+        assert!(format!("{err:#}").contains("unknown option"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,704 @@
+//! Date header parsing with fallbacks.
+//!
+//! `mailparse::dateparse` handles well-formed RFC 2822 dates but rejects localized month names
+//! ("Mi, 3 Mär 2021"), missing seconds, ISO timestamps and numeric dates, and returns 0 for input
+//! with no recognizable tokens at all. Those cases go through a chain of more lenient parsers;
+//! the one that succeeded is recorded on the email so low-confidence dates can be reviewed.
+//!
+//! A missing Date header, or one that parses to an implausible time (the epoch itself, a
+//! misconfigured clock's 2099), would wreck timeline sorting. Such messages are dated from the
+//! earliest plausible `Received` header instead, and `date_source` says which was used.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum DateParser {
+    /// Well-formed RFC 2822 (mailparse).
+    Rfc2822,
+    /// ISO 8601 / RFC 3339 style ("2021-03-03T10:00:00Z").
+    Iso8601,
+    /// English month names in a non-RFC layout ("March 3, 2021 10:00 AM", missing seconds).
+    Lenient,
+    /// Non-English month names ("3 Mär 2021", "3 févr. 2021").
+    Localized,
+    /// All-numeric dates ("03.03.2021 10:00", "3/4/2021"). Slash dates are read month-first
+    /// unless the first field is above 12; dot and dash dates are read day-first.
+    Numeric,
+}
+
+/// Where a message's `date_epoch` came from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DateSource {
+    Date,
+    Received,
+}
+
+/// Earliest time taken as a real message date (1980-01-01).
+const PLAUSIBLE_FROM: i64 = 315_532_800;
+/// How far past the current time a date may be (clock skew between hosts).
+const PLAUSIBLE_SKEW_SECS: i64 = 86_400;
+
+/// Years outside this range are treated as parse failures rather than real dates.
+const YEAR_RANGE: std::ops::RangeInclusive<i64> = 1970..=2100;
+
+const ENGLISH_MONTHS: &[(&str, u32)] = &[
+    ("jan", 1),
+    ("january", 1),
+    ("feb", 2),
+    ("february", 2),
+    ("mar", 3),
+    ("march", 3),
+    ("apr", 4),
+    ("april", 4),
+    ("may", 5),
+    ("jun", 6),
+    ("june", 6),
+    ("jul", 7),
+    ("july", 7),
+    ("aug", 8),
+    ("august", 8),
+    ("sep", 9),
+    ("sept", 9),
+    ("september", 9),
+    ("oct", 10),
+    ("october", 10),
+    ("nov", 11),
+    ("november", 11),
+    ("dec", 12),
+    ("december", 12),
+];
+
+/// German, French, Spanish, Italian, Dutch, Portuguese and Scandinavian names/abbreviations
+/// that differ from the English ones.
+const LOCALIZED_MONTHS: &[(&str, u32)] = &[
+    ("januar", 1),
+    ("jän", 1),
+    ("jänner", 1),
+    ("janvier", 1),
+    ("janv", 1),
+    ("enero", 1),
+    ("ene", 1),
+    ("gennaio", 1),
+    ("gen", 1),
+    ("januari", 1),
+    ("janeiro", 1),
+    ("februar", 2),
+    ("février", 2),
+    ("fevrier", 2),
+    ("févr", 2),
+    ("fév", 2),
+    ("fev", 2),
+    ("febrero", 2),
+    ("febbraio", 2),
+    ("februari", 2),
+    ("fevereiro", 2),
+    ("märz", 3),
+    ("mär", 3),
+    ("mrz", 3),
+    ("mars", 3),
+    ("marzo", 3),
+    ("maart", 3),
+    ("mrt", 3),
+    ("março", 3),
+    ("marco", 3),
+    ("marts", 3),
+    ("avril", 4),
+    ("avr", 4),
+    ("abril", 4),
+    ("abr", 4),
+    ("aprile", 4),
+    ("mai", 5),
+    ("mayo", 5),
+    ("maggio", 5),
+    ("mag", 5),
+    ("mei", 5),
+    ("maio", 5),
+    ("maj", 5),
+    ("juni", 6),
+    ("juin", 6),
+    ("junio", 6),
+    ("giugno", 6),
+    ("giu", 6),
+    ("junho", 6),
+    ("juli", 7),
+    ("juillet", 7),
+    ("juil", 7),
+    ("julio", 7),
+    ("luglio", 7),
+    ("lug", 7),
+    ("julho", 7),
+    ("août", 8),
+    ("aout", 8),
+    ("agosto", 8),
+    ("ago", 8),
+    ("augustus", 8),
+    ("augusti", 8),
+    ("septembre", 9),
+    ("septiembre", 9),
+    ("setiembre", 9),
+    ("settembre", 9),
+    ("setembro", 9),
+    ("set", 9),
+    ("oktober", 10),
+    ("okt", 10),
+    ("octobre", 10),
+    ("octubre", 10),
+    ("ottobre", 10),
+    ("ott", 10),
+    ("outubro", 10),
+    ("out", 10),
+    ("novembre", 11),
+    ("noviembre", 11),
+    ("novembro", 11),
+    ("dezember", 12),
+    ("dez", 12),
+    ("décembre", 12),
+    ("déc", 12),
+    ("decembre", 12),
+    ("diciembre", 12),
+    ("dicembre", 12),
+    ("dic", 12),
+    ("dezembro", 12),
+    ("desember", 12),
+    ("des", 12),
+];
+
+/// Zone abbreviations beyond the RFC 822 set, as seconds east of UTC.
+const ZONES: &[(&str, i64)] = &[
+    ("UT", 0),
+    ("UTC", 0),
+    ("GMT", 0),
+    ("Z", 0),
+    ("WET", 0),
+    ("EDT", -4 * 3600),
+    ("EST", -5 * 3600),
+    ("CDT", -5 * 3600),
+    ("CST", -6 * 3600),
+    ("MDT", -6 * 3600),
+    ("MST", -7 * 3600),
+    ("PDT", -7 * 3600),
+    ("PST", -8 * 3600),
+    ("BST", 3600),
+    ("WEST", 3600),
+    ("CET", 3600),
+    ("MEZ", 3600),
+    ("CEST", 2 * 3600),
+    ("MESZ", 2 * 3600),
+    ("EET", 2 * 3600),
+    ("EEST", 3 * 3600),
+    ("HKT", 8 * 3600),
+    ("SGT", 8 * 3600),
+    ("JST", 9 * 3600),
+    ("AEST", 10 * 3600),
+    ("AEDT", 11 * 3600),
+];
+
+/// Parse a Date header value into a Unix timestamp, returning the parser that accepted it.
+pub fn parse_date(raw: &str) -> Option<(i64, DateParser)> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return None;
+    }
+    if let Some(epoch) = parse_iso8601(raw) {
+        return Some((epoch, DateParser::Iso8601));
+    }
+    // mailparse yields Ok(0) for input without date tokens, so only trust it when the value
+    // has an English month name to anchor on.
+    if tokens(raw).any(|t| english_month(&t).is_some()) {
+        if let Ok(epoch) = mailparse::dateparse(raw) {
+            if YEAR_RANGE.contains(&year_of(epoch)) {
+                return Some((epoch, DateParser::Rfc2822));
+            }
+        }
+    }
+    parse_tokens(raw)
+}
+
+/// The current Unix time.
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+fn plausible(epoch: i64, now: i64) -> bool {
+    (PLAUSIBLE_FROM..=now + PLAUSIBLE_SKEW_SECS).contains(&epoch)
+}
+
+/// A message's date: the Date header when it parses to a plausible time, else the earliest
+/// plausible timestamp among the `Received` headers (the part after the last `;`), else the
+/// Date header's value however implausible. `now` is the current Unix time.
+pub fn message_date(
+    date: Option<&str>,
+    received: &[String],
+    now: i64,
+) -> Option<(i64, DateParser, DateSource)> {
+    let header = date.and_then(parse_date);
+    if let Some((epoch, parser)) = header.filter(|(epoch, _)| plausible(*epoch, now)) {
+        return Some((epoch, parser, DateSource::Date));
+    }
+    let earliest = received
+        .iter()
+        .filter_map(|value| parse_date(value.rsplit_once(';')?.1))
+        .filter(|(epoch, _)| plausible(*epoch, now))
+        .min_by_key(|(epoch, _)| *epoch);
+    match earliest {
+        Some((epoch, parser)) => Some((epoch, parser, DateSource::Received)),
+        None => header.map(|(epoch, parser)| (epoch, parser, DateSource::Date)),
+    }
+}
+
+fn tokens(raw: &str) -> impl Iterator<Item = String> + '_ {
+    raw.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase().trim_end_matches('.').to_string())
+}
+
+fn english_month(token: &str) -> Option<u32> {
+    ENGLISH_MONTHS
+        .iter()
+        .find(|(name, _)| *name == token)
+        .map(|(_, m)| *m)
+}
+
+fn localized_month(token: &str) -> Option<u32> {
+    LOCALIZED_MONTHS
+        .iter()
+        .find(|(name, _)| *name == token)
+        .map(|(_, m)| *m)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// RFC 2822 `Date` value in UTC, for headers synthesized from non-MIME sources (e.g. `.msg`).
+pub fn format_rfc2822(epoch: i64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = epoch.div_euclid(86_400);
+    let secs = epoch.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {day} {} {year} {:02}:{:02}:{:02} +0000",
+        DAYS[days.rem_euclid(7) as usize],
+        MONTHS[month as usize - 1],
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// RFC 1123 HTTP date (`Sun, 06 Nov 1994 08:49:37 GMT`), for signed storage requests.
+pub fn format_http_date(epoch: i64) -> String {
+    let rfc2822 = format_rfc2822(epoch);
+    let (weekday, rest) = rfc2822.split_once(", ").unwrap_or(("", &rfc2822));
+    let rest = rest.trim_end_matches(" +0000");
+    match rest.split_once(' ') {
+        Some((day, tail)) => format!("{weekday}, {day:0>2} {tail} GMT"),
+        None => format!("{weekday}, {rest} GMT"),
+    }
+}
+
+/// UTC calendar day (`YYYY-MM-DD`) of an epoch, for day-bucketed outputs.
+pub fn format_day(epoch: i64) -> String {
+    let (year, month, day) = civil_from_days(epoch.div_euclid(86_400));
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// ISO 8601 UTC timestamp (`2024-06-03T10:00:00Z`).
+pub fn format_rfc3339(epoch: i64) -> String {
+    let of_day = epoch.rem_euclid(86_400);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        format_day(epoch),
+        of_day / 3600,
+        of_day % 3600 / 60,
+        of_day % 60
+    )
+}
+
+/// RFC 3339 UTC timestamp with milliseconds (`2024-06-03T10:00:00.250Z`), for log lines.
+pub fn format_rfc3339_millis(millis: i64) -> String {
+    let secs = millis.div_euclid(1000);
+    let of_day = secs.rem_euclid(86_400);
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        format_day(secs),
+        of_day / 3600,
+        of_day % 3600 / 60,
+        of_day % 60,
+        millis.rem_euclid(1000)
+    )
+}
+
+fn year_of(epoch: i64) -> i64 {
+    // Good enough for a range check.
+    1970 + epoch.div_euclid(31_556_952)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Unix timestamp for a validated civil date/time; `offset` is seconds east of UTC.
+pub fn to_epoch(
+    year: i64,
+    month: u32,
+    day: u32,
+    (h, m, s): (u32, u32, u32),
+    offset: i64,
+) -> Option<i64> {
+    if !YEAR_RANGE.contains(&year)
+        || !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month(year, month)
+        || h > 23
+        || m > 59
+        || s > 60
+    {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86_400 + (h * 3600 + m * 60 + s) as i64;
+    Some(secs - offset)
+}
+
+fn expand_year(year: i64, digits: usize) -> i64 {
+    match (digits, year) {
+        (1 | 2, y) if y < 70 => 2000 + y,
+        (1 | 2, y) => 1900 + y,
+        (_, y) => y,
+    }
+}
+
+/// "hh:mm", "hh:mm:ss" or "hh:mm:ss.fff", optionally with a trailing "Z".
+fn parse_time(token: &str) -> Option<(u32, u32, u32)> {
+    let token = token.trim_end_matches(['z', 'Z']);
+    let mut parts = token.split(':');
+    let h = parts.next()?.parse().ok()?;
+    let m = parts.next()?.parse().ok()?;
+    let s = match parts.next() {
+        Some(sec) => sec.split('.').next()?.parse().ok()?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((h, m, s))
+}
+
+/// "+0100", "-05:00", "+1", "+01" as seconds east of UTC.
+fn parse_offset(token: &str) -> Option<i64> {
+    let sign = match token.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let rest = &token[1..];
+    let (h, m) = if let Some((h, m)) = rest.split_once(':') {
+        (h.parse::<i64>().ok()?, m.parse::<i64>().ok()?)
+    } else if rest.len() == 4 && rest.bytes().all(|b| b.is_ascii_digit()) {
+        (rest[..2].parse().ok()?, rest[2..].parse().ok()?)
+    } else if (1..=2).contains(&rest.len()) {
+        (rest.parse().ok()?, 0)
+    } else {
+        return None;
+    };
+    if h > 14 || m > 59 {
+        return None;
+    }
+    Some(sign * (h * 3600 + m * 60))
+}
+
+/// A zone token: numeric offset, abbreviation, or "GMT+1" / "UTC+01:00".
+fn parse_zone(token: &str) -> Option<i64> {
+    if let Some(offset) = parse_offset(token) {
+        return Some(offset);
+    }
+    let upper = token.trim_matches(['(', ')']).to_ascii_uppercase();
+    for prefix in ["GMT", "UTC"] {
+        if let Some(rest) = upper.strip_prefix(prefix) {
+            if !rest.is_empty() {
+                return parse_offset(rest);
+            }
+        }
+    }
+    ZONES
+        .iter()
+        .find(|(name, _)| *name == upper)
+        .map(|(_, off)| *off)
+}
+
+/// "2021-03-03", "2021-03-03T10:00:00Z", "2021-03-03 10:00:00.123+01:00".
+fn parse_iso8601(raw: &str) -> Option<i64> {
+    let b = raw.as_bytes();
+    if b.len() < 10
+        || b[4] != b'-'
+        || b[7] != b'-'
+        || !b[..4]
+            .iter()
+            .chain(&b[5..7])
+            .chain(&b[8..10])
+            .all(u8::is_ascii_digit)
+    {
+        return None;
+    }
+    let year: i64 = raw[..4].parse().ok()?;
+    let month: u32 = raw[5..7].parse().ok()?;
+    let day: u32 = raw[8..10].parse().ok()?;
+    let rest = raw[10..].trim_start_matches(['T', 't', ' ']);
+    if rest.is_empty() {
+        return to_epoch(year, month, day, (0, 0, 0), 0);
+    }
+    // Split the zone designator off the time.
+    let zone_at = rest
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| matches!(c, '+' | '-' | 'Z' | 'z' | ' '))
+        .map(|(i, _)| i)
+        .unwrap_or(rest.len());
+    let time = parse_time(&rest[..zone_at])?;
+    let zone = rest[zone_at..].trim();
+    let offset = if zone.is_empty() {
+        0
+    } else {
+        parse_zone(zone)?
+    };
+    to_epoch(year, month, day, time, offset)
+}
+
+/// "03.03.2021", "3/4/21", "2021/03/03" → (year, month, day).
+fn parse_numeric_date(token: &str) -> Option<(i64, u32, u32)> {
+    let sep = ['.', '/', '-'].into_iter().find(|&c| token.contains(c))?;
+    let parts: Vec<&str> = token.split(sep).collect();
+    if parts.len() != 3
+        || parts
+            .iter()
+            .any(|p| p.is_empty() || !p.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+    let nums: Vec<i64> = parts
+        .iter()
+        .map(|p| p.parse().ok())
+        .collect::<Option<_>>()?;
+    if parts[0].len() == 4 {
+        return Some((nums[0], nums[1] as u32, nums[2] as u32));
+    }
+    let year = expand_year(nums[2], parts[2].len());
+    let (day, month) = if sep == '/' && nums[0] <= 12 {
+        (nums[1], nums[0])
+    } else {
+        (nums[0], nums[1])
+    };
+    Some((year, month as u32, day as u32))
+}
+
+/// Order-insensitive token scan used for everything mailparse and ISO parsing reject.
+fn parse_tokens(raw: &str) -> Option<(i64, DateParser)> {
+    // Drop RFC 822 comments such as "(GMT Standard Time)".
+    let mut cleaned = String::with_capacity(raw.len());
+    let mut depth = 0usize;
+    for c in raw.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => cleaned.push(c),
+            _ => {}
+        }
+    }
+
+    let mut day: Option<u32> = None;
+    let mut month: Option<u32> = None;
+    let mut year: Option<i64> = None;
+    let mut time: Option<(u32, u32, u32)> = None;
+    let mut pm: Option<bool> = None;
+    let mut offset: Option<i64> = None;
+    let mut kind = DateParser::Lenient;
+
+    for token in tokens(&cleaned) {
+        if time.is_none() && token.contains(':') && !token.starts_with(['+', '-']) {
+            if let Some(t) = parse_time(&token) {
+                time = Some(t);
+                continue;
+            }
+        }
+        if month.is_none() && day.is_none() {
+            if let Some((y, m, d)) = parse_numeric_date(&token) {
+                (year, month, day) = (Some(y), Some(m), Some(d));
+                kind = DateParser::Numeric;
+                continue;
+            }
+        }
+        match token.as_str() {
+            "am" | "a.m" => {
+                pm = Some(false);
+                continue;
+            }
+            "pm" | "p.m" => {
+                pm = Some(true);
+                continue;
+            }
+            _ => {}
+        }
+        if offset.is_none() && (day.is_some() || time.is_some()) {
+            if let Some(off) = parse_zone(&token) {
+                offset = Some(off);
+                continue;
+            }
+        }
+        let digits = token.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            let n: i64 = digits.parse().ok()?;
+            if digits.len() == 4 && year.is_none() {
+                year = Some(n);
+            } else if day.is_none() && (1..=31).contains(&n) {
+                day = Some(n as u32);
+            } else if year.is_none() && digits.len() <= 2 {
+                year = Some(expand_year(n, digits.len()));
+            }
+            continue;
+        }
+        if month.is_none() {
+            if let Some(m) = english_month(&token) {
+                month = Some(m);
+            } else if let Some(m) = localized_month(&token) {
+                month = Some(m);
+                kind = DateParser::Localized;
+            }
+        }
+    }
+
+    let (mut h, m, s) = time.unwrap_or((0, 0, 0));
+    match pm {
+        Some(true) if h < 12 => h += 12,
+        Some(false) if h == 12 => h = 0,
+        _ => {}
+    }
+    let epoch = to_epoch(year?, month?, day?, (h, m, s), offset.unwrap_or(0))?;
+    Some((epoch, kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn well_formed_dates_use_mailparse() {
+        assert_eq!(
+            parse_date("Sun, 25 Sep 2016 18:36:33 -0400"),
+            Some((1474842993, DateParser::Rfc2822))
+        );
+        assert_eq!(parse_date("garbage"), None);
+        assert_eq!(parse_date(""), None);
+    }
+
+    #[test]
+    fn falls_back_for_broken_and_localized_dates() {
+        // 2021-03-03 10:00:00 UTC
+        let expected = 1614765600;
+        assert_eq!(
+            parse_date("Mi, 3 Mär 2021 11:00:00 +0100"),
+            Some((expected, DateParser::Localized))
+        );
+        assert_eq!(
+            parse_date("mer. 3 mars 2021 11:00 CET"),
+            Some((expected, DateParser::Localized))
+        );
+        assert_eq!(
+            parse_date("2021-03-03T10:00:00Z"),
+            Some((expected, DateParser::Iso8601))
+        );
+        assert_eq!(
+            parse_date("Wednesday, March 3, 2021 10:00 AM"),
+            Some((expected, DateParser::Lenient))
+        );
+        assert_eq!(
+            parse_date("03.03.2021 11:00 +0100 (Amsterdam)"),
+            Some((expected, DateParser::Numeric))
+        );
+        assert_eq!(
+            parse_date("3/3/21 10:00:00 AM GMT"),
+            Some((expected, DateParser::Numeric))
+        );
+        assert_eq!(parse_date("30.02.2021 10:00"), None);
+    }
+
+    #[test]
+    fn implausible_dates_fall_back_to_received() {
+        let now = 1_700_000_000;
+        let received = [
+            "from mx2.example by mx1.example; Tue, 14 Nov 2023 22:13:25 +0000".to_string(),
+            "from client by mx2.example; Tue, 14 Nov 2023 22:13:20 +0000".to_string(),
+            "from relay (misconfigured); Thu, 1 Jan 1970 00:00:00 +0000".to_string(),
+        ];
+        let good = "Tue, 14 Nov 2023 22:13:00 +0000";
+        assert_eq!(
+            message_date(Some(good), &received, now),
+            Some((1_700_000_000 - 20, DateParser::Rfc2822, DateSource::Date))
+        );
+        for date in [
+            None,
+            Some("Thu, 1 Jan 2099 00:00:00 +0000"),
+            Some("Thu, 1 Jan 1970 00:00:00 +0000"),
+        ] {
+            assert_eq!(
+                message_date(date, &received, now),
+                Some((1_700_000_000, DateParser::Rfc2822, DateSource::Received))
+            );
+        }
+        // Nothing better: the implausible header stands.
+        assert_eq!(
+            message_date(Some("Thu, 1 Jan 2099 00:00:00 +0000"), &[], now).map(|d| d.2),
+            Some(DateSource::Date)
+        );
+        assert_eq!(message_date(None, &[], now), None);
+        assert_eq!(format_rfc3339(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn formats_rfc2822_round_trip() {
+        assert_eq!(format_rfc2822(0), "Thu, 1 Jan 1970 00:00:00 +0000");
+        assert_eq!(format_http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(
+            format_rfc2822(1_709_210_096),
+            "Thu, 29 Feb 2024 12:34:56 +0000"
+        );
+        assert_eq!(
+            parse_date(&format_rfc2822(1_709_210_096)).map(|(e, _)| e),
+            Some(1_709_210_096)
+        );
+        assert_eq!(
+            format_rfc3339_millis(1_709_210_096_250),
+            "2024-02-29T12:34:56.250Z"
+        );
+    }
+}
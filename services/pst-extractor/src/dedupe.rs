@@ -0,0 +1,304 @@
+//! Message deduplication (`--dedupe`).
+//!
+//! The same message often appears in several folders of one mailbox (Inbox, an archive folder,
+//! a Sent Items copy). Each message gets a normalized hash over its Message-ID, body and
+//! attachment contents; only the first message with a given hash is emitted and later copies are
+//! listed in `duplicates.ndjson.gz` with a pointer to the primary.
+//!
+//! Across PSTs (multi-custodian cases) the same hashes are claimed in a shared index
+//! (`--dedupe-index`, S3 prefix or DynamoDB table). The first email to claim a hash owns it;
+//! later ones are still emitted but marked `is_global_duplicate` with the owner's email id.
+
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Fields that identify a message regardless of the folder it was filed in.
+pub struct HashInput<'a> {
+    pub message_id: Option<&'a str>,
+    /// body_text, or text derived from body_html.
+    pub body: &'a str,
+    /// SHA-256 of each attachment's content, in any order.
+    pub attachment_hashes: Vec<String>,
+    // Only used when there is no Message-ID, so that distinct messages with identical (often
+    // empty) bodies don't collapse.
+    pub sender_email: Option<&'a str>,
+    pub date_epoch: Option<i64>,
+    pub subject: Option<&'a str>,
+}
+
+/// Hex SHA-256 over the normalized fields. Message-IDs compare case-insensitively without angle
+/// brackets; body whitespace is collapsed because folder copies are often re-wrapped.
+pub fn message_hash(input: &HashInput) -> String {
+    let mut hasher = Sha256::new();
+    match input
+        .message_id
+        .map(|m| m.trim().trim_matches(['<', '>']).to_ascii_lowercase())
+    {
+        Some(mid) if !mid.is_empty() => hasher.update(format!("mid:{mid}\n")),
+        _ => hasher.update(format!(
+            "from:{}\ndate:{}\nsubject:{}\n",
+            input.sender_email.unwrap_or("").to_ascii_lowercase(),
+            input.date_epoch.map(|d| d.to_string()).unwrap_or_default(),
+            input.subject.unwrap_or("").trim()
+        )),
+    }
+    hasher.update(b"body:");
+    for (i, word) in input.body.split_whitespace().enumerate() {
+        if i > 0 {
+            hasher.update(b" ");
+        }
+        hasher.update(word.as_bytes());
+    }
+    let mut attachments = input.attachment_hashes.clone();
+    attachments.sort();
+    for hash in attachments {
+        hasher.update(format!("\natt:{hash}"));
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// One line of duplicates.ndjson.gz: a suppressed copy and the primary record it duplicates.
+#[derive(Serialize)]
+pub struct DuplicateEntry {
+    pub email_id: String,
+    pub source_path: String,
+    pub message_index: usize,
+    pub dedupe_hash: String,
+    pub primary_email_id: String,
+    pub primary_source_path: String,
+}
+
+/// First-seen owner of a message hash in the cross-PST index.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub email_id: String,
+    pub pst_file_id: String,
+    pub source_path: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IndexLocation {
+    /// One small JSON object per hash under this prefix.
+    S3 { bucket: String, prefix: String },
+    /// Items keyed by the string attribute `dedupe_hash`.
+    DynamoDb { table: String },
+}
+
+/// `s3://bucket/prefix` or `dynamodb://table`.
+pub fn parse_location(location: &str) -> Result<IndexLocation> {
+    if let Some(rest) = location.strip_prefix("s3://") {
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        let prefix = prefix.trim_matches('/');
+        return Ok(IndexLocation::S3 {
+            bucket: bucket.to_string(),
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("{prefix}/")
+            },
+        });
+    }
+    if let Some(table) = location.strip_prefix("dynamodb://") {
+        return Ok(IndexLocation::DynamoDb {
+            table: table.to_string(),
+        });
+    }
+    Err(anyhow!(
+        "--dedupe-index must be s3://bucket/prefix or dynamodb://table, got {location}"
+    ))
+}
+
+/// S3 key for a hash; the two-character fan-out keeps listings manageable.
+fn s3_key(prefix: &str, hash: &str) -> String {
+    format!("{prefix}{}/{hash}.json", &hash[..2.min(hash.len())])
+}
+
+pub enum GlobalIndex {
+    S3 {
+        client: aws_sdk_s3::Client,
+        bucket: String,
+        prefix: String,
+    },
+    DynamoDb {
+        client: aws_sdk_dynamodb::Client,
+        table: String,
+    },
+}
+
+impl GlobalIndex {
+    pub fn new(
+        location: IndexLocation,
+        s3: &aws_sdk_s3::Client,
+        cfg: &aws_config::SdkConfig,
+    ) -> Self {
+        match location {
+            IndexLocation::S3 { bucket, prefix } => GlobalIndex::S3 {
+                client: s3.clone(),
+                bucket,
+                prefix,
+            },
+            IndexLocation::DynamoDb { table } => GlobalIndex::DynamoDb {
+                client: aws_sdk_dynamodb::Client::new(cfg),
+                table,
+            },
+        }
+    }
+
+    /// Record `entry` as the owner of `hash` unless another email already owns it, and return
+    /// the owner. Writes are conditional (S3 `If-None-Match: *`, DynamoDB
+    /// `attribute_not_exists`), so concurrent extractors agree on a single owner.
+    pub async fn claim(&self, hash: &str, entry: &IndexEntry) -> Result<IndexEntry> {
+        match self {
+            GlobalIndex::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                let key = s3_key(prefix, hash);
+                let put = crate::upload::policy()
+                    .put_unclassed(client.put_object())
+                    .bucket(bucket)
+                    .key(&key)
+                    .if_none_match("*")
+                    .content_type("application/json")
+                    .body(serde_json::to_vec(entry)?.into())
+                    .send()
+                    .await;
+                match put {
+                    Ok(_) => Ok(entry.clone()),
+                    // 412: already claimed; 409: a concurrent conditional write is in flight.
+                    Err(e)
+                        if matches!(
+                            e.raw_response().map(|r| r.status().as_u16()),
+                            Some(412 | 409)
+                        ) =>
+                    {
+                        let body = client
+                            .get_object()
+                            .bucket(bucket)
+                            .key(&key)
+                            .send()
+                            .await
+                            .with_context(|| format!("read dedupe index s3://{bucket}/{key}"))?
+                            .body
+                            .collect()
+                            .await?
+                            .into_bytes();
+                        serde_json::from_slice(&body)
+                            .with_context(|| format!("parse dedupe index s3://{bucket}/{key}"))
+                    }
+                    Err(e) => Err(anyhow!(e))
+                        .with_context(|| format!("claim dedupe index s3://{bucket}/{key}")),
+                }
+            }
+            GlobalIndex::DynamoDb { client, table } => {
+                let s = |v: &str| AttributeValue::S(v.to_string());
+                let put = client
+                    .put_item()
+                    .table_name(table)
+                    .item("dedupe_hash", s(hash))
+                    .item("email_id", s(&entry.email_id))
+                    .item("pst_file_id", s(&entry.pst_file_id))
+                    .item("source_path", s(&entry.source_path))
+                    .condition_expression("attribute_not_exists(dedupe_hash)")
+                    .send()
+                    .await;
+                match put {
+                    Ok(_) => Ok(entry.clone()),
+                    Err(e)
+                        if e.as_service_error()
+                            .is_some_and(|se| se.is_conditional_check_failed_exception()) =>
+                    {
+                        let item = client
+                            .get_item()
+                            .table_name(table)
+                            .key("dedupe_hash", s(hash))
+                            .consistent_read(true)
+                            .send()
+                            .await
+                            .with_context(|| format!("read dedupe index {table}"))?
+                            .item
+                            .ok_or_else(|| anyhow!("dedupe index {table} lost item {hash}"))?;
+                        let get = |name: &str| match item.get(name) {
+                            Some(AttributeValue::S(v)) => v.clone(),
+                            _ => String::new(),
+                        };
+                        Ok(IndexEntry {
+                            email_id: get("email_id"),
+                            pst_file_id: get("pst_file_id"),
+                            source_path: get("source_path"),
+                        })
+                    }
+                    Err(e) => {
+                        Err(anyhow!(e)).with_context(|| format!("claim dedupe index {table}"))
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_copies_hash_alike() {
+        let inbox = HashInput {
+            message_id: Some("<ABC@example.com>"),
+            body: "Please see the\r\nattached  report.",
+            attachment_hashes: vec!["b".into(), "a".into()],
+            sender_email: None,
+            date_epoch: None,
+            subject: None,
+        };
+        let archived = HashInput {
+            message_id: Some("abc@example.com"),
+            body: "Please see the attached report.\n",
+            attachment_hashes: vec!["a".into(), "b".into()],
+            sender_email: Some("x@example.com"),
+            date_epoch: Some(1),
+            subject: Some("ignored when Message-ID is present"),
+        };
+        assert_eq!(message_hash(&inbox), message_hash(&archived));
+
+        let other_attachment = HashInput {
+            attachment_hashes: vec!["a".into()],
+            ..archived
+        };
+        assert_ne!(message_hash(&inbox), message_hash(&other_attachment));
+
+        // Without Message-IDs, empty bodies only match when sender/date/subject do.
+        let blank = |date| HashInput {
+            message_id: None,
+            body: "",
+            attachment_hashes: Vec::new(),
+            sender_email: Some("a@example.com"),
+            date_epoch: Some(date),
+            subject: Some("hi"),
+        };
+        assert_eq!(message_hash(&blank(1)), message_hash(&blank(1)));
+        assert_ne!(message_hash(&blank(1)), message_hash(&blank(2)));
+    }
+
+    #[test]
+    fn parses_index_locations() {
+        assert_eq!(
+            parse_location("s3://dedupe-bucket/case-17/").expect("s3"),
+            IndexLocation::S3 {
+                bucket: "dedupe-bucket".into(),
+                prefix: "case-17/".into()
+            }
+        );
+        assert_eq!(
+            parse_location("dynamodb://case-17-dedupe").expect("ddb"),
+            IndexLocation::DynamoDb {
+                table: "case-17-dedupe".into()
+            }
+        );
+        assert!(parse_location("/tmp/index").is_err());
+        assert_eq!(s3_key("case-17/", "abcdef"), "case-17/ab/abcdef.json");
+    }
+}
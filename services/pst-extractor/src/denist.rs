@@ -0,0 +1,148 @@
+//! DeNISTing: known system and application files by hash (`--denist-list`).
+//!
+//! Reference sets such as the NSRL RDS list millions of hashes of files that ship with operating
+//! systems and software: logos, signature images, installers, stock templates. Matching
+//! attachments are marked `is_nist`, or left out entirely, before review.
+//!
+//! The list is one hash per line (MD5 or SHA-256 hex, optionally followed by other
+//! comma/whitespace-separated columns), sorted. It is not loaded into memory. Each lookup is a
+//! binary search over byte offsets in the file (like `look(1)`), so a multi-gigabyte list costs a
+//! few dozen small reads per attachment.
+
+use anyhow::{anyhow, Context, Result};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+
+/// Lines checked for sort order when the list is opened.
+const SORT_CHECK_LINES: usize = 1000;
+
+pub struct HashList {
+    file: BufReader<File>,
+    len: u64,
+    line: Vec<u8>,
+}
+
+/// The hash column of a list line, lowercased (quotes, as in NSRL CSV exports, are stripped).
+fn key_of(line: &[u8]) -> Vec<u8> {
+    line.split(|b| b.is_ascii_whitespace() || *b == b',')
+        .next()
+        .unwrap_or_default()
+        .iter()
+        .filter(|b| **b != b'"')
+        .map(u8::to_ascii_lowercase)
+        .collect()
+}
+
+impl HashList {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+        let len = file.metadata()?.len();
+        let mut list = Self {
+            file: BufReader::with_capacity(4096, file),
+            len,
+            line: Vec::new(),
+        };
+        let mut previous: Option<Vec<u8>> = None;
+        for n in 1..=SORT_CHECK_LINES {
+            let Some(key) = list.next_key()? else { break };
+            if previous.as_ref().is_some_and(|p| *p > key) {
+                return Err(anyhow!(
+                    "hash list {} is not sorted (line {n})",
+                    path.display()
+                ));
+            }
+            previous = Some(key);
+        }
+        Ok(list)
+    }
+
+    /// Key of the next line from the current position; None at end of file.
+    fn next_key(&mut self) -> Result<Option<Vec<u8>>> {
+        self.line.clear();
+        if self.file.read_until(b'\n', &mut self.line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(key_of(&self.line)))
+    }
+
+    /// Key of the first full line starting at or after `offset`.
+    fn key_after(&mut self, offset: u64) -> Result<Option<Vec<u8>>> {
+        if offset == 0 {
+            self.file.seek(SeekFrom::Start(0))?;
+        } else {
+            // Skip the rest of the line holding byte `offset - 1`: nothing but its newline when
+            // `offset` is already a line start.
+            self.file.seek(SeekFrom::Start(offset - 1))?;
+            self.line.clear();
+            self.file.read_until(b'\n', &mut self.line)?;
+        }
+        self.next_key()
+    }
+
+    /// True when the lowercase hex `hash` is in the list.
+    pub fn contains(&mut self, hash: &str) -> Result<bool> {
+        let target = hash.to_ascii_lowercase().into_bytes();
+        // Invariant: every line starting before `low` sorts below the target.
+        let (mut low, mut high) = (0u64, self.len);
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            match self.key_after(mid)? {
+                Some(key) if key < target => low = mid,
+                _ => high = mid,
+            }
+        }
+        let mut key = self.key_after(low)?;
+        while let Some(k) = key {
+            match k.cmp(&target) {
+                Ordering::Less => key = self.next_key()?,
+                Ordering::Equal => return Ok(true),
+                Ordering::Greater => return Ok(false),
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// MD5 of `data` as lowercase hex (NSRL and most vendor lists carry MD5).
+pub fn md5_hex(data: &[u8]) -> String {
+    use md5::{Digest, Md5};
+    let mut out = String::with_capacity(32);
+    for b in Md5::digest(data) {
+        out.push_str(&format!("{b:02x}"));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_hashes_by_binary_search() {
+        let dir = std::env::temp_dir().join(format!("denist-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let path = dir.join("nsrl.txt");
+        let mut hashes: Vec<String> = (0..500u32)
+            .map(|i| md5_hex(&i.to_le_bytes()).to_uppercase())
+            .collect();
+        hashes.sort();
+        let text: String = hashes.iter().map(|h| format!("{h},file.dll\n")).collect();
+        std::fs::write(&path, text).expect("write");
+
+        let mut list = HashList::open(&path).expect("open");
+        for i in [0u32, 1, 250, 499] {
+            assert!(list.contains(&md5_hex(&i.to_le_bytes())).expect("lookup"));
+        }
+        assert!(!list.contains(&md5_hex(b"not listed")).expect("lookup"));
+        assert!(!list.contains("0").expect("lookup"));
+        assert!(!list
+            .contains("ffffffffffffffffffffffffffffffff")
+            .expect("lookup"));
+
+        std::fs::write(&path, "bb\naa\n").expect("write");
+        assert!(HashList::open(&path).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
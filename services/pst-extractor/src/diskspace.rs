@@ -0,0 +1,183 @@
+//! Scratch space guard (`--min-free-bytes`) and work-dir cleanup (`--keep-workdir`).
+//!
+//! Jobs share the scratch volume, and readpst typically expands a PST 3-5x. A job that fills the
+//! volume breaks every other job writing to it, so each job checks free space:
+//!
+//! * before each download: the object must fit with `--min-free-bytes` to spare;
+//! * before readpst: a warning is logged when 4x the PST would not fit;
+//! * every 250 ms while readpst runs: once free space drops below `--min-free-bytes`, readpst is
+//!   killed and the job fails.
+//!
+//! A job's `work_root` (`{work_dir}/{pst_file_id}`) is emptied when the job starts, so a rerun
+//! never parses files left by an earlier attempt, and removed when it ends, whatever the outcome.
+//! Because both delete recursively, `pst_file_id` must be a single plain path component (see
+//! [`check_job_id`]): an absolute path or `..` would reach outside the scratch root, and an
+//! empty id would wipe the scratch root itself, including other jobs' directories.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Typical readpst output size relative to the PST, for the pre-readpst warning.
+pub const READPST_EXPANSION: u64 = 4;
+
+/// Bytes available to this process on the filesystem holding `path` (or its nearest existing
+/// ancestor). None where it can't be determined.
+pub fn free_bytes(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    statvfs_free(existing)
+}
+
+#[cfg(unix)]
+fn statvfs_free(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid out-pointer.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn statvfs_free(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Fail unless `needed` bytes fit under `path` with `min_free` to spare.
+pub fn ensure_free(path: &Path, needed: u64, min_free: u64, what: &str) -> Result<()> {
+    let Some(free) = free_bytes(path) else {
+        return Ok(());
+    };
+    if free < needed.saturating_add(min_free) {
+        return Err(anyhow!(
+            "not enough scratch space for {what}: {free} bytes free on {}, need {needed} plus \
+             --min-free-bytes {min_free}",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Free bytes under `path` when they are below `min_free` (never when `min_free` is 0).
+pub fn below_min(path: &Path, min_free: u64) -> Option<u64> {
+    free_bytes(path).filter(|free| min_free > 0 && *free < min_free)
+}
+
+/// A job's scratch directory, removed on drop unless kept.
+pub struct WorkRoot {
+    path: PathBuf,
+    keep: bool,
+}
+
+/// Refuse a `pst_file_id` that isn't one plain, non-empty path component.
+pub fn check_job_id(pst_file_id: &str) -> Result<()> {
+    let mut components = Path::new(pst_file_id).components();
+    let plain = matches!(components.next(), Some(std::path::Component::Normal(_)))
+        && components.next().is_none()
+        && !pst_file_id.contains(['/', '\\', '\0']);
+    if plain {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "invalid pst_file_id {pst_file_id:?}: must be a single path component"
+        ))
+    }
+}
+
+impl WorkRoot {
+    /// The job's work root under `work_dir`, after checking `pst_file_id`.
+    pub fn for_job(work_dir: &Path, pst_file_id: &str, keep: bool) -> Result<Self> {
+        check_job_id(pst_file_id)?;
+        Self::create(work_dir.join(pst_file_id), keep)
+    }
+
+    /// Create `path`, clearing anything an earlier run of the same job left there.
+    fn create(path: PathBuf, keep: bool) -> Result<Self> {
+        if path.exists() {
+            tracing::warn!(path = %path.display(), "removing work dir left by an earlier run");
+            fs::remove_dir_all(&path).with_context(|| format!("clear {}", path.display()))?;
+        }
+        fs::create_dir_all(&path).with_context(|| format!("create {}", path.display()))?;
+        Ok(Self { path, keep })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for WorkRoot {
+    fn drop(&mut self) {
+        if self.keep {
+            tracing::info!(path = %self.path.display(), "keeping work dir");
+        } else if let Err(e) = fs::remove_dir_all(&self.path) {
+            tracing::warn!(path = %self.path.display(), "failed to remove work dir: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_free_space_and_cleans_up_work_root() {
+        let base = std::env::temp_dir().join(format!("diskspace-{}", uuid::Uuid::new_v4()));
+        let path = base.join("job");
+        fs::create_dir_all(path.join("extract")).expect("stale dir");
+        fs::write(path.join("extract/old.eml"), b"stale").expect("stale file");
+
+        let root = WorkRoot::create(path.clone(), false).expect("work root");
+        assert!(!path.join("extract").exists());
+        if cfg!(unix) {
+            // Not created yet: measured on the nearest existing ancestor.
+            assert!(free_bytes(&path.join("not/yet")).expect("free") > 0);
+            assert!(ensure_free(&path, 1, 0, "test").is_ok());
+            assert!(below_min(&path, 0).is_none());
+            assert!(below_min(&path, u64::MAX).is_some());
+            let err = ensure_free(&path, u64::MAX / 2, 1 << 30, "the PST").expect_err("full");
+            assert!(err.to_string().contains("--min-free-bytes 1073741824"));
+        }
+        drop(root);
+        assert!(!path.exists());
+
+        let kept = WorkRoot::create(path.clone(), true).expect("work root");
+        drop(kept);
+        assert!(path.exists());
+        fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn rejects_job_ids_that_leave_the_work_dir() {
+        let base = std::env::temp_dir().join(format!("diskspace-{}", uuid::Uuid::new_v4()));
+        let other = base.join("other-job");
+        fs::create_dir_all(&other).expect("other job");
+        for id in [
+            "",
+            ".",
+            "..",
+            "../other-job",
+            "/tmp",
+            "a/b",
+            "a\\b",
+            "nul\0",
+            "./x",
+        ] {
+            assert!(check_job_id(id).is_err(), "{id:?}");
+            assert!(
+                WorkRoot::for_job(&base.join("work"), id, false).is_err(),
+                "{id:?}"
+            );
+        }
+        assert!(other.exists());
+
+        check_job_id("8f1c-2024.pst").expect("plain id");
+        let root = WorkRoot::for_job(&base, "job", false).expect("work root");
+        assert_eq!(root.path(), base.join("job"));
+        drop(root);
+        fs::remove_dir_all(&base).ok();
+    }
+}
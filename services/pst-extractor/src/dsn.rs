@@ -0,0 +1,160 @@
+//! Delivery status notifications (RFC 3464 `multipart/report; report-type=delivery-status`).
+//!
+//! A bounce carries a human-readable explanation, a machine-readable `message/delivery-status`
+//! part, and usually the returned message. The status part is a block of per-message fields
+//! followed by one block per recipient. It is parsed here so that bounce analysis can filter on
+//! action and status code instead of matching free-text bodies.
+
+use serde::{Deserialize, Serialize};
+
+/// The machine-readable part of one DSN.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeliveryStatus {
+    pub reporting_mta: Option<String>,
+    pub arrival_date: Option<String>,
+    pub recipients: Vec<RecipientStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecipientStatus {
+    /// Address the report is about (`Final-Recipient`, falling back to `Original-Recipient`).
+    pub recipient: Option<String>,
+    /// `failed`, `delayed`, `delivered`, `relayed` or `expanded`.
+    pub action: Option<String>,
+    /// Enhanced status code, e.g. `5.1.1`.
+    pub status: Option<String>,
+    pub remote_mta: Option<String>,
+    pub diagnostic_code: Option<String>,
+}
+
+impl DeliveryStatus {
+    /// Recipients the report says could not be delivered to.
+    pub fn failed_recipients(&self) -> impl Iterator<Item = &str> {
+        self.recipients
+            .iter()
+            .filter(|r| r.action.as_deref() == Some("failed"))
+            .filter_map(|r| r.recipient.as_deref())
+    }
+}
+
+/// True for the content types that hold DSN fields (RFC 3464 and the RFC 6533 UTF-8 variant).
+pub fn is_delivery_status_type(mimetype: &str) -> bool {
+    mimetype.eq_ignore_ascii_case("message/delivery-status")
+        || mimetype.eq_ignore_ascii_case("message/global-delivery-status")
+}
+
+/// Value after the `type;` prefix of address and MTA fields (`rfc822; a@b`, `dns; mx.example`).
+fn typed_value(value: &str) -> String {
+    value
+        .split_once(';')
+        .map_or(value, |(_, v)| v)
+        .trim()
+        .to_string()
+}
+
+/// Parse the body of a delivery-status part. None if it holds no recipient block.
+pub fn parse(body: &str) -> Option<DeliveryStatus> {
+    let mut report = DeliveryStatus::default();
+    let mut blocks: Vec<Vec<(String, String)>> = vec![Vec::new()];
+    for line in body.lines() {
+        if line.trim().is_empty() {
+            if !blocks.last().is_some_and(Vec::is_empty) {
+                blocks.push(Vec::new());
+            }
+            continue;
+        }
+        let block = blocks.last_mut().expect("at least one block");
+        // Folded field: continue the previous value.
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = block.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            block.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let mut blocks = blocks.into_iter().filter(|b| !b.is_empty());
+    for (name, value) in blocks.next()? {
+        match name.as_str() {
+            "reporting-mta" => report.reporting_mta = Some(typed_value(&value)),
+            "arrival-date" => report.arrival_date = Some(value),
+            _ => {}
+        }
+    }
+    for block in blocks {
+        let mut recipient = RecipientStatus::default();
+        let mut original = None;
+        for (name, value) in block {
+            match name.as_str() {
+                "final-recipient" => recipient.recipient = Some(typed_value(&value)),
+                "original-recipient" => original = Some(typed_value(&value)),
+                "action" => recipient.action = Some(value.to_ascii_lowercase()),
+                // Drop trailing comments such as "5.1.1 (bad destination mailbox)".
+                "status" => recipient.status = value.split_whitespace().next().map(str::to_string),
+                "remote-mta" => recipient.remote_mta = Some(typed_value(&value)),
+                "diagnostic-code" => recipient.diagnostic_code = Some(typed_value(&value)),
+                _ => {}
+            }
+        }
+        recipient.recipient = recipient.recipient.or(original);
+        recipient.recipient = recipient
+            .recipient
+            .map(|r| r.trim_matches(['<', '>']).to_ascii_lowercase());
+        report.recipients.push(recipient);
+    }
+    Some(report).filter(|r| !r.recipients.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_recipient_blocks() {
+        let body = "Reporting-MTA: dns; mail.contractor.example\r\n\
+            Arrival-Date: Mon, 4 Mar 2024 09:15:02 +0000\r\n\
+            \r\n\
+            Final-Recipient: rfc822; Site.Office@Client.example\r\n\
+            Action: failed\r\n\
+            Status: 5.1.1 (bad destination mailbox address)\r\n\
+            Remote-MTA: dns; mx1.client.example\r\n\
+            Diagnostic-Code: smtp; 550 5.1.1 <site.office@client.example>:\r\n\
+            \x20Recipient address rejected: User unknown\r\n\
+            \r\n\
+            Original-Recipient: rfc822; qs@client.example\r\n\
+            Action: delayed\r\n\
+            Status: 4.4.7\r\n";
+        let report = parse(body).expect("dsn");
+        assert_eq!(
+            report.reporting_mta.as_deref(),
+            Some("mail.contractor.example")
+        );
+        assert_eq!(report.recipients.len(), 2);
+        let failed = &report.recipients[0];
+        assert_eq!(
+            failed.recipient.as_deref(),
+            Some("site.office@client.example")
+        );
+        assert_eq!(failed.status.as_deref(), Some("5.1.1"));
+        assert_eq!(failed.remote_mta.as_deref(), Some("mx1.client.example"));
+        assert_eq!(
+            failed.diagnostic_code.as_deref(),
+            Some(
+                "550 5.1.1 <site.office@client.example>: Recipient address rejected: User unknown"
+            )
+        );
+        assert_eq!(
+            report.recipients[1].recipient.as_deref(),
+            Some("qs@client.example")
+        );
+        assert_eq!(
+            report.failed_recipients().collect::<Vec<_>>(),
+            ["site.office@client.example"]
+        );
+        assert!(parse("Reporting-MTA: dns; x\r\n").is_none());
+    }
+}
@@ -0,0 +1,306 @@
+//! RFC 2047 encoded words in header values and RFC 2231 extended MIME parameters.
+//!
+//! mailparse decodes well-formed encoded words, but Outlook and older gateways routinely emit
+//! words glued together without whitespace (`=?UTF-8?Q?a?==?UTF-8?Q?b?=`), RFC 2231 language
+//! tags (`=?UTF-8*en?Q?...?=`) and encoded words inside quoted filenames. Those would otherwise
+//! reach `subject`, `from_header` and attachment names as raw MIME escapes.
+
+use charset::Charset;
+
+/// Decode a raw (possibly folded) header value. Text outside encoded words is kept as is,
+/// whitespace between two adjacent encoded words is dropped as RFC 2047 requires, and words with
+/// an unknown charset or a malformed payload are left undecoded.
+pub fn decode_header(raw: &[u8]) -> String {
+    let text = match std::str::from_utf8(raw) {
+        Ok(s) => s.to_string(),
+        Err(_) => crate::charsets::decode(raw, None).0,
+    };
+    let text = unfold(&text);
+    if !text.contains("=?") {
+        return text;
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    // Whitespace between two encoded words is discarded.
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        match decode_word(&rest[start..]) {
+            Some((decoded, used)) => {
+                let between = &rest[..start];
+                if !(after_word && between.trim().is_empty()) {
+                    out.push_str(between);
+                }
+                out.push_str(&decoded);
+                rest = &rest[start + used..];
+                after_word = true;
+            }
+            None => {
+                out.push_str(&rest[..start + 2]);
+                rest = &rest[start + 2..];
+                after_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn unfold(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+/// Decode one `=?charset[*lang]?enc?payload?=` at the start of `s`, returning the text and the
+/// number of bytes consumed.
+fn decode_word(s: &str) -> Option<(String, usize)> {
+    let body = s.strip_prefix("=?")?;
+    let (label, body) = body.split_once('?')?;
+    let (encoding, body) = body.split_once('?')?;
+    let end = body.find("?=")?;
+    let payload = &body[..end];
+    if payload.contains(char::is_whitespace) || encoding.len() != 1 {
+        return None;
+    }
+    let used = 2 + label.len() + 1 + encoding.len() + 1 + end + 2;
+    let label = label.split('*').next().unwrap_or(label);
+    let bytes = match encoding.as_bytes()[0].to_ascii_uppercase() {
+        b'B' => base64_decode(payload)?,
+        b'Q' => q_decode(payload),
+        _ => return None,
+    };
+    let charset = Charset::for_label_no_replacement(label.as_bytes())?;
+    let (decoded, _, _) = charset.decode(&bytes);
+    Some((decoded.into_owned(), used))
+}
+
+fn q_decode(payload: &str) -> Vec<u8> {
+    let bytes = payload.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'_' => out.push(b' '),
+            b'=' => match hex_pair(&bytes[i + 1..]) {
+                Some(b) => {
+                    out.push(b);
+                    i += 2;
+                }
+                None => out.push(b'='),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn hex_pair(bytes: &[u8]) -> Option<u8> {
+    let hi = (*bytes.first()? as char).to_digit(16)?;
+    let lo = (*bytes.get(1)? as char).to_digit(16)?;
+    Some((hi * 16 + lo) as u8)
+}
+
+/// Lenient base64: ignores padding and stray characters, as mail clients do.
+pub fn base64_decode(payload: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(payload.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in payload.bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        acc = (acc << 6) | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Split a parameterized header value (`type; a=1; b="x;y"`) into lowercase names and values,
+/// honouring quoted strings.
+fn split_params(header_value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for c in header_value.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' if in_quotes => escaped = true,
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            ';' if !in_quotes => segments.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    segments.push(current);
+    for segment in segments.into_iter().skip(1) {
+        if let Some((k, v)) = segment.split_once('=') {
+            let v = v.trim();
+            let v = v
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(v);
+            params.push((k.trim().to_ascii_lowercase(), v.to_string()));
+        }
+    }
+    params
+}
+
+/// Value of parameter `key`, assembling RFC 2231 continuations (`key*0*=`, `key*1=`, ...) and
+/// charset/percent-encoded values (`key*=UTF-8''r%C3%A9sum%C3%A9.pdf`). Plain values that carry
+/// RFC 2047 encoded words (a common Outlook habit for filenames) are decoded too.
+pub fn param(header_value: &str, key: &str) -> Option<String> {
+    let key = key.to_ascii_lowercase();
+    let params = split_params(header_value);
+
+    // RFC 2231 forms take precedence over the plain parameter when both are present.
+    let mut sections: Vec<(u32, bool, &str)> = Vec::new();
+    for (name, value) in &params {
+        let Some(suffix) = name
+            .strip_prefix(key.as_str())
+            .and_then(|s| s.strip_prefix('*'))
+        else {
+            continue;
+        };
+        let (index, extended) = match suffix {
+            "" => (0, true),
+            s => match s.strip_suffix('*') {
+                Some(n) => (n.parse().ok()?, true),
+                None => (s.parse().ok()?, false),
+            },
+        };
+        sections.push((index, extended, value));
+    }
+    if !sections.is_empty() {
+        sections.sort_by_key(|&(index, _, _)| index);
+        let mut charset = None;
+        let mut bytes = Vec::new();
+        for (i, (_, extended, value)) in sections.iter().enumerate() {
+            let mut value = *value;
+            if *extended && i == 0 {
+                // charset'language'encoded-text
+                let mut pieces = value.splitn(3, '\'');
+                if let (Some(cs), Some(_lang), Some(encoded)) =
+                    (pieces.next(), pieces.next(), pieces.next())
+                {
+                    charset = Charset::for_label_no_replacement(cs.as_bytes());
+                    value = encoded;
+                }
+            }
+            if *extended {
+                bytes.extend(percent_decode(value));
+            } else {
+                bytes.extend_from_slice(value.as_bytes());
+            }
+        }
+        let decoded = match charset {
+            Some(cs) => cs.decode(&bytes).0.into_owned(),
+            None => decode_header(&bytes),
+        };
+        return Some(decoded).filter(|v| !v.trim().is_empty());
+    }
+
+    params
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| decode_header(value.trim().trim_matches('\'').as_bytes()))
+        .filter(|v| !v.trim().is_empty())
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(b) = hex_pair(&bytes[i + 1..]) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_encoded_words_mailparse_leaves_raw() {
+        assert_eq!(
+            decode_header(b"=?UTF-8?Q?caf=C3=A9?==?UTF-8?Q?_bar?="),
+            "café bar"
+        );
+        assert_eq!(
+            decode_header(b"=?utf-8?b?w6k=?=\r\n =?utf-8?b?w6k=?= and =?UTF-8*en?Q?hi?="),
+            "éé and hi"
+        );
+        assert_eq!(
+            decode_header(b"\"=?ISO-8859-1?Q?Andr=E9?= Pirard\" <andre@example.com>"),
+            "\"André Pirard\" <andre@example.com>"
+        );
+        // Unknown charsets and plain text pass through untouched.
+        assert_eq!(
+            decode_header(b"=?x-bogus?Q?a?= 50=?"),
+            "=?x-bogus?Q?a?= 50=?"
+        );
+        assert_eq!(decode_header(b"Re: plain"), "Re: plain");
+    }
+
+    #[test]
+    fn assembles_rfc2231_parameters() {
+        assert_eq!(
+            param(
+                "attachment; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf",
+                "filename"
+            )
+            .as_deref(),
+            Some("résumé.pdf")
+        );
+        assert_eq!(
+            param(
+                "application/pdf; name*1=\"e.pdf\"; name*0*=UTF-8''r%C3%A9sum",
+                "name"
+            )
+            .as_deref(),
+            Some("résume.pdf")
+        );
+        assert_eq!(
+            param(
+                "attachment; filename=\"=?UTF-8?B?0J7RgtGH0ZHRgi5kb2N4?=\"",
+                "filename"
+            )
+            .as_deref(),
+            Some("Отчёт.docx")
+        );
+        assert_eq!(
+            param("attachment; filename=\"a;b.txt\"; size=3", "filename").as_deref(),
+            Some("a;b.txt")
+        );
+        assert_eq!(param("inline", "filename"), None);
+    }
+}
@@ -0,0 +1,491 @@
+//! Gzip output written as a series of independent members, each ending on a record boundary.
+//!
+//! Concatenated gzip members are still one valid `.gz` file (`gzip -dc`, Python's `gzip`, and
+//! `MultiGzDecoder` read straight through), but each member can also be decompressed on its own.
+//! The member index (`<file>.members.json`) gives every member's byte range and record range, so
+//! a downstream reader can split one large part across workers with S3 range GETs, and a file cut
+//! short by a crash is readable up to its last complete member.
+//!
+//! With `--compression zstd` the same files are written as `.zst`, one zstd frame per member.
+//! Concatenated frames are likewise one valid stream (`zstd -dc`, Athena) and each frame
+//! decodes on its own, so the member index means the same thing for either codec.
+
+use anyhow::{anyhow, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Codec for the email and attachment NDJSON/CSV outputs (`--compression`).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Codec {
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+/// A codec and its level (`--compression-level`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputCompression {
+    pub codec: Codec,
+    pub level: u32,
+}
+
+impl Default for OutputCompression {
+    fn default() -> Self {
+        Self {
+            codec: Codec::Gzip,
+            level: 6,
+        }
+    }
+}
+
+impl OutputCompression {
+    /// `level` None takes the codec's default: 6 for gzip (flate2's default), 3 for zstd.
+    pub fn new(codec: Codec, level: Option<u32>) -> Result<Self> {
+        let (default, range) = match codec {
+            Codec::Gzip => (6, 0..=9),
+            Codec::Zstd => (3, 1..=22),
+        };
+        let level = level.unwrap_or(default);
+        if !range.contains(&level) {
+            return Err(anyhow!(
+                "--compression-level {level} is out of range for {} ({}-{})",
+                format!("{codec:?}").to_lowercase(),
+                range.start(),
+                range.end()
+            ));
+        }
+        Ok(Self { codec, level })
+    }
+
+    /// File name extension without the dot: `gz` or `zst`.
+    pub fn extension(&self) -> &'static str {
+        match self.codec {
+            Codec::Gzip => "gz",
+            Codec::Zstd => "zst",
+        }
+    }
+
+    /// Decoder reading every member of a file written with this codec.
+    pub fn reader(&self, file: File) -> io::Result<Box<dyn Read>> {
+        Ok(match self.codec {
+            Codec::Gzip => Box::new(flate2::read::MultiGzDecoder::new(file)),
+            Codec::Zstd => Box::new(zstd::Decoder::new(file)?),
+        })
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Counting<File>>),
+    Zstd(zstd::Encoder<'static, Counting<File>>),
+}
+
+impl Encoder {
+    fn new(file: Counting<File>, compression: OutputCompression) -> io::Result<Self> {
+        Ok(match compression.codec {
+            Codec::Gzip => Encoder::Gzip(GzEncoder::new(file, Compression::new(compression.level))),
+            Codec::Zstd => Encoder::Zstd(zstd::Encoder::new(file, compression.level as i32)?),
+        })
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Zstd(encoder) => encoder,
+        }
+    }
+
+    fn finish(self) -> io::Result<Counting<File>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// One gzip member: compressed byte range in the file and the records it holds.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub offset: u64,
+    pub length: u64,
+    pub first_record: u64,
+    pub records: u64,
+    pub uncompressed_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MemberIndex {
+    pub records_total: u64,
+    /// Lines before the first record (CSV header), all in member 0.
+    pub header_lines: u64,
+    pub members: Vec<Member>,
+}
+
+struct Counting<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Gzip (or zstd) writer that starts a new member once the current one holds `member_bytes` of
+/// uncompressed data (0 = a single member). Callers write one record and then call
+/// [`MemberWriter::end_record`]; members only ever end there.
+pub struct MemberWriter {
+    // Exactly one is Some: an open member, or the file between members. Members are opened on
+    // the first write so a roll-over after the last record doesn't leave an empty member.
+    encoder: Option<Encoder>,
+    idle: Option<Counting<File>>,
+    compression: OutputCompression,
+    member_bytes: u64,
+    header_lines: u64,
+    records: u64,
+    current: Member,
+    members: Vec<Member>,
+}
+
+impl MemberWriter {
+    pub fn new(file: File, member_bytes: u64, compression: OutputCompression) -> Self {
+        Self {
+            encoder: None,
+            idle: Some(Counting {
+                inner: file,
+                written: 0,
+            }),
+            compression,
+            member_bytes,
+            header_lines: 0,
+            records: 0,
+            current: Member {
+                offset: 0,
+                length: 0,
+                first_record: 0,
+                records: 0,
+                uncompressed_bytes: 0,
+            },
+            members: Vec::new(),
+        }
+    }
+
+    /// Write a line that precedes the records (e.g. the CSV header).
+    pub fn write_header(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self, "{line}")?;
+        self.header_lines += 1;
+        Ok(())
+    }
+
+    /// Mark the end of a record; rolls over to a new member if this one is full.
+    pub fn end_record(&mut self) -> io::Result<()> {
+        self.records += 1;
+        self.current.records += 1;
+        if self.member_bytes > 0 && self.current.uncompressed_bytes >= self.member_bytes {
+            self.close_member()?;
+        }
+        Ok(())
+    }
+
+    fn open_member(&mut self) -> io::Result<&mut dyn Write> {
+        if let Some(file) = self.idle.take() {
+            self.encoder = Some(Encoder::new(file, self.compression)?);
+        }
+        Ok(self
+            .encoder
+            .as_mut()
+            .expect("member writer already finished")
+            .get_mut())
+    }
+
+    fn close_member(&mut self) -> io::Result<()> {
+        let Some(encoder) = self.encoder.take() else {
+            return Ok(());
+        };
+        let mut counting = encoder.finish()?;
+        // Each finished member is on disk before the next one starts.
+        counting.flush()?;
+        let end = counting.written;
+        let next = Member {
+            offset: end,
+            length: 0,
+            first_record: self.records,
+            records: 0,
+            uncompressed_bytes: 0,
+        };
+        let mut done = std::mem::replace(&mut self.current, next);
+        done.length = end - done.offset;
+        self.members.push(done);
+        self.idle = Some(counting);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<MemberIndex> {
+        if self.members.is_empty() {
+            // An empty file isn't valid gzip; write one empty member instead.
+            self.open_member()?;
+        }
+        self.close_member()?;
+        Ok(MemberIndex {
+            records_total: self.records,
+            header_lines: self.header_lines,
+            members: self.members,
+        })
+    }
+}
+
+impl Write for MemberWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.open_member()?.write(buf)?;
+        self.current.uncompressed_bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.encoder.as_mut() {
+            Some(encoder) => encoder.get_mut().flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// One `<stem>-part-NNNN.ndjson.gz` file cut from a larger output.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    /// File name; the object key is this under the output prefix.
+    pub name: String,
+    pub records: u64,
+    pub uncompressed_bytes: u64,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// Cut the NDJSON file `src` into parts under `dir`, starting a new part once the current
+/// one holds `max_rows` records or `max_bytes` uncompressed bytes (0 = no limit on that axis).
+/// Parts are written as members of `member_bytes` with the file's own compression. An empty
+/// `src` still gives one (empty) part so every output has at least one object.
+pub fn split_parts(
+    src: &Path,
+    dir: &Path,
+    stem: &str,
+    max_rows: u64,
+    max_bytes: u64,
+    member_bytes: u64,
+    compression: OutputCompression,
+) -> io::Result<Vec<(Part, MemberIndex)>> {
+    let reader = BufReader::new(compression.reader(File::open(src)?)?);
+    let ext = compression.extension();
+    let mut parts = Vec::new();
+    let mut current: Option<(Part, MemberWriter)> = None;
+    let close = |(part, writer): (Part, MemberWriter)| writer.finish().map(|index| (part, index));
+    for line in reader.lines() {
+        let line = line?;
+        let (part, writer) = match current.as_mut() {
+            Some(open) => open,
+            None => {
+                let name = format!("{stem}-part-{:04}.ndjson.{ext}", parts.len() + 1);
+                let path = dir.join(&name);
+                let writer = MemberWriter::new(File::create(&path)?, member_bytes, compression);
+                let part = Part {
+                    name,
+                    records: 0,
+                    uncompressed_bytes: 0,
+                    path,
+                };
+                current.insert((part, writer))
+            }
+        };
+        writeln!(writer, "{line}")?;
+        writer.end_record()?;
+        part.records += 1;
+        part.uncompressed_bytes += line.len() as u64 + 1;
+        let full = (max_rows > 0 && part.records >= max_rows)
+            || (max_bytes > 0 && part.uncompressed_bytes >= max_bytes);
+        if full {
+            parts.push(close(current.take().expect("open part"))?);
+        }
+    }
+    if let Some(open) = current.take() {
+        parts.push(close(open)?);
+    }
+    if parts.is_empty() {
+        let name = format!("{stem}-part-0001.ndjson.{ext}");
+        let path = dir.join(&name);
+        let writer = MemberWriter::new(File::create(&path)?, member_bytes, compression);
+        let part = Part {
+            name,
+            records: 0,
+            uncompressed_bytes: 0,
+            path,
+        };
+        parts.push(close((part, writer))?);
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn members_split_on_record_boundaries_and_decode_independently() {
+        let path = std::env::temp_dir().join(format!("gzmembers-{}.gz", uuid::Uuid::new_v4()));
+        let mut writer = MemberWriter::new(
+            File::create(&path).expect("create"),
+            64,
+            OutputCompression::default(),
+        );
+        writer.write_header("id,body").expect("header");
+        for i in 0..10 {
+            writeln!(writer, "{i},\"record number {i}\"").expect("write");
+            writer.end_record().expect("end");
+        }
+        let index = writer.finish().expect("finish");
+        let bytes = std::fs::read(&path).expect("read");
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(index.records_total, 10);
+        assert!(index.members.len() > 1);
+        assert_eq!(
+            index.members.iter().map(|m| m.records).sum::<u64>(),
+            index.records_total
+        );
+        let last = index.members.last().expect("member");
+        assert_eq!(last.offset + last.length, bytes.len() as u64);
+        assert!(index.members.iter().all(|m| m.records > 0));
+
+        // The whole file reads as one stream...
+        let mut all = String::new();
+        flate2::read::MultiGzDecoder::new(&bytes[..])
+            .read_to_string(&mut all)
+            .expect("multi");
+        assert_eq!(all.lines().count(), 11);
+        // ...and each member on its own holds exactly its records.
+        for member in &index.members {
+            let range = &bytes[member.offset as usize..(member.offset + member.length) as usize];
+            let mut text = String::new();
+            flate2::read::GzDecoder::new(range)
+                .read_to_string(&mut text)
+                .expect("member");
+            let header = if member.offset == 0 { 1 } else { 0 };
+            assert_eq!(text.lines().count() as u64, member.records + header);
+            assert_eq!(
+                text.lines()
+                    .nth(header as usize)
+                    .and_then(|l| l.split(',').next()),
+                Some(member.first_record.to_string().as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn splits_ndjson_into_parts_by_rows_and_bytes() {
+        let dir = std::env::temp_dir().join(format!("gzparts-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let src = dir.join("emails.ndjson.gz");
+        let mut writer = MemberWriter::new(
+            File::create(&src).expect("create"),
+            0,
+            OutputCompression::default(),
+        );
+        for i in 0..7 {
+            writeln!(writer, "{{\"n\":{i}}}").expect("write");
+            writer.end_record().expect("end");
+        }
+        writer.finish().expect("finish");
+
+        let parts = split_parts(&src, &dir, "emails", 3, 0, 0, OutputCompression::default())
+            .expect("split");
+        let names: Vec<_> = parts.iter().map(|(p, _)| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "emails-part-0001.ndjson.gz",
+                "emails-part-0002.ndjson.gz",
+                "emails-part-0003.ndjson.gz"
+            ]
+        );
+        assert_eq!(
+            parts.iter().map(|(p, _)| p.records).collect::<Vec<_>>(),
+            [3, 3, 1]
+        );
+        let mut text = String::new();
+        flate2::read::MultiGzDecoder::new(File::open(&parts[2].0.path).expect("part"))
+            .read_to_string(&mut text)
+            .expect("read part");
+        assert_eq!(text, "{\"n\":6}\n");
+
+        // Each line is 8 bytes with its newline: a 20-byte limit closes parts after 3 records.
+        let by_bytes =
+            split_parts(&src, &dir, "b", 0, 20, 0, OutputCompression::default()).expect("split");
+        assert_eq!(by_bytes[0].0.uncompressed_bytes, 24);
+        assert_eq!(by_bytes.len(), 3);
+
+        let empty = dir.join("empty.ndjson.gz");
+        MemberWriter::new(
+            File::create(&empty).expect("create"),
+            0,
+            OutputCompression::default(),
+        )
+        .finish()
+        .expect("finish");
+        let parts = split_parts(
+            &empty,
+            &dir,
+            "attachments",
+            3,
+            0,
+            0,
+            OutputCompression::default(),
+        )
+        .expect("split");
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].0.records, 0);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn zstd_members_are_frames_that_decode_independently() {
+        assert!(OutputCompression::new(Codec::Gzip, Some(12)).is_err());
+        let zstd = OutputCompression::new(Codec::Zstd, None).expect("zstd");
+        assert_eq!((zstd.level, zstd.extension()), (3, "zst"));
+
+        let dir = std::env::temp_dir().join(format!("zstmembers-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let path = dir.join("emails.ndjson.zst");
+        let mut writer = MemberWriter::new(File::create(&path).expect("create"), 16, zstd);
+        for i in 0..6 {
+            writeln!(writer, "{{\"record\":{i},\"pad\":\"xxxxxxxx\"}}").expect("write");
+            writer.end_record().expect("end");
+        }
+        let index = writer.finish().expect("finish");
+        let bytes = std::fs::read(&path).expect("read");
+        assert_eq!(index.members.len(), 6);
+        let member = &index.members[4];
+        let frame = &bytes[member.offset as usize..(member.offset + member.length) as usize];
+        let text = String::from_utf8(zstd::decode_all(frame).expect("frame")).expect("utf8");
+        assert_eq!(text, "{\"record\":4,\"pad\":\"xxxxxxxx\"}\n");
+
+        let parts = split_parts(&path, &dir, "emails", 4, 0, 0, zstd).expect("split");
+        assert_eq!(parts[1].0.name, "emails-part-0002.ndjson.zst");
+        let mut all = String::new();
+        zstd.reader(File::open(&path).expect("open"))
+            .expect("decoder")
+            .read_to_string(&mut all)
+            .expect("read all");
+        assert_eq!(all.lines().count(), 6);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
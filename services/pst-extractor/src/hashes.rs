@@ -0,0 +1,73 @@
+//! Extra digests on records (`--hash-algos`).
+//!
+//! SHA-256 is always computed (`attachment_hash`, dedupe). Review platforms that key
+//! deduplication and chain of custody on older digests get them alongside: attachments gain
+//! `md5` / `sha1`, and each top-level email gets `raw_hashes`, the requested digests of the
+//! message's raw RFC822 bytes.
+
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgo {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+/// Digests of one byte string, lowercase hex; only the requested algorithms are set.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct Digests {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl Digests {
+    /// None when no algorithm was requested.
+    pub fn compute(algos: &[HashAlgo], data: &[u8]) -> Option<Self> {
+        if algos.is_empty() {
+            return None;
+        }
+        let wants = |algo| algos.contains(&algo);
+        Some(Self {
+            md5: wants(HashAlgo::Md5).then(|| hex(&Md5::digest(data))),
+            sha1: wants(HashAlgo::Sha1).then(|| hex(&Sha1::digest(data))),
+            sha256: wants(HashAlgo::Sha256).then(|| hex(&Sha256::digest(data))),
+        })
+    }
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_only_requested_digests() {
+        assert_eq!(Digests::compute(&[], b"abc"), None);
+        let digests = Digests::compute(&[HashAlgo::Md5, HashAlgo::Sha1], b"abc").expect("digests");
+        assert_eq!(
+            digests.md5.as_deref(),
+            Some("900150983cd24fb0d6963f7d28e17f72")
+        );
+        assert_eq!(
+            digests.sha1.as_deref(),
+            Some("a9993e364706816aba3e25717850c26c9cd0d89d")
+        );
+        assert_eq!(digests.sha256, None);
+        assert_eq!(
+            serde_json::to_string(&digests).expect("json"),
+            r#"{"md5":"900150983cd24fb0d6963f7d28e17f72","sha1":"a9993e364706816aba3e25717850c26c9cd0d89d"}"#
+        );
+    }
+}
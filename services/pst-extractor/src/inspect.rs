@@ -0,0 +1,355 @@
+//! `inspect`: size up a PST before scheduling the real job.
+//!
+//! Reads the PST header and walks the node B-tree ([MS-PST] 2.2.2.6 and 2.2.2.7) without
+//! running readpst or uploading anything. The report gives the format and version, the
+//! header's encryption method, node-type counts as message and folder estimates, and the scratch
+//! space an extraction needs (the download plus readpst's typical expansion).
+//!
+//! The node B-tree lists top-level nodes only, so the counts include search folders and hidden
+//! associated messages (reported separately), but not attachments or embedded messages, which
+//! live in subnode trees. 4 KiB-page files (wVer 36) report their header only.
+
+use crate::diskspace::READPST_EXPANSION;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const PAGE_SIZE: usize = 512;
+/// Through bCryptMethod at offset 513 in Unicode headers; ANSI headers end sooner.
+const HEADER_SIZE: usize = 514;
+/// ptype of a node B-tree page.
+const PTYPE_NBT: u8 = 0x81;
+/// Deeper trees than this are treated as corrupt.
+const MAX_LEVELS: u8 = 16;
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct NodeCounts {
+    pub folders: u64,
+    pub search_folders: u64,
+    pub messages: u64,
+    pub associated_messages: u64,
+    pub other_nodes: u64,
+    pub btree_pages: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PstInfo {
+    pub size_bytes: u64,
+    /// `pst`, `ost`, or `unknown` when the file doesn't start with `!BDN`.
+    pub format: &'static str,
+    /// `ansi` (wVer 14/15), `unicode` (23) or `unicode-4k` (36).
+    pub format_version: Option<&'static str>,
+    pub wver: Option<u16>,
+    /// bCryptMethod: `none`, `compressible` (permutation), `high` (cyclic) or `cipher`. PST
+    /// "encryption" is obfuscation readpst always undoes; a store password isn't visible here.
+    pub encryption: Option<&'static str>,
+    /// ibFileEof from the header; a smaller file was cut short (e.g. an incomplete upload).
+    pub header_file_size: Option<u64>,
+    pub truncated: bool,
+    /// None when the node B-tree couldn't be walked.
+    pub estimated: Option<NodeCounts>,
+    /// Why `estimated` is missing.
+    pub estimate_error: Option<String>,
+}
+
+/// What `inspect` prints.
+#[derive(Serialize, Debug)]
+pub struct Report<'a> {
+    pub source: &'a str,
+    #[serde(flatten)]
+    pub info: PstInfo,
+    pub scratch: ScratchEstimate,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScratchEstimate {
+    /// The downloaded PST plus readpst output at the usual expansion.
+    pub needed_bytes: u64,
+    pub readpst_expansion: u64,
+    pub min_free_bytes: u64,
+    pub free_bytes: Option<u64>,
+    pub fits: Option<bool>,
+}
+
+pub fn scratch_estimate(size: u64, free: Option<u64>, min_free: u64) -> ScratchEstimate {
+    let needed = size.saturating_mul(1 + READPST_EXPANSION);
+    ScratchEstimate {
+        needed_bytes: needed,
+        readpst_expansion: READPST_EXPANSION,
+        min_free_bytes: min_free,
+        free_bytes: free,
+        fits: free.map(|free| free >= needed.saturating_add(min_free)),
+    }
+}
+
+struct Layout {
+    unicode: bool,
+    file_eof: u64,
+    nbt_root: u64,
+}
+
+fn u16_at(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn u32_at(b: &[u8], at: usize) -> u64 {
+    u32::from_le_bytes(b[at..at + 4].try_into().expect("4 bytes")) as u64
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().expect("8 bytes"))
+}
+
+/// Inspect the PST at `path`.
+pub fn inspect_pst(path: &Path) -> Result<PstInfo> {
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let size = file.metadata()?.len();
+    let mut header = [0u8; HEADER_SIZE];
+    let read = read_up_to(&mut file, &mut header)?;
+    let mut info = PstInfo {
+        size_bytes: size,
+        format: "unknown",
+        format_version: None,
+        wver: None,
+        encryption: None,
+        header_file_size: None,
+        truncated: false,
+        estimated: None,
+        estimate_error: None,
+    };
+    if read < 4 || &header[..4] != b"!BDN" {
+        return Ok(info);
+    }
+    info.format = match header.get(8..10) {
+        Some(b"SO") => "ost",
+        _ => "pst",
+    };
+    if read < HEADER_SIZE {
+        info.truncated = true;
+        info.estimate_error = Some("header is incomplete".into());
+        return Ok(info);
+    }
+    let wver = u16_at(&header, 10);
+    info.wver = Some(wver);
+    // ROOT holds ibFileEof and the node B-tree's BREF; bCryptMethod follows the free maps.
+    let (layout, crypt) = match wver {
+        14 | 15 => (
+            Layout {
+                unicode: false,
+                file_eof: u32_at(&header, 168),
+                nbt_root: u32_at(&header, 188),
+            },
+            header[461],
+        ),
+        23 | 36 => (
+            Layout {
+                unicode: true,
+                file_eof: u64_at(&header, 184),
+                nbt_root: u64_at(&header, 224),
+            },
+            header[513],
+        ),
+        _ => {
+            info.estimate_error = Some(format!("unsupported wVer {wver}"));
+            return Ok(info);
+        }
+    };
+    info.format_version = Some(match wver {
+        14 | 15 => "ansi",
+        23 => "unicode",
+        _ => "unicode-4k",
+    });
+    info.encryption = Some(match crypt {
+        0x00 => "none",
+        0x01 => "compressible",
+        0x02 => "high",
+        0x10 => "cipher",
+        _ => "unknown",
+    });
+    info.header_file_size = Some(layout.file_eof);
+    info.truncated = size < layout.file_eof;
+    if wver == 36 {
+        info.estimate_error = Some("4 KiB-page files are not walked".into());
+        return Ok(info);
+    }
+    let mut counts = NodeCounts::default();
+    let mut visited = HashSet::new();
+    match walk_nbt(
+        &mut file,
+        &layout,
+        size,
+        layout.nbt_root,
+        MAX_LEVELS,
+        &mut visited,
+        &mut counts,
+    ) {
+        Ok(()) => info.estimated = Some(counts),
+        Err(e) => info.estimate_error = Some(format!("{e:#}")),
+    }
+    Ok(info)
+}
+
+fn read_up_to(file: &mut File, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn walk_nbt(
+    file: &mut File,
+    layout: &Layout,
+    size: u64,
+    offset: u64,
+    levels_left: u8,
+    visited: &mut HashSet<u64>,
+    counts: &mut NodeCounts,
+) -> Result<()> {
+    if offset.saturating_add(PAGE_SIZE as u64) > size {
+        return Err(anyhow!(
+            "B-tree page at {offset} is past the end of the file"
+        ));
+    }
+    if !visited.insert(offset) || levels_left == 0 {
+        return Err(anyhow!("B-tree loops or is too deep at {offset}"));
+    }
+    let mut page = [0u8; PAGE_SIZE];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut page)?;
+    counts.btree_pages += 1;
+    // Trailer: cEnt, cEntMax, cbEnt, cLevel, then the page trailer with ptype.
+    let (meta, ptype_at, entries_len) = if layout.unicode {
+        (488, 496, 488)
+    } else {
+        (496, 500, 496)
+    };
+    if page[ptype_at] != PTYPE_NBT {
+        return Err(anyhow!("page at {offset} is not a node B-tree page"));
+    }
+    let (entries, entry_size, level) =
+        (page[meta] as usize, page[meta + 2] as usize, page[meta + 3]);
+    if entry_size == 0 || entries * entry_size > entries_len {
+        return Err(anyhow!("bad B-tree page header at {offset}"));
+    }
+    for i in 0..entries {
+        let entry = &page[i * entry_size..(i + 1) * entry_size];
+        if level > 0 {
+            // BTENTRY: btkey, then BREF (bid, ib).
+            let child = if layout.unicode {
+                u64_at(entry, 16)
+            } else {
+                u32_at(entry, 8)
+            };
+            walk_nbt(file, layout, size, child, levels_left - 1, visited, counts)?;
+        } else {
+            // NBTENTRY: the nid's low 5 bits are its type.
+            match entry[0] & 0x1f {
+                0x02 => counts.folders += 1,
+                0x03 => counts.search_folders += 1,
+                0x04 => counts.messages += 1,
+                0x08 => counts.associated_messages += 1,
+                _ => counts.other_nodes += 1,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Unicode PST: header, then a root intermediate page and two leaf pages.
+    fn unicode_pst() -> Vec<u8> {
+        let mut pst = vec![0u8; PAGE_SIZE * 5];
+        pst[..4].copy_from_slice(b"!BDN");
+        pst[8..10].copy_from_slice(b"SM");
+        pst[10..12].copy_from_slice(&23u16.to_le_bytes());
+        pst[184..192].copy_from_slice(&(PAGE_SIZE as u64 * 5).to_le_bytes());
+        pst[224..232].copy_from_slice(&1024u64.to_le_bytes());
+        pst[513] = 0x01;
+        let page = |pst: &mut Vec<u8>, at: usize, entries: &[Vec<u8>], size: u8, level: u8| {
+            for (i, entry) in entries.iter().enumerate() {
+                let start = at + i * size as usize;
+                pst[start..start + entry.len()].copy_from_slice(entry);
+            }
+            pst[at + 488] = entries.len() as u8;
+            pst[at + 490] = size;
+            pst[at + 491] = level;
+            pst[at + 496] = PTYPE_NBT;
+        };
+        let bref = |ib: u64| {
+            let mut e = vec![0u8; 24];
+            e[16..24].copy_from_slice(&ib.to_le_bytes());
+            e
+        };
+        let nid = |nid: u32| {
+            let mut e = vec![0u8; 32];
+            e[..4].copy_from_slice(&nid.to_le_bytes());
+            e
+        };
+        page(&mut pst, 1024, &[bref(1536), bref(2048)], 24, 1);
+        page(
+            &mut pst,
+            1536,
+            &[nid(0x122), nid(0x8022), nid(0x204)],
+            32,
+            0,
+        );
+        page(
+            &mut pst,
+            2048,
+            &[nid(0x2224), nid(0x2248), nid(0x61)],
+            32,
+            0,
+        );
+        pst
+    }
+
+    #[test]
+    fn reads_header_and_counts_nodes() {
+        let dir = std::env::temp_dir().join(format!("inspect-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let path = dir.join("a.pst");
+        std::fs::write(&path, unicode_pst()).expect("write");
+        let info = inspect_pst(&path).expect("inspect");
+        assert_eq!(info.format, "pst");
+        assert_eq!(info.format_version, Some("unicode"));
+        assert_eq!(info.encryption, Some("compressible"));
+        assert!(!info.truncated);
+        assert_eq!(
+            info.estimated,
+            Some(NodeCounts {
+                folders: 2,
+                search_folders: 0,
+                messages: 2,
+                associated_messages: 1,
+                other_nodes: 1,
+                btree_pages: 3,
+            })
+        );
+
+        // Cut short: the header still reads, the tree doesn't.
+        std::fs::write(&path, &unicode_pst()[..1700]).expect("write");
+        let info = inspect_pst(&path).expect("inspect");
+        assert!(info.truncated);
+        assert!(info.estimated.is_none());
+        assert!(info.estimate_error.expect("error").contains("past the end"));
+
+        std::fs::write(&path, b"From someone").expect("write");
+        assert_eq!(inspect_pst(&path).expect("inspect").format, "unknown");
+        std::fs::remove_dir_all(&dir).ok();
+
+        let estimate = scratch_estimate(100, Some(600), 50);
+        assert_eq!(estimate.needed_bytes, 500);
+        assert_eq!(estimate.fits, Some(true));
+        assert_eq!(scratch_estimate(100, Some(549), 50).fits, Some(false));
+    }
+}
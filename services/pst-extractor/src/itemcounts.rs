@@ -0,0 +1,156 @@
+//! Extracted counts checked against the PST's own item counts (manifest `count_validation`).
+//!
+//! Defensibility reports need to show that every item in each folder of the PST is accounted
+//! for. readpst prints one summary line per folder as it finishes it:
+//!
+//! ```text
+//!     "Inbox" - 212 items done, 3 items skipped.
+//! ```
+//!
+//! `done + skipped` is the number of items the folder holds according to the PST. The extractor
+//! counts what it actually produced from each folder: parsed emails (including copies suppressed
+//! by `--dedupe`) and calendar, contact and task items. Folders whose counts differ are listed.
+//!
+//! readpst names folders by display name only, so folders are matched by name. Two folders with
+//! the same name (say, two `Archive` folders) are checked as one.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Default, Debug, Clone, Copy)]
+struct Counts {
+    exported: u64,
+    skipped: u64,
+    extracted: u64,
+}
+
+#[derive(Default)]
+pub struct CountCheck {
+    folders: BTreeMap<String, Counts>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct FolderDiscrepancy {
+    pub folder: String,
+    /// Items in the folder according to the PST (`exported + readpst_skipped`).
+    pub pst_items: u64,
+    pub readpst_skipped: u64,
+    pub extracted: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CountValidation {
+    pub folders_checked: usize,
+    pub pst_items_total: u64,
+    pub extracted_total: u64,
+    pub discrepancies: Vec<FolderDiscrepancy>,
+}
+
+/// `"<folder>" - <n> items done, <m> items skipped.` → (folder, n, m).
+fn parse_summary(line: &str) -> Option<(&str, u64, u64)> {
+    let line = line.trim().strip_prefix('"')?;
+    let (folder, rest) = line.rsplit_once("\" - ")?;
+    let (done, rest) = rest.split_once(" items done, ")?;
+    let skipped = rest.strip_suffix(" items skipped.")?;
+    Some((folder, done.trim().parse().ok()?, skipped.trim().parse().ok()?))
+}
+
+/// The folder an extracted file came from: the last directory of its readpst-relative path.
+fn folder_of(source_path: &str) -> Option<&str> {
+    let mut components = source_path.rsplit(['/', '\\']);
+    components.next();
+    components.next()
+}
+
+impl CountCheck {
+    /// Feed one line of readpst's stdout; lines other than folder summaries are ignored.
+    pub fn record_line(&mut self, line: &str) {
+        if let Some((folder, done, skipped)) = parse_summary(line) {
+            let counts = self.folders.entry(folder.to_string()).or_default();
+            counts.exported += done;
+            counts.skipped += skipped;
+        }
+    }
+
+    /// Whether readpst reported any folder (older builds, or quiet mode, print nothing).
+    pub fn has_summary(&self) -> bool {
+        !self.folders.is_empty()
+    }
+
+    /// Count `items` extracted from the file at `source_path`.
+    pub fn add_extracted(&mut self, source_path: &str, items: u64) {
+        if let Some(folder) = folder_of(source_path) {
+            self.folders.entry(folder.to_string()).or_default().extracted += items;
+        }
+    }
+
+    pub fn report(&self) -> CountValidation {
+        let mut report = CountValidation {
+            folders_checked: self.folders.len(),
+            pst_items_total: 0,
+            extracted_total: 0,
+            discrepancies: Vec::new(),
+        };
+        for (folder, counts) in &self.folders {
+            let pst_items = counts.exported + counts.skipped;
+            report.pst_items_total += pst_items;
+            report.extracted_total += counts.extracted;
+            if pst_items != counts.extracted {
+                report.discrepancies.push(FolderDiscrepancy {
+                    folder: folder.clone(),
+                    pst_items,
+                    readpst_skipped: counts.skipped,
+                    extracted: counts.extracted,
+                });
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_readpst_folder_summaries_with_extracted_items() {
+        let mut check = CountCheck::default();
+        for line in [
+            "Opening PST file and indexes...",
+            "Processing Folder \"Inbox\"",
+            "\t\"Inbox\" - 3 items done, 1 items skipped.",
+            "\t\"Sent \"Q1\" Items\" - 2 items done, 0 items skipped.",
+            "\t\"Calendar\" - 1 items done, 0 items skipped.",
+        ] {
+            check.record_line(line);
+        }
+        assert!(check.has_summary());
+        check.add_extracted("Top of Personal Folders/Inbox/1.eml", 1);
+        check.add_extracted("Top of Personal Folders/Inbox/2.eml", 1);
+        check.add_extracted("Top of Personal Folders/Inbox/3.eml", 1);
+        check.add_extracted("Top of Personal Folders/Sent \"Q1\" Items/1.eml", 1);
+        check.add_extracted("Top of Personal Folders/Calendar/1", 1);
+
+        let report = check.report();
+        assert_eq!(report.folders_checked, 3);
+        assert_eq!(report.pst_items_total, 7);
+        assert_eq!(report.extracted_total, 5);
+        assert_eq!(
+            report.discrepancies,
+            vec![
+                FolderDiscrepancy {
+                    folder: "Inbox".to_string(),
+                    pst_items: 4,
+                    readpst_skipped: 1,
+                    extracted: 3,
+                },
+                FolderDiscrepancy {
+                    folder: "Sent \"Q1\" Items".to_string(),
+                    pst_items: 2,
+                    readpst_skipped: 0,
+                    extracted: 1,
+                },
+            ]
+        );
+    }
+}
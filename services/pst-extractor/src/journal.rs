@@ -0,0 +1,171 @@
+//! Exchange journal reports (envelope journaling).
+//!
+//! A journaled mailbox holds reports, not the mail itself: each report is a message from the
+//! journaling agent carrying `X-MS-Journal-Report`, a text/plain envelope listing who the
+//! original was really delivered to, and the original attached as `message/rfc822`. The
+//! envelope is the only place Bcc recipients and distribution-list members appear, so it is
+//! parsed here and kept with the unwrapped original.
+//!
+//! Envelope lines look like:
+//!
+//! ```text
+//! Sender: alice@contoso.com
+//! Subject: Q3 numbers
+//! Message-Id: <1234@contoso.com>
+//! To: bob@fabrikam.com
+//! Cc: team-member@contoso.com, Expanded: team@contoso.com
+//! Bcc: carol@contoso.com
+//! Recipient: dave@contoso.com, Forwarded: erin@contoso.com
+//! ```
+
+use mailparse::{MailHeader, MailHeaderMap};
+use serde::{Deserialize, Serialize};
+
+/// The journal envelope of one report.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Envelope {
+    pub sender: Option<String>,
+    pub on_behalf_of: Option<String>,
+    pub message_id: Option<String>,
+    pub recipients: Vec<EnvelopeRecipient>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvelopeRecipient {
+    /// Lowercased SMTP address the original was delivered to.
+    pub address: String,
+    /// `to`, `cc`, `bcc`, or `recipient` when the report doesn't say which header it was in.
+    pub field: String,
+    /// Distribution list this recipient was expanded from.
+    pub expanded_from: Option<String>,
+    /// Mailbox that forwarded the message to this recipient.
+    pub forwarded_from: Option<String>,
+}
+
+impl Envelope {
+    /// Every recipient address, in envelope order, deduplicated.
+    pub fn recipient_emails(&self) -> Vec<String> {
+        distinct(self.recipients.iter())
+    }
+
+    /// Bcc recipients, including members of distribution lists that were Bcc'd.
+    pub fn bcc_emails(&self) -> Vec<String> {
+        distinct(self.recipients.iter().filter(|r| r.field == "bcc"))
+    }
+}
+
+fn distinct<'a>(recipients: impl Iterator<Item = &'a EnvelopeRecipient>) -> Vec<String> {
+    let mut emails: Vec<String> = Vec::new();
+    for recipient in recipients {
+        if !emails.contains(&recipient.address) {
+            emails.push(recipient.address.clone());
+        }
+    }
+    emails
+}
+
+/// True for a journal report's headers.
+pub fn is_report(headers: &[MailHeader]) -> bool {
+    headers.get_first_header("X-MS-Journal-Report").is_some()
+        || headers
+            .get_first_value("Content-Identifier")
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("ExJournalReport"))
+}
+
+/// `alice@contoso.com` from `alice@contoso.com`, `<alice@contoso.com>` or `Alice
+/// <alice@contoso.com>`, lowercased.
+fn address(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = match (value.rfind('<'), value.rfind('>')) {
+        (Some(open), Some(close)) if open < close => &value[open + 1..close],
+        _ => value,
+    };
+    Some(value.trim().to_ascii_lowercase()).filter(|a| a.contains('@'))
+}
+
+/// Parse a report's envelope body. None if it names no sender and no recipient.
+pub fn parse_envelope(body: &str) -> Option<Envelope> {
+    let mut envelope = Envelope::default();
+    for line in body.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name.trim().to_ascii_lowercase();
+        match name.as_str() {
+            "sender" => envelope.sender = envelope.sender.or_else(|| address(value)),
+            "on-behalf-of" => {
+                envelope.on_behalf_of = envelope.on_behalf_of.or_else(|| address(value))
+            }
+            "message-id" => {
+                let id = value.trim();
+                if envelope.message_id.is_none() && !id.is_empty() {
+                    envelope.message_id = Some(id.to_string());
+                }
+            }
+            "to" | "cc" | "bcc" | "recipient" => {
+                let mut parts = value.split(',');
+                let Some(address) = parts.next().and_then(address) else {
+                    continue;
+                };
+                let mut recipient = EnvelopeRecipient {
+                    address,
+                    field: name,
+                    ..Default::default()
+                };
+                for part in parts {
+                    let Some((kind, value)) = part.split_once(':') else {
+                        continue;
+                    };
+                    match kind.trim().to_ascii_lowercase().as_str() {
+                        "expanded" => recipient.expanded_from = self::address(value),
+                        "forwarded" => recipient.forwarded_from = self::address(value),
+                        _ => {}
+                    }
+                }
+                envelope.recipients.push(recipient);
+            }
+            _ => {}
+        }
+    }
+    (envelope.sender.is_some() || !envelope.recipients.is_empty()).then_some(envelope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_envelope_recipients() {
+        let body = "Sender: Alice@Contoso.com\r\n\
+            Subject: Q3 numbers: draft\r\n\
+            Message-Id: <1234@contoso.com>\r\n\
+            To: bob@fabrikam.com\r\n\
+            Cc: member@contoso.com, Expanded: team@contoso.com\r\n\
+            Bcc: carol@contoso.com\r\n\
+            Bcc: dan@contoso.com, Expanded: board@contoso.com\r\n\
+            Recipient: erin@contoso.com, Forwarded: bob@fabrikam.com\r\n\
+            To: bob@fabrikam.com\r\n";
+        let envelope = parse_envelope(body).expect("envelope");
+        assert_eq!(envelope.sender.as_deref(), Some("alice@contoso.com"));
+        assert_eq!(envelope.message_id.as_deref(), Some("<1234@contoso.com>"));
+        assert_eq!(envelope.recipients.len(), 6);
+        assert_eq!(
+            envelope.recipients[1].expanded_from.as_deref(),
+            Some("team@contoso.com")
+        );
+        assert_eq!(
+            envelope.recipients[4].forwarded_from.as_deref(),
+            Some("bob@fabrikam.com")
+        );
+        assert_eq!(
+            envelope.bcc_emails(),
+            ["carol@contoso.com", "dan@contoso.com"]
+        );
+        assert_eq!(envelope.recipient_emails().len(), 5);
+
+        assert!(parse_envelope("Hello,\r\nplease see the attached.\r\n").is_none());
+        let (headers, _) =
+            mailparse::parse_headers(b"X-MS-Journal-Report:\r\nSubject: x\r\n\r\n").expect("hdr");
+        assert!(is_report(&headers));
+    }
+}
@@ -0,0 +1,7114 @@
+//! PST extraction: readpst, then email, attachment and family records, uploads and a manifest.
+//!
+//! The `pst-extractor` binary is [`run_cli`]; other services run the same job in-process with
+//! [`Extractor`].
+
+use anyhow::{anyhow, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use mailparse::{MailHeaderMap, ParsedMail};
+use memchr::memmem;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::collections::{HashSet, VecDeque};
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+pub use api::{Config, Extractor, Summary};
+/// The mailparse version [`select_email_bodies`] takes messages from.
+pub use mailparse;
+
+mod aggregate;
+mod anonymize;
+mod api;
+mod archives;
+mod attachment_policy;
+mod batch;
+mod bates;
+mod callback;
+mod cfb;
+mod charsets;
+mod cid;
+mod classify;
+mod concordance;
+mod config;
+mod dates;
+mod dedupe;
+mod denist;
+mod diskspace;
+mod dsn;
+mod encoded_words;
+mod fileio;
+mod gzmembers;
+mod hashes;
+mod inspect;
+mod itemcounts;
+mod journal;
+mod loadfile;
+mod logging;
+mod mbox;
+mod meetings;
+mod merge;
+mod metrics;
+mod mime_recovery;
+mod msg;
+mod msgid;
+mod neardupe;
+mod opensearch;
+mod otel;
+mod outcome;
+mod pii;
+mod platform;
+mod priority;
+mod pim;
+mod quoting;
+mod progress;
+mod pstindex;
+mod rawstore;
+mod rtf;
+mod rules;
+mod sample;
+mod scan;
+mod schema;
+mod scoring;
+mod security;
+mod server;
+mod sniff;
+mod stats;
+mod storage;
+mod terms;
+mod textextract;
+mod threads;
+mod timeseries;
+mod timing;
+mod tnef;
+mod upload;
+mod watchdog;
+mod worker;
+mod ziparchive;
+
+use dates::DateParser;
+use fileio::{FileIo, IoBackend};
+use gzmembers::{Codec, MemberIndex, MemberWriter, OutputCompression};
+use mbox::{looks_like_mbox, MboxItem, MboxReader};
+use opensearch::{BulkIndexer, IndexStats};
+use progress::{Phase, Progress, ProgressSinks};
+use rawstore::RawBlobWriter;
+use security::{audit_headers, FindingKind, SecurityFinding, VipList};
+use terms::{tokenizer_from_spec, TermMatcher};
+use threads::{ThreadInput, ThreadStats};
+use timing::{ParseTimer, ParseTimingStats, TimedItem};
+
+/// Concurrent upload limit for attachment batches
+const ATTACHMENT_UPLOAD_CONCURRENCY: usize = 10;
+
+// Args doubles as the job spec for worker mode: queue messages are JSON objects with these
+// field names, merged over the worker's own arguments.
+#[derive(Parser, Debug, Clone, Serialize, Deserialize)]
+#[command(author, version, about, subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Tool>,
+
+    /// Read options from a TOML, YAML or JSON file keyed by argument name; the command line and
+    /// environment variables take precedence over it.
+    #[arg(long, env = "CONFIG_FILE")]
+    #[serde(skip)]
+    config: Option<String>,
+
+    #[arg(
+        long,
+        env = "PST_FILE_ID",
+        required_unless_present_any = ["worker", "jobs_file", "serve"],
+        default_value = ""
+    )]
+    pst_file_id: String,
+
+    #[arg(long, env = "PROJECT_ID", default_value = "")]
+    project_id: String,
+
+    #[arg(long, env = "CASE_ID", default_value = "")]
+    case_id: String,
+
+    /// Custodian this PST was collected from, stamped on every record and the manifest.
+    #[arg(long, env = "CUSTODIAN_ID", default_value = "")]
+    custodian_id: String,
+
+    #[arg(long, env = "CUSTODIAN_NAME", default_value = "")]
+    custodian_name: String,
+
+    /// S3 bucket, or `az://account/container` (Azure Blob) / `gs://bucket` (GCS).
+    #[arg(
+        long,
+        env = "SOURCE_BUCKET",
+        required_unless_present_any = ["worker", "jobs_file", "serve", "source_path"],
+        default_value = ""
+    )]
+    source_bucket: String,
+
+    #[arg(
+        long,
+        env = "SOURCE_KEY",
+        required_unless_present_any = ["worker", "jobs_file", "serve", "source_path"],
+        default_value = ""
+    )]
+    source_key: String,
+
+    /// Read the source from this local file (or, for loose messages, directory) instead of S3.
+    #[arg(long, env = "SOURCE_PATH", conflicts_with_all = ["source_bucket", "source_key"])]
+    source_path: Option<String>,
+
+    /// What the source holds: `pst` (also accepts a `.msg` or ZIP of `.msg`), or loose messages
+    /// that skip readpst – `eml-archive` (`.eml` files) or `maildir` (`cur/` and `new/`), each as
+    /// a ZIP, an S3 prefix ending in `/` or a local directory.
+    #[arg(long, env = "INPUT_FORMAT", value_enum, default_value_t = InputFormat::Pst)]
+    input_format: InputFormat,
+
+    /// S3 bucket, or `az://account/container` (Azure Blob) / `gs://bucket` (GCS).
+    #[arg(
+        long,
+        env = "OUTPUT_BUCKET",
+        required_unless_present_any = ["worker", "jobs_file", "serve", "output_dir"],
+        default_value = ""
+    )]
+    output_bucket: String,
+
+    #[arg(
+        long,
+        env = "OUTPUT_PREFIX",
+        required_unless_present_any = ["worker", "jobs_file", "serve", "output_dir"],
+        default_value = ""
+    )]
+    output_prefix: String,
+
+    /// Write outputs under this local directory instead of S3, in the layout the bucket would
+    /// get (below OUTPUT_PREFIX, if one is given).
+    #[arg(long, env = "OUTPUT_DIR", conflicts_with = "output_bucket")]
+    output_dir: Option<String>,
+
+    /// Encrypt every uploaded object with SSE-KMS under this key (ID, ARN or alias).
+    #[arg(long, env = "KMS_KEY_ID")]
+    kms_key_id: Option<String>,
+
+    /// S3 storage class for every uploaded object (`STANDARD_IA`, `INTELLIGENT_TIERING`, ...).
+    #[arg(long, env = "STORAGE_CLASS")]
+    storage_class: Option<String>,
+
+    /// Tags for every uploaded object, as `key=value,key2=value2` (at most 10).
+    #[arg(long, env = "OBJECT_TAGS")]
+    object_tags: Option<String>,
+
+    /// Scratch directory (`/scratch` on Linux, the temp dir elsewhere).
+    #[arg(long, env = "WORK_DIR", default_value_t = platform::default_work_dir())]
+    work_dir: String,
+
+    /// Free space to leave on the scratch volume: downloads that would cut into it fail up front,
+    /// and readpst is stopped (failing the job) once free space drops below it. `0` disables.
+    #[arg(long, env = "MIN_FREE_BYTES", default_value_t = 1 << 30)]
+    min_free_bytes: u64,
+
+    /// Leave the job's work dir in place when it ends (for debugging).
+    #[arg(long, env = "KEEP_WORKDIR")]
+    keep_workdir: bool,
+
+    /// readpst binary. A bare name is looked up next to the extractor, on PATH, then in the
+    /// usual Homebrew / MacPorts / MSYS2 / Cygwin locations.
+    #[arg(long, env = "READPST_PATH", default_value = "readpst")]
+    readpst_path: String,
+
+    /// Folders extracted at once: readpst exports this many in parallel (its `-j`), and the parse
+    /// pass reads and MIME-parses this many top-level folders ahead of the records being written.
+    /// `0` picks the CPU count, capped at 8. Output doesn't depend on it.
+    #[arg(long, env = "EXTRACT_CONCURRENCY", default_value_t = 0)]
+    extract_concurrency: usize,
+
+    /// S3-compatible endpoint (MinIO, LocalStack, ...) for S3 only; SQS, SNS and DynamoDB keep
+    /// their usual endpoints. `AWS_ENDPOINT_URL` redirects every service instead.
+    #[arg(long, env = "S3_ENDPOINT_URL")]
+    #[serde(skip)]
+    s3_endpoint_url: Option<String>,
+
+    /// Address buckets as `endpoint/bucket/key` instead of `bucket.endpoint/key`. Needed for
+    /// MinIO and most other S3 emulators.
+    #[arg(long, env = "S3_FORCE_PATH_STYLE")]
+    #[serde(skip)]
+    s3_force_path_style: bool,
+
+    /// Attempts per S3 request, with jittered exponential backoff between them. Downloads that
+    /// break off mid-stream resume from where they stopped, up to the same count.
+    #[arg(long, env = "S3_MAX_ATTEMPTS", default_value_t = 5)]
+    #[serde(skip)]
+    s3_max_attempts: u32,
+
+    /// Files larger than this are uploaded to S3 in parts of this size (minimum 5 MiB).
+    #[arg(long, env = "UPLOAD_PART_SIZE", default_value_t = 16 << 20)]
+    #[serde(skip)]
+    upload_part_size: u64,
+
+    /// Most S3 upload requests (whole objects or parts) in flight at once, across all uploads.
+    #[arg(long, env = "UPLOAD_CONCURRENCY", default_value_t = 16)]
+    #[serde(skip)]
+    upload_concurrency: usize,
+
+    /// Local file I/O for readpst output and staged attachments: `std`, or `uring` (Linux builds
+    /// with the `io-uring` feature; falls back to `std` if the kernel refuses).
+    #[arg(long, env = "IO_BACKEND", value_enum, default_value_t = IoBackend::Std)]
+    #[serde(skip)]
+    io_backend: IoBackend,
+
+    /// Largest single message accepted from an mbox file; bigger ones are skipped.
+    #[arg(long, env = "MAX_MESSAGE_BYTES", default_value_t = 256 * 1024 * 1024)]
+    max_message_bytes: usize,
+
+    /// Store raw messages concatenated into size-capped blobs with a byte-range index.
+    #[arg(long, env = "RAW_BLOBS")]
+    raw_blobs: bool,
+
+    /// Upload each message's original RFC822 bytes to `{prefix}eml/{email_id}.eml`.
+    #[arg(long, env = "EXPORT_RAW_EML")]
+    export_raw_eml: bool,
+
+    /// Also write a review-platform load file with extracted text under `text/`: `concordance`
+    /// (loadfile.dat) or `edrm` (loadfile.xml).
+    #[arg(long, env = "LOADFILE", value_enum)]
+    loadfile: Option<loadfile::LoadfileFormat>,
+
+    /// Concordance DAT delimiters: one character, or a code point as `0x14` / `U+00FE`.
+    #[arg(
+        long,
+        env = "LOADFILE_FIELD_DELIMITER",
+        value_parser = loadfile::parse_delimiter,
+        default_value = "0x14"
+    )]
+    loadfile_field_delimiter: char,
+
+    #[arg(
+        long,
+        env = "LOADFILE_QUOTE",
+        value_parser = loadfile::parse_delimiter,
+        default_value = "U+00FE"
+    )]
+    loadfile_quote: char,
+
+    /// Stands in for line breaks inside a DAT value.
+    #[arg(
+        long,
+        env = "LOADFILE_NEWLINE",
+        value_parser = loadfile::parse_delimiter,
+        default_value = "U+00AE"
+    )]
+    loadfile_newline: char,
+
+    /// Separates the values of multi-value DAT fields (recipients).
+    #[arg(
+        long,
+        env = "LOADFILE_MULTI_VALUE",
+        value_parser = loadfile::parse_delimiter,
+        default_value = ";"
+    )]
+    loadfile_multi_value: char,
+
+    /// Give every email and attachment record a control number, `{prefix}{number}`, in family
+    /// order (email, its attachments, then embedded messages).
+    #[arg(long, env = "CONTROL_NUMBER_PREFIX")]
+    control_number_prefix: Option<String>,
+
+    /// First control number; continue a production from the last manifest's
+    /// `control_numbers.next`.
+    #[arg(long, env = "CONTROL_NUMBER_START", default_value_t = 1)]
+    control_number_start: u64,
+
+    /// Continue the numbering of an earlier job: start at `control_numbers.next` from its
+    /// manifest (local path or object URI) instead of `--control-number-start`.
+    #[arg(
+        long,
+        env = "CONTROL_NUMBER_RESUME_FROM",
+        requires = "control_number_prefix",
+        conflicts_with = "control_number_start"
+    )]
+    control_number_resume_from: Option<String>,
+
+    /// Digits control numbers are zero-padded to.
+    #[arg(long, env = "CONTROL_NUMBER_WIDTH", default_value_t = 8)]
+    control_number_width: usize,
+
+    /// Extra digests, comma-separated (`md5`, `sha1`, `sha256`): attachments get `md5` / `sha1`
+    /// next to attachment_hash, and emails a `raw_hashes` set over their raw bytes.
+    #[arg(long, env = "HASH_ALGOS", value_enum, value_delimiter = ',')]
+    hash_algos: Vec<hashes::HashAlgo>,
+
+    /// Headers copied verbatim into each email's `retained_headers` (comma-separated names;
+    /// the first value of each).
+    #[arg(
+        long,
+        env = "RETAIN_HEADERS",
+        value_delimiter = ',',
+        default_value = "X-Mailer,X-Originating-IP"
+    )]
+    retain_headers: Vec<String>,
+
+    /// Size cap for each raw blob file when `--raw-blobs` is enabled.
+    #[arg(long, env = "RAW_BLOB_MAX_BYTES", default_value_t = 1024 * 1024 * 1024)]
+    raw_blob_max_bytes: u64,
+
+    /// Per-message parse timeout; messages exceeding it are dead-lettered. 0 (the default)
+    /// disables it. A timed-out parse can't be cancelled and keeps its blocking-pool thread until
+    /// it returns.
+    #[arg(long, env = "MESSAGE_TIMEOUT_SECS", default_value_t = 0)]
+    message_timeout_secs: u64,
+
+    /// Total time budget (seconds, or `90m` / `2h`). readpst is stopped at half of it and no new
+    /// messages are taken once it is spent; what was processed is uploaded with a partial
+    /// manifest. Set it below the Batch job timeout.
+    #[arg(long, env = "MAX_DURATION", value_parser = watchdog::parse_duration_secs)]
+    max_duration: Option<u64>,
+
+    /// OpenSearch/Elasticsearch base URL; when set, emails are bulk-indexed as they are parsed.
+    #[arg(long, env = "OPENSEARCH_URL")]
+    opensearch_url: Option<String>,
+
+    #[arg(long, env = "OPENSEARCH_INDEX", default_value = "emails")]
+    index_name: String,
+
+    #[arg(long, env = "OPENSEARCH_USERNAME")]
+    opensearch_username: Option<String>,
+
+    #[arg(long, env = "OPENSEARCH_PASSWORD", hide_env_values = true)]
+    opensearch_password: Option<String>,
+
+    /// Search terms or boolean expressions (`AND`/`OR`/`NOT`, parentheses, "quotes"), one per
+    /// line; local path or s3://bucket/key. Hits are tagged per email and attachment and
+    /// reported in `term_hits.ndjson.gz`.
+    #[arg(long, env = "TERMS_FILE")]
+    terms_file: Option<String>,
+
+    /// Tokenizer for term matching: `word`, `ngram[:N]` (CJK character n-grams) or `dict:<path>`.
+    #[arg(long, env = "TERMS_TOKENIZER", default_value = "word")]
+    tokenizer: String,
+
+    /// Write `concordance.ndjson.gz`: term frequencies across all subjects and bodies, for
+    /// building keyword lists. Uses the `--tokenizer` tokens.
+    #[arg(long, env = "CONCORDANCE")]
+    concordance: bool,
+
+    /// Stopword list for the concordance (one per line; local path or s3://). Replaces the
+    /// built-in English list.
+    #[arg(long, env = "CONCORDANCE_STOPWORDS")]
+    concordance_stopwords: Option<String>,
+
+    /// Shortest term (in characters) counted in the concordance.
+    #[arg(long, env = "CONCORDANCE_MIN_LENGTH", default_value_t = 3)]
+    concordance_min_length: usize,
+
+    /// Terms occurring fewer times than this are left out of the concordance.
+    #[arg(long, env = "CONCORDANCE_MIN_COUNT", default_value_t = 2)]
+    concordance_min_count: u64,
+
+    /// Flag personal data (US SSNs, Luhn-valid card numbers, IBANs, UK NI numbers) in body text
+    /// and extracted attachment text as `pii_flags`.
+    #[arg(long, env = "PII_SCAN")]
+    pii_scan: bool,
+
+    /// Extra PII patterns (JSON pack; local path or s3://); implies --pii-scan. A pattern with a
+    /// built-in's name replaces it.
+    #[arg(long, env = "PII_PATTERNS")]
+    pii_patterns: Option<String>,
+
+    /// Give each stored attachment a coarse `doc_type` (contract, invoice, spreadsheet_report,
+    /// presentation, other) and a `doc_language` from its extracted text.
+    #[arg(long, env = "CLASSIFY_ATTACHMENTS")]
+    classify_attachments: bool,
+
+    /// Classification model endpoint (http(s)://) used instead of the built-in heuristics;
+    /// implies --classify-attachments.
+    #[arg(long, env = "CLASSIFY_ENDPOINT")]
+    classify_endpoint: Option<String>,
+
+    /// Review-priority model: an `http(s)://` scoring endpoint, or a JSON rules file (local path
+    /// or s3://). Each email gets a 0-100 `review_priority`.
+    #[arg(long, env = "PRIORITY_MODEL")]
+    priority_model: Option<String>,
+
+    /// The organisation's own mail domains (comma-separated; subdomains included), used to tell
+    /// inbound from outbound and internal mail. Each email gets a `direction`.
+    #[arg(
+        long,
+        env = "INTERNAL_DOMAINS",
+        visible_alias = "custodian-domains",
+        value_delimiter = ','
+    )]
+    internal_domains: Vec<String>,
+
+    /// VIP list (local path or s3://): one `Display Name <addr@domain>` per line. Senders using a
+    /// VIP's display name from another address, or a lookalike address/domain, are flagged
+    /// `spoofing_suspected`.
+    #[arg(long, env = "VIP_LIST")]
+    vip_list: Option<String>,
+
+    /// Banner, disclaimer and tagging rules (JSON; local path or s3://). A worker keeps the file
+    /// it was started with and re-reads it every --rules-poll-secs.
+    #[arg(long, env = "RULES")]
+    #[serde(skip)]
+    rules: Option<String>,
+
+    #[arg(long, env = "RULES_POLL_SECS", default_value_t = 300)]
+    #[serde(skip)]
+    rules_poll_secs: u64,
+
+    /// Log lines on stderr: `text` or `json` (one object per line, for CloudWatch Logs Insights).
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value_t = logging::LogFormat::Text)]
+    #[serde(skip)]
+    log_format: logging::LogFormat,
+
+    /// OTLP/HTTP collector base URL; job, phase and per-file spans are exported to
+    /// `{endpoint}/v1/traces` (JSON encoding). The trace context comes from `TRACEPARENT`.
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    #[serde(skip)]
+    otlp_endpoint: Option<String>,
+
+    #[arg(long, env = "OTEL_SERVICE_NAME", default_value = "pst-extractor")]
+    #[serde(skip)]
+    otel_service_name: String,
+
+    /// Emit one record per duplicate group (same Message-ID, body and attachments); later copies
+    /// are listed in duplicates.ndjson.gz instead.
+    #[arg(long, env = "DEDUPE")]
+    dedupe: bool,
+
+    /// Body similarity (0.5-1.0) at which emails share a `near_dupe_cluster_id`, compared as the
+    /// share of matching bits in their 64-bit `near_dupe_hash`.
+    #[arg(long, env = "NEAR_DUPE_SIMILARITY", default_value_t = 0.9)]
+    near_dupe_similarity: f64,
+
+    /// Body `near_dupe_hash` is computed over: `clean` (`body_text_clean`, quoted replies and
+    /// signatures removed) or `full` (the whole body, so a forward matches what it quotes).
+    #[arg(
+        long,
+        env = "NEAR_DUPE_SOURCE",
+        value_enum,
+        default_value_t = NearDupeSource::Clean
+    )]
+    near_dupe_source: NearDupeSource,
+
+    /// Upload only aggregate_report.json (counts and histograms, small counts suppressed) and no
+    /// record-level output, for a processing report ahead of a full data transfer.
+    #[arg(long, env = "AGGREGATE_ONLY")]
+    aggregate_only: bool,
+
+    /// With --aggregate-only, withhold counts below this.
+    #[arg(long, env = "SUPPRESS_BELOW", default_value_t = 5)]
+    suppress_below: u64,
+
+    /// Shared cross-PST dedupe index, `s3://bucket/prefix` or `dynamodb://table`. Emails whose
+    /// hash was first claimed by another email are kept but marked `is_global_duplicate`.
+    #[arg(long, env = "DEDUPE_INDEX")]
+    dedupe_index: Option<String>,
+
+    /// Which emails get their attachments extracted: `all`, or `tagged-only` (emails with a
+    /// search-term hit or a security finding). Needs `--terms-file` or `--vip-list`.
+    #[arg(long, env = "ATTACHMENTS_FOR", value_enum, default_value_t = AttachmentsFor::All)]
+    attachments_for: AttachmentsFor,
+
+    /// Where attachment bodies go: `per-email` (`attachments/{email}/{id}__{name}`) or `by-hash`
+    /// (`attachments/by-hash/{sha256}`, uploaded once and shared by every record with that body).
+    #[arg(
+        long,
+        env = "ATTACHMENT_STORE",
+        value_enum,
+        default_value_t = AttachmentStore::PerEmail
+    )]
+    attachment_store: AttachmentStore,
+
+    /// DeNIST list (local path or s3://): sorted MD5/SHA-256 hashes of known system files, e.g.
+    /// an NSRL export. Matching attachments are marked `is_nist` or skipped (`--denist-action`).
+    #[arg(long, env = "DENIST_LIST")]
+    denist_list: Option<String>,
+
+    /// What to do with attachments on the DeNIST list: `mark` or `skip` (not stored or listed).
+    #[arg(long, env = "DENIST_ACTION", value_enum, default_value_t = DenistAction::Mark)]
+    denist_action: DenistAction,
+
+    /// Emit `body_html_rewritten`: body_html with `cid:` image references replaced by the inline
+    /// part's `attachment-id` or `s3-key`.
+    #[arg(long, env = "REWRITE_CID", value_enum)]
+    rewrite_cid: Option<CidTarget>,
+
+    /// Unpack ZIP, tar and gzip attachments into child attachments (`parent_attachment_id`).
+    #[arg(long, env = "EXPAND_ARCHIVES")]
+    expand_archives: bool,
+
+    /// Deepest archive nesting expanded (a .tar.gz counts as two levels).
+    #[arg(long, env = "ARCHIVE_MAX_DEPTH", default_value_t = 3)]
+    archive_max_depth: usize,
+
+    /// Decompressed bytes allowed per email across all its archives; guards against zip bombs.
+    #[arg(long, env = "ARCHIVE_MAX_BYTES", default_value_t = 1024 * 1024 * 1024)]
+    archive_max_bytes: u64,
+
+    /// Also package each top-level email (raw .eml) with all its family's attachments into
+    /// `families/{email_id}.zip` for one-click native download.
+    #[arg(long, env = "FAMILY_ZIPS")]
+    family_zips: bool,
+
+    /// Attachments larger than this are recorded (with hash and `skipped_reason`) but not stored.
+    #[arg(long, env = "MAX_ATTACHMENT_BYTES")]
+    max_attachment_bytes: Option<u64>,
+
+    /// Only store attachments of these types (comma-separated MIME types, `family/*`, or
+    /// `.ext`); others are recorded as skipped.
+    #[arg(long, env = "ATTACHMENT_TYPE_ALLOWLIST", value_delimiter = ',')]
+    attachment_type_allowlist: Vec<String>,
+
+    /// Never store attachments of these types (same pattern syntax as the allowlist).
+    #[arg(long, env = "ATTACHMENT_TYPE_BLOCKLIST", value_delimiter = ',')]
+    attachment_type_blocklist: Vec<String>,
+
+    /// Also write `anonymized.zip`: every message with addresses hashed and text replaced by
+    /// lorem ipsum of the same length, MIME structure intact, for sharing as a test corpus. The
+    /// ZIP is valid `--input-format eml-archive` input.
+    #[arg(long, env = "ANONYMIZED_EXPORT")]
+    anonymized_export: bool,
+
+    /// Key for the anonymized export's address and ID hashes. Reuse it to keep pseudonyms
+    /// consistent across PSTs; defaults to a random key per run.
+    #[arg(long, env = "ANONYMIZE_KEY", hide_env_values = true)]
+    anonymize_key: Option<String>,
+
+    /// Scan each stored attachment with clamd (`host:port`, `tcp://`, `unix://` or a socket path)
+    /// or an ICAP service (`icap://host:1344/avscan`); flagged files go under `quarantine/`.
+    #[arg(long, env = "SCAN_ENDPOINT")]
+    scan_endpoint: Option<String>,
+
+    /// Seconds to wait on the scanner per attachment.
+    #[arg(long, env = "SCAN_TIMEOUT_SECS", default_value_t = 60)]
+    scan_timeout_secs: u64,
+
+    /// Write `attachment_text.ndjson.gz`: text extracted from PDF, DOCX, XLSX and plain-text
+    /// attachments, for the search index.
+    #[arg(long, env = "ATTACHMENT_TEXT")]
+    attachment_text: bool,
+
+    /// Characters of text kept per attachment; longer text is cut and marked `truncated`.
+    #[arg(long, env = "ATTACHMENT_TEXT_MAX_CHARS", default_value_t = 1_000_000)]
+    attachment_text_max_chars: usize,
+
+    /// Re-extract only these items: a list (local path or s3://) of readpst-relative source
+    /// paths, folder prefixes, or email IDs, one per line.
+    #[arg(long, env = "ONLY_SOURCE_PATHS")]
+    only_source_paths: Option<String>,
+
+    /// Preview: parse only this many messages (with their attachments); the manifest is marked
+    /// `sample: true`.
+    #[arg(long, env = "SAMPLE")]
+    sample: Option<usize>,
+
+    /// Preview: keep each message with this probability, in percent; `--sample` caps the total.
+    #[arg(long, env = "SAMPLE_PERCENT")]
+    sample_percent: Option<f64>,
+
+    /// Which messages `--sample` takes: `first` (folder and message order) or `random`.
+    #[arg(long, env = "SAMPLE_MODE", value_enum, default_value_t = sample::SampleMode::First)]
+    sample_mode: sample::SampleMode,
+
+    /// Seed for `--sample-mode random` and `--sample-percent`; a rerun with the same seed picks
+    /// the same messages.
+    #[arg(long, env = "SAMPLE_SEED", default_value_t = 0)]
+    sample_seed: u64,
+
+    /// Seconds between JSON progress events on stdout. 0 disables heartbeats.
+    #[arg(long, env = "PROGRESS_INTERVAL_SECS", default_value_t = 30)]
+    progress_interval_secs: u64,
+
+    /// Also publish progress events to this SNS topic.
+    #[arg(long, env = "PROGRESS_SNS_TOPIC_ARN")]
+    progress_sns_topic_arn: Option<String>,
+
+    /// Also upsert the latest progress event into this DynamoDB table (key: pst_file_id).
+    #[arg(long, env = "PROGRESS_DYNAMODB_TABLE")]
+    progress_dynamodb_table: Option<String>,
+
+    /// Report job metrics (parse rate, upload bytes and latency, failures) as CloudWatch EMF
+    /// lines on stdout or to a Prometheus pushgateway.
+    #[arg(long, env = "METRICS_SINK", value_enum)]
+    metrics_sink: Option<metrics::MetricsSink>,
+
+    /// Pushgateway base URL for `--metrics-sink pushgateway`.
+    #[arg(long, env = "PUSHGATEWAY_URL")]
+    pushgateway_url: Option<String>,
+
+    /// HTTPS endpoint POSTed a JSON summary when the job succeeds or fails.
+    #[arg(long, env = "CALLBACK_URL")]
+    callback_url: Option<String>,
+
+    /// HMAC-SHA256 key for signing callback payloads (`X-Signature-256`).
+    #[arg(long, env = "CALLBACK_SECRET", hide_env_values = true)]
+    callback_secret: Option<String>,
+
+    /// Also store each body_html as a Brotli-compressed object under `bodies/` (served with
+    /// `Content-Encoding: br`).
+    #[arg(long, env = "BROTLI_BODIES")]
+    brotli_bodies: bool,
+
+    #[arg(long, env = "BROTLI_QUALITY", default_value_t = 9)]
+    brotli_quality: u32,
+
+    /// Codec for the email/attachment NDJSON and CSV files: `gzip` (`.gz`) or `zstd` (`.zst`).
+    #[arg(long, env = "COMPRESSION", value_enum, default_value_t = Codec::Gzip)]
+    compression: Codec,
+
+    /// Compression level (gzip 0-9, default 6; zstd 1-22, default 3).
+    #[arg(long, env = "COMPRESSION_LEVEL")]
+    compression_level: Option<u32>,
+
+    /// Start a new gzip member in the email/attachment NDJSON and CSV files after this many
+    /// uncompressed bytes (0 = one member per file). Member ranges go to `<file>.members.json`.
+    #[arg(long, env = "GZIP_MEMBER_BYTES", default_value_t = 16 * 1024 * 1024)]
+    gzip_member_bytes: u64,
+
+    /// Upload emails.ndjson.gz and attachments.ndjson.gz as `<name>-part-0001.ndjson.gz`, ...
+    /// instead, starting a new part after this many records (0 = no row limit).
+    #[arg(long, env = "OUTPUT_PART_ROWS", default_value_t = 0)]
+    output_part_rows: u64,
+
+    /// Start a new output part after this many uncompressed bytes (0 = no byte limit).
+    #[arg(long, env = "OUTPUT_PART_BYTES", default_value_t = 0)]
+    output_part_bytes: u64,
+
+    /// Keep partial readpst output when readpst fails part-way, and run a second pass that
+    /// includes deleted items. Messages only that pass produced are marked `is_recovered`.
+    #[arg(long, env = "RECOVERY_MODE")]
+    recovery_mode: bool,
+
+    /// Read the PST's node database and write `pst_index.ndjson.gz`: each message's node ID
+    /// and the offset and size of its blocks in the PST, linked to its email record by
+    /// Message-ID where the PST isn't encrypted (PST input only; see pstindex).
+    #[arg(long, env = "PST_INDEX")]
+    pst_index: bool,
+
+    /// Run as a long-lived worker polling `--queue-url` for job messages.
+    #[arg(long, env = "WORKER", requires = "queue_url")]
+    #[serde(skip)]
+    worker: bool,
+
+    #[arg(long, env = "QUEUE_URL")]
+    #[serde(skip)]
+    queue_url: Option<String>,
+
+    /// Failed job messages are forwarded here (with the error) and removed from the main queue.
+    /// Without it, failed messages are left to the queue's own redrive policy.
+    #[arg(long, env = "DLQ_URL")]
+    #[serde(skip)]
+    dlq_url: Option<String>,
+
+    /// Visibility timeout applied to received messages; renewed at half this interval while
+    /// the job runs.
+    #[arg(long, env = "VISIBILITY_TIMEOUT_SECS", default_value_t = 300)]
+    #[serde(skip)]
+    visibility_timeout_secs: i32,
+
+    /// Extract every PST in this job list (local path or s3://): a JSON array of objects or a
+    /// CSV with a header row, using the argument names above, each overlaid on this run's own.
+    #[arg(long, env = "JOBS_FILE", conflicts_with = "worker")]
+    #[serde(skip)]
+    jobs_file: Option<String>,
+
+    /// Jobs from `--jobs-file` (or submitted to `--serve`) run at the same time.
+    #[arg(long, env = "JOBS_CONCURRENCY", default_value_t = 1)]
+    #[serde(skip)]
+    jobs_concurrency: usize,
+
+    /// Where to write the batch summary (local path or s3://, az:// or gs:// URI); stdout when
+    /// unset.
+    #[arg(long, env = "BATCH_SUMMARY")]
+    #[serde(skip)]
+    batch_summary: Option<String>,
+
+    /// Where to write the job's outcome as JSON (local path or s3://, az:// or gs:// URI): status,
+    /// counts, manifest key and, on failure, an `error_class` that also sets the exit code.
+    #[arg(long, env = "RESULT_FILE", conflicts_with_all = ["worker", "jobs_file", "serve"])]
+    #[serde(skip)]
+    result_file: Option<String>,
+
+    /// Serve the job API on this address (`:8080` listens on loopback only; use `0.0.0.0:8080`
+    /// for every interface): POST /jobs, GET /jobs/{id}, GET /healthz.
+    #[arg(
+        long,
+        env = "SERVE_ADDR",
+        conflicts_with_all = ["worker", "jobs_file"],
+        requires = "serve_token"
+    )]
+    #[serde(skip)]
+    serve: Option<String>,
+
+    /// Bearer token clients of `--serve` must send (`Authorization: Bearer <token>`).
+    #[arg(long, env = "SERVE_TOKEN", hide_env_values = true)]
+    #[serde(skip)]
+    serve_token: Option<String>,
+}
+
+// Tools that run instead of an extraction.
+#[derive(Subcommand, Debug, Clone)]
+enum Tool {
+    /// Combine the extraction manifests of one case into a case-level manifest with cross-PST
+    /// duplicate statistics.
+    MergeManifests {
+        /// Extraction manifests: local paths or s3://, az:// or gs:// URIs.
+        #[arg(required = true)]
+        manifests: Vec<String>,
+
+        /// Where to write the case manifest: local path or s3://, az:// or gs:// URI.
+        #[arg(long)]
+        output: String,
+
+        /// Case id recorded in the case manifest.
+        #[arg(long, env = "CASE_ID", default_value = "")]
+        case_id: String,
+    },
+    /// Report a PST's size, format, encryption, estimated message and folder counts and scratch
+    /// needs as JSON on stdout, without extracting or uploading anything.
+    Inspect {
+        /// The PST: a local path or s3://, az:// or gs:// URI (downloaded to the work dir).
+        source: String,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum AttachmentsFor {
+    All,
+    TaggedOnly,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum NearDupeSource {
+    Clean,
+    Full,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum AttachmentStore {
+    PerEmail,
+    ByHash,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum DenistAction {
+    Mark,
+    Skip,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum CidTarget {
+    AttachmentId,
+    S3Key,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum InputFormat {
+    #[default]
+    Pst,
+    EmlArchive,
+    Maildir,
+}
+
+impl InputFormat {
+    fn name(self) -> &'static str {
+        match self {
+            InputFormat::Pst => "pst",
+            InputFormat::EmlArchive => "eml-archive",
+            InputFormat::Maildir => "maildir",
+        }
+    }
+
+    /// Whether `rel` (a `/`-separated path in the archive or under the prefix) is a message.
+    fn is_message_path(self, rel: &str) -> bool {
+        match self {
+            InputFormat::Pst => false,
+            InputFormat::EmlArchive => rel.to_ascii_lowercase().ends_with(".eml"),
+            // Delivered mail lives in cur/ and new/; tmp/ holds deliveries in progress.
+            InputFormat::Maildir => {
+                let mut parts = rel.rsplit('/');
+                let file = parts.next().unwrap_or("");
+                matches!(parts.next(), Some("cur" | "new")) && !file.starts_with('.')
+            }
+        }
+    }
+}
+
+// Deserialize lets the threading pass round-trip records (see write_threaded_records).
+#[derive(Serialize, Deserialize, Default)]
+struct EmailRecord {
+    id: String,
+    pst_file_id: String,
+    project_id: Option<String>,
+    case_id: Option<String>,
+    custodian_id: Option<String>,
+    custodian_name: Option<String>,
+    source_path: String,
+
+    message_id: Option<String>,
+    // message_id without brackets or whitespace and with the domain lowercased; for messages
+    // without one, a synthetic ID hashed from sender, date, subject and body
+    // (`<hex>@synthetic.invalid`, flagged message_id_synthetic), which threading uses.
+    message_id_normalized: Option<String>,
+    message_id_synthetic: bool,
+    in_reply_to: Option<String>,
+    references: Option<String>,
+    subject: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    cc: Option<String>,
+    bcc: Option<String>,
+    // Recipient addresses parsed from to/cc/bcc: lowercased, in header order, deduplicated.
+    to_emails: Vec<String>,
+    cc_emails: Vec<String>,
+    bcc_emails: Vec<String>,
+    date: Option<String>,
+    date_epoch: Option<i64>,
+    // Which parser produced date_epoch; anything but rfc2822 came from a malformed header.
+    date_parser: Option<DateParser>,
+    // `date`, or `received` when the Date header was missing or implausible and date_epoch is
+    // the earliest Received timestamp; date_utc is date_epoch as ISO 8601 UTC.
+    date_source: Option<dates::DateSource>,
+    date_utc: Option<String>,
+    received: Vec<String>,
+
+    body_text: Option<String>,
+    body_html: Option<String>,
+    // --rewrite-cid: body_html with `cid:` references to stored inline parts replaced by their
+    // attachment id or S3 key; empty when nothing was replaced.
+    #[serde(default)]
+    body_html_rewritten: Option<String>,
+    // body_text without quoted replies (`On ... wrote:`, `>` lines, Outlook reply headers) and
+    // the trailing signature, for near-duplicate detection and previews.
+    body_text_clean: Option<String>,
+    // 64-bit simhash (16 hex digits) over the normalized body; empty for bodies under 8 words.
+    near_dupe_hash: Option<String>,
+    // Shared by emails whose near_dupe_hash is within --near-dupe-similarity, once there are two.
+    #[serde(default)]
+    near_dupe_cluster_id: Option<String>,
+    // ISO 639-1 language of the author's text (body_text_clean, else the HTML body's text), from
+    // the stopword heuristics used for attachments; empty when there is too little text to tell.
+    body_language: Option<String>,
+    // --internal-domains: internal, outbound, inbound or external, from the sender's and the
+    // to/cc/bcc domains.
+    direction: Option<scoring::Direction>,
+    // Lightweight derived fields to ease downstream loading.
+    sender_email: Option<String>,
+    sender_name: Option<String>,
+    // Where/how the message was composed (investigator metadata).
+    originating_ip: Option<String>,
+    mail_client: Option<String>,
+    // Optional passes that altered this record; lets QA segment quality by processing path.
+    processing_flags: Vec<ProcessingFlag>,
+    // Search-term hit counts over subject and body (only terms that hit).
+    term_hits: std::collections::BTreeMap<String, usize>,
+    // --pii-scan: PII patterns found in the body text.
+    pii_flags: Vec<String>,
+    // --rules: tags whose expression matched subject or body.
+    rule_tags: Vec<String>,
+    parse_ms: f64,
+    // Brotli-compressed copy of body_html for direct web delivery (--brotli-bodies).
+    body_html_br_key: Option<String>,
+    // The message ended before a closing MIME boundary; complete parts were salvaged and the
+    // incomplete trailing part dropped.
+    truncated_mime: bool,
+    // The subject or a body part had a missing, unknown or wrong charset, so it was sniffed.
+    charset_issues: bool,
+    // eDiscovery family: embedded message/rfc822 attachments are emitted as their own records
+    // pointing at the enclosing email. family_id is the top-level email's id; depth 0 = top.
+    parent_email_id: Option<String>,
+    family_id: String,
+    depth: usize,
+    // Singleton headers (From, Date, Subject, ...) present more than once; the first copy is
+    // used. Smuggling is suspected when the copies disagree (see security_report.ndjson.gz).
+    duplicate_header_names: Vec<String>,
+    header_smuggling_suspected: bool,
+    // Display name or address impersonates a --vip-list entry.
+    spoofing_suspected: bool,
+    // Attachments not extracted because the email was untagged (--attachments-for tagged-only).
+    attachments_withheld: usize,
+    // Conversation thread (filled in after the parse pass; None for embedded copies).
+    // thread_id is derived from the thread root's Message-ID, so it agrees across PSTs.
+    thread_id: Option<String>,
+    thread_position: Option<usize>,
+    // Normalized message hash (--dedupe / --dedupe-index); copies with the same hash are in
+    // duplicates.ndjson.gz.
+    dedupe_hash: Option<String>,
+    // Family already claimed in --dedupe-index by another email (set on the whole family).
+    is_global_duplicate: bool,
+    global_primary_email_id: Option<String>,
+    // The message is wrapped in multipart/signed. The signature part isn't an attachment; it is
+    // stored on its own under signatures/.
+    is_signed: bool,
+    signature_protocol: Option<String>,
+    signature_s3_key: Option<String>,
+    // Parsed message/delivery-status part of a bounce (reporting MTA plus per-recipient action,
+    // status code, remote MTA and diagnostic).
+    delivery_status: Option<dsn::DeliveryStatus>,
+    // Meeting invite (text/calendar part): its UID, SEQUENCE and METHOD. All emails of one UID
+    // are collapsed into a single meeting in meetings.ndjson.gz.
+    calendar_uid: Option<String>,
+    calendar_sequence: Option<u32>,
+    calendar_method: Option<String>,
+    // Deleted item salvaged by --recovery-mode (source_path under _recovered/).
+    is_recovered: bool,
+    // 0-100 score from --priority-model (filled in with the thread fields).
+    review_priority: Option<u8>,
+    // Sender and timestamp from the mbox "From " separator line (top-level mbox messages only).
+    // envelope_date_epoch is parsed like the Date header but never replaces date_epoch.
+    envelope_from: Option<String>,
+    envelope_date: Option<String>,
+    envelope_date_epoch: Option<i64>,
+    // --family-zips: the family's native package (same key on every record of the family).
+    family_zip_s3_key: Option<String>,
+    // --export-raw-eml: the untouched message under eml/ and the SHA-256 of its bytes (top-level
+    // records only; embedded messages are inside their parent's .eml).
+    raw_eml_s3_key: Option<String>,
+    raw_sha256: Option<String>,
+    // --hash-algos digests of the raw message bytes (top-level records only).
+    raw_hashes: Option<hashes::Digests>,
+    // --control-number-prefix: this record's control (Bates) number.
+    control_number: Option<String>,
+    // Exchange journal report unwrapped into this record (the original message): the report's
+    // envelope, every address it was delivered to, and the Bcc recipients among them (including
+    // members of Bcc'd distribution lists), which the original's headers don't show.
+    journal_envelope: Option<journal::Envelope>,
+    journal_recipient_emails: Vec<String>,
+    journal_bcc_emails: Vec<String>,
+    // Importance (Importance / X-Priority / Priority headers), Sensitivity, whether a read or
+    // delivery receipt was requested, and what kind of receipt this message itself is.
+    importance: Option<priority::Importance>,
+    sensitivity: Option<priority::Sensitivity>,
+    read_receipt_requested: bool,
+    delivery_receipt_requested: bool,
+    receipt_type: Option<priority::ReceiptType>,
+    // --retain-headers: the configured headers present on this message, by configured name.
+    retained_headers: std::collections::BTreeMap<String, String>,
+}
+
+/// How a record's content was altered on the way out (`processing_flags`). There is no OCR
+/// flag: attachment text is only ever extracted, never OCR'd.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingFlag {
+    /// External-email banner lines were removed from (or caused us to drop) body_text.
+    BannerStripped,
+    /// body_text was derived from the HTML body rather than a text/plain part.
+    HtmlDerivedText,
+    /// A winmail.dat (application/ms-tnef) part was unpacked into its attachments and body.
+    TnefDecoded,
+    /// body_text and/or body_html were recovered from an RTF (possibly compressed) body.
+    RtfDerivedBody,
+    /// A --rules disclaimer pattern was cut from body_text.
+    DisclaimerStripped,
+    /// A body part's charset was sniffed: its label was missing, unknown or wrong for the bytes.
+    CharsetGuessed,
+}
+
+fn push_flag(flags: &mut Vec<ProcessingFlag>, flag: ProcessingFlag) {
+    if !flags.contains(&flag) {
+        flags.push(flag);
+    }
+}
+
+#[derive(Serialize, Default)]
+struct AttachmentRecord {
+    id: String,
+    email_message_id: String,
+    pst_file_id: String,
+    project_id: Option<String>,
+    case_id: Option<String>,
+    custodian_id: Option<String>,
+    custodian_name: Option<String>,
+    filename: String,
+    content_type: Option<String>,
+    file_size_bytes: usize,
+    s3_bucket: String,
+    s3_key: String,
+    attachment_hash: String,
+    is_inline: bool,
+    content_id: Option<String>,
+    source_path: String,
+    is_encrypted_attachment: bool,
+    /// Container the attachment was unpacked from (e.g. "winmail.dat", or "journal report" for
+    /// a part of an unwrapped journal report); None for the message's own MIME parts.
+    source_container: Option<String>,
+    /// The part's Content-Type header (same as content_type) and the type its bytes show.
+    declared_content_type: Option<String>,
+    detected_content_type: Option<String>,
+    /// The filename's extension doesn't fit detected_content_type.
+    extension_mismatch: bool,
+    /// The filename's charset was sniffed, or part of it couldn't be decoded.
+    charset_issues: bool,
+    /// MD5 or SHA-256 is on the --denist-list.
+    is_nist: bool,
+    /// --expand-archives: the archive attachment this file was unpacked from, and its path there.
+    parent_attachment_id: Option<String>,
+    archive_path: Option<String>,
+    /// Set on archives: `expanded`, `encrypted`, `limit_exceeded` or `corrupt`.
+    archive_status: Option<String>,
+    /// Not stored (s3_key is empty): `too_large`, `type_blocked` or `type_not_allowed`.
+    skipped_reason: Option<String>,
+    /// --scan-endpoint: `clean`, `infected` or `error` (scanner failed on this file).
+    scan_status: Option<String>,
+    scan_signature: Option<String>,
+    /// Infected and unscanned files are stored here instead of s3_key (which stays empty).
+    quarantine_s3_key: Option<String>,
+    /// --pii-scan: PII patterns found in the extracted text.
+    pii_flags: Vec<String>,
+    /// --classify-attachments: coarse document type and ISO 639-1 language.
+    doc_type: Option<String>,
+    doc_language: Option<String>,
+    /// --hash-algos md5 / sha1 digests of the content (SHA-256 is attachment_hash).
+    md5: Option<String>,
+    sha1: Option<String>,
+    /// --control-number-prefix: this record's control (Bates) number.
+    control_number: Option<String>,
+}
+
+/// A message that exceeded the per-message timeout; its raw bytes go to `dead_letter/`.
+#[derive(Serialize)]
+struct DeadLetterEntry {
+    id: String,
+    source_path: String,
+    message_index: usize,
+    size_bytes: usize,
+    elapsed_ms: u64,
+    timeout_secs: u64,
+    s3_key: String,
+}
+
+/// A file, message or attachment part that was skipped or failed (`errors.ndjson.gz`). Byte
+/// offsets are into the source file.
+#[derive(Serialize)]
+struct ErrorEntry {
+    /// `file_skipped`, `email_failed`, `attachment_skipped` or `upload_failed` (an attachment,
+    /// body, signature, dead-letter or family ZIP object that could not be stored after retries;
+    /// `reason` names the key).
+    kind: &'static str,
+    source_path: String,
+    message_index: Option<usize>,
+    email_id: Option<String>,
+    part_index: Option<usize>,
+    reason: String,
+    byte_start: Option<u64>,
+    byte_end: Option<u64>,
+}
+
+impl ErrorEntry {
+    fn file(source_path: &str, reason: &str, len: u64) -> Self {
+        Self {
+            kind: "file_skipped",
+            source_path: source_path.to_string(),
+            message_index: None,
+            email_id: None,
+            part_index: None,
+            reason: reason.to_string(),
+            byte_start: Some(0),
+            byte_end: Some(len),
+        }
+    }
+
+    fn message(
+        source_path: &str,
+        message_index: usize,
+        reason: String,
+        start: u64,
+        len: u64,
+    ) -> Self {
+        Self {
+            kind: "email_failed",
+            source_path: source_path.to_string(),
+            message_index: Some(message_index),
+            email_id: None,
+            part_index: None,
+            reason,
+            byte_start: Some(start),
+            byte_end: Some(start + len),
+        }
+    }
+
+    /// An object that could not be stored after retries.
+    fn upload(
+        source_path: &str,
+        message_index: Option<usize>,
+        email_id: Option<&str>,
+        key: &str,
+        err: &anyhow::Error,
+    ) -> Self {
+        Self {
+            kind: "upload_failed",
+            source_path: source_path.to_string(),
+            message_index,
+            email_id: email_id.map(str::to_string),
+            part_index: None,
+            reason: format!("{key}: {err:#}"),
+            byte_start: None,
+            byte_end: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    pst_file_id: String,
+    custodian_id: Option<String>,
+    custodian_name: Option<String>,
+    source_bucket: String,
+    source_key: String,
+    output_bucket: String,
+    output_prefix: String,
+    // --source-path / --output-dir (local mode); the matching S3 fields are empty then.
+    source_path: Option<String>,
+    output_dir: Option<String>,
+    // "pst", "msg" (a single Outlook .msg), "msg-zip" (a ZIP of .msg files), "eml-archive" or
+    // "maildir".
+    input_format: &'static str,
+    // .msg files in the input that could not be converted.
+    msg_failed_total: usize,
+    #[serde(flatten)]
+    emails: EmailCounts,
+    #[serde(flatten)]
+    attachments: AttachmentCounts,
+    // --family-zips: packages written under families/.
+    family_zips_total: usize,
+    // --attachment-text: attachments with a line in attachment_text.ndjson.gz.
+    attachment_text_total: usize,
+    // --terms-file: every term with the emails, attachments and families it hit.
+    term_summary: std::collections::BTreeMap<String, terms::TermSummary>,
+    // --rules: the rule set this job ran with (emails per tag are in `rule_tags`).
+    rules_version: Option<String>,
+    // --anonymized-export: messages written to anonymized.zip.
+    anonymized_total: usize,
+    dead_letter_total: usize,
+    #[serde(flatten)]
+    errors: ErrorCounts,
+    security_findings_total: usize,
+    threads: ThreadStats,
+    // Near-duplicate clusters of two or more emails, and the emails in them.
+    near_dupe_clusters_total: usize,
+    near_dupe_emails_total: usize,
+    // Folder copies suppressed by --dedupe (listed in duplicates.ndjson.gz).
+    duplicates_suppressed_total: usize,
+    // --concordance: distinct terms written to concordance.ndjson.gz.
+    concordance_terms_total: usize,
+    // --control-number-prefix: the first and last numbers assigned, and where the next batch
+    // starts.
+    control_numbers: Option<bates::Range>,
+    // --loadfile: documents (emails and attachments) listed in loadfile.dat / loadfile.xml.
+    loadfile_documents_total: usize,
+    // --recovery-mode: the normal readpst pass failed (output is partial); the emails that came
+    // only from the deleted-items pass are `recovered_emails_total`.
+    readpst_failed: bool,
+    // readpst's per-folder item counts against the items extracted from each folder (full PST
+    // runs only); folders that differ are listed.
+    count_validation: Option<itemcounts::CountValidation>,
+    // Non-mail PST items (calendar.ndjson.gz / contacts.ndjson.gz / tasks.ndjson.gz).
+    calendar_total: usize,
+    // Meetings in meetings.ndjson.gz, and the invite emails beyond the first per meeting
+    // (updates, cancellations and replies folded into another email's meeting).
+    meetings_total: usize,
+    meeting_updates_collapsed: usize,
+    contacts_total: usize,
+    tasks_total: usize,
+    // Early-case-assessment counts: emails per folder and month, top sender and recipient
+    // domains, attachments per type and their total bytes.
+    stats: stats::CaseStats,
+    // "complete", or "partial" (--max-duration ran out) / "interrupted" (SIGTERM): outputs cover
+    // only what was processed before `cutoff`.
+    status: &'static str,
+    partial: bool,
+    cutoff: Option<watchdog::Cutoff>,
+    duration_s: f64,
+    // Null when the file was uploaded as parts (OUTPUT_PART_ROWS / OUTPUT_PART_BYTES); the
+    // parts are then listed in `output_parts`, keyed by the file they replace.
+    ndjson_gz_key: Option<String>,
+    csv_gz_key: String,
+    attachments_ndjson_gz_key: Option<String>,
+    attachments_csv_gz_key: String,
+    output_parts: std::collections::BTreeMap<String, Vec<gzmembers::Part>>,
+    // Codec of the four files above (and their parts): `gzip` or `zstd`.
+    compression: Codec,
+    manifest_key: String,
+    raw_index_ndjson_gz_key: Option<String>,
+    // --pst-index: pst_index.ndjson.gz, and the PST's format and encryption with the number of
+    // messages indexed and linked to an email record (or why the PST couldn't be read).
+    pst_index_ndjson_gz_key: Option<String>,
+    pst_index: Option<pstindex::Summary>,
+    raw_blob_keys: Vec<String>,
+    opensearch: Option<IndexStats>,
+    // Set when this run was restricted with --only-source-paths (a targeted re-extraction).
+    source_filter: Option<String>,
+    // A preview (--sample / --sample-percent): outputs cover only `sampling.messages_taken`
+    // messages.
+    sample: bool,
+    sampling: Option<sample::Summary>,
+    parse_timing: ParseTimingStats,
+    sha256: std::collections::BTreeMap<String, String>,
+    version: String,
+    // Output contract version; the field/column list is in schema.json.
+    schema_version: u32,
+}
+
+/// Manifest counts derived from the email records as they are written.
+#[derive(Serialize, Default)]
+struct EmailCounts {
+    emails_total: usize,
+    // Records (included in emails_total) extracted from attached message/rfc822 parts.
+    embedded_emails_total: usize,
+    // Emails per date parser, and emails whose Date header could not be parsed at all.
+    date_parsers: std::collections::BTreeMap<DateParser, usize>,
+    dates_unparsed: usize,
+    // Emails dated from a Received header (Date missing, unparseable or implausible).
+    dates_from_received: usize,
+    truncated_mime_total: usize,
+    charset_issues_total: usize,
+    // --internal-domains: emails per direction.
+    directions: std::collections::BTreeMap<scoring::Direction, usize>,
+    // Emails per body_language.
+    body_languages: std::collections::BTreeMap<String, usize>,
+    // --pii-scan: emails flagged, per pattern.
+    pii_emails_flagged: std::collections::BTreeMap<String, usize>,
+    // --rules: emails per tag.
+    rule_tags: std::collections::BTreeMap<String, usize>,
+    // Attachments of untagged emails skipped under --attachments-for tagged-only.
+    attachments_withheld_total: usize,
+    // Emails wrapped in multipart/signed (signatures under signatures/).
+    signed_emails_total: usize,
+    // Bounce messages with a parsed delivery_status, and the recipients they report as failed.
+    delivery_reports_total: usize,
+    failed_recipients_total: usize,
+    // Exchange journal reports replaced by the original message they carried.
+    journal_reports_unwrapped: usize,
+    // --recovery-mode: emails that came only from the deleted-items pass.
+    recovered_emails_total: usize,
+    // Top-level emails already claimed in --dedupe-index by another email.
+    global_duplicates_total: usize,
+}
+
+impl EmailCounts {
+    fn add(&mut self, record: &EmailRecord) {
+        self.emails_total += 1;
+        if record.depth > 0 {
+            self.embedded_emails_total += 1;
+        } else if record.is_global_duplicate {
+            self.global_duplicates_total += 1;
+        }
+        match (record.date_source, record.date_parser) {
+            (Some(dates::DateSource::Received), _) => self.dates_from_received += 1,
+            (_, Some(parser)) => *self.date_parsers.entry(parser).or_insert(0) += 1,
+            (_, None) if record.date.is_some() => self.dates_unparsed += 1,
+            _ => {}
+        }
+        self.truncated_mime_total += usize::from(record.truncated_mime);
+        self.charset_issues_total += usize::from(record.charset_issues);
+        if let Some(direction) = record.direction {
+            *self.directions.entry(direction).or_default() += 1;
+        }
+        if let Some(lang) = &record.body_language {
+            *self.body_languages.entry(lang.clone()).or_default() += 1;
+        }
+        for flag in &record.pii_flags {
+            *self.pii_emails_flagged.entry(flag.clone()).or_default() += 1;
+        }
+        for tag in &record.rule_tags {
+            *self.rule_tags.entry(tag.clone()).or_default() += 1;
+        }
+        self.attachments_withheld_total += record.attachments_withheld;
+        self.signed_emails_total += usize::from(record.is_signed);
+        if let Some(report) = &record.delivery_status {
+            self.delivery_reports_total += 1;
+            self.failed_recipients_total += report.failed_recipients().count();
+        }
+        self.journal_reports_unwrapped += usize::from(record.journal_envelope.is_some());
+        self.recovered_emails_total += usize::from(record.is_recovered);
+    }
+}
+
+/// Manifest counts for attachments: those derived from the records as they are written, and
+/// those counted where the parse loop decides (NIST matches, archive members, deduplication).
+#[derive(Serialize, Default)]
+struct AttachmentCounts {
+    attachments_total: usize,
+    attachments_encrypted_total: usize,
+    // Attachments whose filename extension disagrees with their detected content type.
+    extension_mismatch_total: usize,
+    // --denist-list: attachments matching a known-file hash (marked or skipped) and their bytes.
+    nist_attachments_total: usize,
+    nist_bytes_total: u64,
+    // --expand-archives: files unpacked from archive attachments, and archives that couldn't be
+    // fully opened (password-protected entries, or over the depth/byte limits).
+    archive_members_total: usize,
+    archives_encrypted_total: usize,
+    archives_limit_exceeded_total: usize,
+    // Attachments recorded but not stored, by skipped_reason, and their total size.
+    attachments_skipped: std::collections::BTreeMap<String, usize>,
+    attachments_skipped_bytes: u64,
+    // --pii-scan: attachments flagged, per pattern.
+    pii_attachments_flagged: std::collections::BTreeMap<String, usize>,
+    // --classify-attachments: attachments per doc_type.
+    doc_types: std::collections::BTreeMap<String, usize>,
+    // --scan-endpoint: attachments scanned, and those quarantined as infected or unscannable.
+    attachments_scanned_total: usize,
+    scan_infected_total: usize,
+    scan_errors_total: usize,
+    // --attachment-store by-hash: stored attachments whose body was already in the store (from
+    // this job or an earlier one) and was not uploaded again, and their bytes.
+    attachments_deduplicated_total: usize,
+    attachment_bytes_deduplicated: u64,
+}
+
+impl AttachmentCounts {
+    fn add(&mut self, record: &AttachmentRecord) {
+        self.attachments_total += 1;
+        self.attachments_encrypted_total += usize::from(record.is_encrypted_attachment);
+        self.extension_mismatch_total += usize::from(record.extension_mismatch);
+        if let Some(reason) = &record.skipped_reason {
+            *self.attachments_skipped.entry(reason.clone()).or_default() += 1;
+            self.attachments_skipped_bytes += record.file_size_bytes as u64;
+        }
+        match record.archive_status.as_deref() {
+            Some("encrypted") => self.archives_encrypted_total += 1,
+            Some("limit_exceeded") => self.archives_limit_exceeded_total += 1,
+            _ => {}
+        }
+        if let Some(status) = record.scan_status.as_deref() {
+            self.attachments_scanned_total += 1;
+            match status {
+                "infected" => self.scan_infected_total += 1,
+                "error" => self.scan_errors_total += 1,
+                _ => {}
+            }
+        }
+        for flag in &record.pii_flags {
+            *self.pii_attachments_flagged.entry(flag.clone()).or_default() += 1;
+        }
+        if let Some(doc_type) = &record.doc_type {
+            *self.doc_types.entry(doc_type.clone()).or_default() += 1;
+        }
+    }
+}
+
+/// Everything listed in errors.ndjson.gz: messages that could not be parsed (timeouts, oversized
+/// and MIME failures), files that were not mail, attachment parts dropped as empty or
+/// undecodable, and attachment/body/signature objects that failed to upload.
+#[derive(Serialize, Default)]
+struct ErrorCounts {
+    emails_failed: usize,
+    files_skipped: usize,
+    attachment_parts_skipped: usize,
+    upload_failures_total: usize,
+}
+
+// Header values are decoded from the raw bytes rather than via mailparse's get_value so that
+// glued-together and language-tagged encoded words are handled too (see encoded_words).
+fn header_first(mail: &ParsedMail, name: &str) -> Option<String> {
+    mail.headers
+        .get_first_header(name)
+        .map(|h| encoded_words::decode_header(h.get_value_raw()).trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn header_all(mail: &ParsedMail, name: &str) -> Vec<String> {
+    mail.headers
+        .get_all_headers(name)
+        .into_iter()
+        .map(|h| encoded_words::decode_header(h.get_value_raw()).trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// Whether a header's raw value wasn't UTF-8 (so its charset was sniffed) or had undecodable
+/// bytes.
+fn header_charset_issue(mail: &ParsedMail, name: &str) -> bool {
+    mail.headers.get_all_headers(name).into_iter().any(|h| {
+        let raw = h.get_value_raw();
+        std::str::from_utf8(raw).is_err()
+            || encoded_words::decode_header(raw).contains(char::REPLACEMENT_CHARACTER)
+    })
+}
+
+/// First non-empty value among several header aliases, in priority order.
+fn header_first_of(mail: &ParsedMail, names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| header_first(mail, name))
+}
+
+/// Headers carrying the submitting client's IP. Exchange stamps its own variant; webmail
+/// providers use X-Originating-IP.
+const ORIGINATING_IP_HEADERS: &[&str] = &[
+    "X-Originating-IP",
+    "X-MS-Exchange-Organization-OriginalClientIPAddress",
+    "X-Sender-IP",
+    "X-Source-IP",
+];
+
+/// Headers identifying the composing client, in priority order.
+const MAIL_CLIENT_HEADERS: &[&str] = &[
+    "X-Mailer",
+    "User-Agent",
+    "X-MimeOLE",
+    "X-Newsreader",
+    "X-MS-Exchange-Organization-ClientInfo",
+];
+
+fn parse_originating_ip(value: &str) -> Option<String> {
+    // Values are usually "[203.0.113.7]" but some MTAs append comments: "203.0.113.7 (helo)".
+    let v = value.trim().trim_start_matches('[');
+    let ip = v
+        .split(|c: char| c == ']' || c.is_whitespace())
+        .next()
+        .unwrap_or("")
+        .trim();
+    ip.parse::<std::net::IpAddr>().ok().map(|addr| addr.to_string())
+}
+
+fn extract_originating_ip(mail: &ParsedMail) -> Option<String> {
+    ORIGINATING_IP_HEADERS
+        .iter()
+        .filter_map(|name| header_first(mail, name))
+        .find_map(|v| parse_originating_ip(&v))
+}
+
+fn is_attachment_disposition(part: &ParsedMail) -> bool {
+    let cd = header_first(part, "Content-Disposition")
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    cd.trim_start().starts_with("attachment")
+}
+
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn core_alnum_len(text: &str) -> usize {
+    text.chars().filter(|c| c.is_ascii_alphanumeric()).count()
+}
+
+fn strip_external_banner_lines(text: &str) -> String {
+    // Very conservative: only drop lines that *strongly* look like external-email warnings.
+    // We do not attempt full disclaimer stripping here (that's handled downstream in the API).
+    let normalized = normalize_newlines(text);
+    let mut kept: Vec<&str> = Vec::new();
+    for line in normalized.lines() {
+        let l = line.trim().to_ascii_lowercase();
+        if l.is_empty() {
+            kept.push(line);
+            continue;
+        }
+
+        let looks_like_external_banner = (l.contains("external email")
+            && (l.contains("caution") || l.contains("warning") || l.contains("external sender") || l.contains("originated")))
+            || l.starts_with("caution") && l.contains("external")
+            || l.starts_with("warning") && l.contains("external")
+            || l.starts_with("this email originated")
+            || l.starts_with("do not click")
+            || l.starts_with("don't click")
+            || l.contains("unless you recognise")
+            || l.contains("unless you recognize")
+            || l.contains("expected and known to be safe");
+
+        if looks_like_external_banner {
+            continue;
+        }
+        kept.push(line);
+    }
+    kept.join("\n")
+}
+
+fn is_mostly_external_banner(text: &str) -> bool {
+    let lower = text.to_ascii_lowercase();
+    // memmem is vectorized on aarch64 too; std's str::contains only is on x86_64.
+    if memmem::find(lower.as_bytes(), b"external").is_none() {
+        return false;
+    }
+    let core_total = core_alnum_len(text);
+    let core_stripped = core_alnum_len(&strip_external_banner_lines(text));
+
+    // If stripping banner-like lines removes almost everything and the overall body is short,
+    // treat it as banner-only.
+    core_total > 0 && core_total < 220 && core_stripped < 40
+}
+
+fn html_to_text_rough(html: &str) -> String {
+    // Cheap tag stripper used ONLY for scoring. This is not a full HTML->text conversion.
+    // (Display conversion happens server-side.)
+    let mut out = String::with_capacity(html.len().min(32_768));
+    let mut in_tag = false;
+    for ch in html.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ => {
+                if !in_tag {
+                    out.push(ch);
+                }
+            }
+        }
+    }
+    out
+}
+
+fn collect_text_bodies<'a>(
+    mail: &'a ParsedMail<'a>,
+    mime_prefix: &str,
+    out: &mut Vec<String>,
+    flags: &mut Vec<ProcessingFlag>,
+) {
+    if mail.subparts.is_empty() {
+        let ctype = mail.ctype.mimetype.to_ascii_lowercase();
+        if (ctype == mime_prefix) || ctype.starts_with(mime_prefix) {
+            // Avoid mistakenly selecting text/plain attachments as the message body.
+            if is_attachment_disposition(mail) {
+                return;
+            }
+            // Decoded here rather than by mailparse, which reads a missing or unknown label as
+            // ASCII; see charsets.
+            if let Ok(raw) = mail.get_body_raw() {
+                let mut declared = mail.ctype.params.get("charset").cloned();
+                if declared.is_none() && ctype == "text/html" {
+                    declared = charsets::html_meta_charset(&raw);
+                }
+                let (b, guessed) = charsets::decode(&raw, declared.as_deref());
+                if !b.trim().is_empty() {
+                    if guessed {
+                        push_flag(flags, ProcessingFlag::CharsetGuessed);
+                    }
+                    out.push(b);
+                }
+            }
+        }
+        return;
+    }
+    for part in &mail.subparts {
+        collect_text_bodies(part, mime_prefix, out, flags);
+    }
+}
+
+fn choose_best_body_text(mail: &ParsedMail, flags: &mut Vec<ProcessingFlag>) -> Option<String> {
+    let mut candidates: Vec<String> = Vec::new();
+    collect_text_bodies(mail, "text/plain", &mut candidates, flags);
+    if candidates.is_empty() {
+        return None;
+    }
+
+    // Prefer the candidate with the most meaningful content *after* stripping obvious banners.
+    // If all are banner-like, keep the longest (better than returning empty).
+    let mut best_idx: usize = 0;
+    let mut best_score: usize = 0;
+    for (idx, c) in candidates.iter().enumerate() {
+        let stripped = strip_external_banner_lines(c);
+        let score = core_alnum_len(&stripped);
+        if score > best_score {
+            best_score = score;
+            best_idx = idx;
+        }
+    }
+    Some(candidates.swap_remove(best_idx))
+}
+
+fn choose_best_body_html(mail: &ParsedMail, flags: &mut Vec<ProcessingFlag>) -> Option<String> {
+    let mut candidates: Vec<String> = Vec::new();
+    collect_text_bodies(mail, "text/html", &mut candidates, flags);
+    if candidates.is_empty() {
+        return None;
+    }
+    let mut best_idx: usize = 0;
+    let mut best_score: usize = 0;
+    for (idx, c) in candidates.iter().enumerate() {
+        // Score based on rough text content length (ignoring tags) after stripping banner lines.
+        let as_text = html_to_text_rough(c);
+        let stripped = strip_external_banner_lines(&as_text);
+        let score = core_alnum_len(&stripped);
+        if score > best_score {
+            best_score = score;
+            best_idx = idx;
+        }
+    }
+    Some(candidates.swap_remove(best_idx))
+}
+
+/// The text and HTML bodies of a parsed message, as the extractor picks them: the most
+/// substantial `text/plain` and `text/html` parts, with external-email banners stripped and a
+/// text body derived from the HTML when the text part is only a banner. `flags` gets the
+/// [`ProcessingFlag`]s for what was changed.
+pub fn select_email_bodies(
+    mail: &ParsedMail,
+    flags: &mut Vec<ProcessingFlag>,
+) -> (Option<String>, Option<String>) {
+    let mut body_text = choose_best_body_text(mail, flags);
+    let mut body_html = choose_best_body_html(mail, flags);
+
+    // If the chosen text/plain body is just an external-email banner, but we have a
+    // meaningful HTML body, prefer deriving a text body from the HTML. This improves
+    // downstream previews (which often prefer body_text) while still preserving HTML.
+    if let (Some(ref bt), Some(ref bh)) = (&body_text, &body_html) {
+        if is_mostly_external_banner(bt) {
+            let html_text = html_to_text_rough(bh);
+            let stripped = strip_external_banner_lines(&html_text);
+            let candidate = stripped.trim();
+
+            // Keep a conservative floor so we don't replace with near-empty noise.
+            if core_alnum_len(candidate) >= 20 {
+                body_text = Some(candidate.to_string());
+                push_flag(flags, ProcessingFlag::HtmlDerivedText);
+            } else {
+                body_text = None;
+            }
+            push_flag(flags, ProcessingFlag::BannerStripped);
+        }
+    }
+
+    // PST messages sometimes only have an RTF body (readpst writes it as rtf-body.rtf).
+    if body_text.is_none() || body_html.is_none() {
+        if let Some(rtf) = find_rtf_body(mail) {
+            fill_bodies_from_rtf(&rtf, &mut body_text, &mut body_html, flags);
+        }
+    }
+
+    (body_text, body_html)
+}
+
+fn find_rtf_body(mail: &ParsedMail) -> Option<Vec<u8>> {
+    if mail.subparts.is_empty() {
+        let ctype = mail.ctype.mimetype.to_ascii_lowercase();
+        let is_rtf_part = ctype == "text/rtf"
+            || ctype == "application/rtf"
+            || parse_filename_from_headers(mail)
+                .is_some_and(|name| name.eq_ignore_ascii_case("rtf-body.rtf"));
+        return if is_rtf_part {
+            mail.get_body_raw().ok().filter(|b| !b.is_empty())
+        } else {
+            None
+        };
+    }
+    mail.subparts.iter().find_map(find_rtf_body)
+}
+
+/// Fill whichever of body_text / body_html is missing from an RTF body (raw or compressed).
+fn fill_bodies_from_rtf(
+    rtf: &[u8],
+    body_text: &mut Option<String>,
+    body_html: &mut Option<String>,
+    flags: &mut Vec<ProcessingFlag>,
+) {
+    let Some(bodies) = rtf::bodies(rtf) else {
+        return;
+    };
+    let mut filled = false;
+    if body_html.is_none() && bodies.html.is_some() {
+        *body_html = bodies.html;
+        filled = true;
+    }
+    if body_text.is_none() && bodies.text.is_some() {
+        *body_text = bodies.text;
+        filled = true;
+    }
+    if filled {
+        push_flag(flags, ProcessingFlag::RtfDerivedBody);
+    }
+}
+
+fn stable_uuid(seed: &str) -> Uuid {
+    // Deterministic UUID derived from SHA-256(seed). This supports idempotent reruns.
+    let mut hasher = Sha256::new();
+    hasher.update(seed.as_bytes());
+    let digest = hasher.finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    // RFC4122 variant + "v5-like" version marker (0101) to keep UUIDs well-formed.
+    bytes[6] = (bytes[6] & 0x0F) | 0x50;
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+    Uuid::from_bytes(bytes)
+}
+
+fn sha256_bytes(bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    format!("{:x}", hasher.finalize())
+}
+
+fn sanitize_filename(value: &str, fallback: &str) -> String {
+    let mut name = value.trim().to_string();
+    if name.is_empty() {
+        name = fallback.to_string();
+    }
+    // Prevent path traversal and control chars.
+    name = name
+        .replace(['\\', '/'], "_")
+        .replace(['\0', '\r', '\n'], "");
+    // Keep it bounded; S3 keys support long names but UIs/DBs often don't.
+    if name.len() > 200 {
+        name.truncate(200);
+    }
+    name
+}
+
+fn parse_filename_from_headers(mail: &ParsedMail) -> Option<String> {
+    // Prefer Content-Disposition filename
+    if let Some(cd) = header_first(mail, "Content-Disposition") {
+        if let Some(fname) = encoded_words::param(&cd, "filename") {
+            return Some(fname);
+        }
+    }
+    // Fallback: Content-Type name
+    if let Some(ct) = header_first(mail, "Content-Type") {
+        if let Some(name) = encoded_words::param(&ct, "name") {
+            return Some(name);
+        }
+    }
+    None
+}
+
+fn parse_sender(from_header: &str) -> (Option<String>, Option<String>) {
+    // Best-effort: "Name <email@domain>" or "email@domain"
+    let text = from_header.trim();
+    if text.is_empty() {
+        return (None, None);
+    }
+    if let Some(start) = text.find('<') {
+        if let Some(end) = text.find('>') {
+            let email = text[start + 1..end].trim();
+            let name = text[..start].trim().trim_matches('"').trim_matches('\'');
+            let email_opt = if email.is_empty() { None } else { Some(email.to_string()) };
+            let name_opt = if name.is_empty() { None } else { Some(name.to_string()) };
+            return (email_opt, name_opt);
+        }
+    }
+    if text.contains('@') {
+        return (Some(text.to_string()), None);
+    }
+    (None, Some(text.to_string()))
+}
+
+/// Lowercased addresses from an address-list header (To/Cc/Bcc), in order, without duplicates.
+/// mailparse's RFC 5322 parser handles quoted names, groups and encoded words; lists it rejects
+/// (Outlook's unquoted "Smith, John <j@x.com>; ...") fall back to picking out address tokens.
+fn header_addresses(mail: &ParsedMail, name: &str) -> Vec<String> {
+    let Some(header) = mail.headers.get_first_header(name) else {
+        return Vec::new();
+    };
+    let candidates: Vec<String> = match mailparse::addrparse_header(header) {
+        Ok(list) => list
+            .iter()
+            .flat_map(|addr| match addr {
+                mailparse::MailAddr::Single(info) => vec![info.addr.clone()],
+                mailparse::MailAddr::Group(group) => {
+                    group.addrs.iter().map(|info| info.addr.clone()).collect()
+                }
+            })
+            .collect(),
+        Err(_) => encoded_words::decode_header(header.get_value_raw())
+            .split(|c: char| c.is_whitespace() || ",;<>()\"'".contains(c))
+            .map(str::to_string)
+            .collect(),
+    };
+    let mut out: Vec<String> = Vec::new();
+    for candidate in candidates {
+        let addr = candidate
+            .trim_matches(|c: char| c.is_whitespace() || ",;<>\"'".contains(c))
+            .to_ascii_lowercase();
+        let valid = addr
+            .split_once('@')
+            .is_some_and(|(local, domain)| !local.is_empty() && !domain.is_empty());
+        if valid && !out.contains(&addr) {
+            out.push(addr);
+        }
+    }
+    out
+}
+
+fn is_attachment_part(part: &ParsedMail) -> bool {
+    if !part.subparts.is_empty() {
+        return false;
+    }
+    let ctype = part.ctype.mimetype.to_ascii_lowercase();
+    if ctype.starts_with("text/plain") || ctype.starts_with("text/html") {
+        return false;
+    }
+    // Treat non-text leaf parts with either a disposition or filename as attachment-like.
+    let cd = header_first(part, "Content-Disposition").unwrap_or_default().to_ascii_lowercase();
+    let has_filename = parse_filename_from_headers(part).is_some();
+    if cd.starts_with("attachment") {
+        return true;
+    }
+    if cd.starts_with("inline") && has_filename {
+        return true;
+    }
+    // No explicit disposition, but has a name/filename and isn't text => likely an attachment.
+    has_filename
+}
+
+fn is_embedded_message(part: &ParsedMail) -> bool {
+    let ctype = part.ctype.mimetype.to_ascii_lowercase();
+    part.subparts.is_empty() && (ctype == "message/rfc822" || ctype == "message/global")
+}
+
+/// Attachment-like leaf parts. The signature part of a `multipart/signed` wrapper (RFC 1847:
+/// signed content first, then the signature) goes to `signatures` rather than `out`.
+fn collect_attachment_parts<'a>(
+    mail: &'a ParsedMail<'a>,
+    out: &mut Vec<&'a ParsedMail<'a>>,
+    signatures: &mut Vec<&'a ParsedMail<'a>>,
+) {
+    if mail.subparts.is_empty() {
+        if is_attachment_part(mail) || is_embedded_message(mail) {
+            out.push(mail);
+        }
+        return;
+    }
+    if mail.ctype.mimetype.eq_ignore_ascii_case("multipart/signed") && mail.subparts.len() >= 2 {
+        collect_attachment_parts(&mail.subparts[0], out, signatures);
+        signatures.extend(mail.subparts[1..].iter().filter(|p| p.subparts.is_empty()));
+        return;
+    }
+    for part in &mail.subparts {
+        collect_attachment_parts(part, out, signatures);
+    }
+}
+
+/// Fields of the first `message/delivery-status` part, for bounce messages. Embedded messages
+/// are leaves here, so a returned bounce inside a forward isn't picked up for the outer email.
+fn find_delivery_status(mail: &ParsedMail) -> Option<dsn::DeliveryStatus> {
+    if mail.subparts.is_empty() {
+        if !dsn::is_delivery_status_type(&mail.ctype.mimetype) {
+            return None;
+        }
+        return dsn::parse(&mail.get_body().ok()?);
+    }
+    mail.subparts.iter().find_map(find_delivery_status)
+}
+
+/// The first `text/calendar` part with an event, whether sent as the invite body alternative or
+/// as an `.ics` attachment.
+fn find_invite(mail: &ParsedMail) -> Option<meetings::Invite> {
+    if mail.subparts.is_empty() {
+        if !mail.ctype.mimetype.eq_ignore_ascii_case("text/calendar") {
+            return None;
+        }
+        return meetings::Invite::parse(&mail.get_body_raw().ok()?);
+    }
+    mail.subparts.iter().find_map(find_invite)
+}
+
+/// Detached signature from a `multipart/signed` wrapper.
+struct SignaturePart {
+    /// Signature content type, e.g. `application/pkcs7-signature` or `application/pgp-signature`.
+    protocol: String,
+    content: Vec<u8>,
+}
+
+impl SignaturePart {
+    fn extension(&self) -> &'static str {
+        if self.protocol.contains("pgp") {
+            "asc"
+        } else {
+            "p7s"
+        }
+    }
+}
+
+/// Owned result of parsing one RFC822 message. Produced off the async runtime so a per-message
+/// timeout can be enforced around it.
+struct ParsedMessage {
+    message_id: Option<String>,
+    message_id_normalized: String,
+    message_id_synthetic: bool,
+    in_reply_to: Option<String>,
+    references: Option<String>,
+    subject: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    cc: Option<String>,
+    bcc: Option<String>,
+    to_emails: Vec<String>,
+    cc_emails: Vec<String>,
+    bcc_emails: Vec<String>,
+    date: Option<String>,
+    date_epoch: Option<i64>,
+    date_parser: Option<DateParser>,
+    date_source: Option<dates::DateSource>,
+    received: Vec<String>,
+    truncated_mime: bool,
+    /// The subject or a body part needed a sniffed charset.
+    charset_issues: bool,
+    body_text: Option<String>,
+    body_html: Option<String>,
+    sender_email: Option<String>,
+    sender_name: Option<String>,
+    originating_ip: Option<String>,
+    mail_client: Option<String>,
+    processing_flags: Vec<ProcessingFlag>,
+    attachments: Vec<ParsedAttachment>,
+    /// Attached message/rfc822 parts, parsed recursively, with their MIME part index.
+    embedded: Vec<(usize, ParsedMessage)>,
+    duplicate_header_names: Vec<String>,
+    conflicting_header_names: Vec<String>,
+    signature: Option<SignaturePart>,
+    delivery_status: Option<dsn::DeliveryStatus>,
+    invite: Option<meetings::Invite>,
+    /// Attachment-like parts that were dropped: (part index, reason).
+    skipped_parts: Vec<(usize, &'static str)>,
+    /// Envelope of the journal report this message was unwrapped from.
+    journal: Option<journal::Envelope>,
+    importance: Option<priority::Importance>,
+    sensitivity: Option<priority::Sensitivity>,
+    read_receipt_requested: bool,
+    delivery_receipt_requested: bool,
+    receipt_type: Option<priority::ReceiptType>,
+    /// Every header (name, decoded value), for --retain-headers.
+    headers: Vec<(String, String)>,
+}
+
+/// Nesting limit for embedded messages; deeper ones stay opaque .eml attachments.
+const MAX_EMBEDDED_DEPTH: usize = 8;
+
+struct ParsedAttachment {
+    part_idx: usize,
+    content: Vec<u8>,
+    filename: String,
+    content_type: Option<String>,
+    is_inline: bool,
+    content_id: Option<String>,
+    is_encrypted_attachment: bool,
+    source_container: Option<String>,
+    charset_issues: bool,
+}
+
+/// Where an attachment unpacked by --expand-archives came from.
+struct ArchiveParent {
+    attachment_id: String,
+    /// Path inside the archive.
+    path: String,
+    /// Archive nesting level (1 for a member of a top-level archive).
+    depth: usize,
+}
+
+/// Parse headers, bodies and attachment parts. Returns None if mailparse rejects the message.
+fn parse_message(bytes: &[u8]) -> Option<ParsedMessage> {
+    parse_message_at(bytes, 0)
+}
+
+fn parse_message_at(bytes: &[u8], depth: usize) -> Option<ParsedMessage> {
+    // A multipart message cut off before its closing boundary is repaired and parsed again so
+    // complete parts survive and the half-written trailing part is not taken as whole.
+    let (repaired, first_parse) = match mailparse::parse_mail(bytes) {
+        Ok(mail) => (
+            mime_recovery::repair(bytes, &mime_recovery::tree_boundaries(&mail)),
+            Some(mail),
+        ),
+        Err(_) => (
+            mime_recovery::repair(bytes, &mime_recovery::scan_boundaries(bytes)),
+            None,
+        ),
+    };
+    let truncated_mime = repaired.is_some();
+    let mail = match &repaired {
+        Some(fixed) => mailparse::parse_mail(fixed).ok()?,
+        None => first_parse?,
+    };
+
+    let header_audit = audit_headers(&mail.headers);
+    let from = header_first(&mail, "From");
+    let date = header_first(&mail, "Date");
+    let received = header_all(&mail, "Received");
+    let (date_epoch, date_parser, date_source) =
+        match dates::message_date(date.as_deref(), &received, dates::unix_now()) {
+            Some((epoch, parser, source)) => (Some(epoch), Some(parser), Some(source)),
+            None => (None, None, None),
+        };
+    let (sender_email, sender_name) = from
+        .as_deref()
+        .map(parse_sender)
+        .unwrap_or((None, None));
+
+    let mut processing_flags = Vec::new();
+    let (body_text, body_html) = select_email_bodies(&mail, &mut processing_flags);
+
+    let mut parts: Vec<&ParsedMail> = Vec::new();
+    let mut signature_parts: Vec<&ParsedMail> = Vec::new();
+    collect_attachment_parts(&mail, &mut parts, &mut signature_parts);
+    let signature = signature_parts.into_iter().find_map(|part| {
+        let content = part.get_body_raw().ok().filter(|c| !c.is_empty())?;
+        Some(SignaturePart {
+            protocol: part.ctype.mimetype.to_ascii_lowercase(),
+            content,
+        })
+    });
+    let mut attachments: Vec<ParsedAttachment> = Vec::new();
+    let mut embedded = Vec::new();
+    let mut skipped_parts = Vec::new();
+    for (part_idx, part) in parts.into_iter().enumerate() {
+        let content = match part.get_body_raw() {
+            Ok(v) => v,
+            Err(_) => {
+                skipped_parts.push((part_idx, "attachment part could not be decoded"));
+                continue;
+            }
+        };
+        if content.is_empty() {
+            skipped_parts.push((part_idx, "empty attachment part"));
+            continue;
+        }
+        let is_message = is_embedded_message(part);
+        if is_message && depth < MAX_EMBEDDED_DEPTH {
+            if let Some(child) = parse_message_at(&content, depth + 1) {
+                embedded.push((part_idx, child));
+                continue;
+            }
+        }
+        let fallback_ext = if is_message { "eml" } else { "bin" };
+        let filename_raw = parse_filename_from_headers(part)
+            .unwrap_or_else(|| format!("attachment-{:03}.{}", part_idx, fallback_ext));
+        let cd = header_first(part, "Content-Disposition")
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let content_id = header_first(part, "Content-ID");
+        let charset_issues = filename_raw.contains(char::REPLACEMENT_CHARACTER)
+            || header_charset_issue(part, "Content-Disposition")
+            || header_charset_issue(part, "Content-Type");
+        attachments.push(ParsedAttachment {
+            part_idx,
+            filename: sanitize_filename(&filename_raw, "attachment.bin"),
+            content_type: Some(part.ctype.mimetype.clone()).filter(|v| !v.is_empty()),
+            is_inline: cd.starts_with("inline") || content_id.is_some(),
+            content_id,
+            is_encrypted_attachment: is_encrypted_content(&content),
+            source_container: None,
+            charset_issues,
+            content,
+        });
+    }
+    let (body_text, body_html) =
+        expand_tnef_attachments(&mut attachments, body_text, body_html, &mut processing_flags);
+
+    let delivery_status = find_delivery_status(&mail);
+    let message_id = header_first(&mail, "Message-ID");
+    let subject = header_first(&mail, "Subject");
+    let charset_issues = header_charset_issue(&mail, "Subject")
+        || processing_flags.contains(&ProcessingFlag::CharsetGuessed);
+    let (message_id_normalized, message_id_synthetic) =
+        match message_id.as_deref().and_then(msgid::normalize) {
+            Some(id) => (id, false),
+            None => {
+                let body = match (&body_text, &body_html) {
+                    (Some(text), _) => text.clone(),
+                    (None, Some(html)) => html_to_text_rough(html),
+                    (None, None) => String::new(),
+                };
+                let sender = sender_email.as_deref();
+                let id = msgid::synthetic(sender, date_epoch, subject.as_deref(), &body);
+                (id, true)
+            }
+        };
+    let message = ParsedMessage {
+        message_id,
+        message_id_normalized,
+        message_id_synthetic,
+        in_reply_to: header_first(&mail, "In-Reply-To"),
+        references: header_first(&mail, "References"),
+        subject,
+        to: header_first(&mail, "To"),
+        cc: header_first(&mail, "Cc"),
+        bcc: header_first(&mail, "Bcc"),
+        to_emails: header_addresses(&mail, "To"),
+        cc_emails: header_addresses(&mail, "Cc"),
+        bcc_emails: header_addresses(&mail, "Bcc"),
+        received,
+        originating_ip: extract_originating_ip(&mail),
+        mail_client: header_first_of(&mail, MAIL_CLIENT_HEADERS),
+        from,
+        date,
+        date_epoch,
+        date_parser,
+        date_source,
+        truncated_mime,
+        charset_issues,
+        body_text,
+        body_html,
+        sender_email,
+        sender_name,
+        processing_flags,
+        attachments,
+        embedded,
+        duplicate_header_names: header_audit.duplicate_header_names,
+        conflicting_header_names: header_audit.conflicting_header_names,
+        signature,
+        importance: priority::importance(&mail),
+        sensitivity: priority::sensitivity(&mail),
+        read_receipt_requested: priority::read_receipt_requested(&mail),
+        delivery_receipt_requested: priority::delivery_receipt_requested(&mail),
+        receipt_type: priority::receipt_type(&mail, delivery_status.as_ref()),
+        headers: mail
+            .headers
+            .iter()
+            .map(|h| (h.get_key(), encoded_words::decode_header(h.get_value_raw())))
+            .collect(),
+        delivery_status,
+        invite: find_invite(&mail),
+        skipped_parts,
+        journal: None,
+    };
+    Some(if journal::is_report(&mail.headers) {
+        unwrap_journal(message)
+    } else {
+        message
+    })
+}
+
+/// The first value of each `names` header present, keyed by the name as configured.
+fn retained_headers(
+    headers: &[(String, String)],
+    names: &[String],
+) -> std::collections::BTreeMap<String, String> {
+    names
+        .iter()
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let (_, value) = headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name))?;
+            Some((name.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// `source_container` of attachments carried over from an unwrapped journal report.
+const JOURNAL_REPORT_CONTAINER: &str = "journal report";
+
+/// A journal report's original message, carrying the report's envelope. Anything else attached
+/// to the report (files, other messages) is carried over to the original, numbered after its own
+/// parts. Reports without a readable envelope or an attached original are kept as they are.
+fn unwrap_journal(mut report: ParsedMessage) -> ParsedMessage {
+    let Some(envelope) = report.body_text.as_deref().and_then(journal::parse_envelope) else {
+        return report;
+    };
+    // The original named by the envelope, else the first attached message.
+    let named = report.embedded.iter().position(|(_, m)| {
+        envelope.message_id.is_some() && m.message_id == envelope.message_id
+    });
+    let Some(at) = named.or((!report.embedded.is_empty()).then_some(0)) else {
+        return report;
+    };
+    let (_, mut original) = report.embedded.remove(at);
+    original.truncated_mime |= report.truncated_mime;
+    original.journal = Some(envelope);
+    let base = original
+        .attachments
+        .iter()
+        .map(|a| a.part_idx)
+        .chain(original.embedded.iter().map(|(idx, _)| *idx))
+        .chain(original.skipped_parts.iter().map(|(idx, _)| *idx))
+        .max()
+        .map_or(0, |idx| idx + 1);
+    for mut att in report.attachments {
+        att.part_idx += base;
+        att.source_container
+            .get_or_insert_with(|| JOURNAL_REPORT_CONTAINER.to_string());
+        original.attachments.push(att);
+    }
+    original.embedded.extend(
+        report
+            .embedded
+            .into_iter()
+            .map(|(idx, message)| (idx + base, message)),
+    );
+    original
+}
+
+fn message_dedupe_hash(msg: &ParsedMessage) -> String {
+    let body = match (&msg.body_text, &msg.body_html) {
+        (Some(text), _) => text.clone(),
+        (None, Some(html)) => html_to_text_rough(html),
+        (None, None) => String::new(),
+    };
+    dedupe::message_hash(&dedupe::HashInput {
+        message_id: msg.message_id.as_deref(),
+        body: &body,
+        attachment_hashes: msg.attachments.iter().map(|a| sha256_bytes(&a.content)).collect(),
+        sender_email: msg.sender_email.as_deref(),
+        date_epoch: msg.date_epoch,
+        subject: msg.subject.as_deref(),
+    })
+}
+
+/// Per-email fields only known once every record has been written.
+struct RecordUpdates<'a> {
+    thread_of: &'a std::collections::HashMap<String, (String, usize)>,
+    priority_of: &'a std::collections::HashMap<String, u8>,
+    cid_targets: &'a std::collections::HashMap<String, cid::Targets>,
+    near_dupe_of: &'a std::collections::HashMap<String, String>,
+}
+
+/// Copy email records from `src` to `dst`, filling in thread_id / thread_position, the review
+/// priority, body_html_rewritten and near_dupe_cluster_id.
+fn write_threaded_records(
+    src: &Path,
+    dst: &Path,
+    updates: &RecordUpdates,
+    member_bytes: u64,
+    compression: OutputCompression,
+) -> Result<MemberIndex> {
+    let reader = BufReader::new(flate2::read::GzDecoder::new(File::open(src)?));
+    let mut out = MemberWriter::new(File::create(dst)?, member_bytes, compression);
+    for line in reader.lines() {
+        let mut record: EmailRecord =
+            serde_json::from_str(&line?).context("re-read email record for threading")?;
+        if let Some((thread_id, position)) = updates.thread_of.get(&record.id) {
+            record.thread_id = Some(thread_id.clone());
+            record.thread_position = Some(*position);
+        }
+        record.review_priority = updates.priority_of.get(&record.id).copied();
+        let targets = updates.cid_targets.get(&record.id);
+        if let (Some(html), Some(targets)) = (&record.body_html, targets) {
+            record.body_html_rewritten = cid::rewrite(html, targets);
+        }
+        record.near_dupe_cluster_id = updates.near_dupe_of.get(&record.id).cloned();
+        api::emit_email(&record);
+        writeln!(out, "{}", serde_json::to_string(&record)?)?;
+        out.end_record()?;
+    }
+    Ok(out.finish()?)
+}
+
+fn is_tnef_attachment(att: &ParsedAttachment) -> bool {
+    let declared = att
+        .content_type
+        .as_deref()
+        .is_some_and(|ct| ct.eq_ignore_ascii_case("application/ms-tnef"))
+        || att.filename.eq_ignore_ascii_case("winmail.dat");
+    declared && tnef::is_tnef(&att.content)
+}
+
+/// Replace winmail.dat parts with the attachments they wrap. A TNEF body fills in whichever of
+/// body_text / body_html the MIME structure lacked, and the compressed RTF body is surfaced as an
+/// `rtf-body.rtf` attachment (the name readpst uses for RTF bodies).
+fn expand_tnef_attachments(
+    attachments: &mut Vec<ParsedAttachment>,
+    mut body_text: Option<String>,
+    mut body_html: Option<String>,
+    flags: &mut Vec<ProcessingFlag>,
+) -> (Option<String>, Option<String>) {
+    if !attachments.iter().any(is_tnef_attachment) {
+        return (body_text, body_html);
+    }
+    // Unpacked items get part indexes after the MIME parts so attachment ids stay unique.
+    let mut next_idx = attachments.iter().map(|a| a.part_idx + 1).max().unwrap_or(0);
+    let mut expanded = Vec::with_capacity(attachments.len());
+    for att in attachments.drain(..) {
+        let decoded = if is_tnef_attachment(&att) {
+            tnef::parse(&att.content)
+        } else {
+            None
+        };
+        let Some(decoded) = decoded else {
+            expanded.push(att);
+            continue;
+        };
+        push_flag(flags, ProcessingFlag::TnefDecoded);
+        let container = Some(att.filename.clone());
+
+        let rtf_body = decoded
+            .rtf_compressed
+            .as_deref()
+            .and_then(rtf::decompress)
+            .filter(|rtf| !rtf.is_empty());
+        let inner = decoded.attachments.into_iter().map(|inner| {
+            let content_type = inner
+                .mime_type
+                .or_else(|| Some("application/octet-stream".to_string()));
+            (inner.filename, content_type, inner.content_id, inner.data)
+        });
+        let rtf = rtf_body.map(|rtf| {
+            (
+                Some("rtf-body.rtf".to_string()),
+                Some("application/rtf".to_string()),
+                None,
+                rtf,
+            )
+        });
+        for (filename, content_type, content_id, content) in inner.chain(rtf) {
+            let part_idx = next_idx;
+            next_idx += 1;
+            let fallback = format!("attachment-{:03}.bin", part_idx);
+            expanded.push(ParsedAttachment {
+                part_idx,
+                filename: sanitize_filename(filename.as_deref().unwrap_or(&fallback), "attachment.bin"),
+                content_type,
+                is_inline: content_id.is_some(),
+                content_id,
+                is_encrypted_attachment: is_encrypted_content(&content),
+                source_container: container.clone(),
+                charset_issues: false,
+                content,
+            });
+        }
+
+        if body_text.is_none() {
+            body_text = decoded.body_text;
+        }
+        if body_html.is_none() {
+            body_html = decoded.body_html;
+        }
+        if let Some(compressed) = &decoded.rtf_compressed {
+            fill_bodies_from_rtf(compressed, &mut body_text, &mut body_html, flags);
+        }
+    }
+    *attachments = expanded;
+    (body_text, body_html)
+}
+
+const OLE2_MAGIC: &[u8] = &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+fn utf16le(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+}
+
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    !needle.is_empty() && memmem::find(haystack, needle).is_some()
+}
+
+fn is_encrypted_content(content: &[u8]) -> bool {
+    // Password-protected ZIP (also covers legacy-encrypted OOXML saved as zip): any local
+    // file header with general-purpose flag bit 0 set.
+    if content.starts_with(b"PK\x03\x04") {
+        for hdr in memmem::find_iter(content, b"PK\x03\x04") {
+            if hdr + 8 > content.len() {
+                break;
+            }
+            let flags = u16::from_le_bytes([content[hdr + 6], content[hdr + 7]]);
+            if flags & 0x0001 != 0 {
+                return true;
+            }
+        }
+        return false;
+    }
+    // PDF with an /Encrypt dictionary in its trailer.
+    if content.starts_with(b"%PDF") {
+        return contains_bytes(content, b"/Encrypt");
+    }
+    // Encrypted Office documents are wrapped in an OLE2 container holding
+    // "EncryptionInfo"/"EncryptedPackage" streams (directory names are UTF-16LE).
+    if content.starts_with(OLE2_MAGIC) {
+        return contains_bytes(content, &utf16le("EncryptedPackage"))
+            || contains_bytes(content, &utf16le("EncryptionInfo"));
+    }
+    false
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Optional HTTP metadata stored with an uploaded object.
+#[derive(Clone, Default)]
+struct ObjectMeta {
+    content_type: Option<&'static str>,
+    content_encoding: Option<&'static str>,
+}
+
+/// Upload a local file; returns its size in bytes.
+async fn upload_file(s3: &aws_sdk_s3::Client, bucket: &str, key: &str, path: &Path) -> Result<u64> {
+    upload_file_with_meta(s3, bucket, key, path, &ObjectMeta::default()).await
+}
+
+async fn upload_file_with_meta(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    meta: &ObjectMeta,
+) -> Result<u64> {
+    upload::put_file(s3, bucket, key, path, meta).await
+}
+
+fn brotli_compress(data: &[u8], quality: u32) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() / 4);
+    {
+        let mut writer = brotli::CompressorWriter::new(&mut out, 64 * 1024, quality.min(11), 22);
+        writer.write_all(data)?;
+    }
+    Ok(out)
+}
+
+/// Full-jitter exponential backoff before retry `attempt` (1-based): up to 200 ms doubled per
+/// attempt, capped at 20 s.
+fn retry_backoff(attempt: u32) -> std::time::Duration {
+    let ceiling_ms = (200u64 << attempt.saturating_sub(1).min(16)).min(20_000);
+    let jitter = (Uuid::new_v4().as_u128() % 1_000_000) as u64;
+    std::time::Duration::from_millis(ceiling_ms * jitter / 1_000_000)
+}
+
+/// Local path of an input given as a local path or an object URI (`s3://bucket/key`,
+/// `az://account/container/key`, `gs://bucket/key`; downloaded to scratch).
+async fn fetch_input(s3: &aws_sdk_s3::Client, location: &str, scratch: &Path) -> Result<PathBuf> {
+    Ok(match storage::split_uri(location) {
+        Some(uri) => {
+            let (bucket, key) = uri?;
+            let name = sanitize_filename(key.rsplit('/').next().unwrap_or(&key), "input.txt");
+            let local = scratch.join(format!("input-{name}"));
+            storage::open(s3, &bucket)?.get(&key, &local, None, 0).await?;
+            local
+        }
+        None => PathBuf::from(location),
+    })
+}
+
+/// Read a small text input (terms lists, VIP lists, ...) from a local path or object URI.
+async fn read_text_input(s3: &aws_sdk_s3::Client, location: &str, scratch: &Path) -> Result<String> {
+    let path = fetch_input(s3, location, scratch).await?;
+    fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))
+}
+
+/// Identity columns shared by calendar, contact and task records.
+#[derive(Serialize)]
+struct PimRecord<'a, T: Serialize> {
+    id: String,
+    pst_file_id: &'a str,
+    project_id: Option<&'a str>,
+    case_id: Option<&'a str>,
+    custodian_id: Option<&'a str>,
+    custodian_name: Option<&'a str>,
+    source_path: &'a str,
+    #[serde(flatten)]
+    item: T,
+}
+
+/// Quotes a CSV field when it needs it, doubling embedded quotes (RFC 4180).
+fn csv_escape(value: &str) -> String {
+    let needs_quotes =
+        value.contains(',') || value.contains('"') || value.contains('\n') || value.contains('\r');
+    if !needs_quotes {
+        return value.to_string();
+    }
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/// One emails.csv row, in `schema::EMAIL_COLUMNS` order.
+fn write_email_csv_row(csv: &mut MemberWriter, args: &Args, record: &EmailRecord) -> Result<()> {
+    let text = |value: &Option<String>| csv_escape(value.as_deref().unwrap_or(""));
+    writeln!(
+        csv,
+        "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        csv_escape(&record.id),
+        csv_escape(&args.pst_file_id),
+        csv_escape(&args.project_id),
+        csv_escape(&args.case_id),
+        text(&record.message_id),
+        text(&record.in_reply_to),
+        text(&record.references),
+        text(&record.subject),
+        text(&record.from),
+        text(&record.to),
+        text(&record.cc),
+        text(&record.bcc),
+        text(&record.date),
+        csv_escape(&record.date_epoch.map(|v| v.to_string()).unwrap_or_default()),
+        text(&record.sender_email),
+        text(&record.sender_name),
+        text(&record.body_text),
+        text(&record.body_html),
+        csv_escape(&record.source_path),
+    )?;
+    csv.end_record()?;
+    Ok(())
+}
+
+/// One attachments.csv row, in `schema::ATTACHMENT_COLUMNS` order.
+fn write_attachment_csv_row(csv: &mut MemberWriter, record: &AttachmentRecord) -> Result<()> {
+    writeln!(
+        csv,
+        "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        csv_escape(&record.id),
+        csv_escape(&record.email_message_id),
+        csv_escape(&record.pst_file_id),
+        csv_escape(record.project_id.as_deref().unwrap_or("")),
+        csv_escape(record.case_id.as_deref().unwrap_or("")),
+        csv_escape(&record.filename),
+        csv_escape(record.content_type.as_deref().unwrap_or("")),
+        csv_escape(&record.file_size_bytes.to_string()),
+        csv_escape(&record.s3_bucket),
+        csv_escape(&record.s3_key),
+        csv_escape(&record.attachment_hash),
+        csv_escape(if record.is_inline { "true" } else { "false" }),
+        csv_escape(record.content_id.as_deref().unwrap_or("")),
+        csv_escape(&record.source_path),
+    )?;
+    csv.end_record()?;
+    Ok(())
+}
+
+/// Write one NDJSON line per item; returns how many were written.
+fn write_pim_records<T: Serialize>(
+    out: &mut Sidecar,
+    args: &Args,
+    rel_source: &str,
+    kind: &str,
+    items: Vec<T>,
+    filter: Option<&SourceFilter>,
+) -> Result<usize> {
+    let mut written = 0;
+    for (idx, item) in items.into_iter().enumerate() {
+        let id = stable_uuid(&format!(
+            "pst:{}|src:{}|{}:{}",
+            args.pst_file_id, rel_source, kind, idx
+        ))
+        .to_string();
+        if filter.is_some_and(|f| !f.matches(rel_source, &id)) {
+            continue;
+        }
+        let record = PimRecord {
+            id,
+            pst_file_id: &args.pst_file_id,
+            project_id: Some(args.project_id.as_str()).filter(|v| !v.is_empty()),
+            case_id: Some(args.case_id.as_str()).filter(|v| !v.is_empty()),
+            custodian_id: Some(args.custodian_id.as_str()).filter(|v| !v.is_empty()),
+            custodian_name: Some(args.custodian_name.as_str()).filter(|v| !v.is_empty()),
+            source_path: rel_source,
+            item,
+        };
+        out.write(&record)?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Selection for targeted re-extraction (`--only-source-paths`).
+struct SourceFilter {
+    // Normalized readpst-relative paths; each matches itself and anything beneath it.
+    paths: Vec<String>,
+    email_ids: std::collections::HashSet<String>,
+}
+
+impl SourceFilter {
+    fn parse(text: &str) -> Self {
+        let mut paths = Vec::new();
+        let mut email_ids = std::collections::HashSet::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Uuid::parse_str(line) {
+                Ok(id) => {
+                    email_ids.insert(id.to_string());
+                }
+                Err(_) => paths.push(normalize_rel_path(line)),
+            }
+        }
+        Self { paths, email_ids }
+    }
+
+    fn matches_path(&self, rel_source: &str) -> bool {
+        let rel = normalize_rel_path(rel_source);
+        self.paths.iter().any(|p| {
+            rel == *p || (rel.starts_with(p.as_str()) && rel[p.len()..].starts_with('/'))
+        })
+    }
+
+    /// Whether a source file may hold selected messages. Email IDs are only known after
+    /// parsing, so any file may qualify when IDs are listed.
+    fn may_contain(&self, rel_source: &str) -> bool {
+        !self.email_ids.is_empty() || self.matches_path(rel_source)
+    }
+
+    fn matches(&self, rel_source: &str, email_id: &str) -> bool {
+        self.email_ids.contains(email_id) || self.matches_path(rel_source)
+    }
+}
+
+fn normalize_rel_path(path: &str) -> String {
+    path.trim().replace('\\', "/").trim_matches('/').to_string()
+}
+
+/// Name for an attachment inside a family ZIP: `attachments/<name>`, with ` (2)`, ` (3)`, ...
+/// before the extension when the family already used it.
+fn family_entry_name(used: &mut HashSet<String>, file_name: &str) -> String {
+    let (stem, ext) = match file_name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (file_name, String::new()),
+    };
+    let mut candidate = file_name.to_string();
+    let mut n = 1;
+    while !used.insert(candidate.to_ascii_lowercase()) {
+        n += 1;
+        candidate = format!("{stem} ({n}){ext}");
+    }
+    format!("attachments/{candidate}")
+}
+
+/// Relative path for an archive entry or S3 key suffix, minus anything that would escape the
+/// extract directory. None if nothing is left.
+fn archive_rel_path(name: &str) -> Option<PathBuf> {
+    let rel: PathBuf = name
+        .split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != "." && *c != "..")
+        .collect();
+    Some(rel).filter(|r| !r.as_os_str().is_empty())
+}
+
+/// Unpack the message files of a ZIP (`--input-format eml-archive|maildir`) into `out_dir`,
+/// keeping the archive's folder layout as the source path. Returns how many were written.
+fn unpack_loose_zip(
+    input: &Path,
+    format: InputFormat,
+    out_dir: &Path,
+    max_message_bytes: usize,
+) -> Result<usize> {
+    let mut zip = ziparchive::ZipArchive::new(BufReader::new(File::open(input)?))
+        .with_context(|| format!("{} input is not a ZIP", format.name()))?;
+    let mut written = 0usize;
+    for idx in 0..zip.entries().len() {
+        let entry = zip.entries()[idx].clone();
+        if entry.is_dir() || !format.is_message_path(&entry.name) {
+            continue;
+        }
+        let Some(rel) = archive_rel_path(&entry.name) else {
+            continue;
+        };
+        if entry.size > max_message_bytes as u64 {
+            warn!(
+                source_path = %entry.name,
+                bytes = entry.size,
+                "skipping file: exceeds max_message_bytes"
+            );
+            continue;
+        }
+        let data = match zip.read(idx) {
+            Ok(data) => data,
+            Err(err) => {
+                warn!(source_path = %entry.name, "skipping file: {err:#}");
+                continue;
+            }
+        };
+        let dest = out_dir.join(rel);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        }
+        fs::write(&dest, data)?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Download every message object under a bucket prefix into `out_dir`, keyed by the path below
+/// the prefix. Returns how many were downloaded.
+async fn download_loose_prefix(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: &str,
+    format: InputFormat,
+    out_dir: &Path,
+    progress: &Progress,
+    min_free_bytes: u64,
+) -> Result<usize> {
+    let store = storage::open(s3, bucket)?;
+    let mut keys = Vec::new();
+    for key in store.list(prefix).await? {
+        let Some(rel) = key.strip_prefix(prefix) else {
+            continue;
+        };
+        if format.is_message_path(rel) {
+            if let Some(path) = archive_rel_path(rel) {
+                keys.push((key.clone(), out_dir.join(path)));
+            }
+        }
+    }
+    let count = keys.len();
+    let store = &store;
+    let results: Vec<Result<()>> = stream::iter(keys)
+        .map(|(key, dest)| async move {
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("create {}", parent.display()))?;
+            }
+            store.get(&key, &dest, Some(progress), min_free_bytes).await
+        })
+        .buffer_unordered(ATTACHMENT_UPLOAD_CONCURRENCY)
+        .collect()
+        .await;
+    for result in results {
+        result?;
+    }
+    Ok(count)
+}
+
+/// Copy every message file under a local directory into `out_dir`, keyed by the path below it.
+/// Returns how many were copied.
+fn copy_loose_dir(dir: &Path, format: InputFormat, out_dir: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in walkdir::WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("read {}", dir.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(dir)?.to_string_lossy().replace('\\', "/");
+        if !format.is_message_path(&rel) {
+            continue;
+        }
+        if let Some(path) = archive_rel_path(&rel) {
+            let dest = out_dir.join(path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("create {}", parent.display()))?;
+            }
+            fs::copy(entry.path(), &dest)
+                .with_context(|| format!("copy {}", entry.path().display()))?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Source input that isn't a PST: a single Outlook `.msg`, or a ZIP of them. Each message is
+/// converted to RFC822 in `out_dir` (named after the .msg) and readpst is skipped. Returns None
+/// for anything else, plus the number of .msg files that failed to convert.
+fn unpack_msg_input(
+    input: &Path,
+    source_key: &str,
+    out_dir: &Path,
+    max_message_bytes: usize,
+) -> Result<Option<(&'static str, usize)>> {
+    let mut head = [0u8; 8];
+    let n = File::open(input)?.read(&mut head)?;
+    let head = &head[..n];
+    if cfb::is_compound_file(head) {
+        let data = fs::read(input)?;
+        let Some(eml) = msg::to_rfc822(&data) else {
+            return Ok(None);
+        };
+        let name = source_key.rsplit('/').next().unwrap_or("message.msg");
+        let name = if name.is_empty() { "message.msg" } else { name };
+        fs::write(out_dir.join(name), eml)?;
+        return Ok(Some(("msg", 0)));
+    }
+    if !ziparchive::is_zip(head) {
+        return Ok(None);
+    }
+    let mut zip = ziparchive::ZipArchive::new(BufReader::new(File::open(input)?))?;
+    let mut failed = 0usize;
+    for idx in 0..zip.entries().len() {
+        let entry = zip.entries()[idx].clone();
+        if entry.is_dir() || !entry.name.to_ascii_lowercase().ends_with(".msg") {
+            continue;
+        }
+        // Keep the archive's folder layout as the source path.
+        let Some(rel) = archive_rel_path(&entry.name) else {
+            continue;
+        };
+        if entry.size > max_message_bytes as u64 {
+            warn!(
+                source_path = %entry.name,
+                bytes = entry.size,
+                "skipping file: exceeds max_message_bytes"
+            );
+            failed += 1;
+            continue;
+        }
+        match zip.read(idx).map(|data| msg::to_rfc822(&data)) {
+            Ok(Some(eml)) => {
+                let dest = out_dir.join(&rel);
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("create {}", parent.display()))?;
+                }
+                fs::write(&dest, eml)?;
+            }
+            Ok(None) => {
+                warn!(source_path = %entry.name, "skipping file: not an Outlook message");
+                failed += 1;
+            }
+            Err(err) => {
+                warn!(source_path = %entry.name, "skipping file: {err:#}");
+                failed += 1;
+            }
+        }
+    }
+    Ok(Some(("msg-zip", failed)))
+}
+
+/// When a running readpst is stopped early.
+#[derive(Clone, Copy, Default)]
+struct ReadpstLimits {
+    /// --max-duration: stop, keeping what was written.
+    deadline: Option<Instant>,
+    /// --min-free-bytes: stop and fail.
+    min_free_bytes: u64,
+}
+
+/// Folders extracted at once for `--extract-concurrency` (readpst's `-j`, and the parse pass's
+/// shards in flight): as given, or the CPU count when 0.
+fn extract_jobs(extract_concurrency: usize) -> usize {
+    if extract_concurrency > 0 {
+        return extract_concurrency;
+    }
+    // Determine optimal parallel job count based on available CPUs
+    let num_cpus = std::thread::available_parallelism()
+        .map(|p| p.get())
+        .unwrap_or(4);
+    num_cpus.min(8) // Cap at 8 to avoid memory pressure
+}
+
+/// A running readpst and its watchdog thread. Dropped without [`ReadpstRun::finish`] (an early
+/// return), it kills and reaps readpst and joins the watchdog, so neither outlives the call.
+struct ReadpstRun {
+    child: Arc<std::sync::Mutex<std::process::Child>>,
+    done: Option<std::sync::mpsc::Sender<()>>,
+    watchdog: Option<std::thread::JoinHandle<()>>,
+}
+
+impl ReadpstRun {
+    fn new(child: std::process::Child) -> Self {
+        Self {
+            child: Arc::new(std::sync::Mutex::new(child)),
+            done: None,
+            watchdog: None,
+        }
+    }
+
+    fn stop_watchdog(&mut self) {
+        self.done.take();
+        if let Some(watchdog) = self.watchdog.take() {
+            let _ = watchdog.join();
+        }
+    }
+
+    /// Wait for readpst to exit on its own (or the watchdog to kill it).
+    fn finish(mut self) -> Result<std::process::ExitStatus> {
+        self.stop_watchdog();
+        let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        child.wait().context("wait for readpst")
+    }
+}
+
+impl Drop for ReadpstRun {
+    fn drop(&mut self) {
+        self.stop_watchdog();
+        let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(child.try_wait(), Ok(None)) {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn run_readpst(
+    readpst_path: &str,
+    pst_path: &Path,
+    out_dir: &Path,
+    include_deleted: bool,
+    jobs: usize,
+    mut counts: Option<&mut itemcounts::CountCheck>,
+    limits: ReadpstLimits,
+) -> Result<Option<watchdog::Stop>> {
+    let jobs = jobs.to_string();
+    let readpst = platform::locate_readpst(readpst_path)?;
+    let mut cmd = Command::new(&readpst);
+    if include_deleted {
+        cmd.arg("-D"); // Also export soft-deleted items still present in the PST
+    }
+    let mut child = cmd
+        .args([
+            "-8", // Force UTF-8 output encoding for proper character handling
+            "-M", // Separate .eml files per message (better for parallel processing)
+            "-j", &jobs, // Parallel folder processing for faster extraction
+            "-o",
+            out_dir
+                .to_str()
+                .ok_or_else(|| anyhow!("invalid out_dir"))?,
+            pst_path
+                .to_str()
+                .ok_or_else(|| anyhow!("invalid pst_path"))?,
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("spawn {}", readpst.display()))?;
+    let stdout = child.stdout.take();
+    let mut run = ReadpstRun::new(child);
+    let child = Arc::clone(&run.child);
+    // Kill readpst at the --max-duration deadline or on SIGTERM (the files it already wrote are
+    // kept), or when scratch space runs low.
+    let stopped = Arc::new(std::sync::Mutex::new(None));
+    let low_space = Arc::new(std::sync::Mutex::new(None));
+    let (done, rx) = std::sync::mpsc::channel::<()>();
+    run.done = Some(done);
+    run.watchdog = Some({
+        let (child, stopped) = (Arc::clone(&child), Arc::clone(&stopped));
+        let low_space = Arc::clone(&low_space);
+        let out_dir = out_dir.to_path_buf();
+        let deadline = limits.deadline;
+        std::thread::spawn(move || loop {
+            if let Some(free) = diskspace::below_min(&out_dir, limits.min_free_bytes) {
+                *low_space.lock().expect("readpst low space") = Some(free);
+                let _ = child.lock().expect("readpst child").kill();
+                return;
+            }
+            let stop = if watchdog::terminating() {
+                Some(watchdog::Stop::Interrupted)
+            } else {
+                deadline
+                    .filter(|d| Instant::now() >= *d)
+                    .map(|_| watchdog::Stop::MaxDuration)
+            };
+            if let Some(stop) = stop {
+                *stopped.lock().expect("readpst stop") = Some(stop);
+                let _ = child.lock().expect("readpst child").kill();
+                return;
+            }
+            let poll = std::time::Duration::from_millis(250);
+            let wait = deadline.map_or(poll, |d| d.saturating_duration_since(Instant::now()));
+            match rx.recv_timeout(wait.min(poll)) {
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        })
+    });
+    // Log readpst's progress at debug level (stdout is reserved for JSON events), picking up its
+    // per-folder item counts on the way.
+    if let Some(stdout) = stdout {
+        for line in BufReader::new(stdout).split(b'\n') {
+            let line = line.context("read readpst output")?;
+            let line = String::from_utf8_lossy(&line);
+            tracing::debug!(target: "readpst", "{}", line.trim_end());
+            if let Some(counts) = counts.as_deref_mut() {
+                counts.record_line(&line);
+            }
+        }
+    }
+    let status = run.finish()?;
+    if let Some(free) = *low_space.lock().expect("readpst low space") {
+        return Err(anyhow!(
+            "readpst stopped: scratch space on {} fell to {free} bytes, below --min-free-bytes {}",
+            out_dir.display(),
+            limits.min_free_bytes
+        ));
+    }
+    if !status.success() {
+        if let Some(stop) = *stopped.lock().expect("readpst stop") {
+            warn!(reason = ?stop, "readpst stopped early; keeping partial output");
+            return Ok(Some(stop));
+        }
+        return Err(anyhow!("readpst failed with status {}", status));
+    }
+    Ok(None)
+}
+
+/// Folder (under the extract dir) for messages only the deleted-items pass produced.
+const RECOVERED_DIR: &str = "_recovered";
+
+/// `--recovery-mode` extraction. A failed readpst run keeps whatever it wrote before it stopped
+/// (a corrupt page mid-file no longer loses the whole mailbox). A second pass then runs with
+/// deleted items included, and messages it adds are copied under `_recovered/`. Returns whether
+/// the normal pass failed, how many files were recovered and why a pass was stopped early, if one
+/// was. Fails only if nothing came out.
+fn run_readpst_recovery(
+    readpst_path: &str,
+    pst_path: &Path,
+    extract_dir: &Path,
+    work_root: &Path,
+    jobs: usize,
+    counts: &mut itemcounts::CountCheck,
+    limits: ReadpstLimits,
+) -> Result<(bool, usize, Option<watchdog::Stop>)> {
+    let first = run_readpst(
+        readpst_path,
+        pst_path,
+        extract_dir,
+        false,
+        jobs,
+        Some(counts),
+        limits,
+    );
+    if first.is_err() && diskspace::below_min(extract_dir, limits.min_free_bytes).is_some() {
+        // Out of scratch space: a second pass can only make it worse.
+        return first.map(|_| (true, 0, None));
+    }
+    if let Err(err) = &first {
+        warn!("readpst failed ({err:#}); keeping partial output and retrying with deleted items");
+    }
+
+    let deleted_dir = work_root.join("extract-deleted");
+    fs::create_dir_all(&deleted_dir)
+        .with_context(|| format!("create {}", deleted_dir.display()))?;
+    let second = run_readpst(readpst_path, pst_path, &deleted_dir, true, jobs, None, limits);
+    if second.is_err() && diskspace::below_min(&deleted_dir, limits.min_free_bytes).is_some() {
+        fs::remove_dir_all(&deleted_dir).ok();
+        return second.map(|_| (true, 0, None));
+    }
+    if let Err(err) = &second {
+        warn!("readpst deleted-items pass failed ({err:#}); keeping partial output");
+    }
+    let stopped = [&first, &second]
+        .into_iter()
+        .find_map(|pass| pass.as_ref().ok().copied().flatten());
+
+    let mut seen = std::collections::HashSet::new();
+    for entry in WalkDir::new(extract_dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            seen.insert(sha256_file(entry.path())?);
+        }
+    }
+    let mut recovered = 0usize;
+    for entry in WalkDir::new(&deleted_dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() || !seen.insert(sha256_file(entry.path())?) {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(&deleted_dir).unwrap_or(entry.path());
+        let dest = extract_dir.join(RECOVERED_DIR).join(rel);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        }
+        fs::rename(entry.path(), &dest)
+            .or_else(|_| fs::copy(entry.path(), &dest).map(|_| ()))
+            .with_context(|| format!("move {}", entry.path().display()))?;
+        recovered += 1;
+    }
+    fs::remove_dir_all(&deleted_dir).ok();
+
+    let failed = first.is_err();
+    if seen.is_empty() {
+        // Neither pass produced anything; report the original failure.
+        first?;
+    }
+    Ok((failed, recovered, stopped))
+}
+
+/// The `pst-extractor` command line: parse arguments, then run the tool, worker, batch, server
+/// or job.
+pub async fn run_cli() -> Result<()> {
+    let args = Args::parse_from(config::expand(std::env::args_os().collect(), &Args::command())?);
+    logging::init(args.log_format);
+    watchdog::install_sigterm_handler();
+    upload::configure(args.upload_part_size, args.upload_concurrency);
+    if let Some(endpoint) = &args.otlp_endpoint {
+        otel::init(endpoint, &args.otel_service_name);
+    }
+    let (cfg, s3) = clients(&args).await;
+
+    if let Some(Tool::MergeManifests {
+        manifests,
+        output,
+        case_id,
+    }) = &args.command
+    {
+        let merge = merge_manifests(&s3, manifests, output, case_id, Path::new(&args.work_dir));
+        return upload::with_target(output_target(&args)?, merge).await;
+    }
+    if let Some(Tool::Inspect { source }) = &args.command {
+        let report = inspect_source(&s3, source, Path::new(&args.work_dir), args.min_free_bytes);
+        println!("{}", serde_json::to_string_pretty(&report.await?)?);
+        return Ok(());
+    }
+    if args.worker {
+        worker::run(&args, &cfg, &s3).await?;
+        if watchdog::terminating() {
+            std::process::exit(watchdog::EXIT_INTERRUPTED);
+        }
+        return Ok(());
+    }
+    let rules = load_rules(&args, &s3).await?;
+    if let Some(addr) = &args.serve {
+        server::run(addr, &args, cfg, s3, rules).await?;
+        if watchdog::terminating() {
+            std::process::exit(watchdog::EXIT_INTERRUPTED);
+        }
+        return Ok(());
+    }
+    if let Some(location) = &args.jobs_file {
+        let scratch = Path::new(&args.work_dir).join("jobs");
+        fs::create_dir_all(&scratch)?;
+        let jobs = batch::parse_jobs(&read_text_input(&s3, location, &scratch).await?)?;
+        let jobs = batch::plan(&args, &jobs).with_context(|| format!("jobs file {location}"))?;
+        let summary = batch::run(jobs, args.jobs_concurrency, &cfg, &s3, rules.as_ref());
+        let json = serde_json::to_vec_pretty(&summary)?;
+        match &args.batch_summary {
+            Some(output) => write_json_output(&s3, output, &json, &scratch).await?,
+            None => println!("{}", String::from_utf8_lossy(&json)),
+        }
+        fs::remove_dir_all(&scratch).ok();
+        if summary.interrupted() {
+            std::process::exit(watchdog::EXIT_INTERRUPTED);
+        }
+        if summary.failed > 0 {
+            return Err(anyhow!("{} of {} jobs failed", summary.failed, summary.jobs_total));
+        }
+        return Ok(());
+    }
+    let result = run_job(&args, &cfg, &s3, rules.as_ref()).await;
+    let error_class = result.as_ref().err().map(outcome::classify);
+    if let Some(output) = &args.result_file {
+        let summary = result.as_ref().ok();
+        let report = outcome::ResultFile {
+            status: summary.map_or("failed", |s| s.status),
+            exit_code: match (summary, error_class) {
+                (Some(s), _) if s.interrupted() => watchdog::EXIT_INTERRUPTED,
+                (_, Some(class)) => class.exit_code(),
+                _ => 0,
+            },
+            pst_file_id: args.pst_file_id.clone(),
+            output_bucket: args.output_bucket.clone(),
+            manifest_key: summary.map(|s| s.manifest_key.clone()),
+            emails_total: summary.map(|s| s.emails_total),
+            attachments_total: summary.map(|s| s.attachments_total),
+            duration_s: summary.map(|s| s.duration_s),
+            error_class,
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        };
+        let json = serde_json::to_vec_pretty(&report)?;
+        let scratch = Path::new(&args.work_dir);
+        fs::create_dir_all(scratch).ok();
+        // The exit code still reports the outcome if the file can't be written.
+        if let Err(e) = write_json_output(&s3, output, &json, scratch).await {
+            warn!(%output, "writing the result file failed: {e:#}");
+        }
+    }
+    match (result, error_class) {
+        (Ok(summary), _) if summary.interrupted() => {
+            std::process::exit(watchdog::EXIT_INTERRUPTED)
+        }
+        (Ok(_), _) => Ok(()),
+        (Err(e), Some(class)) if class != outcome::ErrorClass::Internal => {
+            error!(error = ?e, class = ?class, "job failed");
+            std::process::exit(class.exit_code())
+        }
+        (Err(e), _) => Err(e),
+    }
+}
+
+/// AWS config and the S3 client, honouring `--s3-endpoint-url` and the retry settings.
+async fn clients(args: &Args) -> (aws_config::SdkConfig, aws_sdk_s3::Client) {
+    // Fully local jobs never touch AWS, so an air-gapped machine doesn't wait on IMDS.
+    let cfg = if args.source_path.is_some() && args.output_dir.is_some() && !args.worker {
+        aws_config::SdkConfig::builder()
+            .behavior_version(aws_config::BehaviorVersion::latest())
+            .build()
+    } else {
+        info!("loading AWS config (if this hangs locally, set AWS_EC2_METADATA_DISABLED=true to skip IMDS)...");
+        aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await
+    };
+    let mut s3_config = aws_sdk_s3::config::Builder::from(&cfg);
+    if let Some(endpoint) = args.s3_endpoint_url.as_deref().filter(|e| !e.is_empty()) {
+        info!(%endpoint, "using S3 endpoint override");
+        s3_config = s3_config.endpoint_url(endpoint);
+    }
+    let s3 = aws_sdk_s3::Client::from_conf(
+        s3_config
+            .force_path_style(args.s3_force_path_style)
+            .retry_config(
+                aws_config::retry::RetryConfig::standard()
+                    .with_max_attempts(args.s3_max_attempts.max(1)),
+            )
+            .build(),
+    );
+    (cfg, s3)
+}
+
+/// The job's rules file (`--rules`), parsed.
+async fn load_rules(args: &Args, s3: &aws_sdk_s3::Client) -> Result<Option<rules::RuleSet>> {
+    let Some(location) = &args.rules else {
+        return Ok(None);
+    };
+    let scratch = Path::new(&args.work_dir).join("rules");
+    fs::create_dir_all(&scratch)?;
+    let text = read_text_input(s3, location, &scratch).await?;
+    Ok(Some(rules::RuleSet::parse(&text)?))
+}
+
+/// Write a small JSON document to a local path or an object URI (`s3://bucket/key`,
+/// `az://account/container/key`, `gs://bucket/key`).
+async fn write_json_output(
+    s3: &aws_sdk_s3::Client,
+    output: &str,
+    json: &[u8],
+    scratch: &Path,
+) -> Result<()> {
+    match storage::split_uri(output) {
+        Some(uri) => {
+            let (bucket, key) = uri?;
+            let path = scratch.join(format!("output-{}.json", Uuid::new_v4()));
+            fs::write(&path, json)?;
+            let meta = ObjectMeta {
+                content_type: Some("application/json"),
+                content_encoding: None,
+            };
+            let uploaded = upload_file_with_meta(s3, &bucket, &key, &path, &meta).await;
+            fs::remove_file(&path).ok();
+            uploaded?;
+        }
+        None => {
+            fs::write(output, json).with_context(|| format!("write {output}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// `merge-manifests`: read each manifest and its emails.ndjson.gz, write the case manifest.
+async fn merge_manifests(
+    s3: &aws_sdk_s3::Client,
+    manifests: &[String],
+    output: &str,
+    case_id: &str,
+    work_dir: &Path,
+) -> Result<()> {
+    let scratch = work_dir.join(format!("merge-{}", Uuid::new_v4()));
+    fs::create_dir_all(&scratch)?;
+    let mut case = merge::CaseManifest::default();
+    for (n, location) in manifests.iter().enumerate() {
+        let dir = scratch.join(n.to_string());
+        fs::create_dir_all(&dir)?;
+        let path = fetch_input(s3, location, &dir).await?;
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(&path)?)
+            .with_context(|| format!("parse manifest {location}"))?;
+        let store: Option<Arc<dyn storage::ObjectStore>> = match (
+            manifest["output_dir"].as_str(),
+            manifest["output_bucket"].as_str(),
+        ) {
+            (Some(root), _) => Some(Arc::new(storage::LocalStore { root: root.into() })),
+            (None, Some(bucket)) => Some(storage::open(s3, bucket)?),
+            (None, None) => None,
+        };
+        // A partitioned output lists its parts instead; parts concatenate into one stream.
+        let prefix = manifest["output_prefix"].as_str().unwrap_or_default();
+        let codec = serde_json::from_value(manifest["compression"].clone()).unwrap_or_default();
+        let compression = OutputCompression::new(codec, None)?;
+        let file = format!("emails.ndjson.{}", compression.extension());
+        let keys: Vec<String> = match manifest["output_parts"][&file].as_array() {
+            Some(parts) => parts
+                .iter()
+                .filter_map(|p| p["name"].as_str())
+                .map(|name| format!("{prefix}{name}"))
+                .collect(),
+            None => manifest["ndjson_gz_key"].as_str().map(String::from).into_iter().collect(),
+        };
+        let emails = match store {
+            Some(store) if !keys.is_empty() => {
+                let local = dir.join(&file);
+                let mut joined = File::create(&local)?;
+                for key in &keys {
+                    let part = dir.join("part");
+                    store.get(key, &part, None, 0).await?;
+                    std::io::copy(&mut File::open(&part)?, &mut joined)?;
+                }
+                Some(BufReader::new(compression.reader(File::open(&local)?)?))
+            }
+            _ => {
+                warn!(manifest = %location, "no emails.ndjson.gz key; counted without dedupe");
+                None
+            }
+        };
+        case.add(location, &manifest, emails)?;
+        info!(manifest = %location, "merged");
+    }
+    let merged = serde_json::to_vec_pretty(&case.finish(case_id))?;
+    write_json_output(s3, output, &merged, &scratch).await?;
+    fs::remove_dir_all(&scratch).ok();
+    info!(manifests = manifests.len(), %output, "case manifest written");
+    Ok(())
+}
+
+/// `inspect`: fetch the PST if it's remote, read its header and node B-tree, then remove the copy.
+async fn inspect_source<'a>(
+    s3: &aws_sdk_s3::Client,
+    source: &'a str,
+    work_dir: &Path,
+    min_free_bytes: u64,
+) -> Result<inspect::Report<'a>> {
+    let scratch = work_dir.join(format!("inspect-{}", Uuid::new_v4()));
+    let info = match storage::split_uri(source) {
+        Some(uri) => {
+            let (bucket, key) = uri?;
+            fs::create_dir_all(&scratch)?;
+            let local = scratch.join("source.pst");
+            let fetched = storage::open(s3, &bucket)?.get(&key, &local, None, min_free_bytes).await;
+            let info = fetched.and_then(|()| inspect::inspect_pst(&local));
+            fs::remove_dir_all(&scratch).ok();
+            info?
+        }
+        None => inspect::inspect_pst(Path::new(source))?,
+    };
+    let free = diskspace::free_bytes(work_dir);
+    let scratch = inspect::scratch_estimate(info.size_bytes, free, min_free_bytes);
+    Ok(inspect::Report { source, info, scratch })
+}
+
+/// Counts reported once a job has uploaded its manifest.
+struct JobSummary {
+    manifest_key: String,
+    emails_total: usize,
+    attachments_total: usize,
+    duration_s: f64,
+    // Manifest status.
+    status: &'static str,
+}
+
+impl JobSummary {
+    /// SIGTERM cut the job short (its partial outputs were still uploaded).
+    fn interrupted(&self) -> bool {
+        self.status == watchdog::Stop::Interrupted.status()
+    }
+}
+
+/// Run one job and report the outcome to `--callback-url`, if configured.
+async fn run_job(
+    args: &Args,
+    cfg: &aws_config::SdkConfig,
+    s3: &aws_sdk_s3::Client,
+    rules: Option<&rules::RuleSet>,
+) -> Result<JobSummary> {
+    let result = {
+        let _job = tracing::info_span!("job", pst_file_id = %args.pst_file_id).entered();
+        let result = match output_target(args) {
+            // Boxed: the extraction future is too large for a 2 MiB thread stack (library
+            // callers' runtimes, batch threads).
+            Ok(target) => {
+                upload::with_target(target, Box::pin(extract(args, cfg, s3, rules))).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Some(url) = &args.callback_url {
+            let non_empty = |v: &str| Some(v.to_string()).filter(|v| !v.is_empty());
+            let summary = result.as_ref().ok();
+            let payload = callback::CallbackPayload {
+                status: match summary {
+                    Some(s) if s.status != "complete" => s.status,
+                    Some(_) => "succeeded",
+                    None => "failed",
+                },
+                pst_file_id: args.pst_file_id.clone(),
+                project_id: non_empty(&args.project_id),
+                case_id: non_empty(&args.case_id),
+                output_bucket: args.output_bucket.clone(),
+                manifest_key: summary.map(|s| s.manifest_key.clone()),
+                emails_total: summary.map(|s| s.emails_total),
+                attachments_total: summary.map(|s| s.attachments_total),
+                duration_s: summary.map(|s| s.duration_s),
+                error: result.as_ref().err().map(|e| format!("{e:#}")),
+            };
+            // A failed callback is logged but doesn't change the job outcome.
+            if let Err(e) = callback::notify(url, args.callback_secret.as_deref(), &payload).await {
+                warn!(%url, "completion callback failed: {e:#}");
+            }
+        }
+        result
+    };
+    // After the job span has closed, so it is exported too.
+    otel::flush().await;
+    result
+}
+
+fn output_target(args: &Args) -> Result<upload::Target> {
+    Ok(upload::Target {
+        policy: upload::ObjectPolicy::new(
+            args.kms_key_id.as_deref(),
+            args.storage_class.as_deref(),
+            args.object_tags.as_deref(),
+        )?,
+        local_dir: args.output_dir.as_ref().map(PathBuf::from),
+    })
+}
+
+/// Extract one PST end to end: download, unpack (readpst), parse, then upload the outputs and
+/// manifest, each stage in a function of its own below.
+async fn extract(
+    args: &Args,
+    cfg: &aws_config::SdkConfig,
+    s3: &aws_sdk_s3::Client,
+    rules: Option<&rules::RuleSet>,
+) -> Result<JobSummary> {
+    let started = Instant::now();
+    let compression = OutputCompression::new(args.compression, args.compression_level)?;
+    let ext = compression.extension();
+
+    let progress = Arc::new(Progress::new(&args.pst_file_id));
+    progress::publish(&progress);
+    let progress_sinks = ProgressSinks {
+        sns: args
+            .progress_sns_topic_arn
+            .clone()
+            .map(|topic| (aws_sdk_sns::Client::new(cfg), topic)),
+        dynamodb: args
+            .progress_dynamodb_table
+            .clone()
+            .map(|table| (aws_sdk_dynamodb::Client::new(cfg), table)),
+    };
+    let _reporter = progress::spawn_reporter(
+        Arc::clone(&progress),
+        progress_sinks.clone(),
+        args.progress_interval_secs,
+    );
+
+    let mut phase = logging::PhaseSpan::default();
+    phase.set("download");
+    let source = match &args.source_path {
+        Some(path) => path.clone(),
+        None => storage::open(s3, &args.source_bucket)?.url(&args.source_key),
+    };
+    let output = match &args.output_dir {
+        Some(dir) => format!("{}/{}", dir.trim_end_matches('/'), args.output_prefix),
+        None => storage::open(s3, &args.output_bucket)?.url(&args.output_prefix),
+    };
+    info!(%source, %output, "pst-extractor starting");
+
+    if args.metrics_sink == Some(metrics::MetricsSink::Pushgateway)
+        && args.pushgateway_url.is_none()
+    {
+        return Err(anyhow!("--metrics-sink pushgateway requires --pushgateway-url"));
+    }
+    if args.aggregate_only && (args.opensearch_url.is_some() || args.dedupe_index.is_some()) {
+        return Err(anyhow!(
+            "--aggregate-only can't be combined with --opensearch-url or --dedupe-index"
+        ));
+    }
+    if !(0.5..=1.0).contains(&args.near_dupe_similarity) {
+        return Err(anyhow!("--near-dupe-similarity must be between 0.5 and 1.0"));
+    }
+    let deadline = watchdog::JobDeadline::new(started, args.max_duration);
+
+    let file_io = FileIo::new(args.io_backend)?;
+    if file_io.backend() != IoBackend::Std {
+        info!(backend = ?file_io.backend(), "local file I/O");
+    }
+    let custodian_id = Some(args.custodian_id.clone()).filter(|v| !v.is_empty());
+    let custodian_name = Some(args.custodian_name.clone()).filter(|v| !v.is_empty());
+
+    // Removed when the job returns, however it ends.
+    let work_guard = diskspace::WorkRoot::for_job(
+        Path::new(&args.work_dir),
+        &args.pst_file_id,
+        args.keep_workdir,
+    )?;
+    let work_root = work_guard.path();
+    let extract_dir = work_root.join("extract");
+    let out_dir = work_root.join("out");
+    fs::create_dir_all(&extract_dir)
+        .with_context(|| format!("create extract dir {}", extract_dir.display()))?;
+    fs::create_dir_all(&out_dir)
+        .with_context(|| format!("create out dir {}", out_dir.display()))?;
+
+    let source = fetch_source(args, s3, &progress, work_root, &extract_dir).await?;
+
+    progress.set_phase(Phase::Readpst);
+    phase.set("readpst");
+    let mut unpacked = unpack_source(args, &source, &extract_dir, work_root, &deadline)?;
+    let pst_index = (args.pst_index && unpacked.input_format == "pst")
+        .then(|| index_pst(&source.pst_path));
+
+    let job = Job {
+        args,
+        cfg,
+        s3,
+        rules,
+        started,
+        deadline,
+        compression,
+        ext,
+        prefix: args.output_prefix.trim_start_matches('/').to_string(),
+        progress,
+        progress_sinks,
+        file_io: Arc::new(file_io),
+        custodian_id,
+        custodian_name,
+        work_root,
+        extract_dir,
+        out_dir,
+    };
+    phase.set("parse");
+    let indexed = pst_index.as_ref().and_then(|index| index.as_ref().ok());
+    let parsed = parse_stage(&job, &mut unpacked, indexed).await?;
+    finish_job(&job, unpacked, pst_index, parsed, phase).await
+}
+
+/// A job's input on local disk.
+struct Source {
+    /// The PST, `.msg` or ZIP to unpack: downloaded to scratch, or a local source read in place.
+    pst_path: PathBuf,
+    source_name: String,
+    /// Loose messages from a directory or S3 prefix, already copied into the extract directory.
+    loose_prefix: bool,
+}
+
+/// Download stage: fetch the source into scratch, or loose messages into `extract_dir`.
+async fn fetch_source(
+    args: &Args,
+    s3: &aws_sdk_s3::Client,
+    progress: &Progress,
+    work_root: &Path,
+    extract_dir: &Path,
+) -> Result<Source> {
+    let local_source = args.source_path.as_ref().map(PathBuf::from);
+    // A local source is read in place rather than copied to scratch.
+    let pst_path = match &local_source {
+        Some(path) => path.clone(),
+        None => work_root.join("input.pst"),
+    };
+    let source_name = match &local_source {
+        Some(path) => path.file_name().map_or(String::new(), |n| n.to_string_lossy().into()),
+        None => args.source_key.clone(),
+    };
+    let loose_prefix = args.input_format != InputFormat::Pst
+        && match &local_source {
+            Some(path) => path.is_dir(),
+            None => args.source_key.ends_with('/'),
+        };
+    if loose_prefix && local_source.is_some() {
+        let count = copy_loose_dir(&pst_path, args.input_format, extract_dir)?;
+        info!(messages = count, dest = %extract_dir.display(), "copied loose messages");
+    } else if loose_prefix {
+        info!(
+            format = args.input_format.name(),
+            dest = %extract_dir.display(),
+            "downloading loose messages"
+        );
+        let count = download_loose_prefix(
+            s3,
+            &args.source_bucket,
+            &args.source_key,
+            args.input_format,
+            extract_dir,
+            progress,
+            args.min_free_bytes,
+        )
+        .await?;
+        info!(messages = count, "downloaded");
+    } else {
+        if local_source.is_some() {
+            if !pst_path.is_file() {
+                return Err(anyhow!("source path {} is not a file", pst_path.display()));
+            }
+        } else {
+            info!(dest = %pst_path.display(), "downloading PST");
+            storage::open(s3, &args.source_bucket)?
+                .get(&args.source_key, &pst_path, Some(progress), args.min_free_bytes)
+                .await?;
+        }
+        let pst_len = fs::metadata(&pst_path)?.len();
+        let expanded = pst_len.saturating_mul(diskspace::READPST_EXPANSION);
+        if diskspace::ensure_free(extract_dir, expanded, args.min_free_bytes, "readpst").is_err() {
+            warn!(
+                pst_bytes = pst_len,
+                free_bytes = diskspace::free_bytes(extract_dir),
+                "scratch space may run out during readpst"
+            );
+        }
+    }
+    Ok(Source {
+        pst_path,
+        source_name,
+        loose_prefix,
+    })
+}
+
+/// What the unpack stage left in the extract directory.
+struct Unpacked {
+    input_format: &'static str,
+    msg_failed_total: usize,
+    readpst_failed: bool,
+    readpst_stopped: Option<watchdog::Stop>,
+    count_check: itemcounts::CountCheck,
+}
+
+/// Unpack stage: readpst for a PST; `.msg` conversion or ZIP unpacking for other input.
+fn unpack_source(
+    args: &Args,
+    source: &Source,
+    extract_dir: &Path,
+    work_root: &Path,
+    deadline: &watchdog::JobDeadline,
+) -> Result<Unpacked> {
+    let Source {
+        pst_path,
+        source_name,
+        loose_prefix,
+    } = source;
+    let mut readpst_failed = false;
+    let mut readpst_stopped = None;
+    let readpst_limits = ReadpstLimits {
+        deadline: deadline.readpst_deadline(),
+        min_free_bytes: args.min_free_bytes,
+    };
+    let extract_jobs = extract_jobs(args.extract_concurrency);
+    let mut count_check = itemcounts::CountCheck::default();
+    let (input_format, msg_failed_total) = if args.input_format != InputFormat::Pst {
+        // Loose messages skip readpst and go straight to the parse pass.
+        if !loose_prefix {
+            let count = unpack_loose_zip(
+                pst_path,
+                args.input_format,
+                extract_dir,
+                args.max_message_bytes,
+            )?;
+            info!(messages = count, dest = %extract_dir.display(), "unpacked");
+        }
+        (args.input_format.name(), 0)
+    } else {
+        match unpack_msg_input(
+            pst_path,
+            source_name,
+            extract_dir,
+            args.max_message_bytes,
+        )? {
+            Some((format, failed)) => {
+                info!(format, dest = %extract_dir.display(), "converted input");
+                (format, failed)
+            }
+            None if args.recovery_mode => {
+                info!(
+                    dest = %extract_dir.display(),
+                    jobs = extract_jobs,
+                    "running readpst (recovery mode)"
+                );
+                let recovered;
+                (readpst_failed, recovered, readpst_stopped) = run_readpst_recovery(
+                    &args.readpst_path,
+                    pst_path,
+                    extract_dir,
+                    work_root,
+                    extract_jobs,
+                    &mut count_check,
+                    readpst_limits,
+                )?;
+                info!(recovered, "recovered deleted items");
+                ("pst", 0)
+            }
+            None => {
+                info!(dest = %extract_dir.display(), jobs = extract_jobs, "running readpst");
+                readpst_stopped = run_readpst(
+                    &args.readpst_path,
+                    pst_path,
+                    extract_dir,
+                    false,
+                    extract_jobs,
+                    Some(&mut count_check),
+                    readpst_limits,
+                )?;
+                ("pst", 0)
+            }
+        }
+    };
+    Ok(Unpacked {
+        input_format,
+        msg_failed_total,
+        readpst_failed,
+        readpst_stopped,
+        count_check,
+    })
+}
+
+/// Read the PST's node database for `--pst-index`; failing to is logged, not fatal.
+fn index_pst(pst_path: &Path) -> Result<pstindex::Index> {
+    let index = pstindex::read(pst_path);
+    match &index {
+        Ok(index) => info!(
+            messages = index.messages.len(),
+            encryption = index.encryption,
+            "indexed PST"
+        ),
+        Err(e) => warn!(error = %format!("{e:#}"), "couldn't index the PST"),
+    }
+    index
+}
+
+/// What the stages after the download share.
+struct Job<'a> {
+    args: &'a Args,
+    cfg: &'a aws_config::SdkConfig,
+    s3: &'a aws_sdk_s3::Client,
+    rules: Option<&'a rules::RuleSet>,
+    started: Instant,
+    deadline: watchdog::JobDeadline,
+    compression: OutputCompression,
+    ext: &'static str,
+    /// `--output-prefix` without a leading slash.
+    prefix: String,
+    progress: Arc<Progress>,
+    progress_sinks: ProgressSinks,
+    /// Shared with the parse pass's shard tasks.
+    file_io: Arc<FileIo>,
+    custodian_id: Option<String>,
+    custodian_name: Option<String>,
+    work_root: &'a Path,
+    extract_dir: PathBuf,
+    out_dir: PathBuf,
+}
+
+/// The core output files: emails / attachments NDJSON and CSV under the output directory.
+struct CoreFiles {
+    emails_ndjson: PathBuf,
+    emails_csv: PathBuf,
+    attachments_ndjson: PathBuf,
+    attachments_csv: PathBuf,
+}
+
+impl CoreFiles {
+    fn new(out_dir: &Path, ext: &str) -> Self {
+        Self {
+            emails_ndjson: out_dir.join(format!("emails.ndjson.{ext}")),
+            emails_csv: out_dir.join(format!("emails.csv.{ext}")),
+            attachments_ndjson: out_dir.join(format!("attachments.ndjson.{ext}")),
+            attachments_csv: out_dir.join(format!("attachments.csv.{ext}")),
+        }
+    }
+}
+
+/// A gzip'd NDJSON sidecar (errors, dead letters, calendar items, ...) and the number of records
+/// written to it.
+struct Sidecar {
+    name: &'static str,
+    path: PathBuf,
+    out: GzEncoder<File>,
+    total: usize,
+}
+
+impl Sidecar {
+    fn create(out_dir: &Path, name: &'static str) -> Result<Self> {
+        let path = out_dir.join(name);
+        let out = GzEncoder::new(File::create(&path)?, Compression::default());
+        Ok(Self {
+            name,
+            path,
+            out,
+            total: 0,
+        })
+    }
+
+    fn write(&mut self, record: &impl Serialize) -> Result<()> {
+        writeln!(self.out, "{}", serde_json::to_string(record)?)?;
+        self.total += 1;
+        Ok(())
+    }
+
+    /// Closes the file and lists it among the job's outputs if anything was written to it (or
+    /// `always`). Returns the record count.
+    fn finish(self, outputs: &mut Vec<(String, PathBuf)>, always: bool) -> Result<usize> {
+        self.out.finish()?;
+        if always || self.total > 0 {
+            outputs.push((self.name.to_string(), self.path));
+        }
+        Ok(self.total)
+    }
+}
+
+/// errors.ndjson.gz and the count of each kind of entry in it.
+struct ErrorLog {
+    file: Sidecar,
+    counts: ErrorCounts,
+}
+
+impl ErrorLog {
+    fn record(&mut self, entry: &ErrorEntry) -> Result<()> {
+        match entry.kind {
+            "file_skipped" => self.counts.files_skipped += 1,
+            "email_failed" => self.counts.emails_failed += 1,
+            "attachment_skipped" => self.counts.attachment_parts_skipped += 1,
+            "upload_failed" => self.counts.upload_failures_total += 1,
+            _ => {}
+        }
+        self.file.write(entry)
+    }
+
+    /// An object that could not be stored after the SDK's retries is reported, not fatal.
+    fn upload_failed(
+        &mut self,
+        source_path: &str,
+        message_index: Option<usize>,
+        email_id: Option<&str>,
+        key: &str,
+        err: &anyhow::Error,
+    ) -> Result<()> {
+        warn!(%key, "upload failed: {err:#}");
+        self.record(&ErrorEntry::upload(source_path, message_index, email_id, key, err))
+    }
+}
+
+/// The sidecar outputs the parse loop writes besides emails and attachments.
+struct Sidecars {
+    errors: ErrorLog,
+    dead_letter: Sidecar,
+    security_report: Sidecar,
+    duplicates: Sidecar,
+    calendar: Sidecar,
+    contacts: Sidecar,
+    tasks: Sidecar,
+    term_hits: Option<Sidecar>,
+    attachment_text: Option<Sidecar>,
+}
+
+/// How many records each sidecar got, for the manifest.
+struct SidecarTotals {
+    dead_letter: usize,
+    security_findings: usize,
+    duplicates_suppressed: usize,
+    calendar: usize,
+    contacts: usize,
+    tasks: usize,
+    attachment_text: usize,
+}
+
+impl Sidecars {
+    fn create(out_dir: &Path, args: &Args, terms: bool) -> Result<Self> {
+        let optional = |on: bool, name| on.then(|| Sidecar::create(out_dir, name)).transpose();
+        Ok(Self {
+            errors: ErrorLog {
+                file: Sidecar::create(out_dir, "errors.ndjson.gz")?,
+                counts: ErrorCounts::default(),
+            },
+            dead_letter: Sidecar::create(out_dir, "dead_letter.ndjson.gz")?,
+            security_report: Sidecar::create(out_dir, "security_report.ndjson.gz")?,
+            duplicates: Sidecar::create(out_dir, "duplicates.ndjson.gz")?,
+            calendar: Sidecar::create(out_dir, "calendar.ndjson.gz")?,
+            contacts: Sidecar::create(out_dir, "contacts.ndjson.gz")?,
+            tasks: Sidecar::create(out_dir, "tasks.ndjson.gz")?,
+            term_hits: optional(terms, "term_hits.ndjson.gz")?,
+            attachment_text: optional(args.attachment_text, "attachment_text.ndjson.gz")?,
+        })
+    }
+
+    /// Closes every sidecar. Empty ones aren't listed, except the opt-in term hits and
+    /// attachment text.
+    fn finish(
+        self,
+        outputs: &mut Vec<(String, PathBuf)>,
+    ) -> Result<(ErrorCounts, SidecarTotals)> {
+        self.errors.file.finish(outputs, false)?;
+        if let Some(term_hits) = self.term_hits {
+            term_hits.finish(outputs, true)?;
+        }
+        let attachment_text = match self.attachment_text {
+            Some(sidecar) => sidecar.finish(outputs, true)?,
+            None => 0,
+        };
+        let totals = SidecarTotals {
+            dead_letter: self.dead_letter.finish(outputs, false)?,
+            security_findings: self.security_report.finish(outputs, false)?,
+            duplicates_suppressed: self.duplicates.finish(outputs, false)?,
+            calendar: self.calendar.finish(outputs, false)?,
+            contacts: self.contacts.finish(outputs, false)?,
+            tasks: self.tasks.finish(outputs, false)?,
+            attachment_text,
+        };
+        Ok((self.errors.counts, totals))
+    }
+}
+
+/// --raw-blobs: the message blobs, and raw_index.ndjson.gz (email id -> blob, offset, length).
+struct RawStore {
+    blobs: RawBlobWriter,
+    index: Sidecar,
+}
+
+/// --anonymized-export: scrubbed copies of the messages, by folder, in anonymized.zip.
+struct AnonymizedExport {
+    anonymizer: anonymize::Anonymizer,
+    zip: ziparchive::ZipWriter<BufWriter<File>>,
+    path: PathBuf,
+    total: usize,
+}
+
+impl AnonymizedExport {
+    fn add(
+        &mut self,
+        source_path: &str,
+        email_id: &str,
+        msg_idx: usize,
+        bytes: &[u8],
+    ) -> Result<()> {
+        match self.anonymizer.message(bytes) {
+            Ok(scrubbed) => {
+                let folder = self.anonymizer.folder_path(source_path);
+                let name = if folder.is_empty() {
+                    format!("{email_id}.eml")
+                } else {
+                    format!("{folder}/{email_id}.eml")
+                };
+                self.zip.add(&name, &scrubbed)?;
+                self.total += 1;
+            }
+            Err(err) => warn!(message_index = msg_idx, "not anonymized: {err:#}"),
+        }
+        Ok(())
+    }
+}
+
+/// emails.ndjson / emails.csv as the parse loop writes them, their counts, and what the passes
+/// after the loop (threading, near-duplicates, cid rewriting) need from each record.
+struct EmailOutput {
+    /// Records are written here first and copied to emails.ndjson once threads are known.
+    unthreaded_path: PathBuf,
+    ndjson: GzEncoder<File>,
+    csv: MemberWriter,
+    counts: EmailCounts,
+    thread_inputs: Vec<ThreadInput>,
+    near_dupe_inputs: Vec<(String, u64)>,
+    /// --rewrite-cid: email id -> cid key -> replacement, applied in the threading rewrite.
+    cid_targets: std::collections::HashMap<String, cid::Targets>,
+}
+
+impl EmailOutput {
+    fn create(job: &Job<'_>, files: &CoreFiles) -> Result<Self> {
+        let unthreaded_path = job.out_dir.join("emails.unthreaded.ndjson.gz");
+        let ndjson = GzEncoder::new(File::create(&unthreaded_path)?, Compression::default());
+        let file = File::create(&files.emails_csv)?;
+        let mut csv = MemberWriter::new(file, job.args.gzip_member_bytes, job.compression);
+        // CSV header: keep this stable; loader COPY uses this ordering (checked via schema.json).
+        csv.write_header(&schema::csv_header(schema::EMAIL_COLUMNS))?;
+        Ok(Self {
+            unthreaded_path,
+            ndjson,
+            csv,
+            counts: EmailCounts::default(),
+            thread_inputs: Vec::new(),
+            near_dupe_inputs: Vec::new(),
+            cid_targets: Default::default(),
+        })
+    }
+
+    /// Writes one record's NDJSON line and CSV row and counts it.
+    fn write(&mut self, args: &Args, record: &EmailRecord) -> Result<()> {
+        writeln!(self.ndjson, "{}", serde_json::to_string(record)?)?;
+        write_email_csv_row(&mut self.csv, args, record)?;
+        self.counts.add(record);
+        // Embedded copies are part of their family, not of the conversation.
+        if record.depth == 0 {
+            self.thread_inputs.push(ThreadInput {
+                email_id: record.id.clone(),
+                // Real IDs as written, so they match References; else the synthetic.
+                message_id: record
+                    .message_id
+                    .clone()
+                    .or_else(|| record.message_id_normalized.clone()),
+                in_reply_to: record.in_reply_to.clone(),
+                references: record.references.clone(),
+                subject: record.subject.clone(),
+                date_epoch: record.date_epoch,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// attachments.ndjson / attachments.csv and their counts.
+struct AttachmentOutput {
+    ndjson: MemberWriter,
+    csv: MemberWriter,
+    counts: AttachmentCounts,
+    /// --attachment-store by-hash: bodies known to be stored (uploaded by this job or found by a
+    /// HEAD), so later copies skip the upload. A failed upload leaves its hash out.
+    stored_hashes: HashSet<String>,
+    by_hash_prefix: String,
+}
+
+impl AttachmentOutput {
+    fn create(job: &Job<'_>, files: &CoreFiles) -> Result<Self> {
+        let member_writer = |path: &Path| -> Result<MemberWriter> {
+            let file = File::create(path)?;
+            Ok(MemberWriter::new(file, job.args.gzip_member_bytes, job.compression))
+        };
+        let mut csv = member_writer(&files.attachments_csv)?;
+        csv.write_header(&schema::csv_header(schema::ATTACHMENT_COLUMNS))?;
+        Ok(Self {
+            ndjson: member_writer(&files.attachments_ndjson)?,
+            csv,
+            counts: AttachmentCounts::default(),
+            stored_hashes: HashSet::new(),
+            by_hash_prefix: format!("{}attachments/by-hash/", job.prefix),
+        })
+    }
+
+    /// Writes one record's NDJSON line and CSV row and counts it.
+    fn write(&mut self, record: &AttachmentRecord) -> Result<()> {
+        api::emit_attachment(record);
+        writeln!(self.ndjson, "{}", serde_json::to_string(record)?)?;
+        self.ndjson.end_record()?;
+        write_attachment_csv_row(&mut self.csv, record)?;
+        self.counts.add(record);
+        Ok(())
+    }
+
+    /// --attachment-store by-hash: whether the body at `key` is already stored, by an earlier
+    /// upload of this job, a copy earlier in this email (sharing its pending upload), or found by
+    /// a HEAD request. Counted as deduplicated when it is.
+    async fn already_stored(
+        &mut self,
+        job: &Job<'_>,
+        key: &str,
+        hash: &str,
+        size: usize,
+        pending: &[PendingUpload],
+    ) -> bool {
+        let args = job.args;
+        let stored = if self.stored_hashes.contains(hash) || pending.iter().any(|(k, ..)| k == key)
+        {
+            true
+        } else if args.aggregate_only {
+            // Nothing is uploaded; later copies still count as deduped.
+            self.stored_hashes.insert(hash.to_string());
+            false
+        } else {
+            let exists = upload::exists(job.s3, &args.output_bucket, key)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(%key, "HEAD failed, uploading: {e:#}");
+                    false
+                });
+            if exists {
+                self.stored_hashes.insert(hash.to_string());
+            }
+            exists
+        };
+        if stored {
+            self.counts.attachments_deduplicated_total += 1;
+            self.counts.attachment_bytes_deduplicated += size as u64;
+        }
+        stored
+    }
+
+    /// A by-hash body counts as stored once its upload succeeded.
+    fn uploaded(&mut self, key: &str) {
+        if let Some(hash) = key.strip_prefix(&self.by_hash_prefix) {
+            self.stored_hashes.insert(hash.to_string());
+        }
+    }
+}
+
+/// An object to upload: key, local file and headers.
+type PendingUpload = (String, PathBuf, ObjectMeta);
+
+/// Inputs of the parse stage loaded from the job's flags before the first file.
+struct ParseTools {
+    source_filter: Option<SourceFilter>,
+    term_matcher: Option<TermMatcher>,
+    vip_list: Option<VipList>,
+    internal_domains: Vec<String>,
+    pii_scanner: Option<pii::PiiScanner>,
+    classifier: Option<classify::Classifier>,
+    global_index: Option<dedupe::GlobalIndex>,
+    attachment_policy: attachment_policy::AttachmentPolicy,
+    scanner: Option<Arc<scan::Scanner>>,
+    denist: Option<denist::HashList>,
+}
+
+impl ParseTools {
+    async fn load(job: &Job<'_>) -> Result<Self> {
+        let (args, s3, work_root) = (job.args, job.s3, job.work_root);
+        let global_index = match &args.dedupe_index {
+            Some(location) => Some(dedupe::GlobalIndex::new(
+                dedupe::parse_location(location)?,
+                s3,
+                job.cfg,
+            )),
+            None => None,
+        };
+        let term_matcher = match &args.terms_file {
+            Some(location) => {
+                let text = read_text_input(s3, location, work_root).await?;
+                Some(TermMatcher::new(
+                    &text,
+                    tokenizer_from_spec(&args.tokenizer)?,
+                )?)
+            }
+            None => None,
+        };
+        let denist = match &args.denist_list {
+            Some(location) => Some(denist::HashList::open(
+                &fetch_input(s3, location, work_root).await?,
+            )?),
+            None => None,
+        };
+        let vip_list = match &args.vip_list {
+            Some(location) => {
+                let vips = VipList::parse(&read_text_input(s3, location, work_root).await?);
+                info!(vips = vips.len(), "spoofing checks enabled");
+                Some(vips)
+            }
+            None => None,
+        };
+        let internal_domains: Vec<String> = args
+            .internal_domains
+            .iter()
+            .map(|d| d.trim().trim_start_matches('@').to_ascii_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect();
+        let pii_scanner = if args.pii_scan || args.pii_patterns.is_some() {
+            let pack = match &args.pii_patterns {
+                Some(location) => Some(read_text_input(s3, location, work_root).await?),
+                None => None,
+            };
+            Some(pii::PiiScanner::new(pack.as_deref())?)
+        } else {
+            None
+        };
+        if let Some(rules) = job.rules {
+            info!(rules_version = %rules.version, "rules loaded");
+        }
+        let classifier = match &args.classify_endpoint {
+            Some(url) => Some(classify::Classifier::endpoint(url)?),
+            None if args.classify_attachments => Some(classify::Classifier::Heuristic),
+            None => None,
+        };
+        if args.attachments_for == AttachmentsFor::TaggedOnly
+            && term_matcher.is_none()
+            && vip_list.is_none()
+        {
+            return Err(anyhow!(
+                "--attachments-for tagged-only needs --terms-file or --vip-list to tag emails"
+            ));
+        }
+        let source_filter = match &args.only_source_paths {
+            Some(location) => {
+                let filter = SourceFilter::parse(&read_text_input(s3, location, work_root).await?);
+                info!(
+                    source_paths = filter.paths.len(),
+                    email_ids = filter.email_ids.len(),
+                    "restricting extraction"
+                );
+                Some(filter)
+            }
+            None => None,
+        };
+        let attachment_policy = attachment_policy::AttachmentPolicy::new(
+            args.max_attachment_bytes,
+            &args.attachment_type_allowlist,
+            &args.attachment_type_blocklist,
+        );
+        let scanner = match &args.scan_endpoint {
+            Some(endpoint) => {
+                let endpoint = endpoint.clone();
+                let timeout = std::time::Duration::from_secs(args.scan_timeout_secs);
+                let connect =
+                    tokio::task::spawn_blocking(move || scan::Scanner::connect(&endpoint, timeout));
+                Some(Arc::new(connect.await??))
+            }
+            None => None,
+        };
+        Ok(Self {
+            source_filter,
+            term_matcher,
+            vip_list,
+            internal_domains,
+            pii_scanner,
+            classifier,
+            global_index,
+            attachment_policy,
+            scanner,
+            denist,
+        })
+    }
+}
+
+/// What an extracted file holds, as the parse loop takes it.
+enum ExtractedFile {
+    /// Not read as mail; the reason goes to errors.ndjson.gz.
+    Skipped(&'static str),
+    /// Appointments, contacts and tasks readpst wrote as iCalendar or vCard.
+    Pim(pim::PimItems),
+    /// Messages with their byte offset in the file.
+    Messages(Box<dyn Iterator<Item = std::io::Result<(u64, MboxItem)>> + Send>),
+}
+
+/// Decides how to read one extracted file. mbox files are streamed one message at a time;
+/// anything else is a single message and is bounded by the same size cap.
+fn open_extracted(
+    path: &Path,
+    file_len: u64,
+    max_message_bytes: usize,
+    file_io: &FileIo,
+) -> Result<ExtractedFile> {
+    // Heuristic: `readpst` outputs lots of small metadata files; only parse files that look like
+    // mail.
+    if file_len < 10 {
+        return Ok(ExtractedFile::Skipped("smaller than 10 bytes"));
+    }
+    // Most RFC822 messages start with headers like "From:" or include an mbox envelope line.
+    let mut reader = BufReader::with_capacity(1024 * 1024, File::open(path)?);
+    if reader.fill_buf()?.starts_with(b"From ") {
+        let messages = MboxReader::new(reader, max_message_bytes);
+        return Ok(ExtractedFile::Messages(Box::new(messages)));
+    }
+    if file_len > max_message_bytes as u64 {
+        warn!(bytes = file_len, "skipping file: exceeds max_message_bytes");
+        return Ok(ExtractedFile::Skipped("exceeds max_message_bytes"));
+    }
+    drop(reader);
+    let buf = file_io.read_file(path)?;
+    Ok(if looks_like_mbox(&buf) {
+        let messages = MboxReader::new(Cursor::new(buf), max_message_bytes);
+        ExtractedFile::Messages(Box::new(messages))
+    } else if pim::looks_like_pim(&buf) {
+        ExtractedFile::Pim(pim::parse(&buf))
+    } else if [
+        &b"From:"[..],
+        b"Return-Path:",
+        b"Received:",
+        b"Date:",
+        b"Subject:",
+    ]
+    .iter()
+    .any(|start| buf.starts_with(start))
+    {
+        let message = (0, MboxItem::Message(buf, None));
+        ExtractedFile::Messages(Box::new(std::iter::once(Ok(message))))
+    } else {
+        // Skip obvious non-mail files early.
+        ExtractedFile::Skipped("not an email, mbox, iCalendar or vCard file")
+    })
+}
+
+/// How parsing one message ended.
+enum ParseOutcome {
+    /// `None` when the MIME structure couldn't be parsed.
+    Done(Option<Box<ParsedMessage>>),
+    TimedOut,
+}
+
+/// Parses one message on the blocking pool, so pathological content can't stall the job past
+/// `--message-timeout-secs` (0: no limit). A timed-out parse can't be cancelled; it finishes (or
+/// spins) in the background while the job moves on.
+async fn parse_with_timeout(bytes: Arc<Vec<u8>>, timeout_secs: u64) -> Result<ParseOutcome> {
+    let task = tokio::task::spawn_blocking(move || parse_message(&bytes).map(Box::new));
+    if timeout_secs == 0 {
+        return Ok(ParseOutcome::Done(task.await?));
+    }
+    match tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), task).await {
+        Ok(joined) => Ok(ParseOutcome::Done(joined?)),
+        Err(_) => Ok(ParseOutcome::TimedOut),
+    }
+}
+
+/// Messages a shard's task parses ahead of the parse loop.
+const SHARD_READAHEAD: usize = 8;
+
+/// A file of the parse pass.
+#[derive(Clone)]
+struct ExtractedPath {
+    path: PathBuf,
+    /// readpst-relative, `/`-separated.
+    rel_source: String,
+    len: u64,
+}
+
+/// --extract-concurrency: splits the files to parse (in the order they are visited) into
+/// shards, one per top-level folder: the first folder below the mailbox root, which readpst
+/// names after the PST's root folder. The `_recovered` tree of --recovery-mode is split the
+/// same way. Each shard is a run of consecutive files, so taking the shards in order keeps the
+/// visit order.
+fn folder_shards(rel_paths: &[&str]) -> Vec<std::ops::Range<usize>> {
+    let recovered_prefix = format!("{RECOVERED_DIR}/");
+    let dirs: Vec<(bool, Vec<&str>)> = rel_paths
+        .iter()
+        .map(|path| {
+            let (recovered, path) = match path.strip_prefix(&recovered_prefix) {
+                Some(rest) => (true, rest),
+                None => (false, *path),
+            };
+            let mut parts: Vec<&str> = path.split('/').collect();
+            parts.pop();
+            (recovered, parts)
+        })
+        .collect();
+    let root_depth = dirs
+        .iter()
+        .map(|(_, parts)| parts.as_slice())
+        .reduce(|common, parts| {
+            let shared = common.iter().zip(parts).take_while(|(a, b)| a == b).count();
+            &common[..shared]
+        })
+        .map_or(0, <[&str]>::len);
+    let key = |i: usize| (dirs[i].0, dirs[i].1.get(root_depth).copied());
+    let mut shards: Vec<std::ops::Range<usize>> = Vec::new();
+    for i in 0..dirs.len() {
+        match shards.last_mut() {
+            Some(last) if key(last.start) == key(i) => last.end = i + 1,
+            _ => shards.push(i..i + 1),
+        }
+    }
+    shards
+}
+
+/// What a shard's task sends the parse loop, in file and message order. A file's events are
+/// `Skipped`, `Pim`, or its `Message`s followed by `FileDone`.
+enum ShardEvent {
+    Skipped(&'static str),
+    Pim(pim::PimItems),
+    Message {
+        index: usize,
+        offset: u64,
+        message: ShardMessage,
+    },
+    FileDone,
+    /// The file couldn't be read; the job fails.
+    Failed(anyhow::Error),
+}
+
+enum ShardMessage {
+    Oversized(usize),
+    Parsed {
+        bytes: Arc<Vec<u8>>,
+        envelope: mbox::Envelope,
+        outcome: ParseOutcome,
+        elapsed: std::time::Duration,
+    },
+}
+
+/// Starts a task that opens one shard's files and parses their messages, up to
+/// SHARD_READAHEAD messages ahead of the parse loop. Dropping the receiver stops it.
+fn spawn_shard(
+    files: Vec<ExtractedPath>,
+    max_message_bytes: usize,
+    timeout_secs: u64,
+    file_io: Arc<FileIo>,
+) -> tokio::sync::mpsc::Receiver<ShardEvent> {
+    use tracing::Instrument;
+    let (tx, rx) = tokio::sync::mpsc::channel(SHARD_READAHEAD);
+    tokio::spawn(async move {
+        for file in files {
+            let span = tracing::info_span!("parse_file", source_path = %file.rel_source);
+            let send = send_file(&file, max_message_bytes, timeout_secs, &file_io, &tx);
+            if !send.instrument(span).await {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Sends one file's events; false once the parse loop has stopped listening or the file
+/// failed.
+async fn send_file(
+    file: &ExtractedPath,
+    max_message_bytes: usize,
+    timeout_secs: u64,
+    file_io: &FileIo,
+    tx: &tokio::sync::mpsc::Sender<ShardEvent>,
+) -> bool {
+    let messages = match open_extracted(&file.path, file.len, max_message_bytes, file_io) {
+        Ok(ExtractedFile::Messages(messages)) => messages,
+        Ok(ExtractedFile::Pim(items)) => return tx.send(ShardEvent::Pim(items)).await.is_ok(),
+        Ok(ExtractedFile::Skipped(reason)) => {
+            return tx.send(ShardEvent::Skipped(reason)).await.is_ok()
+        }
+        Err(e) => {
+            tx.send(ShardEvent::Failed(e)).await.ok();
+            return false;
+        }
+    };
+    for (index, item) in messages.enumerate() {
+        let (offset, message) = match item {
+            Ok((offset, MboxItem::Oversized { bytes })) => (offset, ShardMessage::Oversized(bytes)),
+            Ok((offset, MboxItem::Message(bytes, envelope))) => {
+                // Best-effort parse; malformed items are recorded instead of failing the PST.
+                let bytes = Arc::new(bytes);
+                let started = Instant::now();
+                let outcome = match parse_with_timeout(Arc::clone(&bytes), timeout_secs).await {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        tx.send(ShardEvent::Failed(e)).await.ok();
+                        return false;
+                    }
+                };
+                let message = ShardMessage::Parsed {
+                    bytes,
+                    envelope: envelope.unwrap_or_default(),
+                    outcome,
+                    elapsed: started.elapsed(),
+                };
+                (offset, message)
+            }
+            Err(e) => {
+                tx.send(ShardEvent::Failed(e.into())).await.ok();
+                return false;
+            }
+        };
+        let event = ShardEvent::Message {
+            index,
+            offset,
+            message,
+        };
+        if tx.send(event).await.is_err() {
+            return false;
+        }
+    }
+    tx.send(ShardEvent::FileDone).await.is_ok()
+}
+
+/// A top-level message being written, and what its embedded messages share with it.
+struct Family {
+    source_path: String,
+    message_index: usize,
+    id: String,
+    /// Parse time is measured for the whole family and attributed to the top message.
+    parse_ms: f64,
+    envelope: mbox::Envelope,
+    dedupe_hash: Option<String>,
+    global_primary: Option<String>,
+    /// --export-raw-eml: the .eml's key and SHA-256; its upload goes with the top message's.
+    raw_eml: Option<(String, String)>,
+    eml_upload: Option<PendingUpload>,
+    raw_hashes: Option<hashes::Digests>,
+    zip: Option<FamilyZip>,
+    /// --terms-file terms hit anywhere in the family.
+    terms: std::collections::BTreeSet<String>,
+}
+
+/// --family-zips: the family's package, its key, and the entry names used so far.
+struct FamilyZip {
+    zip: ziparchive::ZipWriter<BufWriter<File>>,
+    path: PathBuf,
+    key: String,
+    names: HashSet<String>,
+}
+
+/// What the parse loop accumulates: the outputs it writes and the state later messages depend
+/// on.
+struct ParseState<'a> {
+    job: &'a Job<'a>,
+    tools: ParseTools,
+    count_check: &'a mut itemcounts::CountCheck,
+    emails: EmailOutput,
+    attachments: AttachmentOutput,
+    sidecars: Sidecars,
+    raw_store: Option<RawStore>,
+    anonymized: Option<AnonymizedExport>,
+    indexer: Option<BulkIndexer>,
+    concordance: Option<concordance::Concordance>,
+    scorer: Option<scoring::Scorer>,
+    control_numbers: Option<bates::ControlNumbers>,
+    sampler: Option<sample::Sampler>,
+    /// dedupe hash -> (primary email id, primary source path)
+    dedupe_seen: std::collections::HashMap<String, (String, String)>,
+    /// Message-IDs of the indexed messages, and the email records found for them.
+    pst_index_links: Option<(HashSet<String>, std::collections::HashMap<String, String>)>,
+    term_summary: std::collections::BTreeMap<String, terms::TermSummary>,
+    meeting_chains: meetings::MeetingChains,
+    timeseries: timeseries::TimeSeries,
+    case_stats: stats::StatsCollector,
+    parse_timer: ParseTimer,
+    job_metrics: metrics::JobMetrics,
+    cutoff: Option<watchdog::Cutoff>,
+    family_zips_total: usize,
+}
+
+impl<'a> ParseState<'a> {
+    async fn new(
+        job: &'a Job<'a>,
+        files: &CoreFiles,
+        count_check: &'a mut itemcounts::CountCheck,
+        pst_index: Option<&pstindex::Index>,
+        cutoff: Option<watchdog::Cutoff>,
+    ) -> Result<Self> {
+        let (args, s3, work_root, out_dir) = (job.args, job.s3, job.work_root, &job.out_dir);
+        let tools = ParseTools::load(job).await?;
+        let term_summary = tools
+            .term_matcher
+            .iter()
+            .flat_map(|matcher| matcher.terms())
+            .map(|term| (term.to_string(), Default::default()))
+            .collect();
+        let concordance = if args.concordance {
+            let stopwords = match &args.concordance_stopwords {
+                Some(location) => Some(read_text_input(s3, location, work_root).await?),
+                None => None,
+            };
+            Some(concordance::Concordance::new(
+                tokenizer_from_spec(&args.tokenizer)?,
+                stopwords.as_deref(),
+                args.concordance_min_length,
+                concordance::DEFAULT_MAX_TERMS,
+            ))
+        } else {
+            None
+        };
+        let scorer = match &args.priority_model {
+            Some(model) if model.starts_with("http://") || model.starts_with("https://") => {
+                Some(scoring::Scorer::from_endpoint(model)?)
+            }
+            Some(location) => Some(scoring::Scorer::from_rules(
+                &read_text_input(s3, location, work_root).await?,
+            )?),
+            None => None,
+        };
+        let sampler = sample::Sampler::new(
+            args.sample,
+            args.sample_percent,
+            args.sample_mode,
+            args.sample_seed,
+        )?;
+        let indexer = match &args.opensearch_url {
+            Some(url) => {
+                let auth = args
+                    .opensearch_username
+                    .clone()
+                    .map(|u| (u, args.opensearch_password.clone().unwrap_or_default()));
+                Some(BulkIndexer::new(url, &args.index_name, auth)?)
+            }
+            None => None,
+        };
+        let raw_store = if args.raw_blobs {
+            Some(RawStore {
+                blobs: RawBlobWriter::new(
+                    &out_dir.join("raw"),
+                    &job.prefix,
+                    args.raw_blob_max_bytes,
+                )?,
+                index: Sidecar::create(out_dir, "raw_index.ndjson.gz")?,
+            })
+        } else {
+            None
+        };
+        let control_numbers = match args.control_number_prefix.as_deref() {
+            Some(prefix) => {
+                let start = match &args.control_number_resume_from {
+                    Some(location) => {
+                        let manifest = read_text_input(s3, location, work_root).await?;
+                        bates::resume_start(&manifest, prefix)
+                            .with_context(|| format!("resume control numbers from {location}"))?
+                    }
+                    None => args.control_number_start,
+                };
+                Some(bates::ControlNumbers::new(
+                    prefix,
+                    start,
+                    args.control_number_width,
+                ))
+            }
+            None => None,
+        };
+        let anonymized = if args.anonymized_export {
+            let key = args
+                .anonymize_key
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
+            let path = out_dir.join("anonymized.zip");
+            Some(AnonymizedExport {
+                anonymizer: anonymize::Anonymizer::new(&key),
+                zip: ziparchive::ZipWriter::new(BufWriter::new(File::create(&path)?)),
+                path,
+                total: 0,
+            })
+        } else {
+            None
+        };
+        Ok(Self {
+            job,
+            count_check,
+            emails: EmailOutput::create(job, files)?,
+            attachments: AttachmentOutput::create(job, files)?,
+            sidecars: Sidecars::create(out_dir, args, tools.term_matcher.is_some())?,
+            tools,
+            raw_store,
+            anonymized,
+            indexer,
+            concordance,
+            scorer,
+            control_numbers,
+            sampler,
+            dedupe_seen: Default::default(),
+            pst_index_links: pst_index
+                .map(|index| (index.message_ids(), std::collections::HashMap::new())),
+            term_summary,
+            meeting_chains: Default::default(),
+            timeseries: timeseries::TimeSeries::new(),
+            case_stats: Default::default(),
+            parse_timer: Default::default(),
+            job_metrics: Default::default(),
+            cutoff,
+            family_zips_total: 0,
+        })
+    }
+
+    /// Appointments, tasks and contacts from one file.
+    fn write_pim(&mut self, rel_source: &str, items: pim::PimItems) -> Result<()> {
+        let args = self.job.args;
+        let filter = self.tools.source_filter.as_ref();
+        let sidecars = &mut self.sidecars;
+        let written = write_pim_records(
+            &mut sidecars.calendar,
+            args,
+            rel_source,
+            "event",
+            items.events,
+            filter,
+        )? + write_pim_records(&mut sidecars.tasks, args, rel_source, "task", items.tasks, filter)?
+            + write_pim_records(
+                &mut sidecars.contacts,
+                args,
+                rel_source,
+                "contact",
+                items.contacts,
+                filter,
+            )?;
+        if !rel_source.starts_with(RECOVERED_DIR) {
+            self.count_check.add_extracted(rel_source, written.min(1) as u64);
+        }
+        Ok(())
+    }
+
+    /// Uploads one object unless --aggregate-only; a failure is recorded, not fatal.
+    async fn upload_or_record(
+        &mut self,
+        rel_source: &str,
+        msg_idx: usize,
+        key: &str,
+        path: &Path,
+        meta: &ObjectMeta,
+    ) -> Result<()> {
+        let args = self.job.args;
+        if args.aggregate_only {
+            return Ok(());
+        }
+        match upload_file_with_meta(self.job.s3, &args.output_bucket, key, path, meta).await {
+            Ok(bytes) => self.job_metrics.record_upload(bytes),
+            Err(err) => {
+                let errors = &mut self.sidecars.errors;
+                errors.upload_failed(rel_source, Some(msg_idx), None, key, &err)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A message whose parse ran past the timeout: its raw bytes go to dead_letter/, and it is
+    /// listed in dead_letter.ndjson.gz and errors.ndjson.gz.
+    async fn dead_letter(
+        &mut self,
+        rel_source: &str,
+        msg_idx: usize,
+        offset: u64,
+        bytes: &[u8],
+        elapsed: std::time::Duration,
+    ) -> Result<()> {
+        let Job {
+            args,
+            prefix,
+            out_dir,
+            ..
+        } = self.job;
+        let dl_id = stable_uuid(&format!(
+            "pst:{}|src:{}|idx:{}|dead-letter",
+            args.pst_file_id, rel_source, msg_idx
+        ))
+        .to_string();
+        let dl_key = format!("{prefix}dead_letter/{dl_id}.eml");
+        let dl_path = out_dir.join("dead_letter").join(format!("{dl_id}.eml"));
+        fs::create_dir_all(out_dir.join("dead_letter"))?;
+        File::create(&dl_path)?.write_all(bytes)?;
+        self.upload_or_record(rel_source, msg_idx, &dl_key, &dl_path, &ObjectMeta::default())
+            .await?;
+        let entry = DeadLetterEntry {
+            id: dl_id,
+            source_path: rel_source.to_string(),
+            message_index: msg_idx,
+            size_bytes: bytes.len(),
+            elapsed_ms: elapsed.as_millis() as u64,
+            timeout_secs: args.message_timeout_secs,
+            s3_key: dl_key,
+        };
+        warn!(
+            message_index = msg_idx,
+            timeout_secs = args.message_timeout_secs,
+            dead_letter_key = %entry.s3_key,
+            "message timed out"
+        );
+        self.sidecars.dead_letter.write(&entry)?;
+        let error = ErrorEntry::message(
+            rel_source,
+            msg_idx,
+            format!(
+                "parse timed out after {}s (dead-lettered)",
+                args.message_timeout_secs
+            ),
+            offset,
+            bytes.len() as u64,
+        );
+        self.sidecars.errors.record(&error)
+    }
+
+    /// A parsed top-level message: deduplicated, stored raw where asked, then written with its
+    /// embedded family.
+    async fn message(
+        &mut self,
+        rel_source: &str,
+        msg_idx: usize,
+        msg_bytes: &[u8],
+        envelope: mbox::Envelope,
+        msg: ParsedMessage,
+        parse_ms: f64,
+    ) -> Result<()> {
+        let Job {
+            args,
+            prefix,
+            out_dir,
+            file_io,
+            ..
+        } = self.job;
+        // Deterministic email ID
+        let seed = format!(
+            "pst:{}|src:{}|mid:{}|idx:{}",
+            args.pst_file_id,
+            rel_source,
+            msg.message_id.clone().unwrap_or_default(),
+            msg_idx
+        );
+        let id = stable_uuid(&seed).to_string();
+        if let Some(filter) = &self.tools.source_filter {
+            if !filter.matches(rel_source, &id) {
+                return Ok(());
+            }
+        }
+        if !rel_source.starts_with(RECOVERED_DIR) {
+            self.count_check.add_extracted(rel_source, 1);
+        }
+        if let Some(export) = self.anonymized.as_mut() {
+            export.add(rel_source, &id, msg_idx, msg_bytes)?;
+        }
+
+        // A suppressed copy takes its embedded family with it.
+        let global_index = self.tools.global_index.as_ref();
+        let dedupe_hash =
+            (args.dedupe || global_index.is_some()).then(|| message_dedupe_hash(&msg));
+        if let (true, Some(hash)) = (args.dedupe, &dedupe_hash) {
+            if let Some((primary_id, primary_path)) = self.dedupe_seen.get(hash) {
+                let entry = dedupe::DuplicateEntry {
+                    email_id: id,
+                    source_path: rel_source.to_string(),
+                    message_index: msg_idx,
+                    dedupe_hash: hash.clone(),
+                    primary_email_id: primary_id.clone(),
+                    primary_source_path: primary_path.clone(),
+                };
+                return self.sidecars.duplicates.write(&entry);
+            }
+            self.dedupe_seen
+                .insert(hash.clone(), (id.clone(), rel_source.to_string()));
+        }
+        // Re-running the same PST finds its own claims, which are not duplicates.
+        let global_primary = match (global_index, &dedupe_hash) {
+            (Some(index), Some(hash)) => {
+                let owner = index
+                    .claim(
+                        hash,
+                        &dedupe::IndexEntry {
+                            email_id: id.clone(),
+                            pst_file_id: args.pst_file_id.clone(),
+                            source_path: rel_source.to_string(),
+                        },
+                    )
+                    .await?;
+                (owner.email_id != id).then_some(owner.email_id)
+            }
+            _ => None,
+        };
+
+        self.parse_timer.record_message(TimedItem {
+            source_path: rel_source.to_string(),
+            message_index: Some(msg_idx),
+            email_id: Some(id.clone()),
+            size_bytes: msg_bytes.len() as u64,
+            duration_ms: parse_ms,
+        });
+
+        if let Some(store) = self.raw_store.as_mut() {
+            let entry = store.blobs.append(&id, msg_bytes)?;
+            store.index.write(&entry)?;
+        }
+        let mut raw_eml = None;
+        let mut eml_upload = None;
+        if args.export_raw_eml && !args.aggregate_only {
+            let dir = out_dir.join("eml");
+            fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("{id}.eml"));
+            file_io.write_file(&path, msg_bytes)?;
+            let key = format!("{prefix}eml/{id}.eml");
+            raw_eml = Some((key.clone(), sha256_bytes(msg_bytes)));
+            let meta = ObjectMeta {
+                content_type: Some("message/rfc822"),
+                content_encoding: None,
+            };
+            eml_upload = Some((key, path, meta));
+        }
+
+        // Embedded message/rfc822 attachments become records of their own, emitted after
+        // their parent and linked by parent_email_id / family_id.
+        let zip = if args.family_zips {
+            let dir = out_dir.join("families");
+            fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("{id}.zip"));
+            let mut zip = ziparchive::ZipWriter::new(BufWriter::new(File::create(&path)?));
+            zip.add(&format!("{id}.eml"), msg_bytes)?;
+            Some(FamilyZip {
+                zip,
+                path,
+                key: format!("{prefix}families/{id}.zip"),
+                names: HashSet::new(),
+            })
+        } else {
+            None
+        };
+        let mut family = Family {
+            source_path: rel_source.to_string(),
+            message_index: msg_idx,
+            id: id.clone(),
+            parse_ms,
+            envelope,
+            dedupe_hash,
+            global_primary,
+            raw_eml,
+            eml_upload,
+            raw_hashes: hashes::Digests::compute(&args.hash_algos, msg_bytes),
+            zip,
+            terms: Default::default(),
+        };
+        let mut members: VecDeque<(String, Option<String>, usize, ParsedMessage)> =
+            VecDeque::from([(id, None, 0, msg)]);
+        while let Some((id, parent_email_id, depth, mut msg)) = members.pop_front() {
+            for (part_idx, child) in std::mem::take(&mut msg.embedded) {
+                let child_id = stable_uuid(&format!("{id}|embedded:{part_idx}")).to_string();
+                members.push_back((child_id, Some(id.clone()), depth + 1, child));
+            }
+            self.email(&mut family, id, parent_email_id, depth, msg).await?;
+        }
+
+        for term in std::mem::take(&mut family.terms) {
+            self.term_summary.entry(term).or_default().families += 1;
+        }
+        if let Some(FamilyZip { zip, path, key, .. }) = family.zip {
+            zip.finish()?;
+            let meta = ObjectMeta {
+                content_type: Some("application/zip"),
+                content_encoding: None,
+            };
+            self.upload_or_record(rel_source, msg_idx, &key, &path, &meta)
+                .await?;
+            self.family_zips_total += 1;
+        }
+        Ok(())
+    }
+
+    /// One record of a family (the top message or an embedded one): the email record, its
+    /// attachments, and the objects they need uploaded.
+    async fn email(
+        &mut self,
+        family: &mut Family,
+        id: String,
+        parent_email_id: Option<String>,
+        depth: usize,
+        mut msg: ParsedMessage,
+    ) -> Result<()> {
+        let Job {
+            args,
+            rules,
+            prefix,
+            out_dir,
+            file_io,
+            ..
+        } = self.job;
+        let tools = &self.tools;
+        for (part_idx, reason) in std::mem::take(&mut msg.skipped_parts) {
+            let entry = ErrorEntry {
+                kind: "attachment_skipped",
+                source_path: family.source_path.clone(),
+                message_index: Some(family.message_index),
+                email_id: Some(id.clone()),
+                part_index: Some(part_idx),
+                reason: reason.to_string(),
+                byte_start: None,
+                byte_end: None,
+            };
+            self.sidecars.errors.record(&entry)?;
+        }
+        if let (Some(rules), Some(text)) = (rules, msg.body_text.as_mut()) {
+            if let Some(stripped) = rules.strip_banners(text) {
+                *text = stripped;
+                push_flag(&mut msg.processing_flags, ProcessingFlag::BannerStripped);
+            }
+            if let Some(stripped) = rules.strip_disclaimers(text) {
+                *text = stripped;
+                push_flag(&mut msg.processing_flags, ProcessingFlag::DisclaimerStripped);
+            }
+        }
+
+        let body_for_terms = if tools.term_matcher.is_some()
+            || self.concordance.is_some()
+            || tools.pii_scanner.is_some()
+            || rules.is_some()
+        {
+            match (&msg.body_text, &msg.body_html) {
+                (Some(t), _) => t.clone(),
+                (None, Some(h)) => html_to_text_rough(h),
+                (None, None) => String::new(),
+            }
+        } else {
+            String::new()
+        };
+        let term_texts = [msg.subject.as_deref().unwrap_or(""), &body_for_terms];
+        let term_hits = match &tools.term_matcher {
+            Some(matcher) => matcher.hits(&term_texts),
+            None => Default::default(),
+        };
+        if let Some(concordance) = self.concordance.as_mut() {
+            concordance.add(&term_texts);
+        }
+        if let Some(out) = self.sidecars.term_hits.as_mut() {
+            for (term, hits) in &term_hits {
+                out.write(&serde_json::json!({
+                    "email_id": id,
+                    "attachment_id": null,
+                    "term": term,
+                    "hits": hits,
+                }))?;
+                let summary = self.term_summary.entry(term.clone()).or_default();
+                summary.emails += 1;
+                summary.hits += hits;
+                family.terms.insert(term.clone());
+            }
+        }
+        let pii_flags = match &tools.pii_scanner {
+            Some(scanner) => scanner.flags(&body_for_terms),
+            None => Vec::new(),
+        };
+
+        let mut body_upload: Option<PendingUpload> = None;
+        if args.brotli_bodies {
+            if let Some(html) = &msg.body_html {
+                let key = format!("{prefix}bodies/{id}.html");
+                let dir = out_dir.join("bodies");
+                fs::create_dir_all(&dir)?;
+                let path = dir.join(format!("{id}.html.br"));
+                fs::write(&path, brotli_compress(html.as_bytes(), args.brotli_quality)?)?;
+                let meta = ObjectMeta {
+                    content_type: Some("text/html; charset=utf-8"),
+                    content_encoding: Some("br"),
+                };
+                body_upload = Some((key, path, meta));
+            }
+        }
+        let signature_upload = match &msg.signature {
+            Some(sig) => {
+                let file_name = format!("{id}.{}", sig.extension());
+                let dir = out_dir.join("signatures");
+                fs::create_dir_all(&dir)?;
+                let path = dir.join(&file_name);
+                file_io.write_file(&path, &sig.content)?;
+                let key = format!("{prefix}signatures/{file_name}");
+                Some((key, path, ObjectMeta::default()))
+            }
+            None => None,
+        };
+
+        let spoofing = tools
+            .vip_list
+            .as_ref()
+            .and_then(|vips| vips.check(msg.sender_name.as_deref(), msg.sender_email.as_deref()));
+        let rule_tags = match rules {
+            Some(rules) => rules.tags(&term_texts),
+            None => Vec::new(),
+        };
+        let tagged = !term_hits.is_empty()
+            || !rule_tags.is_empty()
+            || spoofing.is_some()
+            || !msg.conflicting_header_names.is_empty();
+        let attachments_withheld = if args.attachments_for == AttachmentsFor::TaggedOnly && !tagged
+        {
+            std::mem::take(&mut msg.attachments).len()
+        } else {
+            0
+        };
+
+        let body_text_clean = msg.body_text.as_deref().map(quoting::clean_body);
+        let body_language = match (&body_text_clean, &msg.body_html) {
+            (Some(text), _) => classify::language(text),
+            (None, Some(html)) => classify::language(&html_to_text_rough(html)),
+            (None, None) => None,
+        };
+        let near_dupe_hash = match (args.near_dupe_source, &msg.body_text, &msg.body_html) {
+            (NearDupeSource::Clean, Some(_), _) => {
+                body_text_clean.as_deref().and_then(neardupe::simhash)
+            }
+            (NearDupeSource::Full, Some(text), _) => neardupe::simhash(text),
+            (source, None, Some(html)) => {
+                let text = html_to_text_rough(html);
+                match source {
+                    NearDupeSource::Clean => neardupe::simhash(&quoting::clean_body(&text)),
+                    NearDupeSource::Full => neardupe::simhash(&text),
+                }
+            }
+            (_, None, None) => None,
+        };
+        if let Some(hash) = near_dupe_hash {
+            self.emails.near_dupe_inputs.push((id.clone(), hash));
+        }
+        if let Some((wanted, linked)) = self.pst_index_links.as_mut() {
+            if wanted.contains(&msg.message_id_normalized) {
+                linked
+                    .entry(msg.message_id_normalized.clone())
+                    .or_insert_with(|| id.clone());
+            }
+        }
+
+        let mut record = EmailRecord {
+            near_dupe_hash: near_dupe_hash.map(|h| format!("{h:016x}")),
+            body_language: body_language.map(str::to_string),
+            body_text_clean,
+            term_hits,
+            pii_flags,
+            rule_tags,
+            parse_ms: if depth == 0 { family.parse_ms } else { 0.0 },
+            body_html_br_key: body_upload.as_ref().map(|(key, _, _)| key.clone()),
+            signature_s3_key: signature_upload.as_ref().map(|(key, _, _)| key.clone()),
+            header_smuggling_suspected: !msg.conflicting_header_names.is_empty(),
+            spoofing_suspected: spoofing.is_some(),
+            attachments_withheld,
+            control_number: self
+                .control_numbers
+                .as_mut()
+                .map(bates::ControlNumbers::assign),
+            ..email_record(self.job, family, &id, parent_email_id, depth, &mut msg)
+        };
+
+        let mut findings = Vec::new();
+        if record.header_smuggling_suspected {
+            findings.push((
+                FindingKind::HeaderSmuggling,
+                format!(
+                    "conflicting duplicate headers: {}",
+                    msg.conflicting_header_names.join(", ")
+                ),
+            ));
+        }
+        if let Some(detail) = spoofing {
+            findings.push((FindingKind::SpoofingSuspected, detail));
+        }
+        for (kind, detail) in findings {
+            self.sidecars.security_report.write(&SecurityFinding {
+                email_id: id.clone(),
+                source_path: family.source_path.clone(),
+                kind,
+                detail,
+            })?;
+        }
+        if let Some(invite) = msg.invite.take() {
+            self.meeting_chains
+                .add(&id, record.date.as_deref(), record.date_epoch, invite);
+        }
+
+        let recipients: Vec<String> = record
+            .to_emails
+            .iter()
+            .chain(&record.cc_emails)
+            .chain(&record.bcc_emails)
+            .cloned()
+            .collect();
+        let sender = record.sender_email.as_ref().map(|s| s.to_ascii_lowercase());
+        let direction = scoring::direction(&tools.internal_domains, sender.as_deref(), &recipients);
+        record.direction = direction;
+        if depth == 0 {
+            self.timeseries.add(
+                record.date_epoch,
+                direction,
+                timeseries::folder_class(&record.source_path),
+            );
+            self.case_stats.add_email(
+                &record.source_path,
+                record.date_epoch,
+                sender.as_deref(),
+                &recipients,
+            );
+        }
+        if let Some(scorer) = self.scorer.as_mut() {
+            scorer
+                .add(scoring::ScoreInput {
+                    email_id: id.clone(),
+                    direction,
+                    sender_email: sender,
+                    recipients,
+                    subject: record.subject.clone(),
+                    term_hits: record.term_hits.clone(),
+                    attachment_names: msg.attachments.iter().map(|a| a.filename.clone()).collect(),
+                    spoofing_suspected: record.spoofing_suspected,
+                    header_smuggling_suspected: record.header_smuggling_suspected,
+                })
+                .await?;
+        }
+
+        self.emails.write(args, &record)?;
+        if let Some(indexer) = self.indexer.as_mut() {
+            indexer.add(&id, &record).await?;
+        }
+
+        // Attachments: extract MIME leaf parts and upload under OUTPUT_PREFIX/attachments/.
+        // Archive members are queued behind the attachments.
+        let mut uploads: Vec<PendingUpload> = Vec::new();
+        let mut archive_budget = args.archive_max_bytes;
+        let mut queue: VecDeque<(ParsedAttachment, Option<ArchiveParent>)> =
+            msg.attachments.into_iter().map(|att| (att, None)).collect();
+        while let Some((att, parent)) = queue.pop_front() {
+            let members = self
+                .attachment(family, &id, att, parent, &mut archive_budget, &mut uploads)
+                .await?;
+            queue.extend(members);
+        }
+
+        let attachment_uploads = uploads.len() as u64;
+        uploads.extend(body_upload);
+        uploads.extend(signature_upload);
+        uploads.extend(family.eml_upload.take());
+        if !args.aggregate_only && !uploads.is_empty() {
+            self.upload_email_objects(family, &id, uploads).await?;
+            Progress::add(&self.job.progress.attachments_uploaded, attachment_uploads);
+        }
+        Progress::add(&self.job.progress.messages_parsed, 1);
+        Ok(())
+    }
+
+    /// Uploads one email's attachments, body and signature (and, with the top message, the
+    /// family's .eml) up to ATTACHMENT_UPLOAD_CONCURRENCY at once.
+    async fn upload_email_objects(
+        &mut self,
+        family: &Family,
+        email_id: &str,
+        uploads: Vec<PendingUpload>,
+    ) -> Result<()> {
+        let (s3, bucket) = (self.job.s3, &self.job.args.output_bucket);
+        let results: Vec<(String, Result<(u64, std::time::Duration)>)> = stream::iter(uploads)
+            .map(|(key, path, meta)| async move {
+                let upload_started = Instant::now();
+                let bytes = upload_file_with_meta(s3, bucket, &key, &path, &meta).await;
+                (key, bytes.map(|b| (b, upload_started.elapsed())))
+            })
+            .buffer_unordered(ATTACHMENT_UPLOAD_CONCURRENCY)
+            .collect()
+            .await;
+        for (key, result) in results {
+            match result {
+                Ok((bytes, elapsed)) => {
+                    self.job_metrics.record_attachment_upload(bytes, elapsed);
+                    self.attachments.uploaded(&key);
+                }
+                Err(err) => self.sidecars.errors.upload_failed(
+                    &family.source_path,
+                    Some(family.message_index),
+                    Some(email_id),
+                    &key,
+                    &err,
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    /// One attachment (or archive member): checked against the hash list and policy, scanned,
+    /// stored, described and written. Returns the members to queue when it is an archive that
+    /// gets expanded.
+    async fn attachment(
+        &mut self,
+        family: &mut Family,
+        email_id: &str,
+        att: ParsedAttachment,
+        parent: Option<ArchiveParent>,
+        archive_budget: &mut u64,
+        uploads: &mut Vec<PendingUpload>,
+    ) -> Result<Vec<(ParsedAttachment, Option<ArchiveParent>)>> {
+        let Job {
+            args, prefix, out_dir, file_io, ..
+        } = self.job;
+        let ParsedAttachment {
+            part_idx,
+            content,
+            filename,
+            content_type,
+            is_inline,
+            content_id,
+            is_encrypted_attachment,
+            source_container,
+            charset_issues,
+        } = att;
+        let attachment_hash = sha256_bytes(&content);
+        let digests = hashes::Digests::compute(&args.hash_algos, &content);
+        let is_nist = match self.tools.denist.as_mut() {
+            Some(list) => {
+                list.contains(&attachment_hash)? || list.contains(&denist::md5_hex(&content))?
+            }
+            None => false,
+        };
+        if is_nist {
+            self.attachments.counts.nist_attachments_total += 1;
+            self.attachments.counts.nist_bytes_total += content.len() as u64;
+            if args.denist_action == DenistAction::Skip {
+                return Ok(Vec::new());
+            }
+        }
+        let detected_content_type = sniff::detect(&content);
+        let extension_mismatch = detected_content_type
+            .is_some_and(|detected| sniff::extension_mismatch(&filename, detected));
+
+        // Deterministic attachment ID.
+        let mut att_seed = format!(
+            "pst:{}|email:{}|hash:{}|name:{}|idx:{}",
+            args.pst_file_id, email_id, attachment_hash, filename, part_idx
+        );
+        if let Some(parent) = &parent {
+            att_seed.push_str(&format!("|parent:{}", parent.attachment_id));
+        }
+        let attachment_id = stable_uuid(&att_seed).to_string();
+
+        let skipped_reason = self.tools.attachment_policy.skip_reason(
+            content.len() as u64,
+            &filename,
+            content_type.as_deref(),
+            detected_content_type,
+        );
+        let (content, verdict) = match &self.tools.scanner {
+            Some(scanner) if skipped_reason.is_none() => scan_attachment(scanner, content).await?,
+            _ => (content, None),
+        };
+        let (scan_status, scan_signature) = match verdict {
+            Some(Ok(scan::Verdict::Clean)) => (Some("clean"), None),
+            Some(Ok(scan::Verdict::Infected(signature))) => (Some("infected"), Some(signature)),
+            Some(Err(err)) => {
+                warn!(%attachment_id, "attachment scan failed: {err:#}");
+                (Some("error"), None)
+            }
+            None => (None, None),
+        };
+        let quarantined = matches!(scan_status, Some("infected" | "error"));
+
+        let archive_depth = parent.as_ref().map_or(0, |p| p.depth);
+        let mut archive_status = None;
+        let mut members = Vec::new();
+        if args.expand_archives
+            && archive_depth < args.archive_max_depth
+            && skipped_reason.is_none()
+            && !quarantined
+        {
+            if let Some(kind) = archives::kind(&content) {
+                let expansion = archives::expand(kind, &filename, &content, archive_budget);
+                archive_status = Some(expansion.status.to_string());
+                self.attachments.counts.archive_members_total += expansion.members.len();
+                members =
+                    archive_members(expansion.members, &filename, &attachment_id, archive_depth);
+            }
+        }
+        let (parent_attachment_id, archive_path) = match parent {
+            Some(parent) => (Some(parent.attachment_id), Some(parent.path)),
+            None => (None, None),
+        };
+
+        let safe_name = sanitize_filename(&filename, "attachment.bin");
+        let mut att_key = String::new();
+        let mut quarantine_key = None;
+        let mut pii_flags = Vec::new();
+        let mut classification = classify::Classification::default();
+        if quarantined {
+            let key = format!("{prefix}quarantine/{email_id}/{attachment_id}__{safe_name}");
+            let dir = out_dir.join("quarantine").join(email_id);
+            fs::create_dir_all(&dir).ok();
+            let path = dir.join(format!("{attachment_id}__{safe_name}"));
+            file_io.write_file(&path, &content)?;
+            uploads.push((key.clone(), path, ObjectMeta::default()));
+            quarantine_key = Some(key);
+        } else if skipped_reason.is_none() {
+            let upload_needed = match args.attachment_store {
+                AttachmentStore::PerEmail => {
+                    att_key =
+                        format!("{prefix}attachments/{email_id}/{attachment_id}__{safe_name}");
+                    true
+                }
+                AttachmentStore::ByHash => {
+                    att_key = format!("{}{attachment_hash}", self.attachments.by_hash_prefix);
+                    let size = content.len();
+                    !self
+                        .attachments
+                        .already_stored(self.job, &att_key, &attachment_hash, size, uploads)
+                        .await
+                }
+            };
+            if upload_needed {
+                // Write attachment to local disk (keeps S3 upload path-based + avoids
+                // holding multiple ByteStreams).
+                let att_dir = out_dir.join("attachments").join(email_id);
+                fs::create_dir_all(&att_dir).ok();
+                let att_path = att_dir.join(format!("{attachment_id}__{safe_name}"));
+                file_io.write_file(&att_path, &content)?;
+                uploads.push((att_key.clone(), att_path, ObjectMeta::default()));
+            }
+            if let Some(family_zip) = family.zip.as_mut() {
+                let name = family_entry_name(&mut family_zip.names, &safe_name);
+                family_zip.zip.add(&name, &content)?;
+            }
+            let text = AttachmentText {
+                email_id,
+                attachment_id: &attachment_id,
+                filename: &filename,
+                content: &content,
+                detected_content_type,
+                content_type: content_type.as_deref(),
+            };
+            (pii_flags, classification) = self.describe_attachment(family, &text).await?;
+        }
+
+        if let (Some(target), Some(content_id), None) =
+            (args.rewrite_cid, &content_id, &parent_attachment_id)
+        {
+            let replacement = match target {
+                CidTarget::AttachmentId => Some(attachment_id.clone()),
+                CidTarget::S3Key => Some(att_key.clone()).filter(|k| !k.is_empty()),
+            };
+            if let Some(replacement) = replacement {
+                self.emails
+                    .cid_targets
+                    .entry(email_id.to_string())
+                    .or_default()
+                    .insert(cid::key(content_id), replacement);
+            }
+        }
+
+        let record = AttachmentRecord {
+            id: attachment_id,
+            email_message_id: email_id.to_string(),
+            pst_file_id: args.pst_file_id.clone(),
+            project_id: Some(args.project_id.clone()).filter(|v| !v.is_empty()),
+            case_id: Some(args.case_id.clone()).filter(|v| !v.is_empty()),
+            custodian_id: self.job.custodian_id.clone(),
+            custodian_name: self.job.custodian_name.clone(),
+            filename,
+            declared_content_type: content_type.clone(),
+            detected_content_type: detected_content_type.map(str::to_string),
+            extension_mismatch,
+            charset_issues,
+            is_nist,
+            parent_attachment_id,
+            archive_path,
+            archive_status,
+            skipped_reason: skipped_reason.map(str::to_string),
+            scan_status: scan_status.map(str::to_string),
+            scan_signature,
+            quarantine_s3_key: quarantine_key,
+            pii_flags,
+            doc_type: classification.doc_type,
+            doc_language: classification.doc_language,
+            md5: digests.as_ref().and_then(|d| d.md5.clone()),
+            sha1: digests.and_then(|d| d.sha1),
+            control_number: self
+                .control_numbers
+                .as_mut()
+                .map(bates::ControlNumbers::assign),
+            content_type,
+            file_size_bytes: content.len(),
+            s3_bucket: args.output_bucket.clone(),
+            s3_key: att_key,
+            attachment_hash,
+            is_inline,
+            content_id,
+            source_path: family.source_path.clone(),
+            is_encrypted_attachment,
+            source_container,
+        };
+        self.case_stats
+            .add_attachment(&record.filename, content.len() as u64);
+        self.attachments.write(&record)?;
+        Ok(members)
+    }
+
+    /// Text extraction for a stored attachment, and what is derived from the text: PII flags,
+    /// document type, term hits and the attachment_text.ndjson.gz line.
+    async fn describe_attachment(
+        &mut self,
+        family: &mut Family,
+        att: &AttachmentText<'_>,
+    ) -> Result<(Vec<String>, classify::Classification)> {
+        let tools = &self.tools;
+        let wanted = self.sidecars.attachment_text.is_some()
+            || tools.pii_scanner.is_some()
+            || tools.term_matcher.is_some()
+            || tools.classifier.is_some();
+        let extracted = if wanted {
+            textextract::extract(
+                att.content,
+                att.detected_content_type,
+                att.content_type,
+                self.job.args.attachment_text_max_chars,
+            )
+        } else {
+            None
+        };
+        let mut pii_flags = Vec::new();
+        if let (Some(scanner), Some(extracted)) = (&tools.pii_scanner, &extracted) {
+            pii_flags = scanner.flags(&extracted.text);
+        }
+        let mut classification = classify::Classification::default();
+        if let Some(classifier) = &tools.classifier {
+            let input = classify::DocInput {
+                attachment_id: att.attachment_id,
+                filename: att.filename,
+                content_type: att.detected_content_type.or(att.content_type),
+                text: extracted.as_ref().map(|e| e.text.as_str()),
+            };
+            classification = match classifier.classify(&input).await {
+                Ok(found) => found,
+                Err(e) => {
+                    warn!(attachment_id = %att.attachment_id, "classification failed: {e:#}");
+                    Default::default()
+                }
+            };
+        }
+        if let (Some(matcher), Some(extracted), Some(out)) = (
+            &tools.term_matcher,
+            &extracted,
+            self.sidecars.term_hits.as_mut(),
+        ) {
+            for (term, hits) in matcher.hits(&[&extracted.text]) {
+                out.write(&serde_json::json!({
+                    "email_id": att.email_id,
+                    "attachment_id": att.attachment_id,
+                    "term": term,
+                    "hits": hits,
+                }))?;
+                let summary = self.term_summary.entry(term.clone()).or_default();
+                summary.attachments += 1;
+                summary.hits += hits;
+                family.terms.insert(term);
+            }
+        }
+        if let (Some(out), Some(extracted)) = (self.sidecars.attachment_text.as_mut(), extracted) {
+            out.write(&serde_json::json!({
+                "attachment_id": att.attachment_id,
+                "email_message_id": att.email_id,
+                "method": extracted.method,
+                "truncated": extracted.truncated,
+                "text": extracted.text,
+            }))?;
+        }
+        Ok((pii_flags, classification))
+    }
+
+    /// After the last message: the threading, priority and near-duplicate passes over the
+    /// written records, which rewrite emails.ndjson with their results.
+    async fn finish(self, files: &CoreFiles, parse_started: Instant) -> Result<Parsed> {
+        let Self {
+            job,
+            emails,
+            attachments,
+            sidecars,
+            raw_store,
+            anonymized,
+            mut indexer,
+            concordance,
+            scorer,
+            control_numbers,
+            sampler,
+            pst_index_links,
+            term_summary,
+            meeting_chains,
+            timeseries,
+            case_stats,
+            parse_timer,
+            mut job_metrics,
+            cutoff,
+            family_zips_total,
+            ..
+        } = self;
+        let args = job.args;
+        let EmailOutput {
+            unthreaded_path,
+            ndjson,
+            csv,
+            counts: email_counts,
+            thread_inputs,
+            near_dupe_inputs,
+            cid_targets,
+        } = emails;
+        ndjson.finish()?;
+        let (assignments, thread_stats) = threads::assign(&thread_inputs);
+        let thread_of: std::collections::HashMap<String, (String, usize)> = thread_inputs
+            .into_iter()
+            .zip(assignments)
+            .map(|(input, a)| {
+                let thread_id = stable_uuid(&format!("thread:{}", a.thread_key)).to_string();
+                (input.email_id, (thread_id, a.position))
+            })
+            .collect();
+        let priority_of = match scorer {
+            Some(scorer) => scorer.finish().await?,
+            None => Default::default(),
+        };
+        let near_dupe_clusters = neardupe::clusters(
+            &near_dupe_inputs,
+            neardupe::max_distance(args.near_dupe_similarity),
+        );
+        let near_dupe_clusters_total = near_dupe_clusters.len();
+        let mut near_dupe_of: std::collections::HashMap<String, String> = Default::default();
+        for members in near_dupe_clusters {
+            let cluster_id = stable_uuid(&format!("near-dupe:{}", members[0])).to_string();
+            for id in members {
+                near_dupe_of.insert(id, cluster_id.clone());
+            }
+        }
+        let updates = RecordUpdates {
+            thread_of: &thread_of,
+            priority_of: &priority_of,
+            cid_targets: &cid_targets,
+            near_dupe_of: &near_dupe_of,
+        };
+        let ndjson_members = write_threaded_records(
+            &unthreaded_path,
+            &files.emails_ndjson,
+            &updates,
+            args.gzip_member_bytes,
+            job.compression,
+        )?;
+        fs::remove_file(&unthreaded_path).ok();
+        if let Some(indexer) = indexer.as_mut() {
+            for (id, (thread_id, position)) in &thread_of {
+                let fields =
+                    serde_json::json!({ "thread_id": thread_id, "thread_position": position });
+                indexer.update(id, &fields).await?;
+            }
+            for (id, priority) in &priority_of {
+                let fields = serde_json::json!({ "review_priority": priority });
+                indexer.update(id, &fields).await?;
+            }
+        }
+        info!(
+            threads = thread_stats.threads_total,
+            threaded_emails = thread_stats.threaded_emails,
+            largest_thread = thread_stats.largest_thread_size,
+            "threading complete"
+        );
+        let ext = job.ext;
+        let member_indexes = vec![
+            (format!("emails.ndjson.{ext}"), ndjson_members),
+            (format!("emails.csv.{ext}"), csv.finish()?),
+            (format!("attachments.ndjson.{ext}"), attachments.ndjson.finish()?),
+            (format!("attachments.csv.{ext}"), attachments.csv.finish()?),
+        ];
+        job_metrics.parse_secs = parse_started.elapsed().as_secs_f64();
+
+        Ok(Parsed {
+            member_indexes,
+            emails: email_counts,
+            attachments: attachments.counts,
+            sidecars,
+            raw_store,
+            anonymized,
+            indexer,
+            concordance,
+            sampler,
+            control_numbers,
+            meeting_chains,
+            timeseries,
+            case_stats,
+            parse_timer,
+            job_metrics,
+            cutoff,
+            pst_index_links,
+            term_summary,
+            threads: thread_stats,
+            near_dupe_clusters_total,
+            near_dupe_emails_total: near_dupe_of.len(),
+            family_zips_total,
+        })
+    }
+}
+
+/// The attachment fields text extraction and classification look at.
+struct AttachmentText<'a> {
+    email_id: &'a str,
+    attachment_id: &'a str,
+    filename: &'a str,
+    content: &'a [u8],
+    detected_content_type: Option<&'static str>,
+    content_type: Option<&'a str>,
+}
+
+/// --scan-endpoint: scans one attachment. The scanner client blocks on its socket, so it runs
+/// off the async workers; the bytes come back with the verdict.
+async fn scan_attachment(
+    scanner: &Arc<scan::Scanner>,
+    content: Vec<u8>,
+) -> Result<(Vec<u8>, Option<Result<scan::Verdict>>)> {
+    let scanner = Arc::clone(scanner);
+    let (content, verdict) = tokio::task::spawn_blocking(move || {
+        let verdict = scanner.scan(&content);
+        (content, verdict)
+    })
+    .await?;
+    Ok((content, Some(verdict)))
+}
+
+/// --expand-archives: an archive's members as attachments queued behind it.
+fn archive_members(
+    members: Vec<archives::Member>,
+    archive_name: &str,
+    archive_id: &str,
+    archive_depth: usize,
+) -> Vec<(ParsedAttachment, Option<ArchiveParent>)> {
+    members
+        .into_iter()
+        .enumerate()
+        .map(|(member_idx, member)| {
+            let name = member.path.rsplit('/').next().unwrap_or(&member.path);
+            let child = ParsedAttachment {
+                part_idx: member_idx,
+                filename: sanitize_filename(name, "attachment.bin"),
+                content_type: Some(
+                    sniff::detect(&member.content)
+                        .unwrap_or("application/octet-stream")
+                        .to_string(),
+                ),
+                is_inline: false,
+                content_id: None,
+                is_encrypted_attachment: is_encrypted_content(&member.content),
+                source_container: Some(archive_name.to_string()),
+                charset_issues: false,
+                content: member.content,
+            };
+            let parent = ArchiveParent {
+                attachment_id: archive_id.to_string(),
+                path: member.path,
+                depth: archive_depth + 1,
+            };
+            (child, Some(parent))
+        })
+        .collect()
+}
+
+/// The email record fields that come from the message itself and its family; the caller fills
+/// in what the job derived from it (term hits, tags, flags, keys of stored copies).
+fn email_record(
+    job: &Job<'_>,
+    family: &Family,
+    id: &str,
+    parent_email_id: Option<String>,
+    depth: usize,
+    msg: &mut ParsedMessage,
+) -> EmailRecord {
+    let args = job.args;
+    let top = depth == 0;
+    let invite = msg.invite.as_ref();
+    let journal = msg.journal.take();
+    EmailRecord {
+        id: id.to_string(),
+        pst_file_id: args.pst_file_id.clone(),
+        project_id: Some(args.project_id.clone()).filter(|v| !v.is_empty()),
+        case_id: Some(args.case_id.clone()).filter(|v| !v.is_empty()),
+        custodian_id: job.custodian_id.clone(),
+        custodian_name: job.custodian_name.clone(),
+        source_path: family.source_path.clone(),
+        message_id: msg.message_id.take(),
+        message_id_normalized: Some(msg.message_id_normalized.clone()),
+        message_id_synthetic: msg.message_id_synthetic,
+        in_reply_to: msg.in_reply_to.take(),
+        references: msg.references.take(),
+        subject: msg.subject.take(),
+        from: msg.from.take(),
+        to: msg.to.take(),
+        cc: msg.cc.take(),
+        bcc: msg.bcc.take(),
+        to_emails: std::mem::take(&mut msg.to_emails),
+        cc_emails: std::mem::take(&mut msg.cc_emails),
+        bcc_emails: std::mem::take(&mut msg.bcc_emails),
+        date: msg.date.take(),
+        date_epoch: msg.date_epoch,
+        date_parser: msg.date_parser,
+        date_source: msg.date_source,
+        date_utc: msg.date_epoch.map(dates::format_rfc3339),
+        truncated_mime: msg.truncated_mime,
+        charset_issues: msg.charset_issues,
+        received: std::mem::take(&mut msg.received),
+        body_text: msg.body_text.take(),
+        body_html: msg.body_html.take(),
+        sender_email: msg.sender_email.take(),
+        sender_name: msg.sender_name.take(),
+        originating_ip: msg.originating_ip.take(),
+        mail_client: msg.mail_client.take(),
+        processing_flags: std::mem::take(&mut msg.processing_flags),
+        parent_email_id,
+        family_id: family.id.clone(),
+        family_zip_s3_key: family.zip.as_ref().map(|zip| zip.key.clone()),
+        depth,
+        duplicate_header_names: std::mem::take(&mut msg.duplicate_header_names),
+        dedupe_hash: family.dedupe_hash.clone().filter(|_| top),
+        is_global_duplicate: family.global_primary.is_some(),
+        global_primary_email_id: family.global_primary.clone(),
+        is_signed: msg.signature.is_some(),
+        signature_protocol: msg.signature.as_ref().map(|sig| sig.protocol.clone()),
+        delivery_status: msg.delivery_status.take(),
+        calendar_uid: invite.and_then(|i| i.event.uid.clone()),
+        calendar_sequence: invite.and_then(|i| i.event.sequence),
+        calendar_method: invite.and_then(|i| i.method.clone()),
+        is_recovered: family.source_path.starts_with(&format!("{RECOVERED_DIR}/")),
+        envelope_from: family.envelope.from.clone().filter(|_| top),
+        envelope_date: family.envelope.date.clone().filter(|_| top),
+        envelope_date_epoch: family
+            .envelope
+            .date
+            .as_deref()
+            .filter(|_| top)
+            .and_then(dates::parse_date)
+            .map(|(epoch, _)| epoch),
+        raw_eml_s3_key: family.raw_eml.as_ref().filter(|_| top).map(|(k, _)| k.clone()),
+        raw_sha256: family.raw_eml.as_ref().filter(|_| top).map(|(_, h)| h.clone()),
+        raw_hashes: family.raw_hashes.clone().filter(|_| top),
+        journal_recipient_emails: journal
+            .as_ref()
+            .map(journal::Envelope::recipient_emails)
+            .unwrap_or_default(),
+        journal_bcc_emails: journal
+            .as_ref()
+            .map(journal::Envelope::bcc_emails)
+            .unwrap_or_default(),
+        journal_envelope: journal,
+        importance: msg.importance,
+        sensitivity: msg.sensitivity,
+        read_receipt_requested: msg.read_receipt_requested,
+        delivery_receipt_requested: msg.delivery_receipt_requested,
+        receipt_type: msg.receipt_type,
+        retained_headers: retained_headers(&msg.headers, &args.retain_headers),
+        ..Default::default()
+    }
+}
+
+/// Parse stage: every extracted file into email, attachment and sidecar records, then the
+/// threading and near-duplicate passes over the written records.
+async fn parse_stage(
+    job: &Job<'_>,
+    unpacked: &mut Unpacked,
+    pst_index: Option<&pstindex::Index>,
+) -> Result<Parsed> {
+    let Job {
+        args,
+        deadline,
+        progress,
+        file_io,
+        extract_dir,
+        out_dir,
+        ext,
+        ..
+    } = job;
+    let mut cutoff: Option<watchdog::Cutoff> = None;
+    if let Some(stop) = unpacked.readpst_stopped {
+        watchdog::record(&mut cutoff, "readpst", stop, deadline.elapsed_s(Instant::now()));
+    }
+
+    info!("parsing extracted mail files");
+    let parse_phase_started = Instant::now();
+    let files = CoreFiles::new(out_dir, ext);
+    let mut state =
+        ParseState::new(job, &files, &mut unpacked.count_check, pst_index, cutoff).await?;
+
+    // Sorted so record order, and with it control numbers, doesn't depend on the filesystem.
+    let mut entries: Box<dyn Iterator<Item = walkdir::DirEntry>> = Box::new(
+        WalkDir::new(extract_dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok()),
+    );
+    if let Some(sampler) = &state.sampler {
+        entries = Box::new(sampler.order(entries, extract_dir).into_iter());
+    }
+    let paths: Vec<ExtractedPath> = entries
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            let path = entry.path();
+            let rel_source = path
+                .strip_prefix(extract_dir)
+                .ok()
+                .map(platform::portable_rel_path)
+                .unwrap_or_else(|| path.display().to_string());
+            ExtractedPath {
+                len: entry.metadata().map(|m| m.len()).unwrap_or(0),
+                path: entry.into_path(),
+                rel_source,
+            }
+        })
+        .collect();
+    progress
+        .files_total
+        .store(paths.len() as u64, std::sync::atomic::Ordering::Relaxed);
+    progress.set_phase(Phase::Parse);
+
+    // Top-level folders are read and parsed by their own tasks, up to --extract-concurrency at
+    // once; their results are taken one folder after the other, in the order above.
+    let rel_paths: Vec<&str> = paths.iter().map(|p| p.rel_source.as_str()).collect();
+    let mut shards = folder_shards(&rel_paths)
+        .into_iter()
+        .map(|range| paths[range].to_vec());
+    let mut running: VecDeque<(Vec<ExtractedPath>, tokio::sync::mpsc::Receiver<ShardEvent>)> =
+        VecDeque::new();
+    let shards_in_flight = extract_jobs(args.extract_concurrency);
+    'files: loop {
+        while running.len() < shards_in_flight {
+            let Some(shard) = shards.next() else { break };
+            let filter = state.tools.source_filter.as_ref();
+            let wanted = shard
+                .iter()
+                .filter(|file| filter.is_none_or(|f| f.may_contain(&file.rel_source)))
+                .cloned()
+                .collect();
+            let events = spawn_shard(
+                wanted,
+                args.max_message_bytes,
+                args.message_timeout_secs,
+                Arc::clone(file_io),
+            );
+            running.push_back((shard, events));
+        }
+        let Some((shard, mut events)) = running.pop_front() else {
+            break;
+        };
+        'file: for file in shard {
+            if state.sampler.as_ref().is_some_and(sample::Sampler::full) {
+                break 'files;
+            }
+            Progress::add(&progress.files_done, 1);
+            let file_started = Instant::now();
+            let rel_source = file.rel_source;
+            if let Some(filter) = &state.tools.source_filter {
+                if !filter.may_contain(&rel_source) {
+                    continue;
+                }
+            }
+            let _file_span =
+                tracing::info_span!("parse_file", source_path = %rel_source).entered();
+            if let Some(stop) = deadline.stop(Instant::now()) {
+                warn!(reason = ?stop, "no new messages taken");
+                let elapsed_s = deadline.elapsed_s(Instant::now());
+                let cut = watchdog::record(&mut state.cutoff, "parse", stop, elapsed_s);
+                cut.source_path = Some(rel_source);
+                // This file wasn't started.
+                progress
+                    .files_done
+                    .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+                break 'files;
+            }
+            loop {
+                let event = events
+                    .recv()
+                    .await
+                    .ok_or_else(|| anyhow!("parse of {rel_source} stopped unexpectedly"))?;
+                let (msg_idx, offset, message) = match event {
+                    ShardEvent::Message {
+                        index,
+                        offset,
+                        message,
+                    } => (index, offset, message),
+                    ShardEvent::FileDone => break,
+                    ShardEvent::Pim(items) => {
+                        state.write_pim(&rel_source, items)?;
+                        continue 'file;
+                    }
+                    ShardEvent::Skipped(reason) => {
+                        let entry = ErrorEntry::file(&rel_source, reason, file.len);
+                        state.sidecars.errors.record(&entry)?;
+                        continue 'file;
+                    }
+                    ShardEvent::Failed(e) => {
+                        return Err(e.context(format!("read {rel_source}")));
+                    }
+                };
+                if let Some(stop) = deadline.stop(Instant::now()).filter(|_| msg_idx > 0) {
+                    warn!(reason = ?stop, message_index = msg_idx, "no new messages taken");
+                    let elapsed_s = deadline.elapsed_s(Instant::now());
+                    let cut = watchdog::record(&mut state.cutoff, "parse", stop, elapsed_s);
+                    cut.source_path = Some(rel_source.clone());
+                    cut.message_index = Some(msg_idx);
+                    break 'files;
+                }
+                if let Some(sampler) = state.sampler.as_mut() {
+                    if sampler.full() {
+                        break 'files;
+                    }
+                    if !sampler.take(&rel_source, msg_idx) {
+                        continue;
+                    }
+                }
+                let (msg_bytes, envelope, outcome, elapsed) = match message {
+                    ShardMessage::Parsed {
+                        bytes,
+                        envelope,
+                        outcome,
+                        elapsed,
+                    } => (bytes, envelope, outcome, elapsed),
+                    ShardMessage::Oversized(bytes) => {
+                        warn!(
+                            message_index = msg_idx,
+                            bytes,
+                            "skipping message: exceeds max_message_bytes"
+                        );
+                        let entry = ErrorEntry::message(
+                            &rel_source,
+                            msg_idx,
+                            "exceeds max_message_bytes".to_string(),
+                            offset,
+                            bytes as u64,
+                        );
+                        state.sidecars.errors.record(&entry)?;
+                        continue;
+                    }
+                };
+                let parse_ms = elapsed.as_secs_f64() * 1000.0;
+                match outcome {
+                    ParseOutcome::Done(Some(msg)) => {
+                        state
+                            .message(&rel_source, msg_idx, &msg_bytes, envelope, *msg, parse_ms)
+                            .await?
+                    }
+                    ParseOutcome::Done(None) => {
+                        let entry = ErrorEntry::message(
+                            &rel_source,
+                            msg_idx,
+                            "MIME parse failed".to_string(),
+                            offset,
+                            msg_bytes.len() as u64,
+                        );
+                        state.sidecars.errors.record(&entry)?;
+                    }
+                    ParseOutcome::TimedOut => {
+                        state
+                            .dead_letter(&rel_source, msg_idx, offset, &msg_bytes, elapsed)
+                            .await?
+                    }
+                }
+            }
+
+            state.parse_timer.record_file(TimedItem {
+                source_path: rel_source,
+                message_index: None,
+                email_id: None,
+                size_bytes: file.len,
+                duration_ms: file_started.elapsed().as_secs_f64() * 1000.0,
+            });
+        }
+    }
+
+    state.finish(&files, parse_phase_started).await
+}
+
+/// What the parse stage hands to the upload stage.
+struct Parsed {
+    /// Gzip member indexes of the core four files.
+    member_indexes: Vec<(String, MemberIndex)>,
+    emails: EmailCounts,
+    attachments: AttachmentCounts,
+    sidecars: Sidecars,
+    raw_store: Option<RawStore>,
+    anonymized: Option<AnonymizedExport>,
+
+    // What is finished or reported once every record is written.
+    indexer: Option<BulkIndexer>,
+    concordance: Option<concordance::Concordance>,
+    sampler: Option<sample::Sampler>,
+    control_numbers: Option<bates::ControlNumbers>,
+    meeting_chains: meetings::MeetingChains,
+    timeseries: timeseries::TimeSeries,
+    case_stats: stats::StatsCollector,
+    parse_timer: ParseTimer,
+    job_metrics: metrics::JobMetrics,
+    cutoff: Option<watchdog::Cutoff>,
+    pst_index_links: Option<(HashSet<String>, std::collections::HashMap<String, String>)>,
+    term_summary: std::collections::BTreeMap<String, terms::TermSummary>,
+    threads: ThreadStats,
+    near_dupe_clusters_total: usize,
+    near_dupe_emails_total: usize,
+    family_zips_total: usize,
+}
+
+/// --output-part-rows / --output-part-bytes: splits emails.ndjson and attachments.ndjson into
+/// parts, which replace them among the outputs and member indexes.
+fn split_output_parts(
+    job: &Job<'_>,
+    files: &CoreFiles,
+    member_indexes: &mut Vec<(String, MemberIndex)>,
+    outputs: &mut Vec<(String, PathBuf)>,
+) -> Result<std::collections::BTreeMap<String, Vec<gzmembers::Part>>> {
+    let (args, ext) = (job.args, job.ext);
+    let mut output_parts = std::collections::BTreeMap::new();
+    for (stem, path) in [
+        ("emails", &files.emails_ndjson),
+        ("attachments", &files.attachments_ndjson),
+    ] {
+        let file = format!("{stem}.ndjson.{ext}");
+        member_indexes.retain(|(name, _)| *name != file);
+        let parts = gzmembers::split_parts(
+            path,
+            &job.out_dir,
+            stem,
+            args.output_part_rows,
+            args.output_part_bytes,
+            args.gzip_member_bytes,
+            job.compression,
+        )
+        .with_context(|| format!("split {file} into parts"))?;
+        info!(file = %file, parts = parts.len(), "output split into parts");
+        let mut listed = Vec::new();
+        for (part, index) in parts {
+            outputs.push((part.name.clone(), part.path.clone()));
+            member_indexes.push((part.name.clone(), index));
+            listed.push(part);
+        }
+        output_parts.insert(file, listed);
+    }
+    Ok(output_parts)
+}
+
+/// --concordance: writes concordance.ndjson.gz; returns the number of terms written.
+fn write_concordance(
+    job: &Job<'_>,
+    concordance: concordance::Concordance,
+    outputs: &mut Vec<(String, PathBuf)>,
+) -> Result<usize> {
+    let path = job.out_dir.join("concordance.ndjson.gz");
+    let mut out = GzEncoder::new(File::create(&path)?, Compression::default());
+    let stats = concordance.write(&mut out, job.args.concordance_min_count)?;
+    out.finish()?;
+    info!(
+        terms = stats.terms_written,
+        emails = stats.emails_scanned,
+        tokens = stats.tokens_counted,
+        prunes = stats.prunes,
+        "concordance written"
+    );
+    outputs.push(("concordance.ndjson.gz".to_string(), path));
+    Ok(stats.terms_written as usize)
+}
+
+/// --loadfile: writes the load file (listed among the outputs) and its extracted-text files.
+/// Returns the document count and the text files.
+fn write_loadfile(
+    job: &Job<'_>,
+    format: loadfile::LoadfileFormat,
+    files: &CoreFiles,
+    outputs: &mut Vec<(String, PathBuf)>,
+) -> Result<(usize, Vec<(String, PathBuf)>)> {
+    let (args, out_dir) = (job.args, &job.out_dir);
+    let delimiters = loadfile::Delimiters {
+        field: args.loadfile_field_delimiter,
+        quote: args.loadfile_quote,
+        newline: args.loadfile_newline,
+        multi: args.loadfile_multi_value,
+    };
+    let attachment_text = out_dir.join("attachment_text.ndjson.gz");
+    let inputs = loadfile::Inputs {
+        emails: &files.emails_ndjson,
+        attachments: &files.attachments_ndjson,
+        attachment_text: args.attachment_text.then_some(attachment_text.as_path()),
+        compression: job.compression,
+        key_prefix: &job.prefix,
+    };
+    let written = loadfile::write(format, delimiters, &inputs, out_dir)?;
+    info!(
+        documents = written.documents,
+        text_files = written.text_files.len(),
+        "load file written"
+    );
+    outputs.push((written.loadfile.clone(), out_dir.join(&written.loadfile)));
+    Ok((written.documents, loadfile::text_paths(out_dir, &written)))
+}
+
+/// meetings.ndjson.gz: every meeting invite chain collapsed into one record. Returns the
+/// meetings and the invite emails folded into another email's meeting.
+fn write_meetings(
+    out_dir: &Path,
+    chains: meetings::MeetingChains,
+    outputs: &mut Vec<(String, PathBuf)>,
+) -> Result<(usize, usize)> {
+    let meeting_emails = chains.emails();
+    let mut out = Sidecar::create(out_dir, "meetings.ndjson.gz")?;
+    for meeting in chains.finish() {
+        out.write(&meeting)?;
+    }
+    let meetings_total = out.finish(outputs, false)?;
+    Ok((meetings_total, meeting_emails - meetings_total))
+}
+
+/// --pst-index: writes pst_index.ndjson.gz with the links found while parsing. Returns its key
+/// and the summary for the manifest (or why the PST couldn't be indexed).
+fn write_pst_index(
+    job: &Job<'_>,
+    index: Option<Result<pstindex::Index>>,
+    links: Option<(HashSet<String>, std::collections::HashMap<String, String>)>,
+    outputs: &mut Vec<(String, PathBuf)>,
+) -> Result<(Option<String>, Option<pstindex::Summary>)> {
+    match index {
+        Some(Ok(index)) => {
+            let path = job.out_dir.join("pst_index.ndjson.gz");
+            let mut out = GzEncoder::new(File::create(&path)?, Compression::default());
+            let linked = links.map(|(_, linked)| linked).unwrap_or_default();
+            let summary = index.write(&linked, &mut out)?;
+            out.finish()?;
+            outputs.push(("pst_index.ndjson.gz".to_string(), path));
+            Ok((Some(format!("{}pst_index.ndjson.gz", job.prefix)), Some(summary)))
+        }
+        Some(Err(e)) => Ok((
+            None,
+            Some(pstindex::Summary {
+                error: Some(format!("{e:#}")),
+                ..Default::default()
+            }),
+        )),
+        None => Ok((None, None)),
+    }
+}
+
+/// Uploads the data files in parallel, then the manifest once everything it points at is in
+/// place.
+async fn upload_outputs(
+    job: &Job<'_>,
+    mut outputs: Vec<(String, &Path)>,
+    manifest_key: &str,
+    job_metrics: &mut metrics::JobMetrics,
+) -> Result<()> {
+    let (s3, bucket) = (job.s3, &job.args.output_bucket);
+    let manifest_upload = outputs.pop_if(|(key, _)| key == manifest_key);
+    let uploaded: Vec<u64> = stream::iter(outputs)
+        .map(|(key, path)| async move { upload_file(s3, bucket, &key, path).await })
+        .buffer_unordered(job.args.upload_concurrency.max(1))
+        .try_collect()
+        .await?;
+    for bytes in uploaded {
+        job_metrics.record_upload(bytes);
+    }
+    if let Some((key, path)) = manifest_upload {
+        job_metrics.record_upload(upload_file(s3, bucket, &key, path).await?);
+    }
+    Ok(())
+}
+
+/// Upload stage: finish the sidecar outputs, write the manifest and upload everything.
+async fn finish_job(
+    job: &Job<'_>,
+    unpacked: Unpacked,
+    pst_index: Option<Result<pstindex::Index>>,
+    parsed: Parsed,
+    mut phase: logging::PhaseSpan,
+) -> Result<JobSummary> {
+    let Job {
+        args,
+        rules,
+        started,
+        compression,
+        ext,
+        prefix,
+        progress,
+        progress_sinks,
+        out_dir,
+        ..
+    } = job;
+    let mut parsed = parsed;
+    progress.set_phase(Phase::Upload);
+    phase.set("upload");
+
+    let opensearch_stats = match parsed.indexer.take() {
+        Some(indexer) => {
+            let stats = indexer.finish(&args.pst_file_id).await;
+            info!(
+                index = %stats.index_name,
+                indexed = stats.docs_indexed,
+                failed = stats.docs_failed,
+                count = ?stats.index_count,
+                "opensearch indexing complete"
+            );
+            Some(stats)
+        }
+        None => None,
+    };
+
+    // Outputs besides the core files: (output file name, local path). Hashed into the manifest
+    // and uploaded under the output prefix alongside the core files.
+    let files = CoreFiles::new(out_dir, ext);
+    let mut outputs: Vec<(String, PathBuf)> = Vec::new();
+    let partitioned = args.output_part_rows > 0 || args.output_part_bytes > 0;
+    let output_parts = if partitioned {
+        split_output_parts(job, &files, &mut parsed.member_indexes, &mut outputs)?
+    } else {
+        Default::default()
+    };
+    let (errors, sidecar_totals) = parsed.sidecars.finish(&mut outputs)?;
+    let mut anonymized_total = 0;
+    if let Some(export) = parsed.anonymized.take() {
+        export.zip.finish()?;
+        anonymized_total = export.total;
+        outputs.push(("anonymized.zip".to_string(), export.path));
+    }
+    let concordance_terms_total = match parsed.concordance.take() {
+        Some(concordance) => write_concordance(job, concordance, &mut outputs)?,
+        None => 0,
+    };
+    let (loadfile_documents_total, loadfile_texts) =
+        match args.loadfile.filter(|_| !args.aggregate_only) {
+            Some(format) => write_loadfile(job, format, &files, &mut outputs)?,
+            None => (0, Vec::new()),
+        };
+    let schema_path = out_dir.join("schema.json");
+    fs::write(&schema_path, serde_json::to_vec_pretty(&schema::document())?)?;
+    outputs.push(("schema.json".to_string(), schema_path));
+    let timeseries_path = out_dir.join("timeseries.json");
+    fs::write(&timeseries_path, serde_json::to_vec(&parsed.timeseries)?)?;
+    outputs.push(("timeseries.json".to_string(), timeseries_path));
+    for (name, index) in &parsed.member_indexes {
+        let index_name = format!("{name}.members.json");
+        let index_path = out_dir.join(&index_name);
+        fs::write(&index_path, serde_json::to_vec(index)?)?;
+        outputs.push((index_name, index_path));
+    }
+    let (meetings_total, meeting_updates_collapsed) =
+        write_meetings(out_dir, parsed.meeting_chains, &mut outputs)?;
+    let mut raw_blobs = Vec::new();
+    let mut raw_index_key = None;
+    if let Some(store) = parsed.raw_store.take() {
+        store.index.finish(&mut outputs, true)?;
+        raw_blobs = store.blobs.finish()?;
+        raw_index_key = Some(format!("{prefix}raw_index.ndjson.gz"));
+    }
+    let (pst_index_key, pst_index) =
+        write_pst_index(job, pst_index, parsed.pst_index_links, &mut outputs)?;
+
+    let mut sha = std::collections::BTreeMap::new();
+    if !partitioned {
+        sha.insert(format!("emails.ndjson.{ext}"), sha256_file(&files.emails_ndjson)?);
+        sha.insert(
+            format!("attachments.ndjson.{ext}"),
+            sha256_file(&files.attachments_ndjson)?,
+        );
+    }
+    sha.insert(format!("emails.csv.{ext}"), sha256_file(&files.emails_csv)?);
+    sha.insert(
+        format!("attachments.csv.{ext}"),
+        sha256_file(&files.attachments_csv)?,
+    );
+    for (name, path) in &outputs {
+        sha.insert(name.clone(), sha256_file(path)?);
+    }
+
+    let ndjson_key = format!("{prefix}emails.ndjson.{ext}");
+    let csv_key = format!("{prefix}emails.csv.{ext}");
+    let attachments_ndjson_key = format!("{prefix}attachments.ndjson.{ext}");
+    let attachments_csv_key = format!("{prefix}attachments.csv.{ext}");
+    let manifest_key = format!("{prefix}manifest.json");
+
+    let sampling = parsed.sampler.map(sample::Sampler::finish);
+    let count_validation = (unpacked.input_format == "pst"
+        && args.only_source_paths.is_none()
+        && sampling.is_none()
+        && unpacked.count_check.has_summary())
+    .then(|| unpacked.count_check.report());
+    if let Some(validation) = count_validation.as_ref() {
+        for folder in &validation.discrepancies {
+            warn!(
+                folder = %folder.folder,
+                pst_items = folder.pst_items,
+                extracted = folder.extracted,
+                "count check: folder item count mismatch"
+            );
+        }
+    }
+    let mut cutoff = parsed.cutoff;
+    if let Some(cutoff) = cutoff.as_mut() {
+        cutoff.files_done = progress.files_done.load(std::sync::atomic::Ordering::Relaxed);
+        cutoff.files_total = progress.files_total.load(std::sync::atomic::Ordering::Relaxed);
+    }
+    let status = cutoff.as_ref().map_or("complete", |c| c.reason.status());
+    let (emails_total, attachments_total) =
+        (parsed.emails.emails_total, parsed.attachments.attachments_total);
+    let manifest = Manifest {
+        pst_file_id: args.pst_file_id.clone(),
+        custodian_id: job.custodian_id.clone(),
+        custodian_name: job.custodian_name.clone(),
+        source_bucket: args.source_bucket.clone(),
+        source_key: args.source_key.clone(),
+        output_bucket: args.output_bucket.clone(),
+        output_prefix: prefix.clone(),
+        source_path: args.source_path.clone(),
+        output_dir: args.output_dir.clone(),
+        input_format: unpacked.input_format,
+        msg_failed_total: unpacked.msg_failed_total,
+        emails: parsed.emails,
+        attachments: parsed.attachments,
+        family_zips_total: parsed.family_zips_total,
+        attachment_text_total: sidecar_totals.attachment_text,
+        term_summary: parsed.term_summary,
+        rules_version: rules.map(|r| r.version.clone()),
+        anonymized_total,
+        dead_letter_total: sidecar_totals.dead_letter,
+        errors,
+        security_findings_total: sidecar_totals.security_findings,
+        threads: parsed.threads,
+        near_dupe_clusters_total: parsed.near_dupe_clusters_total,
+        near_dupe_emails_total: parsed.near_dupe_emails_total,
+        duplicates_suppressed_total: sidecar_totals.duplicates_suppressed,
+        concordance_terms_total,
+        loadfile_documents_total,
+        control_numbers: parsed
+            .control_numbers
+            .as_ref()
+            .map(bates::ControlNumbers::range),
+        readpst_failed: unpacked.readpst_failed,
+        count_validation,
+        calendar_total: sidecar_totals.calendar,
+        meetings_total,
+        meeting_updates_collapsed,
+        contacts_total: sidecar_totals.contacts,
+        tasks_total: sidecar_totals.tasks,
+        stats: parsed.case_stats.finish(),
+        status,
+        partial: cutoff.is_some(),
+        cutoff,
+        duration_s: started.elapsed().as_secs_f64(),
+        ndjson_gz_key: (!partitioned).then(|| ndjson_key.clone()),
+        csv_gz_key: csv_key.clone(),
+        attachments_ndjson_gz_key: (!partitioned).then(|| attachments_ndjson_key.clone()),
+        attachments_csv_gz_key: attachments_csv_key.clone(),
+        output_parts,
+        compression: compression.codec,
+        manifest_key: manifest_key.clone(),
+        raw_index_ndjson_gz_key: raw_index_key,
+        pst_index_ndjson_gz_key: pst_index_key,
+        pst_index,
+        raw_blob_keys: raw_blobs.iter().map(|b| b.key.clone()).collect(),
+        opensearch: opensearch_stats,
+        source_filter: args.only_source_paths.clone(),
+        sample: sampling.is_some(),
+        sampling,
+        parse_timing: parsed.parse_timer.finish(),
+        sha256: sha,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: schema::SCHEMA_VERSION,
+    };
+    let manifest_path = out_dir.join("manifest.json");
+    File::create(&manifest_path)?.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+
+    let report_key = format!("{prefix}aggregate_report.json");
+    let report_path = out_dir.join("aggregate_report.json");
+    let mut uploads: Vec<(String, &Path)> = Vec::new();
+    if args.aggregate_only {
+        let report = aggregate::Aggregates::from_outputs(
+            &files.emails_ndjson,
+            &files.attachments_ndjson,
+            *compression,
+        )?
+        .report(
+            &args.pst_file_id,
+            args.suppress_below,
+            &[
+                ("emails_failed", manifest.errors.emails_failed as u64),
+                ("files_skipped", manifest.errors.files_skipped as u64),
+                ("threads", manifest.threads.threads_total as u64),
+                ("calendar_items", manifest.calendar_total as u64),
+                ("contacts", manifest.contacts_total as u64),
+            ],
+        );
+        fs::write(&report_path, serde_json::to_vec_pretty(&report)?)?;
+        uploads.push((report_key.clone(), &report_path));
+    } else {
+        if !partitioned {
+            uploads.extend([
+                (ndjson_key, files.emails_ndjson.as_path()),
+                (attachments_ndjson_key, &files.attachments_ndjson),
+            ]);
+        }
+        uploads.extend([
+            (csv_key, files.emails_csv.as_path()),
+            (attachments_csv_key, &files.attachments_csv),
+        ]);
+        for (name, path) in &outputs {
+            uploads.push((format!("{prefix}{name}"), path));
+        }
+        for blob in &raw_blobs {
+            uploads.push((blob.key.clone(), &blob.path));
+        }
+        for (name, path) in &loadfile_texts {
+            uploads.push((format!("{prefix}{name}"), path));
+        }
+        uploads.push((manifest_key.clone(), &manifest_path));
+    }
+    let mut job_metrics = parsed.job_metrics;
+    upload_outputs(job, uploads, &manifest_key, &mut job_metrics).await?;
+
+    info!(emails_total, attachments_total, "uploads complete");
+
+    if let Some(sink) = args.metrics_sink {
+        job_metrics.messages_parsed = emails_total as u64;
+        job_metrics.parse_failures = manifest.errors.emails_failed as u64;
+        // Metrics are best-effort; the job's outputs are already uploaded.
+        if let Err(e) = metrics::emit(
+            sink,
+            args.pushgateway_url.as_deref(),
+            &args.pst_file_id,
+            &job_metrics,
+        )
+        .await
+        {
+            warn!("metrics emission failed: {e:#}");
+        }
+    }
+
+    progress.set_phase(Phase::Complete);
+    progress::emit(progress, progress_sinks).await;
+
+    println!(
+        "OK pst_file_id={} emails_total={} attachments_total={} duration_s={:.2}",
+        args.pst_file_id,
+        emails_total,
+        attachments_total,
+        started.elapsed().as_secs_f64()
+    );
+
+    Ok(JobSummary {
+        // The callback points at the report when there's no manifest.
+        manifest_key: if args.aggregate_only {
+            report_key
+        } else {
+            manifest_key
+        },
+        emails_total,
+        attachments_total,
+        duration_s: started.elapsed().as_secs_f64(),
+        status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_non_banner_plain_text_over_banner_only_part() {
+        let raw = concat!(
+            "From: Sender <s@example.com>\r\n",
+            "To: You <y@example.com>\r\n",
+            "Subject: Test\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/mixed; boundary=BOUND\r\n",
+            "\r\n",
+            "--BOUND\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "CAUTION: EXTERNAL EMAIL\r\n",
+            "Do not click links unless you recognize the sender\r\n",
+            "\r\n",
+            "--BOUND\r\n",
+            "Content-Type: multipart/alternative; boundary=ALT\r\n",
+            "\r\n",
+            "--ALT\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "Hello team,\r\n",
+            "This is the real body text.\r\n",
+            "--ALT\r\n",
+            "Content-Type: text/html; charset=utf-8\r\n",
+            "\r\n",
+            "<html><body><p>Hello team,</p><p>This is the real body text.</p></body></html>\r\n",
+            "--ALT--\r\n",
+            "--BOUND--\r\n"
+        )
+        .as_bytes();
+
+        let mail = mailparse::parse_mail(raw).expect("parse_mail");
+        let (bt, _bh) = select_email_bodies(&mail, &mut Vec::new());
+        let bt = bt.expect("expected body text");
+        assert!(bt.contains("real body"));
+        assert!(!is_mostly_external_banner(&bt));
+    }
+
+    #[test]
+    fn drops_banner_only_plain_when_html_has_meaningful_content() {
+        let raw = concat!(
+            "From: Sender <s@example.com>\r\n",
+            "To: You <y@example.com>\r\n",
+            "Subject: Test\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/alternative; boundary=ALT\r\n",
+            "\r\n",
+            "--ALT\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "CAUTION: EXTERNAL EMAIL\r\n",
+            "Do not click links unless you recognize the sender\r\n",
+            "--ALT\r\n",
+            "Content-Type: text/html; charset=utf-8\r\n",
+            "\r\n",
+            "<html><body><p>Real content is only in HTML.</p></body></html>\r\n",
+            "--ALT--\r\n"
+        )
+        .as_bytes();
+
+        let mail = mailparse::parse_mail(raw).expect("parse_mail");
+        let mut flags = Vec::new();
+        let (bt, bh) = select_email_bodies(&mail, &mut flags);
+
+        let bt = bt.expect("expected derived text body");
+        assert_eq!(
+            flags,
+            vec![ProcessingFlag::HtmlDerivedText, ProcessingFlag::BannerStripped]
+        );
+        assert!(!is_mostly_external_banner(&bt));
+        assert!(bt.to_ascii_lowercase().contains("real content"));
+        assert!(bh.is_some(), "expected HTML body");
+    }
+
+    #[test]
+    fn ignores_text_plain_attachments_when_selecting_body() {
+        let raw = concat!(
+            "From: Sender <s@example.com>\r\n",
+            "To: You <y@example.com>\r\n",
+            "Subject: Test\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/mixed; boundary=BOUND\r\n",
+            "\r\n",
+            "--BOUND\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "Body text here.\r\n",
+            "--BOUND\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "Content-Disposition: attachment; filename=\"note.txt\"\r\n",
+            "\r\n",
+            "This is an attached note and should not be selected as the body.\r\n",
+            "--BOUND--\r\n"
+        )
+        .as_bytes();
+
+        let mail = mailparse::parse_mail(raw).expect("parse_mail");
+        let (bt, _bh) = select_email_bodies(&mail, &mut Vec::new());
+        let bt = bt.expect("expected body text");
+        assert!(bt.contains("Body text here"));
+        assert!(!bt.contains("attached note"));
+    }
+
+    #[test]
+    fn extracts_originating_ip_and_mail_client() {
+        let raw = concat!(
+            "From: Sender <s@example.com>\r\n",
+            "Subject: Test\r\n",
+            "X-Originating-IP: [203.0.113.7]\r\n",
+            "User-Agent: Mozilla Thunderbird\r\n",
+            "\r\n",
+            "Body\r\n"
+        )
+        .as_bytes();
+
+        let mail = mailparse::parse_mail(raw).expect("parse_mail");
+        assert_eq!(extract_originating_ip(&mail).as_deref(), Some("203.0.113.7"));
+        let msg = parse_message(raw).expect("parse");
+        let names = ["x-originating-ip".to_string(), "X-Mailer".into()];
+        let retained = retained_headers(&msg.headers, &names);
+        assert_eq!(retained.len(), 1);
+        assert_eq!(retained["x-originating-ip"], "[203.0.113.7]");
+        assert_eq!(
+            header_first_of(&mail, MAIL_CLIENT_HEADERS).as_deref(),
+            Some("Mozilla Thunderbird")
+        );
+        assert_eq!(parse_originating_ip("not an ip"), None);
+        assert_eq!(
+            parse_originating_ip("2001:db8::1 (via proxy)").as_deref(),
+            Some("2001:db8::1")
+        );
+    }
+
+    #[test]
+    fn detects_encrypted_attachment_containers() {
+        let mut zip = b"PK\x03\x04\x14\x00".to_vec();
+        zip.extend_from_slice(&[0x01, 0x00]);
+        zip.extend_from_slice(&[0u8; 30]);
+        assert!(is_encrypted_content(&zip));
+
+        let mut plain_zip = b"PK\x03\x04\x14\x00".to_vec();
+        plain_zip.extend_from_slice(&[0x00, 0x00]);
+        plain_zip.extend_from_slice(&[0u8; 30]);
+        assert!(!is_encrypted_content(&plain_zip));
+
+        assert!(is_encrypted_content(b"%PDF-1.7\n...trailer << /Encrypt 5 0 R >>"));
+        assert!(!is_encrypted_content(b"%PDF-1.7\n...trailer << /Root 1 0 R >>"));
+
+        let mut ole = OLE2_MAGIC.to_vec();
+        ole.extend_from_slice(&utf16le("EncryptedPackage"));
+        assert!(is_encrypted_content(&ole));
+        assert!(!is_encrypted_content(b"just some text"));
+    }
+
+    #[test]
+    fn source_filter_matches_paths_folders_and_email_ids() {
+        let filter = SourceFilter::parse(
+            "# rerun after fix\nInbox/Projects\n/Sent Items/12.eml\n\
+             0b7c5f0e-8a52-5d3e-9f1a-2f6c1b2a3d4e\n",
+        );
+        assert!(filter.matches_path("Inbox/Projects/3.eml"));
+        assert!(!filter.matches_path("Inbox/ProjectsOld/3.eml"));
+        assert!(filter.matches_path("Sent Items/12.eml"));
+        assert!(filter.matches("Other/1.eml", "0b7c5f0e-8a52-5d3e-9f1a-2f6c1b2a3d4e"));
+        assert!(!filter.matches("Other/1.eml", "11111111-1111-5111-8111-111111111111"));
+        assert!(filter.may_contain("Other/1.eml"));
+    }
+
+    #[test]
+    fn brotli_bodies_round_trip() {
+        let html = "<html><body>".to_string() + &"<p>repeated paragraph</p>".repeat(200);
+        let compressed = brotli_compress(html.as_bytes(), 9).expect("compress");
+        assert!(compressed.len() < html.len() / 10);
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(compressed.as_slice(), 4096)
+            .read_to_end(&mut decoded)
+            .expect("decompress");
+        assert_eq!(decoded, html.as_bytes());
+    }
+
+    #[test]
+    fn unpacks_winmail_dat_into_attachments() {
+        let mut tnef = Vec::new();
+        tnef.extend_from_slice(&0x223E_9F78u32.to_le_bytes());
+        tnef.extend_from_slice(&1u16.to_le_bytes());
+        for (level, tag, value) in [
+            (1u8, 0x0002_800Cu32, &b"Body from TNEF\0"[..]),
+            (2, 0x0006_9002, &[0u8; 14][..]),
+            (2, 0x0001_8010, &b"budget.xlsx\0"[..]),
+            (2, 0x0006_800F, &b"sheet-bytes"[..]),
+        ] {
+            tnef.push(level);
+            tnef.extend_from_slice(&tag.to_le_bytes());
+            tnef.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            tnef.extend_from_slice(value);
+            tnef.extend_from_slice(&[0, 0]);
+        }
+
+        let mut attachments = vec![ParsedAttachment {
+            part_idx: 0,
+            is_encrypted_attachment: false,
+            filename: "winmail.dat".to_string(),
+            content_type: Some("application/ms-tnef".to_string()),
+            is_inline: false,
+            content_id: None,
+            source_container: None,
+            charset_issues: false,
+            content: tnef,
+        }];
+        let mut flags = Vec::new();
+        let (text, html) = expand_tnef_attachments(&mut attachments, None, None, &mut flags);
+        assert_eq!(text.as_deref(), Some("Body from TNEF"));
+        assert!(html.is_none());
+        assert_eq!(flags, vec![ProcessingFlag::TnefDecoded]);
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].filename, "budget.xlsx");
+        assert_eq!(attachments[0].content, b"sheet-bytes");
+        assert_eq!(attachments[0].part_idx, 1);
+        assert_eq!(attachments[0].source_container.as_deref(), Some("winmail.dat"));
+    }
+
+    #[test]
+    fn recovers_body_from_rtf_only_message() {
+        let raw = b"From: a@example.com\r\nSubject: rtf\r\nMIME-Version: 1.0\r\nContent-Type: multipart/mixed; boundary=\"b\"\r\n\r\n--b\r\nContent-Type: application/rtf\r\nContent-Disposition: attachment; filename=\"rtf-body.rtf\"\r\n\r\n{\\rtf1\\ansi{\\fonttbl{\\f0 Arial;}}\\f0 Meeting moved to Friday.\\par}\r\n--b--\r\n";
+        let mail = mailparse::parse_mail(raw).expect("parse");
+        let mut flags = Vec::new();
+        let (text, html) = select_email_bodies(&mail, &mut flags);
+        assert_eq!(text.as_deref(), Some("Meeting moved to Friday."));
+        assert!(html.is_none());
+        assert_eq!(flags, vec![ProcessingFlag::RtfDerivedBody]);
+    }
+
+    #[test]
+    fn parses_embedded_messages_recursively() {
+        let raw = b"From: outer@example.com\r\nSubject: Fwd: report\r\nContent-Type: multipart/mixed; boundary=\"b\"\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nSee below.\r\n--b\r\nContent-Type: message/rfc822\r\n\r\nFrom: inner@example.com\r\nSubject: report\r\nContent-Type: multipart/mixed; boundary=\"c\"\r\n\r\n--c\r\nContent-Type: text/plain\r\n\r\nInner body.\r\n--c\r\nContent-Type: application/pdf\r\nContent-Disposition: attachment; filename=\"report.pdf\"\r\n\r\n%PDF-1.4\r\n--c--\r\n\r\n--b--\r\n";
+        let msg = parse_message(raw).expect("parse");
+        assert!(msg.attachments.is_empty());
+        assert_eq!(msg.embedded.len(), 1);
+        let (part_idx, child) = &msg.embedded[0];
+        assert_eq!(*part_idx, 0);
+        assert_eq!(child.subject.as_deref(), Some("report"));
+        assert_eq!(child.body_text.as_deref().map(str::trim), Some("Inner body."));
+        assert_eq!(child.attachments.len(), 1);
+        assert_eq!(child.attachments[0].filename, "report.pdf");
+    }
+
+    #[test]
+    fn family_entry_names_are_unique_per_family() {
+        let mut used = HashSet::new();
+        assert_eq!(family_entry_name(&mut used, "Notice.pdf"), "attachments/Notice.pdf");
+        assert_eq!(family_entry_name(&mut used, "notice.PDF"), "attachments/notice (2).PDF");
+        assert_eq!(family_entry_name(&mut used, "Notice.pdf"), "attachments/Notice (3).pdf");
+        assert_eq!(family_entry_name(&mut used, ".profile"), "attachments/.profile");
+    }
+
+    #[test]
+    fn maildir_zip_unpacks_only_delivered_messages() {
+        let dir = std::env::temp_dir().join(format!("maildir-{}", Uuid::new_v4()));
+        let zip_path = dir.join("mail.zip");
+        fs::create_dir_all(&dir).expect("dir");
+        let mail: &[u8] = b"From: a@example.com\r\nSubject: hi\r\n\r\nbody\r\n";
+        fs::write(
+            &zip_path,
+            ziparchive::build(&[
+                ("Maildir/cur/1700000000.M1P1.host:2,S", mail),
+                ("Maildir/new/1700000001.M2P1.host", mail),
+                ("Maildir/tmp/1700000002.M3P1.host", mail),
+                ("Maildir/.Projects/cur/1700000003.M4P1.host:2,", mail),
+                ("Maildir/dovecot-uidlist", b"3 V1 N4"),
+                ("../escape/cur/evil", mail),
+            ]),
+        )
+        .expect("zip");
+        let out = dir.join("extract");
+        let written =
+            unpack_loose_zip(&zip_path, InputFormat::Maildir, &out, 1024).expect("unpack");
+        assert_eq!(written, 4);
+        assert!(out.join("Maildir/cur/1700000000.M1P1.host:2,S").is_file());
+        assert!(out.join("Maildir/.Projects/cur/1700000003.M4P1.host:2,").is_file());
+        assert!(!out.join("Maildir/tmp").exists());
+        assert!(out.join("escape/cur/evil").is_file());
+        assert!(!InputFormat::EmlArchive.is_message_path("Inbox/notes.txt"));
+        assert!(InputFormat::EmlArchive.is_message_path("Inbox/1.EML"));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recovery_mode_keeps_partial_output_and_adds_deleted_items() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = std::env::temp_dir().join(format!("recovery-{}", Uuid::new_v4()));
+        let extract = root.join("extract");
+        fs::create_dir_all(&extract).expect("dir");
+        // Fake readpst: the normal pass writes one message then dies on a "corrupt page"; the -D
+        // pass gets further and also finds a deleted message.
+        let shim = root.join("readpst");
+        fs::write(
+            &shim,
+            "#!/bin/sh\nfor a; do case \"$a\" in -D) deleted=1;; esac; done\n\
+             out=\"\"; while [ $# -gt 0 ]; do [ \"$1\" = -o ] && out=\"$2\"; shift; done\n\
+             mkdir -p \"$out/Inbox\"; printf 'Subject: kept\\n\\nbody\\n' > \"$out/Inbox/1\"\n\
+             printf '\\t\"Inbox\" - 1 items done, 0 items skipped.\\n'\n\
+             [ -n \"$deleted\" ] || exit 1\n\
+             printf 'Subject: deleted\\n\\nbody\\n' > \"$out/Inbox/2\"\n",
+        )
+        .expect("shim");
+        fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).expect("chmod");
+
+        let mut counts = itemcounts::CountCheck::default();
+        let (failed, recovered, stopped) = run_readpst_recovery(
+            shim.to_str().expect("path"),
+            &root.join("input.pst"),
+            &extract,
+            &root,
+            1,
+            &mut counts,
+            ReadpstLimits::default(),
+        )
+        .expect("recovery");
+        assert!(failed);
+        assert_eq!(stopped, None);
+        assert_eq!(counts.report().pst_items_total, 1);
+        assert_eq!(recovered, 1);
+        assert!(extract.join("Inbox/1").is_file());
+        let salvaged = fs::read_to_string(extract.join(RECOVERED_DIR).join("Inbox/2")).expect("2");
+        assert!(salvaged.starts_with("Subject: deleted"));
+        assert!(!root.join("extract-deleted").exists());
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn readpst_is_stopped_at_the_deadline_or_on_low_space() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = std::env::temp_dir().join(format!("deadline-{}", Uuid::new_v4()));
+        let extract = root.join("extract");
+        fs::create_dir_all(&extract).expect("dir");
+        // Fake readpst: writes one message, then hangs on the next folder.
+        let shim = root.join("readpst");
+        fs::write(
+            &shim,
+            "#!/bin/sh\nout=\"\"; while [ $# -gt 0 ]; do [ \"$1\" = -o ] && out=\"$2\"; shift; done\n\
+             mkdir -p \"$out/Inbox\"; printf 'Subject: kept\\n\\nbody\\n' > \"$out/Inbox/1\"\n\
+             exec sleep 30\n",
+        )
+        .expect("shim");
+        fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).expect("chmod");
+
+        let started = Instant::now();
+        let limits = ReadpstLimits {
+            deadline: Some(started + std::time::Duration::from_millis(300)),
+            ..Default::default()
+        };
+        let stopped = run_readpst(
+            shim.to_str().expect("path"),
+            &root.join("input.pst"),
+            &extract,
+            false,
+            1,
+            None,
+            limits,
+        )
+        .expect("readpst");
+        assert_eq!(stopped, Some(watchdog::Stop::MaxDuration));
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        assert!(extract.join("Inbox/1").is_file());
+
+        // Scratch space "below" the threshold fails the run instead.
+        let limits = ReadpstLimits {
+            min_free_bytes: u64::MAX,
+            ..Default::default()
+        };
+        let err = run_readpst(
+            shim.to_str().expect("path"),
+            &root.join("input.pst"),
+            &extract,
+            false,
+            1,
+            None,
+            limits,
+        )
+        .expect_err("low space");
+        assert!(err.to_string().contains("--min-free-bytes"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(20));
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn extract_jobs_follows_extract_concurrency() {
+        assert_eq!(extract_jobs(1), 1);
+        assert_eq!(extract_jobs(12), 12);
+        assert!((1..=8).contains(&extract_jobs(0)));
+    }
+
+    #[test]
+    fn shards_files_by_top_level_folder_below_the_root() {
+        let paths = [
+            "Outlook Data File/Inbox/1",
+            "Outlook Data File/Inbox/2",
+            "Outlook Data File/Inbox/Projects/1",
+            "Outlook Data File/Sent Items/1",
+            "Outlook Data File/mbox",
+            "_recovered/Outlook Data File/Inbox/3",
+            "Outlook Data File/Inbox/4",
+        ];
+        assert_eq!(folder_shards(&paths), vec![0..3, 3..4, 4..5, 5..6, 6..7]);
+        // The test shim's layout has no root folder.
+        assert_eq!(
+            folder_shards(&["Inbox/1", "Inbox/2", "Sent Items/1"]),
+            vec![0..2, 2..3]
+        );
+        assert!(folder_shards(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn json_outputs_go_to_any_object_store() {
+        let s3 = aws_sdk_s3::Client::from_conf(
+            aws_sdk_s3::Config::builder()
+                .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+                .build(),
+        );
+        let scratch = std::env::temp_dir().join(format!("json-output-{}", Uuid::new_v4()));
+        fs::create_dir_all(&scratch).expect("scratch");
+
+        let (endpoint, requests) = storage::fake_http("200 OK", "{}");
+        // Nothing else in the tests opens a gs:// store, so the override can't leak.
+        std::env::set_var("GCS_ENDPOINT", &endpoint);
+        let bucket = format!("results-{}", Uuid::new_v4());
+        let output = format!("gs://{bucket}/runs/result.json");
+        write_json_output(&s3, &output, br#"{"status":"ok"}"#, &scratch)
+            .await
+            .expect("gs upload");
+        let request = requests.recv().expect("request");
+        assert!(
+            request.starts_with(&format!(
+                "post /upload/storage/v1/b/{bucket}/o?uploadtype=media&name=runs%2fresult.json "
+            )),
+            "{request}"
+        );
+        assert!(request.contains("content-type: application/json"));
+        assert!(request.ends_with(r#"{"status":"ok"}"#));
+        assert!(write_json_output(&s3, "gs://bucket-only", b"{}", &scratch)
+            .await
+            .is_err());
+
+        // Anything else is a local path.
+        let local = scratch.join("result.json");
+        write_json_output(&s3, local.to_str().expect("path"), b"{}", &scratch)
+            .await
+            .expect("local");
+        assert_eq!(fs::read(&local).expect("read"), b"{}");
+        fs::remove_dir_all(&scratch).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn abandoned_readpst_run_is_killed_and_reaped() {
+        let child = Command::new("sleep").arg("30").spawn().expect("sleep");
+        let pid = child.id().to_string();
+        let mut run = ReadpstRun::new(child);
+        let (done, rx) = std::sync::mpsc::channel::<()>();
+        run.done = Some(done);
+        run.watchdog = Some(std::thread::spawn(move || {
+            let _ = rx.recv();
+        }));
+        let started = Instant::now();
+        // As on an early `?` return: no finish().
+        drop(run);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        let alive = Command::new("kill")
+            .args(["-0", &pid])
+            .stderr(Stdio::null())
+            .status()
+            .expect("kill -0");
+        assert!(!alive.success(), "readpst {pid} still running");
+    }
+
+    #[test]
+    fn retry_backoff_is_jittered_and_capped() {
+        for attempt in 1..40 {
+            let ceiling = (200u64 << (attempt - 1).min(16)).min(20_000);
+            assert!(retry_backoff(attempt) <= std::time::Duration::from_millis(ceiling));
+        }
+        let spread: std::collections::HashSet<_> = (0..20).map(|_| retry_backoff(6)).collect();
+        assert!(spread.len() > 1);
+    }
+
+    #[test]
+    fn bounce_report_fills_delivery_status() {
+        let raw = b"From: MAILER-DAEMON@contractor.example\r\nSubject: Undelivered Mail Returned to Sender\r\nContent-Type: multipart/report; report-type=delivery-status; boundary=\"r\"\r\n\r\n--r\r\nContent-Type: text/plain\r\n\r\nYour message could not be delivered.\r\n--r\r\nContent-Type: message/delivery-status\r\n\r\nReporting-MTA: dns; mail.contractor.example\r\n\r\nFinal-Recipient: rfc822; old.pm@client.example\r\nAction: failed\r\nStatus: 5.1.1\r\nRemote-MTA: dns; mx.client.example\r\n\r\n--r\r\nContent-Type: text/rfc822-headers\r\n\r\nSubject: Programme rev C\r\n--r--\r\n";
+        let msg = parse_message(raw).expect("parse");
+        let report = msg.delivery_status.expect("dsn");
+        assert_eq!(report.recipients.len(), 1);
+        assert_eq!(report.recipients[0].status.as_deref(), Some("5.1.1"));
+        assert_eq!(report.recipients[0].remote_mta.as_deref(), Some("mx.client.example"));
+        assert_eq!(report.failed_recipients().collect::<Vec<_>>(), ["old.pm@client.example"]);
+        assert!(msg.attachments.is_empty());
+    }
+
+    #[test]
+    fn journal_report_is_unwrapped_into_the_original() {
+        let raw = b"From: journal@contoso.com\r\nSubject: Q3 numbers\r\nX-MS-Journal-Report:\r\nContent-Type: multipart/mixed; boundary=\"j\"\r\n\r\n--j\r\nContent-Type: text/plain\r\n\r\nSender: alice@contoso.com\r\nSubject: Q3 numbers\r\nMessage-Id: <q3@contoso.com>\r\nTo: bob@fabrikam.com\r\nBcc: dan@contoso.com, Expanded: board@contoso.com\r\n--j\r\nContent-Type: message/rfc822\r\n\r\nFrom: alice@contoso.com\r\nTo: bob@fabrikam.com\r\nMessage-ID: <q3@contoso.com>\r\nSubject: Q3 numbers\r\nContent-Type: multipart/mixed; boundary=\"o\"\r\n\r\n--o\r\nContent-Type: text/plain\r\n\r\nDraft attached.\r\n--o\r\nContent-Type: application/pdf\r\nContent-Disposition: attachment; filename=\"q3.pdf\"\r\n\r\n%PDF-1.4\r\n--o--\r\n\r\n--j--\r\n";
+        let msg = parse_message(raw).expect("parse");
+        assert_eq!(msg.sender_email.as_deref(), Some("alice@contoso.com"));
+        assert_eq!(msg.body_text.as_deref().map(str::trim), Some("Draft attached."));
+        assert_eq!(msg.attachments.len(), 1);
+        assert!(msg.embedded.is_empty());
+        let envelope = msg.journal.expect("envelope");
+        assert_eq!(envelope.bcc_emails(), ["dan@contoso.com"]);
+        assert_eq!(envelope.recipients[1].expanded_from.as_deref(), Some("board@contoso.com"));
+    }
+
+    #[test]
+    fn journal_report_parts_are_carried_over_to_the_original() {
+        let raw = concat!(
+            "From: journal@contoso.com\r\nSubject: Q3 numbers\r\nX-MS-Journal-Report:\r\n",
+            "Content-Type: multipart/mixed; boundary=\"j\"\r\n\r\n",
+            "--j\r\nContent-Type: text/plain\r\n\r\n",
+            "Sender: alice@contoso.com\r\nMessage-Id: <q3@contoso.com>\r\nTo: bob@fabrikam.com\r\n",
+            "--j\r\nContent-Type: message/rfc822\r\n\r\n",
+            "From: alice@contoso.com\r\nMessage-ID: <q3@contoso.com>\r\nSubject: Q3 numbers\r\n",
+            "Content-Type: multipart/mixed; boundary=\"o\"\r\n\r\n",
+            "--o\r\nContent-Type: text/plain\r\n\r\nDraft attached.\r\n",
+            "--o\r\nContent-Type: application/pdf\r\n",
+            "Content-Disposition: attachment; filename=\"q3.pdf\"\r\n\r\n%PDF-1.4\r\n--o--\r\n",
+            "--j\r\nContent-Type: text/csv\r\n",
+            "Content-Disposition: attachment; filename=\"recipients.csv\"\r\n\r\nbob\r\n",
+            "--j\r\nContent-Type: message/rfc822\r\n\r\n",
+            "From: bob@fabrikam.com\r\nMessage-ID: <re@fabrikam.com>\r\nSubject: Re: Q3\r\n\r\n",
+            "Thanks.\r\n--j--\r\n",
+        );
+        let msg = parse_message(raw.as_bytes()).expect("parse");
+        assert!(msg.journal.is_some());
+        assert_eq!(msg.message_id.as_deref(), Some("<q3@contoso.com>"));
+        let attachments: Vec<_> = msg
+            .attachments
+            .iter()
+            .map(|a| (a.filename.as_str(), a.source_container.as_deref()))
+            .collect();
+        assert_eq!(
+            attachments,
+            [("q3.pdf", None), ("recipients.csv", Some(JOURNAL_REPORT_CONTAINER))]
+        );
+        assert_eq!(msg.embedded.len(), 1);
+        assert_eq!(msg.embedded[0].1.message_id.as_deref(), Some("<re@fabrikam.com>"));
+        // Carried parts are numbered after the original's own.
+        let own = msg.attachments[0].part_idx;
+        assert!(msg.attachments[1].part_idx > own && msg.embedded[0].0 > own);
+        assert_ne!(msg.attachments[1].part_idx, msg.embedded[0].0);
+    }
+
+    #[test]
+    fn multipart_signed_keeps_signature_out_of_attachments() {
+        let raw = b"From: signer@example.com\r\nSubject: signed\r\nContent-Type: multipart/signed; protocol=\"application/pkcs7-signature\"; micalg=sha-256; boundary=\"s\"\r\n\r\n--s\r\nContent-Type: multipart/mixed; boundary=\"m\"\r\n\r\n--m\r\nContent-Type: text/plain\r\n\r\nSigned body.\r\n--m\r\nContent-Type: application/pdf\r\nContent-Disposition: attachment; filename=\"valuation.pdf\"\r\n\r\n%PDF-1.4\r\n--m--\r\n\r\n--s\r\nContent-Type: application/pkcs7-signature; name=\"smime.p7s\"\r\nContent-Disposition: attachment; filename=\"smime.p7s\"\r\nContent-Transfer-Encoding: base64\r\n\r\nMIIGc2lnbmF0dXJl\r\n--s--\r\n";
+        let msg = parse_message(raw).expect("parse");
+        assert_eq!(msg.body_text.as_deref().map(str::trim), Some("Signed body."));
+        let names: Vec<_> = msg.attachments.iter().map(|a| a.filename.as_str()).collect();
+        assert_eq!(names, ["valuation.pdf"]);
+        let sig = msg.signature.expect("signature");
+        assert_eq!(sig.protocol, "application/pkcs7-signature");
+        assert_eq!(sig.extension(), "p7s");
+        assert!(!sig.content.is_empty());
+    }
+
+    #[test]
+    fn decodes_encoded_subject_sender_and_filenames() {
+        let raw = b"From: =?ISO-8859-1?Q?Andr=E9?= =?ISO-8859-1?Q?_M=FCller?= <andre@example.com>\r\nSubject: =?UTF-8?Q?Caf=C3=A9?==?UTF-8?Q?_order?=\r\nContent-Type: multipart/mixed; boundary=\"b\"\r\n\r\n--b\r\nContent-Type: text/plain\r\n\r\nhi\r\n--b\r\nContent-Type: application/pdf\r\nContent-Disposition: attachment; filename*0*=UTF-8''r%C3%A9sum; filename*1=\"e.pdf\"\r\n\r\n%PDF-1.4\r\n--b--\r\n";
+        let msg = parse_message(raw).expect("parsed");
+        assert_eq!(msg.subject.as_deref(), Some("Café order"));
+        assert_eq!(msg.sender_name.as_deref(), Some("André Müller"));
+        assert_eq!(msg.sender_email.as_deref(), Some("andre@example.com"));
+        assert_eq!(msg.attachments[0].filename, "résume.pdf");
+        assert!(!msg.charset_issues && !msg.attachments[0].charset_issues);
+    }
+
+    #[test]
+    fn sniffs_mislabelled_and_unlabelled_charsets() {
+        let mut raw = b"From: a@example.com\r\nSubject: Caf\xe9 order\r\nContent-Type: multipart/mixed; boundary=\"b\"\r\n\r\n--b\r\nContent-Type: text/plain; charset=iso-8859-1\r\n\r\nR\xc3\xa9sum\xc3\xa9 attached\r\n--b\r\nContent-Type: application/octet-stream; name=\"".to_vec();
+        raw.extend(encoding_rs::SHIFT_JIS.encode("資料について.txt").0.iter());
+        raw.extend(b"\"\r\nContent-Disposition: attachment\r\n\r\nx\r\n--b--\r\n");
+        let msg = parse_message(&raw).expect("parsed");
+        assert_eq!(msg.subject.as_deref(), Some("Café order"));
+        assert_eq!(msg.body_text.as_deref().map(str::trim), Some("Résumé attached"));
+        assert!(msg.charset_issues);
+        assert!(msg.processing_flags.contains(&ProcessingFlag::CharsetGuessed));
+        assert_eq!(msg.attachments[0].filename, "資料について.txt");
+        assert!(msg.attachments[0].charset_issues);
+    }
+
+    #[test]
+    fn parses_recipient_address_lists() {
+        let raw = b"To: \"Smith, John\" <John.Smith@Example.com>, b@y.org, john.smith@example.com\r\nCc: Smith, John <j@x.com>; Doe, Jane <D@X.com>\r\nBcc: undisclosed-recipients:;\r\n\r\nbody";
+        let mail = mailparse::parse_mail(raw).expect("parse");
+        assert_eq!(
+            header_addresses(&mail, "To"),
+            vec!["john.smith@example.com", "b@y.org"]
+        );
+        assert_eq!(header_addresses(&mail, "Cc"), vec!["j@x.com", "d@x.com"]);
+        assert!(header_addresses(&mail, "Bcc").is_empty());
+        assert!(header_addresses(&mail, "Reply-To").is_empty());
+    }
+
+    #[test]
+    fn ndjson_schema_lists_every_record_field() {
+        fn keys<T: Serialize>(record: &T) -> Vec<String> {
+            let value = serde_json::to_value(record).expect("record");
+            let mut keys: Vec<String> = value.as_object().expect("object").keys().cloned().collect();
+            keys.sort();
+            keys
+        }
+        fn names(fields: &[schema::Field]) -> Vec<String> {
+            let mut names: Vec<String> = fields.iter().map(|f| f.name.to_string()).collect();
+            names.sort();
+            names
+        }
+        assert_eq!(keys(&EmailRecord::default()), names(schema::EMAIL_FIELDS));
+        assert_eq!(keys(&AttachmentRecord::default()), names(schema::ATTACHMENT_FIELDS));
+    }
+}
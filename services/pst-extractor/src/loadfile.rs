@@ -0,0 +1,646 @@
+//! Review-platform load files (`--loadfile concordance|edrm`).
+//!
+//! Traditional review platforms ingest a production as natives, extracted text and a load file
+//! that ties them to document metadata. After the NDJSON outputs are written, they are re-read
+//! to produce:
+//!
+//! * `text/{id}.txt`: body_text for each email, and the extracted text of each attachment
+//!   (with `--attachment-text`);
+//! * `loadfile.dat` (Concordance: UTF-8 with BOM, CRLF lines, `þ`-quoted fields separated by
+//!   DC4, embedded newlines as `®`) or `loadfile.xml` (EDRM XML 1.2, attachments as
+//!   `Relationship` elements).
+//!
+//! Documents are in family order: each email, then its attachments. Native and text paths are
+//! relative to the output prefix. Email natives are the `.eml` objects from `--export-raw-eml`;
+//! without it emails have no native path. With `--control-number-prefix`, control numbers are
+//! the document ids (and parent/family ids); otherwise record ids are. No OPT file is written: OPT cross-references page
+//! images, which the extractor does not render.
+
+use crate::gzmembers::OutputCompression;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LoadfileFormat {
+    Concordance,
+    Edrm,
+}
+
+/// Concordance DAT delimiters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delimiters {
+    pub field: char,
+    pub quote: char,
+    /// Stands in for line breaks inside a value.
+    pub newline: char,
+    /// Separates the values of a multi-value field (recipients).
+    pub multi: char,
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Self {
+            field: '\u{14}',
+            quote: 'þ',
+            newline: '®',
+            multi: ';',
+        }
+    }
+}
+
+/// A delimiter argument: one character, or its code point as `0x14` / `U+00FE`.
+pub fn parse_delimiter(value: &str) -> Result<char, String> {
+    let mut chars = value.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(c);
+    }
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("U+"))
+        .or_else(|| value.strip_prefix("u+"))
+        .ok_or_else(|| format!("invalid delimiter {value:?} (one character, 0xHH or U+HHHH)"))?;
+    u32::from_str_radix(hex, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| format!("invalid delimiter code point {value:?}"))
+}
+
+const DAT_FIELDS: &[&str] = &[
+    "DOCID",
+    "PARENTID",
+    "FAMILYID",
+    "DOCTYPE",
+    "CUSTODIAN",
+    "FROM",
+    "TO",
+    "CC",
+    "BCC",
+    "SUBJECT",
+    "DATESENT",
+    "TIMESENT",
+    "FILENAME",
+    "FILESIZE",
+    "SHA256",
+    "NATIVEPATH",
+    "TEXTPATH",
+];
+
+#[derive(Deserialize)]
+struct EmailFields {
+    id: String,
+    parent_email_id: Option<String>,
+    family_id: String,
+    custodian_name: Option<String>,
+    from: Option<String>,
+    #[serde(default)]
+    to_emails: Vec<String>,
+    #[serde(default)]
+    cc_emails: Vec<String>,
+    #[serde(default)]
+    bcc_emails: Vec<String>,
+    subject: Option<String>,
+    date_epoch: Option<i64>,
+    body_text: Option<String>,
+    raw_eml_s3_key: Option<String>,
+    raw_sha256: Option<String>,
+    control_number: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AttachmentFields {
+    id: String,
+    email_message_id: String,
+    custodian_name: Option<String>,
+    filename: String,
+    file_size_bytes: u64,
+    s3_key: String,
+    attachment_hash: String,
+    control_number: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AttachmentText {
+    attachment_id: String,
+    text: String,
+}
+
+/// One load file row.
+#[derive(Default)]
+struct Document {
+    doc_id: String,
+    parent_id: Option<String>,
+    family_id: String,
+    is_email: bool,
+    custodian: Option<String>,
+    from: Option<String>,
+    to: Vec<String>,
+    cc: Vec<String>,
+    bcc: Vec<String>,
+    subject: Option<String>,
+    date_epoch: Option<i64>,
+    filename: Option<String>,
+    file_size: Option<u64>,
+    sha256: Option<String>,
+    native_path: Option<String>,
+    text_path: Option<String>,
+}
+
+/// What [`write`] produced, relative to the output directory.
+#[derive(Debug)]
+pub struct Written {
+    pub loadfile: String,
+    pub text_files: Vec<String>,
+    pub documents: usize,
+}
+
+pub struct Inputs<'a> {
+    pub emails: &'a Path,
+    pub attachments: &'a Path,
+    pub attachment_text: Option<&'a Path>,
+    pub compression: OutputCompression,
+    /// Output prefix, stripped from object keys to make paths relative.
+    pub key_prefix: &'a str,
+}
+
+/// Write the load file and text files into `out_dir`.
+pub fn write(
+    format: LoadfileFormat,
+    delimiters: Delimiters,
+    inputs: &Inputs,
+    out_dir: &Path,
+) -> Result<Written> {
+    let text_dir = out_dir.join("text");
+    fs::create_dir_all(&text_dir)?;
+    let mut text_files = Vec::new();
+    let mut write_text = |id: &str, text: &str| -> Result<String> {
+        let name = format!("text/{id}.txt");
+        fs::write(out_dir.join(&name), text)?;
+        text_files.push(name.clone());
+        Ok(name)
+    };
+
+    let mut attachment_texts = HashSet::new();
+    if let Some(path) = inputs.attachment_text {
+        read_ndjson(path, OutputCompression::default(), |t: AttachmentText| {
+            write_text(&t.attachment_id, &t.text)?;
+            attachment_texts.insert(t.attachment_id);
+            Ok(())
+        })?;
+    }
+    let mut attachments: HashMap<String, Vec<AttachmentFields>> = HashMap::new();
+    read_ndjson(
+        inputs.attachments,
+        inputs.compression,
+        |a: AttachmentFields| {
+            attachments
+                .entry(a.email_message_id.clone())
+                .or_default()
+                .push(a);
+            Ok(())
+        },
+    )?;
+
+    let name = match format {
+        LoadfileFormat::Concordance => "loadfile.dat",
+        LoadfileFormat::Edrm => "loadfile.xml",
+    };
+    let mut out = BufWriter::new(File::create(out_dir.join(name))?);
+    let mut relationships = Vec::new();
+    match format {
+        LoadfileFormat::Concordance => {
+            // UTF-8 BOM, so platforms don't guess a legacy code page.
+            out.write_all("\u{feff}".as_bytes())?;
+            let header: Vec<String> = DAT_FIELDS.iter().map(|f| f.to_string()).collect();
+            write_dat_row(&mut out, &delimiters, &header)?;
+        }
+        LoadfileFormat::Edrm => {
+            writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            writeln!(out, r#"<Root MajorVersion="1" MinorVersion="2">"#)?;
+            writeln!(out, "  <Batch>\n    <Documents>")?;
+        }
+    }
+    let relative = |key: &str| -> Option<String> {
+        (!key.is_empty()).then(|| {
+            key.strip_prefix(inputs.key_prefix)
+                .unwrap_or(key)
+                .to_string()
+        })
+    };
+    let mut documents = 0;
+    // Email id -> document id, for parent and family ids (parents come before their children).
+    let mut doc_ids: HashMap<String, String> = HashMap::new();
+    read_ndjson(inputs.emails, inputs.compression, |email: EmailFields| {
+        let text_path = match email.body_text.as_deref() {
+            Some(text) if !text.is_empty() => Some(write_text(&email.id, text)?),
+            _ => None,
+        };
+        let doc_id = email.control_number.unwrap_or_else(|| email.id.clone());
+        doc_ids.insert(email.id.clone(), doc_id.clone());
+        let doc_id_of = |id: &String| doc_ids.get(id).cloned().unwrap_or_else(|| id.clone());
+        let family_id = doc_id_of(&email.family_id);
+        let mut family = vec![Document {
+            parent_id: email.parent_email_id.as_ref().map(doc_id_of),
+            family_id: family_id.clone(),
+            is_email: true,
+            custodian: email.custodian_name,
+            from: email.from,
+            to: email.to_emails,
+            cc: email.cc_emails,
+            bcc: email.bcc_emails,
+            subject: email.subject,
+            date_epoch: email.date_epoch,
+            sha256: email.raw_sha256,
+            native_path: email.raw_eml_s3_key.as_deref().and_then(relative),
+            text_path,
+            doc_id: doc_id.clone(),
+            ..Default::default()
+        }];
+        for att in attachments.remove(&email.id).unwrap_or_default() {
+            let text_path = attachment_texts
+                .contains(&att.id)
+                .then(|| format!("text/{}.txt", att.id));
+            family.push(Document {
+                parent_id: Some(doc_id.clone()),
+                family_id: family_id.clone(),
+                custodian: att.custodian_name,
+                filename: Some(att.filename),
+                file_size: Some(att.file_size_bytes),
+                sha256: Some(att.attachment_hash),
+                native_path: relative(&att.s3_key),
+                text_path,
+                doc_id: att.control_number.unwrap_or(att.id),
+                ..Default::default()
+            });
+        }
+        for doc in &family {
+            match format {
+                LoadfileFormat::Concordance => {
+                    write_dat_row(&mut out, &delimiters, &dat_values(doc, &delimiters))?
+                }
+                LoadfileFormat::Edrm => write_edrm_document(&mut out, doc)?,
+            }
+            if let Some(parent) = &doc.parent_id {
+                relationships.push((parent.clone(), doc.doc_id.clone(), doc.is_email));
+            }
+            documents += 1;
+        }
+        Ok(())
+    })?;
+    if format == LoadfileFormat::Edrm {
+        writeln!(out, "    </Documents>\n    <Relationships>")?;
+        for (parent, child, is_email) in &relationships {
+            // Embedded messages and attachments are both children of their enclosing email.
+            let kind = if *is_email { "Embedded" } else { "Attachment" };
+            writeln!(
+                out,
+                r#"      <Relationship Type="{kind}" ParentDocId="{}" ChildDocId="{}"/>"#,
+                xml_escape(parent),
+                xml_escape(child)
+            )?;
+        }
+        writeln!(out, "    </Relationships>\n  </Batch>\n</Root>")?;
+    }
+    out.flush()?;
+    Ok(Written {
+        loadfile: name.to_string(),
+        text_files,
+        documents,
+    })
+}
+
+fn read_ndjson<T: for<'de> Deserialize<'de>>(
+    path: &Path,
+    compression: OutputCompression,
+    mut each: impl FnMut(T) -> Result<()>,
+) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    for line in BufReader::new(compression.reader(file)?).lines() {
+        let line = line?;
+        if !line.is_empty() {
+            each(
+                serde_json::from_str(&line).with_context(|| format!("parse {}", path.display()))?,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// `YYYY-MM-DD` and `HH:MM:SS` (UTC).
+fn date_time(epoch: i64) -> (String, String) {
+    let stamp = crate::dates::format_rfc3339_millis(epoch * 1000);
+    (stamp[..10].to_string(), stamp[11..19].to_string())
+}
+
+fn dat_values(doc: &Document, delimiters: &Delimiters) -> Vec<String> {
+    let multi = |values: &[String]| values.join(&format!("{} ", delimiters.multi));
+    let (date, time) = doc.date_epoch.map(date_time).unwrap_or_default();
+    let opt = |value: &Option<String>| value.clone().unwrap_or_default();
+    vec![
+        doc.doc_id.clone(),
+        opt(&doc.parent_id),
+        doc.family_id.clone(),
+        if doc.is_email { "Email" } else { "Attachment" }.to_string(),
+        opt(&doc.custodian),
+        opt(&doc.from),
+        multi(&doc.to),
+        multi(&doc.cc),
+        multi(&doc.bcc),
+        opt(&doc.subject),
+        date,
+        time,
+        opt(&doc.filename),
+        doc.file_size.map(|n| n.to_string()).unwrap_or_default(),
+        opt(&doc.sha256),
+        opt(&doc.native_path),
+        opt(&doc.text_path),
+    ]
+}
+
+fn write_dat_row(out: &mut impl Write, delimiters: &Delimiters, values: &[String]) -> Result<()> {
+    let mut line = String::new();
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            line.push(delimiters.field);
+        }
+        line.push(delimiters.quote);
+        // Delimiter characters can't be escaped in a DAT; they are dropped from values.
+        let value = value.replace("\r\n", "\n");
+        for c in value.chars() {
+            match c {
+                '\n' | '\r' => line.push(delimiters.newline),
+                c if c == delimiters.quote || c == delimiters.field => {}
+                c => line.push(c),
+            }
+        }
+        line.push(delimiters.quote);
+    }
+    line.push_str("\r\n");
+    out.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn write_edrm_document(out: &mut impl Write, doc: &Document) -> Result<()> {
+    let doc_type = if doc.is_email { "Message" } else { "File" };
+    writeln!(
+        out,
+        r#"      <Document DocID="{}" DocType="{doc_type}">"#,
+        xml_escape(&doc.doc_id)
+    )?;
+    writeln!(out, "        <Tags>")?;
+    let (date, time) = doc.date_epoch.map(date_time).unwrap_or_default();
+    let date_sent = doc.date_epoch.map(|_| format!("{date}T{time}Z"));
+    let tags: [(&str, &str, Option<String>); 10] = [
+        ("#From", "Text", doc.from.clone()),
+        (
+            "#To",
+            "Text",
+            Some(doc.to.join("; ")).filter(|s| !s.is_empty()),
+        ),
+        (
+            "#CC",
+            "Text",
+            Some(doc.cc.join("; ")).filter(|s| !s.is_empty()),
+        ),
+        (
+            "#BCC",
+            "Text",
+            Some(doc.bcc.join("; ")).filter(|s| !s.is_empty()),
+        ),
+        ("#Subject", "Text", doc.subject.clone()),
+        ("#DateSent", "DateTime", date_sent),
+        ("#FileName", "Text", doc.filename.clone()),
+        ("#FileSize", "Integer", doc.file_size.map(|n| n.to_string())),
+        ("#Custodian", "Text", doc.custodian.clone()),
+        ("#SHA256", "Text", doc.sha256.clone()),
+    ];
+    for (name, data_type, value) in tags {
+        if let Some(value) = value {
+            writeln!(
+                out,
+                r#"          <Tag TagName="{name}" TagDataType="{data_type}" TagValue="{}"/>"#,
+                xml_escape(&value)
+            )?;
+        }
+    }
+    writeln!(out, "        </Tags>\n        <Files>")?;
+    for (file_type, path) in [("Native", &doc.native_path), ("Text", &doc.text_path)] {
+        let Some(path) = path else { continue };
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        writeln!(
+            out,
+            r#"          <File FileType="{file_type}"><ExternalFile FilePath="{}" FileName="{}"/></File>"#,
+            xml_escape(dir),
+            xml_escape(name)
+        )?;
+    }
+    writeln!(out, "        </Files>\n      </Document>")?;
+    Ok(())
+}
+
+fn xml_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            // Control characters other than tab/newline aren't allowed in XML 1.0.
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Local paths of the text files, for upload.
+pub fn text_paths(out_dir: &Path, written: &Written) -> Vec<(String, PathBuf)> {
+    written
+        .text_files
+        .iter()
+        .map(|name| (name.clone(), out_dir.join(name)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gzmembers::MemberWriter;
+
+    fn gz(path: &Path, lines: &[serde_json::Value]) {
+        let mut writer = MemberWriter::new(
+            File::create(path).expect("create"),
+            0,
+            OutputCompression::default(),
+        );
+        for line in lines {
+            writeln!(writer, "{line}").expect("write");
+            writer.end_record().expect("end");
+        }
+        writer.finish().expect("finish");
+    }
+
+    #[test]
+    fn writes_concordance_and_edrm_in_family_order() {
+        assert_eq!(parse_delimiter("|"), Ok('|'));
+        assert_eq!(parse_delimiter("0x14"), Ok('\u{14}'));
+        assert_eq!(parse_delimiter("U+00FE"), Ok('þ'));
+        assert!(parse_delimiter("ab").is_err());
+
+        let dir = std::env::temp_dir().join(format!("loadfile-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).expect("dir");
+        let emails = dir.join("emails.ndjson.gz");
+        gz(
+            &emails,
+            &[serde_json::json!({
+                "id": "e1", "parent_email_id": null, "family_id": "e1", "custodian_name": "Ann",
+                "from": "Bob <bob@x.com>", "to_emails": ["a@x.com", "c@x.com"],
+                "subject": "Q3\r\nplan þ", "date_epoch": 1614556800, "body_text": "hello",
+                "raw_eml_s3_key": "p/eml/e1.eml", "raw_sha256": "ab"
+            })],
+        );
+        let attachments = dir.join("attachments.ndjson.gz");
+        gz(
+            &attachments,
+            &[serde_json::json!({
+                "id": "a1", "email_message_id": "e1", "filename": "r&d.pdf",
+                "file_size_bytes": 10, "s3_key": "p/attachments/a1/r&d.pdf",
+                "attachment_hash": "cd"
+            })],
+        );
+        let inputs = Inputs {
+            emails: &emails,
+            attachments: &attachments,
+            attachment_text: None,
+            compression: OutputCompression::default(),
+            key_prefix: "p/",
+        };
+
+        let written = write(
+            LoadfileFormat::Concordance,
+            Delimiters::default(),
+            &inputs,
+            &dir,
+        )
+        .expect("dat");
+        assert_eq!(written.documents, 2);
+        assert_eq!(written.text_files, ["text/e1.txt"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("text/e1.txt")).expect("text"),
+            "hello"
+        );
+        let dat = fs::read_to_string(dir.join("loadfile.dat")).expect("dat");
+        let rows: Vec<Vec<&str>> = dat
+            .trim_start_matches('\u{feff}')
+            .split_terminator("\r\n")
+            .map(|row| row.split('\u{14}').map(|v| v.trim_matches('þ')).collect())
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].len(), DAT_FIELDS.len());
+        assert_eq!(rows[1][0], "e1");
+        assert_eq!(rows[1][6], "a@x.com; c@x.com");
+        assert_eq!(rows[1][9], "Q3®plan ");
+        assert_eq!((rows[1][10], rows[1][11]), ("2021-03-01", "00:00:00"));
+        assert_eq!(rows[1][15], "eml/e1.eml");
+        assert_eq!(rows[2][..4], ["a1", "e1", "e1", "Attachment"]);
+        assert_eq!(rows[2][15], "attachments/a1/r&d.pdf");
+
+        write(LoadfileFormat::Edrm, Delimiters::default(), &inputs, &dir).expect("xml");
+        let xml = fs::read_to_string(dir.join("loadfile.xml")).expect("xml");
+        assert!(xml.contains(r#"<Document DocID="a1" DocType="File">"#));
+        assert!(xml.contains(r#"TagValue="r&amp;d.pdf""#));
+        assert!(xml.contains(r#"<ExternalFile FilePath="attachments/a1" FileName="r&amp;d.pdf"/>"#));
+        assert!(
+            xml.contains(r#"<Relationship Type="Attachment" ParentDocId="e1" ChildDocId="a1"/>"#)
+        );
+
+        // Control numbers replace record ids, including as parent and family ids.
+        gz(
+            &attachments,
+            &[serde_json::json!({
+                "id": "a1", "email_message_id": "e1", "filename": "r&d.pdf",
+                "file_size_bytes": 10, "s3_key": "p/attachments/a1/r&d.pdf",
+                "attachment_hash": "cd", "control_number": "VCJ0002"
+            })],
+        );
+        gz(
+            &emails,
+            &[
+                serde_json::json!({"id": "e1", "family_id": "e1", "control_number": "VCJ0001"}),
+                serde_json::json!({
+                    "id": "e2", "parent_email_id": "e1", "family_id": "e1",
+                    "control_number": "VCJ0003"
+                }),
+            ],
+        );
+        write(LoadfileFormat::Edrm, Delimiters::default(), &inputs, &dir).expect("xml");
+        let xml = fs::read_to_string(dir.join("loadfile.xml")).expect("xml");
+        assert!(xml.contains(
+            r#"<Relationship Type="Attachment" ParentDocId="VCJ0001" ChildDocId="VCJ0002"/>"#
+        ));
+        assert!(xml.contains(
+            r#"<Relationship Type="Embedded" ParentDocId="VCJ0001" ChildDocId="VCJ0003"/>"#
+        ));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn custom_delimiters_are_used_and_dropped_from_values() {
+        let delimiters = Delimiters {
+            field: '|',
+            quote: '^',
+            newline: '~',
+            multi: ',',
+        };
+        let mut out = Vec::new();
+        let values = ["a|b^c".to_string(), "one\r\ntwo\nthree".to_string()];
+        write_dat_row(&mut out, &delimiters, &values).expect("row");
+        assert_eq!(
+            String::from_utf8(out).expect("utf8"),
+            "^abc^|^one~two~three^\r\n"
+        );
+        assert_eq!(xml_escape("a\u{1}b\t<c>"), "ab\t&lt;c&gt;");
+    }
+
+    #[test]
+    fn rejects_bad_delimiters_and_inputs() {
+        for value in ["", "0x", "0xZZ", "0xD800", "U+110000", "tab"] {
+            assert!(parse_delimiter(value).is_err(), "{value:?}");
+        }
+
+        let dir = std::env::temp_dir().join(format!("loadfile-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).expect("dir");
+        let emails = dir.join("emails.ndjson.gz");
+        let attachments = dir.join("attachments.ndjson.gz");
+        let inputs = Inputs {
+            emails: &emails,
+            attachments: &attachments,
+            attachment_text: None,
+            compression: OutputCompression::default(),
+            key_prefix: "p/",
+        };
+        let err = write(
+            LoadfileFormat::Concordance,
+            Delimiters::default(),
+            &inputs,
+            &dir,
+        )
+        .expect_err("missing attachments");
+        assert!(err.to_string().contains("attachments.ndjson.gz"), "{err}");
+
+        gz(&attachments, &[]);
+        // A record missing a required field (family_id) is an error, not a silently short file.
+        gz(&emails, &[serde_json::json!({"id": "e1"})]);
+        let err = write(LoadfileFormat::Edrm, Delimiters::default(), &inputs, &dir)
+            .expect_err("bad record");
+        assert!(err.to_string().contains("parse"), "{err}");
+        fs::write(&emails, b"not gzip").expect("write");
+        assert!(write(LoadfileFormat::Edrm, Delimiters::default(), &inputs, &dir).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
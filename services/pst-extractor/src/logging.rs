@@ -0,0 +1,378 @@
+//! Log output (`--log-format`).
+//!
+//! Everything the extractor logs goes through `tracing` to stderr, one line per event, in either
+//! a human-readable `text` form or `json` for CloudWatch Logs Insights. Each line carries the
+//! fields of the spans it was logged in: the `job` span adds `pst_file_id`, the `phase` span the
+//! current phase (`download`, `readpst`, `parse`, `upload`) and `parse_file` the file being parsed:
+//!
+//! ```text
+//! 2024-06-03T10:00:00.250Z WARN job{pst_file_id=p1} phase{phase=parse} parse_file{source_path=Inbox/12.eml}: message timed out message_index=0
+//! {"timestamp":"2024-06-03T10:00:00.250Z","level":"WARN","target":"pst_extractor","message":"message timed out","pst_file_id":"p1","phase":"parse","source_path":"Inbox/12.eml","message_index":0}
+//! ```
+//!
+//! The same spans are exported to OpenTelemetry when it is enabled (see `otel`).
+//!
+//! Events from this crate are logged at INFO and above; dependencies (the AWS SDK in particular)
+//! only at WARN and above. Progress events and the final `OK` line stay on stdout.
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_core::span::Current;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+struct SpanData {
+    meta: &'static Metadata<'static>,
+    fields: Vec<(&'static str, Value)>,
+    refs: usize,
+    /// Set when spans are exported (`OTEL_EXPORTER_OTLP_ENDPOINT`).
+    otel: Option<crate::otel::SpanContext>,
+}
+
+struct Logger {
+    format: LogFormat,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+thread_local! {
+    /// Entered spans on this thread, innermost last.
+    static STACK: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Default)]
+struct Fields(Vec<(&'static str, Value)>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .push((field.name(), Value::String(format!("{value:?}"))));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0
+            .push((field.name(), Value::String(value.to_string())));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push((field.name(), value.into()));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((field.name(), value.into()));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.push((field.name(), value.into()));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push((field.name(), value.into()));
+    }
+}
+
+fn text_value(value: &Value) -> String {
+    match value {
+        Value::String(s) if s.is_empty() || s.contains(char::is_whitespace) => format!("{s:?}"),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl Logger {
+    fn line(&self, event: &Event<'_>) -> String {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let meta = event.metadata();
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+        let timestamp = crate::dates::format_rfc3339_millis(millis);
+        let message = fields
+            .0
+            .iter()
+            .position(|(name, _)| *name == "message")
+            .map(|i| fields.0.remove(i).1);
+        let message = match message {
+            Some(Value::String(s)) => s,
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+        let spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let stack = STACK.with(|stack| stack.borrow().clone());
+        let context: Vec<&SpanData> = stack.iter().filter_map(|id| spans.get(id)).collect();
+
+        match self.format {
+            LogFormat::Json => {
+                let mut out = Map::new();
+                out.insert("timestamp".into(), timestamp.into());
+                out.insert("level".into(), meta.level().as_str().into());
+                out.insert("target".into(), meta.target().into());
+                out.insert("message".into(), message.into());
+                for span in &context {
+                    for (name, value) in &span.fields {
+                        out.insert(name.to_string(), value.clone());
+                    }
+                }
+                for (name, value) in fields.0 {
+                    out.insert(name.to_string(), value);
+                }
+                Value::Object(out).to_string()
+            }
+            LogFormat::Text => {
+                let mut out = format!("{timestamp} {}", meta.level());
+                for span in &context {
+                    let _ = write!(out, " {}{{", span.meta.name());
+                    for (i, (name, value)) in span.fields.iter().enumerate() {
+                        let sep = if i == 0 { "" } else { " " };
+                        let _ = write!(out, "{sep}{name}={}", text_value(value));
+                    }
+                    out.push('}');
+                }
+                let _ = write!(out, ": {message}");
+                for (name, value) in &fields.0 {
+                    let _ = write!(out, " {name}={}", text_value(value));
+                }
+                out
+            }
+        }
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, meta: &Metadata<'_>) -> bool {
+        let floor = if meta.target().starts_with("pst_extractor") {
+            Level::INFO
+        } else {
+            Level::WARN
+        };
+        *meta.level() <= floor
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let otel = crate::otel::collector().map(|collector| {
+            let parent = if let Some(parent) = attrs.parent() {
+                Some(parent.into_u64())
+            } else if attrs.is_contextual() {
+                STACK.with(|stack| stack.borrow().last().copied())
+            } else {
+                None
+            };
+            let parent = parent.and_then(|p| spans.get(&p)?.otel.as_ref());
+            collector.start(parent)
+        });
+        let span = SpanData {
+            meta: attrs.metadata(),
+            fields: fields.0,
+            refs: 1,
+            otel,
+        };
+        spans.insert(id, span);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(data) = self
+            .spans
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&span.into_u64())
+        {
+            data.fields.extend(fields.0);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn current_span(&self) -> Current {
+        let Some(id) = STACK.with(|stack| stack.borrow().last().copied()) else {
+            return Current::none();
+        };
+        match self
+            .spans
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&id)
+        {
+            Some(data) => Current::new(Id::from_u64(id), data.meta),
+            None => Current::none(),
+        }
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        let line = self.line(event);
+        let _ = writeln!(std::io::stderr().lock(), "{line}");
+    }
+
+    fn enter(&self, span: &Id) {
+        STACK.with(|stack| stack.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some(pos) = stack.iter().rposition(|id| *id == span.into_u64()) {
+                stack.remove(pos);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self
+            .spans
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&span.into_u64())
+        {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        let id = span.into_u64();
+        let closed = match spans.get_mut(&id) {
+            Some(data) => {
+                data.refs -= 1;
+                data.refs == 0
+            }
+            None => false,
+        };
+        if closed {
+            if let Some(data) = spans.remove(&id) {
+                if let (Some(context), Some(collector)) = (data.otel, crate::otel::collector()) {
+                    collector.finish(crate::otel::FinishedSpan {
+                        context,
+                        name: data.meta.name(),
+                        end: std::time::SystemTime::now(),
+                        attributes: data.fields,
+                    });
+                }
+            }
+        }
+        closed
+    }
+}
+
+fn logger(format: LogFormat) -> Logger {
+    Logger {
+        format,
+        // Span ids must be non-zero.
+        next_id: AtomicU64::new(1),
+        spans: Mutex::new(HashMap::new()),
+    }
+}
+
+/// Install the process-wide logger. Called once, before anything logs.
+pub fn init(format: LogFormat) {
+    if tracing::subscriber::set_global_default(logger(format)).is_err() {
+        eprintln!("logger already installed");
+    }
+}
+
+/// Holds the current phase span; setting a new phase leaves the previous one.
+#[derive(Default)]
+pub struct PhaseSpan(Option<tracing::span::EnteredSpan>);
+
+impl PhaseSpan {
+    pub fn set(&mut self, phase: &'static str) {
+        self.0 = None;
+        self.0 = Some(tracing::info_span!("phase", phase).entered());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex as StdMutex};
+
+    /// Captures formatted lines instead of writing them to stderr.
+    struct Capture(Logger, Arc<StdMutex<Vec<String>>>);
+
+    impl Subscriber for Capture {
+        fn enabled(&self, meta: &Metadata<'_>) -> bool {
+            self.0.enabled(meta)
+        }
+        fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+            self.0.new_span(attrs)
+        }
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            self.0.record(span, values)
+        }
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let line = self.0.line(event);
+            self.1.lock().expect("lines").push(line);
+        }
+        fn enter(&self, span: &Id) {
+            self.0.enter(span)
+        }
+        fn exit(&self, span: &Id) {
+            self.0.exit(span)
+        }
+        fn try_close(&self, span: Id) -> bool {
+            self.0.try_close(span)
+        }
+        fn current_span(&self) -> Current {
+            self.0.current_span()
+        }
+    }
+
+    #[test]
+    fn lines_carry_span_fields_in_both_formats() {
+        for format in [LogFormat::Json, LogFormat::Text] {
+            let lines = Arc::new(StdMutex::new(Vec::new()));
+            let capture = Capture(logger(format), Arc::clone(&lines));
+            tracing::subscriber::with_default(capture, || {
+                let _job = tracing::info_span!("job", pst_file_id = "p1").entered();
+                let mut phase = PhaseSpan::default();
+                phase.set("download");
+                phase.set("parse");
+                tracing::info!(
+                    source_path = "Inbox/1.eml",
+                    bytes = 12u64,
+                    "message skipped"
+                );
+                tracing::debug!("not logged");
+            });
+            let lines = lines.lock().expect("lines");
+            assert_eq!(lines.len(), 1);
+            if format == LogFormat::Json {
+                let line: Value = serde_json::from_str(&lines[0]).expect("json");
+                assert_eq!(line["level"], "INFO");
+                assert_eq!(line["message"], "message skipped");
+                assert_eq!(line["pst_file_id"], "p1");
+                assert_eq!(line["phase"], "parse");
+                assert_eq!(line["bytes"], 12);
+            } else {
+                assert!(lines[0].ends_with(
+                    " INFO job{pst_file_id=p1} phase{phase=parse}: message skipped \
+                     source_path=Inbox/1.eml bytes=12"
+                ));
+            }
+        }
+    }
+}
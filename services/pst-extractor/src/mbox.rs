@@ -0,0 +1,205 @@
+//! Streaming mbox splitting.
+//!
+//! readpst can emit multi-gigabyte mbox files for large folders, so messages are read one at a
+//! time from a buffered reader rather than loading the whole file and scanning it in memory.
+
+use memchr::memmem;
+use std::io::{self, BufRead};
+
+/// One entry yielded by [`MboxReader`].
+pub enum MboxItem {
+    /// RFC822 message bytes, without the "From " envelope line, and what that line said.
+    Message(Vec<u8>, Option<Envelope>),
+    /// A message larger than the configured cap. Its bytes were discarded while reading.
+    Oversized { bytes: usize },
+}
+
+/// Sender and timestamp from a `From sender date` envelope line. Both can survive when the
+/// message's own From/Date headers are missing or mangled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Envelope {
+    pub from: Option<String>,
+    pub date: Option<String>,
+}
+
+/// Parse an envelope line (with or without the line ending). readpst quotes the sender, and
+/// some writers use `-` or `MAILER-DAEMON` when there is none.
+pub fn parse_envelope(line: &[u8]) -> Envelope {
+    let line = String::from_utf8_lossy(line);
+    let rest = line
+        .strip_prefix("From ")
+        .unwrap_or(&line)
+        .trim_end_matches(['\r', '\n'])
+        .trim_start();
+    let (from, date) = match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+        None => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+    };
+    let from = from.trim();
+    let date = date.trim();
+    Envelope {
+        from: Some(from.to_string()).filter(|f| !f.is_empty() && f != "-"),
+        date: Some(date.to_string()).filter(|d| !d.is_empty()),
+    }
+}
+
+pub fn looks_like_mbox(buf: &[u8]) -> bool {
+    buf.starts_with(b"From ") || memmem::find(buf, b"\nFrom ").is_some()
+}
+
+/// Yields messages from an mbox stream, holding at most one message in memory, each with the
+/// byte offset where it starts (just after its envelope line).
+///
+/// This is a best-effort parser and is intentionally simple: any line starting with "From "
+/// begins a new message, and content before the first separator is ignored.
+pub struct MboxReader<R> {
+    reader: R,
+    max_message_bytes: usize,
+    started: bool,
+    done: bool,
+    /// Envelope line of the message about to be read.
+    envelope: Option<Envelope>,
+    /// Bytes consumed so far.
+    position: u64,
+}
+
+impl<R: BufRead> MboxReader<R> {
+    pub fn new(reader: R, max_message_bytes: usize) -> Self {
+        Self {
+            reader,
+            max_message_bytes,
+            started: false,
+            done: false,
+            envelope: None,
+            position: 0,
+        }
+    }
+
+    /// Skip forward to the first envelope line. Returns false if the stream has none.
+    fn seek_first_separator(&mut self) -> io::Result<bool> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let n = self.reader.read_until(b'\n', &mut line)?;
+            if n == 0 {
+                return Ok(false);
+            }
+            self.position += n as u64;
+            if line.starts_with(b"From ") {
+                self.envelope = Some(parse_envelope(&line));
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Read one message body up to (and consuming) the next envelope line.
+    fn read_message(&mut self) -> io::Result<MboxItem> {
+        let envelope = self.envelope.take();
+        let mut msg = Vec::new();
+        let mut total = 0usize;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let n = self.reader.read_until(b'\n', &mut line)?;
+            if n == 0 {
+                self.done = true;
+                break;
+            }
+            self.position += n as u64;
+            if line.starts_with(b"From ") {
+                self.envelope = Some(parse_envelope(&line));
+                break;
+            }
+            total += n;
+            if total <= self.max_message_bytes {
+                msg.extend_from_slice(&line);
+            } else if !msg.is_empty() {
+                // Over the cap: stop buffering, but keep consuming to the next separator.
+                msg = Vec::new();
+            }
+        }
+        if total > self.max_message_bytes {
+            return Ok(MboxItem::Oversized { bytes: total });
+        }
+        Ok(MboxItem::Message(msg, envelope))
+    }
+}
+
+impl<R: BufRead> Iterator for MboxReader<R> {
+    type Item = io::Result<(u64, MboxItem)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            match self.seek_first_separator() {
+                Ok(true) => {}
+                Ok(false) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        while !self.done {
+            let offset = self.position;
+            match self.read_message() {
+                Ok(MboxItem::Message(msg, _)) if msg.is_empty() => continue,
+                Ok(item) => return Some(Ok((offset, item))),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn messages(raw: &[u8], max: usize) -> Vec<(u64, MboxItem)> {
+        MboxReader::new(Cursor::new(raw.to_vec()), max)
+            .collect::<io::Result<Vec<_>>>()
+            .expect("read mbox")
+    }
+
+    #[test]
+    fn splits_messages_and_drops_envelope_lines() {
+        let raw = b"From a@example.com Mon Jan  1 00:00:00 2024\nSubject: one\n\nbody one\n\
+From b@example.com Tue Jan  2 00:00:00 2024\nSubject: two\n\nbody two\n";
+        let items = messages(raw, 1024);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].0, 44);
+        assert_eq!(items[1].0, 111);
+        match &items[1].1 {
+            MboxItem::Message(m, envelope) => {
+                assert_eq!(m.as_slice(), b"Subject: two\n\nbody two\n");
+                assert_eq!(
+                    envelope,
+                    &Some(Envelope {
+                        from: Some("b@example.com".into()),
+                        date: Some("Tue Jan  2 00:00:00 2024".into()),
+                    })
+                );
+            }
+            MboxItem::Oversized { .. } => panic!("unexpected oversized"),
+        }
+        assert_eq!(
+            parse_envelope(b"From \"site office@example.com\" Wed Mar  6 10:00:00 2024\r\n").from,
+            Some("site office@example.com".into())
+        );
+        assert_eq!(parse_envelope(b"From -\n"), Envelope::default());
+    }
+
+    #[test]
+    fn reports_oversized_messages_without_losing_following_ones() {
+        let raw =
+            b"From a\nSubject: big\n\nxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx\nFrom b\nSubject: ok\n\n";
+        let items = messages(raw, 24);
+        assert!(matches!(items[0].1, MboxItem::Oversized { bytes } if bytes > 24));
+        assert!(matches!(&items[1].1, MboxItem::Message(m, _) if m.starts_with(b"Subject: ok")));
+    }
+}
@@ -0,0 +1,209 @@
+//! Meeting update chains (`meetings.ndjson.gz`).
+//!
+//! A meeting that is rescheduled three times arrives as four invitation emails, each carrying a
+//! `text/calendar` part with the same UID and a rising SEQUENCE. Reviewed as plain emails they
+//! look like four unrelated items. Each email keeps its record (with `calendar_uid`,
+//! `calendar_sequence` and `calendar_method` set), and the invites of one UID are collapsed into
+//! a single canonical meeting: the details of the latest revision, plus the history of every
+//! email in the chain.
+//!
+//! The latest revision is the highest SEQUENCE, ties broken by the email's date. Attendee
+//! replies (`METHOD:REPLY`, `COUNTER`) stay in the history but never become the canonical
+//! details, since they echo the organizer's copy. A `CANCEL` marks the meeting cancelled.
+
+use crate::pim::{self, CalendarEvent};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The calendar payload of one email.
+pub struct Invite {
+    pub method: Option<String>,
+    pub event: CalendarEvent,
+}
+
+impl Invite {
+    /// The first VEVENT with a UID in an iCalendar part; None for anything else.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let items = pim::parse(bytes);
+        let event = items.events.into_iter().find(|ev| ev.uid.is_some())?;
+        Some(Self {
+            method: items.method,
+            event,
+        })
+    }
+
+    fn is_reply(&self) -> bool {
+        matches!(self.method.as_deref(), Some("REPLY" | "COUNTER"))
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct MeetingUpdate {
+    pub email_id: String,
+    pub method: Option<String>,
+    pub sequence: Option<u32>,
+    pub date: Option<String>,
+    pub date_epoch: Option<i64>,
+    pub summary: Option<String>,
+    pub start: Option<String>,
+    pub location: Option<String>,
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Meeting {
+    /// The email whose invite supplied the details below.
+    pub canonical_email_id: String,
+    pub cancelled: bool,
+    /// Details of the latest revision (uid, sequence, start, ...).
+    #[serde(flatten)]
+    pub event: CalendarEvent,
+    /// Every email of the chain, oldest revision first.
+    pub updates: Vec<MeetingUpdate>,
+}
+
+struct Entry {
+    email_id: String,
+    date: Option<String>,
+    date_epoch: Option<i64>,
+    invite: Invite,
+}
+
+impl Entry {
+    fn order(&self) -> (u32, i64) {
+        (
+            self.invite.event.sequence.unwrap_or(0),
+            self.date_epoch.unwrap_or(i64::MIN),
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct MeetingChains {
+    by_uid: BTreeMap<String, Vec<Entry>>,
+}
+
+impl MeetingChains {
+    pub fn add(
+        &mut self,
+        email_id: &str,
+        date: Option<&str>,
+        date_epoch: Option<i64>,
+        invite: Invite,
+    ) {
+        let Some(uid) = invite.event.uid.clone() else {
+            return;
+        };
+        self.by_uid.entry(uid).or_default().push(Entry {
+            email_id: email_id.to_string(),
+            date: date.map(str::to_string),
+            date_epoch,
+            invite,
+        });
+    }
+
+    /// Emails that carried an invite.
+    pub fn emails(&self) -> usize {
+        self.by_uid.values().map(Vec::len).sum()
+    }
+
+    /// One meeting per UID, in UID order.
+    pub fn finish(self) -> Vec<Meeting> {
+        let mut meetings = Vec::with_capacity(self.by_uid.len());
+        for mut entries in self.by_uid.into_values() {
+            entries.sort_by_key(Entry::order);
+            let cancelled = entries.iter().any(|e| {
+                e.invite.method.as_deref() == Some("CANCEL")
+                    || e.invite
+                        .event
+                        .status
+                        .as_deref()
+                        .is_some_and(|s| s.eq_ignore_ascii_case("CANCELLED"))
+            });
+            let canonical = entries
+                .iter()
+                .rposition(|e| !e.invite.is_reply())
+                .unwrap_or(entries.len() - 1);
+            let updates = entries
+                .iter()
+                .map(|e| MeetingUpdate {
+                    email_id: e.email_id.clone(),
+                    method: e.invite.method.clone(),
+                    sequence: e.invite.event.sequence,
+                    date: e.date.clone(),
+                    date_epoch: e.date_epoch,
+                    summary: e.invite.event.summary.clone(),
+                    start: e.invite.event.start.clone(),
+                    location: e.invite.event.location.clone(),
+                    status: e.invite.event.status.clone(),
+                })
+                .collect();
+            let entry = entries.swap_remove(canonical);
+            meetings.push(Meeting {
+                canonical_email_id: entry.email_id,
+                cancelled,
+                event: entry.invite.event,
+                updates,
+            });
+        }
+        meetings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invite(method: &str, sequence: u32, start: &str) -> Invite {
+        let ics = format!(
+            "BEGIN:VCALENDAR\r\nMETHOD:{method}\r\nBEGIN:VEVENT\r\nUID:site-42\r\nSEQUENCE:{sequence}\r\nSUMMARY:Site meeting\r\nDTSTART:{start}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n"
+        );
+        Invite::parse(ics.as_bytes()).expect("invite")
+    }
+
+    #[test]
+    fn collapses_updates_by_uid_and_sequence() {
+        let mut chains = MeetingChains::default();
+        chains.add(
+            "e2",
+            None,
+            Some(200),
+            invite("REQUEST", 1, "20210304T100000Z"),
+        );
+        chains.add(
+            "e1",
+            None,
+            Some(100),
+            invite("REQUEST", 0, "20210303T100000Z"),
+        );
+        chains.add(
+            "e3",
+            None,
+            Some(300),
+            invite("REPLY", 1, "20210304T100000Z"),
+        );
+        assert!(Invite::parse(b"BEGIN:VCARD\r\nFN:Ann\r\nEND:VCARD\r\n").is_none());
+        assert_eq!(chains.emails(), 3);
+
+        let meetings = chains.finish();
+        assert_eq!(meetings.len(), 1);
+        let meeting = &meetings[0];
+        assert_eq!(meeting.event.uid.as_deref(), Some("site-42"));
+        assert_eq!(meeting.canonical_email_id, "e2");
+        assert_eq!(meeting.event.sequence, Some(1));
+        assert_eq!(meeting.event.start_epoch, Some(1614852000));
+        assert!(!meeting.cancelled);
+        let order: Vec<&str> = meeting
+            .updates
+            .iter()
+            .map(|u| u.email_id.as_str())
+            .collect();
+        assert_eq!(order, ["e1", "e2", "e3"]);
+        assert_eq!(meeting.updates[2].method.as_deref(), Some("REPLY"));
+
+        let mut chains = MeetingChains::default();
+        chains.add("a", None, Some(1), invite("REQUEST", 0, "20210303T100000Z"));
+        chains.add("b", None, Some(2), invite("CANCEL", 1, "20210303T100000Z"));
+        assert!(chains.finish()[0].cancelled);
+    }
+}
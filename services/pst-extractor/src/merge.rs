@@ -0,0 +1,257 @@
+//! Case-level manifest (`merge-manifests`).
+//!
+//! Each extraction writes a manifest for one PST. The platform also wants a single artifact for
+//! the whole collection. That artifact lists every source and sums their counters. It also
+//! reports how many emails occur in more than one source, which is the cross-custodian
+//! duplication a per-PST manifest can't see.
+//!
+//! Emails are matched across sources by `dedupe_hash` when the extraction ran with `--dedupe`,
+//! otherwise by normalized Message-ID. Embedded copies (depth > 0) are not counted.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::io::BufRead;
+
+/// Manifest fields that are not counters: identity, keys, checksums and per-run statistics
+/// (percentiles don't add up).
+const NOT_SUMMED: &[&str] = &[
+    "schema_version",
+    "sha256",
+    "parse_timing",
+    "opensearch",
+    "version",
+];
+
+/// Identity fields copied into each source entry.
+const SOURCE_FIELDS: &[&str] = &[
+    "pst_file_id",
+    "custodian_id",
+    "custodian_name",
+    "source_bucket",
+    "source_key",
+    "output_bucket",
+    "output_prefix",
+    "input_format",
+    "emails_total",
+    "attachments_total",
+];
+
+#[derive(Deserialize)]
+struct EmailLine {
+    #[serde(default)]
+    depth: usize,
+    dedupe_hash: Option<String>,
+    message_id: Option<String>,
+}
+
+#[derive(Default)]
+pub struct CaseManifest {
+    sources: Vec<Value>,
+    totals: Map<String, Value>,
+    custodians: BTreeSet<String>,
+    schema_versions: BTreeSet<u64>,
+    /// Match key → (first source index, copies seen).
+    seen: HashMap<String, (usize, u32)>,
+    emails_keyed: u64,
+    emails_unkeyed: u64,
+    duplicate_emails: u64,
+    cross_source_duplicates: u64,
+}
+
+/// Add numeric fields of `from` into `into`: integers and floats are summed, booleans count the
+/// manifests where they were true, and objects are merged key by key.
+fn sum_into(into: &mut Map<String, Value>, from: &Map<String, Value>) {
+    for (key, value) in from {
+        let merged = match (into.get(key), value) {
+            (_, Value::Bool(b)) => {
+                json!(into.get(key).and_then(Value::as_u64).unwrap_or(0) + u64::from(*b))
+            }
+            (Some(Value::Number(a)), Value::Number(b)) => match (a.as_u64(), b.as_u64()) {
+                (Some(a), Some(b)) => json!(a + b),
+                _ => json!(a.as_f64().unwrap_or(0.0) + b.as_f64().unwrap_or(0.0)),
+            },
+            (None, Value::Number(_)) => value.clone(),
+            (existing, Value::Object(inner)) => {
+                let mut map = match existing {
+                    Some(Value::Object(map)) => map.clone(),
+                    _ => Map::new(),
+                };
+                sum_into(&mut map, inner);
+                if map.is_empty() {
+                    continue;
+                }
+                Value::Object(map)
+            }
+            _ => continue,
+        };
+        into.insert(key.clone(), merged);
+    }
+}
+
+fn match_key(line: &EmailLine) -> Option<String> {
+    if let Some(hash) = line.dedupe_hash.as_deref().filter(|h| !h.is_empty()) {
+        return Some(format!("hash:{hash}"));
+    }
+    let id = line
+        .message_id
+        .as_deref()?
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_ascii_lowercase();
+    (!id.is_empty()).then(|| format!("message-id:{id}"))
+}
+
+impl CaseManifest {
+    /// Add one extraction manifest (read from `location`) and, when available, its
+    /// `emails.ndjson` lines for duplicate matching.
+    pub fn add(
+        &mut self,
+        location: &str,
+        manifest: &Value,
+        emails: Option<impl BufRead>,
+    ) -> Result<()> {
+        let index = self.sources.len();
+        let mut source = Map::new();
+        source.insert("manifest".to_string(), json!(location));
+        for field in SOURCE_FIELDS {
+            if let Some(value) = manifest.get(*field) {
+                source.insert(field.to_string(), value.clone());
+            }
+        }
+        if let Some(custodian) = manifest
+            .get("custodian_id")
+            .or_else(|| manifest.get("custodian_name"))
+            .and_then(Value::as_str)
+        {
+            self.custodians.insert(custodian.to_string());
+        }
+        if let Some(version) = manifest.get("schema_version").and_then(Value::as_u64) {
+            self.schema_versions.insert(version);
+        }
+        if let Some(fields) = manifest.as_object() {
+            let counters: Map<String, Value> = fields
+                .iter()
+                .filter(|(k, _)| !NOT_SUMMED.contains(&k.as_str()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            sum_into(&mut self.totals, &counters);
+        }
+
+        let (mut unique, mut cross) = (0u64, 0u64);
+        if let Some(emails) = emails {
+            for line in emails.lines() {
+                let line = line.with_context(|| format!("read emails for {location}"))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let email: EmailLine = serde_json::from_str(&line)
+                    .with_context(|| format!("parse email record for {location}"))?;
+                if email.depth > 0 {
+                    continue;
+                }
+                let Some(key) = match_key(&email) else {
+                    self.emails_unkeyed += 1;
+                    unique += 1;
+                    continue;
+                };
+                self.emails_keyed += 1;
+                match self.seen.get_mut(&key) {
+                    Some((first, copies)) => {
+                        *copies += 1;
+                        self.duplicate_emails += 1;
+                        if *first != index {
+                            self.cross_source_duplicates += 1;
+                            cross += 1;
+                        }
+                    }
+                    None => {
+                        self.seen.insert(key, (index, 1));
+                        unique += 1;
+                    }
+                }
+            }
+            source.insert("unique_emails".to_string(), json!(unique));
+            source.insert("cross_source_duplicates".to_string(), json!(cross));
+        }
+        self.sources.push(Value::Object(source));
+        Ok(())
+    }
+
+    pub fn finish(self, case_id: &str) -> Value {
+        let groups = self.seen.values().filter(|(_, copies)| *copies > 1).count();
+        let largest = self
+            .seen
+            .values()
+            .map(|(_, copies)| *copies)
+            .max()
+            .unwrap_or(0);
+        json!({
+            "case_id": (!case_id.is_empty()).then_some(case_id),
+            "manifests_total": self.sources.len(),
+            "custodians": self.custodians,
+            "schema_versions": self.schema_versions,
+            "totals": self.totals,
+            "dedupe": {
+                "emails_keyed": self.emails_keyed,
+                "emails_unkeyed": self.emails_unkeyed,
+                "unique_emails": self.seen.len() as u64 + self.emails_unkeyed,
+                "duplicate_emails": self.duplicate_emails,
+                "cross_source_duplicates": self.cross_source_duplicates,
+                "duplicate_groups": groups,
+                "largest_group": largest,
+            },
+            "sources": self.sources,
+            "version": env!("CARGO_PKG_VERSION"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_counters_and_counts_cross_source_duplicates() {
+        let a = json!({
+            "pst_file_id": "a", "custodian_id": "c1", "emails_total": 3, "attachments_total": 1,
+            "readpst_failed": false, "date_parsers": {"rfc2822": 3}, "duration_s": 1.5,
+            "schema_version": 4, "sha256": {"emails.csv.gz": "x"}
+        });
+        let b = json!({
+            "pst_file_id": "b", "custodian_id": "c2", "emails_total": 2, "attachments_total": 0,
+            "readpst_failed": true, "date_parsers": {"rfc2822": 1, "lenient": 1}, "duration_s": 2.0,
+            "schema_version": 4
+        });
+        let emails_a = "{\"message_id\":\"<One@x>\",\"depth\":0}\n\
+            {\"message_id\":\"<two@x>\",\"depth\":0}\n\
+            {\"message_id\":\"<two@x>\",\"depth\":0}\n\
+            {\"message_id\":\"<one@x>\",\"depth\":1}\n";
+        let emails_b = "{\"message_id\":\"one@x\",\"depth\":0}\n{\"message_id\":null}\n";
+
+        let mut case = CaseManifest::default();
+        case.add("s3://out/a/manifest.json", &a, Some(emails_a.as_bytes()))
+            .expect("a");
+        case.add("s3://out/b/manifest.json", &b, Some(emails_b.as_bytes()))
+            .expect("b");
+        let merged = case.finish("case-7");
+
+        assert_eq!(merged["case_id"], "case-7");
+        assert_eq!(merged["custodians"], json!(["c1", "c2"]));
+        assert_eq!(merged["totals"]["emails_total"], 5);
+        assert_eq!(merged["totals"]["readpst_failed"], 1);
+        assert_eq!(
+            merged["totals"]["date_parsers"],
+            json!({"rfc2822": 4, "lenient": 1})
+        );
+        assert_eq!(merged["totals"]["duration_s"], 3.5);
+        assert!(merged["totals"].get("sha256").is_none());
+        assert_eq!(merged["dedupe"]["duplicate_emails"], 2);
+        assert_eq!(merged["dedupe"]["cross_source_duplicates"], 1);
+        assert_eq!(merged["dedupe"]["unique_emails"], 3);
+        assert_eq!(merged["sources"][1]["cross_source_duplicates"], 1);
+        assert_eq!(merged["sources"][0]["pst_file_id"], "a");
+    }
+}
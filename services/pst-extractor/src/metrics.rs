@@ -0,0 +1,295 @@
+//! Job metrics for fleet capacity planning (`--metrics-sink`).
+//!
+//! At the end of each successful job the extractor reports how fast it parsed and how fast it
+//! could push results to S3:
+//!
+//! * `MessagesParsed`, `ParseFailures` (counters) and `MessagesPerSecond` (over the parse phase)
+//! * `BytesUploaded` (attachments, bodies and output files) and `ObjectsUploaded`
+//! * `AttachmentUploadLatency` (histogram, milliseconds)
+//!
+//! `emf` prints one CloudWatch Embedded Metric Format line on stdout, which CloudWatch Logs turns
+//! into metrics in the `PstExtractor` namespace (dimension `Service`; `pst_file_id` rides along
+//! as a property). EMF has no histogram type, so the latency is reported as
+//! `AttachmentUploadLatencyP50`/`P90`/`P99`/`Max`. `pushgateway` PUTs the Prometheus text format
+//! to `--pushgateway-url` under `job=pst_extractor` and `pst_file_id`, with a real histogram.
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::fmt::Write as _;
+use std::time::Duration;
+
+const NAMESPACE: &str = "PstExtractor";
+const SERVICE: &str = "pst-extractor";
+
+/// Upper bounds of the latency buckets, in milliseconds.
+const LATENCY_BUCKETS_MS: &[f64] = &[
+    5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+];
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetricsSink {
+    Emf,
+    Pushgateway,
+}
+
+/// Fixed-bucket histogram; the last count is the `+Inf` bucket.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+    max: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; LATENCY_BUCKETS_MS.len() + 1],
+            sum: 0.0,
+            count: 0,
+            max: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value: f64) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+        self.max = self.max.max(value);
+    }
+
+    /// Upper bound of the bucket holding quantile `q` (the maximum for the `+Inf` bucket).
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(
+                    LATENCY_BUCKETS_MS
+                        .get(bucket)
+                        .map_or(self.max, |bound| bound.min(self.max)),
+                );
+            }
+        }
+        Some(self.max)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct JobMetrics {
+    pub messages_parsed: u64,
+    pub parse_failures: u64,
+    pub parse_secs: f64,
+    pub bytes_uploaded: u64,
+    pub objects_uploaded: u64,
+    pub attachment_upload_latency_ms: Histogram,
+}
+
+impl JobMetrics {
+    pub fn record_upload(&mut self, bytes: u64) {
+        self.bytes_uploaded += bytes;
+        self.objects_uploaded += 1;
+    }
+
+    pub fn record_attachment_upload(&mut self, bytes: u64, elapsed: Duration) {
+        self.record_upload(bytes);
+        self.attachment_upload_latency_ms
+            .observe(elapsed.as_secs_f64() * 1000.0);
+    }
+
+    pub fn messages_per_sec(&self) -> f64 {
+        if self.parse_secs > 0.0 {
+            self.messages_parsed as f64 / self.parse_secs
+        } else {
+            0.0
+        }
+    }
+
+    /// One EMF document.
+    pub fn emf(&self, pst_file_id: &str, timestamp_ms: u64) -> Value {
+        let mut values: Vec<(&str, &str, f64)> = vec![
+            ("MessagesParsed", "Count", self.messages_parsed as f64),
+            ("ParseFailures", "Count", self.parse_failures as f64),
+            ("MessagesPerSecond", "Count/Second", self.messages_per_sec()),
+            ("BytesUploaded", "Bytes", self.bytes_uploaded as f64),
+            ("ObjectsUploaded", "Count", self.objects_uploaded as f64),
+        ];
+        let latency = &self.attachment_upload_latency_ms;
+        for (name, q) in [
+            ("AttachmentUploadLatencyP50", 0.5),
+            ("AttachmentUploadLatencyP90", 0.9),
+            ("AttachmentUploadLatencyP99", 0.99),
+            ("AttachmentUploadLatencyMax", 1.0),
+        ] {
+            if let Some(value) = latency.quantile(q) {
+                values.push((name, "Milliseconds", value));
+            }
+        }
+        let mut doc = Map::new();
+        doc.insert(
+            "_aws".into(),
+            json!({
+                "Timestamp": timestamp_ms,
+                "CloudWatchMetrics": [{
+                    "Namespace": NAMESPACE,
+                    "Dimensions": [["Service"]],
+                    "Metrics": values
+                        .iter()
+                        .map(|(name, unit, _)| json!({"Name": name, "Unit": unit}))
+                        .collect::<Vec<_>>(),
+                }],
+            }),
+        );
+        doc.insert("Service".into(), SERVICE.into());
+        doc.insert("pst_file_id".into(), pst_file_id.into());
+        for (name, _, value) in values {
+            doc.insert(name.into(), value.into());
+        }
+        Value::Object(doc)
+    }
+
+    /// Prometheus text exposition format.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, value) in [
+            (
+                "messages_parsed_total",
+                "counter",
+                self.messages_parsed as f64,
+            ),
+            (
+                "parse_failures_total",
+                "counter",
+                self.parse_failures as f64,
+            ),
+            ("messages_per_second", "gauge", self.messages_per_sec()),
+            (
+                "bytes_uploaded_total",
+                "counter",
+                self.bytes_uploaded as f64,
+            ),
+            (
+                "objects_uploaded_total",
+                "counter",
+                self.objects_uploaded as f64,
+            ),
+        ] {
+            let _ = writeln!(out, "# TYPE pst_extractor_{name} {kind}");
+            let _ = writeln!(out, "pst_extractor_{name} {value}");
+        }
+        let name = "pst_extractor_attachment_upload_latency_ms";
+        let latency = &self.attachment_upload_latency_ms;
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS_MS.iter().zip(&latency.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", latency.count);
+        let _ = writeln!(out, "{name}_sum {}", latency.sum);
+        let _ = writeln!(out, "{name}_count {}", latency.count);
+        out
+    }
+}
+
+/// Send the job's metrics to `sink`.
+pub async fn emit(
+    sink: MetricsSink,
+    pushgateway_url: Option<&str>,
+    pst_file_id: &str,
+    metrics: &JobMetrics,
+) -> Result<()> {
+    match sink {
+        MetricsSink::Emf => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0);
+            println!("{}", metrics.emf(pst_file_id, now));
+            Ok(())
+        }
+        MetricsSink::Pushgateway => {
+            let base = pushgateway_url
+                .ok_or_else(|| anyhow!("--metrics-sink pushgateway requires --pushgateway-url"))?;
+            let url = format!(
+                "{}/metrics/job/pst_extractor/pst_file_id/{pst_file_id}",
+                base.trim_end_matches('/')
+            );
+            let resp = reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()?
+                .put(&url)
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(metrics.prometheus())
+                .send()
+                .await
+                .with_context(|| format!("push metrics to {url}"))?;
+            if !resp.status().is_success() {
+                return Err(anyhow!("pushgateway returned HTTP {}", resp.status()));
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emf_and_prometheus_carry_counters_and_latency() {
+        let mut metrics = JobMetrics {
+            messages_parsed: 120,
+            parse_failures: 2,
+            parse_secs: 4.0,
+            ..Default::default()
+        };
+        for ms in [3, 8, 40, 40, 700] {
+            metrics.record_attachment_upload(1000, Duration::from_millis(ms));
+        }
+        metrics.record_upload(500);
+        assert_eq!(metrics.bytes_uploaded, 5500);
+        assert_eq!(
+            metrics.attachment_upload_latency_ms.quantile(0.5),
+            Some(50.0)
+        );
+        assert_eq!(
+            metrics.attachment_upload_latency_ms.quantile(1.0),
+            Some(700.0)
+        );
+
+        let emf = metrics.emf("p1", 1_700_000_000_000);
+        assert_eq!(
+            emf["_aws"]["CloudWatchMetrics"][0]["Namespace"],
+            "PstExtractor"
+        );
+        assert_eq!(emf["MessagesPerSecond"], 30.0);
+        assert_eq!(emf["ObjectsUploaded"], 6.0);
+        assert_eq!(emf["AttachmentUploadLatencyP50"], 50.0);
+        assert_eq!(emf["pst_file_id"], "p1");
+        let declared = emf["_aws"]["CloudWatchMetrics"][0]["Metrics"]
+            .as_array()
+            .expect("metrics")
+            .len();
+        assert_eq!(declared, 9);
+
+        let text = metrics.prometheus();
+        assert!(text.contains("pst_extractor_parse_failures_total 2\n"));
+        assert!(text.contains("pst_extractor_attachment_upload_latency_ms_bucket{le=\"50\"} 4\n"));
+        assert!(text.contains("pst_extractor_attachment_upload_latency_ms_bucket{le=\"+Inf\"} 5\n"));
+        assert!(text.contains("pst_extractor_attachment_upload_latency_ms_count 5\n"));
+    }
+}
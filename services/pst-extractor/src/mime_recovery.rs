@@ -0,0 +1,142 @@
+//! Recovery for multipart messages that end before their closing boundary.
+//!
+//! readpst occasionally truncates a message mid-part. mailparse then either fails outright or
+//! returns the half-written trailing part as if it were complete. When a multipart boundary has
+//! no closing delimiter, the message is cut back to the last delimiter (dropping the incomplete
+//! part), the open boundaries are closed innermost-first, and the result is parsed again so every
+//! complete part is kept.
+
+use mailparse::ParsedMail;
+use memchr::memmem;
+
+/// Boundaries declared by multipart nodes of an already-parsed message, outermost first.
+pub fn tree_boundaries(mail: &ParsedMail) -> Vec<String> {
+    let mut out = Vec::new();
+    collect_boundaries(mail, &mut out);
+    out
+}
+
+fn collect_boundaries(mail: &ParsedMail, out: &mut Vec<String>) {
+    if mail
+        .ctype
+        .mimetype
+        .to_ascii_lowercase()
+        .starts_with("multipart/")
+    {
+        if let Some(b) = mail.ctype.params.get("boundary").filter(|b| !b.is_empty()) {
+            if !out.contains(b) {
+                out.push(b.clone());
+            }
+        }
+    }
+    for part in &mail.subparts {
+        collect_boundaries(part, out);
+    }
+}
+
+/// Boundaries found by scanning raw bytes for `boundary=` parameters; used when mailparse
+/// rejects the message entirely.
+pub fn scan_boundaries(raw: &[u8]) -> Vec<String> {
+    let text = String::from_utf8_lossy(raw);
+    let lower = text.to_ascii_lowercase();
+    let mut out: Vec<String> = Vec::new();
+    let mut from = 0;
+    while let Some(pos) = lower[from..].find("boundary=") {
+        let start = from + pos + "boundary=".len();
+        from = start;
+        let rest = &text[start..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            quoted.split('"').next().unwrap_or("")
+        } else {
+            rest.split(|c: char| c == ';' || c.is_whitespace())
+                .next()
+                .unwrap_or("")
+        };
+        if !value.is_empty() && !out.iter().any(|b| b == value) {
+            out.push(value.to_string());
+        }
+    }
+    out
+}
+
+/// Byte offsets of delimiter lines ("--boundary" at the start of a line).
+fn delimiter_positions(raw: &[u8], boundary: &str) -> Vec<usize> {
+    let line = format!("\n--{boundary}");
+    let mut out = Vec::new();
+    if raw.starts_with(&line.as_bytes()[1..]) {
+        out.push(0);
+    }
+    out.extend(memmem::find_iter(raw, line.as_bytes()).map(|pos| pos + 1));
+    out
+}
+
+fn is_closed(raw: &[u8], boundary: &str) -> bool {
+    delimiter_positions(raw, boundary)
+        .iter()
+        .any(|&pos| raw[pos + 2 + boundary.len()..].starts_with(b"--"))
+}
+
+/// If any of `boundaries` is never closed, return a repaired copy of the message: everything
+/// after the last delimiter of an unclosed boundary is dropped and closing delimiters are
+/// appended for the boundaries still open at that point. Returns None for intact messages.
+pub fn repair(raw: &[u8], boundaries: &[String]) -> Option<Vec<u8>> {
+    let unclosed: Vec<&String> = boundaries
+        .iter()
+        .filter(|b| !delimiter_positions(raw, b).is_empty() && !is_closed(raw, b))
+        .collect();
+    if unclosed.is_empty() {
+        return None;
+    }
+
+    // The part after the final delimiter is where the data ran out.
+    let cut = unclosed
+        .iter()
+        .filter_map(|b| delimiter_positions(raw, b).last().copied())
+        .max()?;
+    let mut repaired = raw[..cut].to_vec();
+
+    // Boundaries opened before the cut, closed innermost (latest-opened) first.
+    let mut open: Vec<(usize, &String)> = unclosed
+        .iter()
+        .filter_map(|b| {
+            delimiter_positions(raw, b)
+                .first()
+                .filter(|&&first| first < cut)
+                .map(|&first| (first, *b))
+        })
+        .collect();
+    open.sort_by_key(|&(first, _)| std::cmp::Reverse(first));
+    if !repaired.ends_with(b"\n") {
+        repaired.extend_from_slice(b"\r\n");
+    }
+    for (_, boundary) in open {
+        repaired.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    }
+    Some(repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRUNCATED: &[u8] = b"Content-Type: multipart/mixed; boundary=\"outer\"\r\n\r\n--outer\r\nContent-Type: multipart/alternative; boundary=\"inner\"\r\n\r\n--inner\r\nContent-Type: text/plain\r\n\r\nhello\r\n--inner\r\nContent-Type: text/html\r\n\r\n<p>hello</p>\r\n--inner--\r\n--outer\r\nContent-Type: application/pdf\r\nContent-Disposition: attachment; filename=a.pdf\r\n\r\n%PDF-1.4 complete\r\n--outer\r\nContent-Type: application/pdf\r\nContent-Disposition: attachment; filename=b.pdf\r\n\r\n%PDF-1.4 trunc";
+
+    #[test]
+    fn drops_incomplete_trailing_part_and_closes_boundaries() {
+        let mail = mailparse::parse_mail(TRUNCATED).expect("parse");
+        let boundaries = tree_boundaries(&mail);
+        assert_eq!(boundaries, vec!["outer".to_string(), "inner".to_string()]);
+        assert_eq!(scan_boundaries(TRUNCATED), boundaries);
+
+        let repaired = repair(TRUNCATED, &boundaries).expect("repaired");
+        assert!(repaired.ends_with(b"--outer--\r\n"));
+        let mail = mailparse::parse_mail(&repaired).expect("parse repaired");
+        assert_eq!(mail.subparts.len(), 2);
+        assert!(mail.subparts[1]
+            .get_body_raw()
+            .expect("body")
+            .starts_with(b"%PDF-1.4 complete"));
+
+        assert!(repair(&repaired, &boundaries).is_none());
+    }
+}
@@ -0,0 +1,629 @@
+//! Outlook `.msg` files: one MAPI message stored in an OLE2 compound file.
+//!
+//! Loose MSG exports bypass readpst. Each file is converted to an RFC822 message and written into
+//! the extract directory, so it goes through the same body, attachment, threading and security
+//! passes as PST output. Outlook's saved transport headers (`PR_TRANSPORT_MESSAGE_HEADERS`) are
+//! kept when present; otherwise headers are rebuilt from MAPI properties. Attachments become MIME
+//! parts, embedded messages become `message/rfc822` parts, and an RTF-only body is attached as
+//! `rtf-body.rtf`, like readpst does.
+
+use crate::cfb::{CompoundFile, EntryKind};
+use crate::dates;
+use std::collections::HashMap;
+
+// MAPI property ids.
+const PR_SUBJECT: u16 = 0x0037;
+const PR_CLIENT_SUBMIT_TIME: u16 = 0x0039;
+const PR_SENT_REPRESENTING_NAME: u16 = 0x0042;
+const PR_SENT_REPRESENTING_EMAIL: u16 = 0x0065;
+const PR_TRANSPORT_MESSAGE_HEADERS: u16 = 0x007D;
+const PR_RECIPIENT_TYPE: u16 = 0x0C15;
+const PR_SENDER_NAME: u16 = 0x0C1A;
+const PR_SENDER_EMAIL: u16 = 0x0C1F;
+const PR_DISPLAY_BCC: u16 = 0x0E02;
+const PR_DISPLAY_CC: u16 = 0x0E03;
+const PR_DISPLAY_TO: u16 = 0x0E04;
+const PR_MESSAGE_DELIVERY_TIME: u16 = 0x0E06;
+const PR_BODY: u16 = 0x1000;
+const PR_RTF_COMPRESSED: u16 = 0x1009;
+const PR_BODY_HTML: u16 = 0x1013;
+const PR_INTERNET_MESSAGE_ID: u16 = 0x1035;
+const PR_INTERNET_REFERENCES: u16 = 0x1039;
+const PR_IN_REPLY_TO: u16 = 0x1042;
+const PR_DISPLAY_NAME: u16 = 0x3001;
+const PR_EMAIL_ADDRESS: u16 = 0x3003;
+const PR_CREATION_TIME: u16 = 0x3007;
+const PR_ATTACH_DATA: u16 = 0x3701;
+const PR_ATTACH_FILENAME: u16 = 0x3704;
+const PR_ATTACH_LONG_FILENAME: u16 = 0x3707;
+const PR_ATTACH_MIME_TAG: u16 = 0x370E;
+const PR_ATTACH_CONTENT_ID: u16 = 0x3712;
+const PR_SMTP_ADDRESS: u16 = 0x39FE;
+const PR_INTERNET_CPID: u16 = 0x3FDE;
+const PR_SENDER_SMTP_ADDRESS: u16 = 0x5D01;
+const PR_SENT_REPRESENTING_SMTP: u16 = 0x5D02;
+
+// MAPI property types.
+const PT_LONG: u16 = 0x0003;
+const PT_OBJECT: u16 = 0x000D;
+const PT_STRING8: u16 = 0x001E;
+const PT_UNICODE: u16 = 0x001F;
+const PT_SYSTIME: u16 = 0x0040;
+const PT_BINARY: u16 = 0x0102;
+
+const PROPERTIES_STREAM: &str = "__properties_version1.0";
+const SUBSTG_PREFIX: &str = "__substg1.0_";
+const RECIP_PREFIX: &str = "__recip_version1.0_";
+const ATTACH_PREFIX: &str = "__attach_version1.0_";
+// Size of the header before the fixed-size entries in __properties_version1.0.
+const TOP_LEVEL_HEADER: usize = 32;
+const EMBEDDED_HEADER: usize = 24;
+const CHILD_HEADER: usize = 8;
+const MAX_DEPTH: usize = 8;
+
+/// True for a compound file that carries MAPI message properties.
+pub fn looks_like_msg(data: &[u8]) -> bool {
+    CompoundFile::parse(data).is_some_and(|cf| {
+        let root = cf.root();
+        cf.child(root, PROPERTIES_STREAM).is_some()
+            || cf
+                .children(root)
+                .into_iter()
+                .any(|c| cf.entry(c).name.starts_with(SUBSTG_PREFIX))
+    })
+}
+
+/// Convert a `.msg` file into RFC822 bytes. None if the data isn't an Outlook message.
+pub fn to_rfc822(data: &[u8]) -> Option<Vec<u8>> {
+    if !looks_like_msg(data) {
+        return None;
+    }
+    let cf = CompoundFile::parse(data)?;
+    Some(message(&cf, cf.root(), TOP_LEVEL_HEADER, 0))
+}
+
+#[derive(Default)]
+struct Props {
+    strings: HashMap<u16, String>,
+    ansi: HashMap<u16, Vec<u8>>,
+    binaries: HashMap<u16, Vec<u8>>,
+    longs: HashMap<u16, u32>,
+    times: HashMap<u16, i64>,
+    /// Storage of an embedded message (attachment data stored as PT_OBJECT).
+    object: Option<usize>,
+}
+
+impl Props {
+    fn string(&self, id: u16) -> Option<&str> {
+        self.strings
+            .get(&id)
+            .map(|s| s.trim_end_matches('\0').trim())
+            .filter(|s| !s.is_empty())
+    }
+}
+
+fn read_props(cf: &CompoundFile, storage: usize, header: usize) -> Props {
+    let mut props = Props::default();
+    for child in cf.children(storage) {
+        let entry = cf.entry(child);
+        if entry.name == PROPERTIES_STREAM {
+            let raw = cf.read(child);
+            for item in raw.get(header..).unwrap_or_default().chunks_exact(16) {
+                let tag = u32::from_le_bytes([item[0], item[1], item[2], item[3]]);
+                let (id, ty) = ((tag >> 16) as u16, tag as u16);
+                let value = &item[8..16];
+                match ty {
+                    PT_LONG => {
+                        props.longs.insert(
+                            id,
+                            u32::from_le_bytes([value[0], value[1], value[2], value[3]]),
+                        );
+                    }
+                    PT_SYSTIME => {
+                        let filetime = u64::from_le_bytes(value.try_into().expect("8 bytes"));
+                        if filetime > 0 {
+                            let epoch = (filetime / 10_000_000) as i64 - 11_644_473_600;
+                            props.times.insert(id, epoch);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            continue;
+        }
+        let Some(tag) = entry.name.strip_prefix(SUBSTG_PREFIX) else {
+            continue;
+        };
+        let (Ok(id), Ok(ty)) = (
+            u16::from_str_radix(tag.get(..4).unwrap_or(""), 16),
+            u16::from_str_radix(tag.get(4..8).unwrap_or(""), 16),
+        ) else {
+            continue;
+        };
+        match ty {
+            PT_UNICODE => {
+                let raw = cf.read(child);
+                let units: Vec<u16> = raw
+                    .chunks_exact(2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    .collect();
+                props.strings.insert(id, String::from_utf16_lossy(&units));
+            }
+            PT_STRING8 => {
+                props.ansi.insert(id, cf.read(child));
+            }
+            PT_BINARY => {
+                props.binaries.insert(id, cf.read(child));
+            }
+            PT_OBJECT if id == PR_ATTACH_DATA && entry.kind == EntryKind::Storage => {
+                props.object = Some(child);
+            }
+            _ => {}
+        }
+    }
+    // 8-bit strings are in the message's code page.
+    let label = codepage_label(props.longs.get(&PR_INTERNET_CPID).copied());
+    for (id, raw) in std::mem::take(&mut props.ansi) {
+        props
+            .strings
+            .entry(id)
+            .or_insert_with(|| decode(&raw, label));
+    }
+    props
+}
+
+fn codepage_label(cpid: Option<u32>) -> &'static str {
+    match cpid {
+        Some(65001) => "utf-8",
+        Some(20127) => "us-ascii",
+        Some(1250) => "windows-1250",
+        Some(1251) => "windows-1251",
+        Some(1253) => "windows-1253",
+        Some(1254) => "windows-1254",
+        Some(1255) => "windows-1255",
+        Some(1256) => "windows-1256",
+        Some(1257) => "windows-1257",
+        Some(1258) => "windows-1258",
+        Some(28591) => "iso-8859-1",
+        Some(28592) => "iso-8859-2",
+        Some(28595) => "iso-8859-5",
+        Some(28605) => "iso-8859-15",
+        Some(932) => "shift_jis",
+        Some(936) => "gbk",
+        Some(949) => "euc-kr",
+        Some(950) => "big5",
+        Some(50220) => "iso-2022-jp",
+        Some(51932) => "euc-jp",
+        Some(874) => "windows-874",
+        _ => "windows-1252",
+    }
+}
+
+fn decode(raw: &[u8], label: &str) -> String {
+    let raw = raw.strip_suffix(&[0]).unwrap_or(raw);
+    match charset::Charset::for_label(label.as_bytes()) {
+        Some(cs) => cs.decode_without_bom_handling(raw).0.into_owned(),
+        None => String::from_utf8_lossy(raw).into_owned(),
+    }
+}
+
+/// Children of `storage` whose names start with `prefix`, in name (= index) order.
+fn sub_storages(cf: &CompoundFile, storage: usize, prefix: &str) -> Vec<usize> {
+    let mut out: Vec<usize> = cf
+        .children(storage)
+        .into_iter()
+        .filter(|&c| cf.entry(c).kind == EntryKind::Storage && cf.entry(c).name.starts_with(prefix))
+        .collect();
+    out.sort_by(|&a, &b| cf.entry(a).name.cmp(&cf.entry(b).name));
+    out
+}
+
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().fold(0u32, |acc, &b| acc << 8 | b as u32) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Base64 body wrapped at 76 columns.
+pub fn base64_lines(data: &[u8]) -> String {
+    let encoded = base64(data);
+    let mut out = String::with_capacity(encoded.len() + encoded.len() / 38);
+    for line in encoded.as_bytes().chunks(76) {
+        out.push_str(std::str::from_utf8(line).expect("ascii"));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Header-safe text: RFC 2047 encoded when not plain ASCII.
+fn header_text(value: &str) -> String {
+    let value = value.replace(['\r', '\n'], " ");
+    if value.is_ascii() {
+        value
+    } else {
+        format!("=?UTF-8?B?{}?=", base64(value.as_bytes()))
+    }
+}
+
+fn mailbox(name: Option<&str>, email: Option<&str>) -> Option<String> {
+    match (name.filter(|n| Some(*n) != email), email) {
+        (Some(name), Some(email)) if name.is_ascii() => Some(format!(
+            "\"{}\" <{email}>",
+            name.replace(['\\', '"'], "").replace(['\r', '\n'], " ")
+        )),
+        (Some(name), Some(email)) => Some(format!("{} <{email}>", header_text(name))),
+        (None, Some(email)) => Some(format!("<{email}>")),
+        (Some(name), None) => Some(header_text(name)),
+        (None, None) => None,
+    }
+}
+
+/// SMTP address from the first property that holds one (Exchange DNs are skipped).
+fn smtp<'a>(props: &'a Props, ids: &[u16]) -> Option<&'a str> {
+    ids.iter()
+        .filter_map(|&id| props.string(id))
+        .find(|v| v.contains('@'))
+}
+
+/// Header block from Outlook's saved transport headers, minus the MIME structure headers that
+/// the rebuilt body replaces.
+fn transport_headers(raw: &str) -> String {
+    const REPLACED: [&str; 4] = [
+        "content-type",
+        "content-transfer-encoding",
+        "mime-version",
+        "content-disposition",
+    ];
+    let mut out = String::new();
+    let mut skipping = false;
+    for line in raw.lines() {
+        if line.trim().is_empty() {
+            break;
+        }
+        if !line.starts_with([' ', '\t']) {
+            let name = line.split(':').next().unwrap_or("").trim();
+            skipping = REPLACED.iter().any(|r| name.eq_ignore_ascii_case(r));
+        }
+        if !skipping {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    }
+    out
+}
+
+fn message(cf: &CompoundFile, storage: usize, header: usize, depth: usize) -> Vec<u8> {
+    let props = read_props(cf, storage, header);
+    let mut head = String::new();
+
+    match props.string(PR_TRANSPORT_MESSAGE_HEADERS) {
+        Some(raw) if raw.contains(':') => head.push_str(&transport_headers(raw)),
+        _ => {
+            let from_email = smtp(
+                &props,
+                &[
+                    PR_SENDER_SMTP_ADDRESS,
+                    PR_SENT_REPRESENTING_SMTP,
+                    PR_SENDER_EMAIL,
+                    PR_SENT_REPRESENTING_EMAIL,
+                ],
+            );
+            let from_name = props
+                .string(PR_SENDER_NAME)
+                .or(props.string(PR_SENT_REPRESENTING_NAME));
+            if let Some(from) = mailbox(from_name, from_email) {
+                head.push_str(&format!("From: {from}\r\n"));
+            }
+
+            let mut by_type: [Vec<String>; 3] = Default::default();
+            for recip in sub_storages(cf, storage, RECIP_PREFIX) {
+                let r = read_props(cf, recip, CHILD_HEADER);
+                let kind = match r.longs.get(&PR_RECIPIENT_TYPE).map(|t| t & 0xF) {
+                    Some(2) => 1,
+                    Some(3) => 2,
+                    _ => 0,
+                };
+                let email = smtp(&r, &[PR_SMTP_ADDRESS, PR_EMAIL_ADDRESS]);
+                if let Some(addr) = mailbox(r.string(PR_DISPLAY_NAME), email) {
+                    by_type[kind].push(addr);
+                }
+            }
+            for ((name, display), addrs) in [
+                ("To", PR_DISPLAY_TO),
+                ("Cc", PR_DISPLAY_CC),
+                ("Bcc", PR_DISPLAY_BCC),
+            ]
+            .into_iter()
+            .zip(&by_type)
+            {
+                if !addrs.is_empty() {
+                    head.push_str(&format!("{name}: {}\r\n", addrs.join(", ")));
+                } else if let Some(names) = props.string(display) {
+                    head.push_str(&format!("{name}: {}\r\n", header_text(names)));
+                }
+            }
+
+            if let Some(subject) = props.string(PR_SUBJECT) {
+                head.push_str(&format!("Subject: {}\r\n", header_text(subject)));
+            }
+            let date = [
+                PR_CLIENT_SUBMIT_TIME,
+                PR_MESSAGE_DELIVERY_TIME,
+                PR_CREATION_TIME,
+            ]
+            .iter()
+            .find_map(|id| props.times.get(id));
+            if let Some(epoch) = date {
+                head.push_str(&format!("Date: {}\r\n", dates::format_rfc2822(*epoch)));
+            }
+            for (name, id) in [
+                ("Message-ID", PR_INTERNET_MESSAGE_ID),
+                ("In-Reply-To", PR_IN_REPLY_TO),
+                ("References", PR_INTERNET_REFERENCES),
+            ] {
+                if let Some(value) = props.string(id) {
+                    head.push_str(&format!("{name}: {}\r\n", header_text(value)));
+                }
+            }
+        }
+    }
+
+    let boundary = format!("=_msg_{depth}_mixed");
+    let alt_boundary = format!("=_msg_{depth}_alt");
+    let mut out = head.into_bytes();
+    out.extend_from_slice(
+        format!(
+            "MIME-Version: 1.0\r\nContent-Type: multipart/mixed; boundary=\"{boundary}\"\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+
+    let text = props.string(PR_BODY);
+    let html = props
+        .binaries
+        .get(&PR_BODY_HTML)
+        .filter(|h| !h.is_empty())
+        .map(|h| h.as_slice())
+        .or(props.string(PR_BODY_HTML).map(str::as_bytes));
+    if text.is_some() || html.is_some() {
+        out.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Type: multipart/alternative; boundary=\"{alt_boundary}\"\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        if let Some(text) = text {
+            out.extend_from_slice(
+                format!(
+                    "--{alt_boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+                    base64_lines(text.as_bytes())
+                )
+                .as_bytes(),
+            );
+        }
+        if let Some(html) = html {
+            // Binary HTML is in the message code page; string HTML was decoded to UTF-8.
+            let charset = if props.binaries.contains_key(&PR_BODY_HTML) {
+                codepage_label(props.longs.get(&PR_INTERNET_CPID).copied())
+            } else {
+                "utf-8"
+            };
+            out.extend_from_slice(
+                format!(
+                    "--{alt_boundary}\r\nContent-Type: text/html; charset={charset}\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+                    base64_lines(html)
+                )
+                .as_bytes(),
+            );
+        }
+        out.extend_from_slice(format!("--{alt_boundary}--\r\n").as_bytes());
+    }
+    if text.is_none() || html.is_none() {
+        if let Some(rtf) = props.binaries.get(&PR_RTF_COMPRESSED) {
+            out.extend_from_slice(
+                format!(
+                    "--{boundary}\r\nContent-Type: application/rtf; name=\"rtf-body.rtf\"\r\nContent-Disposition: attachment; filename=\"rtf-body.rtf\"\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+                    base64_lines(rtf)
+                )
+                .as_bytes(),
+            );
+        }
+    }
+
+    for attach in sub_storages(cf, storage, ATTACH_PREFIX) {
+        let a = read_props(cf, attach, CHILD_HEADER);
+        let filename = a
+            .string(PR_ATTACH_LONG_FILENAME)
+            .or(a.string(PR_ATTACH_FILENAME))
+            .or(a.string(PR_DISPLAY_NAME));
+        let (content_type, data) = match (a.object, a.binaries.get(&PR_ATTACH_DATA)) {
+            (Some(embedded), _) if depth < MAX_DEPTH => (
+                "message/rfc822".to_string(),
+                message(cf, embedded, EMBEDDED_HEADER, depth + 1),
+            ),
+            (_, Some(data)) => (
+                a.string(PR_ATTACH_MIME_TAG)
+                    .unwrap_or("application/octet-stream")
+                    .to_string(),
+                data.clone(),
+            ),
+            _ => continue,
+        };
+        let mut part = format!("--{boundary}\r\nContent-Type: {content_type}");
+        let disposition = if a.string(PR_ATTACH_CONTENT_ID).is_some() {
+            "inline"
+        } else {
+            "attachment"
+        };
+        match filename {
+            Some(name) if name.is_ascii() => {
+                let name = name.replace(['"', '\\', '\r', '\n'], "_");
+                part.push_str(&format!(
+                    "; name=\"{name}\"\r\nContent-Disposition: {disposition}; filename=\"{name}\""
+                ));
+            }
+            Some(name) => {
+                // RFC 2231 percent-encoded UTF-8.
+                let encoded: String = name
+                    .bytes()
+                    .map(|b| match b {
+                        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' => {
+                            (b as char).to_string()
+                        }
+                        _ => format!("%{b:02X}"),
+                    })
+                    .collect();
+                part.push_str(&format!(
+                    "\r\nContent-Disposition: {disposition}; filename*=UTF-8''{encoded}"
+                ));
+            }
+            None => part.push_str(&format!("\r\nContent-Disposition: {disposition}")),
+        }
+        if let Some(cid) = a.string(PR_ATTACH_CONTENT_ID) {
+            part.push_str(&format!(
+                "\r\nContent-ID: <{}>",
+                cid.trim_matches(['<', '>']).replace(['\r', '\n'], "")
+            ));
+        }
+        part.push_str("\r\nContent-Transfer-Encoding: base64\r\n\r\n");
+        out.extend_from_slice(part.as_bytes());
+        out.extend_from_slice(base64_lines(&data).as_bytes());
+    }
+    out.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfb::{build, TestNode};
+
+    fn unicode(id: u16, value: &str) -> TestNode<'static> {
+        let name: &'static str = Box::leak(format!("{SUBSTG_PREFIX}{id:04X}001F").into_boxed_str());
+        TestNode::Stream(
+            name,
+            value.encode_utf16().flat_map(|u| u.to_le_bytes()).collect(),
+        )
+    }
+
+    fn binary(id: u16, value: &[u8]) -> TestNode<'static> {
+        let name: &'static str = Box::leak(format!("{SUBSTG_PREFIX}{id:04X}0102").into_boxed_str());
+        TestNode::Stream(name, value.to_vec())
+    }
+
+    fn properties(header: usize, entries: &[(u16, u16, u64)]) -> TestNode<'static> {
+        let mut raw = vec![0u8; header];
+        for (id, ty, value) in entries {
+            raw.extend(((*id as u32) << 16 | *ty as u32).to_le_bytes());
+            raw.extend(6u32.to_le_bytes());
+            raw.extend(value.to_le_bytes());
+        }
+        TestNode::Stream(PROPERTIES_STREAM, raw)
+    }
+
+    #[test]
+    fn converts_msg_properties_to_rfc822() {
+        // 2024-02-29 12:34:56 UTC as a FILETIME.
+        let filetime = (1_709_210_096u64 + 11_644_473_600) * 10_000_000;
+        let file = build(&[
+            properties(
+                TOP_LEVEL_HEADER,
+                &[(PR_CLIENT_SUBMIT_TIME, PT_SYSTIME, filetime)],
+            ),
+            unicode(PR_SUBJECT, "Délai – programme"),
+            unicode(PR_SENDER_NAME, "Site Manager"),
+            unicode(
+                PR_SENDER_EMAIL,
+                "/O=EXCHANGE/OU=FIRST/CN=RECIPIENTS/CN=SITEMGR",
+            ),
+            unicode(PR_SENDER_SMTP_ADDRESS, "site.manager@example.com"),
+            unicode(PR_INTERNET_MESSAGE_ID, "<delay-1@example.com>"),
+            unicode(PR_BODY, "Pour is delayed to Monday."),
+            TestNode::Storage(
+                "__recip_version1.0_#00000000",
+                vec![
+                    properties(CHILD_HEADER, &[(PR_RECIPIENT_TYPE, PT_LONG, 1)]),
+                    unicode(PR_DISPLAY_NAME, "QS Team"),
+                    unicode(PR_SMTP_ADDRESS, "qs@example.com"),
+                ],
+            ),
+            TestNode::Storage(
+                "__recip_version1.0_#00000001",
+                vec![
+                    properties(CHILD_HEADER, &[(PR_RECIPIENT_TYPE, PT_LONG, 2)]),
+                    unicode(PR_SMTP_ADDRESS, "pm@example.com"),
+                ],
+            ),
+            TestNode::Storage(
+                "__attach_version1.0_#00000000",
+                vec![
+                    unicode(PR_ATTACH_LONG_FILENAME, "programme rev C.pdf"),
+                    unicode(PR_ATTACH_MIME_TAG, "application/pdf"),
+                    binary(PR_ATTACH_DATA, b"%PDF-1.4 programme"),
+                ],
+            ),
+        ]);
+        assert!(looks_like_msg(&file));
+        let raw = to_rfc822(&file).expect("msg");
+        let mail = mailparse::parse_mail(&raw).expect("mime");
+        use mailparse::MailHeaderMap;
+        let header = |name| mail.headers.get_first_value(name).unwrap_or_default();
+        assert_eq!(header("Subject"), "Délai – programme");
+        assert_eq!(
+            header("From"),
+            "\"Site Manager\" <site.manager@example.com>"
+        );
+        assert_eq!(header("To"), "\"QS Team\" <qs@example.com>");
+        assert_eq!(header("Cc"), "<pm@example.com>");
+        assert_eq!(header("Message-ID"), "<delay-1@example.com>");
+        assert_eq!(header("Date"), "Thu, 29 Feb 2024 12:34:56 +0000");
+
+        let text = &mail.subparts[0].subparts[0];
+        assert_eq!(text.get_body().expect("text"), "Pour is delayed to Monday.");
+        let pdf = &mail.subparts[1];
+        assert_eq!(pdf.ctype.mimetype, "application/pdf");
+        assert_eq!(pdf.get_body_raw().expect("pdf"), b"%PDF-1.4 programme");
+        assert_eq!(
+            pdf.get_content_disposition()
+                .params
+                .get("filename")
+                .map(String::as_str),
+            Some("programme rev C.pdf")
+        );
+
+        assert!(to_rfc822(&build(&[TestNode::Stream("WordDocument", vec![1, 2, 3])])).is_none());
+    }
+
+    #[test]
+    fn truncated_msg_files_do_not_panic() {
+        let file = build(&[
+            properties(TOP_LEVEL_HEADER, &[]),
+            unicode(PR_SUBJECT, "Site diary"),
+            unicode(PR_BODY, &"Concrete pour on level 3. ".repeat(40)),
+        ]);
+        assert!(to_rfc822(&file).is_some());
+        // Cut before the directory: not recognised as a message at all.
+        for len in [0, 100, 511, 1000] {
+            assert!(!looks_like_msg(&file[..len]), "{len}");
+            assert!(to_rfc822(&file[..len]).is_none(), "{len}");
+        }
+        // Cut inside the body stream: the message converts with what is left of the body.
+        let raw = to_rfc822(&file[..file.len() - 300]).expect("msg");
+        let mail = mailparse::parse_mail(&raw).expect("mime");
+        use mailparse::MailHeaderMap;
+        assert_eq!(
+            mail.headers.get_first_value("Subject").as_deref(),
+            Some("Site diary")
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Message-ID normalization and synthetic IDs.
+//!
+//! The same Message-ID reaches us as `<AbC@Mail.Example.com>`, ` <AbC@mail.example.com> ` or
+//! without brackets, depending on the client and on readpst. The normalized form drops the
+//! brackets and whitespace and lowercases the domain; the local part is case-sensitive and kept.
+//!
+//! Messages without a Message-ID (drafts, some Outlook items, calendar forwards) get a
+//! deterministic synthetic ID from sender, date, subject and body, so every extraction of the
+//! same message, in any PST, gives it the same ID. Synthetic IDs end in `@synthetic.invalid`
+//! (a reserved TLD) and can't collide with real ones.
+
+use sha2::{Digest, Sha256};
+
+const SYNTHETIC_DOMAIN: &str = "synthetic.invalid";
+
+/// `local@domain` from a Message-ID header value: the first `<...>` token, or the whole value
+/// when it has no brackets. None when nothing is left.
+pub fn normalize(raw: &str) -> Option<String> {
+    let token = match (raw.find('<'), raw.find('>')) {
+        (Some(open), Some(close)) if open < close => &raw[open + 1..close],
+        _ => raw.trim().trim_matches(['<', '>']),
+    };
+    let id: String = token.chars().filter(|c| !c.is_whitespace()).collect();
+    if id.is_empty() {
+        return None;
+    }
+    Some(match id.rsplit_once('@') {
+        Some((local, domain)) => format!("{local}@{}", domain.to_ascii_lowercase()),
+        None => id,
+    })
+}
+
+/// A stable ID for a message without one. Whitespace in the body is collapsed, as folder
+/// copies are often re-wrapped.
+pub fn synthetic(
+    sender: Option<&str>,
+    date_epoch: Option<i64>,
+    subject: Option<&str>,
+    body: &str,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "from:{}\ndate:{}\nsubject:{}\nbody:",
+        sender.unwrap_or("").trim().to_ascii_lowercase(),
+        date_epoch.map(|d| d.to_string()).unwrap_or_default(),
+        subject.unwrap_or("").trim()
+    ));
+    for (i, word) in body.split_whitespace().enumerate() {
+        if i > 0 {
+            hasher.update(b" ");
+        }
+        hasher.update(word.as_bytes());
+    }
+    let digest = format!("{:x}", hasher.finalize());
+    format!("{}@{SYNTHETIC_DOMAIN}", &digest[..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_and_synthesizes_ids() {
+        assert_eq!(
+            normalize(" <AbC.123@Mail.Example.COM> ").as_deref(),
+            Some("AbC.123@mail.example.com")
+        );
+        assert_eq!(
+            normalize("AbC@Example.com").as_deref(),
+            Some("AbC@example.com")
+        );
+        assert_eq!(
+            normalize("<a b@x.example>").as_deref(),
+            Some("ab@x.example")
+        );
+        assert_eq!(normalize("<>"), None);
+        assert_eq!(normalize("  "), None);
+
+        let id = synthetic(
+            Some("A@x.example"),
+            Some(1),
+            Some("Hi"),
+            "one  two\r\nthree",
+        );
+        assert!(id.ends_with("@synthetic.invalid"));
+        assert_eq!(
+            id,
+            synthetic(Some("a@x.example"), Some(1), Some(" Hi "), "one two three")
+        );
+        assert_ne!(
+            id,
+            synthetic(Some("a@x.example"), Some(2), Some("Hi"), "one two three")
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Near-duplicate fingerprints and clusters.
+//!
+//! Exact dedupe (`dedupe`) only catches byte-for-byte copies. Reviewers also want the "FYI"
+//! forwards of a message and the successive edits of a draft grouped with it, so every email gets
+//! a 64-bit simhash (`near_dupe_hash`) over three-word shingles of its normalized body: lowercase
+//! words only, so quote markers, punctuation and re-wrapping don't count. `--near-dupe-source`
+//! picks the body: `clean` (the default, `body_text_clean`) groups edits of the same text, while
+//! `full` keeps quoted replies, since a forward's own text is often just "FYI" and the content it
+//! shares with the original is the quoted part.
+//!
+//! After the parse pass, emails whose fingerprints differ in at most
+//! `(1 - --near-dupe-similarity) * 64` bits are joined into clusters (transitively), and every
+//! member of a cluster of two or more gets the same `near_dupe_cluster_id`. Candidate pairs come
+//! from splitting the fingerprint into one more band than the allowed distance: two fingerprints
+//! within the distance agree on at least one whole band.
+
+use std::collections::HashMap;
+
+/// Bodies with fewer words don't get a fingerprint; "Thanks" and "FYI" say nothing.
+const MIN_WORDS: usize = 8;
+const SHINGLE_WORDS: usize = 3;
+
+/// The simhash of `text`, or None if it is too short to compare.
+pub fn simhash(text: &str) -> Option<u64> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    if words.len() < MIN_WORDS {
+        return None;
+    }
+    let mut weights = [0i64; 64];
+    for shingle in words.windows(SHINGLE_WORDS) {
+        let hash = shingle_hash(shingle);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+        }
+    }
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| **w > 0)
+            .fold(0u64, |acc, (bit, _)| acc | 1 << bit),
+    )
+}
+
+/// FNV-1a over the words, finished with SplitMix64 so every bit is well mixed. Stable across
+/// builds and platforms, unlike `DefaultHasher`.
+fn shingle_hash(words: &[String]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for word in words {
+        for byte in word.bytes().chain([b' ']) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Most differing bits for a similarity in 0..=1.
+pub fn max_distance(similarity: f64) -> u32 {
+    ((1.0 - similarity.clamp(0.0, 1.0)) * 64.0).floor() as u32
+}
+
+/// Clusters of two or more inputs (email id, fingerprint) within `max_distance` bits of each
+/// other, transitively. Each cluster lists its email ids in input order.
+pub fn clusters(inputs: &[(String, u64)], max_distance: u32) -> Vec<Vec<String>> {
+    let mut parent: Vec<usize> = (0..inputs.len()).collect();
+
+    // Identical fingerprints are joined directly; the bands only compare distinct ones.
+    let mut by_value: HashMap<u64, usize> = HashMap::new();
+    for (i, (_, hash)) in inputs.iter().enumerate() {
+        match by_value.get(hash) {
+            Some(&first) => union(&mut parent, first, i),
+            None => {
+                by_value.insert(*hash, i);
+            }
+        }
+    }
+    if max_distance > 0 {
+        let bands = (max_distance + 1).min(64);
+        let width = 64 / bands;
+        let mut buckets: HashMap<(u32, u64), Vec<usize>> = HashMap::new();
+        for (&hash, &i) in &by_value {
+            for band in 0..bands {
+                let shift = band * width;
+                let bits = if band == bands - 1 { 64 - shift } else { width };
+                let mask = if bits == 64 {
+                    u64::MAX
+                } else {
+                    (1 << bits) - 1
+                };
+                buckets
+                    .entry((band, hash >> shift & mask))
+                    .or_default()
+                    .push(i);
+            }
+        }
+        for members in buckets.values() {
+            for (n, &a) in members.iter().enumerate() {
+                for &b in &members[n + 1..] {
+                    if (inputs[a].1 ^ inputs[b].1).count_ones() <= max_distance {
+                        union(&mut parent, a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<String>> = HashMap::new();
+    for (i, (id, _)) in inputs.iter().enumerate() {
+        let r = root(&mut parent, i);
+        groups.entry(r).or_default().push(id.clone());
+    }
+    let mut clusters: Vec<(usize, Vec<String>)> = groups
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .collect();
+    clusters.sort_by_key(|(r, _)| *r);
+    clusters.into_iter().map(|(_, ids)| ids).collect()
+}
+
+/// Union-find root, halving the path on the way.
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Join two sets under the lower index, so a cluster's root is its first input.
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (ra, rb) = (root(parent, a), root(parent, b));
+    if ra != rb {
+        parent[ra.max(rb)] = ra.min(rb);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clusters_forwards_and_edits() {
+        let original = "Please find attached the revised programme for the works. The delay to \
+                        the steel frame is now eleven days and we expect the cladding package to \
+                        start on the fourteenth of March subject to the crane being available. \
+                        The contractor has asked for an extension of time under clause 2.26 and \
+                        has provided a revised critical path showing the knock-on effect on the \
+                        mechanical and electrical installation. Could you review the attached \
+                        and let me have your comments by Friday so that we can respond to the \
+                        employer's agent before the next progress meeting on site.";
+        let forward = format!("FYI\n\n-----Original Message-----\n> {original}");
+        let edited = original.replace("eleven days", "twelve days");
+        let other = "The minutes of Tuesday's progress meeting are attached; actions are on \
+                     page two and the next meeting is on the twentieth at the site office.";
+
+        let fp = |text: &str| simhash(text).expect("fingerprint");
+        assert!((fp(original) ^ fp(&forward)).count_ones() <= max_distance(0.9));
+        assert!((fp(original) ^ fp(other)).count_ones() > max_distance(0.9));
+        assert_eq!(simhash("FYI, see below."), None);
+
+        let inputs = vec![
+            ("a".to_string(), fp(original)),
+            ("b".to_string(), fp(other)),
+            ("c".to_string(), fp(&forward)),
+            ("d".to_string(), fp(&edited)),
+            ("e".to_string(), fp(other)),
+        ];
+        assert_eq!(
+            clusters(&inputs, max_distance(0.9)),
+            [vec!["a", "c", "d"], vec!["b", "e"]]
+        );
+        assert_eq!(clusters(&inputs, 0), [vec!["b", "e"]]);
+        assert_eq!(max_distance(0.95), 3);
+    }
+}
@@ -0,0 +1,270 @@
+//! OpenSearch/Elasticsearch bulk indexing sink.
+//!
+//! Emails are buffered and sent via the `_bulk` API as they are parsed so review UIs can search a
+//! PST minutes after extraction starts. Documents rejected with 429 (queue full) are retried with
+//! backoff; other per-item failures are counted and reported in the manifest. Fields computed
+//! after the parse pass (thread assignments) are applied later as partial updates.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Bulk request size, in documents.
+const BULK_BATCH_DOCS: usize = 500;
+/// Attempts per batch before giving up on throttled documents.
+const BULK_MAX_ATTEMPTS: u32 = 6;
+
+/// Indexing counts recorded in the manifest.
+#[derive(Serialize, Default, Clone)]
+pub struct IndexStats {
+    pub index_name: String,
+    pub docs_sent: usize,
+    pub docs_indexed: usize,
+    pub docs_failed: usize,
+    /// Documents for this PST visible in the index after a final refresh, if the count query
+    /// succeeded. Should equal `docs_indexed` on a clean run.
+    pub index_count: Option<u64>,
+    /// Partial updates (e.g. thread fields) applied to already-indexed documents.
+    pub docs_updated: usize,
+    pub updates_failed: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BulkOp {
+    Index,
+    Update,
+}
+
+pub struct BulkIndexer {
+    client: reqwest::Client,
+    base_url: String,
+    auth: Option<(String, String)>,
+    // (op, id, serialized document) awaiting the next flush.
+    pending: Vec<(BulkOp, String, String)>,
+    stats: IndexStats,
+}
+
+impl BulkIndexer {
+    pub fn new(base_url: &str, index_name: &str, auth: Option<(String, String)>) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .context("build OpenSearch client")?;
+        Ok(Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            auth,
+            pending: Vec::new(),
+            stats: IndexStats {
+                index_name: index_name.to_string(),
+                ..IndexStats::default()
+            },
+        })
+    }
+
+    pub async fn add<T: Serialize>(&mut self, id: &str, doc: &T) -> Result<()> {
+        self.push(BulkOp::Index, id, serde_json::to_string(doc)?)
+            .await;
+        Ok(())
+    }
+
+    /// Merge `fields` into an already-indexed document.
+    pub async fn update<T: Serialize>(&mut self, id: &str, fields: &T) -> Result<()> {
+        let doc = serde_json::to_string(&serde_json::json!({ "doc": fields }))?;
+        self.push(BulkOp::Update, id, doc).await;
+        Ok(())
+    }
+
+    async fn push(&mut self, op: BulkOp, id: &str, doc: String) {
+        self.pending.push((op, id.to_string(), doc));
+        if self.pending.len() >= BULK_BATCH_DOCS {
+            self.flush().await;
+        }
+    }
+
+    fn count_failed(&mut self, docs: &[(BulkOp, String, String)]) {
+        for (op, _, _) in docs {
+            match op {
+                BulkOp::Index => self.stats.docs_failed += 1,
+                BulkOp::Update => self.stats.updates_failed += 1,
+            }
+        }
+    }
+
+    /// Send buffered documents. Failures are counted rather than aborting the extraction.
+    pub async fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut batch = std::mem::take(&mut self.pending);
+        self.stats.docs_sent += batch
+            .iter()
+            .filter(|(op, _, _)| *op == BulkOp::Index)
+            .count();
+        let mut attempt = 0u32;
+        while !batch.is_empty() {
+            attempt += 1;
+            let body = bulk_body(&self.stats.index_name, &batch);
+            let retry = match self.post_bulk(body).await {
+                Ok(BulkResponse::Throttled) => batch.len(),
+                Ok(BulkResponse::Items(statuses)) => {
+                    let mut throttled = Vec::new();
+                    for (doc, status) in batch.into_iter().zip(statuses) {
+                        match (status, doc.0) {
+                            (200..=299, BulkOp::Index) => self.stats.docs_indexed += 1,
+                            (200..=299, BulkOp::Update) => self.stats.docs_updated += 1,
+                            (429, _) => throttled.push(doc),
+                            _ => self.count_failed(std::slice::from_ref(&doc)),
+                        }
+                    }
+                    batch = throttled;
+                    batch.len()
+                }
+                Err(e) => {
+                    tracing::warn!("opensearch bulk request failed: {e:#}");
+                    self.count_failed(&batch);
+                    return;
+                }
+            };
+            if retry == 0 {
+                break;
+            }
+            if attempt >= BULK_MAX_ATTEMPTS {
+                tracing::warn!(
+                    attempts = attempt,
+                    dropped = retry,
+                    "opensearch still throttling; dropping docs"
+                );
+                self.count_failed(&batch);
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(250 * 2u64.pow(attempt))).await;
+        }
+    }
+
+    async fn post_bulk(&self, body: String) -> Result<BulkResponse> {
+        let resp = self
+            .request(reqwest::Method::POST, "/_bulk")
+            .header("Content-Type", "application/x-ndjson")
+            .body(body)
+            .send()
+            .await?;
+        if resp.status().as_u16() == 429 {
+            return Ok(BulkResponse::Throttled);
+        }
+        if !resp.status().is_success() {
+            return Err(anyhow!("bulk returned HTTP {}", resp.status()));
+        }
+        let json: serde_json::Value = resp.json().await?;
+        Ok(BulkResponse::Items(item_statuses(&json)))
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let req = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.auth {
+            Some((user, pass)) => req.basic_auth(user, Some(pass)),
+            None => req,
+        }
+    }
+
+    /// Flush remaining documents, then refresh and count this PST's documents for reconciliation.
+    pub async fn finish(mut self, pst_file_id: &str) -> IndexStats {
+        self.flush().await;
+        self.stats.index_count = self.count_for(pst_file_id).await.ok();
+        self.stats
+    }
+
+    async fn count_for(&self, pst_file_id: &str) -> Result<u64> {
+        let index = &self.stats.index_name;
+        self.request(reqwest::Method::POST, &format!("/{index}/_refresh"))
+            .send()
+            .await?
+            .error_for_status()?;
+        let query = serde_json::json!({ "query": { "term": { "pst_file_id": pst_file_id } } });
+        let json: serde_json::Value = self
+            .request(reqwest::Method::POST, &format!("/{index}/_count"))
+            .json(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        json.get("count")
+            .and_then(|c| c.as_u64())
+            .ok_or_else(|| anyhow!("count missing from response"))
+    }
+}
+
+enum BulkResponse {
+    Throttled,
+    Items(Vec<u16>),
+}
+
+fn bulk_body(index: &str, docs: &[(BulkOp, String, String)]) -> String {
+    let mut body = String::new();
+    for (op, id, doc) in docs {
+        let op = match op {
+            BulkOp::Index => "index",
+            BulkOp::Update => "update",
+        };
+        let action = serde_json::json!({ op: { "_index": index, "_id": id } });
+        body.push_str(&action.to_string());
+        body.push('\n');
+        body.push_str(doc);
+        body.push('\n');
+    }
+    body
+}
+
+/// Per-item HTTP statuses from a `_bulk` response, in request order.
+fn item_statuses(resp: &serde_json::Value) -> Vec<u16> {
+    resp.get("items")
+        .and_then(|v| v.as_array())
+        .map(|items| {
+            items
+                .iter()
+                .map(|item| {
+                    item.as_object()
+                        .and_then(|o| o.values().next())
+                        .and_then(|v| v.get("status"))
+                        .and_then(|s| s.as_u64())
+                        .unwrap_or(500) as u16
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_bulk_body_and_reads_item_statuses() {
+        let docs = vec![
+            (BulkOp::Index, "a".to_string(), "{\"x\":1}".to_string()),
+            (
+                BulkOp::Update,
+                "b".to_string(),
+                "{\"doc\":{\"x\":2}}".to_string(),
+            ),
+        ];
+        let body = bulk_body("emails", &docs);
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].contains("\"_id\":\"a\""));
+        assert!(lines[2].starts_with("{\"update\""));
+        assert_eq!(lines[3], "{\"doc\":{\"x\":2}}");
+
+        let resp = serde_json::json!({
+            "errors": true,
+            "items": [
+                { "index": { "_id": "a", "status": 201 } },
+                { "index": { "_id": "b", "status": 429 } }
+            ]
+        });
+        assert_eq!(item_statuses(&resp), vec![201, 429]);
+    }
+}
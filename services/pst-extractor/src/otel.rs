@@ -0,0 +1,309 @@
+//! OpenTelemetry trace export (`OTEL_EXPORTER_OTLP_ENDPOINT`).
+//!
+//! The `tracing` spans the extractor already opens become OpenTelemetry spans: `job` (one per
+//! PST), `phase` (`download`, `readpst`, `parse`, `upload`) and `parse_file` (one per extracted
+//! mail file). When a job finishes they are POSTed to `{endpoint}/v1/traces` as OTLP/HTTP JSON,
+//! so a job shows up in the end-to-end trace view next to the orchestrator's own spans.
+//!
+//! The orchestrator passes its trace context in the `TRACEPARENT` (and optionally `TRACESTATE`)
+//! environment variable, in W3C `traceparent` form; job spans then join that trace as children of
+//! its span. Without it each job starts a new trace.
+//!
+//! The OTLP protobuf/gRPC stack isn't a dependency, so only the HTTP JSON encoding is supported:
+//! point the endpoint at a collector's OTLP/HTTP receiver (port 4318). Export failures are logged
+//! and never fail the job.
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Spans buffered per export; a huge PST with one `parse_file` span per message is cut off here.
+const MAX_BUFFERED_SPANS: usize = 20_000;
+
+/// A W3C trace context (`00-{trace_id}-{span_id}-{flags}`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: String,
+    pub span_id: String,
+}
+
+impl TraceParent {
+    pub fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        let [version, trace_id, span_id, flags] = parts[..] else {
+            return None;
+        };
+        let hex = |s: &str, len: usize| {
+            s.len() == len
+                && s.bytes()
+                    .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+                && s.bytes().any(|b| b != b'0')
+        };
+        (version == "00" && hex(trace_id, 32) && hex(span_id, 16) && flags.len() == 2).then(|| {
+            Self {
+                trace_id: trace_id.to_string(),
+                span_id: span_id.to_string(),
+            }
+        })
+    }
+}
+
+/// OpenTelemetry identity of an open span.
+#[derive(Debug, Clone)]
+pub struct SpanContext {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    pub start: SystemTime,
+}
+
+pub struct FinishedSpan {
+    pub context: SpanContext,
+    pub name: &'static str,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, Value)>,
+}
+
+pub struct Collector {
+    url: String,
+    headers: Vec<(String, String)>,
+    service: String,
+    parent: Option<TraceParent>,
+    trace_state: Option<String>,
+    spans: Mutex<Vec<FinishedSpan>>,
+    dropped: AtomicU64,
+}
+
+static COLLECTOR: OnceLock<Collector> = OnceLock::new();
+
+pub fn new_trace_id() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+pub fn new_span_id() -> String {
+    Uuid::new_v4().simple().to_string()[..16].to_string()
+}
+
+/// `OTEL_EXPORTER_OTLP_HEADERS` form: `key=value,key2=value2`.
+fn parse_headers(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+fn unix_nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_nanos()
+        .to_string()
+}
+
+fn attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({"boolValue": b}),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({"intValue": n.to_string()}),
+        Value::Number(n) => json!({"doubleValue": n.as_f64()}),
+        Value::String(s) => json!({"stringValue": s}),
+        other => json!({"stringValue": other.to_string()}),
+    };
+    json!({"key": key, "value": value})
+}
+
+impl Collector {
+    pub fn new(
+        endpoint: &str,
+        service: &str,
+        traceparent: Option<&str>,
+        trace_state: Option<&str>,
+        headers: Option<&str>,
+    ) -> Self {
+        let parent = traceparent.and_then(TraceParent::parse);
+        Self {
+            url: format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            headers: headers.map(parse_headers).unwrap_or_default(),
+            service: service.to_string(),
+            trace_state: trace_state.filter(|_| parent.is_some()).map(str::to_string),
+            parent,
+            spans: Mutex::new(Vec::new()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Context for a span opened with `parent` (None for a root span, which joins the
+    /// orchestrator's trace when one was passed in).
+    pub fn start(&self, parent: Option<&SpanContext>) -> SpanContext {
+        let (trace_id, parent_span_id) = match (parent, &self.parent) {
+            (Some(parent), _) => (parent.trace_id.clone(), Some(parent.span_id.clone())),
+            (None, Some(remote)) => (remote.trace_id.clone(), Some(remote.span_id.clone())),
+            (None, None) => (new_trace_id(), None),
+        };
+        SpanContext {
+            trace_id,
+            span_id: new_span_id(),
+            parent_span_id,
+            start: SystemTime::now(),
+        }
+    }
+
+    pub fn finish(&self, span: FinishedSpan) {
+        let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+        if spans.len() < MAX_BUFFERED_SPANS {
+            spans.push(span);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// OTLP/HTTP JSON `ExportTraceServiceRequest`.
+    fn request(&self, spans: &[FinishedSpan]) -> Value {
+        let spans: Vec<Value> = spans
+            .iter()
+            .map(|span| {
+                let mut out = json!({
+                    "traceId": span.context.trace_id,
+                    "spanId": span.context.span_id,
+                    "name": span.name,
+                    // SPAN_KIND_INTERNAL
+                    "kind": 1,
+                    "startTimeUnixNano": unix_nanos(span.context.start),
+                    "endTimeUnixNano": unix_nanos(span.end),
+                    "attributes": span
+                        .attributes
+                        .iter()
+                        .map(|(k, v)| attribute(k, v))
+                        .collect::<Vec<_>>(),
+                });
+                if let Some(parent) = &span.context.parent_span_id {
+                    out["parentSpanId"] = parent.as_str().into();
+                }
+                if let (Some(state), Some(remote)) = (&self.trace_state, &self.parent) {
+                    if span.context.trace_id == remote.trace_id {
+                        out["traceState"] = state.as_str().into();
+                    }
+                }
+                out
+            })
+            .collect();
+        let service = attribute("service.name", &Value::from(self.service.as_str()));
+        json!({
+            "resourceSpans": [{
+                "resource": {"attributes": [service]},
+                "scopeSpans": [{
+                    "scope": {"name": "pst-extractor", "version": env!("CARGO_PKG_VERSION")},
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+
+    async fn export(&self) -> Result<()> {
+        let spans = std::mem::take(&mut *self.spans.lock().unwrap_or_else(|e| e.into_inner()));
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            tracing::warn!(dropped, "trace export buffer full; spans dropped");
+        }
+        if spans.is_empty() {
+            return Ok(());
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        let mut req = client.post(&self.url).json(&self.request(&spans));
+        for (k, v) in &self.headers {
+            req = req.header(k.as_str(), v.as_str());
+        }
+        let resp = req
+            .send()
+            .await
+            .with_context(|| format!("export traces to {}", self.url))?;
+        if !resp.status().is_success() {
+            return Err(anyhow!("OTLP endpoint returned HTTP {}", resp.status()));
+        }
+        Ok(())
+    }
+}
+
+/// Enable export. Called once at startup, before any span is opened.
+pub fn init(endpoint: &str, service: &str) {
+    let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let collector = Collector::new(
+        endpoint,
+        service,
+        env("TRACEPARENT").as_deref(),
+        env("TRACESTATE").as_deref(),
+        env("OTEL_EXPORTER_OTLP_HEADERS").as_deref(),
+    );
+    if env("TRACEPARENT").is_some() && collector.parent.is_none() {
+        tracing::warn!("ignoring malformed TRACEPARENT");
+    }
+    let _ = COLLECTOR.set(collector);
+}
+
+pub fn collector() -> Option<&'static Collector> {
+    COLLECTOR.get()
+}
+
+/// Send the spans finished so far. A failed export is logged, not returned.
+pub async fn flush() {
+    if let Some(collector) = collector() {
+        if let Err(e) = collector.export().await {
+            tracing::warn!("trace export failed: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_remote_trace_and_encodes_otlp_json() {
+        let remote = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        assert!(
+            TraceParent::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(TraceParent::parse("garbage").is_none());
+        let collector = Collector::new(
+            "http://collector:4318/",
+            "pst-extractor",
+            Some(remote),
+            Some("vendor=1"),
+            Some("x-api-key=k1, bad"),
+        );
+        assert_eq!(collector.url, "http://collector:4318/v1/traces");
+        assert_eq!(collector.headers, [("x-api-key".into(), "k1".into())]);
+
+        let job = collector.start(None);
+        assert_eq!(job.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(job.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        let phase = collector.start(Some(&job));
+        assert_eq!(phase.trace_id, job.trace_id);
+        assert_eq!(phase.parent_span_id.as_ref(), Some(&job.span_id));
+        assert_eq!(phase.span_id.len(), 16);
+
+        let spans = [FinishedSpan {
+            context: phase,
+            name: "phase",
+            end: SystemTime::now(),
+            attributes: vec![("phase", "parse".into()), ("files", 3.into())],
+        }];
+        let body = collector.request(&spans);
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "pst-extractor"
+        );
+        let span = &resource["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["name"], "phase");
+        assert_eq!(span["traceState"], "vendor=1");
+        assert_eq!(span["attributes"][0]["value"]["stringValue"], "parse");
+        assert_eq!(span["attributes"][1]["value"]["intValue"], "3");
+        assert!(span["startTimeUnixNano"].as_str().expect("start").len() >= 19);
+    }
+}
@@ -0,0 +1,180 @@
+//! `--result-file`: a machine-readable outcome for Step Functions and Batch.
+//!
+//! A single-PST run writes one JSON document when it finishes, success or not: the status, the
+//! manifest key and counts, and for a failure an `error_class` the orchestrator can branch on
+//! instead of parsing log lines. The process exit code carries the same class, so a Batch retry
+//! strategy (`onExitCode`) can tell a corrupt PST, which won't improve on retry, from a full disk,
+//! which might.
+//!
+//! Classification goes by the error chain: `ENOSPC` and the scratch-space checks, encrypted
+//! inputs, a missing readpst, readpst or ZIP failures on the input, and the storage backends'
+//! not-found and access-denied responses. Anything else is `INTERNAL`.
+
+use serde::Serialize;
+
+/// Why a job failed.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorClass {
+    /// Scratch space ran out, or wouldn't fit the download.
+    DiskFull,
+    /// An encrypted or password-protected input.
+    PasswordRequired,
+    /// readpst failed on the PST, or a ZIP input couldn't be read.
+    CorruptPst,
+    /// The source object or path doesn't exist.
+    SourceNotFound,
+    /// The storage backend refused a request.
+    AccessDenied,
+    /// No readpst binary was found, or it couldn't be started.
+    ReadpstUnavailable,
+    Internal,
+}
+
+impl ErrorClass {
+    /// Exit code for this class; sysexits(3) values where one fits.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Internal => 1,
+            Self::CorruptPst => 65,         // EX_DATAERR
+            Self::SourceNotFound => 66,     // EX_NOINPUT
+            Self::ReadpstUnavailable => 69, // EX_UNAVAILABLE
+            Self::DiskFull => 74,           // EX_IOERR
+            Self::AccessDenied => 77,       // EX_NOPERM
+            Self::PasswordRequired => 79,
+        }
+    }
+}
+
+/// Lowercased fragments of error messages, per class, checked in this order.
+const PATTERNS: &[(ErrorClass, &[&str])] = &[
+    (
+        ErrorClass::DiskFull,
+        &[
+            "no space left",
+            "--min-free-bytes",
+            "not enough scratch space",
+        ],
+    ),
+    (ErrorClass::PasswordRequired, &["is encrypted", "password"]),
+    (
+        ErrorClass::ReadpstUnavailable,
+        &["install libpst", "set readpst_path"],
+    ),
+    (
+        ErrorClass::CorruptPst,
+        &[
+            "readpst failed",
+            "is not a zip",
+            "end of central directory",
+            "zip64",
+        ],
+    ),
+    (
+        ErrorClass::SourceNotFound,
+        &[
+            "nosuchkey",
+            "nosuchbucket",
+            "notfound",
+            "http 404",
+            "is not a file",
+        ],
+    ),
+    (
+        ErrorClass::AccessDenied,
+        &[
+            "accessdenied",
+            "forbidden",
+            "http 403",
+            "authorizationfailure",
+        ],
+    ),
+];
+
+/// The class of a job's error.
+pub fn classify(error: &anyhow::Error) -> ErrorClass {
+    let disk_full = error.chain().any(|cause| {
+        cause.downcast_ref::<std::io::Error>().is_some_and(|e| {
+            e.kind() == std::io::ErrorKind::StorageFull || e.raw_os_error() == Some(28)
+        })
+    });
+    if disk_full {
+        return ErrorClass::DiskFull;
+    }
+    // Debug as well as Display: SDK errors only name their error code in Debug.
+    let message = error
+        .chain()
+        .map(|cause| format!("{cause}\n{cause:?}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .to_lowercase();
+    if message.contains("spawn ") && message.contains("readpst") {
+        return ErrorClass::ReadpstUnavailable;
+    }
+    PATTERNS
+        .iter()
+        .find(|(_, fragments)| fragments.iter().any(|f| message.contains(f)))
+        .map_or(ErrorClass::Internal, |(class, _)| *class)
+}
+
+/// What `--result-file` holds.
+#[derive(Serialize, Debug)]
+pub struct ResultFile {
+    /// The manifest status (`complete`, `partial`, `interrupted`), or `failed`.
+    pub status: &'static str,
+    pub exit_code: i32,
+    pub pst_file_id: String,
+    pub output_bucket: String,
+    pub manifest_key: Option<String>,
+    pub emails_total: Option<usize>,
+    pub attachments_total: Option<usize>,
+    pub duration_s: Option<f64>,
+    pub error_class: Option<ErrorClass>,
+    pub error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn classifies_errors_by_chain() {
+        let enospc = Err::<(), _>(std::io::Error::from_raw_os_error(28))
+            .context("write /work/extract/Inbox/1")
+            .unwrap_err();
+        assert_eq!(classify(&enospc), ErrorClass::DiskFull);
+        let cases = [
+            (
+                "readpst failed with status exit status: 255",
+                ErrorClass::CorruptPst,
+            ),
+            (
+                "zip: Inbox/1.eml is encrypted",
+                ErrorClass::PasswordRequired,
+            ),
+            (
+                "source path /in/x.pst is not a file",
+                ErrorClass::SourceNotFound,
+            ),
+            (
+                "readpst not found next to the extractor, on PATH, or in []; install libpst \
+                 (apt install pst-utils) or set READPST_PATH",
+                ErrorClass::ReadpstUnavailable,
+            ),
+            (
+                "download gs://b/k: HTTP 403 Forbidden: denied",
+                ErrorClass::AccessDenied,
+            ),
+            ("re-read email record for threading", ErrorClass::Internal),
+        ];
+        for (message, class) in cases {
+            assert_eq!(classify(&anyhow!(message)), class, "{message}");
+            assert!(class.exit_code() != 0 && class.exit_code() != 75);
+        }
+        let wrapped = Err::<(), _>(anyhow!("service error: NoSuchKey"))
+            .context("download s3://in/a.pst")
+            .unwrap_err();
+        assert_eq!(classify(&wrapped), ErrorClass::SourceNotFound);
+    }
+}
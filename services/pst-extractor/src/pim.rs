@@ -0,0 +1,334 @@
+//! Calendar items, contacts and tasks.
+//!
+//! readpst writes non-mail PST items next to the messages: appointments as iCalendar
+//! (`BEGIN:VCALENDAR` / `VEVENT`) and contacts as vCards. Those files used to be skipped as
+//! "not mail"; they are parsed here into records for `calendar.ndjson.gz`,
+//! `contacts.ndjson.gz` and `tasks.ndjson.gz` (`VTODO` components).
+
+use crate::dates;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Default)]
+pub struct CalendarEvent {
+    pub uid: Option<String>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub organizer: Option<String>,
+    pub attendees: Vec<String>,
+    pub start: Option<String>,
+    pub start_epoch: Option<i64>,
+    pub end: Option<String>,
+    pub end_epoch: Option<i64>,
+    pub all_day: bool,
+    pub recurrence_rule: Option<String>,
+    pub status: Option<String>,
+    /// SEQUENCE: revision number, bumped by the organizer on each significant update.
+    pub sequence: Option<u32>,
+    pub created: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Task {
+    pub uid: Option<String>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub start: Option<String>,
+    pub due: Option<String>,
+    pub due_epoch: Option<i64>,
+    pub completed: Option<String>,
+    pub completed_epoch: Option<i64>,
+    pub percent_complete: Option<u8>,
+    pub status: Option<String>,
+    pub priority: Option<u8>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Contact {
+    pub full_name: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub organization: Option<String>,
+    pub title: Option<String>,
+    pub emails: Vec<String>,
+    pub phones: Vec<String>,
+    pub addresses: Vec<String>,
+    pub birthday: Option<String>,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct PimItems {
+    pub events: Vec<CalendarEvent>,
+    pub tasks: Vec<Task>,
+    pub contacts: Vec<Contact>,
+    /// The VCALENDAR's METHOD (REQUEST, CANCEL, REPLY, ...) for iTIP messages.
+    pub method: Option<String>,
+}
+
+/// True for iCalendar / vCard content (leading whitespace ignored).
+pub fn looks_like_pim(bytes: &[u8]) -> bool {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let head = &bytes[start..bytes.len().min(start + 16)];
+    [
+        &b"BEGIN:VCALENDAR"[..],
+        b"BEGIN:VEVENT",
+        b"BEGIN:VTODO",
+        b"BEGIN:VCARD",
+    ]
+    .iter()
+    .any(|tag| head.len() >= tag.len() && head[..tag.len()].eq_ignore_ascii_case(tag))
+}
+
+/// One content line: NAME;PARAM=..:VALUE (parameters dropped, value still escaped).
+struct Property {
+    name: String,
+    value: String,
+}
+
+/// Unfold continuation lines (RFC 5545 §3.1 / RFC 6350 §3.2) and split into properties.
+fn properties(text: &str) -> Vec<Property> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if let Some(rest) = line.strip_prefix([' ', '\t']) {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(rest);
+                continue;
+            }
+        }
+        lines.push(line.to_string());
+    }
+    lines
+        .into_iter()
+        .filter_map(|line| {
+            let colon = line.find(':')?;
+            let (head, value) = (&line[..colon], &line[colon + 1..]);
+            let name = head.split(';').next().unwrap_or(head);
+            Some(Property {
+                name: name.trim().to_ascii_uppercase(),
+                value: value.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+fn text(value: &str) -> Option<String> {
+    let v = unescape(value).trim().to_string();
+    (!v.is_empty()).then_some(v)
+}
+
+/// "mailto:a@b" → "a@b".
+fn cal_address(value: &str) -> Option<String> {
+    let v = value.trim();
+    let v = if v.len() >= 7 && v[..7].eq_ignore_ascii_case("mailto:") {
+        &v[7..]
+    } else {
+        v
+    };
+    text(v)
+}
+
+/// iCalendar DATE / DATE-TIME ("20210303", "20210303T100000", "20210303T100000Z"). Values with
+/// a TZID or no zone are read as UTC.
+fn ics_epoch(value: &str) -> Option<i64> {
+    let v = value.trim().trim_end_matches(['Z', 'z']);
+    let (date, time) = v.split_once(['T', 't']).unwrap_or((v, "000000"));
+    if date.len() != 8
+        || time.len() < 4
+        || !date.bytes().chain(time.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let num = |s: &str| s.parse::<u32>().ok();
+    dates::to_epoch(
+        date[..4].parse().ok()?,
+        num(&date[4..6])?,
+        num(&date[6..8])?,
+        (
+            num(&time[..2])?,
+            num(&time[2..4])?,
+            time.get(4..6).and_then(num).unwrap_or(0),
+        ),
+        0,
+    )
+}
+
+/// Parse an iCalendar or vCard file. Unknown components are ignored.
+pub fn parse(bytes: &[u8]) -> PimItems {
+    let text_content = String::from_utf8_lossy(bytes);
+    let mut items = PimItems::default();
+    let mut event: Option<CalendarEvent> = None;
+    let mut task: Option<Task> = None;
+    let mut contact: Option<Contact> = None;
+    // Nested components (VALARM inside VEVENT) must not overwrite the parent's fields.
+    let mut nested = 0usize;
+
+    for prop in properties(&text_content) {
+        let value = prop.value.as_str();
+        match (
+            prop.name.as_str(),
+            value.trim().to_ascii_uppercase().as_str(),
+        ) {
+            ("BEGIN", "VEVENT") => event = Some(CalendarEvent::default()),
+            ("BEGIN", "VTODO") => task = Some(Task::default()),
+            ("BEGIN", "VCARD") => contact = Some(Contact::default()),
+            ("BEGIN", "VCALENDAR") => {}
+            ("BEGIN", _) => nested += 1,
+            ("END", "VEVENT") => items.events.extend(event.take()),
+            ("END", "VTODO") => items.tasks.extend(task.take()),
+            ("END", "VCARD") => items.contacts.extend(contact.take()),
+            ("END", "VCALENDAR") => {}
+            ("END", _) => nested = nested.saturating_sub(1),
+            _ if nested > 0 => {}
+            _ => {
+                if let Some(ev) = event.as_mut() {
+                    apply_event(ev, &prop);
+                } else if let Some(t) = task.as_mut() {
+                    apply_task(t, &prop);
+                } else if let Some(c) = contact.as_mut() {
+                    apply_contact(c, &prop);
+                } else if prop.name == "METHOD" {
+                    items.method = text(value).map(|m| m.to_ascii_uppercase());
+                }
+            }
+        }
+    }
+    items
+}
+
+fn apply_event(ev: &mut CalendarEvent, prop: &Property) {
+    let v = prop.value.as_str();
+    match prop.name.as_str() {
+        "UID" => ev.uid = text(v),
+        "SUMMARY" => ev.summary = text(v),
+        "DESCRIPTION" => ev.description = text(v),
+        "LOCATION" => ev.location = text(v),
+        "ORGANIZER" => ev.organizer = cal_address(v),
+        "ATTENDEE" => ev.attendees.extend(cal_address(v)),
+        "DTSTART" => {
+            ev.start = text(v);
+            ev.start_epoch = ics_epoch(v);
+            // VALUE=DATE values are bare dates (YYYYMMDD).
+            ev.all_day = v.trim().len() == 8;
+        }
+        "DTEND" => {
+            ev.end = text(v);
+            ev.end_epoch = ics_epoch(v);
+        }
+        "RRULE" => ev.recurrence_rule = text(v),
+        "STATUS" => ev.status = text(v),
+        "SEQUENCE" => ev.sequence = v.trim().parse().ok(),
+        "CREATED" => ev.created = text(v),
+        "LAST-MODIFIED" => ev.last_modified = text(v),
+        _ => {}
+    }
+}
+
+fn apply_task(t: &mut Task, prop: &Property) {
+    let v = prop.value.as_str();
+    match prop.name.as_str() {
+        "UID" => t.uid = text(v),
+        "SUMMARY" => t.summary = text(v),
+        "DESCRIPTION" => t.description = text(v),
+        "DTSTART" => t.start = text(v),
+        "DUE" => {
+            t.due = text(v);
+            t.due_epoch = ics_epoch(v);
+        }
+        "COMPLETED" => {
+            t.completed = text(v);
+            t.completed_epoch = ics_epoch(v);
+        }
+        "PERCENT-COMPLETE" => t.percent_complete = v.trim().parse().ok(),
+        "STATUS" => t.status = text(v),
+        "PRIORITY" => t.priority = v.trim().parse().ok(),
+        _ => {}
+    }
+}
+
+fn apply_contact(c: &mut Contact, prop: &Property) {
+    let v = prop.value.as_str();
+    match prop.name.as_str() {
+        "FN" => c.full_name = text(v),
+        "N" => {
+            // Family;Given;Additional;Prefix;Suffix
+            let mut parts = v.split(';').map(text);
+            c.family_name = parts.next().flatten();
+            c.given_name = parts.next().flatten();
+        }
+        "ORG" => c.organization = text(&v.replace(';', ", ")),
+        "TITLE" => c.title = text(v),
+        "EMAIL" => c.emails.extend(text(v)),
+        "TEL" => c.phones.extend(text(v)),
+        "ADR" => {
+            let parts: Vec<String> = v.split(';').filter_map(text).collect();
+            if !parts.is_empty() {
+                c.addresses.push(parts.join(", "));
+            }
+        }
+        "BDAY" => c.birthday = text(v),
+        "NOTE" => c.note = text(v),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_events_tasks_and_contacts() {
+        let ics = b"BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nUID:abc-1\r\nSUMMARY:Site meeting\\, phase 2\r\nLOCATION:Room 4\r\nORGANIZER;CN=Ann:mailto:ann@example.com\r\nATTENDEE;CN=Bob:MAILTO:bob@example.com\r\nDTSTART:20210303T100000Z\r\nDTEND:20210303T110000Z\r\nDESCRIPTION:Agenda:\\n1. Delays\r\n  and claims\r\nBEGIN:VALARM\r\nDESCRIPTION:Reminder\r\nEND:VALARM\r\nEND:VEVENT\r\nBEGIN:VTODO\r\nSUMMARY:Submit EOT notice\r\nDUE;VALUE=DATE:20210310\r\nPERCENT-COMPLETE:50\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        assert!(looks_like_pim(ics));
+        let items = parse(ics);
+        assert_eq!(items.events.len(), 1);
+        let ev = &items.events[0];
+        assert_eq!(ev.summary.as_deref(), Some("Site meeting, phase 2"));
+        assert_eq!(ev.organizer.as_deref(), Some("ann@example.com"));
+        assert_eq!(ev.attendees, vec!["bob@example.com"]);
+        assert_eq!(ev.start_epoch, Some(1614765600));
+        assert_eq!(ev.end_epoch, Some(1614769200));
+        assert_eq!(
+            ev.description.as_deref(),
+            Some("Agenda:\n1. Delays and claims")
+        );
+        assert!(!ev.all_day);
+        assert_eq!(items.tasks.len(), 1);
+        assert_eq!(items.tasks[0].due_epoch, Some(1615334400));
+        assert_eq!(items.tasks[0].percent_complete, Some(50));
+
+        let vcf = b"BEGIN:VCARD\nVERSION:3.0\nFN:Jane Smith\nN:Smith;Jane;;;\nORG:Acme Ltd;Commercial\nEMAIL;TYPE=work:jane@acme.test\nTEL;TYPE=cell:+44 7700 900000\nADR;TYPE=work:;;1 High St;London;;EC1A 1AA;UK\nEND:VCARD\n";
+        let items = parse(vcf);
+        assert_eq!(items.contacts.len(), 1);
+        let c = &items.contacts[0];
+        assert_eq!(c.full_name.as_deref(), Some("Jane Smith"));
+        assert_eq!(c.family_name.as_deref(), Some("Smith"));
+        assert_eq!(c.given_name.as_deref(), Some("Jane"));
+        assert_eq!(c.organization.as_deref(), Some("Acme Ltd, Commercial"));
+        assert_eq!(c.emails, vec!["jane@acme.test"]);
+        assert_eq!(c.addresses, vec!["1 High St, London, EC1A 1AA, UK"]);
+        assert!(!looks_like_pim(b"From: a@b\r\n"));
+    }
+}
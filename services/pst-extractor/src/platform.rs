@@ -0,0 +1,131 @@
+//! Host differences for running outside the Linux container: scratch location, `readpst`
+//! discovery and portable relative paths.
+//!
+//! Collection engineers run point extractions on Windows and macOS laptops, where there is no
+//! `/scratch` and `readpst` usually isn't on `PATH` (Homebrew, MacPorts, MSYS2/Cygwin builds, or a
+//! copy shipped next to the extractor in the release zip).
+
+use anyhow::{anyhow, Result};
+use std::path::{Component, Path, PathBuf};
+
+/// Default `--work-dir`: `/scratch` on Linux (the container's NVMe/EBS mount), otherwise a
+/// directory under the user's temp dir.
+pub fn default_work_dir() -> String {
+    if cfg!(target_os = "linux") {
+        "/scratch".to_string()
+    } else {
+        std::env::temp_dir()
+            .join("pst-extractor")
+            .display()
+            .to_string()
+    }
+}
+
+fn executable_name(stem: &str) -> String {
+    format!("{stem}{}", std::env::consts::EXE_SUFFIX)
+}
+
+/// Well-known install locations that aren't always on `PATH`.
+fn known_dirs() -> Vec<PathBuf> {
+    let dirs: &[&str] = if cfg!(windows) {
+        &[
+            r"C:\Program Files\libpst\bin",
+            r"C:\msys64\ucrt64\bin",
+            r"C:\msys64\mingw64\bin",
+            r"C:\msys64\usr\bin",
+            r"C:\cygwin64\bin",
+        ]
+    } else if cfg!(target_os = "macos") {
+        &["/opt/homebrew/bin", "/usr/local/bin", "/opt/local/bin"]
+    } else {
+        &["/usr/bin", "/usr/local/bin"]
+    };
+    dirs.iter().map(PathBuf::from).collect()
+}
+
+/// First executable called `stem` found in `candidates`, in order.
+fn find_in(stem: &str, candidates: impl IntoIterator<Item = PathBuf>) -> Option<PathBuf> {
+    let name = executable_name(stem);
+    candidates
+        .into_iter()
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
+
+/// Resolve `--readpst-path`. A value containing a path separator is used as given; a bare name
+/// (the default `readpst`) is looked up next to this executable (bundled builds: `./readpst`,
+/// `./libpst/bin/readpst`), then on `PATH`, then in the platform's usual install locations.
+pub fn locate_readpst(configured: &str) -> Result<PathBuf> {
+    let configured_path = Path::new(configured);
+    if configured_path.components().count() > 1 {
+        return Ok(configured_path.to_path_buf());
+    }
+    let mut candidates = Vec::new();
+    if let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        candidates.push(exe_dir.join("libpst").join("bin"));
+        candidates.push(exe_dir);
+    }
+    if let Some(path) = std::env::var_os("PATH") {
+        candidates.extend(std::env::split_paths(&path));
+    }
+    candidates.extend(known_dirs());
+    find_in(configured, candidates).ok_or_else(|| {
+        anyhow!(
+            "{configured} not found next to the extractor, on PATH, or in {:?}; install libpst \
+             (apt install pst-utils / brew install libpst / MSYS2 pacman -S libpst) or set \
+             READPST_PATH",
+            known_dirs()
+        )
+    })
+}
+
+/// `/`-joined form of a relative path, so `source_path` values and S3 keys are the same on
+/// Windows as on Linux.
+pub fn portable_rel_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_bundled_readpst_and_keeps_explicit_paths() {
+        let dir = std::env::temp_dir().join(format!("pst-platform-{}", uuid::Uuid::new_v4()));
+        let bundled = dir.join("libpst").join("bin");
+        std::fs::create_dir_all(&bundled).expect("mkdir");
+        assert_eq!(find_in("readpst", [dir.clone(), bundled.clone()]), None);
+        std::fs::write(bundled.join(executable_name("readpst")), b"").expect("write");
+        assert_eq!(
+            find_in("readpst", [dir.clone(), bundled.clone()]),
+            Some(bundled.join(executable_name("readpst")))
+        );
+        std::fs::remove_dir_all(&dir).ok();
+
+        let explicit = Path::new("tools").join("readpst");
+        assert_eq!(
+            locate_readpst(&explicit.display().to_string()).expect("explicit"),
+            explicit
+        );
+    }
+
+    #[test]
+    fn relative_paths_use_forward_slashes() {
+        let rel: PathBuf = ["Top of Personal Folders", "Inbox", "12.eml"]
+            .iter()
+            .collect();
+        assert_eq!(
+            portable_rel_path(&rel),
+            "Top of Personal Folders/Inbox/12.eml"
+        );
+    }
+}
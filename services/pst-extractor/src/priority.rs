@@ -0,0 +1,192 @@
+//! Importance, sensitivity and receipt metadata.
+//!
+//! Productions routinely carry these fields, but clients spell them many ways. Importance comes
+//! from `Importance`, else `X-Priority` (1–5), else `X-MSMail-Priority` or `Priority`
+//! (RFC 2156 `urgent` / `non-urgent`). Sensitivity comes from `Sensitivity`
+//! (`Personal`, `Private`, `Company-Confidential`).
+//!
+//! Receipts are classified both ways round: whether the sender asked for one
+//! (`Disposition-Notification-To` or `X-Confirm-Reading-To` for a read receipt,
+//! `Return-Receipt-To` for a delivery receipt), and whether the message is one (an RFC 8098
+//! `message/disposition-notification` part, or a delivery report's recipient actions).
+
+use crate::dsn::DeliveryStatus;
+use mailparse::{MailHeaderMap, ParsedMail};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Importance {
+    High,
+    Normal,
+    Low,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sensitivity {
+    Personal,
+    Private,
+    Confidential,
+}
+
+/// What kind of receipt a message is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptType {
+    /// Read receipt: the recipient displayed the message.
+    Read,
+    /// Not-read notification: deleted unread, or the receipt was denied.
+    NotRead,
+    Delivered,
+    Delayed,
+    NotDelivered,
+}
+
+fn first(mail: &ParsedMail, name: &str) -> Option<String> {
+    mail.headers
+        .get_first_value(name)
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty())
+}
+
+pub fn importance(mail: &ParsedMail) -> Option<Importance> {
+    let from_word = |word: &str| match word {
+        "high" | "urgent" => Some(Importance::High),
+        "normal" => Some(Importance::Normal),
+        "low" | "non-urgent" => Some(Importance::Low),
+        _ => None,
+    };
+    if let Some(value) = first(mail, "Importance") {
+        return from_word(&value);
+    }
+    // "1 (Highest)", "3", "5 (Lowest)".
+    if let Some(value) = first(mail, "X-Priority") {
+        return match value.trim_start().chars().next() {
+            Some('1' | '2') => Some(Importance::High),
+            Some('3') => Some(Importance::Normal),
+            Some('4' | '5') => Some(Importance::Low),
+            _ => None,
+        };
+    }
+    ["X-MSMail-Priority", "Priority"]
+        .iter()
+        .find_map(|name| first(mail, name))
+        .and_then(|value| from_word(&value))
+}
+
+pub fn sensitivity(mail: &ParsedMail) -> Option<Sensitivity> {
+    match first(mail, "Sensitivity")?.as_str() {
+        "personal" => Some(Sensitivity::Personal),
+        "private" => Some(Sensitivity::Private),
+        "company-confidential" | "confidential" => Some(Sensitivity::Confidential),
+        _ => None,
+    }
+}
+
+pub fn read_receipt_requested(mail: &ParsedMail) -> bool {
+    ["Disposition-Notification-To", "X-Confirm-Reading-To"]
+        .iter()
+        .any(|name| mail.headers.get_first_header(name).is_some())
+}
+
+pub fn delivery_receipt_requested(mail: &ParsedMail) -> bool {
+    mail.headers.get_first_header("Return-Receipt-To").is_some()
+}
+
+/// The receipt this message is, if any. `delivery_status` is the message's parsed DSN part.
+pub fn receipt_type(
+    mail: &ParsedMail,
+    delivery_status: Option<&DeliveryStatus>,
+) -> Option<ReceiptType> {
+    if let Some(disposition) = find_disposition(mail) {
+        // "manual-action/MDN-sent-manually; displayed"
+        let kind = disposition.rsplit(';').next().unwrap_or_default();
+        let kind = kind.trim().split('/').next().unwrap_or_default();
+        return match kind.to_ascii_lowercase().as_str() {
+            "displayed" => Some(ReceiptType::Read),
+            "deleted" | "denied" | "failed" => Some(ReceiptType::NotRead),
+            _ => None,
+        };
+    }
+    let actions: Vec<&str> = delivery_status?
+        .recipients
+        .iter()
+        .filter_map(|r| r.action.as_deref())
+        .collect();
+    let any = |wanted: &[&str]| actions.iter().any(|a| wanted.contains(a));
+    if any(&["failed"]) {
+        Some(ReceiptType::NotDelivered)
+    } else if any(&["delayed"]) {
+        Some(ReceiptType::Delayed)
+    } else if any(&["delivered", "relayed", "expanded"]) {
+        Some(ReceiptType::Delivered)
+    } else {
+        None
+    }
+}
+
+/// The `Disposition` field of a `message/disposition-notification` part.
+fn find_disposition(mail: &ParsedMail) -> Option<String> {
+    if mail.subparts.is_empty() {
+        let mimetype = mail.ctype.mimetype.to_ascii_lowercase();
+        if mimetype != "message/disposition-notification"
+            && mimetype != "message/global-disposition-notification"
+        {
+            return None;
+        }
+        let body = mail.get_body().ok()?;
+        let (fields, _) = mailparse::parse_headers(body.as_bytes()).ok()?;
+        return fields.get_first_value("Disposition");
+    }
+    mail.subparts.iter().find_map(find_disposition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsn::RecipientStatus;
+
+    #[test]
+    fn reads_priority_sensitivity_and_receipts() {
+        let parse = |raw: &'static [u8]| mailparse::parse_mail(raw).expect("parse");
+        let mail = parse(
+            b"X-Priority: 1 (Highest)\r\nSensitivity: Company-Confidential\r\n\
+              Disposition-Notification-To: a@example.com\r\n\r\nbody",
+        );
+        assert_eq!(importance(&mail), Some(Importance::High));
+        assert_eq!(sensitivity(&mail), Some(Sensitivity::Confidential));
+        assert!(read_receipt_requested(&mail));
+        assert!(!delivery_receipt_requested(&mail));
+        assert_eq!(receipt_type(&mail, None), None);
+
+        let mail = parse(b"Importance: low\r\nX-Priority: 1\r\n\r\nbody");
+        assert_eq!(importance(&mail), Some(Importance::Low));
+        assert_eq!(
+            importance(&parse(b"Priority: non-urgent\r\n\r\nx")),
+            Some(Importance::Low)
+        );
+
+        let mdn = parse(
+            b"Content-Type: multipart/report; report-type=disposition-notification; \
+              boundary=\"r\"\r\n\r\n--r\r\nContent-Type: text/plain\r\n\r\nRead.\r\n--r\r\n\
+              Content-Type: message/disposition-notification\r\n\r\n\
+              Final-Recipient: rfc822; b@example.com\r\n\
+              Disposition: manual-action/MDN-sent-manually; displayed\r\n--r--\r\n",
+        );
+        assert_eq!(receipt_type(&mdn, None), Some(ReceiptType::Read));
+
+        let report = DeliveryStatus {
+            recipients: vec![RecipientStatus {
+                action: Some("delivered".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mail = parse(b"Subject: x\r\n\r\nx");
+        assert_eq!(
+            receipt_type(&mail, Some(&report)),
+            Some(ReceiptType::Delivered)
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Structured progress events and heartbeats.
+//!
+//! Large PSTs can take an hour with no output. A reporter task periodically prints a JSON progress
+//! line on stdout (and optionally publishes it to SNS and/or upserts a DynamoDB item) so the
+//! orchestration layer can show a progress bar and detect stalled jobs.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Download,
+    Readpst,
+    Parse,
+    Upload,
+    Complete,
+}
+
+/// Shared counters updated by the extraction as it runs.
+pub struct Progress {
+    pst_file_id: String,
+    started: Instant,
+    phase: Mutex<Phase>,
+    pub bytes_downloaded: AtomicU64,
+    pub bytes_total: AtomicU64,
+    pub files_total: AtomicU64,
+    pub files_done: AtomicU64,
+    pub messages_parsed: AtomicU64,
+    pub attachments_uploaded: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct ProgressEvent {
+    event: &'static str,
+    pst_file_id: String,
+    phase: Phase,
+    percent: f64,
+    bytes_downloaded: u64,
+    bytes_total: u64,
+    files_done: u64,
+    files_total: u64,
+    messages_parsed: u64,
+    attachments_uploaded: u64,
+    elapsed_s: f64,
+    ts_epoch: u64,
+}
+
+impl Progress {
+    pub fn new(pst_file_id: &str) -> Self {
+        Self {
+            pst_file_id: pst_file_id.to_string(),
+            started: Instant::now(),
+            phase: Mutex::new(Phase::Download),
+            bytes_downloaded: AtomicU64::new(0),
+            bytes_total: AtomicU64::new(0),
+            files_total: AtomicU64::new(0),
+            files_done: AtomicU64::new(0),
+            messages_parsed: AtomicU64::new(0),
+            attachments_uploaded: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_phase(&self, phase: Phase) {
+        *self.phase.lock().expect("progress lock") = phase;
+    }
+
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Rough overall completion: download 0–10%, readpst 10–20%, parse 20–95% (by files
+    /// walked), upload 95–100%.
+    fn percent(&self, phase: Phase) -> f64 {
+        let ratio = |done: &AtomicU64, total: &AtomicU64| {
+            let total = total.load(Ordering::Relaxed);
+            if total == 0 {
+                0.0
+            } else {
+                (done.load(Ordering::Relaxed) as f64 / total as f64).min(1.0)
+            }
+        };
+        match phase {
+            Phase::Download => 10.0 * ratio(&self.bytes_downloaded, &self.bytes_total),
+            Phase::Readpst => 10.0,
+            Phase::Parse => 20.0 + 75.0 * ratio(&self.files_done, &self.files_total),
+            Phase::Upload => 95.0,
+            Phase::Complete => 100.0,
+        }
+    }
+
+    pub fn snapshot(&self) -> ProgressEvent {
+        let phase = *self.phase.lock().expect("progress lock");
+        ProgressEvent {
+            event: "progress",
+            pst_file_id: self.pst_file_id.clone(),
+            phase,
+            percent: (self.percent(phase) * 10.0).round() / 10.0,
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            bytes_total: self.bytes_total.load(Ordering::Relaxed),
+            files_done: self.files_done.load(Ordering::Relaxed),
+            files_total: self.files_total.load(Ordering::Relaxed),
+            messages_parsed: self.messages_parsed.load(Ordering::Relaxed),
+            attachments_uploaded: self.attachments_uploaded.load(Ordering::Relaxed),
+            elapsed_s: self.started.elapsed().as_secs_f64(),
+            ts_epoch: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// Where a job publishes its [`Progress`] for an observer outside the job (`--serve`).
+pub type Slot = Arc<Mutex<Option<Arc<Progress>>>>;
+
+tokio::task_local! {
+    static SLOT: Slot;
+}
+
+/// Run `job` with its progress published to `slot` once the extraction starts.
+pub async fn observed<F: std::future::Future>(slot: Slot, job: F) -> F::Output {
+    SLOT.scope(slot, job).await
+}
+
+/// Publish the running job's progress to its observer, if one is listening.
+pub fn publish(progress: &Arc<Progress>) {
+    let _ = SLOT.try_with(|slot| *slot.lock().expect("progress slot") = Some(Arc::clone(progress)));
+}
+
+/// Optional destinations in addition to stdout.
+#[derive(Clone, Default)]
+pub struct ProgressSinks {
+    pub sns: Option<(aws_sdk_sns::Client, String)>,
+    pub dynamodb: Option<(aws_sdk_dynamodb::Client, String)>,
+}
+
+/// Print one event and forward it to the configured sinks. Sink failures are logged only; a
+/// progress hiccup must never fail the extraction.
+pub async fn emit(progress: &Progress, sinks: &ProgressSinks) {
+    let event = progress.snapshot();
+    let json = match serde_json::to_string(&event) {
+        Ok(j) => j,
+        Err(_) => return,
+    };
+    println!("{json}");
+
+    if let Some((sns, topic)) = &sinks.sns {
+        if let Err(e) = sns.publish().topic_arn(topic).message(&json).send().await {
+            tracing::warn!("progress: SNS publish failed: {e}");
+        }
+    }
+    if let Some((ddb, table)) = &sinks.dynamodb {
+        use aws_sdk_dynamodb::types::AttributeValue;
+        let n = |v: u64| AttributeValue::N(v.to_string());
+        let result = ddb
+            .put_item()
+            .table_name(table)
+            .item("pst_file_id", AttributeValue::S(event.pst_file_id.clone()))
+            .item(
+                "phase",
+                AttributeValue::S(format!("{:?}", event.phase).to_lowercase()),
+            )
+            .item("percent", AttributeValue::N(event.percent.to_string()))
+            .item("messages_parsed", n(event.messages_parsed))
+            .item("attachments_uploaded", n(event.attachments_uploaded))
+            .item("bytes_downloaded", n(event.bytes_downloaded))
+            .item("updated_at", n(event.ts_epoch))
+            .item("event", AttributeValue::S(json.clone()))
+            .send()
+            .await;
+        if let Err(e) = result {
+            tracing::warn!("progress: DynamoDB put failed: {e}");
+        }
+    }
+}
+
+/// Aborts the periodic reporter when the job finishes or bails out early.
+pub struct ReporterGuard(Option<tokio::task::JoinHandle<()>>);
+
+impl Drop for ReporterGuard {
+    fn drop(&mut self) {
+        if let Some(handle) = self.0.take() {
+            handle.abort();
+        }
+    }
+}
+
+/// Emit an event every `interval_secs` until the guard is dropped. 0 disables the reporter.
+pub fn spawn_reporter(
+    progress: Arc<Progress>,
+    sinks: ProgressSinks,
+    interval_secs: u64,
+) -> ReporterGuard {
+    if interval_secs == 0 {
+        return ReporterGuard(None);
+    }
+    // The reporter logs under the spans of the job that started it.
+    let handle = tokio::spawn(
+        async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+            loop {
+                ticker.tick().await;
+                emit(&progress, &sinks).await;
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+    ReporterGuard(Some(handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_tracks_phase_and_file_counts() {
+        let p = Progress::new("pst-1");
+        p.bytes_total.store(200, Ordering::Relaxed);
+        Progress::add(&p.bytes_downloaded, 100);
+        assert_eq!(p.snapshot().percent, 5.0);
+
+        p.set_phase(Phase::Parse);
+        p.files_total.store(4, Ordering::Relaxed);
+        Progress::add(&p.files_done, 2);
+        assert_eq!(p.snapshot().percent, 57.5);
+
+        p.set_phase(Phase::Complete);
+        let json = serde_json::to_value(p.snapshot()).expect("json");
+        assert_eq!(json["phase"], "complete");
+        assert_eq!(json["pst_file_id"], "pst-1");
+    }
+}
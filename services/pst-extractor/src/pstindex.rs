@@ -0,0 +1,636 @@
+//! Node IDs and byte locations of the messages inside a PST (`pst_index.ndjson.gz`).
+//!
+//! readpst reports neither, so with `--pst-index` the extractor reads the PST's node database
+//! itself (the NDB layer of [MS-PST]). The node B-tree lists every node with its ID (NID), its
+//! parent folder and its data and subnode blocks; the block B-tree gives each block's offset in
+//! the file and its size. Every message node becomes one row: the NID, the offset and size of
+//! its data block (for a data tree, the XBLOCK's offset and the total size of the blocks below
+//! it) and the offset of its subnode block, which is where an examiner finds the item in the
+//! original file.
+//!
+//! Rows are tied to email records by the message's `PidTagInternetMessageId`, read from the
+//! node's property context, and folder names come from the folders' display names. Both are
+//! only readable in PSTs without encryption (`bCryptMethod` 0): Outlook's default compressible
+//! encryption scrambles the property blocks, so those rows carry offsets and sizes but no
+//! `message_id`, `folder` or `email_id`. PSTs with 4K pages (wVer 36) are not read.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8] = b"!BDN";
+const PAGE_SIZE: usize = 512;
+const PTYPE_BBT: u8 = 0x80;
+const PTYPE_NBT: u8 = 0x81;
+/// Deeper B-trees than this only come from corrupt (or looping) pages.
+const MAX_DEPTH: u8 = 8;
+
+const NID_TYPE_FOLDER: u32 = 0x02;
+const NID_TYPE_MESSAGE: u32 = 0x04;
+const NID_TYPE_ASSOCIATED: u32 = 0x08;
+
+const PROP_MESSAGE_ID: u16 = 0x1035;
+const PROP_DISPLAY_NAME: u16 = 0x3001;
+const PT_STRING8: u16 = 0x1E;
+const PT_UNICODE: u16 = 0x1F;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Message,
+    /// Folder-associated information: views, rules and other hidden items.
+    Associated,
+}
+
+#[derive(Debug)]
+pub struct Message {
+    pub nid: u32,
+    pub kind: Kind,
+    pub parent_nid: u32,
+    pub data_offset: Option<u64>,
+    pub data_size: Option<u64>,
+    pub subnode_offset: Option<u64>,
+    pub message_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Index {
+    /// `unicode` or `ansi`.
+    pub format: &'static str,
+    /// `none`, `permute` (compressible) or `cyclic` (high).
+    pub encryption: &'static str,
+    pub messages: Vec<Message>,
+    pub folders: HashMap<u32, String>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct Summary {
+    pub format: Option<&'static str>,
+    pub encryption: Option<&'static str>,
+    pub messages_total: usize,
+    /// Messages whose Message-ID matched an email record.
+    pub matched_total: usize,
+    /// Why the PST couldn't be indexed; the rest of the run is unaffected.
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    nid: u32,
+    kind: Kind,
+    parent_nid: u32,
+    folder: Option<&'a str>,
+    data_offset: Option<u64>,
+    data_size: Option<u64>,
+    subnode_offset: Option<u64>,
+    message_id: Option<&'a str>,
+    email_id: Option<&'a str>,
+}
+
+impl Index {
+    /// Normalized Message-IDs of the indexed messages (see `msgid::normalize`).
+    pub fn message_ids(&self) -> HashSet<String> {
+        self.messages
+            .iter()
+            .filter_map(|m| m.message_id.as_deref().and_then(crate::msgid::normalize))
+            .collect()
+    }
+
+    /// One row per message; `emails` maps normalized Message-IDs to email record IDs.
+    pub fn write(&self, emails: &HashMap<String, String>, mut out: impl Write) -> Result<Summary> {
+        let mut matched_total = 0;
+        for m in &self.messages {
+            let email_id = m
+                .message_id
+                .as_deref()
+                .and_then(crate::msgid::normalize)
+                .and_then(|id| emails.get(&id));
+            matched_total += usize::from(email_id.is_some());
+            let row = Row {
+                nid: m.nid,
+                kind: m.kind,
+                parent_nid: m.parent_nid,
+                folder: self.folders.get(&m.parent_nid).map(String::as_str),
+                data_offset: m.data_offset,
+                data_size: m.data_size,
+                subnode_offset: m.subnode_offset,
+                message_id: m.message_id.as_deref(),
+                email_id: email_id.map(String::as_str),
+            };
+            serde_json::to_writer(&mut out, &row)?;
+            out.write_all(b"\n")?;
+        }
+        Ok(Summary {
+            format: Some(self.format),
+            encryption: Some(self.encryption),
+            messages_total: self.messages.len(),
+            matched_total,
+            error: None,
+        })
+    }
+}
+
+struct Node {
+    nid: u32,
+    bid_data: u64,
+    bid_sub: u64,
+    parent_nid: u32,
+}
+
+struct Pst {
+    file: File,
+    unicode: bool,
+}
+
+pub fn read(path: &Path) -> Result<Index> {
+    let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut pst = Pst {
+        file,
+        unicode: true,
+    };
+    let header = pst.read_at(0, 564)?;
+    if &header[..4] != MAGIC {
+        bail!("not a PST file (bad header magic)");
+    }
+    let (nbt, bbt, crypt) = match u16::from_le_bytes([header[10], header[11]]) {
+        14 | 15 => {
+            pst.unicode = false;
+            (
+                u32_at(&header, 188) as u64,
+                u32_at(&header, 196) as u64,
+                header[461],
+            )
+        }
+        23 => (u64_at(&header, 224), u64_at(&header, 240), header[513]),
+        36 => bail!("PSTs with 4K pages aren't supported"),
+        ver => bail!("unknown PST version {ver}"),
+    };
+    let encryption = match crypt {
+        0 => "none",
+        1 => "permute",
+        2 => "cyclic",
+        other => bail!("unknown PST encryption method {other}"),
+    };
+
+    let mut nodes = Vec::new();
+    pst.walk(nbt, PTYPE_NBT, 0, &mut |w, entry| {
+        let nid = entry_id(entry, 0, w) as u32;
+        if matches!(
+            nid & 0x1f,
+            NID_TYPE_FOLDER | NID_TYPE_MESSAGE | NID_TYPE_ASSOCIATED
+        ) {
+            nodes.push(Node {
+                nid,
+                bid_data: entry_id(entry, w, w),
+                bid_sub: entry_id(entry, 2 * w, w),
+                parent_nid: u32_at(entry, 3 * w),
+            });
+        }
+    })?;
+    let wanted: HashSet<u64> = nodes
+        .iter()
+        .flat_map(|n| [n.bid_data, n.bid_sub])
+        .filter(|&bid| bid != 0)
+        .map(|bid| bid & !1)
+        .collect();
+    let mut blocks = pst.blocks(bbt, &wanted)?;
+
+    // Data trees (internal blocks) hold their size in the XBLOCK header; the property context
+    // needs the blocks below them, looked up in a second pass.
+    let mut trees = HashMap::new();
+    for node in &nodes {
+        if node.bid_data & 2 != 0 {
+            if let Some(&(ib, cb)) = blocks.get(&(node.bid_data & !1)) {
+                let xblock = pst.read_at(ib, cb as usize)?;
+                trees.insert(node.bid_data & !1, pst.xblock(&xblock)?);
+            }
+        }
+    }
+    if crypt == 0 {
+        let children: HashSet<u64> = trees.values().flat_map(|(_, c)| c.clone()).collect();
+        blocks.extend(pst.blocks(bbt, &children)?);
+    }
+
+    let mut index = Index {
+        format: if pst.unicode { "unicode" } else { "ansi" },
+        encryption,
+        messages: Vec::new(),
+        folders: HashMap::new(),
+    };
+    for node in nodes {
+        let data = blocks.get(&(node.bid_data & !1)).copied();
+        let tree = trees.get(&(node.bid_data & !1));
+        let props = match crypt {
+            0 => {
+                let heap_bids = match tree {
+                    Some((_, children)) => children.clone(),
+                    None => vec![node.bid_data & !1],
+                };
+                pst.properties(&heap_bids, &blocks)
+            }
+            _ => HashMap::new(),
+        };
+        let kind = match node.nid & 0x1f {
+            NID_TYPE_FOLDER => {
+                if let Some(name) = props.get(&PROP_DISPLAY_NAME) {
+                    index.folders.insert(node.nid, name.clone());
+                }
+                continue;
+            }
+            NID_TYPE_MESSAGE => Kind::Message,
+            _ => Kind::Associated,
+        };
+        index.messages.push(Message {
+            nid: node.nid,
+            kind,
+            parent_nid: node.parent_nid,
+            data_offset: data.map(|(ib, _)| ib),
+            data_size: match tree {
+                Some(&(total, _)) => Some(total),
+                None => data.map(|(_, cb)| cb as u64),
+            },
+            subnode_offset: blocks.get(&(node.bid_sub & !1)).map(|&(ib, _)| ib),
+            message_id: props.get(&PROP_MESSAGE_ID).cloned(),
+        });
+    }
+    Ok(index)
+}
+
+impl Pst {
+    fn id_size(&self) -> usize {
+        if self.unicode {
+            8
+        } else {
+            4
+        }
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file
+            .read_exact(&mut buf)
+            .with_context(|| format!("PST truncated: {len} bytes at offset {offset}"))?;
+        Ok(buf)
+    }
+
+    /// Calls `leaf` with every leaf entry of the B-tree page at `ib`.
+    fn walk(
+        &mut self,
+        ib: u64,
+        ptype: u8,
+        depth: u8,
+        leaf: &mut dyn FnMut(usize, &[u8]),
+    ) -> Result<()> {
+        let page = self.read_at(ib, PAGE_SIZE)?;
+        let (meta, trailer) = if self.unicode { (488, 496) } else { (496, 500) };
+        if page[trailer] != ptype {
+            bail!("B-tree page at offset {ib} has type {:#x}", page[trailer]);
+        }
+        let (count, size, level) = (page[meta] as usize, page[meta + 2] as usize, page[meta + 3]);
+        let w = self.id_size();
+        if count * size > meta || size < 3 * w {
+            bail!("B-tree page at offset {ib} has malformed entries");
+        }
+        for entry in page[..count * size].chunks_exact(size) {
+            if level == 0 {
+                leaf(w, entry);
+            } else if depth >= MAX_DEPTH {
+                bail!("B-tree deeper than {MAX_DEPTH} levels at offset {ib}");
+            } else {
+                self.walk(entry_id(entry, 2 * w, w), ptype, depth + 1, leaf)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Offset and size of each block in `wanted` (block IDs without the reserved low bit).
+    fn blocks(&mut self, bbt: u64, wanted: &HashSet<u64>) -> Result<HashMap<u64, (u64, u16)>> {
+        let mut found = HashMap::new();
+        if wanted.is_empty() {
+            return Ok(found);
+        }
+        self.walk(bbt, PTYPE_BBT, 0, &mut |w, entry| {
+            let bid = entry_id(entry, 0, w) & !1;
+            if wanted.contains(&bid) {
+                let cb = u16::from_le_bytes([entry[2 * w], entry[2 * w + 1]]);
+                found.insert(bid, (entry_id(entry, w, w), cb));
+            }
+        })?;
+        Ok(found)
+    }
+
+    /// Total size and data block IDs of an XBLOCK; XXBLOCKs are listed by their XBLOCKs.
+    fn xblock(&self, block: &[u8]) -> Result<(u64, Vec<u64>)> {
+        if block.len() < 8 || block[0] != 0x01 {
+            bail!("malformed data tree block");
+        }
+        let count = u16::from_le_bytes([block[2], block[3]]) as usize;
+        let w = self.id_size();
+        if 8 + count * w > block.len() {
+            bail!("malformed data tree block");
+        }
+        let bids = (0..count)
+            .map(|i| entry_id(block, 8 + i * w, w) & !1)
+            .collect();
+        Ok((u32_at(block, 4) as u64, bids))
+    }
+
+    /// The string properties of a property context whose heap is in `bids`; empty when the
+    /// blocks aren't a well-formed property context.
+    fn properties(
+        &mut self,
+        bids: &[u64],
+        blocks: &HashMap<u64, (u64, u16)>,
+    ) -> HashMap<u16, String> {
+        let mut heap = Vec::new();
+        for bid in bids {
+            match blocks
+                .get(bid)
+                .and_then(|&(ib, cb)| self.read_at(ib, cb as usize).ok())
+            {
+                Some(block) => heap.push(block),
+                None => return HashMap::new(),
+            }
+        }
+        property_context(&heap).unwrap_or_default()
+    }
+}
+
+/// Strings among the properties of a property context heap ([MS-PST] 2.3.1 to 2.3.3).
+fn property_context(heap: &[Vec<u8>]) -> Option<HashMap<u16, String>> {
+    let first = heap.first()?;
+    if first.get(2..4)? != [0xEC, 0xBC] {
+        return None;
+    }
+    let bth = alloc(heap, u32_at(first.get(..8)?, 4))?;
+    if bth.len() < 8 || bth[0] != 0xB5 || bth[1] != 2 || bth[2] != 6 {
+        return None;
+    }
+    let mut records = Vec::new();
+    bth_leaves(heap, u32_at(bth, 4), bth[3], &mut records)?;
+    let mut props = HashMap::new();
+    for record in records.chunks_exact(8) {
+        let id = u16::from_le_bytes([record[0], record[1]]);
+        if id != PROP_MESSAGE_ID && id != PROP_DISPLAY_NAME {
+            continue;
+        }
+        // Values too large for the heap live in subnodes; Message-IDs and folder names don't.
+        let Some(bytes) = alloc(heap, u32_at(record, 4)) else {
+            continue;
+        };
+        let value = match u16::from_le_bytes([record[2], record[3]]) {
+            PT_UNICODE => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            PT_STRING8 => String::from_utf8_lossy(bytes).into_owned(),
+            _ => continue,
+        };
+        props.insert(id, value.trim_end_matches('\0').to_string());
+    }
+    Some(props)
+}
+
+/// Appends the leaf records of the BTH node at `hid` (`levels` above the leaves) to `out`.
+fn bth_leaves(heap: &[Vec<u8>], hid: u32, levels: u8, out: &mut Vec<u8>) -> Option<()> {
+    if hid == 0 {
+        return Some(());
+    }
+    if levels == 0 {
+        out.extend_from_slice(alloc(heap, hid)?);
+        return Some(());
+    }
+    if levels > MAX_DEPTH {
+        return None;
+    }
+    for entry in alloc(heap, hid)?.chunks_exact(6) {
+        bth_leaves(heap, u32_at(entry, 2), levels - 1, out)?;
+    }
+    Some(())
+}
+
+/// The heap allocation a HID points to.
+fn alloc(heap: &[Vec<u8>], hid: u32) -> Option<&[u8]> {
+    if hid & 0x1f != 0 {
+        return None;
+    }
+    let index = ((hid >> 5) & 0x7ff) as usize;
+    let block = heap.get((hid >> 16) as usize)?;
+    let map = u16::from_le_bytes([*block.first()?, *block.get(1)?]) as usize;
+    let offset = |i: usize| {
+        let at = map + 4 + 2 * i;
+        Some(u16::from_le_bytes([*block.get(at)?, *block.get(at + 1)?]) as usize)
+    };
+    let allocations = u16::from_le_bytes([*block.get(map)?, *block.get(map + 1)?]) as usize;
+    if index == 0 || index > allocations {
+        return None;
+    }
+    block.get(offset(index - 1)?..offset(index)?)
+}
+
+fn entry_id(entry: &[u8], at: usize, width: usize) -> u64 {
+    match width {
+        8 => u64_at(entry, at),
+        _ => u32_at(entry, at) as u64,
+    }
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().expect("4 bytes"))
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().expect("8 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A property context heap with string properties.
+    fn heap(props: &[(u16, &str)]) -> Vec<u8> {
+        let hid = |i: usize| (i as u32) << 5;
+        let mut bth = vec![0xB5, 2, 6, 0];
+        bth.extend(hid(2).to_le_bytes());
+        let mut records = Vec::new();
+        for (j, (id, _)) in props.iter().enumerate() {
+            records.extend(id.to_le_bytes());
+            records.extend(PT_UNICODE.to_le_bytes());
+            records.extend(hid(3 + j).to_le_bytes());
+        }
+        let mut allocs = vec![bth, records];
+        for (_, value) in props {
+            allocs.push(value.encode_utf16().flat_map(u16::to_le_bytes).collect());
+        }
+        let mut block = vec![0, 0, 0xEC, 0xBC];
+        block.extend(hid(1).to_le_bytes());
+        block.extend([0; 4]);
+        let mut offsets = Vec::new();
+        for a in &allocs {
+            offsets.push(block.len() as u16);
+            block.extend(a);
+        }
+        offsets.push(block.len() as u16);
+        let map = block.len() as u16;
+        block[..2].copy_from_slice(&map.to_le_bytes());
+        block.extend((allocs.len() as u16).to_le_bytes());
+        block.extend([0, 0]);
+        for o in offsets {
+            block.extend(o.to_le_bytes());
+        }
+        block
+    }
+
+    /// A Unicode PST with one leaf page per B-tree; nodes are (nid, data bid, sub bid, parent).
+    fn unicode_pst(
+        crypt: u8,
+        blocks: &[(u64, Vec<u8>)],
+        nodes: &[(u32, u64, u64, u32)],
+    ) -> Vec<u8> {
+        const BBT: usize = 1024;
+        const NBT: usize = 1536;
+        let mut file = vec![0u8; 2048];
+        file[..4].copy_from_slice(MAGIC);
+        file[10..12].copy_from_slice(&23u16.to_le_bytes());
+        file[224..232].copy_from_slice(&(NBT as u64).to_le_bytes());
+        file[240..248].copy_from_slice(&(BBT as u64).to_le_bytes());
+        file[513] = crypt;
+
+        let mut bbt = vec![0u8; PAGE_SIZE];
+        for (i, (bid, data)) in blocks.iter().enumerate() {
+            let entry = &mut bbt[i * 24..(i + 1) * 24];
+            entry[..8].copy_from_slice(&bid.to_le_bytes());
+            entry[8..16].copy_from_slice(&(file.len() as u64).to_le_bytes());
+            entry[16..18].copy_from_slice(&(data.len() as u16).to_le_bytes());
+            file.extend(data);
+            file.resize(file.len().next_multiple_of(64) + 64, 0);
+        }
+        bbt[488] = blocks.len() as u8;
+        bbt[490] = 24;
+        bbt[496] = PTYPE_BBT;
+
+        let mut nbt = vec![0u8; PAGE_SIZE];
+        for (i, &(nid, data, sub, parent)) in nodes.iter().enumerate() {
+            let entry = &mut nbt[i * 32..(i + 1) * 32];
+            entry[..8].copy_from_slice(&(nid as u64).to_le_bytes());
+            entry[8..16].copy_from_slice(&data.to_le_bytes());
+            entry[16..24].copy_from_slice(&sub.to_le_bytes());
+            entry[24..28].copy_from_slice(&parent.to_le_bytes());
+        }
+        nbt[488] = nodes.len() as u8;
+        nbt[490] = 32;
+        nbt[496] = PTYPE_NBT;
+
+        file[BBT..BBT + PAGE_SIZE].copy_from_slice(&bbt);
+        file[NBT..NBT + PAGE_SIZE].copy_from_slice(&nbt);
+        file
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Result<Index> {
+        let path = std::env::temp_dir().join(format!("pstindex-{}.pst", uuid::Uuid::new_v4()));
+        std::fs::write(&path, bytes).unwrap();
+        let index = read(&path);
+        std::fs::remove_file(&path).unwrap();
+        index
+    }
+
+    #[test]
+    fn indexes_message_nodes_with_offsets_and_message_ids() {
+        let second = heap(&[(PROP_MESSAGE_ID, "<b@example.com>")]);
+        let mut xblock = vec![0x01, 1, 1, 0];
+        xblock.extend((second.len() as u32).to_le_bytes());
+        xblock.extend(0x0Cu64.to_le_bytes());
+        let blocks = [
+            (0x04, heap(&[(PROP_DISPLAY_NAME, "Inbox")])),
+            (0x08, heap(&[(PROP_MESSAGE_ID, "<A@Example.COM>")])),
+            (0x0A, xblock),
+            (0x0C, second.clone()),
+            (0x10, vec![0xAA; 40]),
+        ];
+        let nodes = [
+            (0x8022, 0x04, 0, 0x122),
+            (0x802D, 0x10, 0, 0x8022),
+            (0x200004, 0x08, 0x10, 0x8022),
+            (0x200024, 0x0A, 0, 0x8022),
+            (0x200048, 0x20, 0, 0x8022),
+        ];
+        let pst = unicode_pst(0, &blocks, &nodes);
+        let index = read_bytes(&pst).unwrap();
+        assert_eq!((index.format, index.encryption), ("unicode", "none"));
+        assert_eq!(index.folders[&0x8022], "Inbox");
+        assert_eq!(index.messages.len(), 3);
+
+        let first = &index.messages[0];
+        assert_eq!(
+            (first.nid, first.kind, first.parent_nid),
+            (0x200004, Kind::Message, 0x8022)
+        );
+        assert_eq!(first.message_id.as_deref(), Some("<A@Example.COM>"));
+        let ib = first.data_offset.unwrap() as usize;
+        assert_eq!(
+            pst[ib..ib + 4],
+            [blocks[1].1[0], blocks[1].1[1], 0xEC, 0xBC]
+        );
+        assert_eq!(first.data_size, Some(blocks[1].1.len() as u64));
+        let sub = first.subnode_offset.unwrap() as usize;
+        assert_eq!(pst[sub..sub + 40], [0xAA; 40]);
+
+        let tree = &index.messages[1];
+        assert_eq!(pst[tree.data_offset.unwrap() as usize], 0x01);
+        assert_eq!(tree.data_size, Some(second.len() as u64));
+        assert_eq!(tree.message_id.as_deref(), Some("<b@example.com>"));
+        let missing = &index.messages[2];
+        assert_eq!(missing.kind, Kind::Associated);
+        assert_eq!((missing.data_offset, missing.data_size), (None, None));
+
+        let emails = HashMap::from([("A@example.com".to_string(), "e1".to_string())]);
+        assert_eq!(index.message_ids().len(), 2);
+        let mut out = Vec::new();
+        let summary = index.write(&emails, &mut out).unwrap();
+        assert_eq!((summary.messages_total, summary.matched_total), (3, 1));
+        let row: serde_json::Value =
+            serde_json::from_slice(out.split(|&b| b == b'\n').next().unwrap()).unwrap();
+        assert_eq!(row["nid"], 0x200004);
+        assert_eq!(row["folder"], "Inbox");
+        assert_eq!(row["email_id"], "e1");
+        assert_eq!(row["data_offset"], ib);
+
+        // Compressible encryption leaves the locations but hides the properties.
+        let index = read_bytes(&unicode_pst(1, &blocks, &nodes)).unwrap();
+        assert_eq!(index.encryption, "permute");
+        assert!(index.folders.is_empty());
+        assert_eq!(index.messages[0].message_id, None);
+        assert_eq!(index.messages[0].data_offset, Some(ib as u64));
+    }
+
+    #[test]
+    fn rejects_files_it_cannot_index() {
+        let pst = unicode_pst(0, &[], &[]);
+        assert!(read_bytes(&pst).unwrap().messages.is_empty());
+
+        let mut bad = pst.clone();
+        bad[..4].copy_from_slice(b"MZ\0\0");
+        let err = read_bytes(&bad).unwrap_err().to_string();
+        assert!(err.contains("not a PST"), "{err}");
+
+        let mut four_k = pst.clone();
+        four_k[10] = 36;
+        let err = read_bytes(&four_k).unwrap_err().to_string();
+        assert!(err.contains("4K pages"), "{err}");
+
+        let err = read_bytes(&pst[..1200]).unwrap_err().to_string();
+        assert!(err.contains("truncated"), "{err}");
+
+        let mut wrong_page = pst.clone();
+        wrong_page[1536 + 496] = PTYPE_BBT;
+        let err = read_bytes(&wrong_page).unwrap_err().to_string();
+        assert!(err.contains("has type 0x80"), "{err}");
+    }
+}
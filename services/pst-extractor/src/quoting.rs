@@ -0,0 +1,183 @@
+//! `body_text_clean`: the author's own words, without the quoted conversation or signature.
+//!
+//! Near-duplicate detection and previews need the new text of a reply, not the thread it
+//! carries. The body is cut at the first reply or forward header (`-----Original Message-----`,
+//! an Outlook `From:` / `Sent:` block, `On ... wrote:` and its German and French forms,
+//! `Begin forwarded message:`), `>`-quoted lines are dropped, and a trailing signature goes too:
+//! everything after an RFC 3676 `-- ` delimiter, mobile footers (`Sent from my iPhone`), and a
+//! short sign-off block (`Kind regards,` plus a few short lines) at the very end.
+//!
+//! Like the banner stripping, this errs on the side of keeping text: a body that would come out
+//! empty is kept whole.
+
+/// Lines after a valediction that still count as its signature block.
+const MAX_SIGNATURE_LINES: usize = 6;
+/// Longer lines aren't taken for a name, title or phone number.
+const MAX_SIGNATURE_LINE_LEN: usize = 60;
+
+const SEPARATORS: &[&str] = &[
+    "-----original message-----",
+    "-----ursprüngliche nachricht-----",
+    "-----message d'origine-----",
+    "-----mensaje original-----",
+    "---------- forwarded message ---------",
+    "begin forwarded message:",
+];
+
+const VALEDICTIONS: &[&str] = &[
+    "regards",
+    "kind regards",
+    "best regards",
+    "warm regards",
+    "many thanks",
+    "thanks",
+    "thank you",
+    "best",
+    "cheers",
+    "sincerely",
+    "yours sincerely",
+    "yours faithfully",
+    "mit freundlichen grüßen",
+    "cordialement",
+];
+
+const MOBILE_FOOTERS: &[&str] = &["sent from my ", "get outlook for ", "sent from mail for "];
+
+/// `text` without quoted replies and signature; the whole text if nothing would be left.
+pub fn clean_body(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let end = quote_start(&lines).unwrap_or(lines.len());
+    let mut kept: Vec<&str> = lines[..end]
+        .iter()
+        .copied()
+        .filter(|line| !line.trim_start().starts_with('>'))
+        .collect();
+    if let Some(at) = kept.iter().position(|line| line.trim_end() == "--") {
+        kept.truncate(at);
+    }
+    trim_trailing_blank(&mut kept);
+    if kept.last().is_some_and(|line| {
+        let lower = line.trim().to_lowercase();
+        MOBILE_FOOTERS.iter().any(|f| lower.starts_with(f))
+    }) {
+        kept.pop();
+        trim_trailing_blank(&mut kept);
+    }
+    strip_sign_off(&mut kept);
+    trim_trailing_blank(&mut kept);
+    if kept.iter().all(|line| line.trim().is_empty()) {
+        return text.trim_end().to_string();
+    }
+    kept.join("\n")
+}
+
+/// Index of the first line of the quoted conversation.
+fn quote_start(lines: &[&str]) -> Option<usize> {
+    for (i, line) in lines.iter().enumerate() {
+        let lower = line.trim().to_lowercase();
+        if SEPARATORS.iter().any(|s| lower.starts_with(s)) {
+            return Some(i);
+        }
+        // Outlook puts a line of underscores above the From: block.
+        let rule = lower.len() >= 20 && lower.chars().all(|c| c == '_');
+        if rule && is_header_block(&lines[i + 1..]) {
+            return Some(i);
+        }
+        if is_header_block(&lines[i..]) {
+            return Some(i);
+        }
+        // "On Mon, 3 Mar 2024, Alice <a@example.com> wrote:", sometimes wrapped in two.
+        let attribution = |lower: &str| {
+            (lower.starts_with("on ") && lower.ends_with("wrote:"))
+                || (lower.starts_with("am ") && lower.ends_with("schrieb:"))
+                || (lower.starts_with("le ") && lower.ends_with("a écrit :"))
+                || (lower.starts_with("le ") && lower.ends_with("a écrit:"))
+        };
+        if attribution(&lower) {
+            return Some(i);
+        }
+        if let Some(next) = lines.get(i + 1) {
+            let joined = format!("{lower} {}", next.trim().to_lowercase());
+            if lower.starts_with("on ") && attribution(&joined) {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// `From:` followed within four lines by `Sent:` or `Date:`, as in Outlook's reply header.
+fn is_header_block(lines: &[&str]) -> bool {
+    let field = |line: &str, names: &[&str]| {
+        let lower = line.trim().to_lowercase();
+        names.iter().any(|n| lower.starts_with(n))
+    };
+    match lines.split_first() {
+        Some((first, rest)) if field(first, &["from:", "von:", "de :", "de:"]) => {
+            rest.iter().take(4).any(|line| {
+                field(
+                    line,
+                    &["sent:", "date:", "gesendet:", "envoyé :", "envoyé:"],
+                )
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Drop a closing `Kind regards,` and the short lines after it.
+fn strip_sign_off(lines: &mut Vec<&str>) {
+    let start = lines.len().saturating_sub(MAX_SIGNATURE_LINES + 1);
+    let found = (start..lines.len()).find(|&i| {
+        let lower = lines[i]
+            .trim()
+            .trim_end_matches([',', '.', '!'])
+            .to_lowercase();
+        VALEDICTIONS.contains(&lower.as_str())
+            && lines[i + 1..]
+                .iter()
+                .all(|line| line.trim().chars().count() <= MAX_SIGNATURE_LINE_LEN)
+    });
+    if let Some(at) = found {
+        lines.truncate(at);
+    }
+}
+
+fn trim_trailing_blank(lines: &mut Vec<&str>) {
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_quotes_and_signatures() {
+        let reply = "Approved, go ahead.\r\n\r\nKind regards,\r\nAlice Smith\r\nCommercial Manager\
+                     \r\n+44 20 7946 0000\r\n\r\n________________________________\r\nFrom: Bob\r\n\
+                     Sent: 3 March 2024 10:00\r\nTo: Alice\r\nSubject: Variation 12\r\n\r\n\
+                     Please approve variation 12.\r\n";
+        assert_eq!(clean_body(reply), "Approved, go ahead.");
+
+        let gmail = "Fine by me.\n\nOn Mon, 4 Mar 2024 at 09:00, Bob <bob@example.com>\nwrote:\n\
+                     > Shall we meet?\n";
+        assert_eq!(clean_body(gmail), "Fine by me.");
+
+        let inline = "See inline.\n> first point\nAgreed.\n> second point\nNo.\n-- \nAlice\n";
+        assert_eq!(clean_body(inline), "See inline.\nAgreed.\nNo.");
+
+        let mobile = "On my way.\n\nSent from my iPhone";
+        assert_eq!(clean_body(mobile), "On my way.");
+
+        let forward = "FYI\n\n-----Original Message-----\nFrom: x\n";
+        assert_eq!(clean_body(forward), "FYI");
+
+        // A long closing paragraph isn't a signature, and quoting alone keeps the body.
+        let closing = "Thanks\nfor the detailed breakdown of the costs, which we will review with \
+                       the client on Friday.";
+        assert_eq!(clean_body(closing), closing);
+        assert_eq!(clean_body("> only a quote\n"), "> only a quote");
+    }
+}
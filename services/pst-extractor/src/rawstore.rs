@@ -0,0 +1,125 @@
+//! Raw message storage as size-capped blob files.
+//!
+//! Storing millions of individual .eml objects is expensive in S3 request and object counts.
+//! Instead, raw RFC822 bytes are concatenated into blob files capped at a configurable size and
+//! an index records (email_id -> blob, offset, length) so the review UI can still fetch any
+//! single message with a ranged GET.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Index entry locating one raw message inside a blob.
+#[derive(Serialize)]
+pub struct RawIndexEntry {
+    pub email_id: String,
+    pub blob_key: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// A finished blob file ready for upload.
+pub struct RawBlob {
+    pub key: String,
+    pub path: PathBuf,
+}
+
+pub struct RawBlobWriter {
+    dir: PathBuf,
+    key_prefix: String,
+    max_bytes: u64,
+    current: Option<(File, RawBlob, u64)>,
+    next_idx: usize,
+    finished: Vec<RawBlob>,
+}
+
+impl RawBlobWriter {
+    /// `key_prefix` is the output prefix blobs are uploaded under (e.g. "pst-v2/abc/").
+    pub fn new(dir: &Path, key_prefix: &str, max_bytes: u64) -> Result<Self> {
+        std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            key_prefix: key_prefix.to_string(),
+            max_bytes: max_bytes.max(1),
+            current: None,
+            next_idx: 1,
+            finished: Vec::new(),
+        })
+    }
+
+    /// Append one raw message, rolling to a new blob when the cap would be exceeded. A message
+    /// larger than the cap gets a blob to itself rather than being split.
+    pub fn append(&mut self, email_id: &str, bytes: &[u8]) -> Result<RawIndexEntry> {
+        let len = bytes.len() as u64;
+        let needs_roll = match &self.current {
+            Some((_, _, written)) => *written > 0 && written + len > self.max_bytes,
+            None => true,
+        };
+        if needs_roll {
+            self.roll()?;
+        }
+        let (file, blob, written) = self.current.as_mut().expect("blob opened by roll");
+        file.write_all(bytes)
+            .with_context(|| format!("write {}", blob.path.display()))?;
+        let entry = RawIndexEntry {
+            email_id: email_id.to_string(),
+            blob_key: blob.key.clone(),
+            offset: *written,
+            length: len,
+        };
+        *written += len;
+        Ok(entry)
+    }
+
+    fn roll(&mut self) -> Result<()> {
+        self.close_current()?;
+        let name = format!("blob-{:05}.bin", self.next_idx);
+        self.next_idx += 1;
+        let path = self.dir.join(&name);
+        let file = File::create(&path).with_context(|| format!("create {}", path.display()))?;
+        let key = format!("{}raw/{}", self.key_prefix, name);
+        self.current = Some((file, RawBlob { key, path }, 0));
+        Ok(())
+    }
+
+    fn close_current(&mut self) -> Result<()> {
+        if let Some((mut file, blob, _)) = self.current.take() {
+            file.flush()?;
+            self.finished.push(blob);
+        }
+        Ok(())
+    }
+
+    /// Flush the open blob and return every blob written, in order.
+    pub fn finish(mut self) -> Result<Vec<RawBlob>> {
+        self.close_current()?;
+        Ok(self.finished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_blobs_at_cap_and_tracks_offsets() {
+        let dir = std::env::temp_dir().join(format!("rawstore-{}", uuid::Uuid::new_v4()));
+        let mut w = RawBlobWriter::new(&dir, "p/", 10).expect("writer");
+        let a = w.append("a", b"123456").expect("a");
+        let b = w.append("b", b"7890").expect("b");
+        let c = w.append("c", b"abc").expect("c");
+        let blobs = w.finish().expect("finish");
+
+        assert_eq!(
+            (a.blob_key.as_str(), a.offset, a.length),
+            ("p/raw/blob-00001.bin", 0, 6)
+        );
+        assert_eq!((b.blob_key.as_str(), b.offset), ("p/raw/blob-00001.bin", 6));
+        assert_eq!((c.blob_key.as_str(), c.offset), ("p/raw/blob-00002.bin", 0));
+        assert_eq!(blobs.len(), 2);
+        assert_eq!(std::fs::read(&blobs[0].path).expect("read"), b"1234567890");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,412 @@
+//! RTF bodies: compressed RTF (MS-OXRTFCP "LZFu") decompression and RTF → text/HTML conversion.
+//!
+//! Many PST messages carry their body only as PR_RTF_COMPRESSED. Outlook's HTML mail is stored as
+//! RTF with the original HTML encapsulated (`\fromhtml1`, MS-OXRTFEX), which is recovered as-is;
+//! other RTF is flattened to plain text.
+
+/// Dictionary preload defined by MS-OXRTFCP.
+const PREBUF: &[u8] = b"{\\rtf1\\ansi\\mac\\deff0\\deftab720{\\fonttbl;}{\\f0\\fnil \\froman \\fswiss \\fmodern \\fscript \\fdecor MS Sans SerifSymbolArialTimes New RomanCourier{\\colortbl\\red0\\green0\\blue0\r\n\\par \\pard\\plain\\f0\\fs20\\b\\i\\u\\tab\\tx";
+
+const COMPRESSED: u32 = 0x7546_5A4C; // "LZFu"
+const UNCOMPRESSED: u32 = 0x414C_454D; // "MELA"
+
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Decompress a PR_RTF_COMPRESSED stream into raw RTF bytes. Returns None if the header is not
+/// a recognized compressed-RTF header.
+pub fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let comp_size = le_u32(data, 0)? as usize;
+    let raw_size = le_u32(data, 4)? as usize;
+    let comp_type = le_u32(data, 8)?;
+    // comp_size counts everything after the size field itself.
+    let end = (comp_size + 4).min(data.len());
+    let body = data.get(16..end)?;
+
+    match comp_type {
+        UNCOMPRESSED => Some(body.iter().copied().take(raw_size).collect()),
+        COMPRESSED => Some(lzfu(body, raw_size)),
+        _ => None,
+    }
+}
+
+fn lzfu(body: &[u8], raw_size: usize) -> Vec<u8> {
+    let mut dict = [0u8; 4096];
+    dict[..PREBUF.len()].copy_from_slice(PREBUF);
+    let mut write_pos = PREBUF.len();
+    let mut out = Vec::with_capacity(raw_size);
+    let mut i = 0usize;
+
+    while i < body.len() {
+        let control = body[i];
+        i += 1;
+        for bit in 0..8 {
+            if i >= body.len() {
+                return out;
+            }
+            if control & (1 << bit) == 0 {
+                let b = body[i];
+                i += 1;
+                out.push(b);
+                dict[write_pos] = b;
+                write_pos = (write_pos + 1) % 4096;
+                continue;
+            }
+            if i + 1 >= body.len() {
+                return out;
+            }
+            let word = u16::from_be_bytes([body[i], body[i + 1]]);
+            i += 2;
+            let offset = (word >> 4) as usize;
+            let length = (word & 0x0F) as usize + 2;
+            if offset == write_pos {
+                return out;
+            }
+            for k in 0..length {
+                let b = dict[(offset + k) % 4096];
+                out.push(b);
+                dict[write_pos] = b;
+                write_pos = (write_pos + 1) % 4096;
+            }
+        }
+    }
+    out
+}
+
+/// Destinations whose content is never body text.
+const SKIP_DESTINATIONS: &[&str] = &[
+    "fonttbl",
+    "colortbl",
+    "stylesheet",
+    "info",
+    "pict",
+    "object",
+    "header",
+    "headerl",
+    "headerr",
+    "footer",
+    "footerl",
+    "footerr",
+    "fldinst",
+    "listtable",
+    "listoverridetable",
+    "rsidtbl",
+    "generator",
+    "themedata",
+    "colorschememapping",
+    "latentstyles",
+    "datastore",
+    "xmlnstbl",
+    "pgdsctbl",
+];
+
+/// Windows-1252 characters for bytes 0x80..=0x9F; the rest of the code page matches Latin-1.
+const CP1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+fn cp1252(b: u8) -> char {
+    match b {
+        0x80..=0x9F => CP1252_HIGH[(b - 0x80) as usize],
+        _ => b as char,
+    }
+}
+
+/// Bodies recovered from an RTF document.
+#[derive(Debug, Default)]
+pub struct RtfBodies {
+    pub text: Option<String>,
+    /// Present only when the RTF encapsulates an HTML original.
+    pub html: Option<String>,
+}
+
+pub fn is_rtf(data: &[u8]) -> bool {
+    data.starts_with(b"{\\rtf")
+}
+
+/// Convert an RTF document (raw, or compressed with an LZFu/MELA header) into bodies.
+pub fn bodies(data: &[u8]) -> Option<RtfBodies> {
+    let decompressed;
+    let rtf = if is_rtf(data) {
+        data
+    } else {
+        decompressed = decompress(data)?;
+        if !is_rtf(&decompressed) {
+            return None;
+        }
+        &decompressed
+    };
+
+    let html = if contains(rtf, b"\\fromhtml") {
+        tidy(&Converter::new(true).run(rtf))
+    } else {
+        None
+    };
+    Some(RtfBodies {
+        text: tidy(&Converter::new(false).run(rtf)),
+        html,
+    })
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Trim trailing whitespace on each line and collapse runs of blank lines.
+fn tidy(text: &str) -> Option<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut blank_run = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_run += 1;
+            if blank_run > 1 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    let out = out.trim().to_string();
+    (!out.is_empty()).then_some(out)
+}
+
+#[derive(Clone, Copy)]
+struct GroupState {
+    /// Inside a destination that produces no output.
+    skip: bool,
+    /// Inside `\htmlrtf` (RTF-only rendering of encapsulated HTML).
+    htmlrtf: bool,
+    /// Fallback characters following each `\u` (`\ucN`).
+    uc: usize,
+}
+
+struct Converter {
+    html: bool,
+    out: String,
+    state: GroupState,
+    stack: Vec<GroupState>,
+    /// Fallback characters still to drop after a `\u` escape.
+    pending_fallback: usize,
+}
+
+impl Converter {
+    fn new(html: bool) -> Self {
+        Self {
+            html,
+            out: String::new(),
+            state: GroupState {
+                skip: false,
+                htmlrtf: false,
+                uc: 1,
+            },
+            stack: Vec::new(),
+            pending_fallback: 0,
+        }
+    }
+
+    fn emit(&mut self, ch: char) {
+        if self.pending_fallback > 0 {
+            self.pending_fallback -= 1;
+            return;
+        }
+        if self.state.skip || (self.html && self.state.htmlrtf) {
+            return;
+        }
+        self.out.push(ch);
+    }
+
+    fn run(mut self, rtf: &[u8]) -> String {
+        let mut i = 0usize;
+        // Set right after '{' (and kept across `\*`) so the next control word can open a
+        // destination.
+        let mut group_start = false;
+        let mut ignorable = false;
+
+        while i < rtf.len() {
+            match rtf[i] {
+                b'{' => {
+                    self.stack.push(self.state);
+                    group_start = true;
+                    ignorable = false;
+                    i += 1;
+                }
+                b'}' => {
+                    if let Some(state) = self.stack.pop() {
+                        self.state = state;
+                    }
+                    group_start = false;
+                    i += 1;
+                }
+                b'\r' | b'\n' => i += 1,
+                b'\\' => {
+                    let Some(&next) = rtf.get(i + 1) else {
+                        break;
+                    };
+                    if next.is_ascii_alphabetic() {
+                        let word_start = i + 1;
+                        let mut j = word_start;
+                        while j < rtf.len() && rtf[j].is_ascii_alphabetic() {
+                            j += 1;
+                        }
+                        let word = String::from_utf8_lossy(&rtf[word_start..j]).into_owned();
+                        let param_start = j;
+                        if j < rtf.len() && rtf[j] == b'-' {
+                            j += 1;
+                        }
+                        while j < rtf.len() && rtf[j].is_ascii_digit() {
+                            j += 1;
+                        }
+                        let param: Option<i64> = std::str::from_utf8(&rtf[param_start..j])
+                            .ok()
+                            .and_then(|p| p.parse().ok());
+                        if j < rtf.len() && rtf[j] == b' ' {
+                            j += 1;
+                        }
+                        i = j;
+                        let destination = std::mem::take(&mut group_start);
+                        let was_ignorable = std::mem::take(&mut ignorable);
+                        if word == "bin" {
+                            i += param.unwrap_or(0).max(0) as usize;
+                            continue;
+                        }
+                        self.control_word(&word, param, destination, was_ignorable);
+                    } else {
+                        i += 2;
+                        match next {
+                            b'*' => {
+                                ignorable = true;
+                                // Keep group_start so the following word is treated as the
+                                // destination name.
+                                continue;
+                            }
+                            b'\'' => {
+                                let hex =
+                                    rtf.get(i..i + 2).and_then(|h| std::str::from_utf8(h).ok());
+                                if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok())
+                                {
+                                    self.emit(cp1252(byte));
+                                }
+                                i += 2;
+                            }
+                            b'\\' | b'{' | b'}' => self.emit(next as char),
+                            b'~' => self.emit('\u{a0}'),
+                            b'_' => self.emit('-'),
+                            b'\r' | b'\n' => self.emit('\n'),
+                            _ => {}
+                        }
+                        group_start = false;
+                    }
+                }
+                b => {
+                    group_start = false;
+                    self.emit(cp1252(b));
+                    i += 1;
+                }
+            }
+        }
+        self.out
+    }
+
+    fn control_word(&mut self, word: &str, param: Option<i64>, destination: bool, ignorable: bool) {
+        if destination {
+            if self.html && (word == "htmltag" || word == "mhtmltag") {
+                // Encapsulated HTML markup; always part of the HTML output.
+                self.state.htmlrtf = false;
+                return;
+            }
+            if ignorable || SKIP_DESTINATIONS.contains(&word) {
+                self.state.skip = true;
+                return;
+            }
+        }
+        match word {
+            "par" | "line" | "sect" | "page" | "row" => self.emit('\n'),
+            "tab" | "cell" => self.emit('\t'),
+            "emdash" => self.emit('—'),
+            "endash" => self.emit('–'),
+            "bullet" => self.emit('•'),
+            "lquote" => self.emit('‘'),
+            "rquote" => self.emit('’'),
+            "ldblquote" => self.emit('“'),
+            "rdblquote" => self.emit('”'),
+            "emspace" | "enspace" | "qmspace" => self.emit(' '),
+            "uc" => self.state.uc = param.unwrap_or(1).max(0) as usize,
+            "u" => {
+                if let Some(code) = param {
+                    // Negative values are UTF-16 units written as signed 16-bit numbers.
+                    let unit = (code as i16) as u16;
+                    self.emit(char::from_u32(unit as u32).unwrap_or('\u{FFFD}'));
+                    self.pending_fallback = self.state.uc;
+                }
+            }
+            "htmlrtf" => self.state.htmlrtf = param != Some(0),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prebuf_matches_spec_length() {
+        assert_eq!(PREBUF.len(), 207);
+    }
+
+    #[test]
+    fn decompresses_spec_example() {
+        // MS-OXRTFCP section 4.1 example.
+        let data: [u8; 49] = [
+            0x2d, 0x00, 0x00, 0x00, 0x2b, 0x00, 0x00, 0x00, 0x4c, 0x5a, 0x46, 0x75, 0xf1, 0xc5,
+            0xc7, 0xa7, 0x03, 0x00, 0x0a, 0x00, 0x72, 0x63, 0x70, 0x67, 0x31, 0x32, 0x35, 0x42,
+            0x32, 0x0a, 0xf3, 0x20, 0x68, 0x65, 0x6c, 0x09, 0x00, 0x20, 0x62, 0x77, 0x05, 0xb0,
+            0x6c, 0x64, 0x7d, 0x0a, 0x80, 0x0f, 0xa0,
+        ];
+        let rtf = decompress(&data).expect("decompress");
+        assert_eq!(
+            String::from_utf8_lossy(&rtf),
+            "{\\rtf1\\ansi\\ansicpg1252\\pard hello world}\r\n"
+        );
+    }
+
+    #[test]
+    fn passes_through_uncompressed_rtf() {
+        let rtf = b"{\\rtf1 hi}";
+        let mut data = Vec::new();
+        data.extend_from_slice(&((rtf.len() + 12) as u32).to_le_bytes());
+        data.extend_from_slice(&(rtf.len() as u32).to_le_bytes());
+        data.extend_from_slice(&UNCOMPRESSED.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(rtf);
+        assert_eq!(decompress(&data).expect("decompress"), rtf);
+    }
+
+    #[test]
+    fn converts_plain_rtf_to_text() {
+        let rtf = br"{\rtf1\ansi\ansicpg1252{\fonttbl{\f0\fswiss Arial;}}{\*\generator Riched20;}\pard\f0\fs20 Dear team,\par Caf\'e9 meeting \u8364?5 moved\par\par\par See {\b attached}.\par}";
+        let bodies = bodies(rtf).expect("rtf");
+        assert_eq!(
+            bodies.text.as_deref(),
+            Some("Dear team,\nCaf\u{e9} meeting \u{20ac}5 moved\n\nSee attached.")
+        );
+        assert!(bodies.html.is_none());
+    }
+
+    #[test]
+    fn recovers_encapsulated_html() {
+        let rtf = br"{\rtf1\ansi\fbidis\ansicpg1252\deff0\fromhtml1{\fonttbl{\f0\fswiss Arial;}}{\*\htmltag19 <html>}{\*\htmltag34 <body>}\htmlrtf {\htmlrtf0 Hello {\*\htmltag84 <b>}\htmlrtf {\b \htmlrtf0 world{\*\htmltag92 </b>}\htmlrtf }\htmlrtf0 \htmlrtf }\htmlrtf0 {\*\htmltag42 </body>}{\*\htmltag27 </html>}}";
+        let bodies = bodies(rtf).expect("rtf");
+        assert_eq!(
+            bodies.html.as_deref(),
+            Some("<html><body>Hello <b>world</b></body></html>")
+        );
+        assert_eq!(bodies.text.as_deref(), Some("Hello world"));
+    }
+}
@@ -0,0 +1,228 @@
+//! Banner, disclaimer and tagging rules (`--rules`).
+//!
+//! Firms keep adding banner wordings and disclaimers, and review teams keep adding tags, so these
+//! rules live in a JSON file rather than in the binary:
+//!
+//! ```json
+//! {"version": "2024-06-03",
+//!  "banner_patterns": ["^\\[EXTERNAL\\]"],
+//!  "disclaimer_patterns": ["This e-?mail and any attachments are confidential[\\s\\S]*"],
+//!  "tags": {"privileged": "\"without prejudice\" OR privileged"}}
+//! ```
+//!
+//! Banner patterns are matched line by line and matching lines are dropped from `body_text`.
+//! Disclaimer patterns are matched against the whole body and the matched text is cut. Both are
+//! case-insensitive. Tags are search expressions (as in `--terms-file`) over subject and body;
+//! the names of the matching tags become the email's `rule_tags`.
+//!
+//! A worker polls the file (`--rules-poll-secs`) and applies a changed file to the jobs it starts
+//! afterwards. Each manifest records the `rules_version` it ran with: the file's `version`, or
+//! the start of its SHA-256 when it has none.
+
+use crate::terms::{TermMatcher, WordTokenizer};
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize)]
+struct RulesFile {
+    version: Option<String>,
+    #[serde(default)]
+    banner_patterns: Vec<String>,
+    #[serde(default)]
+    disclaimer_patterns: Vec<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+
+pub struct RuleSet {
+    pub version: String,
+    banners: Vec<Regex>,
+    disclaimers: Vec<Regex>,
+    /// Tag expressions, matched together; `tag_names` maps each expression back to its tags.
+    tags: Option<TermMatcher>,
+    tag_names: BTreeMap<String, Vec<String>>,
+}
+
+fn compile(patterns: &[String], multi_line: bool) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .multi_line(multi_line)
+                .build()
+                .with_context(|| format!("rules pattern {pattern:?}"))
+        })
+        .collect()
+}
+
+fn digest(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl RuleSet {
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: RulesFile = serde_json::from_str(text).context("parse rules file")?;
+        let mut tag_names: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, expression) in &file.tags {
+            let expression = expression.trim().to_string();
+            tag_names.entry(expression).or_default().push(name.clone());
+        }
+        let tags = if tag_names.is_empty() {
+            None
+        } else {
+            let lines: Vec<&str> = tag_names.keys().map(String::as_str).collect();
+            Some(TermMatcher::new(
+                &lines.join("\n"),
+                Box::new(WordTokenizer),
+            )?)
+        };
+        Ok(Self {
+            version: file
+                .version
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or_else(|| digest(text)[..12].to_string()),
+            banners: compile(&file.banner_patterns, false)?,
+            disclaimers: compile(&file.disclaimer_patterns, true)?,
+            tags,
+            tag_names,
+        })
+    }
+
+    /// `text` without the lines matching a banner pattern; None when nothing matched.
+    pub fn strip_banners(&self, text: &str) -> Option<String> {
+        if self.banners.is_empty() {
+            return None;
+        }
+        let mut stripped = false;
+        let kept: Vec<&str> = text
+            .lines()
+            .filter(|line| {
+                let banner = self.banners.iter().any(|re| re.is_match(line.trim()));
+                stripped |= banner;
+                !banner
+            })
+            .collect();
+        stripped.then(|| kept.join("\n"))
+    }
+
+    /// `text` with every disclaimer match cut out; None when nothing matched.
+    pub fn strip_disclaimers(&self, text: &str) -> Option<String> {
+        let mut out: Option<String> = None;
+        for re in &self.disclaimers {
+            let current = out.as_deref().unwrap_or(text);
+            if re.is_match(current) {
+                out = Some(re.replace_all(current, "").trim_end().to_string());
+            }
+        }
+        out
+    }
+
+    /// Names of the tags whose expression matches `texts`, in name order.
+    pub fn tags(&self, texts: &[&str]) -> Vec<String> {
+        let Some(matcher) = &self.tags else {
+            return Vec::new();
+        };
+        let mut tags: Vec<String> = matcher
+            .hits(texts)
+            .keys()
+            .flat_map(|expression| self.tag_names[expression].iter().cloned())
+            .collect();
+        tags.sort();
+        tags
+    }
+}
+
+/// The rules file as last read by a long-running worker.
+pub struct RulesSource {
+    pub location: String,
+    poll: Duration,
+    checked: Option<Instant>,
+    digest: String,
+    current: Option<Arc<RuleSet>>,
+}
+
+impl RulesSource {
+    pub fn new(location: &str, poll: Duration) -> Self {
+        Self {
+            location: location.to_string(),
+            poll,
+            checked: None,
+            digest: String::new(),
+            current: None,
+        }
+    }
+
+    /// Whether the file should be read again.
+    pub fn due(&self, now: Instant) -> bool {
+        self.checked
+            .is_none_or(|checked| now.duration_since(checked) >= self.poll)
+    }
+
+    /// Take freshly read file contents. Returns whether the active rules changed; on a parse
+    /// error the previous rules stay active.
+    pub fn update(&mut self, text: &str, now: Instant) -> Result<bool> {
+        self.checked = Some(now);
+        let digest = digest(text);
+        if digest == self.digest {
+            return Ok(false);
+        }
+        let rules = RuleSet::parse(text)?;
+        self.digest = digest;
+        self.current = Some(Arc::new(rules));
+        Ok(true)
+    }
+
+    pub fn current(&self) -> Option<Arc<RuleSet>> {
+        self.current.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_rules_and_reloads_changed_files() {
+        let text = r#"{"version": "v1",
+            "banner_patterns": ["^\\[external\\]"],
+            "disclaimer_patterns": ["^this e-?mail is confidential[\\s\\S]*"],
+            "tags": {"privileged": "\"without prejudice\" OR privileged",
+                     "delay": "delay AND NOT weather"}}"#;
+        let rules = RuleSet::parse(text).expect("rules");
+        assert_eq!(rules.version, "v1");
+        let body = "[EXTERNAL] Be careful\nSee the delay notice.\n\nThis email is confidential.\nIf received in error, delete it.";
+        let body = rules.strip_banners(body).expect("banner");
+        assert_eq!(
+            rules.strip_disclaimers(&body).as_deref(),
+            Some("See the delay notice.")
+        );
+        assert!(rules.strip_banners("Nothing to strip").is_none());
+        assert_eq!(
+            rules.tags(&["Without prejudice", "delay notice"]),
+            ["delay", "privileged"]
+        );
+
+        let start = Instant::now();
+        let mut source = RulesSource::new("s3://rules/rules.json", Duration::from_secs(60));
+        assert!(source.due(start));
+        assert!(source.update(text, start).expect("load"));
+        assert!(!source.due(start + Duration::from_secs(30)));
+        assert!(source.due(start + Duration::from_secs(60)));
+        assert!(!source.update(text, start).expect("unchanged"));
+        assert!(source
+            .update(r#"{"version": "bad", "banner_patterns": ["("]}"#, start)
+            .is_err());
+        assert_eq!(source.current().expect("kept").version, "v1");
+        assert!(source.update(r#"{"tags": {}}"#, start).expect("v2"));
+        assert_eq!(source.current().expect("v2").version.len(), 12);
+    }
+}
@@ -0,0 +1,207 @@
+//! `--sample` / `--sample-percent`: a quick preview of a mailbox instead of the full extraction.
+//!
+//! readpst still unpacks the whole PST; sampling limits the parse pass (and so the records,
+//! attachments and uploads) to the picked messages. `first` visits readpst's files in folder
+//! and message-number order; `random` visits them in an order ranked by a seeded hash, so a
+//! rerun with the same seed picks the same messages. `--sample-percent` keeps each message
+//! with that probability, decided by the same hash, and `--sample` then caps the total.
+//! Messages that fail to parse still count as taken.
+
+use crate::platform::portable_rel_path;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::path::Path;
+use walkdir::DirEntry;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SampleMode {
+    First,
+    Random,
+}
+
+/// Recorded in the manifest as `sampling`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Summary {
+    pub count: Option<usize>,
+    pub percent: Option<f64>,
+    pub mode: SampleMode,
+    pub seed: u64,
+    pub messages_taken: usize,
+}
+
+pub struct Sampler {
+    summary: Summary,
+}
+
+impl Sampler {
+    /// None when neither a count nor a percentage is set.
+    pub fn new(
+        count: Option<usize>,
+        percent: Option<f64>,
+        mode: SampleMode,
+        seed: u64,
+    ) -> Result<Option<Self>> {
+        if let Some(p) = percent {
+            if !(p > 0.0 && p <= 100.0) {
+                return Err(anyhow!("--sample-percent must be above 0 and at most 100"));
+            }
+        }
+        if count == Some(0) {
+            return Err(anyhow!("--sample must be at least 1"));
+        }
+        if count.is_none() && percent.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            summary: Summary {
+                count,
+                percent,
+                mode,
+                seed,
+                messages_taken: 0,
+            },
+        }))
+    }
+
+    /// The files under `root` in the order they should be visited.
+    pub fn order(&self, entries: impl Iterator<Item = DirEntry>, root: &Path) -> Vec<DirEntry> {
+        let mut files: Vec<(String, DirEntry)> = entries
+            .filter(|e| e.file_type().is_file())
+            .map(|e| {
+                let rel = e.path().strip_prefix(root).map(portable_rel_path);
+                (rel.unwrap_or_else(|_| e.path().display().to_string()), e)
+            })
+            .collect();
+        match self.summary.mode {
+            SampleMode::First => files.sort_by(|a, b| natural_cmp(&a.0, &b.0)),
+            SampleMode::Random => {
+                files.sort_by_cached_key(|(rel, _)| (self.score(rel, None), rel.clone()))
+            }
+        }
+        files.into_iter().map(|(_, e)| e).collect()
+    }
+
+    /// The cap has been reached; nothing more is taken.
+    pub fn full(&self) -> bool {
+        self.summary
+            .count
+            .is_some_and(|n| self.summary.messages_taken >= n)
+    }
+
+    /// Whether to take message `index` of `source_path`; counts it when taken.
+    pub fn take(&mut self, source_path: &str, index: usize) -> bool {
+        if self.full() {
+            return false;
+        }
+        if let Some(percent) = self.summary.percent {
+            let threshold = (percent / 100.0 * u64::MAX as f64) as u64;
+            if self.score(source_path, Some(index)) > threshold {
+                return false;
+            }
+        }
+        self.summary.messages_taken += 1;
+        true
+    }
+
+    pub fn finish(self) -> Summary {
+        self.summary
+    }
+
+    fn score(&self, source_path: &str, index: Option<usize>) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(self.summary.seed.to_le_bytes());
+        hasher.update(source_path.as_bytes());
+        if let Some(index) = index {
+            hasher.update(b"\0");
+            hasher.update(index.to_le_bytes());
+        }
+        u64::from_be_bytes(hasher.finalize()[..8].try_into().expect("8 bytes"))
+    }
+}
+
+/// Path order with numbered names compared as numbers, so readpst's `Inbox/2` comes before
+/// `Inbox/10`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn number(part: &str) -> Option<(u128, &str)> {
+        let digits = part.bytes().take_while(u8::is_ascii_digit).count();
+        part[..digits].parse().ok().map(|n| (n, &part[digits..]))
+    }
+    for (x, y) in a.split('/').zip(b.split('/')) {
+        let ordering = match (number(x), number(y)) {
+            (Some(x), Some(y)) => x.cmp(&y),
+            _ => x.cmp(y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.split('/').count().cmp(&b.split('/').count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use walkdir::WalkDir;
+
+    #[test]
+    fn orders_files_and_caps_messages() {
+        let dir = std::env::temp_dir().join(format!("sample-{}", uuid::Uuid::new_v4()));
+        for name in ["Inbox/10", "Inbox/2", "Archive/1", "Inbox/1"] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().expect("parent")).expect("dir");
+            std::fs::write(path, "x").expect("write");
+        }
+        let walk = || WalkDir::new(&dir).into_iter().filter_map(|e| e.ok());
+        let rel = |entries: Vec<DirEntry>| -> Vec<String> {
+            let paths = entries
+                .iter()
+                .map(|e| e.path().strip_prefix(&dir).expect("rel"));
+            paths.map(portable_rel_path).collect()
+        };
+
+        let first = Sampler::new(Some(2), None, SampleMode::First, 0)
+            .expect("new")
+            .expect("on");
+        let order = rel(first.order(walk(), &dir));
+        assert_eq!(order, ["Archive/1", "Inbox/1", "Inbox/2", "Inbox/10"]);
+
+        let random = |seed| {
+            Sampler::new(Some(2), None, SampleMode::Random, seed)
+                .expect("new")
+                .expect("on")
+        };
+        let once = rel(random(7).order(walk(), &dir));
+        assert_eq!(once, rel(random(7).order(walk(), &dir)));
+        assert_eq!(once.len(), 4);
+
+        let mut sampler = random(7);
+        assert!(sampler.take("Inbox/1", 0));
+        assert!(sampler.take("Inbox/2", 0));
+        assert!(sampler.full());
+        assert!(!sampler.take("Inbox/10", 0));
+        assert_eq!(sampler.finish().messages_taken, 2);
+        std::fs::remove_dir_all(&dir).ok();
+
+        // About a quarter of the messages, the same ones every time.
+        let pick = || {
+            let mut sampler = Sampler::new(None, Some(25.0), SampleMode::First, 1)
+                .expect("new")
+                .expect("on");
+            (0..1000)
+                .filter(|i| sampler.take("mbox", *i))
+                .collect::<Vec<_>>()
+        };
+        let picked = pick();
+        assert!((200..300).contains(&picked.len()), "{}", picked.len());
+        assert_eq!(picked, pick());
+
+        assert!(Sampler::new(None, None, SampleMode::First, 0)
+            .expect("new")
+            .is_none());
+        assert!(Sampler::new(None, Some(0.0), SampleMode::First, 0).is_err());
+        assert!(Sampler::new(Some(0), None, SampleMode::First, 0).is_err());
+    }
+}
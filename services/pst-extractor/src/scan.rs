@@ -0,0 +1,358 @@
+//! Malware scanning of attachments (`--scan-endpoint`).
+//!
+//! Review users download attachments straight from the output bucket, so with a scanner
+//! configured every attachment that would be stored is sent to it first. Flagged files go under
+//! `quarantine/` instead of `attachments/` and the record's `s3_key` stays empty, so the
+//! platform never offers them for download.
+//!
+//! Two protocols are supported: clamd's `INSTREAM` over TCP (`tcp://host:3310` or `host:3310`)
+//! or a Unix socket (`unix:///run/clamav/clamd.ctl`), and ICAP `RESPMOD`
+//! (`icap://host:1344/avscan`), which most gateway AV products speak. The client is blocking,
+//! with connect, read and write bounded by `--scan-timeout-secs`; the parse loop runs each scan
+//! on the blocking pool.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Bytes per clamd `INSTREAM` chunk (well under clamd's default StreamMaxLength).
+const CHUNK: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Endpoint {
+    ClamdTcp(String),
+    ClamdUnix(String),
+    Icap {
+        addr: String,
+        host: String,
+        uri: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    Infected(String),
+}
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+pub struct Scanner {
+    endpoint: Endpoint,
+    timeout: Duration,
+}
+
+fn parse_endpoint(endpoint: &str) -> Result<Endpoint> {
+    let endpoint = endpoint.trim();
+    if let Some(rest) = endpoint.strip_prefix("icap://") {
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if authority.is_empty() {
+            bail!("scan endpoint {endpoint}: missing host");
+        }
+        let addr = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{authority}:1344")
+        };
+        return Ok(Endpoint::Icap {
+            host: authority.to_string(),
+            uri: format!("icap://{addr}/{path}"),
+            addr,
+        });
+    }
+    if let Some(path) = endpoint.strip_prefix("unix://") {
+        return Ok(Endpoint::ClamdUnix(path.to_string()));
+    }
+    if endpoint.starts_with('/') {
+        return Ok(Endpoint::ClamdUnix(endpoint.to_string()));
+    }
+    let addr = endpoint.strip_prefix("tcp://").unwrap_or(endpoint);
+    if !addr.contains(':') {
+        bail!("scan endpoint {endpoint}: expected host:port, tcp://, unix:// or icap://");
+    }
+    Ok(Endpoint::ClamdTcp(addr.to_string()))
+}
+
+/// Read up to and including the first NUL (clamd's `z`-command replies).
+fn read_clamd_reply(stream: &mut dyn Stream) -> Result<String> {
+    let mut reply = Vec::new();
+    BufReader::new(stream)
+        .read_until(0, &mut reply)
+        .context("read clamd reply")?;
+    if reply.last() == Some(&0) {
+        reply.pop();
+    }
+    Ok(String::from_utf8_lossy(&reply).trim().to_string())
+}
+
+/// `stream: OK` / `stream: <signature> FOUND` / `... ERROR`.
+fn clamd_verdict(reply: &str) -> Result<Verdict> {
+    let result = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if result == "OK" {
+        return Ok(Verdict::Clean);
+    }
+    if let Some(signature) = result.strip_suffix(" FOUND") {
+        return Ok(Verdict::Infected(signature.trim().to_string()));
+    }
+    Err(anyhow!("clamd: {reply}"))
+}
+
+/// Status line and headers of an ICAP response (the body, if any, is not needed).
+fn read_icap_head(stream: &mut dyn Stream) -> Result<(u16, Vec<(String, String)>)> {
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader
+        .read_line(&mut status_line)
+        .context("read ICAP status")?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| anyhow!("bad ICAP status line: {}", status_line.trim()))?;
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).context("read ICAP headers")? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    Ok((status, headers))
+}
+
+/// 204: unmodified (clean). 200: the service replaced the response, i.e. blocked it; the
+/// signature is in `X-Infection-Found` (`...; Threat=<name>;`) or `X-Virus-ID`.
+fn icap_verdict(status: u16, headers: &[(String, String)]) -> Result<Verdict> {
+    match status {
+        204 => Ok(Verdict::Clean),
+        200 => {
+            let header = |name: &str| {
+                headers
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.as_str())
+            };
+            let threat = header("x-infection-found").and_then(|v| {
+                v.split(';')
+                    .find_map(|part| part.trim().strip_prefix("Threat="))
+                    .map(str::to_string)
+            });
+            let signature = threat
+                .or_else(|| header("x-virus-id").map(str::to_string))
+                .unwrap_or_else(|| "unknown".to_string());
+            Ok(Verdict::Infected(signature))
+        }
+        other => Err(anyhow!("ICAP status {other}")),
+    }
+}
+
+/// Connect to the first address `addr` resolves to that answers within `timeout`.
+fn connect_tcp(addr: &str, timeout: Duration) -> Result<TcpStream> {
+    let mut last_err = None;
+    for sock in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&sock, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.map_or_else(|| anyhow!("{addr} resolved to no addresses"), Into::into))
+}
+
+impl Scanner {
+    /// Parse the endpoint and check the scanner answers (clamd `PING`, ICAP `OPTIONS`), so a
+    /// misconfigured endpoint fails the job up front rather than quarantining every attachment.
+    pub fn connect(endpoint: &str, timeout: Duration) -> Result<Self> {
+        let scanner = Self {
+            endpoint: parse_endpoint(endpoint)?,
+            timeout,
+        };
+        let mut stream = scanner.open()?;
+        match &scanner.endpoint {
+            Endpoint::Icap { host, uri, .. } => {
+                write!(
+                    stream,
+                    "OPTIONS {uri} ICAP/1.0\r\nHost: {host}\r\nEncapsulated: null-body=0\r\n\r\n"
+                )?;
+                let (status, _) = read_icap_head(&mut *stream)?;
+                if status != 200 {
+                    bail!("ICAP OPTIONS {uri}: status {status}");
+                }
+            }
+            _ => {
+                stream.write_all(b"zPING\0")?;
+                let reply = read_clamd_reply(&mut *stream)?;
+                if reply != "PONG" {
+                    bail!("clamd PING: unexpected reply {reply:?}");
+                }
+            }
+        }
+        Ok(scanner)
+    }
+
+    fn open(&self) -> Result<Box<dyn Stream>> {
+        let addr = match &self.endpoint {
+            Endpoint::ClamdTcp(addr) | Endpoint::Icap { addr, .. } => addr,
+            Endpoint::ClamdUnix(path) => return self.open_unix(path),
+        };
+        // Bounded by the scan timeout, so an unreachable host fails fast instead of waiting out
+        // the OS connect timeout.
+        let stream = connect_tcp(addr, self.timeout)
+            .with_context(|| format!("connect to scanner at {addr}"))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        Ok(Box::new(stream))
+    }
+
+    #[cfg(unix)]
+    fn open_unix(&self, path: &str) -> Result<Box<dyn Stream>> {
+        let stream = std::os::unix::net::UnixStream::connect(path)
+            .with_context(|| format!("connect to clamd at {path}"))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        Ok(Box::new(stream))
+    }
+
+    #[cfg(not(unix))]
+    fn open_unix(&self, path: &str) -> Result<Box<dyn Stream>> {
+        bail!("clamd socket {path}: Unix sockets are not available on this platform")
+    }
+
+    pub fn scan(&self, data: &[u8]) -> Result<Verdict> {
+        let mut stream = self.open()?;
+        match &self.endpoint {
+            Endpoint::Icap { host, uri, .. } => {
+                let http = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+                    data.len()
+                );
+                write!(
+                    stream,
+                    "RESPMOD {uri} ICAP/1.0\r\nHost: {host}\r\nAllow: 204\r\nEncapsulated: res-hdr=0, res-body={}\r\n\r\n{http}",
+                    http.len()
+                )?;
+                for chunk in data.chunks(CHUNK) {
+                    write!(stream, "{:x}\r\n", chunk.len())?;
+                    stream.write_all(chunk)?;
+                    stream.write_all(b"\r\n")?;
+                }
+                stream.write_all(b"0\r\n\r\n")?;
+                let (status, headers) = read_icap_head(&mut *stream)?;
+                icap_verdict(status, &headers)
+            }
+            _ => {
+                stream.write_all(b"zINSTREAM\0")?;
+                for chunk in data.chunks(CHUNK) {
+                    stream.write_all(&(chunk.len() as u32).to_be_bytes())?;
+                    stream.write_all(chunk)?;
+                }
+                stream.write_all(&0u32.to_be_bytes())?;
+                clamd_verdict(&read_clamd_reply(&mut *stream)?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// One-connection-per-request fake clamd that flags payloads containing "EICAR".
+    fn fake_clamd() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr").to_string();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.expect("accept");
+                let mut command = Vec::new();
+                let mut reader = BufReader::new(stream.try_clone().expect("clone"));
+                reader.read_until(0, &mut command).expect("command");
+                if command == b"zPING\0" {
+                    stream.write_all(b"PONG\0").expect("reply");
+                    continue;
+                }
+                let mut data = Vec::new();
+                loop {
+                    let mut len = [0u8; 4];
+                    reader.read_exact(&mut len).expect("len");
+                    let len = u32::from_be_bytes(len) as usize;
+                    if len == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0; len];
+                    reader.read_exact(&mut chunk).expect("chunk");
+                    data.extend(chunk);
+                }
+                let infected = data.windows(5).any(|w| w == b"EICAR");
+                let reply: &[u8] = if infected {
+                    b"stream: Eicar-Test-Signature FOUND\0"
+                } else {
+                    b"stream: OK\0"
+                };
+                stream.write_all(reply).expect("reply");
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn clamd_instream_and_icap_verdicts() {
+        let scanner = Scanner::connect(&fake_clamd(), Duration::from_secs(5)).expect("connect");
+        assert_eq!(
+            scanner.scan(b"quarterly report").expect("scan"),
+            Verdict::Clean
+        );
+        let mut payload = vec![b'x'; CHUNK + 10];
+        payload.extend(b"EICAR");
+        assert_eq!(
+            scanner.scan(&payload).expect("scan"),
+            Verdict::Infected("Eicar-Test-Signature".to_string())
+        );
+        assert!(clamd_verdict("INSTREAM size limit exceeded. ERROR").is_err());
+
+        assert_eq!(
+            parse_endpoint("icap://av.internal/avscan").expect("icap"),
+            Endpoint::Icap {
+                addr: "av.internal:1344".to_string(),
+                host: "av.internal".to_string(),
+                uri: "icap://av.internal:1344/avscan".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_endpoint("/run/clamav/clamd.ctl").expect("unix"),
+            Endpoint::ClamdUnix("/run/clamav/clamd.ctl".to_string())
+        );
+        assert!(parse_endpoint("clamd").is_err());
+        assert_eq!(icap_verdict(204, &[]).expect("204"), Verdict::Clean);
+        let headers = vec![(
+            "x-infection-found".to_string(),
+            "Type=0; Resolution=2; Threat=Win.Trojan.Agent;".to_string(),
+        )];
+        assert_eq!(
+            icap_verdict(200, &headers).expect("200"),
+            Verdict::Infected("Win.Trojan.Agent".to_string())
+        );
+        assert!(icap_verdict(500, &[]).is_err());
+    }
+
+    #[test]
+    fn unreachable_scanner_fails_within_the_timeout() {
+        // A port nothing listens on, and a non-routable address that never answers.
+        let closed = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let closed_addr = closed.local_addr().expect("addr").to_string();
+        drop(closed);
+        for endpoint in [closed_addr.as_str(), "10.255.255.1:3310"] {
+            let started = std::time::Instant::now();
+            assert!(Scanner::connect(endpoint, Duration::from_millis(300)).is_err());
+            assert!(started.elapsed() < Duration::from_secs(5), "{endpoint}");
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! The extractor's output contract, emitted per run as `schema.json`.
+//!
+//! The CSV headers written by the extractor are generated from these column lists, and the loader
+//! checks its staging tables against `schema.json` before `COPY`, so a column added on one side
+//! only fails loudly instead of shifting data into the wrong column. Bump `SCHEMA_VERSION` when
+//! a CSV column changes or an NDJSON field is removed or retyped; new NDJSON fields are additive.
+
+use serde::Serialize;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// One CSV column (Postgres type of the staging-table column) or NDJSON field (JSON type).
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Field {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub ty: &'static str,
+    pub nullable: bool,
+}
+
+const fn col(name: &'static str, ty: &'static str, nullable: bool) -> Field {
+    Field { name, ty, nullable }
+}
+
+/// `emails.csv.gz` → `pst_v2_emails_raw`, in file order.
+pub const EMAIL_COLUMNS: &[Field] = &[
+    col("id", "uuid", false),
+    col("pst_file_id", "uuid", false),
+    col("project_id", "uuid", true),
+    col("case_id", "uuid", true),
+    col("message_id", "text", true),
+    col("in_reply_to", "text", true),
+    col("references_header", "text", true),
+    col("subject", "text", true),
+    col("from_header", "text", true),
+    col("to_header", "text", true),
+    col("cc_header", "text", true),
+    col("bcc_header", "text", true),
+    col("date_header", "text", true),
+    col("date_epoch", "bigint", true),
+    col("sender_email", "text", true),
+    col("sender_name", "text", true),
+    col("body_text", "text", true),
+    col("body_html", "text", true),
+    col("source_path", "text", true),
+];
+
+/// `attachments.csv.gz` → `pst_v2_attachments_raw`, in file order.
+pub const ATTACHMENT_COLUMNS: &[Field] = &[
+    col("id", "uuid", false),
+    col("email_message_id", "uuid", false),
+    col("pst_file_id", "uuid", false),
+    col("project_id", "uuid", true),
+    col("case_id", "uuid", true),
+    col("filename", "text", true),
+    col("content_type", "text", true),
+    col("file_size_bytes", "bigint", true),
+    col("s3_bucket", "text", true),
+    col("s3_key", "text", true),
+    col("attachment_hash", "text", true),
+    col("is_inline", "boolean", true),
+    col("content_id", "text", true),
+    col("source_path", "text", true),
+];
+
+/// `emails.ndjson.gz` record fields.
+pub const EMAIL_FIELDS: &[Field] = &[
+    col("id", "string", false),
+    col("pst_file_id", "string", false),
+    col("project_id", "string", true),
+    col("case_id", "string", true),
+    col("custodian_id", "string", true),
+    col("custodian_name", "string", true),
+    col("source_path", "string", false),
+    col("message_id", "string", true),
+    col("message_id_normalized", "string", true),
+    col("message_id_synthetic", "boolean", false),
+    col("in_reply_to", "string", true),
+    col("references", "string", true),
+    col("subject", "string", true),
+    col("from", "string", true),
+    col("to", "string", true),
+    col("cc", "string", true),
+    col("bcc", "string", true),
+    col("to_emails", "array<string>", false),
+    col("cc_emails", "array<string>", false),
+    col("bcc_emails", "array<string>", false),
+    col("date", "string", true),
+    col("date_epoch", "integer", true),
+    col("date_parser", "string", true),
+    col("date_source", "string", true),
+    col("date_utc", "string", true),
+    col("received", "array<string>", false),
+    col("body_text", "string", true),
+    col("body_html", "string", true),
+    col("body_html_rewritten", "string", true),
+    col("body_text_clean", "string", true),
+    col("near_dupe_hash", "string", true),
+    col("near_dupe_cluster_id", "string", true),
+    col("body_language", "string", true),
+    col("direction", "string", true),
+    col("sender_email", "string", true),
+    col("sender_name", "string", true),
+    col("originating_ip", "string", true),
+    col("mail_client", "string", true),
+    col("processing_flags", "array<string>", false),
+    col("term_hits", "object<string,integer>", false),
+    col("pii_flags", "array<string>", false),
+    col("rule_tags", "array<string>", false),
+    col("parse_ms", "number", false),
+    col("body_html_br_key", "string", true),
+    col("truncated_mime", "boolean", false),
+    col("charset_issues", "boolean", false),
+    col("parent_email_id", "string", true),
+    col("family_id", "string", false),
+    col("depth", "integer", false),
+    col("duplicate_header_names", "array<string>", false),
+    col("header_smuggling_suspected", "boolean", false),
+    col("spoofing_suspected", "boolean", false),
+    col("attachments_withheld", "integer", false),
+    col("thread_id", "string", true),
+    col("thread_position", "integer", true),
+    col("dedupe_hash", "string", true),
+    col("is_global_duplicate", "boolean", false),
+    col("global_primary_email_id", "string", true),
+    col("is_signed", "boolean", false),
+    col("signature_protocol", "string", true),
+    col("signature_s3_key", "string", true),
+    col("delivery_status", "object", true),
+    col("calendar_uid", "string", true),
+    col("calendar_sequence", "integer", true),
+    col("calendar_method", "string", true),
+    col("is_recovered", "boolean", false),
+    col("review_priority", "integer", true),
+    col("envelope_from", "string", true),
+    col("envelope_date", "string", true),
+    col("envelope_date_epoch", "integer", true),
+    col("family_zip_s3_key", "string", true),
+    col("raw_eml_s3_key", "string", true),
+    col("raw_sha256", "string", true),
+    col("raw_hashes", "object", true),
+    col("control_number", "string", true),
+    col("journal_envelope", "object", true),
+    col("journal_recipient_emails", "array<string>", false),
+    col("journal_bcc_emails", "array<string>", false),
+    col("importance", "string", true),
+    col("sensitivity", "string", true),
+    col("read_receipt_requested", "boolean", false),
+    col("delivery_receipt_requested", "boolean", false),
+    col("receipt_type", "string", true),
+    col("retained_headers", "object<string,string>", false),
+];
+
+/// `attachments.ndjson.gz` record fields.
+pub const ATTACHMENT_FIELDS: &[Field] = &[
+    col("id", "string", false),
+    col("email_message_id", "string", false),
+    col("pst_file_id", "string", false),
+    col("project_id", "string", true),
+    col("case_id", "string", true),
+    col("custodian_id", "string", true),
+    col("custodian_name", "string", true),
+    col("filename", "string", false),
+    col("content_type", "string", true),
+    col("file_size_bytes", "integer", false),
+    col("s3_bucket", "string", false),
+    col("s3_key", "string", false),
+    col("attachment_hash", "string", false),
+    col("is_inline", "boolean", false),
+    col("content_id", "string", true),
+    col("source_path", "string", false),
+    col("is_encrypted_attachment", "boolean", false),
+    col("source_container", "string", true),
+    col("declared_content_type", "string", true),
+    col("detected_content_type", "string", true),
+    col("extension_mismatch", "boolean", false),
+    col("charset_issues", "boolean", false),
+    col("is_nist", "boolean", false),
+    col("parent_attachment_id", "string", true),
+    col("archive_path", "string", true),
+    col("archive_status", "string", true),
+    col("skipped_reason", "string", true),
+    col("scan_status", "string", true),
+    col("scan_signature", "string", true),
+    col("quarantine_s3_key", "string", true),
+    col("pii_flags", "array<string>", false),
+    col("doc_type", "string", true),
+    col("doc_language", "string", true),
+    col("md5", "string", true),
+    col("sha1", "string", true),
+    col("control_number", "string", true),
+];
+
+/// CSV header line for `columns`.
+pub fn csv_header(columns: &[Field]) -> String {
+    columns.iter().map(|c| c.name).collect::<Vec<_>>().join(",")
+}
+
+#[derive(Serialize)]
+pub struct FileSchema {
+    pub format: &'static str,
+    /// Loader staging table (CSV files only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<&'static str>,
+    pub fields: &'static [Field],
+}
+
+#[derive(Serialize)]
+pub struct Schema {
+    pub schema_version: u32,
+    pub extractor_version: &'static str,
+    pub files: std::collections::BTreeMap<&'static str, FileSchema>,
+}
+
+pub fn document() -> Schema {
+    let csv = |table, fields| FileSchema {
+        format: "csv",
+        table: Some(table),
+        fields,
+    };
+    let ndjson = |fields| FileSchema {
+        format: "ndjson",
+        table: None,
+        fields,
+    };
+    Schema {
+        schema_version: SCHEMA_VERSION,
+        extractor_version: env!("CARGO_PKG_VERSION"),
+        files: [
+            ("emails.csv.gz", csv("pst_v2_emails_raw", EMAIL_COLUMNS)),
+            (
+                "attachments.csv.gz",
+                csv("pst_v2_attachments_raw", ATTACHMENT_COLUMNS),
+            ),
+            ("emails.ndjson.gz", ndjson(EMAIL_FIELDS)),
+            ("attachments.ndjson.gz", ndjson(ATTACHMENT_FIELDS)),
+        ]
+        .into_iter()
+        .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_headers_match_loader_copy_order() {
+        assert_eq!(
+            csv_header(EMAIL_COLUMNS),
+            "id,pst_file_id,project_id,case_id,message_id,in_reply_to,references_header,subject,from_header,to_header,cc_header,bcc_header,date_header,date_epoch,sender_email,sender_name,body_text,body_html,source_path"
+        );
+        assert_eq!(
+            csv_header(ATTACHMENT_COLUMNS),
+            "id,email_message_id,pst_file_id,project_id,case_id,filename,content_type,file_size_bytes,s3_bucket,s3_key,attachment_hash,is_inline,content_id,source_path"
+        );
+        let doc = serde_json::to_value(document()).expect("schema");
+        assert_eq!(
+            doc["files"]["emails.csv.gz"]["fields"][13]["type"],
+            "bigint"
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! Review-priority scoring (`--priority-model`).
+//!
+//! Each email gets a 0–100 `review_priority` so first-pass review queues can be ordered straight
+//! from the extraction output. Two backends are available:
+//!
+//! * a rules file (JSON, local path or `s3://`): additive weights on participants, search-term
+//!   hits, direction, attachments and security flags, clamped to 0–100;
+//! * an HTTP(S) endpoint: emails are POSTed in batches as `{"emails": [ScoreInput, ...]}` and the
+//!   response is `{"scores": [{"email_id": "...", "review_priority": 0-100}, ...]}`. Emails it
+//!   leaves out get no score.
+//!
+//! Scores are filled in during the threading rewrite of `emails.ndjson.gz`.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+const ENDPOINT_BATCH: usize = 500;
+const ENDPOINT_MAX_ATTEMPTS: u32 = 3;
+
+/// Which way an email crossed the organisation boundary (`--internal-domains`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Internal sender, all recipients internal.
+    Internal,
+    /// Internal sender, at least one external recipient.
+    Outbound,
+    /// External sender, at least one internal recipient.
+    Inbound,
+    /// Neither side internal (e.g. a copy forwarded in).
+    External,
+}
+
+fn domain_of(address: &str) -> Option<String> {
+    address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim_end_matches('>').to_ascii_lowercase())
+}
+
+/// Classify by the sender's and recipients' domains. None when there are no internal domains or
+/// no sender.
+pub fn direction(
+    internal_domains: &[String],
+    sender: Option<&str>,
+    recipients: &[String],
+) -> Option<Direction> {
+    if internal_domains.is_empty() {
+        return None;
+    }
+    let internal = |address: &str| {
+        domain_of(address).is_some_and(|d| {
+            internal_domains
+                .iter()
+                .any(|i| d == *i || d.ends_with(&format!(".{i}")))
+        })
+    };
+    let from_internal = internal(sender?);
+    let any_internal = recipients.iter().any(|r| internal(r));
+    let all_internal = !recipients.is_empty() && recipients.iter().all(|r| internal(r));
+    Some(match (from_internal, all_internal, any_internal) {
+        (true, true, _) => Direction::Internal,
+        (true, false, _) => Direction::Outbound,
+        (false, _, true) => Direction::Inbound,
+        (false, _, false) => Direction::External,
+    })
+}
+
+/// The features a model sees for one email.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ScoreInput {
+    pub email_id: String,
+    pub sender_email: Option<String>,
+    /// to/cc/bcc addresses, lowercased.
+    pub recipients: Vec<String>,
+    pub subject: Option<String>,
+    pub term_hits: BTreeMap<String, usize>,
+    pub direction: Option<Direction>,
+    pub attachment_names: Vec<String>,
+    pub spoofing_suspected: bool,
+    pub header_smuggling_suspected: bool,
+}
+
+/// Weights of a rules file. Every section is optional.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    #[serde(default)]
+    pub base: i64,
+    /// Address (`pm@contractor.example`) or domain (`*@client.example`) → weight, added once per
+    /// pattern that matches the sender or any recipient.
+    #[serde(default)]
+    pub participants: BTreeMap<String, i64>,
+    /// Term → weight for each distinct term that hit; `*` applies to terms not listed.
+    #[serde(default)]
+    pub terms: BTreeMap<String, i64>,
+    #[serde(default)]
+    pub direction: BTreeMap<String, i64>,
+    #[serde(default)]
+    pub attachments: AttachmentRules,
+    /// `spoofing_suspected` / `header_smuggling_suspected` → weight.
+    #[serde(default)]
+    pub flags: BTreeMap<String, i64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AttachmentRules {
+    /// Added when the email has at least one attachment.
+    #[serde(default)]
+    pub any: i64,
+    /// Lowercase extension → weight, once per distinct extension present.
+    #[serde(default)]
+    pub extensions: BTreeMap<String, i64>,
+}
+
+impl Rules {
+    pub fn score(&self, input: &ScoreInput) -> u8 {
+        let mut total = self.base;
+        let people: Vec<&str> = input
+            .sender_email
+            .iter()
+            .map(String::as_str)
+            .chain(input.recipients.iter().map(String::as_str))
+            .collect();
+        for (pattern, weight) in &self.participants {
+            let pattern = pattern.to_ascii_lowercase();
+            let matches = |addr: &&str| match pattern.strip_prefix('*') {
+                Some(domain) if domain.starts_with('@') => addr.ends_with(domain),
+                _ => addr.eq_ignore_ascii_case(&pattern),
+            };
+            if people.iter().any(matches) {
+                total += weight;
+            }
+        }
+        for term in input.term_hits.keys() {
+            total += self
+                .terms
+                .get(term)
+                .or_else(|| self.terms.get("*"))
+                .copied()
+                .unwrap_or(0);
+        }
+        if let Some(direction) = input.direction {
+            let key = serde_json::to_value(direction).expect("direction serializes");
+            total += key
+                .as_str()
+                .and_then(|k| self.direction.get(k))
+                .copied()
+                .unwrap_or(0);
+        }
+        if !input.attachment_names.is_empty() {
+            total += self.attachments.any;
+        }
+        let mut extensions: Vec<String> = input
+            .attachment_names
+            .iter()
+            .filter_map(|n| n.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase()))
+            .collect();
+        extensions.sort();
+        extensions.dedup();
+        for ext in extensions {
+            total += self.attachments.extensions.get(&ext).copied().unwrap_or(0);
+        }
+        for (flag, set) in [
+            ("spoofing_suspected", input.spoofing_suspected),
+            (
+                "header_smuggling_suspected",
+                input.header_smuggling_suspected,
+            ),
+        ] {
+            if set {
+                total += self.flags.get(flag).copied().unwrap_or(0);
+            }
+        }
+        total.clamp(0, 100) as u8
+    }
+}
+
+#[derive(Deserialize)]
+struct EndpointResponse {
+    scores: Vec<EndpointScore>,
+}
+
+#[derive(Deserialize)]
+struct EndpointScore {
+    email_id: String,
+    review_priority: f64,
+}
+
+enum Backend {
+    Rules(Rules),
+    Endpoint {
+        url: String,
+        client: reqwest::Client,
+        pending: Vec<ScoreInput>,
+    },
+}
+
+/// Collects a score per email id; endpoint requests are batched.
+pub struct Scorer {
+    backend: Backend,
+    scores: HashMap<String, u8>,
+}
+
+impl Scorer {
+    pub fn from_rules(text: &str) -> Result<Self> {
+        let rules: Rules = serde_json::from_str(text).context("parse priority rules file")?;
+        Ok(Self {
+            backend: Backend::Rules(rules),
+            scores: HashMap::new(),
+        })
+    }
+
+    pub fn from_endpoint(url: &str) -> Result<Self> {
+        Ok(Self {
+            backend: Backend::Endpoint {
+                url: url.to_string(),
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_secs(60))
+                    .build()?,
+                pending: Vec::new(),
+            },
+            scores: HashMap::new(),
+        })
+    }
+
+    pub async fn add(&mut self, input: ScoreInput) -> Result<()> {
+        match &mut self.backend {
+            Backend::Rules(rules) => {
+                let score = rules.score(&input);
+                self.scores.insert(input.email_id, score);
+            }
+            Backend::Endpoint { pending, .. } => {
+                pending.push(input);
+                if pending.len() >= ENDPOINT_BATCH {
+                    self.flush().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        let Backend::Endpoint {
+            url,
+            client,
+            pending,
+        } = &mut self.backend
+        else {
+            return Ok(());
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let body = serde_json::to_vec(&serde_json::json!({ "emails": pending }))?;
+        let mut last_err = anyhow!("priority endpoint not attempted");
+        for attempt in 1..=ENDPOINT_MAX_ATTEMPTS {
+            let sent = client
+                .post(url.as_str())
+                .header("Content-Type", "application/json")
+                .body(body.clone())
+                .send()
+                .await;
+            match sent {
+                Ok(resp) if resp.status().is_success() => {
+                    let parsed: EndpointResponse = resp
+                        .json()
+                        .await
+                        .context("priority endpoint returned invalid JSON")?;
+                    for score in parsed.scores {
+                        let value = score.review_priority.round().clamp(0.0, 100.0) as u8;
+                        self.scores.insert(score.email_id, value);
+                    }
+                    pending.clear();
+                    return Ok(());
+                }
+                Ok(resp) if resp.status().is_client_error() && resp.status().as_u16() != 429 => {
+                    return Err(anyhow!(
+                        "priority endpoint rejected batch with HTTP {}",
+                        resp.status()
+                    ));
+                }
+                Ok(resp) => last_err = anyhow!("priority endpoint returned HTTP {}", resp.status()),
+                Err(e) => last_err = e.into(),
+            }
+            if attempt < ENDPOINT_MAX_ATTEMPTS {
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Score anything still batched and return email id → review_priority.
+    pub async fn finish(mut self) -> Result<HashMap<String, u8>> {
+        self.flush().await?;
+        Ok(self.scores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_add_weights_and_clamp() {
+        let rules: Rules = serde_json::from_str(
+            r#"{
+                "base": 10,
+                "participants": {"*@client.example": 20, "pm@contractor.example": 5},
+                "terms": {"delay": 25, "*": 5},
+                "direction": {"inbound": 10},
+                "attachments": {"any": 5, "extensions": {"mpp": 15}},
+                "flags": {"spoofing_suspected": 60}
+            }"#,
+        )
+        .expect("rules");
+        let internal = vec!["contractor.example".to_string()];
+        let recipients = vec!["pm@contractor.example".to_string()];
+        let input = ScoreInput {
+            email_id: "e1".into(),
+            sender_email: Some("qs@client.example".into()),
+            direction: direction(&internal, Some("qs@client.example"), &recipients),
+            recipients,
+            term_hits: [("delay".to_string(), 3), ("eot".to_string(), 1)].into(),
+            attachment_names: vec!["Programme Rev C.MPP".into(), "notes.mpp".into()],
+            ..Default::default()
+        };
+        assert_eq!(input.direction, Some(Direction::Inbound));
+        // 10 + 20 + 5 + 25 + 5 + 10 + 5 + 15
+        assert_eq!(rules.score(&input), 95);
+        let spoofed = ScoreInput {
+            spoofing_suspected: true,
+            ..input
+        };
+        assert_eq!(rules.score(&spoofed), 100);
+        assert_eq!(rules.score(&ScoreInput::default()), 10);
+    }
+
+    #[test]
+    fn classifies_direction_by_domain() {
+        let internal = vec!["example.com".to_string(), "corp.example.net".to_string()];
+        let to = |addrs: &[&str]| addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let classify = |sender, recipients: &[&str]| direction(&internal, sender, &to(recipients));
+        assert_eq!(
+            classify(
+                Some("a@example.com"),
+                &["b@uk.example.com", "c@corp.example.net"]
+            ),
+            Some(Direction::Internal)
+        );
+        assert_eq!(
+            classify(
+                Some("a@example.com"),
+                &["b@example.com", "c@client.example"]
+            ),
+            Some(Direction::Outbound)
+        );
+        assert_eq!(
+            classify(Some("c@client.example"), &["a@example.com"]),
+            Some(Direction::Inbound)
+        );
+        assert_eq!(
+            classify(Some("c@client.example"), &["d@other.example"]),
+            Some(Direction::External)
+        );
+        // A draft with no recipients yet isn't internal mail.
+        assert_eq!(
+            classify(Some("a@example.com"), &[]),
+            Some(Direction::Outbound)
+        );
+        // Lookalikes don't count as subdomains.
+        assert_eq!(
+            classify(Some("a@notexample.com"), &["b@example.com"]),
+            Some(Direction::Inbound)
+        );
+        assert_eq!(classify(None, &["a@example.com"]), None);
+        assert_eq!(direction(&[], Some("a@example.com"), &[]), None);
+    }
+}
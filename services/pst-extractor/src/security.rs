@@ -0,0 +1,267 @@
+//! Security findings: header anomalies and sender spoofing worth a reviewer's attention.
+//!
+//! Findings are stamped on the email record and also collected into `security_report.ndjson.gz`
+//! so investigators can triage them without scanning every email.
+
+use mailparse::MailHeader;
+use serde::Serialize;
+
+/// Headers RFC 5322 allows at most once. When a message repeats one, the FIRST occurrence is
+/// used for the record (the same value `header_first` has always returned); later copies are
+/// ignored but reported.
+pub const SINGLETON_HEADERS: &[&str] = &[
+    "From",
+    "Sender",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Bcc",
+    "Message-ID",
+    "In-Reply-To",
+    "References",
+    "Subject",
+    "Date",
+];
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HeaderAudit {
+    /// Singleton headers that occur more than once (canonical capitalization).
+    pub duplicate_header_names: Vec<String>,
+    /// Subset of the duplicates whose copies carry different values. Identical repeats are
+    /// usually relay artifacts; differing ones are how header smuggling hides a second sender.
+    pub conflicting_header_names: Vec<String>,
+}
+
+pub fn audit_headers(headers: &[MailHeader]) -> HeaderAudit {
+    let mut audit = HeaderAudit::default();
+    for name in SINGLETON_HEADERS {
+        let values: Vec<String> = headers
+            .iter()
+            .filter(|h| h.get_key_ref().eq_ignore_ascii_case(name))
+            .map(|h| {
+                h.get_value()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        if values.len() < 2 {
+            continue;
+        }
+        audit.duplicate_header_names.push(name.to_string());
+        if values.iter().any(|v| *v != values[0]) {
+            audit.conflicting_header_names.push(name.to_string());
+        }
+    }
+    audit
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    HeaderSmuggling,
+    SpoofingSuspected,
+}
+
+/// Fold a string to its confusable "skeleton": lowercase, common Cyrillic/Greek homoglyphs and
+/// digit substitutions mapped to Latin, accents dropped, and everything but letters and digits
+/// removed. Two strings with the same skeleton look alike to a reader.
+pub fn skeleton(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars().flat_map(char::to_lowercase) {
+        let mapped = match c {
+            'а' | 'α' | 'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | '@' => 'a',
+            'в' | 'β' => 'b',
+            'с' | 'ç' | 'ϲ' => 'c',
+            'ԁ' => 'd',
+            'е' | 'ε' | 'è' | 'é' | 'ê' | 'ë' | '3' => 'e',
+            'ɡ' => 'g',
+            'һ' => 'h',
+            'і' | 'ι' | 'ì' | 'í' | 'î' | 'ï' | '1' | '!' | '|' => 'l',
+            'ј' => 'j',
+            'κ' | 'к' => 'k',
+            'м' => 'm',
+            'η' | 'ñ' => 'n',
+            'о' | 'ο' | 'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | '0' => 'o',
+            'р' | 'ρ' => 'p',
+            'ѕ' | '5' | '$' => 's',
+            'т' | 'τ' => 't',
+            'υ' | 'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ν' => 'v',
+            'х' | 'χ' => 'x',
+            'у' | 'ý' | 'ÿ' => 'y',
+            c if c.is_alphanumeric() => c,
+            _ => continue,
+        };
+        out.push(mapped);
+    }
+    // "i" and "l" are interchangeable in most fonts, as are "rn"/"m" and "vv"/"w".
+    out.replace('i', "l").replace("rn", "m").replace("vv", "w")
+}
+
+/// Skeleton of a display name with word order ignored ("Smith, John" == "John Smith").
+fn name_key(name: &str) -> String {
+    let mut words: Vec<String> = name
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(skeleton)
+        .filter(|w| !w.is_empty())
+        .collect();
+    words.sort();
+    words.concat()
+}
+
+struct Vip {
+    name: String,
+    name_key: String,
+    addresses: Vec<String>,
+}
+
+/// Executives and other likely impersonation targets, from `--vip-list`.
+pub struct VipList {
+    vips: Vec<Vip>,
+    /// Domains of all VIP addresses, for lookalike-domain checks.
+    domains: Vec<String>,
+}
+
+impl VipList {
+    /// One VIP per line: `Display Name <addr@example.com>[, <other@example.com>...]` or
+    /// `Display Name, addr@example.com[, other@example.com...]`. Blank lines and `#` comments
+    /// are ignored.
+    pub fn parse(text: &str) -> Self {
+        let mut vips = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, rest) = match line.find(['<', ',']) {
+                Some(i) => (&line[..i], &line[i..]),
+                None => (line, ""),
+            };
+            let addresses: Vec<String> = rest
+                .split([',', '<', '>', ' ', ';'])
+                .filter(|a| a.contains('@'))
+                .map(|a| a.trim().to_ascii_lowercase())
+                .collect();
+            let name = name.trim().trim_matches('"').to_string();
+            if name.is_empty() {
+                continue;
+            }
+            vips.push(Vip {
+                name_key: name_key(&name),
+                name,
+                addresses,
+            });
+        }
+        let mut domains: Vec<String> = vips
+            .iter()
+            .flat_map(|v| &v.addresses)
+            .filter_map(|a| a.rsplit_once('@').map(|(_, d)| d.to_string()))
+            .collect();
+        domains.sort();
+        domains.dedup();
+        Self { vips, domains }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vips.len()
+    }
+
+    /// Why this sender looks like an impersonation, or None.
+    pub fn check(&self, sender_name: Option<&str>, sender_email: Option<&str>) -> Option<String> {
+        let email = sender_email.map(|e| e.trim().to_ascii_lowercase());
+        let email = email.as_deref().unwrap_or("");
+
+        if let Some(name) = sender_name.filter(|n| !n.trim().is_empty()) {
+            let key = name_key(name);
+            for vip in &self.vips {
+                if key == vip.name_key && !vip.addresses.iter().any(|a| a == email) {
+                    return Some(format!(
+                        "display name {name:?} matches VIP {:?} but sender is {email:?}",
+                        vip.name
+                    ));
+                }
+            }
+        }
+
+        if let Some((local, domain)) = email.rsplit_once('@') {
+            for vip_address in self.vips.iter().flat_map(|v| &v.addresses) {
+                if vip_address != email && skeleton(vip_address) == skeleton(email) {
+                    return Some(format!(
+                        "sender {email:?} is a lookalike of VIP address {vip_address:?}"
+                    ));
+                }
+            }
+            if !self.domains.iter().any(|d| d == domain) {
+                if let Some(d) = self
+                    .domains
+                    .iter()
+                    .find(|d| skeleton(d) == skeleton(domain))
+                {
+                    return Some(format!(
+                        "sender domain {domain:?} is a lookalike of {d:?} (local part {local:?})"
+                    ));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// One line of security_report.ndjson.gz.
+#[derive(Serialize)]
+pub struct SecurityFinding {
+    pub email_id: String,
+    pub source_path: String,
+    pub kind: FindingKind,
+    pub detail: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_duplicate_and_conflicting_singletons() {
+        let raw = b"From: ceo@example.com\r\nFrom: attacker@evil.test\r\nSubject: hi\r\nsubject:  hi\r\nReceived: a\r\nReceived: b\r\n\r\nbody";
+        let (headers, _) = mailparse::parse_headers(raw).expect("headers");
+        let audit = audit_headers(&headers);
+        assert_eq!(audit.duplicate_header_names, vec!["From", "Subject"]);
+        assert_eq!(audit.conflicting_header_names, vec!["From"]);
+
+        let (headers, _) =
+            mailparse::parse_headers(b"From: a@b\r\nTo: c@d\r\n\r\n").expect("headers");
+        assert_eq!(audit_headers(&headers), HeaderAudit::default());
+    }
+
+    #[test]
+    fn flags_display_name_and_homoglyph_spoofs() {
+        let vips = VipList::parse(
+            "# executives\nJohn Smith <john.smith@acme.com>, <jsmith@acme.com>\nMary Jones, mary.jones@acme.com\n",
+        );
+        assert_eq!(vips.len(), 2);
+
+        // Legitimate mail from the VIP.
+        assert!(vips
+            .check(Some("John Smith"), Some("JSmith@acme.com"))
+            .is_none());
+        // Executive display name on a free-mail address, including reordered names.
+        assert!(vips
+            .check(Some("Smith, John"), Some("ceo.office@gmail.com"))
+            .is_some());
+        // Cyrillic "о" in the display name.
+        assert!(vips
+            .check(Some("J\u{043e}hn Smith"), Some("x@evil.test"))
+            .is_some());
+        // Lookalike domain and lookalike address.
+        assert!(vips.check(None, Some("finance@acrne.com")).is_some());
+        assert!(vips.check(None, Some("mary.j0nes@acme.com")).is_some());
+        // Unrelated senders.
+        assert!(vips
+            .check(Some("Bob Brown"), Some("bob@acme.com"))
+            .is_none());
+        assert!(vips
+            .check(Some("Alice"), Some("alice@example.org"))
+            .is_none());
+    }
+}
@@ -0,0 +1,442 @@
+//! `--serve :8080`: the extractor as a small HTTP service.
+//!
+//! On-prem deployments without SQS or Batch run one long-lived process and submit jobs to it.
+//! `POST /jobs` takes a JSON object of per-job fields ([`JOB_FIELDS`]: the PST's identity, its
+//! source key and output prefix), overlaid on the server's own arguments as in batch mode, and
+//! answers 202 with the job's id. Any other field is refused with 400: paths, executables and
+//! callback URLs stay under the operator's control. `GET /jobs/{id}` reports the job's status,
+//! live progress and, once it has finished, its outcome. `GET /healthz` answers 200 for probes.
+//!
+//! Both job routes require `Authorization: Bearer <--serve-token>`. A bare `:port` listens on
+//! loopback only; give an explicit address (`0.0.0.0:8080`) to accept remote clients.
+//!
+//! Jobs run `--jobs-concurrency` at a time, each on its own thread, in submission order. A job
+//! whose `pst_file_id` is already queued or running is refused with 409. Job state is kept in
+//! memory only; the manifest and `--callback-url` remain the durable record. On SIGTERM the
+//! server stops accepting jobs, running jobs wind down as usual, queued ones are `skipped`, and
+//! the process exits.
+
+use crate::batch::{self, JobResult};
+use crate::progress::{self, ProgressEvent};
+use crate::{rules::RuleSet, run_job, watchdog, Args};
+use anyhow::{Context, Result};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Fields a `POST /jobs` body may set.
+const JOB_FIELDS: &[&str] = &[
+    "pst_file_id",
+    "project_id",
+    "case_id",
+    "custodian_id",
+    "custodian_name",
+    "source_key",
+    "input_format",
+    "output_prefix",
+];
+
+struct Job {
+    pst_file_id: String,
+    /// `queued`, `running`, then the outcome's status.
+    status: &'static str,
+    submitted_epoch: u64,
+    progress: progress::Slot,
+    result: Option<JobResult>,
+}
+
+/// What `GET /jobs/{id}` returns.
+#[derive(Serialize)]
+struct JobView<'a> {
+    id: &'a str,
+    pst_file_id: &'a str,
+    status: &'static str,
+    submitted_epoch: u64,
+    progress: Option<ProgressEvent>,
+    result: Option<&'a JobResult>,
+}
+
+struct Jobs {
+    base: Args,
+    token: String,
+    jobs: Mutex<HashMap<String, Job>>,
+    /// Dropped at shutdown, which ends the runner threads once the queue is drained.
+    queue: Mutex<Option<Sender<(String, Args)>>>,
+}
+
+impl Jobs {
+    fn new(base: Args, token: String) -> (Arc<Self>, Receiver<(String, Args)>) {
+        let (sender, receiver) = mpsc::channel();
+        let jobs = Self {
+            base,
+            token,
+            jobs: Mutex::new(HashMap::new()),
+            queue: Mutex::new(Some(sender)),
+        };
+        (Arc::new(jobs), receiver)
+    }
+
+    fn submit(&self, fields: &Map<String, Value>) -> Result<String, (StatusCode, String)> {
+        if watchdog::terminating() {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "shutting down".into()));
+        }
+        if let Some(field) = fields.keys().find(|k| !JOB_FIELDS.contains(&k.as_str())) {
+            let message = format!("field {field:?} can't be set per job");
+            return Err((StatusCode::BAD_REQUEST, message));
+        }
+        let args = batch::job_args(&self.base, fields)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+        let mut jobs = self.jobs.lock().expect("jobs");
+        let active = jobs.values().any(|job| {
+            job.pst_file_id == args.pst_file_id && matches!(job.status, "queued" | "running")
+        });
+        if active {
+            let message = format!(
+                "pst_file_id {} is already queued or running",
+                args.pst_file_id
+            );
+            return Err((StatusCode::CONFLICT, message));
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        let queue = self.queue.lock().expect("job queue");
+        let sent = queue
+            .as_ref()
+            .is_some_and(|q| q.send((id.clone(), args.clone())).is_ok());
+        if !sent {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "shutting down".into()));
+        }
+        jobs.insert(
+            id.clone(),
+            Job {
+                pst_file_id: args.pst_file_id,
+                status: "queued",
+                submitted_epoch: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+                progress: progress::Slot::default(),
+                result: None,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Mark the job running and hand back its progress slot.
+    fn start(&self, id: &str) -> progress::Slot {
+        let mut jobs = self.jobs.lock().expect("jobs");
+        let job = jobs.get_mut(id).expect("submitted job");
+        job.status = "running";
+        Arc::clone(&job.progress)
+    }
+
+    fn finish(&self, id: &str, result: JobResult) {
+        let mut jobs = self.jobs.lock().expect("jobs");
+        let job = jobs.get_mut(id).expect("submitted job");
+        job.status = result.status;
+        job.result = Some(result);
+    }
+
+    /// Whether the request carries the server's bearer token.
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let presented = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or("");
+        // Compared in constant time, so the token can't be guessed byte by byte.
+        presented.len() == self.token.len()
+            && presented
+                .bytes()
+                .zip(self.token.bytes())
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    fn view(&self, id: &str) -> Option<Value> {
+        let jobs = self.jobs.lock().expect("jobs");
+        let job = jobs.get(id)?;
+        let progress = job.progress.lock().expect("progress slot");
+        let view = JobView {
+            id,
+            pst_file_id: &job.pst_file_id,
+            status: job.status,
+            submitted_epoch: job.submitted_epoch,
+            progress: progress.as_ref().map(|p| p.snapshot()),
+            result: job.result.as_ref(),
+        };
+        serde_json::to_value(view).ok()
+    }
+}
+
+fn router(jobs: Arc<Jobs>) -> Router {
+    Router::new()
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/jobs", post(submit))
+        .route("/jobs/{id}", get(status))
+        .with_state(jobs)
+}
+
+fn unauthorized() -> Response {
+    let body = Json(json!({"error": "missing or wrong bearer token"}));
+    (StatusCode::UNAUTHORIZED, body).into_response()
+}
+
+async fn submit(
+    State(jobs): State<Arc<Jobs>>,
+    headers: HeaderMap,
+    Json(fields): Json<Map<String, Value>>,
+) -> Response {
+    if !jobs.authorized(&headers) {
+        return unauthorized();
+    }
+    match jobs.submit(&fields) {
+        Ok(id) => {
+            info!(%id, "job queued");
+            let body = json!({"id": id, "status": "queued"});
+            (StatusCode::ACCEPTED, Json(body)).into_response()
+        }
+        Err((code, error)) => (code, Json(json!({ "error": error }))).into_response(),
+    }
+}
+
+async fn status(
+    State(jobs): State<Arc<Jobs>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    if !jobs.authorized(&headers) {
+        return unauthorized();
+    }
+    match jobs.view(&id) {
+        Some(view) => Json(view).into_response(),
+        None => (StatusCode::NOT_FOUND, Json(json!({"error": "no such job"}))).into_response(),
+    }
+}
+
+/// `:8080` means loopback only.
+fn listen_addr(addr: &str) -> String {
+    match addr.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{port}"),
+        None => addr.to_string(),
+    }
+}
+
+/// Run jobs from the queue until it closes.
+fn runner(
+    jobs: Arc<Jobs>,
+    queue: Arc<Mutex<Receiver<(String, Args)>>>,
+    cfg: aws_config::SdkConfig,
+    s3: aws_sdk_s3::Client,
+    rules: Option<Arc<RuleSet>>,
+    handle: tokio::runtime::Handle,
+) {
+    loop {
+        let Ok((id, args)) = queue.lock().expect("job queue").recv() else {
+            break;
+        };
+        let result = if watchdog::terminating() {
+            JobResult::skipped(&args)
+        } else {
+            let slot = jobs.start(&id);
+            let job = run_job(&args, &cfg, &s3, rules.as_deref());
+            JobResult::new(&args, handle.block_on(progress::observed(slot, job)))
+        };
+        jobs.finish(&id, result);
+    }
+}
+
+/// Serve the job API on `addr` until SIGTERM, then wait for the runners to wind down.
+pub async fn run(
+    addr: &str,
+    base: &Args,
+    cfg: aws_config::SdkConfig,
+    s3: aws_sdk_s3::Client,
+    rules: Option<RuleSet>,
+) -> Result<()> {
+    let token = base
+        .serve_token
+        .clone()
+        .filter(|t| !t.is_empty())
+        .context("--serve requires --serve-token (SERVE_TOKEN)")?;
+    let addr = listen_addr(addr);
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("listen on {addr}"))?;
+    let (jobs, queue) = Jobs::new(base.clone(), token);
+    let queue = Arc::new(Mutex::new(queue));
+    let rules = rules.map(Arc::new);
+    let runners: Vec<_> = (0..base.jobs_concurrency.max(1))
+        .map(|_| {
+            let (jobs, queue) = (Arc::clone(&jobs), Arc::clone(&queue));
+            let (cfg, s3, rules) = (cfg.clone(), s3.clone(), rules.clone());
+            let handle = tokio::runtime::Handle::current();
+            std::thread::spawn(move || runner(jobs, queue, cfg, s3, rules, handle))
+        })
+        .collect();
+    info!(%addr, concurrency = runners.len(), "serving job API");
+
+    let shutdown = async {
+        while !watchdog::terminating() {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    };
+    let served = axum::serve(listener, router(Arc::clone(&jobs)))
+        .with_graceful_shutdown(shutdown)
+        .await;
+    jobs.queue.lock().expect("job queue").take();
+    tokio::task::block_in_place(|| {
+        for runner in runners {
+            runner.join().ok();
+        }
+    });
+    Ok(served?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn queues_jobs_and_reports_status() {
+        let base = Args::parse_from([
+            "pst-extractor",
+            "--serve",
+            ":0",
+            "--serve-token",
+            "s3cret",
+            "--source-bucket",
+            "in",
+            "--output-bucket",
+            "out",
+        ]);
+        let token = base.serve_token.clone().expect("token");
+        let (jobs, queue) = Jobs::new(base, token);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let url = format!("http://{}", listener.local_addr().expect("addr"));
+        let app = router(Arc::clone(&jobs));
+        let server = tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut auth = reqwest::header::HeaderMap::new();
+        auth.insert(
+            reqwest::header::AUTHORIZATION,
+            "Bearer s3cret".parse().expect("header"),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(auth)
+            .build()
+            .expect("client");
+
+        let health = client
+            .get(format!("{url}/healthz"))
+            .send()
+            .await
+            .expect("get");
+        assert_eq!(health.status(), 200);
+
+        let job = json!({"pst_file_id": "p1", "source_key": "a.pst", "output_prefix": "x/p1/"});
+        let resp = client.post(format!("{url}/jobs")).json(&job).send().await;
+        let resp = resp.expect("post");
+        assert_eq!(resp.status(), 202);
+        let id = resp.json::<Value>().await.expect("json")["id"]
+            .as_str()
+            .expect("id")
+            .to_string();
+        let (queued_id, args) = queue.recv().expect("queued");
+        assert_eq!(
+            (queued_id.as_str(), args.source_bucket.as_str()),
+            (id.as_str(), "in")
+        );
+
+        // The same PST can't be queued twice while the first is pending.
+        let resp = client.post(format!("{url}/jobs")).json(&job).send().await;
+        assert_eq!(resp.expect("post").status(), 409);
+        let resp = client
+            .post(format!("{url}/jobs"))
+            .json(&json!({"pst_file_id": "p2"}))
+            .send()
+            .await;
+        assert_eq!(resp.expect("post").status(), 400);
+        // Only the per-job fields can be set.
+        for field in [
+            json!({"readpst_path": "/bin/sh"}),
+            json!({"source_path": "/etc/passwd"}),
+            json!({"callback_url": "http://169.254.169.254/"}),
+        ] {
+            let mut body = job.clone();
+            body.as_object_mut()
+                .expect("object")
+                .extend(field.as_object().expect("object").clone());
+            body["pst_file_id"] = json!("p3");
+            let resp = client.post(format!("{url}/jobs")).json(&body).send().await;
+            assert_eq!(resp.expect("post").status(), 400, "{field}");
+        }
+        // No token, or the wrong one.
+        let anonymous = reqwest::Client::new();
+        let resp = anonymous
+            .post(format!("{url}/jobs"))
+            .json(&job)
+            .send()
+            .await;
+        assert_eq!(resp.expect("post").status(), 401);
+        let resp = anonymous
+            .get(format!("{url}/jobs/{id}"))
+            .bearer_auth("s3cre7")
+            .send()
+            .await;
+        assert_eq!(resp.expect("get").status(), 401);
+
+        let get = |id: String| client.get(format!("{url}/jobs/{id}")).send();
+        let view: Value = get(id.clone())
+            .await
+            .expect("get")
+            .json()
+            .await
+            .expect("json");
+        assert_eq!(view["status"], "queued");
+        assert!(view["progress"].is_null());
+
+        let slot = jobs.start(&id);
+        let running = Arc::new(progress::Progress::new("p1"));
+        *slot.lock().expect("slot") = Some(Arc::clone(&running));
+        running.set_phase(progress::Phase::Parse);
+        let view: Value = get(id.clone())
+            .await
+            .expect("get")
+            .json()
+            .await
+            .expect("json");
+        assert_eq!(view["status"], "running");
+        assert_eq!(view["progress"]["phase"], "parse");
+
+        jobs.finish(
+            &id,
+            JobResult::new(&args, Err(anyhow::anyhow!("no such key"))),
+        );
+        let view: Value = get(id.clone())
+            .await
+            .expect("get")
+            .json()
+            .await
+            .expect("json");
+        assert_eq!(view["status"], "failed");
+        assert_eq!(view["result"]["error"], "no such key");
+        let resp = client.post(format!("{url}/jobs")).json(&job).send().await;
+        assert_eq!(resp.expect("post").status(), 202);
+
+        assert_eq!(get("nope".into()).await.expect("get").status(), 404);
+        assert_eq!(listen_addr(":8080"), "127.0.0.1:8080");
+        assert_eq!(listen_addr("0.0.0.0:8080"), "0.0.0.0:8080");
+        server.abort();
+    }
+}
@@ -0,0 +1,256 @@
+//! Content-based MIME type detection for attachments.
+//!
+//! The `Content-Type` on an attachment part is whatever the sending client chose, often
+//! `application/octet-stream`. This module identifies the common formats in a mailbox from their
+//! leading bytes. ZIP and OLE2 containers are looked into so that Office documents are told apart
+//! from plain archives. It also checks whether the filename's extension fits the detected type.
+
+use crate::cfb::CompoundFile;
+use memchr::memmem;
+
+/// Signature prefixes: (offset, magic bytes, MIME type).
+const MAGIC: &[(usize, &[u8], &str)] = &[
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xFF\xD8\xFF", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"II*\0", "image/tiff"),
+    (0, b"MM\0*", "image/tiff"),
+    (0, b"\0\0\x01\0", "image/vnd.microsoft.icon"),
+    (0, b"{\\rtf", "application/rtf"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"BZh", "application/x-bzip2"),
+    (0, b"7z\xBC\xAF\x27\x1C", "application/x-7z-compressed"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    (0, b"\xFD7zXZ\0", "application/x-xz"),
+    (0, b"\x78\x9f\x3e\x22", "application/ms-tnef"),
+    (0, b"MZ", "application/x-msdownload"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"BEGIN:VCALENDAR", "text/calendar"),
+    (0, b"BEGIN:VCARD", "text/vcard"),
+    (0, b"-----BEGIN PGP", "application/pgp-encrypted"),
+    (4, b"ftypqt", "video/quicktime"),
+    (4, b"ftypM4A", "audio/mp4"),
+    (4, b"ftyp", "video/mp4"),
+    (8, b"WEBP", "image/webp"),
+    (8, b"AVI ", "video/x-msvideo"),
+    (8, b"WAVE", "audio/wav"),
+];
+
+const ZIP: &str = "application/zip";
+const OLE2: &str = "application/x-ole-storage";
+
+/// Detected MIME type, or None when nothing matched.
+pub fn detect(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        return Some(zip_kind(data));
+    }
+    if crate::cfb::is_compound_file(data) {
+        return Some(ole_kind(data));
+    }
+    // "BM" alone is too common at the start of text; also require the zeroed reserved fields.
+    if data.starts_with(b"BM") && data.get(6..10) == Some(&[0; 4]) {
+        return Some("image/bmp");
+    }
+    if let Some((_, _, mime)) = MAGIC
+        .iter()
+        .find(|(at, magic, _)| data.get(*at..).is_some_and(|d| d.starts_with(magic)))
+    {
+        return Some(mime);
+    }
+    text_kind(data)
+}
+
+/// OOXML / OpenDocument / EPUB are ZIPs; tell them apart by their well-known entry names, which
+/// sit in local headers near the start of the file.
+fn zip_kind(data: &[u8]) -> &'static str {
+    let head = &data[..data.len().min(64 * 1024)];
+    if head.get(30..38) == Some(b"mimetype") {
+        let value = &head[38..head.len().min(38 + 80)];
+        for (tag, mime) in [
+            (
+                &b"application/vnd.oasis.opendocument.text"[..],
+                "application/vnd.oasis.opendocument.text",
+            ),
+            (
+                b"application/vnd.oasis.opendocument.spreadsheet",
+                "application/vnd.oasis.opendocument.spreadsheet",
+            ),
+            (
+                b"application/vnd.oasis.opendocument.presentation",
+                "application/vnd.oasis.opendocument.presentation",
+            ),
+            (b"application/epub+zip", "application/epub+zip"),
+        ] {
+            if memmem::find(value, tag).is_some() {
+                return mime;
+            }
+        }
+    }
+    for (entry, mime) in [
+        (
+            &b"word/"[..],
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        ),
+        (
+            b"xl/",
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        ),
+        (
+            b"ppt/",
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        ),
+        (b"visio/", "application/vnd.ms-visio.drawing"),
+    ] {
+        if memmem::find(head, entry).is_some() {
+            return mime;
+        }
+    }
+    ZIP
+}
+
+/// Legacy Office formats and Outlook items are OLE2 compound files; the root streams say which.
+fn ole_kind(data: &[u8]) -> &'static str {
+    let Some(cf) = CompoundFile::parse(data) else {
+        return OLE2;
+    };
+    let root = cf.root();
+    let has = |name: &str| cf.child(root, name).is_some();
+    if has("WordDocument") {
+        "application/msword"
+    } else if has("Workbook") || has("Book") {
+        "application/vnd.ms-excel"
+    } else if has("PowerPoint Document") {
+        "application/vnd.ms-powerpoint"
+    } else if has("__properties_version1.0") {
+        "application/vnd.ms-outlook"
+    } else if has("VisioDocument") {
+        "application/vnd.visio"
+    } else {
+        OLE2
+    }
+}
+
+/// Markup and plain text, judged from the first few KiB.
+fn text_kind(data: &[u8]) -> Option<&'static str> {
+    let head = &data[..data.len().min(4096)];
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // A multi-byte character cut at the window edge.
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    let trimmed = text.trim_start_matches('\u{feff}').trim_start();
+    let lower = trimmed.get(..trimmed.len().min(256))?.to_ascii_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        return Some("text/html");
+    }
+    if lower.starts_with("<?xml") {
+        return Some("application/xml");
+    }
+    let control = text
+        .chars()
+        .filter(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0c'))
+        .count();
+    (!text.is_empty() && control * 100 <= text.len()).then_some("text/plain")
+}
+
+/// Extensions that fit a detected type. Types not listed here (and plain text, which too many
+/// extensions legitimately hold) never count as a mismatch.
+fn extensions(mime: &str) -> Option<&'static [&'static str]> {
+    Some(match mime {
+        "application/pdf" => &["pdf"],
+        "image/png" => &["png"],
+        "image/jpeg" => &["jpg", "jpeg", "jpe", "jfif"],
+        "image/gif" => &["gif"],
+        "image/bmp" => &["bmp", "dib"],
+        "image/tiff" => &["tif", "tiff"],
+        "image/webp" => &["webp"],
+        "application/rtf" => &["rtf", "doc"],
+        "application/gzip" => &["gz", "tgz"],
+        "application/x-7z-compressed" => &["7z"],
+        "application/vnd.rar" => &["rar"],
+        "application/ms-tnef" => &["dat", "tnef"],
+        "application/x-msdownload" => &["exe", "dll", "sys", "scr", "com", "cpl", "ocx"],
+        ZIP => &["zip", "zipx", "jar", "apk", "kmz", "xpi"],
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+            &["docx", "docm", "dotx", "dotm"]
+        }
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => {
+            &["xlsx", "xlsm", "xltx", "xltm", "xlsb"]
+        }
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation" => {
+            &["pptx", "pptm", "potx", "ppsx"]
+        }
+        "application/msword" => &["doc", "dot"],
+        "application/vnd.ms-excel" => &["xls", "xlt", "xla"],
+        "application/vnd.ms-powerpoint" => &["ppt", "pps", "pot"],
+        "application/vnd.ms-outlook" => &["msg", "oft"],
+        "application/vnd.oasis.opendocument.text" => &["odt"],
+        "application/vnd.oasis.opendocument.spreadsheet" => &["ods"],
+        "application/vnd.oasis.opendocument.presentation" => &["odp"],
+        "text/calendar" => &["ics", "vcs"],
+        "text/vcard" => &["vcf"],
+        _ => return None,
+    })
+}
+
+/// True when `filename` has an extension and it doesn't fit the detected type.
+pub fn extension_mismatch(filename: &str, detected: &str) -> bool {
+    let Some((_, ext)) = filename.rsplit_once('.') else {
+        return false;
+    };
+    let ext = ext.to_ascii_lowercase();
+    if ext.is_empty() || ext.len() > 8 {
+        return false;
+    }
+    extensions(detected).is_some_and(|allowed| !allowed.contains(&ext.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_by_content_not_name() {
+        assert_eq!(detect(b"%PDF-1.7\n..."), Some("application/pdf"));
+        assert_eq!(detect(b"\xFF\xD8\xFF\xE0\0\x10JFIF"), Some("image/jpeg"));
+        assert_eq!(detect(b"\0\0\0\x18ftypmp42"), Some("video/mp4"));
+        assert_eq!(detect(b"<!DOCTYPE html><html>"), Some("text/html"));
+        assert_eq!(
+            detect(b"Site diary, 4 March\r\nPour delayed.\r\n"),
+            Some("text/plain")
+        );
+        assert_eq!(detect(&[0u8, 1, 2, 3, 4, 5, 6, 7, 8]), None);
+
+        let docx = crate::ziparchive::build(&[
+            ("[Content_Types].xml", b"<Types/>"),
+            ("word/document.xml", b"<w:document/>"),
+        ]);
+        let docx_type = detect(&docx).expect("docx");
+        assert!(docx_type.ends_with("wordprocessingml.document"));
+        assert_eq!(
+            detect(&crate::ziparchive::build(&[("a.txt", b"x")])),
+            Some(ZIP)
+        );
+
+        let doc = crate::cfb::build(&[crate::cfb::TestNode::Stream("WordDocument", vec![0; 64])]);
+        assert_eq!(detect(&doc), Some("application/msword"));
+    }
+
+    #[test]
+    fn flags_extensions_that_disagree() {
+        assert!(extension_mismatch(
+            "invoice.pdf",
+            "application/x-msdownload"
+        ));
+        assert!(!extension_mismatch("Invoice.PDF", "application/pdf"));
+        assert!(!extension_mismatch("notes.csv", "text/plain"));
+        assert!(!extension_mismatch("no-extension", "application/pdf"));
+        assert!(extension_mismatch("programme.xlsx", "application/msword"));
+    }
+}
@@ -0,0 +1,161 @@
+//! Early-case-assessment numbers for the manifest (`stats`).
+//!
+//! Case managers scope review from the manifest before anything is loaded: where the mail was
+//! filed, the period it covers, who it was exchanged with and what was attached. Folder, month
+//! and domain counts are over top-level emails (embedded copies would count a message twice);
+//! attachment counts are over every attachment record, stored or not.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Sender and recipient domains listed in the manifest; the rest are summed into `other`.
+pub const TOP_DOMAINS: usize = 25;
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct DomainCount {
+    pub domain: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct DomainBreakdown {
+    pub top: Vec<DomainCount>,
+    /// Count across the domains not in `top`.
+    pub other: u64,
+    pub distinct: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct CaseStats {
+    /// Top-level emails per source folder (readpst folder path; `""` for the root).
+    pub folders: BTreeMap<String, u64>,
+    /// Top-level emails per `YYYY-MM` of date_epoch, plus `unknown`.
+    pub emails_by_month: BTreeMap<String, u64>,
+    /// Senders' domains, and recipients' (To, Cc and Bcc; one count per recipient address).
+    pub sender_domains: DomainBreakdown,
+    pub recipient_domains: DomainBreakdown,
+    /// Attachments per lowercased filename extension (`none` without one).
+    pub attachment_types: BTreeMap<String, u64>,
+    pub attachment_bytes_total: u64,
+}
+
+#[derive(Default)]
+pub struct StatsCollector {
+    stats: CaseStats,
+    senders: HashMap<String, u64>,
+    recipients: HashMap<String, u64>,
+}
+
+impl StatsCollector {
+    pub fn add_email<'a>(
+        &mut self,
+        source_path: &str,
+        date_epoch: Option<i64>,
+        sender: Option<&str>,
+        recipients: impl IntoIterator<Item = &'a String>,
+    ) {
+        let folder = source_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        *self.stats.folders.entry(folder.to_string()).or_default() += 1;
+        *self
+            .stats
+            .emails_by_month
+            .entry(crate::aggregate::month(date_epoch))
+            .or_default() += 1;
+        if let Some(domain) = sender.and_then(domain_of) {
+            *self.senders.entry(domain).or_default() += 1;
+        }
+        for domain in recipients.into_iter().filter_map(|r| domain_of(r)) {
+            *self.recipients.entry(domain).or_default() += 1;
+        }
+    }
+
+    pub fn add_attachment(&mut self, filename: &str, size: u64) {
+        *self
+            .stats
+            .attachment_types
+            .entry(crate::aggregate::attachment_type(filename))
+            .or_default() += 1;
+        self.stats.attachment_bytes_total += size;
+    }
+
+    pub fn finish(mut self) -> CaseStats {
+        self.stats.sender_domains = breakdown(self.senders);
+        self.stats.recipient_domains = breakdown(self.recipients);
+        self.stats
+    }
+}
+
+fn domain_of(address: &str) -> Option<String> {
+    let (_, domain) = address.trim().rsplit_once('@')?;
+    let domain = domain.trim_end_matches('>').trim().to_ascii_lowercase();
+    (!domain.is_empty()).then_some(domain)
+}
+
+/// Most frequent first, ties by name.
+fn breakdown(counts: HashMap<String, u64>) -> DomainBreakdown {
+    let distinct = counts.len();
+    let mut sorted: Vec<_> = counts.into_iter().collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let other = sorted.iter().skip(TOP_DOMAINS).map(|(_, n)| n).sum();
+    sorted.truncate(TOP_DOMAINS);
+    DomainBreakdown {
+        top: sorted
+            .into_iter()
+            .map(|(domain, count)| DomainCount { domain, count })
+            .collect(),
+        other,
+        distinct,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_folder_month_domain_and_attachment_counts() {
+        let mut collector = StatsCollector::default();
+        let to = ["a@Example.com".to_string(), "b@example.com".to_string()];
+        // 2021-03-01 and 2021-04-15 UTC.
+        collector.add_email("Inbox/1.eml", Some(1614556800), Some("x@corp.com"), &to);
+        collector.add_email(
+            "Inbox/2.eml",
+            Some(1618444800),
+            Some("y@corp.com"),
+            &to[..1],
+        );
+        collector.add_email("top.eml", None, None, &[]);
+        for i in 0..TOP_DOMAINS + 2 {
+            collector.add_email(
+                "Sent Items/3.eml",
+                None,
+                Some(&format!("z@d{i:02}.org")),
+                &[],
+            );
+        }
+        collector.add_attachment("Report.PDF", 1000);
+        collector.add_attachment("noext", 24);
+        let stats = collector.finish();
+
+        assert_eq!(stats.folders["Inbox"], 2);
+        assert_eq!(stats.folders[""], 1);
+        assert_eq!(stats.folders["Sent Items"], TOP_DOMAINS as u64 + 2);
+        assert_eq!(stats.emails_by_month["2021-03"], 1);
+        assert_eq!(stats.emails_by_month["2021-04"], 1);
+        assert_eq!(stats.emails_by_month["unknown"], TOP_DOMAINS as u64 + 3);
+        assert_eq!(
+            stats.recipient_domains.top,
+            [DomainCount {
+                domain: "example.com".into(),
+                count: 3
+            }]
+        );
+        assert_eq!(stats.sender_domains.top[0].domain, "corp.com");
+        assert_eq!(stats.sender_domains.top.len(), TOP_DOMAINS);
+        assert_eq!(stats.sender_domains.distinct, TOP_DOMAINS + 3);
+        assert_eq!(stats.sender_domains.other, 3);
+        assert_eq!(stats.attachment_types["pdf"], 1);
+        assert_eq!(stats.attachment_types["none"], 1);
+        assert_eq!(stats.attachment_bytes_total, 1024);
+    }
+}
@@ -0,0 +1,1471 @@
+//! Object storage backends, selected by the bucket's URI scheme.
+//!
+//! A bucket (`SOURCE_BUCKET`, `OUTPUT_BUCKET`, or the bucket part of an input URI such as
+//! `TERMS_FILE`) is one of:
+//!
+//! * `name` or `s3://name` – Amazon S3 (or `S3_ENDPOINT_URL`), through the AWS SDK;
+//! * `az://account/container` – Azure Blob Storage, authenticated with `AZURE_STORAGE_SAS_TOKEN`
+//!   or `AZURE_STORAGE_KEY` (Shared Key). `AZURE_STORAGE_ENDPOINT` replaces
+//!   `https://{account}.blob.core.windows.net` (Azurite: `http://127.0.0.1:10000/devstoreaccount1`);
+//! * `gs://bucket` – Google Cloud Storage (JSON API), authenticated with `GCS_ACCESS_TOKEN` (an
+//!   OAuth access token) or else the GCE/GKE metadata server. `GCS_ENDPOINT` replaces
+//!   `https://storage.googleapis.com`; an emulator there needs no token.
+//!
+//! `--output-dir` is one more backend, [`LocalStore`]. Every backend resumes downloads that break
+//! off and retries throttling, 5xx and network errors up to `S3_MAX_ATTEMPTS` times. Files above
+//! `UPLOAD_PART_SIZE` go up in parts (S3 multipart uploads, Azure blocks, GCS resumable-upload
+//! chunks) within the shared `UPLOAD_CONCURRENCY` pool. `KMS_KEY_ID`, `STORAGE_CLASS` and
+//! `OBJECT_TAGS` are S3 settings; Azure containers and GCS buckets apply their own defaults.
+
+use crate::progress::Progress;
+use crate::{diskspace, upload, ObjectMeta};
+use anyhow::{anyhow, Context, Result};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+const AZURE_API_VERSION: &str = "2021-08-06";
+/// GCS resumable-upload chunks must be a multiple of this.
+const GCS_CHUNK_ALIGN: u64 = 256 << 10;
+
+/// One bucket (or container) of some object store.
+pub trait ObjectStore: Send + Sync {
+    /// `s3://bucket/key`-style URL of `key`, for logs and errors.
+    fn url(&self, key: &str) -> String;
+
+    /// Download `key` to `dest`, failing up front if it won't fit in scratch space.
+    fn get<'a>(
+        &'a self,
+        key: &'a str,
+        dest: &'a Path,
+        progress: Option<&'a Progress>,
+        min_free_bytes: u64,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Keys under `prefix`.
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>>;
+
+    /// Store a local file as `key`; returns its size in bytes.
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        meta: &'a ObjectMeta,
+    ) -> BoxFuture<'a, Result<u64>>;
+
+    /// Whether `key` exists (a HEAD request).
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool>>;
+}
+
+/// Azure and GCS stores are built once per bucket and reused (they hold an HTTP client and, for
+/// GCS, a cached token).
+static HTTP_STORES: OnceLock<Mutex<HashMap<String, Arc<dyn ObjectStore>>>> = OnceLock::new();
+
+/// The store for a bucket: `name` / `s3://name`, `az://account/container` or `gs://bucket`.
+pub fn open(s3: &aws_sdk_s3::Client, bucket: &str) -> Result<Arc<dyn ObjectStore>> {
+    let max_attempts = s3.config().retry_config().map_or(1, |r| r.max_attempts());
+    let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let build = |bucket: &str| -> Result<Arc<dyn ObjectStore>> {
+        if let Some(rest) = bucket.strip_prefix("az://") {
+            Ok(Arc::new(AzureStore::from_env(rest, max_attempts, env)?))
+        } else if let Some(rest) = bucket.strip_prefix("gs://") {
+            Ok(Arc::new(GcsStore::from_env(rest, max_attempts, env)?))
+        } else {
+            Err(anyhow!(
+                "unsupported storage URI {bucket:?} (use s3://, az:// or gs://)"
+            ))
+        }
+    };
+    if !bucket.contains("://") || bucket.starts_with("s3://") {
+        let name = bucket.trim_start_matches("s3://").trim_end_matches('/');
+        return Ok(Arc::new(S3Store {
+            client: s3.clone(),
+            bucket: name.to_string(),
+        }));
+    }
+    let bucket = bucket.trim_end_matches('/');
+    let mut stores = HTTP_STORES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(store) = stores.get(bucket) {
+        return Ok(Arc::clone(store));
+    }
+    let store = build(bucket)?;
+    stores.insert(bucket.to_string(), Arc::clone(&store));
+    Ok(store)
+}
+
+/// Split an object URI (`s3://bucket/key`, `az://account/container/key`, `gs://bucket/key`) into
+/// the bucket [`open`] takes and the key. None for anything else (a local path).
+pub fn split_uri(uri: &str) -> Option<Result<(String, String)>> {
+    let (scheme, rest) = uri.split_once("://")?;
+    let segments = match scheme {
+        "s3" | "gs" => 1,
+        "az" => 2,
+        _ => return None,
+    };
+    let mut parts = rest.splitn(segments + 1, '/');
+    let bucket: Vec<&str> = parts.by_ref().take(segments).collect();
+    let key = parts.next().unwrap_or("");
+    if bucket.len() < segments || bucket.iter().any(|b| b.is_empty()) || key.is_empty() {
+        return Some(Err(anyhow!("invalid object URI {uri}")));
+    }
+    let bucket = match scheme {
+        "s3" => bucket[0].to_string(),
+        _ => format!("{scheme}://{}", bucket.join("/")),
+    };
+    Some(Ok((bucket, key.to_string())))
+}
+
+pub struct S3Store {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+impl ObjectStore for S3Store {
+    fn url(&self, key: &str) -> String {
+        format!("s3://{}/{key}", self.bucket)
+    }
+
+    fn get<'a>(
+        &'a self,
+        key: &'a str,
+        dest: &'a Path,
+        progress: Option<&'a Progress>,
+        min_free_bytes: u64,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(download_s3(
+            &self.client,
+            &self.bucket,
+            key,
+            dest,
+            progress,
+            min_free_bytes,
+        ))
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            let mut pages = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .into_paginator()
+                .send();
+            while let Some(page) = pages.next().await {
+                let page = page.with_context(|| format!("list {}", self.url(prefix)))?;
+                keys.extend(
+                    page.contents()
+                        .iter()
+                        .filter_map(|o| o.key().map(str::to_string)),
+                );
+            }
+            Ok(keys)
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        meta: &'a ObjectMeta,
+    ) -> BoxFuture<'a, Result<u64>> {
+        Box::pin(upload::put_s3(&self.client, &self.bucket, key, path, meta))
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let head = self
+                .client
+                .head_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .send()
+                .await;
+            match head {
+                Ok(_) => Ok(true),
+                Err(e) if e.as_service_error().is_some_and(|se| se.is_not_found()) => Ok(false),
+                Err(e) => Err(e).with_context(|| format!("head {}", self.url(key))),
+            }
+        })
+    }
+}
+
+async fn download_s3(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    progress: Option<&Progress>,
+    min_free_bytes: u64,
+) -> Result<()> {
+    use tokio::io::AsyncReadExt;
+
+    let obj = s3
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .with_context(|| format!("download s3://{}/{}", bucket, key))?;
+    let len = obj.content_length().unwrap_or(0).max(0) as u64;
+    diskspace::ensure_free(path, len, min_free_bytes, &format!("s3://{bucket}/{key}"))?;
+    if let Some(p) = progress {
+        p.bytes_total
+            .store(len, std::sync::atomic::Ordering::Relaxed);
+    }
+    let mut reader = obj.body.into_async_read();
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("create {}", path.display()))?;
+    let max_attempts = s3.config().retry_config().map_or(1, |r| r.max_attempts());
+    let mut attempt = 1;
+    let mut written = 0u64;
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(n) => n,
+            // The SDK retries requests, not a body that breaks off: resume with a ranged GET.
+            Err(err) if attempt < max_attempts => {
+                tracing::warn!(%bucket, %key, written, attempt, "download interrupted, resuming: {err}");
+                tokio::time::sleep(crate::retry_backoff(attempt)).await;
+                attempt += 1;
+                reader = s3
+                    .get_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .range(format!("bytes={written}-"))
+                    .send()
+                    .await
+                    .with_context(|| format!("download s3://{}/{}", bucket, key))?
+                    .body
+                    .into_async_read();
+                continue;
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("download s3://{}/{}", bucket, key))
+            }
+        };
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n])
+            .await
+            .with_context(|| format!("write {}", path.display()))?;
+        written += n as u64;
+        if let Some(p) = progress {
+            Progress::add(&p.bytes_downloaded, n as u64);
+        }
+    }
+    file.flush()
+        .await
+        .with_context(|| format!("write {}", path.display()))?;
+    Ok(())
+}
+
+/// A local directory laid out like a bucket (`--output-dir`).
+pub struct LocalStore {
+    pub root: PathBuf,
+}
+
+impl LocalStore {
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key.split('/').any(|part| part == "..") {
+            return Err(anyhow!(
+                "refusing to use {key:?} outside {}",
+                self.root.display()
+            ));
+        }
+        Ok(self.root.join(key.trim_start_matches('/')))
+    }
+}
+
+fn copy_file(src: PathBuf, dest: PathBuf) -> BoxFuture<'static, Result<u64>> {
+    Box::pin(async move {
+        tokio::task::spawn_blocking(move || {
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("create {}", parent.display()))?;
+            }
+            std::fs::copy(&src, &dest)
+                .with_context(|| format!("copy {} to {}", src.display(), dest.display()))
+        })
+        .await?
+    })
+}
+
+impl ObjectStore for LocalStore {
+    fn url(&self, key: &str) -> String {
+        self.root.join(key).display().to_string()
+    }
+
+    fn get<'a>(
+        &'a self,
+        key: &'a str,
+        dest: &'a Path,
+        progress: Option<&'a Progress>,
+        min_free_bytes: u64,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let src = self.path(key)?;
+            let len = std::fs::metadata(&src)
+                .with_context(|| format!("stat {}", src.display()))?
+                .len();
+            diskspace::ensure_free(dest, len, min_free_bytes, &self.url(key))?;
+            let copied = copy_file(src, dest.to_path_buf()).await?;
+            if let Some(p) = progress {
+                Progress::add(&p.bytes_downloaded, copied);
+            }
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            for entry in walkdir::WalkDir::new(&self.root).sort_by_file_name() {
+                let entry = entry.with_context(|| format!("list {}", self.root.display()))?;
+                if entry.file_type().is_file() {
+                    let rel = entry.path().strip_prefix(&self.root)?;
+                    let key = rel.to_string_lossy().replace('\\', "/");
+                    if key.starts_with(prefix) {
+                        keys.push(key);
+                    }
+                }
+            }
+            Ok(keys)
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        _meta: &'a ObjectMeta,
+    ) -> BoxFuture<'a, Result<u64>> {
+        match self.path(key) {
+            Ok(dest) => copy_file(path.to_path_buf(), dest),
+            Err(e) => Box::pin(async move { Err(e) }),
+        }
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move { Ok(self.path(key)?.is_file()) })
+    }
+}
+
+/// HTTP client shared by the Azure and GCS stores.
+struct Http {
+    client: reqwest::Client,
+    max_attempts: u32,
+}
+
+impl Http {
+    fn new(max_attempts: u32) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(10))
+                .build()?,
+            max_attempts: max_attempts.max(1),
+        })
+    }
+
+    /// Send `req`, retrying 408, 429, 5xx and network errors with jittered backoff. Any status
+    /// other than an `accept`ed one is an error.
+    async fn send(
+        &self,
+        req: RequestBuilder,
+        what: &str,
+        accept: impl Fn(&StatusCode) -> bool,
+    ) -> Result<Response> {
+        let mut attempt = 1;
+        loop {
+            let this = req
+                .try_clone()
+                .ok_or_else(|| anyhow!("{what}: request body can't be replayed"))?;
+            match this.send().await {
+                Ok(resp) if accept(&resp.status()) => return Ok(resp),
+                Ok(resp) => {
+                    let status = resp.status();
+                    let retryable = status == StatusCode::REQUEST_TIMEOUT
+                        || status == StatusCode::TOO_MANY_REQUESTS
+                        || status.is_server_error();
+                    if !retryable || attempt >= self.max_attempts {
+                        let body = resp.text().await.unwrap_or_default();
+                        let body: String = body.chars().take(300).collect();
+                        return Err(anyhow!("{what}: HTTP {status}: {body}"));
+                    }
+                    tracing::warn!(attempt, "{what}: HTTP {status}, retrying");
+                }
+                Err(e) if attempt < self.max_attempts => {
+                    tracing::warn!(attempt, "{what}: {e}, retrying");
+                }
+                Err(e) => return Err(e).with_context(|| what.to_string()),
+            }
+            tokio::time::sleep(crate::retry_backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Stream a GET to `dest`. `open(offset)` starts the download at `offset`; a body that
+    /// breaks off is resumed from where it stopped.
+    async fn download<F, Fut>(
+        &self,
+        open: F,
+        label: &str,
+        dest: &Path,
+        progress: Option<&Progress>,
+        min_free_bytes: u64,
+    ) -> Result<()>
+    where
+        F: Fn(u64) -> Fut,
+        Fut: Future<Output = Result<Response>>,
+    {
+        let mut resp = open(0).await?;
+        let len = resp.content_length().unwrap_or(0);
+        diskspace::ensure_free(dest, len, min_free_bytes, label)?;
+        if let Some(p) = progress {
+            p.bytes_total
+                .store(len, std::sync::atomic::Ordering::Relaxed);
+        }
+        let mut file = tokio::fs::File::create(dest)
+            .await
+            .with_context(|| format!("create {}", dest.display()))?;
+        let mut attempt = 1;
+        let mut written = 0u64;
+        loop {
+            match resp.chunk().await {
+                Ok(Some(chunk)) => {
+                    file.write_all(&chunk)
+                        .await
+                        .with_context(|| format!("write {}", dest.display()))?;
+                    written += chunk.len() as u64;
+                    if let Some(p) = progress {
+                        Progress::add(&p.bytes_downloaded, chunk.len() as u64);
+                    }
+                }
+                Ok(None) => break,
+                Err(err) if attempt < self.max_attempts => {
+                    tracing::warn!(
+                        written,
+                        attempt,
+                        "download of {label} interrupted, resuming: {err}"
+                    );
+                    tokio::time::sleep(crate::retry_backoff(attempt)).await;
+                    attempt += 1;
+                    resp = open(written).await?;
+                }
+                Err(err) => return Err(err).with_context(|| format!("download {label}")),
+            }
+        }
+        file.flush()
+            .await
+            .with_context(|| format!("write {}", dest.display()))?;
+        Ok(())
+    }
+}
+
+/// Read `length` bytes of `path` from `offset`.
+async fn read_range(path: &Path, offset: u64, length: u64) -> Result<Vec<u8>> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        use std::io::{Read, Seek, SeekFrom};
+        let mut file =
+            std::fs::File::open(&path).with_context(|| format!("open {}", path.display()))?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::with_capacity(length as usize);
+        file.take(length)
+            .read_to_end(&mut buf)
+            .with_context(|| format!("read {}", path.display()))?;
+        Ok(buf)
+    })
+    .await?
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    Ok(std::fs::metadata(path)
+        .with_context(|| format!("stat {}", path.display()))?
+        .len())
+}
+
+/// Percent-encode each `/`-separated segment of an object name.
+fn encode_path(name: &str) -> String {
+    name.split('/')
+        .map(upload::url_encode)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Text of every `<tag>` element (entities decoded). Enough for Azure's flat list responses.
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{tag}>"), format!("</{tag}>"));
+    let mut out = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else { break };
+        out.push(
+            rest[..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+        rest = &rest[end + close.len()..];
+    }
+    out
+}
+
+enum AzureAuth {
+    Sas(String),
+    SharedKey(Vec<u8>),
+}
+
+/// An Azure Blob Storage container (`az://account/container`).
+pub struct AzureStore {
+    http: Http,
+    account: String,
+    container: String,
+    /// Account endpoint, without a trailing slash.
+    endpoint: String,
+    auth: AzureAuth,
+}
+
+impl AzureStore {
+    /// `env` looks up a (non-empty) environment variable.
+    fn from_env(
+        rest: &str,
+        max_attempts: u32,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let (account, container) = rest
+            .split_once('/')
+            .filter(|(a, c)| !a.is_empty() && !c.is_empty() && !c.contains('/'))
+            .ok_or_else(|| anyhow!("Azure location must be az://account/container"))?;
+        let auth = if let Some(sas) = env("AZURE_STORAGE_SAS_TOKEN") {
+            AzureAuth::Sas(sas.trim_start_matches('?').to_string())
+        } else if let Some(key) = env("AZURE_STORAGE_KEY") {
+            AzureAuth::SharedKey(
+                crate::encoded_words::base64_decode(key.trim())
+                    .ok_or_else(|| anyhow!("AZURE_STORAGE_KEY is not base64"))?,
+            )
+        } else {
+            return Err(anyhow!(
+                "az://{account}/{container} needs AZURE_STORAGE_SAS_TOKEN or AZURE_STORAGE_KEY"
+            ));
+        };
+        let endpoint = env("AZURE_STORAGE_ENDPOINT")
+            .unwrap_or_else(|| format!("https://{account}.blob.core.windows.net"));
+        Ok(Self {
+            http: Http::new(max_attempts)?,
+            account: account.to_string(),
+            container: container.to_string(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            auth,
+        })
+    }
+
+    /// A request for `blob` (or the container when None). `headers` are sent as given and
+    /// signed along with the query when authenticating with Shared Key.
+    fn request(
+        &self,
+        method: Method,
+        blob: Option<&str>,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> RequestBuilder {
+        let mut path = format!("/{}", self.container);
+        if let Some(blob) = blob {
+            path = format!("{path}/{}", encode_path(blob));
+        }
+        let mut query_string: Vec<String> = query
+            .iter()
+            .map(|(k, v)| format!("{k}={}", upload::url_encode(v)))
+            .collect();
+        if let AzureAuth::Sas(sas) = &self.auth {
+            query_string.push(sas.clone());
+        }
+        let mut url = format!("{}{path}", self.endpoint);
+        if !query_string.is_empty() {
+            url = format!("{url}?{}", query_string.join("&"));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let date = crate::dates::format_http_date(now);
+        let mut all_headers: Vec<(String, String)> = vec![
+            ("x-ms-date".into(), date),
+            ("x-ms-version".into(), AZURE_API_VERSION.into()),
+        ];
+        all_headers.extend(headers.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        let mut req = self.http.client.request(method.clone(), &url);
+        if let AzureAuth::SharedKey(key) = &self.auth {
+            let endpoint_path = self
+                .endpoint
+                .split_once("://")
+                .and_then(|(_, rest)| rest.find('/').map(|i| &rest[i..]))
+                .unwrap_or("");
+            let to_sign = shared_key_string(
+                method.as_str(),
+                body.len(),
+                &all_headers,
+                &format!("/{}{endpoint_path}{path}", self.account),
+                query,
+            );
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(to_sign.as_bytes());
+            let signature = crate::msg::base64(&mac.finalize().into_bytes());
+            req = req.header(
+                "Authorization",
+                format!("SharedKey {}:{signature}", self.account),
+            );
+        }
+        for (k, v) in &all_headers {
+            req = req.header(k.as_str(), v.as_str());
+        }
+        req.body(body)
+    }
+
+    async fn put_blocks(&self, key: &str, path: &Path, size: u64, meta: &ObjectMeta) -> Result<()> {
+        let url = self.url(key);
+        let parts = upload::plan_parts(size, upload::part_size());
+        let ids: Vec<String> = (0..parts.len())
+            .map(|i| crate::msg::base64(format!("block-{i:06}").as_bytes()))
+            .collect();
+        // Each block waits for a pool slot before reading its bytes, so this stays within
+        // --upload-concurrency requests and part buffers.
+        futures::future::try_join_all(parts.into_iter().zip(&ids).map(|((offset, length), id)| {
+            let url = &url;
+            async move {
+                let _slot = upload::slot().await?;
+                let body = read_range(path, offset, length).await?;
+                let req = self.request(
+                    Method::PUT,
+                    Some(key),
+                    &[("comp", "block"), ("blockid", id)],
+                    &[],
+                    body,
+                );
+                self.http
+                    .send(
+                        req,
+                        &format!("upload block of {url}"),
+                        StatusCode::is_success,
+                    )
+                    .await
+            }
+        }))
+        .await?;
+        let list: String = ids
+            .iter()
+            .map(|id| format!("<Latest>{id}</Latest>"))
+            .collect();
+        let body =
+            format!(r#"<?xml version="1.0" encoding="utf-8"?><BlockList>{list}</BlockList>"#);
+        let req = self.request(
+            Method::PUT,
+            Some(key),
+            &[("comp", "blocklist")],
+            &blob_headers(meta, false),
+            body.into_bytes(),
+        );
+        self.http
+            .send(req, &format!("commit {url}"), StatusCode::is_success)
+            .await?;
+        Ok(())
+    }
+}
+
+fn blob_headers(meta: &ObjectMeta, block_blob: bool) -> Vec<(&'static str, &'static str)> {
+    let mut headers = Vec::new();
+    if block_blob {
+        headers.push(("x-ms-blob-type", "BlockBlob"));
+    }
+    if let Some(content_type) = meta.content_type {
+        headers.push(("x-ms-blob-content-type", content_type));
+    }
+    if let Some(encoding) = meta.content_encoding {
+        headers.push(("x-ms-blob-content-encoding", encoding));
+    }
+    headers
+}
+
+/// Shared Key string-to-sign for the Blob service. Only `x-ms-*` and `Range` headers are used
+/// by this client, so the other standard header slots are empty.
+fn shared_key_string(
+    method: &str,
+    content_length: usize,
+    headers: &[(String, String)],
+    resource: &str,
+    query: &[(&str, &str)],
+) -> String {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map_or("", |(_, v)| v.as_str())
+    };
+    let length = if content_length == 0 {
+        String::new()
+    } else {
+        content_length.to_string()
+    };
+    let mut ms_headers: Vec<(String, &str)> = headers
+        .iter()
+        .filter(|(k, _)| k.to_ascii_lowercase().starts_with("x-ms-"))
+        .map(|(k, v)| (k.to_ascii_lowercase(), v.trim()))
+        .collect();
+    ms_headers.sort();
+    let mut out = format!(
+        "{method}\n{}\n\n{length}\n\n\n\n\n\n\n\n{}\n",
+        header("content-encoding"),
+        header("range"),
+    );
+    for (k, v) in ms_headers {
+        out.push_str(&format!("{k}:{v}\n"));
+    }
+    out.push_str(resource);
+    let mut params: Vec<(String, &str)> = query
+        .iter()
+        .map(|(k, v)| (k.to_ascii_lowercase(), *v))
+        .collect();
+    params.sort();
+    for (k, v) in params {
+        out.push_str(&format!("\n{k}:{v}"));
+    }
+    out
+}
+
+impl ObjectStore for AzureStore {
+    fn url(&self, key: &str) -> String {
+        format!("az://{}/{}/{key}", self.account, self.container)
+    }
+
+    fn get<'a>(
+        &'a self,
+        key: &'a str,
+        dest: &'a Path,
+        progress: Option<&'a Progress>,
+        min_free_bytes: u64,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let label = self.url(key);
+            let open = |offset: u64| {
+                let range = format!("bytes={offset}-");
+                let headers: Vec<(&str, &str)> = if offset > 0 {
+                    vec![("x-ms-range", range.as_str())]
+                } else {
+                    Vec::new()
+                };
+                let req = self.request(Method::GET, Some(key), &[], &headers, Vec::new());
+                let label = &label;
+                async move {
+                    self.http
+                        .send(req, &format!("download {label}"), StatusCode::is_success)
+                        .await
+                }
+            };
+            self.http
+                .download(open, &label, dest, progress, min_free_bytes)
+                .await
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            let mut marker = String::new();
+            loop {
+                let mut query = vec![
+                    ("restype", "container"),
+                    ("comp", "list"),
+                    ("prefix", prefix),
+                ];
+                if !marker.is_empty() {
+                    query.push(("marker", &marker));
+                }
+                let req = self.request(Method::GET, None, &query, &[], Vec::new());
+                let xml = self
+                    .http
+                    .send(
+                        req,
+                        &format!("list {}", self.url(prefix)),
+                        StatusCode::is_success,
+                    )
+                    .await?
+                    .text()
+                    .await?;
+                keys.extend(xml_values(&xml, "Name"));
+                match xml_values(&xml, "NextMarker").pop() {
+                    Some(next) if !next.is_empty() => marker = next,
+                    _ => return Ok(keys),
+                }
+            }
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        meta: &'a ObjectMeta,
+    ) -> BoxFuture<'a, Result<u64>> {
+        Box::pin(async move {
+            let size = file_size(path)?;
+            if size > upload::part_size() {
+                self.put_blocks(key, path, size, meta).await?;
+                return Ok(size);
+            }
+            let _slot = upload::slot().await?;
+            let body = read_range(path, 0, size).await?;
+            let req = self.request(Method::PUT, Some(key), &[], &blob_headers(meta, true), body);
+            self.http
+                .send(
+                    req,
+                    &format!("upload {}", self.url(key)),
+                    StatusCode::is_success,
+                )
+                .await?;
+            Ok(size)
+        })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let req = self.request(Method::HEAD, Some(key), &[], &[], Vec::new());
+            let resp = self
+                .http
+                .send(req, &format!("head {}", self.url(key)), |status| {
+                    status.is_success() || *status == StatusCode::NOT_FOUND
+                })
+                .await?;
+            Ok(resp.status().is_success())
+        })
+    }
+}
+
+enum GcsAuth {
+    Token(String),
+    /// GCE/GKE metadata server; the token is cached until shortly before it expires.
+    Metadata(tokio::sync::Mutex<Option<(String, Instant)>>),
+    None,
+}
+
+/// A Google Cloud Storage bucket (`gs://bucket`).
+pub struct GcsStore {
+    http: Http,
+    bucket: String,
+    endpoint: String,
+    auth: GcsAuth,
+}
+
+impl GcsStore {
+    /// `env` looks up a (non-empty) environment variable.
+    fn from_env(
+        rest: &str,
+        max_attempts: u32,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        if rest.is_empty() || rest.contains('/') {
+            return Err(anyhow!("GCS location must be gs://bucket"));
+        }
+        let endpoint = env("GCS_ENDPOINT");
+        let auth = match (env("GCS_ACCESS_TOKEN"), &endpoint) {
+            (Some(token), _) => GcsAuth::Token(token),
+            (None, Some(_)) => GcsAuth::None,
+            (None, None) => GcsAuth::Metadata(tokio::sync::Mutex::new(None)),
+        };
+        Ok(Self {
+            http: Http::new(max_attempts)?,
+            bucket: rest.to_string(),
+            endpoint: endpoint
+                .unwrap_or_else(|| "https://storage.googleapis.com".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            auth,
+        })
+    }
+
+    async fn authorize(&self, req: RequestBuilder) -> Result<RequestBuilder> {
+        let token = match &self.auth {
+            GcsAuth::Token(token) => token.clone(),
+            GcsAuth::None => return Ok(req),
+            GcsAuth::Metadata(cache) => {
+                let mut cache = cache.lock().await;
+                match &*cache {
+                    Some((token, expires)) if Instant::now() < *expires => token.clone(),
+                    _ => {
+                        let (token, expires) = self.metadata_token().await?;
+                        *cache = Some((token.clone(), expires));
+                        token
+                    }
+                }
+            }
+        };
+        Ok(req.bearer_auth(token))
+    }
+
+    async fn metadata_token(&self) -> Result<(String, Instant)> {
+        #[derive(serde::Deserialize)]
+        struct Token {
+            access_token: String,
+            expires_in: u64,
+        }
+        let req = self
+            .http
+            .client
+            .get("http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token")
+            .header("Metadata-Flavor", "Google");
+        let token: Token = self
+            .http
+            .send(
+                req,
+                "GCS token from the metadata server (set GCS_ACCESS_TOKEN outside GCP)",
+                StatusCode::is_success,
+            )
+            .await?
+            .json()
+            .await?;
+        let expires = Instant::now() + Duration::from_secs(token.expires_in.saturating_sub(60));
+        Ok((token.access_token, expires))
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!(
+            "{}/storage/v1/b/{}/o/{}",
+            self.endpoint,
+            self.bucket,
+            upload::url_encode(key)
+        )
+    }
+
+    fn upload_url(&self, key: &str, upload_type: &str) -> String {
+        format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType={upload_type}&name={}",
+            self.endpoint,
+            self.bucket,
+            upload::url_encode(key)
+        )
+    }
+
+    /// Resumable upload in chunks, one at a time (GCS takes a session's chunks in order).
+    async fn put_resumable(
+        &self,
+        key: &str,
+        path: &Path,
+        size: u64,
+        meta: &ObjectMeta,
+    ) -> Result<()> {
+        let url = self.url(key);
+        let mut metadata = serde_json::json!({});
+        if let Some(encoding) = meta.content_encoding {
+            metadata["contentEncoding"] = encoding.into();
+        }
+        let mut start = self
+            .http
+            .client
+            .post(self.upload_url(key, "resumable"))
+            .header("Content-Type", "application/json; charset=UTF-8")
+            .body(metadata.to_string());
+        if let Some(content_type) = meta.content_type {
+            start = start.header("X-Upload-Content-Type", content_type);
+        }
+        let session = self
+            .http
+            .send(
+                self.authorize(start).await?,
+                &format!("start upload of {url}"),
+                StatusCode::is_success,
+            )
+            .await?
+            .headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("no upload session for {url}"))?;
+        let chunk = upload::part_size().div_ceil(GCS_CHUNK_ALIGN) * GCS_CHUNK_ALIGN;
+        let mut offset = 0;
+        while offset < size {
+            let length = chunk.min(size - offset);
+            let _slot = upload::slot().await?;
+            let body = read_range(path, offset, length).await?;
+            let req = self
+                .http
+                .client
+                .put(&session)
+                .header(
+                    "Content-Range",
+                    format!("bytes {offset}-{}/{size}", offset + length - 1),
+                )
+                .body(body);
+            // 308 Resume Incomplete acknowledges every chunk but the last.
+            self.http
+                .send(
+                    self.authorize(req).await?,
+                    &format!("upload {url} at byte {offset}"),
+                    |s| s.is_success() || s.as_u16() == 308,
+                )
+                .await?;
+            offset += length;
+        }
+        Ok(())
+    }
+}
+
+impl ObjectStore for GcsStore {
+    fn url(&self, key: &str) -> String {
+        format!("gs://{}/{key}", self.bucket)
+    }
+
+    fn get<'a>(
+        &'a self,
+        key: &'a str,
+        dest: &'a Path,
+        progress: Option<&'a Progress>,
+        min_free_bytes: u64,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let label = self.url(key);
+            let open = |offset: u64| {
+                let label = &label;
+                async move {
+                    let mut req = self
+                        .http
+                        .client
+                        .get(format!("{}?alt=media", self.object_url(key)));
+                    if offset > 0 {
+                        req = req.header("Range", format!("bytes={offset}-"));
+                    }
+                    self.http
+                        .send(
+                            self.authorize(req).await?,
+                            &format!("download {label}"),
+                            StatusCode::is_success,
+                        )
+                        .await
+                }
+            };
+            self.http
+                .download(open, &label, dest, progress, min_free_bytes)
+                .await
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Page {
+            #[serde(default)]
+            items: Vec<Item>,
+            next_page_token: Option<String>,
+        }
+        #[derive(serde::Deserialize)]
+        struct Item {
+            name: String,
+        }
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            let mut token: Option<String> = None;
+            loop {
+                let mut url = format!(
+                    "{}/storage/v1/b/{}/o?prefix={}",
+                    self.endpoint,
+                    self.bucket,
+                    upload::url_encode(prefix)
+                );
+                if let Some(token) = &token {
+                    url = format!("{url}&pageToken={}", upload::url_encode(token));
+                }
+                let req = self.authorize(self.http.client.get(url)).await?;
+                let page: Page = self
+                    .http
+                    .send(
+                        req,
+                        &format!("list {}", self.url(prefix)),
+                        StatusCode::is_success,
+                    )
+                    .await?
+                    .json()
+                    .await?;
+                keys.extend(page.items.into_iter().map(|item| item.name));
+                match page.next_page_token {
+                    Some(next) if !next.is_empty() => token = Some(next),
+                    _ => return Ok(keys),
+                }
+            }
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        path: &'a Path,
+        meta: &'a ObjectMeta,
+    ) -> BoxFuture<'a, Result<u64>> {
+        Box::pin(async move {
+            let size = file_size(path)?;
+            if size > upload::part_size() {
+                self.put_resumable(key, path, size, meta).await?;
+                return Ok(size);
+            }
+            let _slot = upload::slot().await?;
+            let body = read_range(path, 0, size).await?;
+            let mut req = self
+                .http
+                .client
+                .post(self.upload_url(key, "media"))
+                .header(
+                    "Content-Type",
+                    meta.content_type.unwrap_or("application/octet-stream"),
+                )
+                .body(body);
+            if let Some(encoding) = meta.content_encoding {
+                req = req.header("Content-Encoding", encoding);
+            }
+            self.http
+                .send(
+                    self.authorize(req).await?,
+                    &format!("upload {}", self.url(key)),
+                    StatusCode::is_success,
+                )
+                .await?;
+            Ok(size)
+        })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            // The JSON API has no HEAD; a metadata GET is the equivalent.
+            let req = self
+                .authorize(self.http.client.get(self.object_url(key)))
+                .await?;
+            let resp = self
+                .http
+                .send(req, &format!("head {}", self.url(key)), |status| {
+                    status.is_success() || *status == StatusCode::NOT_FOUND
+                })
+                .await?;
+            Ok(resp.status().is_success())
+        })
+    }
+}
+
+/// Fake HTTP endpoint for tests, answering every request with `status` and `body`. Returns its
+/// base URL and a receiver of each request: request line and headers (lowercased), a blank line,
+/// then the body.
+#[cfg(test)]
+pub fn fake_http(
+    status: &'static str,
+    body: &'static str,
+) -> (String, std::sync::mpsc::Receiver<String>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.expect("accept");
+            let mut reader = BufReader::new(stream.try_clone().expect("clone"));
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).expect("read") == 0 || line == "\r\n" {
+                    break;
+                }
+                let line = line.to_ascii_lowercase();
+                if let Some(value) = line.strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap_or(0);
+                }
+                request.push_str(&line);
+            }
+            let mut payload = vec![0; length];
+            reader.read_exact(&mut payload).expect("body");
+            request.push_str("\r\n");
+            request.push_str(&String::from_utf8_lossy(&payload));
+            tx.send(request).ok();
+            let reply = format!(
+                "HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(reply.as_bytes()).expect("reply");
+        }
+    });
+    (format!("http://{addr}"), rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Azurite's well-known development storage account key.
+    const DEVSTORE_KEY: &str =
+        "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let pairs: Vec<(String, String)> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| {
+            pairs
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+        }
+    }
+
+    #[test]
+    fn selects_backends_and_signs_azure_requests() {
+        assert_eq!(
+            split_uri("s3://bucket/a/b.txt").map(Result::ok),
+            Some(Some(("bucket".into(), "a/b.txt".into())))
+        );
+        assert_eq!(
+            split_uri("az://acct/evidence/a/b.txt").map(Result::ok),
+            Some(Some(("az://acct/evidence".into(), "a/b.txt".into())))
+        );
+        assert_eq!(
+            split_uri("gs://bucket/b.txt").map(Result::ok),
+            Some(Some(("gs://bucket".into(), "b.txt".into())))
+        );
+        assert!(split_uri("az://acct/only").expect("uri").is_err());
+        assert!(split_uri("/local/terms.txt").is_none());
+
+        let s3 = aws_sdk_s3::Client::from_conf(
+            aws_sdk_s3::Config::builder()
+                .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+                .build(),
+        );
+        assert_eq!(open(&s3, "s3://out").expect("s3").url("k"), "s3://out/k");
+        assert_eq!(open(&s3, "out").expect("s3").url("k"), "s3://out/k");
+        assert!(open(&s3, "ftp://out").is_err());
+
+        let headers = vec![
+            ("x-ms-version".to_string(), "2021-08-06".to_string()),
+            (
+                "x-ms-date".to_string(),
+                "Thu, 01 Jan 1970 00:00:00 GMT".to_string(),
+            ),
+            ("x-ms-blob-type".to_string(), "BlockBlob".to_string()),
+        ];
+        assert_eq!(
+            shared_key_string(
+                "PUT",
+                5,
+                &headers,
+                "/acct/evidence/a%20b.txt",
+                &[("comp", "block"), ("blockid", "YQ==")],
+            ),
+            "PUT\n\n\n5\n\n\n\n\n\n\n\n\n\
+             x-ms-blob-type:BlockBlob\nx-ms-date:Thu, 01 Jan 1970 00:00:00 GMT\n\
+             x-ms-version:2021-08-06\n/acct/evidence/a%20b.txt\nblockid:YQ==\ncomp:block"
+        );
+        assert_eq!(encode_path("In box/a+b.eml"), "In%20box/a%2Bb.eml");
+        assert_eq!(
+            xml_values(
+                "<Blobs><Blob><Name>a&amp;b</Name></Blob><Blob><Name>c</Name></Blob></Blobs>\
+                 <NextMarker />",
+                "Name"
+            ),
+            ["a&b", "c"]
+        );
+    }
+
+    #[test]
+    fn azure_shared_key_matches_the_published_example() {
+        // The Blob service example in Microsoft's "Authorize with Shared Key": Get Container
+        // Metadata with no standard headers set.
+        let headers = vec![
+            (
+                "x-ms-date".to_string(),
+                "Fri, 26 Jun 2015 23:39:12 GMT".to_string(),
+            ),
+            ("x-ms-version".to_string(), "2015-02-21".to_string()),
+        ];
+        assert_eq!(
+            shared_key_string(
+                "GET",
+                0,
+                &headers,
+                "/myaccount/mycontainer",
+                &[
+                    ("restype", "container"),
+                    ("comp", "metadata"),
+                    ("timeout", "20")
+                ],
+            ),
+            "GET\n\n\n\n\n\n\n\n\n\n\n\n\
+             x-ms-date:Fri, 26 Jun 2015 23:39:12 GMT\nx-ms-version:2015-02-21\n\
+             /myaccount/mycontainer\ncomp:metadata\nrestype:container\ntimeout:20"
+        );
+    }
+
+    #[test]
+    fn azure_signs_emulator_requests_with_the_account_twice() {
+        // Against the storage emulator the account name appears twice in the canonicalized
+        // resource: once for the account, once from the endpoint's path.
+        let store = AzureStore::from_env(
+            "devstoreaccount1/evidence",
+            1,
+            vars(&[
+                ("AZURE_STORAGE_KEY", DEVSTORE_KEY),
+                (
+                    "AZURE_STORAGE_ENDPOINT",
+                    "http://127.0.0.1:10000/devstoreaccount1/",
+                ),
+            ]),
+        )
+        .expect("store");
+        let req = store
+            .request(Method::HEAD, Some("In box/a.eml"), &[], &[], Vec::new())
+            .build()
+            .expect("request");
+        assert_eq!(
+            req.url().as_str(),
+            "http://127.0.0.1:10000/devstoreaccount1/evidence/In%20box/a.eml"
+        );
+        let header = |name: &str| req.headers()[name].to_str().expect("header").to_string();
+        let headers = vec![
+            ("x-ms-date".to_string(), header("x-ms-date")),
+            ("x-ms-version".to_string(), AZURE_API_VERSION.to_string()),
+        ];
+        let to_sign = shared_key_string(
+            "HEAD",
+            0,
+            &headers,
+            "/devstoreaccount1/devstoreaccount1/evidence/In%20box/a.eml",
+            &[],
+        );
+        let key = crate::encoded_words::base64_decode(DEVSTORE_KEY).expect("key");
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("hmac");
+        mac.update(to_sign.as_bytes());
+        assert_eq!(
+            header("authorization"),
+            format!(
+                "SharedKey devstoreaccount1:{}",
+                crate::msg::base64(&mac.finalize().into_bytes())
+            )
+        );
+
+        // A SAS token is appended to the query instead, with no Authorization header.
+        let store = AzureStore::from_env(
+            "acct/evidence",
+            1,
+            vars(&[("AZURE_STORAGE_SAS_TOKEN", "?sv=2021-08-06&sig=abc")]),
+        )
+        .expect("store");
+        let req = store
+            .request(Method::GET, None, &[("comp", "list")], &[], Vec::new())
+            .build()
+            .expect("request");
+        assert_eq!(
+            req.url().as_str(),
+            "https://acct.blob.core.windows.net/evidence?comp=list&sv=2021-08-06&sig=abc"
+        );
+        assert!(!req.headers().contains_key("authorization"));
+    }
+
+    #[test]
+    fn gcs_percent_encodes_object_names() {
+        // The JSON API takes the object name as one path segment, so `/` is encoded too.
+        let store =
+            GcsStore::from_env("evidence", 1, vars(&[("GCS_ACCESS_TOKEN", "tok")])).expect("store");
+        assert_eq!(
+            store.object_url("folder/my file?.eml"),
+            "https://storage.googleapis.com/storage/v1/b/evidence/o/folder%2Fmy%20file%3F.eml"
+        );
+        assert_eq!(
+            store.upload_url("a/b+c.eml", "resumable"),
+            "https://storage.googleapis.com/upload/storage/v1/b/evidence/o\
+             ?uploadType=resumable&name=a%2Fb%2Bc.eml"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_locations_and_credentials() {
+        for uri in [
+            "s3://bucket",
+            "s3:///key",
+            "gs://bucket/",
+            "az://acct/c",
+            "az:///c/k",
+        ] {
+            assert!(split_uri(uri).expect("object URI").is_err(), "{uri}");
+        }
+        let key = vars(&[("AZURE_STORAGE_KEY", DEVSTORE_KEY)]);
+        for rest in ["acct", "acct/", "/evidence", "acct/evidence/sub"] {
+            let err = AzureStore::from_env(rest, 1, &key).err().expect(rest);
+            assert!(err.to_string().contains("az://account/container"), "{err}");
+        }
+        let err = AzureStore::from_env("acct/evidence", 1, vars(&[]))
+            .err()
+            .expect("no auth");
+        assert!(err.to_string().contains("AZURE_STORAGE_SAS_TOKEN"), "{err}");
+        let err = AzureStore::from_env(
+            "acct/evidence",
+            1,
+            vars(&[("AZURE_STORAGE_KEY", "not base64!")]),
+        )
+        .err()
+        .expect("bad key");
+        assert!(err.to_string().contains("not base64"), "{err}");
+        for rest in ["", "bucket/sub"] {
+            let err = GcsStore::from_env(rest, 1, vars(&[])).err().expect(rest);
+            assert!(err.to_string().contains("gs://bucket"), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_fail_with_the_service_error() {
+        let (endpoint, heads) = fake_http("403 Forbidden", "");
+        let store = AzureStore::from_env(
+            "devstoreaccount1/evidence",
+            3,
+            vars(&[
+                ("AZURE_STORAGE_KEY", DEVSTORE_KEY),
+                ("AZURE_STORAGE_ENDPOINT", &endpoint),
+            ]),
+        )
+        .expect("store");
+        let err = store.exists("a.eml").await.expect_err("forbidden");
+        assert!(err.to_string().contains("HTTP 403"), "{err}");
+        // Not retried: a 403 won't change on a second attempt.
+        assert!(heads
+            .recv()
+            .expect("request")
+            .contains("authorization: sharedkey "));
+        assert!(heads.try_recv().is_err());
+
+        let (endpoint, heads) = fake_http("401 Unauthorized", "Invalid Credentials");
+        let store = GcsStore::from_env(
+            "evidence",
+            3,
+            vars(&[("GCS_ACCESS_TOKEN", "expired"), ("GCS_ENDPOINT", &endpoint)]),
+        )
+        .expect("store");
+        // GCS has no HEAD, so the service's error body comes back with the status.
+        let err = store.exists("a.eml").await.expect_err("unauthorized");
+        let err = err.to_string();
+        assert!(
+            err.contains("HTTP 401") && err.contains("Invalid Credentials"),
+            "{err}"
+        );
+        assert!(heads
+            .recv()
+            .expect("request")
+            .contains("authorization: bearer expired"));
+    }
+}
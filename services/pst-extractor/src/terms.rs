@@ -0,0 +1,447 @@
+//! Search-term hit counting with pluggable tokenizers.
+//!
+//! Whitespace/punctuation word boundaries work for most Western languages but not for Chinese,
+//! Japanese or Korean, which don't separate words with spaces. Terms and text are tokenized with
+//! the same tokenizer and a term hits wherever its token sequence appears in the text.
+//!
+//! A terms-file line may also be a boolean expression: phrases combined with `AND`, `OR`, `NOT`
+//! (upper case) and parentheses, e.g. `delay AND (claim OR "extension of time") AND NOT
+//! weather`. Adjacent words form one phrase, quotes are only needed around operator words.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF   // Hiragana, Katakana
+        | 0x3400..=0x4DBF // CJK Extension A
+        | 0x4E00..=0x9FFF // CJK Unified Ideographs
+        | 0xF900..=0xFAFF // CJK Compatibility Ideographs
+        | 0x1100..=0x11FF // Hangul Jamo
+        | 0x3130..=0x318F // Hangul Compatibility Jamo
+        | 0xAC00..=0xD7AF // Hangul Syllables
+        | 0x20000..=0x2A6DF)
+}
+
+/// Splits text into runs: alphanumeric words (lowercased) and CJK runs. Everything else is a
+/// boundary.
+fn runs(text: &str) -> Vec<(bool, String)> {
+    let mut out: Vec<(bool, String)> = Vec::new();
+    let mut cur = String::new();
+    let mut cur_cjk = false;
+    for c in text.chars() {
+        let cjk = is_cjk(c);
+        if cjk || c.is_alphanumeric() {
+            if !cur.is_empty() && cjk != cur_cjk {
+                out.push((cur_cjk, std::mem::take(&mut cur)));
+            }
+            cur_cjk = cjk;
+            cur.extend(c.to_lowercase());
+        } else if !cur.is_empty() {
+            out.push((cur_cjk, std::mem::take(&mut cur)));
+        }
+    }
+    if !cur.is_empty() {
+        out.push((cur_cjk, cur));
+    }
+    out
+}
+
+pub trait Tokenizer: Send + Sync {
+    fn tokens(&self, text: &str) -> Vec<String>;
+
+    /// Occurrences of `term` in `text`. Default: phrase match of the term's token sequence.
+    fn count(&self, text: &str, term: &str) -> usize {
+        count_sequence(&self.tokens(text), &self.tokens(term))
+    }
+}
+
+fn count_sequence(haystack: &[String], needle: &[String]) -> usize {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return 0;
+    }
+    haystack
+        .windows(needle.len())
+        .filter(|w| *w == needle)
+        .count()
+}
+
+/// Word tokens split on whitespace/punctuation. CJK runs become a single token, so this only
+/// matches whole CJK runs; use the n-gram or dictionary tokenizer for CJK matters.
+pub struct WordTokenizer;
+
+impl Tokenizer for WordTokenizer {
+    fn tokens(&self, text: &str) -> Vec<String> {
+        runs(text).into_iter().map(|(_, r)| r).collect()
+    }
+}
+
+/// Overlapping character n-grams over CJK runs; non-CJK text is tokenized into words.
+pub struct NgramTokenizer {
+    n: usize,
+}
+
+impl NgramTokenizer {
+    pub fn new(n: usize) -> Self {
+        Self { n: n.max(1) }
+    }
+}
+
+impl Tokenizer for NgramTokenizer {
+    fn tokens(&self, text: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        for (cjk, run) in runs(text) {
+            if !cjk {
+                out.push(run);
+                continue;
+            }
+            let chars: Vec<char> = run.chars().collect();
+            if chars.len() <= self.n {
+                out.push(run);
+                continue;
+            }
+            for w in chars.windows(self.n) {
+                out.push(w.iter().collect());
+            }
+        }
+        out
+    }
+
+    fn count(&self, text: &str, term: &str) -> usize {
+        // A CJK term shorter than n never forms an n-gram; fall back to counting it inside runs.
+        let term_runs = runs(term);
+        if let [(true, t)] = term_runs.as_slice() {
+            if t.chars().count() < self.n {
+                return runs(text)
+                    .iter()
+                    .filter(|(cjk, _)| *cjk)
+                    .map(|(_, r)| r.matches(t.as_str()).count())
+                    .sum();
+            }
+        }
+        count_sequence(&self.tokens(text), &self.tokens(term))
+    }
+}
+
+/// Forward maximum-matching segmentation of CJK runs against a word list (one word per line);
+/// characters not covered by the dictionary become single-character tokens.
+pub struct DictionaryTokenizer {
+    words: HashSet<String>,
+    max_chars: usize,
+}
+
+impl DictionaryTokenizer {
+    pub fn from_words<I: IntoIterator<Item = String>>(words: I) -> Self {
+        let words: HashSet<String> = words
+            .into_iter()
+            .map(|w| w.trim().to_lowercase())
+            .filter(|w| !w.is_empty())
+            .collect();
+        let max_chars = words.iter().map(|w| w.chars().count()).max().unwrap_or(1);
+        Self { words, max_chars }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("read dictionary {}", path.display()))?;
+        Ok(Self::from_words(text.lines().map(str::to_string)))
+    }
+}
+
+impl Tokenizer for DictionaryTokenizer {
+    fn tokens(&self, text: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        for (cjk, run) in runs(text) {
+            if !cjk {
+                out.push(run);
+                continue;
+            }
+            let chars: Vec<char> = run.chars().collect();
+            let mut i = 0;
+            while i < chars.len() {
+                let longest = (1..=self.max_chars.min(chars.len() - i))
+                    .rev()
+                    .find(|len| {
+                        let cand: String = chars[i..i + len].iter().collect();
+                        self.words.contains(&cand)
+                    })
+                    .unwrap_or(1);
+                out.push(chars[i..i + longest].iter().collect());
+                i += longest;
+            }
+        }
+        out
+    }
+}
+
+/// Build a tokenizer from a CLI spec: `word`, `ngram[:N]` (default bigrams) or `dict:<path>`.
+pub fn tokenizer_from_spec(spec: &str) -> Result<Box<dyn Tokenizer>> {
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+    match kind {
+        "word" => Ok(Box::new(WordTokenizer)),
+        "ngram" => {
+            let n = if arg.is_empty() {
+                2
+            } else {
+                arg.parse()
+                    .with_context(|| format!("invalid n-gram size {arg:?}"))?
+            };
+            Ok(Box::new(NgramTokenizer::new(n)))
+        }
+        "dict" if !arg.is_empty() => Ok(Box::new(DictionaryTokenizer::from_file(Path::new(arg))?)),
+        _ => Err(anyhow!(
+            "unknown tokenizer {spec:?} (expected word, ngram[:N] or dict:<path>)"
+        )),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Phrase(String),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Quoted(String),
+    Word(String),
+}
+
+fn lex(line: &str) -> Vec<Token> {
+    let mut out = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '(' | ')' => {
+                chars.next();
+                out.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '"' => {
+                chars.next();
+                let phrase: String = chars.by_ref().take_while(|&q| q != '"').collect();
+                out.push(Token::Quoted(phrase));
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&w) = chars.peek() {
+                    if w.is_whitespace() || matches!(w, '(' | ')' | '"') {
+                        break;
+                    }
+                    word.push(w);
+                    chars.next();
+                }
+                out.push(Token::Word(word));
+            }
+        }
+    }
+    out
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn operator(&self, name: &str) -> bool {
+        matches!(self.tokens.get(self.at), Some(Token::Word(w)) if w == name)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut parts = vec![self.and()?];
+        while self.operator("OR") {
+            self.at += 1;
+            parts.push(self.and()?);
+        }
+        Ok(if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            Expr::Or(parts)
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut parts = vec![self.unary()?];
+        while self.operator("AND") {
+            self.at += 1;
+            parts.push(self.unary()?);
+        }
+        Ok(if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            Expr::And(parts)
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.operator("NOT") {
+            self.at += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        match self.tokens.get(self.at) {
+            Some(Token::Open) => {
+                self.at += 1;
+                let inner = self.or()?;
+                if self.tokens.get(self.at) != Some(&Token::Close) {
+                    return Err(anyhow!("missing )"));
+                }
+                self.at += 1;
+                Ok(inner)
+            }
+            Some(Token::Quoted(phrase)) => {
+                self.at += 1;
+                Ok(Expr::Phrase(phrase.clone()))
+            }
+            Some(Token::Word(_)) => {
+                let mut words = Vec::new();
+                while let Some(Token::Word(w)) = self.tokens.get(self.at) {
+                    if ["AND", "OR", "NOT"].contains(&w.as_str()) {
+                        break;
+                    }
+                    words.push(w.clone());
+                    self.at += 1;
+                }
+                if words.is_empty() {
+                    return Err(anyhow!("operator without a term"));
+                }
+                Ok(Expr::Phrase(words.join(" ")))
+            }
+            _ => Err(anyhow!("expected a term")),
+        }
+    }
+}
+
+fn parse_expr(line: &str) -> Result<Expr> {
+    let mut parser = Parser {
+        tokens: lex(line),
+        at: 0,
+    };
+    let expr = parser.or()?;
+    if parser.at != parser.tokens.len() {
+        return Err(anyhow!("unexpected {:?}", parser.tokens[parser.at]));
+    }
+    Ok(expr)
+}
+
+/// Per-term totals for the manifest's search-term report.
+#[derive(Serialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct TermSummary {
+    /// Emails (subject or body) and attachments (extracted text) with a hit.
+    pub emails: usize,
+    pub attachments: usize,
+    /// Top-level emails whose family (embedded emails and attachments included) has a hit.
+    pub families: usize,
+    pub hits: usize,
+}
+
+/// A list of search terms and the tokenizer used to match them.
+pub struct TermMatcher {
+    terms: Vec<(String, Expr)>,
+    tokenizer: Box<dyn Tokenizer>,
+}
+
+impl TermMatcher {
+    /// One term or expression per line; blank lines and `#` comments are ignored.
+    pub fn new(terms_text: &str, tokenizer: Box<dyn Tokenizer>) -> Result<Self> {
+        let terms = terms_text
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+            .map(|(n, line)| {
+                let expr = parse_expr(line)
+                    .with_context(|| format!("terms file line {}: {line:?}", n + 1))?;
+                Ok((line.to_string(), expr))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { terms, tokenizer })
+    }
+
+    pub fn terms(&self) -> impl Iterator<Item = &str> {
+        self.terms.iter().map(|(term, _)| term.as_str())
+    }
+
+    /// Whether `expr` matches, and with how many phrase hits (a match on `NOT` alone counts 0).
+    fn eval(&self, expr: &Expr, texts: &[&str]) -> Option<usize> {
+        match expr {
+            Expr::Phrase(phrase) => {
+                let n: usize = texts.iter().map(|t| self.tokenizer.count(t, phrase)).sum();
+                (n > 0).then_some(n)
+            }
+            Expr::And(parts) => parts.iter().map(|p| self.eval(p, texts)).sum(),
+            Expr::Or(parts) => parts
+                .iter()
+                .filter_map(|p| self.eval(p, texts))
+                .reduce(|a, b| a + b),
+            Expr::Not(inner) => self.eval(inner, texts).is_none().then_some(0),
+        }
+    }
+
+    /// Hit counts per term across `texts`; terms with no hits are omitted.
+    pub fn hits(&self, texts: &[&str]) -> BTreeMap<String, usize> {
+        let mut out = BTreeMap::new();
+        for (term, expr) in &self.terms {
+            if let Some(n) = self.eval(expr, texts) {
+                out.insert(term.clone(), n.max(1));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_tokenizer_matches_whole_words_case_insensitively() {
+        let t = WordTokenizer;
+        assert_eq!(t.count("Project Falcon, falcon; FALCONRY", "falcon"), 2);
+        assert_eq!(t.count("the project falcon team", "Project Falcon"), 1);
+    }
+
+    #[test]
+    fn ngram_tokenizer_finds_cjk_terms_without_word_boundaries() {
+        let t = NgramTokenizer::new(2);
+        let text = "我们明天在东京都开会。东京的天气很好";
+        assert_eq!(t.count(text, "东京都"), 1);
+        assert_eq!(t.count(text, "东京"), 2);
+        assert_eq!(t.count(text, "京"), 2);
+        assert_eq!(WordTokenizer.count(text, "东京"), 0);
+    }
+
+    #[test]
+    fn boolean_expressions_combine_phrase_hits() {
+        let terms = "Project Falcon\n\
+            delay AND (claim OR \"extension of time\") AND NOT weather\n\
+            \"AND\" OR falcon";
+        let matcher = TermMatcher::new(terms, Box::new(WordTokenizer)).expect("terms");
+        let hits = matcher.hits(&["Falcon delay", "the extension of time for project falcon"]);
+        assert_eq!(hits.get("Project Falcon"), Some(&1));
+        assert_eq!(
+            hits.get("delay AND (claim OR \"extension of time\") AND NOT weather"),
+            Some(&2)
+        );
+        assert_eq!(hits.get("\"AND\" OR falcon"), Some(&2));
+        let hits = matcher.hits(&["delay claim due to weather"]);
+        assert_eq!(hits.len(), 0);
+        assert!(TermMatcher::new("delay AND", Box::new(WordTokenizer)).is_err());
+        assert!(TermMatcher::new("(delay OR claim", Box::new(WordTokenizer)).is_err());
+    }
+
+    #[test]
+    fn dictionary_tokenizer_segments_by_longest_match() {
+        let t = DictionaryTokenizer::from_words(["契約".to_string(), "契約書".to_string()]);
+        assert_eq!(t.tokens("契約書を送付"), vec!["契約書", "を", "送", "付"]);
+        assert_eq!(t.count("契約書を送付", "契約書"), 1);
+    }
+}
@@ -0,0 +1,537 @@
+//! Attachment text for the search index (`--attachment-text`).
+//!
+//! Handles the formats that make up most of a construction mailbox's documents: PDF, DOCX, XLSX
+//! and plain text. These are best-effort extractors, not renderers.
+//!
+//! * PDF: text-showing operators (`Tj`, `TJ`, `'`, `"`) are read from the content streams,
+//!   inflated when FlateDecode. Fonts with custom encodings and no usable byte mapping come out
+//!   garbled. Those, like scanned pages without a text layer, yield nothing.
+//! * DOCX: the runs of `word/document.xml`, one line per paragraph.
+//! * XLSX: every worksheet's cell values, one row per line and tab-separated, with shared
+//!   strings resolved.
+//! * Plain text (including CSV and other text/* parts) is passed through.
+
+use crate::ziparchive::ZipArchive;
+use std::io::{Cursor, Read};
+
+pub struct Extracted {
+    pub text: String,
+    /// `pdf`, `docx`, `xlsx` or `text`.
+    pub method: &'static str,
+    /// The text was cut at the character limit.
+    pub truncated: bool,
+}
+
+const DOCX: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+const XLSX: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+/// Extract text by the detected type (falling back to a `text/*` declared type). None for other
+/// formats, and when nothing readable came out.
+pub fn extract(
+    data: &[u8],
+    detected: Option<&str>,
+    declared: Option<&str>,
+    max_chars: usize,
+) -> Option<Extracted> {
+    let (text, method) = match detected {
+        Some("application/pdf") => (pdf_text(data)?, "pdf"),
+        Some(DOCX) => (docx_text(data)?, "docx"),
+        Some(XLSX) => (xlsx_text(data)?, "xlsx"),
+        Some("text/plain") => (plain_text(data), "text"),
+        None if declared.is_some_and(|d| d.to_ascii_lowercase().starts_with("text/plain")) => {
+            (plain_text(data), "text")
+        }
+        _ => return None,
+    };
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let (text, truncated) = match text.char_indices().nth(max_chars) {
+        Some((cut, _)) => (&text[..cut], true),
+        None => (text, false),
+    };
+    Some(Extracted {
+        text: text.to_string(),
+        method,
+        truncated,
+    })
+}
+
+fn plain_text(data: &[u8]) -> String {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    String::from_utf8_lossy(data).into_owned()
+}
+
+// ---------------------------------------------------------------------------------------------
+// Office Open XML
+
+enum Xml<'a> {
+    /// Tag name and the whole tag (for attributes).
+    Open(&'a str, &'a str),
+    Close(&'a str),
+    Text(&'a str),
+}
+
+/// Flat tag/text events of an XML document; declarations, comments and CDATA markers are
+/// skipped. Self-closing tags are reported as an `Open` only.
+fn xml_events(xml: &str) -> impl Iterator<Item = Xml<'_>> {
+    let mut rest = xml;
+    std::iter::from_fn(move || loop {
+        if rest.is_empty() {
+            return None;
+        }
+        if let Some(after) = rest.strip_prefix('<') {
+            let end = after.find('>').unwrap_or(after.len());
+            let tag = &after[..end];
+            rest = after.get(end + 1..).unwrap_or("");
+            if tag.starts_with(['?', '!']) {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                return Some(Xml::Close(name.trim()));
+            }
+            let name = tag
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or("");
+            return Some(Xml::Open(name, tag));
+        }
+        let end = rest.find('<').unwrap_or(rest.len());
+        let text = &rest[..end];
+        rest = &rest[end..];
+        return Some(Xml::Text(text));
+    })
+}
+
+fn xml_attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {name}=\""))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+fn unescape_xml(text: &str, out: &mut String) {
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        let after = &rest[amp + 1..];
+        let Some(semi) = after.find(';').filter(|s| *s <= 10) else {
+            out.push('&');
+            rest = after;
+            continue;
+        };
+        let entity = &after[..semi];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &after[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+fn zip_entry_text(zip: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Option<String> {
+    let idx = zip.entries().iter().position(|e| e.name == name)?;
+    String::from_utf8(zip.read(idx).ok()?).ok()
+}
+
+fn docx_text(data: &[u8]) -> Option<String> {
+    let mut zip = ZipArchive::new(Cursor::new(data)).ok()?;
+    let xml = zip_entry_text(&mut zip, "word/document.xml")?;
+    let mut out = String::new();
+    let mut in_text = false;
+    for event in xml_events(&xml) {
+        match event {
+            Xml::Open("w:t", tag) => in_text = !tag.ends_with('/'),
+            Xml::Close("w:t") => in_text = false,
+            Xml::Text(text) if in_text => unescape_xml(text, &mut out),
+            Xml::Open("w:tab", _) => out.push('\t'),
+            Xml::Open("w:br" | "w:cr", _) | Xml::Close("w:p") => out.push('\n'),
+            _ => {}
+        }
+    }
+    Some(out)
+}
+
+fn xlsx_text(data: &[u8]) -> Option<String> {
+    let mut zip = ZipArchive::new(Cursor::new(data)).ok()?;
+    let mut shared = Vec::new();
+    if let Some(xml) = zip_entry_text(&mut zip, "xl/sharedStrings.xml") {
+        let mut current = String::new();
+        let mut in_text = false;
+        for event in xml_events(&xml) {
+            match event {
+                Xml::Open("t", tag) => in_text = !tag.ends_with('/'),
+                Xml::Close("t") => in_text = false,
+                Xml::Text(text) if in_text => unescape_xml(text, &mut current),
+                Xml::Close("si") => shared.push(std::mem::take(&mut current)),
+                _ => {}
+            }
+        }
+    }
+
+    let mut sheets: Vec<(u32, String)> = zip
+        .entries()
+        .iter()
+        .filter_map(|e| {
+            let n = e
+                .name
+                .strip_prefix("xl/worksheets/sheet")?
+                .strip_suffix(".xml")?;
+            Some((n.parse().ok()?, e.name.clone()))
+        })
+        .collect();
+    sheets.sort();
+    let mut out = String::new();
+    for (_, name) in sheets {
+        let Some(xml) = zip_entry_text(&mut zip, &name) else {
+            continue;
+        };
+        let (mut cell_type, mut value, mut in_value) = (String::new(), String::new(), false);
+        let mut cells_in_row = 0usize;
+        for event in xml_events(&xml) {
+            match event {
+                Xml::Open("c", tag) => {
+                    cell_type = xml_attr(tag, "t").unwrap_or("").to_string();
+                    value.clear();
+                }
+                Xml::Open("v" | "t", tag) => in_value = !tag.ends_with('/'),
+                Xml::Close("v" | "t") => in_value = false,
+                Xml::Text(text) if in_value => unescape_xml(text, &mut value),
+                Xml::Close("c") => {
+                    let cell = match cell_type.as_str() {
+                        "s" => value
+                            .trim()
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|i| shared.get(i))
+                            .map_or("", String::as_str),
+                        _ => value.as_str(),
+                    };
+                    if !cell.is_empty() {
+                        if cells_in_row > 0 {
+                            out.push('\t');
+                        }
+                        out.push_str(cell);
+                        cells_in_row += 1;
+                    }
+                }
+                Xml::Close("row") => {
+                    if cells_in_row > 0 {
+                        out.push('\n');
+                    }
+                    cells_in_row = 0;
+                }
+                _ => {}
+            }
+        }
+        out.push('\n');
+    }
+    Some(out)
+}
+
+// ---------------------------------------------------------------------------------------------
+// PDF
+
+/// Content bytes of every stream that may hold page content (images and embedded fonts are
+/// skipped), inflated when FlateDecode.
+fn pdf_streams(data: &[u8]) -> Vec<Vec<u8>> {
+    use memchr::memmem;
+    let mut out = Vec::new();
+    let mut at = 0usize;
+    while let Some(pos) = memmem::find(&data[at..], b"stream") {
+        let start = at + pos;
+        at = start + 6;
+        // "endstream" also contains "stream".
+        if start >= 3 && &data[start - 3..start] == b"end" {
+            continue;
+        }
+        let mut body = at;
+        if data.get(body) == Some(&b'\r') {
+            body += 1;
+        }
+        if data.get(body) == Some(&b'\n') {
+            body += 1;
+        } else if body == at {
+            continue;
+        }
+        let Some(len) = memmem::find(&data[body..], b"endstream") else {
+            break;
+        };
+        let dict_start = memmem::rfind(&data[..start], b"obj").unwrap_or(0);
+        let dict = &data[dict_start..start];
+        at = body + len + 9;
+        let skip = [
+            &b"/Image"[..],
+            b"/FontFile",
+            b"/Length1",
+            b"/XRef",
+            b"/Metadata",
+        ];
+        if skip.iter().any(|s| memmem::find(dict, s).is_some()) {
+            continue;
+        }
+        let raw = &data[body..body + len];
+        if memmem::find(dict, b"/FlateDecode").is_some() {
+            let mut inflated = Vec::new();
+            // Truncated streams still yield what inflated before the error.
+            let _ = flate2::read::ZlibDecoder::new(raw).read_to_end(&mut inflated);
+            out.push(inflated);
+        } else if memmem::find(dict, b"/Filter").is_none() {
+            out.push(raw.to_vec());
+        }
+    }
+    out
+}
+
+/// Bytes of a PDF string as text: UTF-16BE with a BOM, otherwise one char per byte.
+fn pdf_string_text(bytes: &[u8], out: &mut String) {
+    if let Some(utf16) = bytes.strip_prefix(b"\xFE\xFF") {
+        let units: Vec<u16> = utf16
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        out.push_str(&String::from_utf16_lossy(&units));
+        return;
+    }
+    // Two-byte CIDs from Identity-H fonts often equal Unicode for Latin text: 00 xx pairs.
+    if bytes.len() >= 2 && bytes.len().is_multiple_of(2) && bytes.iter().step_by(2).all(|b| *b == 0)
+    {
+        out.extend(bytes.iter().skip(1).step_by(2).map(|b| *b as char));
+        return;
+    }
+    out.extend(bytes.iter().map(|b| *b as char));
+}
+
+/// Literal `( ... )` string starting after the opening parenthesis; returns bytes and the
+/// index after the closing one.
+fn pdf_literal(data: &[u8], mut i: usize) -> (Vec<u8>, usize) {
+    let mut out = Vec::new();
+    let mut depth = 1;
+    while i < data.len() {
+        let b = data[i];
+        i += 1;
+        match b {
+            b'\\' if i < data.len() => {
+                let e = data[i];
+                i += 1;
+                match e {
+                    b'n' => out.push(b'\n'),
+                    b'r' => out.push(b'\r'),
+                    b't' => out.push(b'\t'),
+                    b'b' | b'f' => {}
+                    b'0'..=b'7' => {
+                        let mut v = u32::from(e - b'0');
+                        for _ in 0..2 {
+                            match data.get(i) {
+                                Some(d @ b'0'..=b'7') => {
+                                    v = v * 8 + u32::from(d - b'0');
+                                    i += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        out.push(v as u8);
+                    }
+                    b'\r' | b'\n' => {}
+                    other => out.push(other),
+                }
+            }
+            b'(' => {
+                depth += 1;
+                out.push(b);
+            }
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+                out.push(b);
+            }
+            _ => out.push(b),
+        }
+    }
+    (out, i)
+}
+
+fn pdf_hex(data: &[u8], mut i: usize) -> (Vec<u8>, usize) {
+    let mut digits = Vec::new();
+    while i < data.len() && data[i] != b'>' {
+        if data[i].is_ascii_hexdigit() {
+            digits.push(data[i]);
+        }
+        i += 1;
+    }
+    if digits.len() % 2 == 1 {
+        digits.push(b'0');
+    }
+    let bytes = digits
+        .chunks(2)
+        .filter_map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect();
+    (bytes, i + 1)
+}
+
+/// Text-showing operators of one content stream, in order.
+fn pdf_content_text(content: &[u8], out: &mut String) {
+    let mut pending = String::new();
+    let mut operands: Vec<f64> = Vec::new();
+    let mut i = 0usize;
+    while i < content.len() {
+        let b = content[i];
+        match b {
+            b'(' => {
+                let (bytes, next) = pdf_literal(content, i + 1);
+                pdf_string_text(&bytes, &mut pending);
+                i = next;
+            }
+            b'<' if content.get(i + 1) != Some(&b'<') => {
+                let (bytes, next) = pdf_hex(content, i + 1);
+                pdf_string_text(&bytes, &mut pending);
+                i = next;
+            }
+            b'%' => {
+                while i < content.len() && content[i] != b'\n' && content[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            b'-' | b'+' | b'.' | b'0'..=b'9' => {
+                let start = i;
+                while i < content.len() && matches!(content[i], b'-' | b'+' | b'.' | b'0'..=b'9') {
+                    i += 1;
+                }
+                let number = std::str::from_utf8(&content[start..i])
+                    .ok()
+                    .and_then(|s| s.parse::<f64>().ok());
+                // A large negative kern inside a TJ array is a word gap.
+                if let Some(n) = number {
+                    if n < -200.0 && !pending.is_empty() && !pending.ends_with(' ') {
+                        pending.push(' ');
+                    }
+                    operands.push(n);
+                }
+            }
+            b'a'..=b'z' | b'A'..=b'Z' | b'\'' | b'"' | b'*' => {
+                let start = i;
+                while i < content.len()
+                    && matches!(content[i], b'a'..=b'z' | b'A'..=b'Z' | b'\'' | b'"' | b'*')
+                {
+                    i += 1;
+                }
+                match &content[start..i] {
+                    b"Tj" | b"TJ" => out.push_str(&std::mem::take(&mut pending)),
+                    b"'" | b"\"" => {
+                        out.push('\n');
+                        out.push_str(&std::mem::take(&mut pending));
+                    }
+                    b"T*" | b"ET" | b"Tm" => out.push('\n'),
+                    b"Td" | b"TD" => {
+                        let ty = operands.last().copied().unwrap_or(0.0);
+                        if ty.abs() > 0.01 {
+                            out.push('\n');
+                        } else if !out.ends_with([' ', '\n']) {
+                            out.push(' ');
+                        }
+                    }
+                    _ => pending.clear(),
+                }
+                operands.clear();
+            }
+            _ => i += 1,
+        }
+    }
+}
+
+fn pdf_text(data: &[u8]) -> Option<String> {
+    if memchr::memmem::find(data, b"/Encrypt").is_some() {
+        return None;
+    }
+    let mut out = String::new();
+    for stream in pdf_streams(data) {
+        // Only content streams have text objects.
+        if memchr::memmem::find(&stream, b"BT").is_none() {
+            continue;
+        }
+        pdf_content_text(&stream, &mut out);
+    }
+    // Collapse the blank lines left by positioning operators.
+    let mut text = String::with_capacity(out.len());
+    for line in out
+        .lines()
+        .map(str::trim_end)
+        .filter(|l| !l.trim().is_empty())
+    {
+        text.push_str(line);
+        text.push('\n');
+    }
+    // Garbage from unmapped font encodings: mostly control and replacement characters.
+    let odd = text
+        .chars()
+        .filter(|c| (c.is_control() && !c.is_whitespace()) || *c == '\u{fffd}')
+        .count();
+    (odd * 10 <= text.chars().count()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn extracts_pdf_docx_and_xlsx_text() {
+        let content = b"BT /F1 12 Tf 72 720 Td (Notice of) Tj ( delay) Tj 0 -14 Td \
+            [(Pour ) -250 (C3 \\(level 4\\))] TJ ET";
+        let mut z = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        z.write_all(content).expect("deflate");
+        let packed = z.finish().expect("deflate");
+        let mut pdf =
+            b"%PDF-1.4\n4 0 obj\n<< /Length 99 /Filter /FlateDecode >>\nstream\n".to_vec();
+        pdf.extend(&packed);
+        pdf.extend(b"\nendstream\nendobj\n5 0 obj\n<< /Subtype /Image /Length 3 >>\nstream\nBT(x)Tj\nendstream\n");
+        let got = extract(&pdf, Some("application/pdf"), None, 1000).expect("pdf text");
+        assert_eq!(got.method, "pdf");
+        assert_eq!(got.text, "Notice of delay\nPour C3 (level 4)");
+
+        let docx = crate::ziparchive::build(&[(
+            "word/document.xml",
+            br#"<?xml version="1.0"?><w:document><w:body><w:p><w:r><w:t>Programme</w:t></w:r><w:r><w:tab/><w:t xml:space="preserve">Rev C &amp; D</w:t></w:r></w:p><w:p><w:r><w:t>EOT claim</w:t></w:r></w:p></w:body></w:document>"#,
+        )]);
+        let got = extract(&docx, Some(DOCX), None, 1000).expect("docx text");
+        assert_eq!(got.text, "Programme\tRev C & D\nEOT claim");
+
+        let xlsx = crate::ziparchive::build(&[
+            (
+                "xl/sharedStrings.xml",
+                br#"<sst><si><t>Activity</t></si><si><r><t>Pour</t></r><r><t> C3</t></r></si></sst>"#,
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                br#"<worksheet><sheetData><row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="inlineStr"><is><t>Days</t></is></c></row><row r="2"><c r="A2" t="s"><v>1</v></c><c r="B2"><v>14</v></c></row></sheetData></worksheet>"#,
+            ),
+        ]);
+        let got = extract(&xlsx, Some(XLSX), None, 1000).expect("xlsx text");
+        assert_eq!(got.text, "Activity\tDays\nPour C3\t14");
+
+        let got = extract(b"abcdef", None, Some("text/plain; charset=utf-8"), 4).expect("text");
+        assert_eq!((got.text.as_str(), got.truncated), ("abcd", true));
+        assert!(extract(b"\x89PNG", Some("image/png"), None, 10).is_none());
+    }
+}
@@ -0,0 +1,363 @@
+//! Conversation threading (JWZ-style) over the emails of one PST.
+//!
+//! Messages are linked through Message-ID / References / In-Reply-To into a forest; each tree is
+//! a thread. Replies whose references were lost (common with Outlook-sent items) are then joined
+//! to the thread with the same normalized subject. A thread is identified by the Message-ID of
+//! its root, which is usually the first entry of every reply's References header, so the same
+//! conversation gets the same `thread_id` in every PST it appears in.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Threading inputs for one email record.
+pub struct ThreadInput {
+    pub email_id: String,
+    pub message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub references: Option<String>,
+    pub subject: Option<String>,
+    pub date_epoch: Option<i64>,
+}
+
+/// Result for one input, in input order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadAssignment {
+    /// Seed for the thread id: `mid:<root message-id>` or `email:<email id>`.
+    pub thread_key: String,
+    /// 0-based chronological position within the thread (undated messages last).
+    pub position: usize,
+}
+
+/// Thread summary recorded in the manifest.
+#[derive(Serialize, Default, Clone, Debug, PartialEq, Eq)]
+pub struct ThreadStats {
+    pub threads_total: usize,
+    pub threaded_emails: usize,
+    /// Threads containing a single email.
+    pub singleton_threads: usize,
+    pub largest_thread_size: usize,
+    /// Emails attached to a thread only by the normalized-subject fallback.
+    pub subject_joined_emails: usize,
+}
+
+/// `<abc@host>` tokens of a Message-ID style header, normalized for matching.
+fn message_ids(value: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('<') {
+        let Some(len) = rest[start..].find('>') else {
+            break;
+        };
+        let id = rest[start + 1..start + len].trim().to_ascii_lowercase();
+        if !id.is_empty() {
+            out.push(id);
+        }
+        rest = &rest[start + len + 1..];
+    }
+    if out.is_empty() {
+        // Some clients omit the angle brackets.
+        out.extend(
+            value
+                .split_whitespace()
+                .filter(|t| t.contains('@'))
+                .map(|t| t.to_ascii_lowercase()),
+        );
+    }
+    out
+}
+
+/// Subject with reply/forward prefixes (in the usual languages) and `[list]` tags removed,
+/// lowercased and whitespace-collapsed. The bool reports whether any reply/forward prefix was
+/// present.
+pub fn normalize_subject(subject: &str) -> (String, bool) {
+    const PREFIXES: &[&str] = &[
+        "re", "fw", "fwd", "aw", "wg", "sv", "vs", "antw", "tr", "rv", "ref", "r", "odp", "res",
+    ];
+    let mut s = subject.trim();
+    let mut is_reply = false;
+    loop {
+        if s.starts_with('[') {
+            if let Some(end) = s.find(']') {
+                s = s[end + 1..].trim_start();
+                continue;
+            }
+        }
+        let Some(colon) = s.find([':', '：']) else {
+            break;
+        };
+        let head = s[..colon].trim();
+        // "Re[2]" / "RE(3)" counters.
+        let word = head.split(['[', '(']).next().unwrap_or(head);
+        if PREFIXES.iter().any(|p| word.eq_ignore_ascii_case(p)) {
+            is_reply = true;
+            s = s[colon..].trim_start_matches([':', '：']).trim_start();
+        } else {
+            break;
+        }
+    }
+    let normalized = s
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    (normalized, is_reply)
+}
+
+struct Container {
+    key: String,
+    parent: Option<usize>,
+}
+
+struct Table {
+    containers: Vec<Container>,
+    by_key: HashMap<String, usize>,
+}
+
+impl Table {
+    fn get(&mut self, key: String) -> usize {
+        if let Some(&idx) = self.by_key.get(&key) {
+            return idx;
+        }
+        let idx = self.containers.len();
+        self.by_key.insert(key.clone(), idx);
+        self.containers.push(Container { key, parent: None });
+        idx
+    }
+
+    fn root(&self, mut idx: usize) -> usize {
+        // Parent links never form a cycle (see `is_ancestor` checks), but stay bounded anyway.
+        for _ in 0..self.containers.len() {
+            match self.containers[idx].parent {
+                Some(parent) => idx = parent,
+                None => break,
+            }
+        }
+        idx
+    }
+
+    fn is_ancestor(&self, ancestor: usize, mut idx: usize) -> bool {
+        for _ in 0..=self.containers.len() {
+            if idx == ancestor {
+                return true;
+            }
+            match self.containers[idx].parent {
+                Some(parent) => idx = parent,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    fn link(&mut self, parent: usize, child: usize) {
+        if !self.is_ancestor(child, parent) {
+            self.containers[child].parent = Some(parent);
+        }
+    }
+}
+
+/// Thread every input. Positions are chronological with ties broken by email id; reference
+/// conflicts between messages are resolved first-come, so the result is deterministic for the
+/// same inputs in the same order.
+pub fn assign(inputs: &[ThreadInput]) -> (Vec<ThreadAssignment>, ThreadStats) {
+    let mut table = Table {
+        containers: Vec::new(),
+        by_key: HashMap::new(),
+    };
+
+    // 1. Link reference chains. Messages without a Message-ID get a container of their own.
+    let mut message_container = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let own_key = input
+            .message_id
+            .as_deref()
+            .and_then(|m| message_ids(m).into_iter().next())
+            .map(|m| format!("mid:{m}"))
+            .unwrap_or_else(|| format!("email:{}", input.email_id));
+        let own = table.get(own_key);
+        message_container.push(own);
+
+        let mut refs = input
+            .references
+            .as_deref()
+            .map(message_ids)
+            .unwrap_or_default();
+        if let Some(parent) = input
+            .in_reply_to
+            .as_deref()
+            .and_then(|v| message_ids(v).into_iter().next())
+        {
+            if refs.last() != Some(&parent) {
+                refs.push(parent);
+            }
+        }
+        let chain: Vec<usize> = refs
+            .into_iter()
+            .map(|r| table.get(format!("mid:{r}")))
+            .collect();
+        for pair in chain.windows(2) {
+            if table.containers[pair[1]].parent.is_none() && pair[0] != pair[1] {
+                table.link(pair[0], pair[1]);
+            }
+        }
+        // The message's own references are authoritative for its parent.
+        if let Some(&parent) = chain.last() {
+            if parent != own {
+                table.link(parent, own);
+            }
+        }
+    }
+
+    // 2. Group messages by root container.
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, &container) in message_container.iter().enumerate() {
+        groups.entry(table.root(container)).or_default().push(i);
+    }
+    let sort_key = |i: &usize| {
+        let input = &inputs[*i];
+        (
+            input.date_epoch.is_none(),
+            input.date_epoch,
+            input.email_id.clone(),
+        )
+    };
+    let mut groups: Vec<(usize, Vec<usize>)> = groups.into_iter().collect();
+    for (_, members) in &mut groups {
+        members.sort_by_key(sort_key);
+    }
+    groups.sort_by_key(|(root, members)| {
+        (sort_key(&members[0]), table.containers[*root].key.clone())
+    });
+
+    // 3. Subject fallback: a thread that starts with a reply/forward joins the earliest thread
+    //    with the same normalized subject.
+    let mut by_subject: HashMap<String, usize> = HashMap::new();
+    let mut merged_into: Vec<usize> = (0..groups.len()).collect();
+    let mut subject_joined_emails = 0;
+    for (g, (_, members)) in groups.iter().enumerate() {
+        let subject = inputs[members[0]].subject.as_deref().unwrap_or("");
+        let (normalized, is_reply) = normalize_subject(subject);
+        if normalized.is_empty() {
+            continue;
+        }
+        match by_subject.get(&normalized) {
+            Some(&target) if is_reply => {
+                merged_into[g] = target;
+                subject_joined_emails += members.len();
+            }
+            Some(_) => {}
+            None => {
+                by_subject.insert(normalized, g);
+            }
+        }
+    }
+
+    let mut threads: Vec<Vec<usize>> = vec![Vec::new(); groups.len()];
+    for (g, (_, members)) in groups.iter().enumerate() {
+        threads[merged_into[g]].extend(members);
+    }
+
+    let mut assignments = vec![
+        ThreadAssignment {
+            thread_key: String::new(),
+            position: 0,
+        };
+        inputs.len()
+    ];
+    let mut stats = ThreadStats::default();
+    for (g, mut members) in threads.into_iter().enumerate() {
+        if members.is_empty() {
+            continue;
+        }
+        members.sort_by_key(sort_key);
+        let key = &table.containers[groups[g].0].key;
+        for (position, &i) in members.iter().enumerate() {
+            assignments[i] = ThreadAssignment {
+                thread_key: key.clone(),
+                position,
+            };
+        }
+        stats.threads_total += 1;
+        stats.threaded_emails += members.len();
+        stats.largest_thread_size = stats.largest_thread_size.max(members.len());
+        if members.len() == 1 {
+            stats.singleton_threads += 1;
+        }
+    }
+    stats.subject_joined_emails = subject_joined_emails;
+    (assignments, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(
+        id: &str,
+        mid: Option<&str>,
+        refs: Option<&str>,
+        subject: &str,
+        date: i64,
+    ) -> ThreadInput {
+        ThreadInput {
+            email_id: id.to_string(),
+            message_id: mid.map(str::to_string),
+            in_reply_to: None,
+            references: refs.map(str::to_string),
+            subject: Some(subject.to_string()),
+            date_epoch: Some(date),
+        }
+    }
+
+    #[test]
+    fn threads_by_references_then_subject() {
+        let inputs = vec![
+            // Reply whose parent (<b@x>) is not in this PST; root <a@x> is known via References.
+            input(
+                "e3",
+                Some("<c@x>"),
+                Some("<A@x> <b@x>"),
+                "RE: Re: Budget",
+                300,
+            ),
+            input("e1", Some("<a@x>"), None, "Budget", 100),
+            // Outlook-sent reply with no references: joined by subject.
+            input("e4", None, None, "AW: [ext] Budget", 400),
+            // Same subject but not a reply: its own thread.
+            input("e5", Some("<z@x>"), None, "budget", 500),
+            input("e2", Some("<q@x>"), None, "Lunch", 200),
+        ];
+        let (assigned, stats) = assign(&inputs);
+        assert_eq!(assigned[1].thread_key, "mid:a@x");
+        assert_eq!(assigned[0].thread_key, "mid:a@x");
+        assert_eq!(assigned[2].thread_key, "mid:a@x");
+        assert_eq!(
+            (
+                assigned[1].position,
+                assigned[0].position,
+                assigned[2].position
+            ),
+            (0, 1, 2)
+        );
+        assert_eq!(assigned[3].thread_key, "mid:z@x");
+        assert_eq!(assigned[4].thread_key, "mid:q@x");
+        assert_eq!(
+            stats,
+            ThreadStats {
+                threads_total: 3,
+                threaded_emails: 5,
+                singleton_threads: 2,
+                largest_thread_size: 3,
+                subject_joined_emails: 1,
+            }
+        );
+
+        assert_eq!(
+            normalize_subject("Re[2]: FW:  Q3   Plan"),
+            ("q3 plan".to_string(), true)
+        );
+        assert_eq!(
+            normalize_subject("Agenda: Monday"),
+            ("agenda: monday".to_string(), false)
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Per-day message volume (`timeseries.json`).
+//!
+//! The case dashboard charts communication volume as soon as a PST lands. The full email dataset
+//! isn't queryable yet at that point, so the extractor counts top-level emails per UTC day,
+//! split by direction (with `--internal-domains`) and by the class of folder they were filed in.
+
+use crate::scoring::Direction;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Coarse class of the folder an email was found in, from its readpst-relative path.
+pub fn folder_class(source_path: &str) -> &'static str {
+    for component in source_path.split(['/', '\\']).rev().skip(1) {
+        let name = component.to_ascii_lowercase();
+        let class = match name.as_str() {
+            "_recovered" => "recovered",
+            "inbox" => "inbox",
+            "sent items" | "sent" | "sent mail" | "sent messages" => "sent",
+            "drafts" => "drafts",
+            "deleted items" | "deleted" | "trash" | "bin" => "deleted",
+            "junk e-mail" | "junk email" | "junk" | "spam" => "junk",
+            "outbox" => "outbox",
+            "archive" | "archives" => "archive",
+            _ => continue,
+        };
+        return class;
+    }
+    "other"
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct Bucket {
+    pub total: u64,
+    /// `internal` / `outbound` / `inbound` / `external`, or `unknown` without internal domains.
+    pub direction: BTreeMap<&'static str, u64>,
+    pub folder: BTreeMap<&'static str, u64>,
+}
+
+impl Bucket {
+    fn add(&mut self, direction: Option<Direction>, folder: &'static str) {
+        self.total += 1;
+        let direction = match direction {
+            Some(Direction::Internal) => "internal",
+            Some(Direction::Outbound) => "outbound",
+            Some(Direction::Inbound) => "inbound",
+            Some(Direction::External) => "external",
+            None => "unknown",
+        };
+        *self.direction.entry(direction).or_default() += 1;
+        *self.folder.entry(folder).or_default() += 1;
+    }
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct TimeSeries {
+    pub bucket: &'static str,
+    /// `YYYY-MM-DD` (UTC) → counts, in date order.
+    pub days: BTreeMap<String, Bucket>,
+    /// Emails without a usable date.
+    pub undated: Bucket,
+}
+
+impl TimeSeries {
+    pub fn new() -> Self {
+        Self {
+            bucket: "day",
+            ..Default::default()
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        date_epoch: Option<i64>,
+        direction: Option<Direction>,
+        folder: &'static str,
+    ) {
+        let bucket = match date_epoch {
+            Some(epoch) => self
+                .days
+                .entry(crate::dates::format_day(epoch))
+                .or_default(),
+            None => &mut self.undated,
+        };
+        bucket.add(direction, folder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_by_utc_day_direction_and_folder() {
+        assert_eq!(
+            folder_class("Top of Personal Folders/Sent Items/12.eml"),
+            "sent"
+        );
+        assert_eq!(folder_class("Inbox/Projects/Falcon/3"), "inbox");
+        assert_eq!(folder_class("_recovered/Inbox/4"), "inbox");
+        assert_eq!(folder_class("_recovered/9"), "recovered");
+        assert_eq!(folder_class("Calendar/1"), "other");
+
+        let mut series = TimeSeries::new();
+        // 2024-03-04 23:59:59 and 2024-03-05 00:00:00 UTC.
+        series.add(Some(1_709_596_799), Some(Direction::Inbound), "inbox");
+        series.add(Some(1_709_596_800), None, "sent");
+        series.add(Some(1_709_596_800), Some(Direction::Outbound), "sent");
+        series.add(None, None, "other");
+        let json = serde_json::to_value(&series).expect("json");
+        assert_eq!(json["bucket"], "day");
+        assert_eq!(json["days"]["2024-03-04"]["direction"]["inbound"], 1);
+        assert_eq!(json["days"]["2024-03-05"]["total"], 2);
+        assert_eq!(json["days"]["2024-03-05"]["folder"]["sent"], 2);
+        assert_eq!(json["days"]["2024-03-05"]["direction"]["unknown"], 1);
+        assert_eq!(json["undated"]["total"], 1);
+    }
+}
@@ -0,0 +1,142 @@
+//! Parse timing statistics.
+//!
+//! Per-message and per-source-file parse durations are tracked so pathological content patterns
+//! can be found from the manifest, which lists the slowest items.
+
+use serde::Serialize;
+
+/// Entries kept in each "slowest" list.
+pub const SLOWEST_LIMIT: usize = 50;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TimedItem {
+    pub source_path: String,
+    /// Message position within the source file; None for whole-file entries.
+    pub message_index: Option<usize>,
+    pub email_id: Option<String>,
+    pub size_bytes: u64,
+    pub duration_ms: f64,
+}
+
+/// Keeps the `limit` slowest items seen, without storing every timing.
+pub struct SlowestItems {
+    limit: usize,
+    items: Vec<TimedItem>,
+}
+
+impl SlowestItems {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            items: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, item: TimedItem) {
+        self.items.push(item);
+        // Trim in batches so pushes stay amortized O(log n).
+        if self.items.len() >= self.limit * 2 {
+            self.trim();
+        }
+    }
+
+    fn trim(&mut self) {
+        self.items
+            .sort_by(|a, b| b.duration_ms.total_cmp(&a.duration_ms));
+        self.items.truncate(self.limit);
+    }
+
+    /// Slowest first.
+    pub fn into_sorted(mut self) -> Vec<TimedItem> {
+        self.trim();
+        self.items
+    }
+}
+
+/// Manifest section summarizing parse timings.
+#[derive(Serialize)]
+pub struct ParseTimingStats {
+    pub messages_timed: usize,
+    pub total_message_parse_ms: f64,
+    pub mean_message_parse_ms: f64,
+    pub files_timed: usize,
+    pub slowest_messages: Vec<TimedItem>,
+    pub slowest_files: Vec<TimedItem>,
+}
+
+pub struct ParseTimer {
+    messages: SlowestItems,
+    files: SlowestItems,
+    messages_timed: usize,
+    files_timed: usize,
+    total_message_ms: f64,
+}
+
+impl Default for ParseTimer {
+    fn default() -> Self {
+        Self {
+            messages: SlowestItems::new(SLOWEST_LIMIT),
+            files: SlowestItems::new(SLOWEST_LIMIT),
+            messages_timed: 0,
+            files_timed: 0,
+            total_message_ms: 0.0,
+        }
+    }
+}
+
+impl ParseTimer {
+    pub fn record_message(&mut self, item: TimedItem) {
+        self.messages_timed += 1;
+        self.total_message_ms += item.duration_ms;
+        self.messages.push(item);
+    }
+
+    pub fn record_file(&mut self, item: TimedItem) {
+        self.files_timed += 1;
+        self.files.push(item);
+    }
+
+    pub fn finish(self) -> ParseTimingStats {
+        ParseTimingStats {
+            messages_timed: self.messages_timed,
+            total_message_parse_ms: self.total_message_ms,
+            mean_message_parse_ms: if self.messages_timed == 0 {
+                0.0
+            } else {
+                self.total_message_ms / self.messages_timed as f64
+            },
+            files_timed: self.files_timed,
+            slowest_messages: self.messages.into_sorted(),
+            slowest_files: self.files.into_sorted(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(ms: f64) -> TimedItem {
+        TimedItem {
+            source_path: format!("f{ms}"),
+            message_index: Some(0),
+            email_id: None,
+            size_bytes: 1,
+            duration_ms: ms,
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_slowest_items_in_order() {
+        let mut slowest = SlowestItems::new(3);
+        for ms in [5.0, 1.0, 9.0, 3.0, 7.0, 2.0, 8.0] {
+            slowest.push(item(ms));
+        }
+        let ms: Vec<f64> = slowest
+            .into_sorted()
+            .iter()
+            .map(|i| i.duration_ms)
+            .collect();
+        assert_eq!(ms, vec![9.0, 8.0, 7.0]);
+    }
+}
@@ -0,0 +1,366 @@
+//! TNEF (`application/ms-tnef`, usually `winmail.dat`) decoding.
+//!
+//! Outlook-originated mail often carries a single winmail.dat part that wraps the real
+//! attachments and the rich-text body. The decoder walks the TNEF attribute stream and the
+//! embedded MAPI property blocks so those attachments can be extracted like any other MIME part.
+
+const TNEF_SIGNATURE: u32 = 0x223E_9F78;
+
+const LVL_MESSAGE: u8 = 0x01;
+const LVL_ATTACHMENT: u8 = 0x02;
+
+// Attribute ids (low 16 bits of the attribute tag).
+const ATT_BODY: u16 = 0x800C;
+const ATT_ATTACH_DATA: u16 = 0x800F;
+const ATT_ATTACH_TITLE: u16 = 0x8010;
+const ATT_ATTACH_REND_DATA: u16 = 0x9002;
+const ATT_MSG_PROPS: u16 = 0x9003;
+const ATT_ATTACHMENT: u16 = 0x9005;
+
+// MAPI property ids.
+const PR_BODY: u16 = 0x1000;
+const PR_RTF_COMPRESSED: u16 = 0x1009;
+const PR_BODY_HTML: u16 = 0x1013;
+const PR_ATTACH_DATA_BIN: u16 = 0x3701;
+const PR_ATTACH_FILENAME: u16 = 0x3704;
+const PR_ATTACH_LONG_FILENAME: u16 = 0x3707;
+const PR_ATTACH_MIME_TAG: u16 = 0x370E;
+const PR_ATTACH_CONTENT_ID: u16 = 0x3712;
+
+// MAPI property types.
+const PT_SHORT: u16 = 0x0002;
+const PT_LONG: u16 = 0x0003;
+const PT_FLOAT: u16 = 0x0004;
+const PT_DOUBLE: u16 = 0x0005;
+const PT_CURRENCY: u16 = 0x0006;
+const PT_APPTIME: u16 = 0x0007;
+const PT_ERROR: u16 = 0x000A;
+const PT_BOOLEAN: u16 = 0x000B;
+const PT_OBJECT: u16 = 0x000D;
+const PT_I8: u16 = 0x0014;
+const PT_STRING8: u16 = 0x001E;
+const PT_UNICODE: u16 = 0x001F;
+const PT_SYSTIME: u16 = 0x0040;
+const PT_CLSID: u16 = 0x0048;
+const PT_BINARY: u16 = 0x0102;
+const MV_FLAG: u16 = 0x1000;
+
+#[derive(Debug, Default)]
+pub struct TnefAttachment {
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    pub content_id: Option<String>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct TnefContent {
+    pub attachments: Vec<TnefAttachment>,
+    pub body_text: Option<String>,
+    pub body_html: Option<String>,
+    /// PR_RTF_COMPRESSED, still in compressed form (see `rtf::decompress`).
+    pub rtf_compressed: Option<Vec<u8>>,
+}
+
+pub fn is_tnef(data: &[u8]) -> bool {
+    read_u32(data, 0) == Some(TNEF_SIGNATURE)
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn pad4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// 8-bit TNEF strings are NUL-terminated in the sender's code page; treat them as UTF-8 with a
+/// Latin-1 fallback.
+fn string8(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let bytes = &bytes[..end];
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+fn unicode(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&u| u != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn non_empty(s: String) -> Option<String> {
+    let s = s.trim().to_string();
+    (!s.is_empty()).then_some(s)
+}
+
+/// One decoded MAPI property value; only the shapes the extractor uses are kept.
+enum PropValue<'a> {
+    Bytes(&'a [u8]),
+    String8(&'a [u8]),
+    Unicode(&'a [u8]),
+    Other,
+}
+
+impl PropValue<'_> {
+    fn as_string(&self) -> Option<String> {
+        match self {
+            PropValue::String8(b) | PropValue::Bytes(b) => non_empty(string8(b)),
+            PropValue::Unicode(b) => non_empty(unicode(b)),
+            PropValue::Other => None,
+        }
+    }
+
+    fn as_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            PropValue::String8(b) | PropValue::Bytes(b) | PropValue::Unicode(b) => Some(b.to_vec()),
+            PropValue::Other => None,
+        }
+    }
+}
+
+/// Walk a MAPI property block (attMsgProps / attAttachment) and call `visit(prop_id, value)` for
+/// each single-valued property. Stops quietly at the first malformed entry.
+fn walk_props<'a>(data: &'a [u8], mut visit: impl FnMut(u16, PropValue<'a>)) {
+    let Some(count) = read_u32(data, 0) else {
+        return;
+    };
+    let mut at = 4usize;
+    for _ in 0..count {
+        let (Some(prop_type), Some(prop_id)) = (read_u16(data, at), read_u16(data, at + 2)) else {
+            return;
+        };
+        at += 4;
+        if prop_id >= 0x8000 {
+            // Named property: GUID, kind, then a numeric id or a UTF-16 name.
+            at += 16;
+            match read_u32(data, at) {
+                Some(0) => at += 8,
+                Some(1) => {
+                    let Some(len) = read_u32(data, at + 4) else {
+                        return;
+                    };
+                    at += 8 + pad4(len as usize);
+                }
+                _ => return,
+            }
+        }
+
+        let multi = prop_type & MV_FLAG != 0;
+        let base_type = prop_type & !MV_FLAG;
+        let fixed = match base_type {
+            PT_SHORT | PT_LONG | PT_FLOAT | PT_ERROR | PT_BOOLEAN => Some(4),
+            PT_DOUBLE | PT_CURRENCY | PT_APPTIME | PT_I8 | PT_SYSTIME => Some(8),
+            PT_CLSID => Some(16),
+            PT_STRING8 | PT_UNICODE | PT_BINARY | PT_OBJECT => None,
+            _ => return,
+        };
+
+        let values = match fixed {
+            Some(_) if !multi => 1,
+            _ => match read_u32(data, at) {
+                Some(n) => {
+                    at += 4;
+                    n
+                }
+                None => return,
+            },
+        };
+
+        for _ in 0..values {
+            let value = match fixed {
+                Some(size) => {
+                    at += size;
+                    PropValue::Other
+                }
+                None => {
+                    let Some(len) = read_u32(data, at) else {
+                        return;
+                    };
+                    let start = at + 4;
+                    let Some(bytes) = data.get(start..start + len as usize) else {
+                        return;
+                    };
+                    at = start + pad4(len as usize);
+                    match base_type {
+                        PT_STRING8 => PropValue::String8(bytes),
+                        PT_UNICODE => PropValue::Unicode(bytes),
+                        // Embedded objects carry a 16-byte interface id before the data.
+                        PT_OBJECT => PropValue::Bytes(bytes.get(16..).unwrap_or_default()),
+                        _ => PropValue::Bytes(bytes),
+                    }
+                }
+            };
+            if !multi {
+                visit(prop_id, value);
+            }
+        }
+        if at > data.len() {
+            return;
+        }
+    }
+}
+
+/// Decode a TNEF stream. Returns None if the data is not TNEF; truncated streams yield whatever
+/// was decoded before the damage.
+pub fn parse(data: &[u8]) -> Option<TnefContent> {
+    if !is_tnef(data) {
+        return None;
+    }
+    let mut content = TnefContent::default();
+    let mut current: Option<TnefAttachment> = None;
+    // Signature (4) + legacy key (2).
+    let mut at = 6usize;
+
+    while at + 9 <= data.len() {
+        let level = data[at];
+        let tag = read_u32(data, at + 1)?;
+        let len = read_u32(data, at + 5)? as usize;
+        let start = at + 9;
+        let Some(value) = data.get(start..start + len) else {
+            break;
+        };
+        // Value is followed by a 16-bit checksum.
+        at = start + len + 2;
+        let id = (tag & 0xFFFF) as u16;
+
+        match (level, id) {
+            (LVL_ATTACHMENT, ATT_ATTACH_REND_DATA) => {
+                if let Some(done) = current.take() {
+                    content.attachments.push(done);
+                }
+                current = Some(TnefAttachment::default());
+            }
+            (LVL_ATTACHMENT, ATT_ATTACH_TITLE) => {
+                let att = current.get_or_insert_with(TnefAttachment::default);
+                if att.filename.is_none() {
+                    att.filename = non_empty(string8(value));
+                }
+            }
+            (LVL_ATTACHMENT, ATT_ATTACH_DATA) => {
+                current.get_or_insert_with(TnefAttachment::default).data = value.to_vec();
+            }
+            (LVL_ATTACHMENT, ATT_ATTACHMENT) => {
+                let att = current.get_or_insert_with(TnefAttachment::default);
+                walk_props(value, |prop_id, v| match prop_id {
+                    PR_ATTACH_LONG_FILENAME => {
+                        if let Some(name) = v.as_string() {
+                            att.filename = Some(name);
+                        }
+                    }
+                    PR_ATTACH_FILENAME if att.filename.is_none() => {
+                        att.filename = v.as_string();
+                    }
+                    PR_ATTACH_MIME_TAG => att.mime_type = v.as_string(),
+                    PR_ATTACH_CONTENT_ID => att.content_id = v.as_string(),
+                    PR_ATTACH_DATA_BIN if att.data.is_empty() => {
+                        att.data = v.as_bytes().unwrap_or_default();
+                    }
+                    _ => {}
+                });
+            }
+            (LVL_MESSAGE, ATT_BODY) => {
+                content.body_text = non_empty(string8(value));
+            }
+            (LVL_MESSAGE, ATT_MSG_PROPS) => {
+                walk_props(value, |prop_id, v| match prop_id {
+                    PR_BODY if content.body_text.is_none() => content.body_text = v.as_string(),
+                    PR_BODY_HTML => content.body_html = v.as_string(),
+                    PR_RTF_COMPRESSED => content.rtf_compressed = v.as_bytes(),
+                    _ => {}
+                });
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(done) = current.take() {
+        content.attachments.push(done);
+    }
+    content.attachments.retain(|a| !a.data.is_empty());
+    Some(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(out: &mut Vec<u8>, level: u8, tag: u32, value: &[u8]) {
+        out.push(level);
+        out.extend_from_slice(&tag.to_le_bytes());
+        out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        out.extend_from_slice(value);
+        let checksum = value
+            .iter()
+            .fold(0u16, |acc, &b| acc.wrapping_add(b as u16));
+        out.extend_from_slice(&checksum.to_le_bytes());
+    }
+
+    fn unicode_prop(prop_id: u16, value: &str) -> Vec<u8> {
+        let mut bytes: Vec<u8> = value.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+        bytes.extend_from_slice(&[0, 0]);
+        let mut out = Vec::new();
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&PT_UNICODE.to_le_bytes());
+        out.extend_from_slice(&prop_id.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        out.extend_from_slice(&bytes);
+        out.resize(pad4(out.len()), 0);
+        out
+    }
+
+    #[test]
+    fn extracts_attachments_and_bodies() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&TNEF_SIGNATURE.to_le_bytes());
+        data.extend_from_slice(&0x0001u16.to_le_bytes());
+        attr(&mut data, LVL_MESSAGE, 0x0002_800C, b"Plain body\0");
+        attr(
+            &mut data,
+            LVL_MESSAGE,
+            0x0006_9003,
+            &unicode_prop(PR_BODY_HTML, "<p>Hi</p>"),
+        );
+        attr(&mut data, LVL_ATTACHMENT, 0x0006_9002, &[0u8; 14]);
+        attr(&mut data, LVL_ATTACHMENT, 0x0001_8010, b"REPORT~1.PDF\0");
+        attr(&mut data, LVL_ATTACHMENT, 0x0006_800F, b"%PDF-1.4 data");
+        attr(
+            &mut data,
+            LVL_ATTACHMENT,
+            0x0006_9005,
+            &unicode_prop(PR_ATTACH_LONG_FILENAME, "Quarterly Report.pdf"),
+        );
+        attr(&mut data, LVL_ATTACHMENT, 0x0006_9002, &[0u8; 14]);
+        attr(&mut data, LVL_ATTACHMENT, 0x0001_8010, b"notes.txt\0");
+        attr(&mut data, LVL_ATTACHMENT, 0x0006_800F, b"hello");
+
+        let content = parse(&data).expect("tnef");
+        assert_eq!(content.body_text.as_deref(), Some("Plain body"));
+        assert_eq!(content.body_html.as_deref(), Some("<p>Hi</p>"));
+        assert_eq!(content.attachments.len(), 2);
+        assert_eq!(
+            content.attachments[0].filename.as_deref(),
+            Some("Quarterly Report.pdf")
+        );
+        assert_eq!(content.attachments[0].data, b"%PDF-1.4 data");
+        assert_eq!(
+            content.attachments[1].filename.as_deref(),
+            Some("notes.txt")
+        );
+        assert_eq!(content.attachments[1].data, b"hello");
+
+        assert!(parse(b"not tnef").is_none());
+    }
+}
@@ -0,0 +1,491 @@
+//! Uploads (`--upload-part-size`, `--upload-concurrency`).
+//!
+//! A file larger than the part size goes up as a multipart upload: a single PutObject tops out at
+//! 5 GB and runs on one connection, which makes big attachments and output files slow. Smaller
+//! files are one PutObject. Every request (a whole object or one part) takes a slot from a single
+//! process-wide pool of `--upload-concurrency`, so parallel attachment uploads, output uploads and
+//! the parts of one file together stay within it. A multipart upload that fails is aborted, so no
+//! orphaned parts are left behind to be billed. Azure and GCS buckets (see [`crate::storage`])
+//! share the same part size and pool.
+//!
+//! The binary sets the process-wide pool once at startup ([`configure`]). In-process extractors
+//! (see [`crate::api`]) each run [`with_settings`] instead, so two extractors with different
+//! settings get their own part size and pool rather than whichever configured first.
+//!
+//! Every object a job writes also carries the job's [`ObjectPolicy`] (`--kms-key-id`,
+//! `--storage-class`, `--object-tags`), so encryption with a customer-managed key and tags such as
+//! the case ID don't depend on bucket defaults or re-tagging after the fact.
+//!
+//! With `--output-dir` nothing goes to S3: each object is copied to `{output_dir}/{key}`, so the
+//! directory gets the same layout the bucket would.
+
+use crate::storage::{self, LocalStore, ObjectStore};
+use crate::ObjectMeta;
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_s3::operation::create_multipart_upload::builders::CreateMultipartUploadFluentBuilder;
+use aws_sdk_s3::operation::put_object::builders::PutObjectFluentBuilder;
+use aws_sdk_s3::primitives::{ByteStream, Length};
+use aws_sdk_s3::types::{
+    ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart, ServerSideEncryption, StorageClass,
+};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// S3's smallest part (except the last) and most parts per upload.
+pub const MIN_PART_SIZE: u64 = 5 << 20;
+const MAX_PARTS: u64 = 10_000;
+
+const DEFAULT_PART_SIZE: u64 = 16 << 20;
+const DEFAULT_CONCURRENCY: usize = 16;
+
+/// S3 limits on object tags.
+const MAX_TAGS: usize = 10;
+const MAX_TAG_KEY: usize = 128;
+const MAX_TAG_VALUE: usize = 256;
+
+struct Pool {
+    part_size: u64,
+    concurrency: usize,
+    permits: Arc<Semaphore>,
+}
+
+impl Pool {
+    fn new(part_size: u64, concurrency: usize) -> Arc<Self> {
+        let concurrency = concurrency.max(1);
+        Arc::new(Self {
+            part_size: part_size.max(MIN_PART_SIZE),
+            concurrency,
+            permits: Arc::new(Semaphore::new(concurrency)),
+        })
+    }
+}
+
+static POOL: OnceLock<Arc<Pool>> = OnceLock::new();
+
+tokio::task_local! {
+    static JOB_POOL: Arc<Pool>;
+}
+
+/// Set the process-wide part size and pool size. Called once at startup, before any upload.
+pub fn configure(part_size: u64, concurrency: usize) {
+    let _ = POOL.set(Pool::new(part_size, concurrency));
+}
+
+/// Run `job` with its own part size and pool instead of the process-wide ones.
+pub async fn with_settings<F: Future>(part_size: u64, concurrency: usize, job: F) -> F::Output {
+    JOB_POOL.scope(Pool::new(part_size, concurrency), job).await
+}
+
+fn pool() -> Arc<Pool> {
+    JOB_POOL.try_with(Arc::clone).unwrap_or_else(|_| {
+        Arc::clone(POOL.get_or_init(|| Pool::new(DEFAULT_PART_SIZE, DEFAULT_CONCURRENCY)))
+    })
+}
+
+pub fn part_size() -> u64 {
+    pool().part_size
+}
+
+/// A slot in the upload pool, held for one request.
+pub async fn slot() -> Result<OwnedSemaphorePermit> {
+    Ok(Arc::clone(&pool().permits).acquire_owned().await?)
+}
+
+/// Server-side encryption, storage class and tags applied to every object a job writes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectPolicy {
+    /// SSE-KMS with this key (ID, ARN or alias) when set.
+    pub kms_key_id: Option<String>,
+    pub storage_class: Option<StorageClass>,
+    /// URL-encoded `x-amz-tagging` value.
+    pub tagging: Option<String>,
+}
+
+/// Where and how a job's objects are written.
+#[derive(Debug, Clone, Default)]
+pub struct Target {
+    pub policy: ObjectPolicy,
+    /// `--output-dir`: write objects under this directory instead of S3.
+    pub local_dir: Option<PathBuf>,
+}
+
+tokio::task_local! {
+    static TARGET: Target;
+}
+
+impl ObjectPolicy {
+    /// Build from the job's `--kms-key-id`, `--storage-class` and `--object-tags` values.
+    pub fn new(
+        kms_key_id: Option<&str>,
+        storage_class: Option<&str>,
+        object_tags: Option<&str>,
+    ) -> Result<Self> {
+        fn non_empty(v: Option<&str>) -> Option<&str> {
+            v.map(str::trim).filter(|v| !v.is_empty())
+        }
+        let storage_class = non_empty(storage_class)
+            .map(|class| {
+                let class = class.to_ascii_uppercase();
+                if StorageClass::values().contains(&class.as_str()) {
+                    Ok(StorageClass::from(class.as_str()))
+                } else {
+                    Err(anyhow!(
+                        "unknown storage class {class:?} (one of {})",
+                        StorageClass::values().join(", ")
+                    ))
+                }
+            })
+            .transpose()?;
+        Ok(Self {
+            kms_key_id: non_empty(kms_key_id).map(str::to_string),
+            storage_class,
+            tagging: non_empty(object_tags).map(encode_tags).transpose()?,
+        })
+    }
+
+    /// Apply to a PutObject request.
+    pub fn put(&self, req: PutObjectFluentBuilder) -> PutObjectFluentBuilder {
+        self.put_unclassed(req)
+            .set_storage_class(self.storage_class.clone())
+    }
+
+    /// Apply encryption and tags but not the storage class, for objects that are read back
+    /// (the dedupe index) and must stay in an instantly readable class.
+    pub fn put_unclassed(&self, req: PutObjectFluentBuilder) -> PutObjectFluentBuilder {
+        req.set_server_side_encryption(self.sse())
+            .set_ssekms_key_id(self.kms_key_id.clone())
+            .set_tagging(self.tagging.clone())
+    }
+
+    /// Apply to a CreateMultipartUpload request; the parts inherit it.
+    pub fn create_multipart(
+        &self,
+        req: CreateMultipartUploadFluentBuilder,
+    ) -> CreateMultipartUploadFluentBuilder {
+        req.set_server_side_encryption(self.sse())
+            .set_ssekms_key_id(self.kms_key_id.clone())
+            .set_storage_class(self.storage_class.clone())
+            .set_tagging(self.tagging.clone())
+    }
+
+    fn sse(&self) -> Option<ServerSideEncryption> {
+        self.kms_key_id
+            .as_ref()
+            .map(|_| ServerSideEncryption::AwsKms)
+    }
+}
+
+/// Run `job` with its objects written to `target`.
+pub async fn with_target<F: Future>(target: Target, job: F) -> F::Output {
+    TARGET.scope(target, job).await
+}
+
+/// The running job's policy (the default outside [`with_target`]).
+pub fn policy() -> ObjectPolicy {
+    TARGET
+        .try_with(|target| target.policy.clone())
+        .unwrap_or_default()
+}
+
+fn local_dir() -> Option<PathBuf> {
+    TARGET
+        .try_with(|target| target.local_dir.clone())
+        .ok()
+        .flatten()
+}
+
+/// `--object-tags` form (`key=value,key2=value2`) to the URL-encoded `x-amz-tagging` form.
+fn encode_tags(value: &str) -> Result<String> {
+    let mut tags = Vec::new();
+    for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = pair
+            .split_once('=')
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .ok_or_else(|| anyhow!("object tag {pair:?} is not key=value"))?;
+        if key.is_empty() || key.chars().count() > MAX_TAG_KEY {
+            return Err(anyhow!(
+                "object tag key {key:?} must be 1-{MAX_TAG_KEY} characters"
+            ));
+        }
+        if value.chars().count() > MAX_TAG_VALUE {
+            return Err(anyhow!(
+                "object tag {key:?} value is over {MAX_TAG_VALUE} characters"
+            ));
+        }
+        tags.push(format!("{}={}", url_encode(key), url_encode(value)));
+    }
+    if tags.len() > MAX_TAGS {
+        return Err(anyhow!(
+            "{} object tags given; S3 allows {MAX_TAGS}",
+            tags.len()
+        ));
+    }
+    Ok(tags.join("&"))
+}
+
+pub fn url_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+/// `(offset, length)` of each part of a `size`-byte file. Parts grow beyond `part_size` when
+/// the file would otherwise need more than 10,000 of them.
+pub fn plan_parts(size: u64, part_size: u64) -> Vec<(u64, u64)> {
+    let part_size = part_size.max(size.div_ceil(MAX_PARTS)).max(1);
+    (0..size)
+        .step_by(part_size as usize)
+        .map(|offset| (offset, part_size.min(size - offset)))
+        .collect()
+}
+
+/// Upload a local file to `bucket` (any [`storage`] bucket, or `--output-dir`); returns its size
+/// in bytes.
+pub async fn put_file(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    meta: &ObjectMeta,
+) -> Result<u64> {
+    if let Some(root) = local_dir() {
+        return LocalStore { root }.put(key, path, meta).await;
+    }
+    storage::open(s3, bucket)?.put(key, path, meta).await
+}
+
+/// Whether `key` already exists in `bucket` (or under `--output-dir`).
+pub async fn exists(s3: &aws_sdk_s3::Client, bucket: &str, key: &str) -> Result<bool> {
+    if let Some(root) = local_dir() {
+        return LocalStore { root }.exists(key).await;
+    }
+    storage::open(s3, bucket)?.exists(key).await
+}
+
+/// Upload a local file to S3; returns its size in bytes.
+pub async fn put_s3(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    meta: &ObjectMeta,
+) -> Result<u64> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("stat {}", path.display()))?
+        .len();
+    let pool = pool();
+    let policy = policy();
+    if size <= pool.part_size {
+        let _slot = pool.permits.acquire().await?;
+        let body = ByteStream::from_path(path.to_path_buf())
+            .await
+            .with_context(|| format!("read {}", path.display()))?;
+        policy
+            .put(s3.put_object())
+            .bucket(bucket)
+            .key(key)
+            .body(body)
+            .set_content_type(meta.content_type.map(str::to_string))
+            .set_content_encoding(meta.content_encoding.map(str::to_string))
+            .send()
+            .await
+            .with_context(|| format!("upload s3://{bucket}/{key}"))?;
+        return Ok(size);
+    }
+
+    let upload_id = policy
+        .create_multipart(s3.create_multipart_upload())
+        .bucket(bucket)
+        .key(key)
+        .set_content_type(meta.content_type.map(str::to_string))
+        .set_content_encoding(meta.content_encoding.map(str::to_string))
+        .checksum_algorithm(ChecksumAlgorithm::Crc32)
+        .send()
+        .await
+        .with_context(|| format!("start multipart upload s3://{bucket}/{key}"))?
+        .upload_id()
+        .ok_or_else(|| anyhow!("no upload id for s3://{bucket}/{key}"))?
+        .to_string();
+    let result = match upload_parts(s3, bucket, key, path, size, &upload_id, &pool).await {
+        Ok(parts) => s3
+            .complete_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map(|_| ())
+            .with_context(|| format!("complete multipart upload s3://{bucket}/{key}")),
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        if let Err(e) = s3
+            .abort_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .send()
+            .await
+        {
+            tracing::warn!(%bucket, %key, "failed to abort multipart upload: {e}");
+        }
+    }
+    result.map(|_| size)
+}
+
+async fn upload_parts(
+    s3: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    size: u64,
+    upload_id: &str,
+    pool: &Pool,
+) -> Result<Vec<CompletedPart>> {
+    let mut parts: Vec<CompletedPart> =
+        stream::iter(plan_parts(size, pool.part_size).into_iter().zip(1..))
+            .map(|((offset, length), number)| async move {
+                let _slot = pool.permits.acquire().await?;
+                let body = ByteStream::read_from()
+                    .path(path)
+                    .offset(offset)
+                    .length(Length::Exact(length))
+                    .build()
+                    .await
+                    .with_context(|| format!("read {}", path.display()))?;
+                let resp = s3
+                    .upload_part()
+                    .bucket(bucket)
+                    .key(key)
+                    .upload_id(upload_id)
+                    .part_number(number)
+                    .checksum_algorithm(ChecksumAlgorithm::Crc32)
+                    .body(body)
+                    .send()
+                    .await
+                    .with_context(|| format!("upload part {number} of s3://{bucket}/{key}"))?;
+                Ok::<_, anyhow::Error>(
+                    CompletedPart::builder()
+                        .part_number(number)
+                        .set_e_tag(resp.e_tag().map(str::to_string))
+                        .set_checksum_crc32(resp.checksum_crc32().map(str::to_string))
+                        .build(),
+                )
+            })
+            .buffer_unordered(pool.concurrency)
+            .try_collect()
+            .await?;
+    parts.sort_by_key(|part| part.part_number());
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn job_settings_apply_only_inside_the_job() {
+        assert_eq!(with_settings(64 << 20, 2, async { part_size() }).await, 64 << 20);
+        // Clamped to S3's minimum like the process-wide setting.
+        assert_eq!(with_settings(1, 2, async { part_size() }).await, MIN_PART_SIZE);
+        let permits = with_settings(MIN_PART_SIZE, 2, async {
+            let _held = slot().await.expect("slot");
+            pool().permits.available_permits()
+        });
+        assert_eq!(permits.await, 1);
+    }
+
+    #[test]
+    fn plans_parts_within_s3_limits() {
+        assert_eq!(
+            plan_parts(12 << 20, 5 << 20),
+            [(0, 5 << 20), (5 << 20, 5 << 20), (10 << 20, 2 << 20)]
+        );
+        assert_eq!(plan_parts(5 << 20, 5 << 20), [(0, 5 << 20)]);
+        assert!(plan_parts(0, 5 << 20).is_empty());
+
+        // 200 GB at 16 MiB would be 11,921 parts.
+        let parts = plan_parts(200_000_000_000, 16 << 20);
+        assert!(parts.len() <= 10_000);
+        assert_eq!(
+            parts.iter().map(|(_, len)| len).sum::<u64>(),
+            200_000_000_000
+        );
+        let (last_offset, last_len) = parts[parts.len() - 1];
+        assert_eq!(last_offset + last_len, 200_000_000_000);
+    }
+
+    #[test]
+    fn builds_object_policy() {
+        let policy = ObjectPolicy::new(
+            Some("alias/case-keys"),
+            Some("intelligent_tiering"),
+            Some("case-id=C 17/b, matter=Acme & Co,"),
+        )
+        .expect("policy");
+        assert_eq!(policy.storage_class, Some(StorageClass::IntelligentTiering));
+        assert_eq!(
+            policy.tagging.as_deref(),
+            Some("case-id=C%2017%2Fb&matter=Acme%20%26%20Co")
+        );
+        assert_eq!(policy.sse(), Some(ServerSideEncryption::AwsKms));
+
+        assert_eq!(
+            ObjectPolicy::new(None, Some(""), None).expect("empty"),
+            ObjectPolicy::default()
+        );
+        assert!(ObjectPolicy::new(None, Some("COLD"), None).is_err());
+        assert!(ObjectPolicy::new(None, None, Some("case-id")).is_err());
+        assert!(ObjectPolicy::new(None, None, Some("=x")).is_err());
+        let eleven = (0..11)
+            .map(|i| format!("t{i}=v"))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert!(ObjectPolicy::new(None, None, Some(&eleven)).is_err());
+    }
+
+    #[tokio::test]
+    async fn writes_under_output_dir() {
+        let dir = std::env::temp_dir().join(format!("upload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let src = dir.join("src.txt");
+        std::fs::write(&src, b"hello").expect("src");
+        let out = dir.join("out");
+        let target = Target {
+            local_dir: Some(out.clone()),
+            ..Target::default()
+        };
+        let s3 = aws_sdk_s3::Client::from_conf(
+            aws_sdk_s3::Config::builder()
+                .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+                .build(),
+        );
+        let meta = ObjectMeta::default();
+        let size = with_target(target.clone(), put_file(&s3, "", "p/a/b.txt", &src, &meta))
+            .await
+            .expect("copy");
+        assert_eq!(size, 5);
+        assert_eq!(
+            std::fs::read(out.join("p/a/b.txt")).expect("copied"),
+            b"hello"
+        );
+        assert!(with_target(target, put_file(&s3, "", "../x", &src, &meta))
+            .await
+            .is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
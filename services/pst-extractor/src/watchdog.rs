@@ -0,0 +1,206 @@
+//! Total job time budget (`--max-duration`) and SIGTERM handling.
+//!
+//! AWS Batch hard-kills a job at its timeout and everything processed so far is lost. With a
+//! budget below that timeout the extractor winds down on its own, in stages:
+//!
+//! 1. readpst gets the first half of the budget. If it is still running then, it is killed and
+//!    the messages it already wrote are parsed.
+//! 2. Parsing stops taking new messages once the whole budget is spent.
+//! 3. Outputs and the manifest are finalized and uploaded as usual, with `partial: true` and the
+//!    `cutoff` point (stage, elapsed time, first unprocessed file and message).
+//!
+//! Leave room between the budget and the Batch timeout for step 3.
+//!
+//! SIGTERM (sent by ECS/Batch before the task is killed, 30 s by default) takes the same path
+//! immediately: readpst is killed, the message being parsed and its uploads are finished, the
+//! outputs are flushed and uploaded, and the manifest gets `status: "interrupted"`. The process
+//! then exits with [`EXIT_INTERRUPTED`].
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Exit code after a SIGTERM-interrupted job (`EX_TEMPFAIL`), so a retry strategy can tell it
+/// apart from a failure.
+pub const EXIT_INTERRUPTED: i32 = 75;
+
+static TERMINATING: AtomicBool = AtomicBool::new(false);
+
+/// Start listening for SIGTERM. Needs a Tokio runtime.
+pub fn install_sigterm_handler() {
+    #[cfg(unix)]
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(mut signal) => {
+            tokio::spawn(async move {
+                if signal.recv().await.is_some() {
+                    tracing::warn!("SIGTERM received; finishing in-flight work");
+                    TERMINATING.store(true, Ordering::Relaxed);
+                }
+            });
+        }
+        Err(e) => tracing::warn!("cannot install SIGTERM handler: {e}"),
+    }
+}
+
+/// Whether SIGTERM has been received.
+pub fn terminating() -> bool {
+    TERMINATING.load(Ordering::Relaxed)
+}
+
+/// Why a job stopped taking new work.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stop {
+    MaxDuration,
+    Interrupted,
+}
+
+impl Stop {
+    /// Manifest and callback `status`.
+    pub fn status(self) -> &'static str {
+        match self {
+            Stop::MaxDuration => "partial",
+            Stop::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// `--max-duration` value: seconds, or a number with an `s`, `m` or `h` suffix (`90m`).
+pub fn parse_duration_secs(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&value[..i], c.to_ascii_lowercase()),
+        _ => (value, 's'),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid duration {value:?}"))?;
+    match unit {
+        's' => Ok(number),
+        'm' => Ok(number * 60),
+        'h' => Ok(number * 3600),
+        _ => Err(format!(
+            "invalid duration unit in {value:?} (use s, m or h)"
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct JobDeadline {
+    started: Instant,
+    limit: Option<Duration>,
+}
+
+impl JobDeadline {
+    /// `limit_secs` of None or 0 means no budget.
+    pub fn new(started: Instant, limit_secs: Option<u64>) -> Self {
+        Self {
+            started,
+            limit: limit_secs.filter(|s| *s > 0).map(Duration::from_secs),
+        }
+    }
+
+    /// When a still-running readpst is stopped.
+    pub fn readpst_deadline(&self) -> Option<Instant> {
+        self.limit.map(|limit| self.started + limit / 2)
+    }
+
+    /// Whether the budget is spent.
+    pub fn expired(&self, now: Instant) -> bool {
+        self.limit
+            .is_some_and(|limit| now.duration_since(self.started) >= limit)
+    }
+
+    /// Why new messages should no longer be taken, if they shouldn't.
+    pub fn stop(&self, now: Instant) -> Option<Stop> {
+        if terminating() {
+            Some(Stop::Interrupted)
+        } else if self.expired(now) {
+            Some(Stop::MaxDuration)
+        } else {
+            None
+        }
+    }
+
+    pub fn elapsed_s(&self, now: Instant) -> f64 {
+        now.duration_since(self.started).as_secs_f64()
+    }
+}
+
+/// Where a job stopped when it ran out of time or was interrupted.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Cutoff {
+    pub reason: Stop,
+    /// `readpst` (readpst was stopped; what it wrote was still parsed) and/or `parse`.
+    pub stages: Vec<&'static str>,
+    /// Job time when the last stage was cut.
+    pub elapsed_s: f64,
+    /// First file and message not processed (parse stage only).
+    pub source_path: Option<String>,
+    pub message_index: Option<usize>,
+    pub files_done: u64,
+    pub files_total: u64,
+}
+
+/// Note that `stage` was cut short for `reason`, `elapsed_s` into the job.
+pub fn record<'a>(
+    cutoff: &'a mut Option<Cutoff>,
+    stage: &'static str,
+    reason: Stop,
+    elapsed_s: f64,
+) -> &'a mut Cutoff {
+    let cutoff = cutoff.get_or_insert_with(|| Cutoff {
+        reason,
+        stages: Vec::new(),
+        elapsed_s,
+        source_path: None,
+        message_index: None,
+        files_done: 0,
+        files_total: 0,
+    });
+    cutoff.stages.push(stage);
+    cutoff.reason = reason;
+    cutoff.elapsed_s = elapsed_s;
+    cutoff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_budgets_and_stages_deadlines() {
+        assert_eq!(parse_duration_secs("5400"), Ok(5400));
+        assert_eq!(parse_duration_secs("90m"), Ok(5400));
+        assert_eq!(parse_duration_secs("2H"), Ok(7200));
+        assert!(parse_duration_secs("2d").is_err());
+        assert!(parse_duration_secs("soon").is_err());
+
+        let start = Instant::now();
+        let deadline = JobDeadline::new(start, Some(600));
+        assert_eq!(
+            deadline.readpst_deadline(),
+            Some(start + Duration::from_secs(300))
+        );
+        assert!(!deadline.expired(start + Duration::from_secs(599)));
+        assert!(deadline.expired(start + Duration::from_secs(600)));
+        assert_eq!(
+            deadline.stop(start + Duration::from_secs(600)),
+            Some(Stop::MaxDuration)
+        );
+
+        let mut cutoff = None;
+        record(&mut cutoff, "readpst", Stop::MaxDuration, 300.0);
+        record(&mut cutoff, "parse", Stop::Interrupted, 600.5).message_index = Some(3);
+        let cutoff = cutoff.expect("cutoff");
+        assert_eq!(cutoff.stages, ["readpst", "parse"]);
+        assert_eq!(cutoff.reason.status(), "interrupted");
+        assert_eq!(cutoff.elapsed_s, 600.5);
+        assert_eq!(cutoff.message_index, Some(3));
+
+        let unlimited = JobDeadline::new(start, Some(0));
+        assert!(unlimited.readpst_deadline().is_none());
+        assert!(!unlimited.expired(start + Duration::from_secs(1 << 30)));
+    }
+}
@@ -0,0 +1,282 @@
+//! Long-running SQS worker mode.
+//!
+//! Instead of launching one container per PST, the worker polls a queue for job messages, runs
+//! them one at a time, and deletes each message on success. Visibility is extended while a job
+//! runs so long extractions aren't redelivered to another worker mid-flight.
+//!
+//! On SIGTERM the worker stops polling. A job cut short by it keeps its partial outputs, but its
+//! message is made visible again right away so another worker redoes the PST.
+//!
+//! With `--rules`, the rules file is re-read before a job once `--rules-poll-secs` have passed
+//! since the last read, so rule changes reach the next job without restarting the worker.
+
+use crate::rules::RulesSource;
+use crate::{read_text_input, run_job, watchdog, Args};
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_sqs::types::MessageAttributeValue;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// Long-poll wait per ReceiveMessage call (the SQS maximum).
+const RECEIVE_WAIT_SECS: i32 = 20;
+
+pub async fn run(base: &Args, cfg: &aws_config::SdkConfig, s3: &aws_sdk_s3::Client) -> Result<()> {
+    let queue_url = base
+        .queue_url
+        .clone()
+        .ok_or_else(|| anyhow!("--worker requires --queue-url"))?;
+    let sqs = aws_sdk_sqs::Client::new(cfg);
+    let visibility = base.visibility_timeout_secs.max(30);
+
+    info!(%queue_url, visibility_timeout_secs = visibility, "worker polling");
+
+    let rules_dir = Path::new(&base.work_dir).join("rules");
+    let mut rules = base
+        .rules
+        .as_deref()
+        .map(|location| RulesSource::new(location, Duration::from_secs(base.rules_poll_secs)));
+    if let Some(source) = rules.as_mut() {
+        std::fs::create_dir_all(&rules_dir)?;
+        refresh_rules(source, s3, &rules_dir).await?;
+    }
+
+    while !watchdog::terminating() {
+        let resp = sqs
+            .receive_message()
+            .queue_url(&queue_url)
+            .max_number_of_messages(1)
+            .wait_time_seconds(RECEIVE_WAIT_SECS)
+            .visibility_timeout(visibility)
+            .send()
+            .await
+            .context("receive SQS message")?;
+
+        for message in resp.messages() {
+            let (Some(body), Some(receipt)) = (message.body(), message.receipt_handle()) else {
+                continue;
+            };
+            if watchdog::terminating() {
+                // Received during the last long poll; leave it for another worker.
+                release(&sqs, &queue_url, receipt).await?;
+                continue;
+            }
+
+            let heartbeat = tokio::spawn(extend_visibility(
+                sqs.clone(),
+                queue_url.clone(),
+                receipt.to_string(),
+                visibility,
+            ));
+            if let Some(source) = rules.as_mut() {
+                refresh_rules(source, s3, &rules_dir).await?;
+            }
+            let active = rules.as_ref().and_then(RulesSource::current);
+            let result = match job_args(base, body) {
+                Ok(args) => run_job(&args, cfg, s3, active.as_deref()).await,
+                Err(e) => Err(e),
+            };
+            heartbeat.abort();
+
+            match result {
+                Ok(summary) if summary.interrupted() => {
+                    warn!("job interrupted; returning its message to the queue");
+                    release(&sqs, &queue_url, receipt).await?;
+                }
+                Ok(_) => {
+                    sqs.delete_message()
+                        .queue_url(&queue_url)
+                        .receipt_handle(receipt)
+                        .send()
+                        .await
+                        .context("delete SQS message")?;
+                }
+                Err(e) => {
+                    error!("job failed: {e:#}");
+                    handle_failure(&sqs, base, &queue_url, body, receipt, &e).await?;
+                }
+            }
+        }
+    }
+    info!("SIGTERM received; worker stopped");
+    Ok(())
+}
+
+/// Make a message visible again immediately.
+async fn release(sqs: &aws_sdk_sqs::Client, queue_url: &str, receipt: &str) -> Result<()> {
+    sqs.change_message_visibility()
+        .queue_url(queue_url)
+        .receipt_handle(receipt)
+        .visibility_timeout(0)
+        .send()
+        .await
+        .context("release SQS message")?;
+    Ok(())
+}
+
+/// Re-read the rules file if it is due. A file that can't be read or parsed keeps the previous
+/// rules active; only the first load is fatal.
+async fn refresh_rules(
+    source: &mut RulesSource,
+    s3: &aws_sdk_s3::Client,
+    scratch: &Path,
+) -> Result<()> {
+    let now = Instant::now();
+    if !source.due(now) {
+        return Ok(());
+    }
+    let loaded = match read_text_input(s3, &source.location, scratch).await {
+        Ok(text) => source.update(&text, now),
+        Err(e) => Err(e),
+    };
+    match (loaded, source.current()) {
+        (Ok(true), Some(active)) => info!(rules_version = %active.version, "rules active"),
+        (Ok(_), _) => {}
+        (Err(e), None) => return Err(e.context(format!("load rules {}", source.location))),
+        (Err(e), Some(active)) => warn!(
+            rules = %source.location,
+            rules_version = %active.version,
+            "rules reload failed, keeping the active version: {e:#}"
+        ),
+    }
+    Ok(())
+}
+
+/// Overlay a job message (a JSON object with Args field names) on the worker's own arguments.
+fn job_args(base: &Args, body: &str) -> Result<Args> {
+    let overrides: serde_json::Value =
+        serde_json::from_str(body).context("job message is not valid JSON")?;
+    let overrides = overrides
+        .as_object()
+        .ok_or_else(|| anyhow!("job message must be a JSON object"))?;
+    let args = overlay(base, overrides).context("invalid job message")?;
+    for (name, value) in [
+        ("pst_file_id", &args.pst_file_id),
+        ("source_bucket", &args.source_bucket),
+        ("source_key", &args.source_key),
+        ("output_bucket", &args.output_bucket),
+        ("output_prefix", &args.output_prefix),
+    ] {
+        if value.is_empty() {
+            return Err(anyhow!("job message missing {name}"));
+        }
+    }
+    crate::diskspace::check_job_id(&args.pst_file_id)?;
+    Ok(args)
+}
+
+/// `base` with the fields of `overrides` (Args field names) replaced.
+pub(crate) fn overlay(
+    base: &Args,
+    overrides: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Args> {
+    let mut merged = serde_json::to_value(base)?;
+    let fields = merged
+        .as_object_mut()
+        .expect("Args serializes to an object");
+    for (key, value) in overrides {
+        if !fields.contains_key(key) {
+            return Err(anyhow!("unknown job field {key:?}"));
+        }
+        fields.insert(key.clone(), value.clone());
+    }
+    Ok(serde_json::from_value(merged)?)
+}
+
+async fn extend_visibility(
+    sqs: aws_sdk_sqs::Client,
+    queue_url: String,
+    receipt: String,
+    visibility: i32,
+) {
+    let interval = Duration::from_secs((visibility / 2).max(1) as u64);
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = sqs
+            .change_message_visibility()
+            .queue_url(&queue_url)
+            .receipt_handle(&receipt)
+            .visibility_timeout(visibility)
+            .send()
+            .await
+        {
+            warn!("failed to extend message visibility: {e}");
+        }
+    }
+}
+
+async fn handle_failure(
+    sqs: &aws_sdk_sqs::Client,
+    base: &Args,
+    queue_url: &str,
+    body: &str,
+    receipt: &str,
+    error: &anyhow::Error,
+) -> Result<()> {
+    match &base.dlq_url {
+        Some(dlq) => {
+            let detail = MessageAttributeValue::builder()
+                .data_type("String")
+                .string_value(format!("{error:#}"))
+                .build()?;
+            sqs.send_message()
+                .queue_url(dlq)
+                .message_body(body)
+                .message_attributes("error", detail)
+                .send()
+                .await
+                .context("send to DLQ")?;
+            sqs.delete_message()
+                .queue_url(queue_url)
+                .receipt_handle(receipt)
+                .send()
+                .await
+                .context("delete SQS message")?;
+        }
+        None => {
+            // Make the message visible again right away; the queue's redrive policy moves it to
+            // its DLQ after maxReceiveCount attempts.
+            sqs.change_message_visibility()
+                .queue_url(queue_url)
+                .receipt_handle(receipt)
+                .visibility_timeout(0)
+                .send()
+                .await
+                .context("release SQS message")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn job_message_overrides_worker_args() {
+        let base = Args::parse_from([
+            "pst-extractor",
+            "--worker",
+            "--queue-url",
+            "https://sqs.example/q",
+            "--work-dir",
+            "/data",
+        ]);
+        let args = job_args(
+            &base,
+            r#"{"pst_file_id":"p1","source_bucket":"in","source_key":"a.pst",
+                "output_bucket":"out","output_prefix":"x/","case_id":"c9",
+                "attachments_for":"tagged-only","custodian_id":"cust-7"}"#,
+        )
+        .expect("job args");
+        assert_eq!(args.pst_file_id, "p1");
+        assert_eq!(args.case_id, "c9");
+        assert_eq!(args.custodian_id, "cust-7");
+        assert_eq!(args.work_dir, "/data");
+        assert_eq!(args.attachments_for, crate::AttachmentsFor::TaggedOnly);
+
+        assert!(job_args(&base, r#"{"pst_file_id":"p1"}"#).is_err());
+        assert!(job_args(&base, r#"{"bogus":1}"#).is_err());
+    }
+}
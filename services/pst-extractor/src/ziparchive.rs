@@ -0,0 +1,297 @@
+//! Minimal ZIP reader for archives of loose mail files and ZIP attachments (stored and deflate
+//! entries), and a matching writer for family exports.
+//!
+//! Entries are located through the central directory and read one at a time with seeks, so a
+//! multi-gigabyte archive never has to be held in memory. ZIP64 and encrypted entries are listed
+//! but can't be read, and the writer stops at the classic 4 GiB / 65535-entry limits.
+
+use anyhow::{anyhow, Context, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const EOCD_SIGNATURE: &[u8] = b"PK\x05\x06";
+const CENTRAL_SIGNATURE: &[u8] = b"PK\x01\x02";
+const LOCAL_SIGNATURE: &[u8] = b"PK\x03\x04";
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(LOCAL_SIGNATURE) || data.starts_with(EOCD_SIGNATURE)
+}
+
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    /// Path inside the archive, `/`-separated.
+    pub name: String,
+    pub compressed_size: u64,
+    pub size: u64,
+    method: u16,
+    encrypted: bool,
+    local_offset: u64,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+}
+
+pub struct ZipArchive<R> {
+    reader: R,
+    entries: Vec<ZipEntry>,
+}
+
+fn u16_at(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([b[at], b[at + 1], b[at + 2], b[at + 3]])
+}
+
+impl<R: Read + Seek> ZipArchive<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        // End-of-central-directory record: 22 bytes plus a comment of up to 64 KiB.
+        let tail_len = len.min(22 + 0xFFFF);
+        reader.seek(SeekFrom::Start(len - tail_len))?;
+        let mut tail = vec![0u8; tail_len as usize];
+        reader.read_exact(&mut tail)?;
+        let eocd = tail
+            .windows(4)
+            .rposition(|w| w == EOCD_SIGNATURE)
+            .filter(|&at| at + 22 <= tail.len())
+            .ok_or_else(|| anyhow!("zip: end of central directory not found"))?;
+        let count = u16_at(&tail, eocd + 10) as usize;
+        let dir_size = u32_at(&tail, eocd + 12) as usize;
+        let dir_offset = u32_at(&tail, eocd + 16) as u64;
+        if dir_offset == 0xFFFF_FFFF || count == 0xFFFF {
+            return Err(anyhow!("zip: ZIP64 archives are not supported"));
+        }
+        reader.seek(SeekFrom::Start(dir_offset))?;
+        let mut dir = vec![0u8; dir_size];
+        reader
+            .read_exact(&mut dir)
+            .context("zip: read central directory")?;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut at = 0usize;
+        while at + 46 <= dir.len() && &dir[at..at + 4] == CENTRAL_SIGNATURE {
+            let flags = u16_at(&dir, at + 8);
+            let name_len = u16_at(&dir, at + 28) as usize;
+            let extra_len = u16_at(&dir, at + 30) as usize;
+            let comment_len = u16_at(&dir, at + 32) as usize;
+            let Some(name) = dir.get(at + 46..at + 46 + name_len) else {
+                break;
+            };
+            entries.push(ZipEntry {
+                name: String::from_utf8_lossy(name).replace('\\', "/"),
+                method: u16_at(&dir, at + 10),
+                encrypted: flags & 0x0001 != 0,
+                compressed_size: u32_at(&dir, at + 20) as u64,
+                size: u32_at(&dir, at + 24) as u64,
+                local_offset: u32_at(&dir, at + 42) as u64,
+            });
+            at += 46 + name_len + extra_len + comment_len;
+        }
+        Ok(Self { reader, entries })
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Decompressed contents of entry `idx`.
+    pub fn read(&mut self, idx: usize) -> Result<Vec<u8>> {
+        let entry = self.entries[idx].clone();
+        if entry.encrypted {
+            return Err(anyhow!("zip: {} is encrypted", entry.name));
+        }
+        if entry.size == 0xFFFF_FFFF || entry.compressed_size == 0xFFFF_FFFF {
+            return Err(anyhow!("zip: {} needs ZIP64", entry.name));
+        }
+        self.reader.seek(SeekFrom::Start(entry.local_offset))?;
+        let mut local = [0u8; 30];
+        self.reader.read_exact(&mut local)?;
+        if &local[..4] != LOCAL_SIGNATURE {
+            return Err(anyhow!("zip: bad local header for {}", entry.name));
+        }
+        let skip = u16_at(&local, 26) as i64 + u16_at(&local, 28) as i64;
+        self.reader.seek(SeekFrom::Current(skip))?;
+        let raw = (&mut self.reader).take(entry.compressed_size);
+        let mut out = Vec::with_capacity(entry.size as usize);
+        match entry.method {
+            0 => {
+                raw.take(entry.size).read_to_end(&mut out)?;
+            }
+            8 => {
+                flate2::read::DeflateDecoder::new(raw)
+                    .take(entry.size)
+                    .read_to_end(&mut out)
+                    .with_context(|| format!("zip: inflate {}", entry.name))?;
+            }
+            m => return Err(anyhow!("zip: {} uses unsupported method {m}", entry.name)),
+        }
+        Ok(out)
+    }
+}
+
+/// Streams a ZIP of deflated entries to `out`; the central directory is written by `finish`.
+pub struct ZipWriter<W: Write> {
+    out: W,
+    offset: u64,
+    central: Vec<u8>,
+    count: usize,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            offset: 0,
+            central: Vec::new(),
+            count: 0,
+        }
+    }
+
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let mut enc =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(data)?;
+        let packed = enc.finish()?;
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let offset = self.offset;
+        if offset + 30 + name.len() as u64 + packed.len() as u64 > u32::MAX as u64
+            || data.len() as u64 > u32::MAX as u64
+            || self.count == 0xFFFF
+        {
+            return Err(anyhow!("zip: {name} would need ZIP64"));
+        }
+        // Version, UTF-8 names flag, deflate, 1980-01-01 00:00, crc and sizes, name/extra lengths.
+        let mut fields = Vec::with_capacity(26);
+        fields.extend(20u16.to_le_bytes());
+        fields.extend(0x0800u16.to_le_bytes());
+        fields.extend(8u16.to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+        fields.extend(0x0021u16.to_le_bytes());
+        fields.extend(crc.sum().to_le_bytes());
+        fields.extend((packed.len() as u32).to_le_bytes());
+        fields.extend((data.len() as u32).to_le_bytes());
+        fields.extend((name.len() as u16).to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+
+        self.out.write_all(LOCAL_SIGNATURE)?;
+        self.out.write_all(&fields)?;
+        self.out.write_all(name.as_bytes())?;
+        self.out.write_all(&packed)?;
+        self.offset += 30 + name.len() as u64 + packed.len() as u64;
+
+        self.central.extend(CENTRAL_SIGNATURE);
+        self.central.extend(20u16.to_le_bytes());
+        self.central.extend(&fields);
+        self.central.extend([0u8; 10]); // comment len, disk, internal and external attrs
+        self.central.extend((offset as u32).to_le_bytes());
+        self.central.extend(name.as_bytes());
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        if self.offset + self.central.len() as u64 > u32::MAX as u64 {
+            return Err(anyhow!("zip: central directory would need ZIP64"));
+        }
+        self.out.write_all(&self.central)?;
+        self.out.write_all(EOCD_SIGNATURE)?;
+        self.out.write_all(&[0u8; 4])?;
+        self.out.write_all(&(self.count as u16).to_le_bytes())?;
+        self.out.write_all(&(self.count as u16).to_le_bytes())?;
+        self.out
+            .write_all(&(self.central.len() as u32).to_le_bytes())?;
+        self.out.write_all(&(self.offset as u32).to_le_bytes())?;
+        self.out.write_all(&0u16.to_le_bytes())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Build a ZIP (deflate entries) for tests.
+#[cfg(test)]
+pub fn build(files: &[(&str, &[u8])]) -> Vec<u8> {
+    use std::io::Write;
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in files {
+        let mut enc =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(data).expect("deflate");
+        let packed = enc.finish().expect("deflate");
+        let offset = out.len() as u32;
+        let header = |sig: &[u8], central: bool| {
+            let mut h = sig.to_vec();
+            if central {
+                h.extend(20u16.to_le_bytes());
+            }
+            h.extend(20u16.to_le_bytes());
+            h.extend(0u16.to_le_bytes());
+            h.extend(8u16.to_le_bytes());
+            h.extend([0u8; 8]); // time, date, crc (unchecked here)
+            h.extend((packed.len() as u32).to_le_bytes());
+            h.extend((data.len() as u32).to_le_bytes());
+            h.extend((name.len() as u16).to_le_bytes());
+            h.extend(0u16.to_le_bytes());
+            if central {
+                h.extend([0u8; 10]); // comment len, disk, internal and external attrs
+                h.extend(offset.to_le_bytes());
+            }
+            h.extend(name.as_bytes());
+            h
+        };
+        out.extend(header(LOCAL_SIGNATURE, false));
+        out.extend(&packed);
+        central.extend(header(CENTRAL_SIGNATURE, true));
+    }
+    let dir_offset = out.len() as u32;
+    out.extend(&central);
+    out.extend(EOCD_SIGNATURE);
+    out.extend([0u8; 4]);
+    out.extend((files.len() as u16).to_le_bytes());
+    out.extend((files.len() as u16).to_le_bytes());
+    out.extend((central.len() as u32).to_le_bytes());
+    out.extend(dir_offset.to_le_bytes());
+    out.extend(0u16.to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_deflated_entries() {
+        let data = build(&[("a/one.msg", b"first"), ("two.txt", &[7u8; 5000])]);
+        assert!(is_zip(&data));
+        let mut zip = ZipArchive::new(std::io::Cursor::new(data)).expect("zip");
+        let names: Vec<_> = zip.entries().iter().map(|e| e.name.clone()).collect();
+        assert_eq!(names, ["a/one.msg", "two.txt"]);
+        assert_eq!(zip.read(0).expect("one"), b"first");
+        assert_eq!(zip.read(1).expect("two"), vec![7u8; 5000]);
+    }
+
+    #[test]
+    fn writer_output_reads_back() {
+        let mut writer = ZipWriter::new(Vec::new());
+        writer
+            .add("family.eml", b"Subject: x\r\n\r\nbody")
+            .expect("add");
+        writer
+            .add("attachments/Programme Rév C.pdf", &[3u8; 9000])
+            .expect("add");
+        let data = writer.finish().expect("finish");
+        let mut zip = ZipArchive::new(std::io::Cursor::new(data)).expect("zip");
+        assert_eq!(zip.entries()[1].name, "attachments/Programme Rév C.pdf");
+        assert_eq!(zip.read(0).expect("eml"), b"Subject: x\r\n\r\nbody");
+        assert_eq!(zip.read(1).expect("pdf"), vec![3u8; 9000]);
+    }
+}
@@ -0,0 +1,19 @@
+#!/bin/sh
+# Stand-in for readpst in integration tests: ignores the PST and copies the fixture messages
+# (already in readpst -M layout) from $FIXTURE_MAIL_DIR into the -o directory, and prints
+# readpst's per-folder summary lines. With $READPST_SHIM_HANG it then hangs that many seconds,
+# like readpst stuck on a large folder.
+set -eu
+out=""
+while [ $# -gt 0 ]; do
+  case "$1" in
+    -o) out="$2"; shift 2 ;;
+    *) shift ;;
+  esac
+done
+[ -n "$out" ] || { echo "readpst-shim: missing -o" >&2; exit 2; }
+cp -R "$FIXTURE_MAIL_DIR"/. "$out"/
+for dir in "$FIXTURE_MAIL_DIR"/*/; do
+  printf '\t"%s" - %d items done, 0 items skipped.\n' "$(basename "$dir")" "$(ls "$dir" | wc -l)"
+done
+[ -z "${READPST_SHIM_HANG:-}" ] || exec sleep "$READPST_SHIM_HANG"
@@ -0,0 +1,584 @@
+//! End-to-end run of the extractor binary against a local S3 emulator.
+//!
+//! Needs LocalStack or MinIO listening on `INTEGRATION_S3_ENDPOINT` (default
+//! `http://localhost:4566`; credentials from `AWS_ACCESS_KEY_ID` / `AWS_SECRET_ACCESS_KEY`,
+//! default `test`/`test`). Run with `cargo test --features integration`.
+//!
+//! By default `readpst` is replaced by `tests/fixtures/readpst-shim.sh`, which emits the fixture
+//! messages under `tests/fixtures/mail/`, so exact counts can be asserted without a binary PST in
+//! the repo. Set `INTEGRATION_PST` (and optionally `READPST_PATH`) to run a real PST through the
+//! real readpst instead; only structural checks apply then.
+
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn s3_client(endpoint: &str) -> aws_sdk_s3::Client {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .endpoint_url(endpoint)
+        .region(Region::new(env_or("AWS_REGION", "us-east-1")))
+        .credentials_provider(Credentials::new(
+            env_or("AWS_ACCESS_KEY_ID", "test"),
+            env_or("AWS_SECRET_ACCESS_KEY", "test"),
+            None,
+            None,
+            "integration",
+        ))
+        .force_path_style(true)
+        .build();
+    aws_sdk_s3::Client::from_conf(config)
+}
+
+async fn get_object(s3: &aws_sdk_s3::Client, bucket: &str, key: &str) -> Vec<u8> {
+    s3.get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .unwrap_or_else(|e| panic!("get s3://{bucket}/{key}: {e}"))
+        .body
+        .collect()
+        .await
+        .expect("read object body")
+        .to_vec()
+}
+
+fn gunzip_lines(bytes: &[u8]) -> Vec<String> {
+    let mut text = String::new();
+    flate2::read::MultiGzDecoder::new(bytes)
+        .read_to_string(&mut text)
+        .expect("gunzip");
+    text.lines().map(str::to_string).collect()
+}
+
+/// The extractor binary pointed at the S3 emulator.
+fn extractor(endpoint: &str) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_pst-extractor"));
+    cmd.env("S3_ENDPOINT_URL", endpoint)
+        .env("AWS_ACCESS_KEY_ID", env_or("AWS_ACCESS_KEY_ID", "test"))
+        .env(
+            "AWS_SECRET_ACCESS_KEY",
+            env_or("AWS_SECRET_ACCESS_KEY", "test"),
+        )
+        .env("AWS_REGION", env_or("AWS_REGION", "us-east-1"))
+        .env("AWS_EC2_METADATA_DISABLED", "true")
+        .env("S3_FORCE_PATH_STYLE", "true")
+        .env("PROGRESS_INTERVAL_SECS", "0");
+    cmd
+}
+
+#[tokio::test]
+async fn extracts_fixture_pst_end_to_end() {
+    let endpoint = env_or("INTEGRATION_S3_ENDPOINT", "http://localhost:4566");
+    let s3 = s3_client(&endpoint);
+
+    let run = uuid::Uuid::new_v4().simple().to_string();
+    let source_bucket = format!("pst-it-src-{}", &run[..12]);
+    let output_bucket = format!("pst-it-out-{}", &run[..12]);
+    for bucket in [&source_bucket, &output_bucket] {
+        s3.create_bucket()
+            .bucket(bucket)
+            .send()
+            .await
+            .unwrap_or_else(|e| panic!("create bucket {bucket} at {endpoint}: {e}"));
+    }
+
+    let real_pst = std::env::var("INTEGRATION_PST").ok();
+    let pst_bytes = match &real_pst {
+        Some(path) => std::fs::read(path).expect("read INTEGRATION_PST"),
+        None => b"!BDN fixture placeholder".to_vec(),
+    };
+    s3.put_object()
+        .bucket(&source_bucket)
+        .key("uploads/fixture.pst")
+        .body(pst_bytes.into())
+        .send()
+        .await
+        .expect("upload fixture pst");
+
+    let work_dir = std::env::temp_dir().join(format!("pst-it-{run}"));
+    let readpst = match &real_pst {
+        Some(_) => env_or("READPST_PATH", "readpst"),
+        None => fixtures().join("readpst-shim.sh").display().to_string(),
+    };
+    let pst_file_id = uuid::Uuid::new_v4().to_string();
+    let prefix = format!("runs/{pst_file_id}/");
+    let terms_path = std::env::temp_dir().join(format!("pst-it-terms-{run}.txt"));
+    std::fs::write(&terms_path, "site meeting\ndelay AND notice\nweather\n").expect("terms");
+    let output = extractor(&endpoint)
+        .env("FIXTURE_MAIL_DIR", fixtures().join("mail"))
+        // Small members so the fixture emails span several of them.
+        .env("GZIP_MEMBER_BYTES", "512")
+        .env("LOG_FORMAT", "json")
+        .env("METRICS_SINK", "emf")
+        .args(["--pst-file-id", &pst_file_id])
+        .args(["--source-bucket", &source_bucket])
+        .args(["--source-key", "uploads/fixture.pst"])
+        .args(["--output-bucket", &output_bucket])
+        .args(["--output-prefix", &prefix])
+        .args(["--work-dir", &work_dir.display().to_string()])
+        .args(["--readpst-path", &readpst])
+        .args(["--anonymized-export", "--anonymize-key", "it"])
+        .args(["--terms-file", &terms_path.display().to_string()])
+        .output()
+        .expect("run pst-extractor");
+    std::fs::remove_file(&terms_path).ok();
+    std::fs::remove_dir_all(&work_dir).ok();
+    assert!(
+        output.status.success(),
+        "extractor failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // LOG_FORMAT=json: one object per stderr line, tagged with the job and its phase.
+    let log_lines: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("json log line"))
+        .collect();
+    let phases: Vec<&str> = log_lines
+        .iter()
+        .filter_map(|line| line["phase"].as_str())
+        .collect();
+    for phase in ["download", "readpst", "parse", "upload"] {
+        assert!(phases.contains(&phase), "no log line in phase {phase}");
+    }
+    assert!(log_lines
+        .iter()
+        .filter(|line| line.get("phase").is_some())
+        .all(|line| line["pst_file_id"] == pst_file_id.as_str()));
+
+    // METRICS_SINK=emf: one Embedded Metric Format document on stdout.
+    let emf: serde_json::Value = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.contains("\"_aws\""))
+        .map(|line| serde_json::from_str(line).expect("emf json"))
+        .expect("emf line");
+    assert_eq!(emf["MessagesParsed"], 3.0);
+    assert_eq!(emf["ParseFailures"], 0.0);
+    assert!(emf["BytesUploaded"].as_f64().expect("bytes") > 0.0);
+    // Nothing else on stdout but JSON events and the closing OK line; readpst's output is logged.
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let json = serde_json::from_str::<serde_json::Value>(line).is_ok();
+        assert!(json || line.starts_with("OK "), "stray stdout line {line:?}");
+    }
+
+    // Every core output and sidecar named in the manifest was uploaded under the prefix.
+    let listed = s3
+        .list_objects_v2()
+        .bucket(&output_bucket)
+        .prefix(&prefix)
+        .send()
+        .await
+        .expect("list outputs");
+    let keys: Vec<String> = listed
+        .contents()
+        .iter()
+        .filter_map(|o| o.key().map(str::to_string))
+        .collect();
+    let manifest: serde_json::Value = serde_json::from_slice(
+        &get_object(&s3, &output_bucket, &format!("{prefix}manifest.json")).await,
+    )
+    .expect("manifest json");
+    for name in manifest["sha256"].as_object().expect("sha256 map").keys() {
+        assert!(
+            keys.contains(&format!("{prefix}{name}")),
+            "{name} listed in manifest but not uploaded; keys: {keys:?}"
+        );
+    }
+    for name in [
+        "emails.csv.gz",
+        "attachments.csv.gz",
+        "schema.json",
+        "timeseries.json",
+        "emails.ndjson.gz.members.json",
+    ] {
+        assert!(keys.contains(&format!("{prefix}{name}")), "missing {name}");
+    }
+
+    // Record counts agree between manifest and files.
+    let emails =
+        gunzip_lines(&get_object(&s3, &output_bucket, &format!("{prefix}emails.ndjson.gz")).await);
+    let attachments = gunzip_lines(
+        &get_object(
+            &s3,
+            &output_bucket,
+            &format!("{prefix}attachments.ndjson.gz"),
+        )
+        .await,
+    );
+    let csv =
+        gunzip_lines(&get_object(&s3, &output_bucket, &format!("{prefix}emails.csv.gz")).await);
+    assert_eq!(manifest["emails_total"].as_u64(), Some(emails.len() as u64));
+    assert_eq!(
+        manifest["attachments_total"].as_u64(),
+        Some(attachments.len() as u64)
+    );
+    let members: serde_json::Value = serde_json::from_slice(
+        &get_object(
+            &s3,
+            &output_bucket,
+            &format!("{prefix}emails.ndjson.gz.members.json"),
+        )
+        .await,
+    )
+    .expect("member index json");
+    assert_eq!(members["records_total"].as_u64(), Some(emails.len() as u64));
+    assert!(
+        csv.len() > emails.len(),
+        "CSV header plus at least one row per email"
+    );
+
+    // Attachment objects exist where their records point.
+    for line in &attachments {
+        let record: serde_json::Value = serde_json::from_str(line).expect("attachment json");
+        let key = record["s3_key"].as_str().expect("s3_key");
+        assert!(
+            keys.iter().any(|k| k == key),
+            "attachment object {key} not uploaded"
+        );
+    }
+
+    if real_pst.is_none() {
+        assert_eq!(emails.len(), 3);
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(manifest["threads"]["threads_total"].as_u64(), Some(2));
+        assert_eq!(manifest["anonymized_total"], 3);
+        assert_eq!(manifest["emails_failed"], 0);
+        assert_eq!(manifest["files_skipped"], 0);
+        let terms = &manifest["term_summary"];
+        assert_eq!(terms["site meeting"]["emails"], 2);
+        assert_eq!(terms["site meeting"]["families"], 2);
+        assert_eq!(terms["delay AND notice"]["emails"], 1);
+        assert_eq!(terms["weather"]["emails"], 0);
+        let term_hits = gunzip_lines(
+            &get_object(&s3, &output_bucket, &format!("{prefix}term_hits.ndjson.gz")).await,
+        );
+        assert_eq!(term_hits.len(), 3);
+        let corpus = get_object(&s3, &output_bucket, &format!("{prefix}anonymized.zip")).await;
+        let has = |needle: &[u8]| corpus.windows(needle.len()).any(|w| w == needle);
+        assert!(has(b"Inbox/") && has(b"Sent Items/"));
+        assert_eq!(manifest["count_validation"]["folders_checked"], 2);
+        assert_eq!(manifest["count_validation"]["pst_items_total"], 3);
+        assert_eq!(
+            manifest["count_validation"]["discrepancies"],
+            serde_json::json!([])
+        );
+        let notice = get_object(
+            &s3,
+            &output_bucket,
+            serde_json::from_str::<serde_json::Value>(&attachments[0]).expect("json")["s3_key"]
+                .as_str()
+                .expect("key"),
+        )
+        .await;
+        assert_eq!(notice, b"%PDF-1.4 delay notice\n");
+
+        let series: serde_json::Value = serde_json::from_slice(
+            &get_object(&s3, &output_bucket, &format!("{prefix}timeseries.json")).await,
+        )
+        .expect("timeseries json");
+        let days = series["days"].as_object().expect("days");
+        let dated: u64 = days.values().filter_map(|d| d["total"].as_u64()).sum();
+        assert_eq!(dated + series["undated"]["total"].as_u64().unwrap_or(0), 3);
+        let sent: u64 = days
+            .values()
+            .filter_map(|d| d["folder"]["sent"].as_u64())
+            .sum();
+        assert_eq!(sent, 1);
+    }
+}
+
+#[tokio::test]
+async fn extracts_eml_prefix_without_readpst() {
+    let endpoint = env_or("INTEGRATION_S3_ENDPOINT", "http://localhost:4566");
+    let s3 = s3_client(&endpoint);
+
+    let run = uuid::Uuid::new_v4().simple().to_string();
+    let bucket = format!("pst-it-eml-{}", &run[..12]);
+    s3.create_bucket()
+        .bucket(&bucket)
+        .send()
+        .await
+        .unwrap_or_else(|e| panic!("create bucket {bucket} at {endpoint}: {e}"));
+    // The fixture messages as loose .eml files, plus a non-message that must be ignored.
+    for (name, source) in [
+        ("Inbox/1.eml", "Inbox/1"),
+        ("Inbox/2.eml", "Inbox/2"),
+        ("Sent Items/1.eml", "Sent Items/1"),
+    ] {
+        let body = std::fs::read(fixtures().join("mail").join(source)).expect("fixture");
+        s3.put_object()
+            .bucket(&bucket)
+            .key(format!("export/{name}"))
+            .body(body.into())
+            .send()
+            .await
+            .expect("upload eml");
+    }
+    s3.put_object()
+        .bucket(&bucket)
+        .key("export/index.txt")
+        .body(b"not mail".to_vec().into())
+        .send()
+        .await
+        .expect("upload index");
+
+    let work_dir = std::env::temp_dir().join(format!("pst-it-{run}"));
+    std::fs::create_dir_all(&work_dir).expect("work dir");
+    let rules = work_dir.join("priority.json");
+    std::fs::write(&rules, r#"{"base": 40, "attachments": {"any": 30}}"#).expect("rules");
+    let prefix = format!("runs/{run}/");
+    let output = extractor(&endpoint)
+        .args(["--pst-file-id", &run])
+        .args(["--source-bucket", &bucket])
+        .args(["--source-key", "export/"])
+        .args(["--input-format", "eml-archive"])
+        .args(["--priority-model", &rules.display().to_string()])
+        .arg("--family-zips")
+        .args(["--output-bucket", &bucket])
+        .args(["--output-prefix", &prefix])
+        .args(["--work-dir", &work_dir.display().to_string()])
+        // Never spawned for loose input.
+        .args(["--readpst-path", "/nonexistent/readpst"])
+        .output()
+        .expect("run pst-extractor");
+    std::fs::remove_dir_all(&work_dir).ok();
+    assert!(
+        output.status.success(),
+        "extractor failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&get_object(&s3, &bucket, &format!("{prefix}manifest.json")).await)
+            .expect("manifest json");
+    assert_eq!(manifest["input_format"].as_str(), Some("eml-archive"));
+    assert_eq!(manifest["emails_total"].as_u64(), Some(3));
+    assert_eq!(manifest["attachments_total"].as_u64(), Some(1));
+    let emails =
+        gunzip_lines(&get_object(&s3, &bucket, &format!("{prefix}emails.ndjson.gz")).await);
+    let mut priorities: Vec<u64> = emails
+        .iter()
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line).expect("json")["review_priority"]
+                .as_u64()
+                .expect("review_priority")
+        })
+        .collect();
+    priorities.sort();
+    assert_eq!(priorities, [40, 40, 70]);
+    let mut paths: Vec<String> = emails
+        .iter()
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line).expect("json")["source_path"]
+                .as_str()
+                .expect("source_path")
+                .to_string()
+        })
+        .collect();
+    paths.sort();
+    assert_eq!(paths, ["Inbox/1.eml", "Inbox/2.eml", "Sent Items/1.eml"]);
+
+    // Every email has a family package holding its .eml and attachments.
+    assert_eq!(manifest["family_zips_total"].as_u64(), Some(3));
+    for line in &emails {
+        let record: serde_json::Value = serde_json::from_str(line).expect("json");
+        let key = record["family_zip_s3_key"]
+            .as_str()
+            .expect("family_zip_s3_key");
+        let zip = get_object(&s3, &bucket, key).await;
+        assert!(zip.starts_with(b"PK\x03\x04"), "{key} is not a zip");
+        let has_attachment = record["review_priority"].as_u64() == Some(70);
+        let has = |needle: &[u8]| zip.windows(needle.len()).any(|w| w == needle);
+        assert!(has(
+            format!("{}.eml", record["id"].as_str().expect("id")).as_bytes()
+        ));
+        assert_eq!(has(b"attachments/"), has_attachment);
+    }
+
+    // The same manifest merged twice: every email is a cross-source duplicate.
+    let manifest_uri = format!("s3://{bucket}/{prefix}manifest.json");
+    let case_path = std::env::temp_dir().join(format!("pst-it-case-{run}.json"));
+    let output = extractor(&endpoint)
+        .args(["merge-manifests", &manifest_uri, &manifest_uri])
+        .args(["--output", &case_path.display().to_string()])
+        .args(["--case-id", "case-it"])
+        .output()
+        .expect("run merge-manifests");
+    assert!(
+        output.status.success(),
+        "merge failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let case: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&case_path).expect("case manifest")).expect("json");
+    std::fs::remove_file(&case_path).ok();
+    assert_eq!(case["case_id"], "case-it");
+    assert_eq!(case["manifests_total"], 2);
+    assert_eq!(case["totals"]["emails_total"], 6);
+    assert_eq!(case["dedupe"]["unique_emails"], 3);
+    assert_eq!(case["dedupe"]["cross_source_duplicates"], 3);
+
+    // Aggregate-only: the report is the only object written, with the lone attachment withheld.
+    let aggregate_prefix = format!("aggregate/{run}/");
+    let work_dir = std::env::temp_dir().join(format!("pst-it-agg-{run}"));
+    let output = extractor(&endpoint)
+        .args(["--pst-file-id", &run])
+        .args(["--source-bucket", &bucket])
+        .args(["--source-key", "export/"])
+        .args(["--input-format", "eml-archive"])
+        .arg("--family-zips")
+        .arg("--aggregate-only")
+        .args(["--suppress-below", "2"])
+        .args(["--output-bucket", &bucket])
+        .args(["--output-prefix", &aggregate_prefix])
+        .args(["--work-dir", &work_dir.display().to_string()])
+        .args(["--readpst-path", "/nonexistent/readpst"])
+        .output()
+        .expect("run pst-extractor");
+    std::fs::remove_dir_all(&work_dir).ok();
+    assert!(
+        output.status.success(),
+        "extractor failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let listed = s3
+        .list_objects_v2()
+        .bucket(&bucket)
+        .prefix(&aggregate_prefix)
+        .send()
+        .await
+        .expect("list outputs");
+    let keys: Vec<&str> = listed.contents().iter().filter_map(|o| o.key()).collect();
+    assert_eq!(keys, [format!("{aggregate_prefix}aggregate_report.json")]);
+    let report: serde_json::Value =
+        serde_json::from_slice(&get_object(&s3, &bucket, keys[0]).await).expect("report json");
+    assert_eq!(report["totals"]["emails"], 3);
+    assert_eq!(report["totals"]["attachments"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn sigterm_uploads_an_interrupted_manifest() {
+    let endpoint = env_or("INTEGRATION_S3_ENDPOINT", "http://localhost:4566");
+    let s3 = s3_client(&endpoint);
+
+    let run = uuid::Uuid::new_v4().simple().to_string();
+    let bucket = format!("pst-it-term-{}", &run[..12]);
+    s3.create_bucket()
+        .bucket(&bucket)
+        .send()
+        .await
+        .unwrap_or_else(|e| panic!("create bucket {bucket} at {endpoint}: {e}"));
+    s3.put_object()
+        .bucket(&bucket)
+        .key("uploads/fixture.pst")
+        .body(b"!BDN fixture placeholder".to_vec().into())
+        .send()
+        .await
+        .expect("upload fixture pst");
+
+    let work_dir = std::env::temp_dir().join(format!("pst-it-{run}"));
+    let prefix = format!("runs/{run}/");
+    let mut child = extractor(&endpoint)
+        .env("FIXTURE_MAIL_DIR", fixtures().join("mail"))
+        .env("READPST_SHIM_HANG", "60")
+        .args(["--pst-file-id", &run])
+        .args(["--source-bucket", &bucket])
+        .args(["--source-key", "uploads/fixture.pst"])
+        .args(["--output-bucket", &bucket])
+        .args(["--output-prefix", &prefix])
+        .args(["--work-dir", &work_dir.display().to_string()])
+        .args([
+            "--readpst-path",
+            &fixtures().join("readpst-shim.sh").display().to_string(),
+        ])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("run pst-extractor");
+
+    // Wait for readpst to have written its output, then stop the task the way ECS does.
+    let extracted = work_dir.join(&run).join("extract").join("Inbox");
+    let started = std::time::Instant::now();
+    while !extracted.exists() {
+        assert!(started.elapsed().as_secs() < 30, "readpst never started");
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    let killed = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .expect("kill");
+    assert!(killed.success());
+    let status = child.wait().expect("wait");
+    std::fs::remove_dir_all(&work_dir).ok();
+    assert!(
+        started.elapsed().as_secs() < 30,
+        "SIGTERM did not stop readpst"
+    );
+    assert_eq!(status.code(), Some(75));
+
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&get_object(&s3, &bucket, &format!("{prefix}manifest.json")).await)
+            .expect("manifest json");
+    assert_eq!(manifest["status"], "interrupted");
+    assert_eq!(manifest["partial"], true);
+    assert_eq!(manifest["cutoff"]["reason"], "interrupted");
+    assert_eq!(manifest["cutoff"]["stages"][0], "readpst");
+    // The (empty) outputs are complete gzip streams.
+    let emails = get_object(&s3, &bucket, &format!("{prefix}emails.ndjson.gz")).await;
+    assert!(gunzip_lines(&emails).is_empty());
+}
+
+#[test]
+fn local_mode_runs_without_s3() {
+    let run = uuid::Uuid::new_v4().simple().to_string();
+    let dir = std::env::temp_dir().join(format!("pst-it-local-{run}"));
+    std::fs::create_dir_all(&dir).expect("dir");
+    let pst = dir.join("fixture.pst");
+    std::fs::write(&pst, b"!BDN fixture placeholder").expect("pst");
+    let out = dir.join("out");
+
+    // No S3 settings at all: an unreachable endpoint makes any S3 call fail the job.
+    let output = Command::new(env!("CARGO_BIN_EXE_pst-extractor"))
+        .env("S3_ENDPOINT_URL", "http://127.0.0.1:9")
+        .env("PROGRESS_INTERVAL_SECS", "0")
+        .env("FIXTURE_MAIL_DIR", fixtures().join("mail"))
+        .args(["--pst-file-id", "local"])
+        .args(["--source-path", &pst.display().to_string()])
+        .args(["--output-dir", &out.display().to_string()])
+        .args(["--work-dir", &dir.join("work").display().to_string()])
+        .args([
+            "--readpst-path",
+            &fixtures().join("readpst-shim.sh").display().to_string(),
+        ])
+        .output()
+        .expect("run pst-extractor");
+    assert!(
+        output.status.success(),
+        "extractor failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(out.join("manifest.json")).expect("manifest"))
+            .expect("manifest json");
+    assert_eq!(manifest["emails_total"], 3);
+    assert_eq!(manifest["source_bucket"], "");
+    assert_eq!(manifest["source_path"], pst.display().to_string());
+    for name in manifest["sha256"].as_object().expect("sha256 map").keys() {
+        assert!(out.join(name).is_file(), "{name} listed but not written");
+    }
+    let emails = gunzip_lines(&std::fs::read(out.join("emails.ndjson.gz")).expect("emails"));
+    assert_eq!(emails.len(), 3);
+    assert!(pst.is_file(), "source left in place");
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -1,5 +1,6 @@
 import gzip
 import base64
+import json
 import os
 import sys
 
@@ -7,6 +8,47 @@ import boto3
 import psycopg2
 
 
+# COPY column order. Must match the extractor's CSV header; verified against schema.json.
+EMAIL_COPY_COLUMNS = [
+    "id",
+    "pst_file_id",
+    "project_id",
+    "case_id",
+    "message_id",
+    "in_reply_to",
+    "references_header",
+    "subject",
+    "from_header",
+    "to_header",
+    "cc_header",
+    "bcc_header",
+    "date_header",
+    "date_epoch",
+    "sender_email",
+    "sender_name",
+    "body_text",
+    "body_html",
+    "source_path",
+]
+
+ATTACHMENT_COPY_COLUMNS = [
+    "id",
+    "email_message_id",
+    "pst_file_id",
+    "project_id",
+    "case_id",
+    "filename",
+    "content_type",
+    "file_size_bytes",
+    "s3_bucket",
+    "s3_key",
+    "attachment_hash",
+    "is_inline",
+    "content_id",
+    "source_path",
+]
+
+
 def _normalize_db_url(db_url: str) -> str:
     # Accept SQLAlchemy-style URLs like postgresql+psycopg2://...
     return db_url.replace("postgresql+psycopg2://", "postgresql://", 1)
@@ -39,6 +81,81 @@ def _require_database_url() -> str:
     raise RuntimeError("Missing required env var: DATABASE_URL (or DATABASE_URL_B64)")
 
 
+def _csv_header(path: str) -> list:
+    with gzip.open(path, "rt", encoding="utf-8", errors="replace") as f:
+        return f.readline().strip().split(",")
+
+
+def _schema_problems(cur, schema: dict, file_name: str, csv_path: str, copy_columns: list) -> list:
+    """
+    Compare one CSV entry of schema.json against the downloaded file's header, this loader's
+    COPY column list, and the live staging table. Returns human-readable problems.
+    """
+
+    entry = (schema.get("files") or {}).get(file_name)
+    if not entry:
+        return [f"{file_name} missing from schema.json"]
+    fields = entry.get("fields") or []
+    expected = [f["name"] for f in fields]
+    problems = []
+
+    header = _csv_header(csv_path)
+    if header != expected:
+        problems.append(f"CSV header {header} != schema columns {expected}")
+    if copy_columns != expected:
+        problems.append(f"loader COPY columns {copy_columns} != schema columns {expected}")
+
+    table = entry.get("table")
+    cur.execute(
+        "SELECT column_name, data_type FROM information_schema.columns WHERE table_name = %s",
+        (table,),
+    )
+    actual = dict(cur.fetchall())
+    for field in fields:
+        data_type = actual.get(field["name"])
+        if data_type is None:
+            problems.append(f"{table}.{field['name']} missing (expected {field['type']})")
+        elif data_type != field["type"]:
+            problems.append(
+                f"{table}.{field['name']} is {data_type}, schema.json says {field['type']}"
+            )
+    return problems
+
+
+def _verify_schema(cur, schema: dict, checks: list) -> None:
+    """
+    Fail (SCHEMA_CHECK=strict, default) or warn (SCHEMA_CHECK=warn) on any contract drift.
+    The mismatch is reported as one JSON line on stderr for log-based alerting.
+    """
+
+    mode = os.getenv("SCHEMA_CHECK", "strict").lower()
+    if mode == "off":
+        return
+    problems = {}
+    for file_name, csv_path, copy_columns in checks:
+        found = _schema_problems(cur, schema, file_name, csv_path, copy_columns)
+        if found:
+            problems[file_name] = found
+    if not problems:
+        return
+    print(
+        json.dumps(
+            {
+                "event": "schema_mismatch",
+                "severity": "warning" if mode == "warn" else "error",
+                "schema_version": schema.get("schema_version"),
+                "extractor_version": schema.get("extractor_version"),
+                "problems": problems,
+            }
+        ),
+        file=sys.stderr,
+    )
+    if mode != "warn":
+        raise RuntimeError(
+            "extractor output does not match the staging tables (see schema_mismatch)"
+        )
+
+
 def main() -> int:
     database_url = _normalize_db_url(_require_database_url())
     pst_file_id = _require("PST_FILE_ID")
@@ -47,8 +164,10 @@ def main() -> int:
 
     emails_key = f"{output_prefix}emails.csv.gz"
     atts_key = f"{output_prefix}attachments.csv.gz"
+    schema_key = f"{output_prefix}schema.json"
     emails_path = "/tmp/emails.csv.gz"
     atts_path = "/tmp/attachments.csv.gz"
+    schema_path = "/tmp/schema.json"
 
     s3 = boto3.client("s3")
     s3.download_file(output_bucket, emails_key, emails_path)
@@ -58,6 +177,14 @@ def main() -> int:
         has_attachments_file = True
     except Exception:
         has_attachments_file = False
+    # schema.json is emitted by extractors since schema_version 1; older runs skip verification.
+    try:
+        s3.download_file(output_bucket, schema_key, schema_path)
+        with open(schema_path, encoding="utf-8") as f:
+            schema = json.load(f)
+    except Exception:
+        schema = None
+        print(f"WARN no schema.json at s3://{output_bucket}/{schema_key}; skipping schema check")
 
     conn = psycopg2.connect(database_url)
     conn.autocommit = False
@@ -125,6 +252,12 @@ def main() -> int:
                 """
             )
 
+            if schema is not None:
+                checks = [("emails.csv.gz", emails_path, EMAIL_COPY_COLUMNS)]
+                if has_attachments_file:
+                    checks.append(("attachments.csv.gz", atts_path, ATTACHMENT_COPY_COLUMNS))
+                _verify_schema(cur, schema, checks)
+
             # Clean previous staging rows for this PST run (keeps the staging table bounded).
             cur.execute(
                 "DELETE FROM pst_v2_emails_raw WHERE pst_file_id = %s", (pst_file_id,)
@@ -140,30 +273,8 @@ def main() -> int:
             with gzip.open(emails_path, "rt", encoding="utf-8", errors="replace") as f:
                 # COPY expects header row present (extractor emits it).
                 cur.copy_expert(
-                    """
-                    COPY pst_v2_emails_raw (
-                        id,
-                        pst_file_id,
-                        project_id,
-                        case_id,
-                        message_id,
-                        in_reply_to,
-                        references_header,
-                        subject,
-                        from_header,
-                        to_header,
-                        cc_header,
-                        bcc_header,
-                        date_header,
-                        date_epoch,
-                        sender_email,
-                        sender_name,
-                        body_text,
-                        body_html,
-                        source_path
-                    )
-                    FROM STDIN WITH (FORMAT csv, HEADER true, NULL '');
-                    """,
+                    f"COPY pst_v2_emails_raw ({', '.join(EMAIL_COPY_COLUMNS)}) "
+                    "FROM STDIN WITH (FORMAT csv, HEADER true, NULL '');",
                     f,
                 )
 
@@ -172,25 +283,8 @@ def main() -> int:
                     atts_path, "rt", encoding="utf-8", errors="replace"
                 ) as f:
                     cur.copy_expert(
-                        """
-                        COPY pst_v2_attachments_raw (
-                            id,
-                            email_message_id,
-                            pst_file_id,
-                            project_id,
-                            case_id,
-                            filename,
-                            content_type,
-                            file_size_bytes,
-                            s3_bucket,
-                            s3_key,
-                            attachment_hash,
-                            is_inline,
-                            content_id,
-                            source_path
-                        )
-                        FROM STDIN WITH (FORMAT csv, HEADER true, NULL '');
-                        """,
+                        f"COPY pst_v2_attachments_raw ({', '.join(ATTACHMENT_COPY_COLUMNS)}) "
+                        "FROM STDIN WITH (FORMAT csv, HEADER true, NULL '');",
                         f,
                     )
 